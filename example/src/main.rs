@@ -125,6 +125,7 @@ fn main() {
         &mut input,
         &mut output,
         CompressionLevel::DefaultLevel,
+        DataFormat::Raw,
     );
     // fs::write("./data/CodeResourcesCompress", output.into_inner()).unwrap();
     // assert_eq!(data1, output.into_inner());
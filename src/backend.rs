@@ -0,0 +1,335 @@
+//! An adapter shaped like the backend traits of [flate2](https://github.com/rust-lang/flate2-rs),
+//! so flate2's `rust_backend` (or code written against the same interface) can be driven by
+//! this crate.
+//!
+//! flate2 feeds its backend slices and reads the running totals to see how much was consumed
+//! and written. [`Inflater`] and [`Deflater`] do exactly that on top of
+//! [`inflate_to_slice`] and [`deflate`], and the extension traits add what this crate has on
+//! top of that: [`ProgressExt`] for a progress callback, and with `std`, [`AsyncInflateExt`]
+//! for decompressing straight into an async writer.
+
+use crate::alloc::boxed::Box;
+
+use crate::deflate::core::CompressorOxide;
+use crate::deflate::stream::deflate;
+use crate::inflate::stream::{InflateState, inflate_to_slice};
+use crate::{DataFormat, MZFlush, MZResult, StreamResult};
+#[cfg(feature = "std")]
+use binrw::io::{seek::Seek, write::Write};
+
+/// The totals kept by both directions, as in flate2's `Backend`.
+pub trait Backend {
+    /// The total number of bytes taken in since creation or the last reset.
+    fn total_in(&self) -> u64;
+    /// The total number of bytes put out since creation or the last reset.
+    fn total_out(&self) -> u64;
+}
+
+/// A decompressor with the interface of flate2's `InflateBackend`.
+pub trait InflateBackend: Backend {
+    /// Create a decompressor for zlib data if `zlib_header` is set, and raw deflate otherwise.
+    ///
+    /// `window_bits` is only used by the zlib backends of flate2. The full 32 KiB window is
+    /// always used here, which can decode data compressed with any smaller window.
+    fn make(zlib_header: bool, window_bits: u8) -> Self;
+    /// Decompress from `input` to `output`. How much was consumed and written can be seen from
+    /// the change of the totals.
+    fn decompress(&mut self, input: &[u8], output: &mut [u8], flush: MZFlush) -> MZResult;
+    /// Start over with a new stream.
+    fn reset(&mut self, zlib_header: bool);
+}
+
+/// A compressor with the interface of flate2's `DeflateBackend`.
+pub trait DeflateBackend: Backend {
+    /// Create a compressor at `level` (0-10) that wraps the output in a zlib header if
+    /// `zlib_header` is set.
+    ///
+    /// `window_bits` is only used by the zlib backends of flate2, the window is always 32 KiB
+    /// here.
+    fn make(level: u8, zlib_header: bool, window_bits: u8) -> Self;
+    /// Compress from `input` to `output`. How much was consumed and written can be seen from
+    /// the change of the totals.
+    fn compress(&mut self, input: &[u8], output: &mut [u8], flush: MZFlush) -> MZResult;
+    /// Start over with a new stream, with the same settings.
+    fn reset(&mut self);
+}
+
+/// The totals passed to a progress callback.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Progress {
+    /// The total number of bytes taken in so far.
+    pub total_in: u64,
+    /// The total number of bytes put out so far.
+    pub total_out: u64,
+}
+
+/// A callback for [`ProgressExt::set_progress_callback`].
+pub type ProgressCallback = Box<dyn FnMut(Progress) + Send>;
+
+/// Progress reporting for the backends, which flate2 doesn't have a hook for itself.
+///
+/// The callback is set on the backend before it is handed to flate2, and the code reading or
+/// writing through flate2 then gets progress reports without changes.
+pub trait ProgressExt {
+    /// Call `callback` with the new totals after every call that took in or put out data, or
+    /// stop calling a callback if it is `None`.
+    fn set_progress_callback(&mut self, callback: Option<ProgressCallback>);
+}
+
+/// Decompressing straight into an async writer with a backend, as with
+/// [`inflate`][crate::inflate::stream::inflate].
+#[cfg(feature = "std")]
+pub trait AsyncInflateExt {
+    /// Decompress from `input` into `writer`, updating the totals and reporting progress as
+    /// [`InflateBackend::decompress`] does.
+    fn decompress_to_writer<'a, W: Write + Seek + Send>(
+        &'a mut self,
+        input: &'a [u8],
+        writer: &'a mut W,
+        flush: MZFlush,
+    ) -> impl Future<Output = Result<StreamResult, crate::error::Error>> + Send + 'a;
+}
+
+/// Call `callback`, if there is one, if `res` shows any progress.
+fn report(
+    callback: &mut Option<ProgressCallback>,
+    res: &StreamResult,
+    total_in: u64,
+    total_out: u64,
+) {
+    if res.bytes_consumed == 0 && res.bytes_written == 0 {
+        return;
+    }
+    if let Some(callback) = callback.as_mut() {
+        callback(Progress {
+            total_in,
+            total_out,
+        });
+    }
+}
+
+const fn data_format(zlib_header: bool) -> DataFormat {
+    if zlib_header {
+        DataFormat::Zlib
+    } else {
+        DataFormat::Raw
+    }
+}
+
+/// The decompression backend, using [`inflate_to_slice`].
+pub struct Inflater {
+    state: Box<InflateState>,
+    callback: Option<ProgressCallback>,
+}
+
+impl Inflater {
+    /// The decompressor state, e.g. to get the adler32 checksum.
+    pub fn state(&mut self) -> &mut InflateState {
+        &mut self.state
+    }
+}
+
+impl Backend for Inflater {
+    fn total_in(&self) -> u64 {
+        self.state.total_in()
+    }
+
+    fn total_out(&self) -> u64 {
+        self.state.total_out()
+    }
+}
+
+impl InflateBackend for Inflater {
+    fn make(zlib_header: bool, _window_bits: u8) -> Self {
+        Inflater {
+            state: InflateState::new_boxed(data_format(zlib_header)),
+            callback: None,
+        }
+    }
+
+    fn decompress(&mut self, input: &[u8], output: &mut [u8], flush: MZFlush) -> MZResult {
+        let res = inflate_to_slice(&mut self.state, input, output, flush);
+        report(
+            &mut self.callback,
+            &res,
+            self.state.total_in(),
+            self.state.total_out(),
+        );
+        res.status
+    }
+
+    fn reset(&mut self, zlib_header: bool) {
+        self.state.reset(data_format(zlib_header));
+    }
+}
+
+impl ProgressExt for Inflater {
+    fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.callback = callback;
+    }
+}
+
+#[cfg(feature = "std")]
+impl AsyncInflateExt for Inflater {
+    fn decompress_to_writer<'a, W: Write + Seek + Send>(
+        &'a mut self,
+        input: &'a [u8],
+        writer: &'a mut W,
+        flush: MZFlush,
+    ) -> impl Future<Output = Result<StreamResult, crate::error::Error>> + Send + 'a {
+        async move {
+            let res =
+                crate::inflate::stream::inflate(&mut self.state, input, writer, flush).await?;
+            report(
+                &mut self.callback,
+                &res,
+                self.state.total_in(),
+                self.state.total_out(),
+            );
+            Ok(res)
+        }
+    }
+}
+
+/// The compression backend, using [`deflate`].
+pub struct Deflater {
+    compressor: Box<CompressorOxide>,
+    total_in: u64,
+    total_out: u64,
+    callback: Option<ProgressCallback>,
+}
+
+impl Deflater {
+    /// The compressor, e.g. to change the compression level between blocks.
+    pub fn compressor(&mut self) -> &mut CompressorOxide {
+        &mut self.compressor
+    }
+}
+
+impl Backend for Deflater {
+    fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl DeflateBackend for Deflater {
+    fn make(level: u8, zlib_header: bool, _window_bits: u8) -> Self {
+        Deflater {
+            compressor: CompressorOxide::builder()
+                .format(data_format(zlib_header))
+                .level(level)
+                .build_boxed(),
+            total_in: 0,
+            total_out: 0,
+            callback: None,
+        }
+    }
+
+    fn compress(&mut self, input: &[u8], output: &mut [u8], flush: MZFlush) -> MZResult {
+        let res = deflate(&mut self.compressor, input, output, flush);
+        self.total_in += res.bytes_consumed as u64;
+        self.total_out += res.bytes_written as u64;
+        report(&mut self.callback, &res, self.total_in, self.total_out);
+        res.status
+    }
+
+    fn reset(&mut self) {
+        self.compressor.reset();
+        self.total_in = 0;
+        self.total_out = 0;
+    }
+}
+
+impl ProgressExt for Deflater {
+    fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.callback = callback;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MZStatus;
+    use crate::alloc::sync::Arc;
+    use crate::alloc::vec;
+    use crate::alloc::vec::Vec;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    /// Drive a backend the way flate2 does, through the totals.
+    fn run(
+        mut step: impl FnMut(&[u8], &mut [u8], MZFlush) -> (MZResult, usize, usize),
+        input: &[u8],
+        flush: MZFlush,
+    ) -> Vec<u8> {
+        let mut out = vec![0; 1000];
+        let mut ret = Vec::new();
+        let mut input = input;
+        loop {
+            let (status, consumed, written) = step(input, &mut out, flush);
+            input = &input[consumed..];
+            ret.extend_from_slice(&out[..written]);
+            match status {
+                Ok(MZStatus::StreamEnd) => return ret,
+                Ok(_) | Err(crate::MZError::Buf) => {}
+                Err(err) => panic!("{err:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_with_progress() {
+        let data = crate::test_util::text(50_000);
+        let reported = Arc::new(AtomicU64::new(0));
+
+        let mut deflater = Deflater::make(6, true, 15);
+        let progress = reported.clone();
+        deflater.set_progress_callback(Some(Box::new(move |p: Progress| {
+            progress.store(p.total_in, Ordering::Relaxed)
+        })));
+        let compressed = run(
+            |input, output, flush| {
+                let (before_in, before_out) = (deflater.total_in(), deflater.total_out());
+                let status = deflater.compress(input, output, flush);
+                (
+                    status,
+                    (deflater.total_in() - before_in) as usize,
+                    (deflater.total_out() - before_out) as usize,
+                )
+            },
+            &data,
+            MZFlush::Finish,
+        );
+        assert_eq!(reported.load(Ordering::Relaxed), data.len() as u64);
+        assert_eq!(deflater.total_out(), compressed.len() as u64);
+
+        let mut inflater = Inflater::make(true, 15);
+        let progress = reported.clone();
+        inflater.set_progress_callback(Some(Box::new(move |p: Progress| {
+            progress.store(p.total_out, Ordering::Relaxed)
+        })));
+        let decompressed = run(
+            |input, output, flush| {
+                let (before_in, before_out) = (inflater.total_in(), inflater.total_out());
+                let status = inflater.decompress(input, output, flush);
+                (
+                    status,
+                    (inflater.total_in() - before_in) as usize,
+                    (inflater.total_out() - before_out) as usize,
+                )
+            },
+            &compressed,
+            MZFlush::None,
+        );
+        assert_eq!(decompressed, data);
+        assert_eq!(reported.load(Ordering::Relaxed), data.len() as u64);
+        assert_eq!(inflater.total_in(), compressed.len() as u64);
+
+        inflater.reset(true);
+        deflater.reset();
+        assert_eq!((inflater.total_in(), deflater.total_in()), (0, 0));
+    }
+}
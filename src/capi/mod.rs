@@ -0,0 +1,696 @@
+//! C ABI exports of the classic miniz/zlib style functions, so C code written against
+//! `miniz.h` can link against this crate instead of `miniz.c`.
+//!
+//! The functions and [`mz_stream`] have the same names, signatures and layout as in miniz, so
+//! the existing `miniz.h` can be used as the header. Build the crate as a C library with e.g.
+//! `cargo rustc --release --features capi --crate-type staticlib` (or `cdylib`).
+//!
+//! Unlike miniz, the `zalloc` and `zfree` callbacks of [`mz_stream`] are ignored and the Rust
+//! global allocator is used. Only window bits of 15 (zlib) and -15 (raw deflate) are
+//! supported, as in miniz.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use crate::alloc::boxed::Box;
+use core::ffi::{c_char, c_int, c_uint, c_ulong, c_void};
+use core::{ptr, slice};
+
+use crate::deflate::core::{CompressorOxide, create_comp_flags_from_zip_params};
+use crate::deflate::stream::deflate;
+use crate::inflate::stream::{InflateState, MinReset, inflate_to_slice};
+use crate::shared::{MZ_ADLER32_INIT, MZ_DEFAULT_WINDOW_BITS, update_adler32, update_crc32};
+use crate::{DataFormat, MZError, MZFlush, MZStatus, StreamResult};
+
+#[cfg(feature = "block-boundary")]
+pub mod resume;
+#[cfg(feature = "std")]
+pub mod zip;
+
+/// The compression method of deflate, the only one supported.
+pub const MZ_DEFLATED: c_int = 8;
+
+/// Allocation callback of [`mz_stream`], not used here.
+pub type mz_alloc_func =
+    Option<unsafe extern "C" fn(opaque: *mut c_void, items: usize, size: usize) -> *mut c_void>;
+/// Deallocation callback of [`mz_stream`], not used here.
+pub type mz_free_func = Option<unsafe extern "C" fn(opaque: *mut c_void, address: *mut c_void)>;
+
+/// The state of a compression or decompression stream, laid out like `mz_stream` of miniz.
+#[repr(C)]
+pub struct mz_stream {
+    /// The next input byte.
+    pub next_in: *const u8,
+    /// Number of bytes available at `next_in`.
+    pub avail_in: c_uint,
+    /// Total number of bytes consumed so far.
+    pub total_in: c_ulong,
+    /// Where the next output byte goes.
+    pub next_out: *mut u8,
+    /// Number of bytes of space left at `next_out`.
+    pub avail_out: c_uint,
+    /// Total number of bytes written so far.
+    pub total_out: c_ulong,
+    /// Always null, there are no error messages.
+    pub msg: *mut c_char,
+    /// The compressor or decompressor, owned by the stream between the init and end functions.
+    pub state: *mut c_void,
+    /// Ignored.
+    pub zalloc: mz_alloc_func,
+    /// Ignored.
+    pub zfree: mz_free_func,
+    /// Ignored.
+    pub opaque: *mut c_void,
+    /// Unused.
+    pub data_type: c_int,
+    /// The adler32 checksum of the uncompressed data so far.
+    pub adler: c_ulong,
+    /// Unused.
+    pub reserved: c_ulong,
+}
+
+/// What `mz_stream::state` points to.
+enum StreamState {
+    Deflate(Box<CompressorOxide>),
+    Inflate(Box<InflateState>),
+}
+
+/// Get the stream, or `None` if the pointer is null.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream`.
+unsafe fn stream_mut<'a>(stream: *mut mz_stream) -> Option<&'a mut mz_stream> {
+    unsafe { stream.as_mut() }
+}
+
+/// Get the state of an initialized stream, or `None` if it has none.
+///
+/// # Safety
+///
+/// `stream.state` has to be null or set by one of the init functions.
+unsafe fn stream_state<'a>(stream: &mz_stream) -> Option<&'a mut StreamState> {
+    unsafe { stream.state.cast::<StreamState>().as_mut() }
+}
+
+/// Give `stream` the new `state`, dropping the old one if there is one.
+fn set_state(stream: &mut mz_stream, state: StreamState) {
+    stream.msg = ptr::null_mut();
+    stream.total_in = 0;
+    stream.total_out = 0;
+    stream.adler = c_ulong::from(MZ_ADLER32_INIT);
+    stream.state = Box::into_raw(Box::new(state)).cast();
+}
+
+/// Drop the state of `stream`, if it has one.
+///
+/// # Safety
+///
+/// `stream.state` has to be null or set by one of the init functions.
+unsafe fn drop_state(stream: &mut mz_stream) {
+    if !stream.state.is_null() {
+        drop(unsafe { Box::from_raw(stream.state.cast::<StreamState>()) });
+        stream.state = ptr::null_mut();
+    }
+}
+
+/// Make a slice of `len` bytes at `data`, or `None` if it is null and `len` isn't 0.
+///
+/// # Safety
+///
+/// `data` has to be null or valid for reads of `len` bytes.
+unsafe fn input_slice<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        Some(&[])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(unsafe { slice::from_raw_parts(data, len) })
+    }
+}
+
+/// Make a mutable slice of `len` bytes at `data`, or `None` if it is null and `len` isn't 0.
+///
+/// # Safety
+///
+/// `data` has to be null or valid for writes of `len` bytes.
+unsafe fn output_slice<'a>(data: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    if len == 0 {
+        Some(&mut [])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(unsafe { slice::from_raw_parts_mut(data, len) })
+    }
+}
+
+/// Move the input and output pointers of `stream` past what `res` consumed and wrote, and
+/// return the status code.
+fn advance(stream: &mut mz_stream, res: StreamResult) -> c_int {
+    stream.next_in = stream.next_in.wrapping_add(res.bytes_consumed);
+    stream.avail_in -= res.bytes_consumed as c_uint;
+    stream.total_in += res.bytes_consumed as c_ulong;
+    stream.next_out = stream.next_out.wrapping_add(res.bytes_written);
+    stream.avail_out -= res.bytes_written as c_uint;
+    stream.total_out += res.bytes_written as c_ulong;
+    MZError::result_to_code(res.status)
+}
+
+/// Whether `window_bits` is supported, i.e. zlib or raw deflate with a 32KiB window.
+const fn valid_window_bits(window_bits: c_int) -> bool {
+    window_bits == MZ_DEFAULT_WINDOW_BITS || -window_bits == MZ_DEFAULT_WINDOW_BITS
+}
+
+/// Initialize `stream` for zlib compression at `level`.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream`, which is not initialized or was
+/// ended.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_deflateInit(stream: *mut mz_stream, level: c_int) -> c_int {
+    unsafe { mz_deflateInit2(stream, level, MZ_DEFLATED, MZ_DEFAULT_WINDOW_BITS, 9, 0) }
+}
+
+/// Initialize `stream` for compression, with a zlib wrapper if `window_bits` is 15 and raw
+/// deflate if it is -15.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream`, which is not initialized or was
+/// ended.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_deflateInit2(
+    stream: *mut mz_stream,
+    level: c_int,
+    method: c_int,
+    window_bits: c_int,
+    mem_level: c_int,
+    strategy: c_int,
+) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    if method != MZ_DEFLATED || !(1..=9).contains(&mem_level) || !valid_window_bits(window_bits) {
+        return MZError::Param.to_code();
+    }
+    let flags = create_comp_flags_from_zip_params(level, window_bits, strategy);
+    set_state(
+        stream,
+        StreamState::Deflate(Box::new(CompressorOxide::new(flags))),
+    );
+    MZStatus::Ok.to_code()
+}
+
+/// Compress the input of `stream` to its output, with `flush` being one of the `MZ_*_FLUSH`
+/// values or `MZ_FINISH`.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for compression, with
+/// `next_in` and `next_out` valid for `avail_in` and `avail_out` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_deflate(stream: *mut mz_stream, flush: c_int) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Some(StreamState::Deflate(compressor)) = (unsafe { stream_state(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Ok(flush) = MZFlush::new(flush) else {
+        return MZError::Stream.to_code();
+    };
+    let (Some(input), Some(output)) = (unsafe {
+        (
+            input_slice(stream.next_in, stream.avail_in as usize),
+            output_slice(stream.next_out, stream.avail_out as usize),
+        )
+    }) else {
+        return MZError::Stream.to_code();
+    };
+
+    let res = deflate(compressor, input, output, flush);
+    stream.adler = c_ulong::from(compressor.adler32());
+    advance(stream, res)
+}
+
+/// Reset the compressor of `stream` to start a new stream with the same settings.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for compression.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_deflateReset(stream: *mut mz_stream) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Some(StreamState::Deflate(compressor)) = (unsafe { stream_state(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    compressor.reset();
+    stream.total_in = 0;
+    stream.total_out = 0;
+    stream.adler = c_ulong::from(MZ_ADLER32_INIT);
+    MZStatus::Ok.to_code()
+}
+
+/// Free the compressor of `stream`.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for compression.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_deflateEnd(stream: *mut mz_stream) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    unsafe { drop_state(stream) };
+    MZStatus::Ok.to_code()
+}
+
+/// Get an upper bound of the compressed size of `source_len` bytes.
+///
+/// # Safety
+///
+/// Always safe to call, as `stream` is not used.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_deflateBound(_stream: *mut mz_stream, source_len: c_ulong) -> c_ulong {
+    mz_compressBound(source_len)
+}
+
+/// Initialize `stream` for decompression of zlib data.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream`, which is not initialized or was
+/// ended.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateInit(stream: *mut mz_stream) -> c_int {
+    unsafe { mz_inflateInit2(stream, MZ_DEFAULT_WINDOW_BITS) }
+}
+
+/// Initialize `stream` for decompression, of zlib data if `window_bits` is 15 and raw deflate
+/// if it is -15.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream`, which is not initialized or was
+/// ended.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateInit2(stream: *mut mz_stream, window_bits: c_int) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    if !valid_window_bits(window_bits) {
+        return MZError::Param.to_code();
+    }
+    set_state(
+        stream,
+        StreamState::Inflate(InflateState::new_boxed_with_window_bits(window_bits)),
+    );
+    MZStatus::Ok.to_code()
+}
+
+/// Decompress the input of `stream` to its output, with `flush` being `MZ_NO_FLUSH`,
+/// `MZ_SYNC_FLUSH` or `MZ_FINISH`.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for decompression, with
+/// `next_in` and `next_out` valid for `avail_in` and `avail_out` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflate(stream: *mut mz_stream, flush: c_int) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Some(StreamState::Inflate(state)) = (unsafe { stream_state(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Ok(flush) = MZFlush::new(flush) else {
+        return MZError::Stream.to_code();
+    };
+    let (Some(input), Some(output)) = (unsafe {
+        (
+            input_slice(stream.next_in, stream.avail_in as usize),
+            output_slice(stream.next_out, stream.avail_out as usize),
+        )
+    }) else {
+        return MZError::Stream.to_code();
+    };
+
+    let res = inflate_to_slice(state, input, output, flush);
+    if let Some(adler) = state.decompressor().adler32() {
+        stream.adler = c_ulong::from(adler);
+    }
+    advance(stream, res)
+}
+
+/// Reset the decompressor of `stream` to start a new stream of the same format.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for decompression.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateReset(stream: *mut mz_stream) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Some(StreamState::Inflate(state)) = (unsafe { stream_state(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    state.reset_as(MinReset);
+    stream.total_in = 0;
+    stream.total_out = 0;
+    stream.adler = c_ulong::from(MZ_ADLER32_INIT);
+    MZStatus::Ok.to_code()
+}
+
+/// Free the decompressor of `stream`.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for decompression.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateEnd(stream: *mut mz_stream) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    unsafe { drop_state(stream) };
+    MZStatus::Ok.to_code()
+}
+
+/// Get an upper bound of the zlib compressed size of `source_len` bytes, as in miniz.
+#[unsafe(no_mangle)]
+pub extern "C" fn mz_compressBound(source_len: c_ulong) -> c_ulong {
+    // The worst case is stored blocks, which add 5 bytes per block, plus the zlib wrapper.
+    let stored = 128 + source_len + (source_len / (31 * 1024) + 1) * 5;
+    stored.max(128 + source_len * 110 / 100)
+}
+
+/// Compress `source_len` bytes at `source` into the `*dest_len` bytes at `dest` as zlib data at
+/// the default level, and set `*dest_len` to the compressed size.
+///
+/// # Safety
+///
+/// `dest_len` has to point to a valid `mz_ulong`, and `dest` and `source` have to be valid for
+/// `*dest_len` and `source_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_compress(
+    dest: *mut u8,
+    dest_len: *mut c_ulong,
+    source: *const u8,
+    source_len: c_ulong,
+) -> c_int {
+    unsafe { mz_compress2(dest, dest_len, source, source_len, -1) }
+}
+
+/// Compress `source_len` bytes at `source` into the `*dest_len` bytes at `dest` as zlib data at
+/// `level`, and set `*dest_len` to the compressed size.
+///
+/// Returns `MZ_BUF_ERROR` if the compressed data doesn't fit.
+///
+/// # Safety
+///
+/// `dest_len` has to point to a valid `mz_ulong`, and `dest` and `source` have to be valid for
+/// `*dest_len` and `source_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_compress2(
+    dest: *mut u8,
+    dest_len: *mut c_ulong,
+    source: *const u8,
+    source_len: c_ulong,
+    level: c_int,
+) -> c_int {
+    let Some(dest_len) = (unsafe { dest_len.as_mut() }) else {
+        return MZError::Param.to_code();
+    };
+    let (Some(input), Some(output)) = (unsafe {
+        (
+            input_slice(source, source_len as usize),
+            output_slice(dest, *dest_len as usize),
+        )
+    }) else {
+        return MZError::Param.to_code();
+    };
+
+    let flags = create_comp_flags_from_zip_params(level, MZ_DEFAULT_WINDOW_BITS, 0);
+    let mut compressor = Box::new(CompressorOxide::new(flags));
+    let res = deflate(&mut compressor, input, output, MZFlush::Finish);
+    match res.status {
+        Ok(MZStatus::StreamEnd) => {
+            *dest_len = res.bytes_written as c_ulong;
+            MZStatus::Ok.to_code()
+        }
+        Ok(_) => MZError::Buf.to_code(),
+        Err(err) => err.to_code(),
+    }
+}
+
+/// Decompress the zlib data of `source_len` bytes at `source` into the `*dest_len` bytes at
+/// `dest`, and set `*dest_len` to the decompressed size.
+///
+/// Returns `MZ_BUF_ERROR` if the decompressed data doesn't fit, and `MZ_DATA_ERROR` if it is
+/// invalid or truncated.
+///
+/// # Safety
+///
+/// `dest_len` has to point to a valid `mz_ulong`, and `dest` and `source` have to be valid for
+/// `*dest_len` and `source_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_uncompress(
+    dest: *mut u8,
+    dest_len: *mut c_ulong,
+    source: *const u8,
+    source_len: c_ulong,
+) -> c_int {
+    let mut source_len = source_len;
+    unsafe { mz_uncompress2(dest, dest_len, source, &mut source_len) }
+}
+
+/// Same as [`mz_uncompress`], but `*source_len` is also set to the number of bytes of
+/// compressed data, which may be less than the input.
+///
+/// # Safety
+///
+/// `dest_len` and `source_len` have to point to valid `mz_ulong`s, and `dest` and `source`
+/// have to be valid for `*dest_len` and `*source_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_uncompress2(
+    dest: *mut u8,
+    dest_len: *mut c_ulong,
+    source: *const u8,
+    source_len: *mut c_ulong,
+) -> c_int {
+    let (Some(dest_len), Some(source_len)) = (unsafe { (dest_len.as_mut(), source_len.as_mut()) })
+    else {
+        return MZError::Param.to_code();
+    };
+    let (Some(input), Some(output)) = (unsafe {
+        (
+            input_slice(source, *source_len as usize),
+            output_slice(dest, *dest_len as usize),
+        )
+    }) else {
+        return MZError::Param.to_code();
+    };
+
+    let mut state = InflateState::new_boxed(DataFormat::Zlib);
+    let res = inflate_to_slice(&mut state, input, output, MZFlush::Finish);
+    *source_len = res.bytes_consumed as c_ulong;
+    match res.status {
+        Ok(MZStatus::StreamEnd) => {
+            *dest_len = res.bytes_written as c_ulong;
+            MZStatus::Ok.to_code()
+        }
+        // All the input was used without reaching the end of the stream.
+        Err(MZError::Buf) if res.bytes_consumed == input.len() => MZError::Data.to_code(),
+        Ok(_) => MZError::Buf.to_code(),
+        Err(err) => err.to_code(),
+    }
+}
+
+/// Update the adler32 checksum `adler` with `buf_len` bytes at `ptr`, or get the initial
+/// checksum if `ptr` is null.
+///
+/// # Safety
+///
+/// `ptr` has to be null or valid for `buf_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_adler32(adler: c_ulong, ptr: *const u8, buf_len: usize) -> c_ulong {
+    match unsafe { input_slice(ptr, buf_len) } {
+        Some(data) if !ptr.is_null() => c_ulong::from(update_adler32(adler as u32, data)),
+        _ => c_ulong::from(MZ_ADLER32_INIT),
+    }
+}
+
+/// Update the CRC-32 `crc` with `buf_len` bytes at `ptr`, or get the initial value if `ptr`
+/// is null.
+///
+/// # Safety
+///
+/// `ptr` has to be null or valid for `buf_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_crc32(crc: c_ulong, ptr: *const u8, buf_len: usize) -> c_ulong {
+    match unsafe { input_slice(ptr, buf_len) } {
+        Some(data) if !ptr.is_null() => c_ulong::from(update_crc32(crc as u32, data)),
+        _ => 0,
+    }
+}
+
+/// Get the version of the miniz API that is implemented.
+#[unsafe(no_mangle)]
+pub extern "C" fn mz_version() -> *const c_char {
+    c"11.0.2".as_ptr()
+}
+
+/// Get a description of the status or error code `err`, or null if it isn't one.
+#[unsafe(no_mangle)]
+pub extern "C" fn mz_error(err: c_int) -> *const c_char {
+    let msg = match MZError::result_from_code(err) {
+        Some(Ok(MZStatus::Ok)) => c"",
+        Some(Ok(MZStatus::StreamEnd)) => c"stream end",
+        Some(Ok(MZStatus::NeedDict)) => c"need dictionary",
+        Some(Err(MZError::ErrNo)) => c"file error",
+        Some(Err(MZError::Stream)) => c"stream error",
+        Some(Err(MZError::Data)) => c"data error",
+        Some(Err(MZError::Mem)) => c"out of memory",
+        Some(Err(MZError::Buf)) => c"buf error",
+        Some(Err(MZError::Version)) => c"version error",
+        Some(Err(MZError::Param)) => c"parameter error",
+        None => return ptr::null(),
+    };
+    msg.as_ptr()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::vec;
+    use crate::alloc::vec::Vec;
+    use core::mem::MaybeUninit;
+
+    fn new_stream() -> mz_stream {
+        // All-zero is a valid, uninitialized stream, as in C.
+        unsafe { MaybeUninit::zeroed().assume_init() }
+    }
+
+    #[test]
+    fn one_shot() {
+        let data = crate::test_util::text(50_000);
+        let mut compressed = vec![0; mz_compressBound(data.len() as c_ulong) as usize];
+        let mut compressed_len = compressed.len() as c_ulong;
+        let status = unsafe {
+            mz_compress(
+                compressed.as_mut_ptr(),
+                &mut compressed_len,
+                data.as_ptr(),
+                data.len() as c_ulong,
+            )
+        };
+        assert_eq!(status, MZStatus::Ok.to_code());
+        compressed.truncate(compressed_len as usize);
+        assert_eq!(
+            crate::inflate::decompress_to_vec_zlib(&compressed).unwrap(),
+            data
+        );
+
+        let mut out = vec![0; data.len()];
+        let mut out_len = out.len() as c_ulong;
+        let status = unsafe {
+            mz_uncompress(
+                out.as_mut_ptr(),
+                &mut out_len,
+                compressed.as_ptr(),
+                compressed_len,
+            )
+        };
+        assert_eq!(status, MZStatus::Ok.to_code());
+        assert_eq!(out_len as usize, data.len());
+        assert_eq!(out, data);
+
+        // Too little space, and truncated data.
+        let mut out_len = 100;
+        let status = unsafe {
+            mz_uncompress(
+                out.as_mut_ptr(),
+                &mut out_len,
+                compressed.as_ptr(),
+                compressed_len,
+            )
+        };
+        assert_eq!(status, MZError::Buf.to_code());
+        let mut out_len = out.len() as c_ulong;
+        let status =
+            unsafe { mz_uncompress(out.as_mut_ptr(), &mut out_len, compressed.as_ptr(), 100) };
+        assert_eq!(status, MZError::Data.to_code());
+    }
+
+    #[test]
+    fn streaming() {
+        let data = crate::test_util::text(100_000);
+
+        let mut stream = new_stream();
+        assert_eq!(unsafe { mz_deflateInit(&mut stream, 6) }, 0);
+        let mut compressed = Vec::new();
+        let mut buf = [0; 1000];
+        stream.next_in = data.as_ptr();
+        stream.avail_in = data.len() as c_uint;
+        loop {
+            stream.next_out = buf.as_mut_ptr();
+            stream.avail_out = buf.len() as c_uint;
+            let status = unsafe { mz_deflate(&mut stream, MZFlush::Finish as c_int) };
+            compressed.extend_from_slice(&buf[..buf.len() - stream.avail_out as usize]);
+            if status == MZStatus::StreamEnd.to_code() {
+                break;
+            }
+            assert_eq!(status, MZStatus::Ok.to_code());
+        }
+        assert_eq!(stream.total_in as usize, data.len());
+        assert_eq!(stream.total_out as usize, compressed.len());
+        let adler = stream.adler;
+        assert_eq!(unsafe { mz_deflateEnd(&mut stream) }, 0);
+        assert!(stream.state.is_null());
+
+        let mut stream = new_stream();
+        assert_eq!(unsafe { mz_inflateInit(&mut stream) }, 0);
+        // Inflating with a compression stream is an error.
+        assert_eq!(
+            unsafe { mz_deflate(&mut stream, 0) },
+            MZError::Stream.to_code()
+        );
+        let mut out = Vec::new();
+        stream.next_in = compressed.as_ptr();
+        stream.avail_in = compressed.len() as c_uint;
+        loop {
+            stream.next_out = buf.as_mut_ptr();
+            stream.avail_out = buf.len() as c_uint;
+            let status = unsafe { mz_inflate(&mut stream, 0) };
+            out.extend_from_slice(&buf[..buf.len() - stream.avail_out as usize]);
+            if status == MZStatus::StreamEnd.to_code() {
+                break;
+            }
+            assert_eq!(status, MZStatus::Ok.to_code());
+        }
+        assert_eq!(out, data);
+        assert_eq!(stream.adler, adler);
+        assert_eq!(
+            unsafe { mz_adler32(MZ_ADLER32_INIT.into(), data.as_ptr(), data.len()) },
+            adler
+        );
+        assert_eq!(unsafe { mz_inflateEnd(&mut stream) }, 0);
+    }
+
+    #[test]
+    fn params() {
+        let mut stream = new_stream();
+        assert_eq!(
+            unsafe { mz_deflateInit2(&mut stream, 6, MZ_DEFLATED, 12, 9, 0) },
+            MZError::Param.to_code()
+        );
+        assert_eq!(
+            unsafe { mz_inflateInit2(ptr::null_mut(), 15) },
+            MZError::Stream.to_code()
+        );
+        assert!(!mz_error(-3).is_null());
+        assert!(mz_error(42).is_null());
+    }
+}
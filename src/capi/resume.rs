@@ -0,0 +1,296 @@
+//! C layouts of the state at a block boundary, so a C host can store where decompression
+//! stopped and hand it back later to resume, e.g. after a restart.
+//!
+//! The layouts of [`mz_block_boundary_state`] and [`mz_inflate_resume_state`] only change
+//! together with [`MZ_RESUME_STATE_VERSION`], which is the first field of both, and states
+//! with another version are rejected when they are handed back. All fields are in native
+//! byte order, use [`BlockBoundaryState::to_bytes`] for a portable encoding.
+
+use core::ffi::{c_int, c_uint};
+
+use super::{StreamState, input_slice, set_state, stream_mut, stream_state};
+use crate::alloc::vec::Vec;
+use crate::inflate::core::BlockBoundaryState;
+use crate::inflate::stream::InflateCheckpoint;
+use crate::{DataFormat, MZError, MZStatus};
+
+/// The version of the layouts in this module.
+pub const MZ_RESUME_STATE_VERSION: u32 = 1;
+
+/// A [`BlockBoundaryState`] with a fixed C layout.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct mz_block_boundary_state {
+    /// [`MZ_RESUME_STATE_VERSION`].
+    pub version: u32,
+    /// The number of bits of the last byte consumed that belong to the next block, 0-7.
+    pub num_bits: u8,
+    /// Those bits, in the low bits.
+    pub bit_buf: u8,
+    /// Always 0.
+    pub reserved: [u8; 2],
+    /// Zlib CMF.
+    pub z_header0: u32,
+    /// Zlib FLG.
+    pub z_header1: u32,
+    /// The adler32 checksum of the data decompressed so far.
+    pub check_adler32: u32,
+}
+
+impl From<&BlockBoundaryState> for mz_block_boundary_state {
+    fn from(state: &BlockBoundaryState) -> Self {
+        mz_block_boundary_state {
+            version: MZ_RESUME_STATE_VERSION,
+            num_bits: state.num_bits,
+            bit_buf: state.bit_buf,
+            reserved: [0; 2],
+            z_header0: state.z_header0,
+            z_header1: state.z_header1,
+            check_adler32: state.check_adler32,
+        }
+    }
+}
+
+impl mz_block_boundary_state {
+    /// Get the state back, or `None` if it has another version or `num_bits` is out of range.
+    pub fn to_state(&self) -> Option<BlockBoundaryState> {
+        if self.version != MZ_RESUME_STATE_VERSION || self.num_bits > 7 {
+            return None;
+        }
+        Some(BlockBoundaryState {
+            num_bits: self.num_bits,
+            bit_buf: self.bit_buf,
+            z_header0: self.z_header0,
+            z_header1: self.z_header1,
+            check_adler32: self.check_adler32,
+        })
+    }
+}
+
+/// An [`InflateCheckpoint`] without the window, with a fixed C layout. The window is the tail
+/// of the output, which the host has anyway.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct mz_inflate_resume_state {
+    /// [`MZ_RESUME_STATE_VERSION`].
+    pub version: u32,
+    /// 15 for zlib data and -15 for raw deflate, as for `mz_inflateInit2`.
+    pub window_bits: c_int,
+    /// The decompressor state.
+    pub boundary: mz_block_boundary_state,
+    /// Always 0.
+    pub reserved: u32,
+    /// The number of compressed bytes consumed, where the input continues when resuming.
+    pub total_in: u64,
+    /// The number of bytes decompressed.
+    pub total_out: u64,
+}
+
+impl From<&InflateCheckpoint> for mz_inflate_resume_state {
+    fn from(checkpoint: &InflateCheckpoint) -> Self {
+        mz_inflate_resume_state {
+            version: MZ_RESUME_STATE_VERSION,
+            window_bits: checkpoint.data_format.to_window_bits(),
+            boundary: (&checkpoint.boundary).into(),
+            reserved: 0,
+            total_in: checkpoint.total_in,
+            total_out: checkpoint.total_out,
+        }
+    }
+}
+
+impl mz_inflate_resume_state {
+    /// Get a checkpoint without a window back, or `None` if the state has another version or
+    /// is invalid.
+    pub fn to_checkpoint(&self) -> Option<InflateCheckpoint> {
+        if self.version != MZ_RESUME_STATE_VERSION || !super::valid_window_bits(self.window_bits) {
+            return None;
+        }
+        Some(InflateCheckpoint {
+            boundary: self.boundary.to_state()?,
+            data_format: DataFormat::from_window_bits(self.window_bits),
+            window: Vec::new(),
+            total_in: self.total_in,
+            total_out: self.total_out,
+        })
+    }
+}
+
+/// Make `mz_inflate` stop at the end of every deflate block if `stop` isn't 0, so
+/// [`mz_inflateGetResumeState`] can be called there.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for decompression.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateStopOnBlockBoundary(
+    stream: *mut super::mz_stream,
+    stop: c_int,
+) -> c_int {
+    let Some(stream) = (unsafe { stream_mut(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Some(StreamState::Inflate(state)) = (unsafe { stream_state(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    state.set_stop_on_block_boundary(stop != 0);
+    MZStatus::Ok.to_code()
+}
+
+/// Get the state to resume decompression from into `*out`.
+///
+/// Returns `MZ_STREAM_ERROR` unless the last call to `mz_inflate` stopped at a block boundary
+/// and all its output has been written out.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream` initialized for decompression, and
+/// `out` has to be null or point to writable memory for a `mz_inflate_resume_state`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateGetResumeState(
+    stream: *mut super::mz_stream,
+    out: *mut mz_inflate_resume_state,
+) -> c_int {
+    let (Some(stream), Some(out)) = (unsafe { (stream_mut(stream), out.as_mut()) }) else {
+        return MZError::Stream.to_code();
+    };
+    let Some(StreamState::Inflate(state)) = (unsafe { stream_state(stream) }) else {
+        return MZError::Stream.to_code();
+    };
+    match InflateCheckpoint::capture(state) {
+        Some(checkpoint) => {
+            *out = (&checkpoint).into();
+            MZStatus::Ok.to_code()
+        }
+        None => MZError::Stream.to_code(),
+    }
+}
+
+/// Initialize `stream` to continue decompression from `resume_state`, in place of
+/// `mz_inflateInit2`.
+///
+/// The input has to continue at byte `total_in` of the compressed data, and `window` has to be
+/// the end of the output produced before, of which the last 32 KiB are used. The stream keeps
+/// stopping at block boundaries.
+///
+/// Returns `MZ_VERSION_ERROR` if the state has another version, and `MZ_PARAM_ERROR` if it is
+/// invalid.
+///
+/// # Safety
+///
+/// `stream` has to be null or point to a valid `mz_stream`, which is not initialized or was
+/// ended, `resume_state` has to be null or point to a valid `mz_inflate_resume_state`, and
+/// `window` has to be valid for `window_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_inflateResume(
+    stream: *mut super::mz_stream,
+    resume_state: *const mz_inflate_resume_state,
+    window: *const u8,
+    window_len: c_uint,
+) -> c_int {
+    let (Some(stream), Some(resume_state)) =
+        (unsafe { (stream_mut(stream), resume_state.as_ref()) })
+    else {
+        return MZError::Stream.to_code();
+    };
+    if resume_state.version != MZ_RESUME_STATE_VERSION
+        || resume_state.boundary.version != MZ_RESUME_STATE_VERSION
+    {
+        return MZError::Version.to_code();
+    }
+    let (Some(checkpoint), Some(window)) = (resume_state.to_checkpoint(), unsafe {
+        input_slice(window, window_len as usize)
+    }) else {
+        return MZError::Param.to_code();
+    };
+
+    set_state(
+        stream,
+        StreamState::Inflate(InflateCheckpoint::resume(&checkpoint, Some(window))),
+    );
+    stream.total_in = checkpoint.total_in as _;
+    stream.total_out = checkpoint.total_out as _;
+    stream.adler = checkpoint.boundary.check_adler32.into();
+    MZStatus::Ok.to_code()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use super::*;
+    use crate::alloc::vec;
+    use core::mem::{MaybeUninit, offset_of, size_of};
+
+    #[test]
+    fn layout() {
+        assert_eq!(size_of::<mz_block_boundary_state>(), 20);
+        assert_eq!(offset_of!(mz_block_boundary_state, z_header0), 8);
+        assert_eq!(offset_of!(mz_block_boundary_state, check_adler32), 16);
+        assert_eq!(size_of::<mz_inflate_resume_state>(), 48);
+        assert_eq!(offset_of!(mz_inflate_resume_state, boundary), 8);
+        assert_eq!(offset_of!(mz_inflate_resume_state, total_in), 32);
+        assert_eq!(offset_of!(mz_inflate_resume_state, total_out), 40);
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        let data = crate::test_util::text(300_000);
+        let compressed = crate::deflate::compress_to_vec_zlib(&data, 1);
+        let mut out = vec![0; data.len()];
+
+        let mut stream: mz_stream = unsafe { MaybeUninit::zeroed().assume_init() };
+        assert_eq!(unsafe { mz_inflateInit(&mut stream) }, 0);
+        assert_eq!(unsafe { mz_inflateStopOnBlockBoundary(&mut stream, 1) }, 0);
+        let mut resume_state = MaybeUninit::<mz_inflate_resume_state>::zeroed();
+        stream.next_in = compressed.as_ptr();
+        stream.avail_in = compressed.len() as c_uint;
+        // Stop at the first block boundary after some output.
+        loop {
+            stream.next_out = out[stream.total_out as usize..].as_mut_ptr();
+            stream.avail_out = 1000;
+            assert_eq!(unsafe { mz_inflate(&mut stream, 0) }, 0);
+            if stream.total_out > 100_000
+                && unsafe { mz_inflateGetResumeState(&mut stream, resume_state.as_mut_ptr()) } == 0
+            {
+                break;
+            }
+        }
+        assert_eq!(unsafe { mz_inflateEnd(&mut stream) }, 0);
+        let resume_state = unsafe { resume_state.assume_init() };
+        let (total_in, total_out) = (
+            resume_state.total_in as usize,
+            resume_state.total_out as usize,
+        );
+        assert!(total_out > 100_000 && total_out < data.len());
+
+        let mut stream: mz_stream = unsafe { MaybeUninit::zeroed().assume_init() };
+        let status = unsafe {
+            mz_inflateResume(
+                &mut stream,
+                &resume_state,
+                out.as_ptr(),
+                total_out as c_uint,
+            )
+        };
+        assert_eq!(status, 0);
+        stream.next_in = compressed[total_in..].as_ptr();
+        stream.avail_in = (compressed.len() - total_in) as c_uint;
+        loop {
+            stream.next_out = out[stream.total_out as usize..].as_mut_ptr();
+            stream.avail_out = (data.len() - stream.total_out as usize) as c_uint;
+            let status = unsafe { mz_inflate(&mut stream, 0) };
+            if status == MZStatus::StreamEnd.to_code() {
+                break;
+            }
+            assert_eq!(status, 0);
+        }
+        assert_eq!(stream.total_out as usize, data.len());
+        assert_eq!(out, data);
+        assert_eq!(unsafe { mz_inflateEnd(&mut stream) }, 0);
+
+        let mut bad = resume_state;
+        bad.version = 2;
+        let status = unsafe { mz_inflateResume(&mut stream, &bad, out.as_ptr(), 0) };
+        assert_eq!(status, MZError::Version.to_code());
+    }
+}
@@ -0,0 +1,1405 @@
+//! The core of miniz's zip API, `mz_zip_reader_*` and `mz_zip_writer_*`, over the
+//! [`zip`](crate::zip) module.
+//!
+//! Archives are read from and written to memory. The `_file` functions read the whole file
+//! when the reader is opened, and write the archive to the file when it is finalized. The
+//! allocation, IO callback and alignment fields of [`mz_zip_archive`] are ignored, and memory
+//! returned by the `_heap` functions is freed with [`mz_free`].
+//!
+//! Times are taken as UTC, where miniz uses the local time zone.
+
+use core::alloc::Layout;
+use core::ffi::{CStr, c_char, c_int, c_uint, c_void};
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+use core::{mem, ptr};
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{input_slice, output_slice};
+use crate::alloc::boxed::Box;
+use crate::alloc::string::String;
+use crate::alloc::vec::Vec;
+use crate::zip::{CompressionMethod, EntryOptions, ZipError, ZipReader, ZipWriter};
+
+/// A C boolean, [`MZ_TRUE`] or [`MZ_FALSE`].
+pub type mz_bool = c_int;
+/// True.
+pub const MZ_TRUE: mz_bool = 1;
+/// False.
+pub const MZ_FALSE: mz_bool = 0;
+
+/// What an archive is open for.
+pub type mz_zip_mode = c_int;
+/// Not open.
+pub const MZ_ZIP_MODE_INVALID: mz_zip_mode = 0;
+/// Open for reading.
+pub const MZ_ZIP_MODE_READING: mz_zip_mode = 1;
+/// Open for writing.
+pub const MZ_ZIP_MODE_WRITING: mz_zip_mode = 2;
+/// Written and finalized.
+pub const MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED: mz_zip_mode = 3;
+
+/// Where an archive is read from or written to.
+pub type mz_zip_type = c_int;
+/// Not open.
+pub const MZ_ZIP_TYPE_INVALID: mz_zip_type = 0;
+/// Read from memory.
+pub const MZ_ZIP_TYPE_MEMORY: mz_zip_type = 2;
+/// Written to memory.
+pub const MZ_ZIP_TYPE_HEAP: mz_zip_type = 3;
+/// Read from or written to a file.
+pub const MZ_ZIP_TYPE_FILE: mz_zip_type = 4;
+
+/// Compare names case sensitively in [`mz_zip_reader_locate_file`].
+pub const MZ_ZIP_FLAG_CASE_SENSITIVE: c_uint = 0x0100;
+/// Compare only the part of names after the last `/` in [`mz_zip_reader_locate_file`].
+pub const MZ_ZIP_FLAG_IGNORE_PATH: c_uint = 0x0200;
+/// Read or write the compressed data as is, which is not supported.
+pub const MZ_ZIP_FLAG_COMPRESSED_DATA: c_uint = 0x0400;
+
+/// The size of the name and comment buffers of [`mz_zip_archive_file_stat`].
+pub const MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE: usize = 512;
+/// The size of the comment buffer of [`mz_zip_archive_file_stat`].
+pub const MZ_ZIP_MAX_ARCHIVE_FILE_COMMENT_SIZE: usize = 512;
+
+/// The level used for negative levels in `level_and_flags`.
+const MZ_DEFAULT_LEVEL: c_uint = 6;
+
+/// The error of the last failed call, see [`mz_zip_get_last_error`].
+pub type mz_zip_error = c_int;
+pub const MZ_ZIP_NO_ERROR: mz_zip_error = 0;
+pub const MZ_ZIP_UNDEFINED_ERROR: mz_zip_error = 1;
+pub const MZ_ZIP_TOO_MANY_FILES: mz_zip_error = 2;
+pub const MZ_ZIP_FILE_TOO_LARGE: mz_zip_error = 3;
+pub const MZ_ZIP_UNSUPPORTED_METHOD: mz_zip_error = 4;
+pub const MZ_ZIP_UNSUPPORTED_ENCRYPTION: mz_zip_error = 5;
+pub const MZ_ZIP_UNSUPPORTED_FEATURE: mz_zip_error = 6;
+pub const MZ_ZIP_FAILED_FINDING_CENTRAL_DIR: mz_zip_error = 7;
+pub const MZ_ZIP_NOT_AN_ARCHIVE: mz_zip_error = 8;
+pub const MZ_ZIP_INVALID_HEADER_OR_CORRUPTED: mz_zip_error = 9;
+pub const MZ_ZIP_UNSUPPORTED_MULTIDISK: mz_zip_error = 10;
+pub const MZ_ZIP_DECOMPRESSION_FAILED: mz_zip_error = 11;
+pub const MZ_ZIP_COMPRESSION_FAILED: mz_zip_error = 12;
+pub const MZ_ZIP_UNEXPECTED_DECOMPRESSED_SIZE: mz_zip_error = 13;
+pub const MZ_ZIP_CRC_CHECK_FAILED: mz_zip_error = 14;
+pub const MZ_ZIP_UNSUPPORTED_CDIR_SIZE: mz_zip_error = 15;
+pub const MZ_ZIP_ALLOC_FAILED: mz_zip_error = 16;
+pub const MZ_ZIP_FILE_OPEN_FAILED: mz_zip_error = 17;
+pub const MZ_ZIP_FILE_CREATE_FAILED: mz_zip_error = 18;
+pub const MZ_ZIP_FILE_WRITE_FAILED: mz_zip_error = 19;
+pub const MZ_ZIP_FILE_READ_FAILED: mz_zip_error = 20;
+pub const MZ_ZIP_FILE_CLOSE_FAILED: mz_zip_error = 21;
+pub const MZ_ZIP_FILE_SEEK_FAILED: mz_zip_error = 22;
+pub const MZ_ZIP_FILE_STAT_FAILED: mz_zip_error = 23;
+pub const MZ_ZIP_INVALID_PARAMETER: mz_zip_error = 24;
+pub const MZ_ZIP_INVALID_FILENAME: mz_zip_error = 25;
+pub const MZ_ZIP_BUF_TOO_SMALL: mz_zip_error = 26;
+pub const MZ_ZIP_INTERNAL_ERROR: mz_zip_error = 27;
+pub const MZ_ZIP_FILE_NOT_FOUND: mz_zip_error = 28;
+pub const MZ_ZIP_ARCHIVE_TOO_LARGE: mz_zip_error = 29;
+pub const MZ_ZIP_VALIDATION_FAILED: mz_zip_error = 30;
+pub const MZ_ZIP_WRITE_CALLBACK_FAILED: mz_zip_error = 31;
+
+/// The messages of [`mz_zip_get_error_string`], by error code.
+const ERROR_STRINGS: [&CStr; 32] = [
+    c"no error",
+    c"undefined error",
+    c"too many files",
+    c"file too large",
+    c"unsupported method",
+    c"unsupported encryption",
+    c"unsupported feature",
+    c"failed finding central directory",
+    c"not a ZIP archive",
+    c"invalid header or archive is corrupted",
+    c"unsupported multidisk archive",
+    c"decompression failed or archive is corrupted",
+    c"compression failed",
+    c"unexpected decompressed size",
+    c"CRC-32 check failed",
+    c"unsupported central directory size",
+    c"allocation failed",
+    c"file open failed",
+    c"file create failed",
+    c"file write failed",
+    c"file read failed",
+    c"file close failed",
+    c"file seek failed",
+    c"file stat failed",
+    c"invalid parameter",
+    c"invalid filename",
+    c"buffer too small",
+    c"internal error",
+    c"file not found",
+    c"archive is too large",
+    c"validation failed",
+    c"write callback failed",
+];
+
+/// `time_t`, which is 64 bits on the platforms this is built for.
+pub type MZ_TIME_T = i64;
+
+/// Opaque state of an open archive.
+#[repr(C)]
+pub struct mz_zip_internal_state {
+    _private: [u8; 0],
+}
+
+/// An archive open for reading or writing, laid out like `mz_zip_archive` of miniz.
+///
+/// Zero it, e.g. with [`mz_zip_zero_struct`], before passing it to an init function.
+#[repr(C)]
+pub struct mz_zip_archive {
+    /// The size of the archive.
+    pub m_archive_size: u64,
+    /// Unused.
+    pub m_central_directory_file_ofs: u64,
+    /// The number of entries.
+    pub m_total_files: u32,
+    /// What the archive is open for.
+    pub m_zip_mode: mz_zip_mode,
+    /// Where the archive is read from or written to.
+    pub m_zip_type: mz_zip_type,
+    /// The error of the last failed call.
+    pub m_last_error: mz_zip_error,
+    /// Ignored.
+    pub m_file_offset_alignment: u64,
+    /// Ignored.
+    pub m_pAlloc: *mut c_void,
+    /// Ignored.
+    pub m_pFree: *mut c_void,
+    /// Ignored.
+    pub m_pRealloc: *mut c_void,
+    /// Ignored.
+    pub m_pAlloc_opaque: *mut c_void,
+    /// Ignored.
+    pub m_pRead: *mut c_void,
+    /// Ignored.
+    pub m_pWrite: *mut c_void,
+    /// Ignored.
+    pub m_pNeeds_keepalive: *mut c_void,
+    /// Ignored.
+    pub m_pIO_opaque: *mut c_void,
+    /// The reader or writer, owned by the archive between the init and end functions.
+    pub m_pState: *mut mz_zip_internal_state,
+}
+
+/// What is known about an entry, laid out like `mz_zip_archive_file_stat` of miniz.
+#[repr(C)]
+pub struct mz_zip_archive_file_stat {
+    /// The index of the entry.
+    pub m_file_index: u32,
+    /// Unused.
+    pub m_central_dir_ofs: u64,
+    /// Unused.
+    pub m_version_made_by: u16,
+    /// The version of the specification needed to extract the entry.
+    pub m_version_needed: u16,
+    /// The general purpose flags.
+    pub m_bit_flag: u16,
+    /// The compression method.
+    pub m_method: u16,
+    /// The CRC-32 of the uncompressed data.
+    pub m_crc32: u32,
+    /// The size of the data in the archive.
+    pub m_comp_size: u64,
+    /// The size of the data once decompressed.
+    pub m_uncomp_size: u64,
+    /// Unused.
+    pub m_internal_attr: u16,
+    /// The MS-DOS directory attribute for directories.
+    pub m_external_attr: u32,
+    /// The position of the local header of the entry.
+    pub m_local_header_ofs: u64,
+    /// Always 0, entry comments are not read.
+    pub m_comment_size: u32,
+    /// Whether the entry is a directory.
+    pub m_is_directory: mz_bool,
+    /// Whether the entry is encrypted.
+    pub m_is_encrypted: mz_bool,
+    /// Whether the entry can be extracted.
+    pub m_is_supported: mz_bool,
+    /// The name, truncated to fit and nul-terminated.
+    pub m_filename: [c_char; MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE],
+    /// Always empty.
+    pub m_comment: [c_char; MZ_ZIP_MAX_ARCHIVE_FILE_COMMENT_SIZE],
+    /// The modification time, in seconds since the Unix epoch. It comes last since miniz 3.0.
+    pub m_time: MZ_TIME_T,
+}
+
+/// What `mz_zip_archive::m_pState` points to.
+enum ZipState {
+    Reader(ZipReader<Cursor<Vec<u8>>>),
+    Writer {
+        writer: ZipWriter<Cursor<Vec<u8>>>,
+        /// Where the archive is written when it is finalized.
+        file: Option<String>,
+    },
+    /// The archive was finalized, and only [`mz_zip_writer_end`] is left.
+    Finalized,
+}
+
+/// Run a future whose IO, on memory, is always ready to completion.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+            return out;
+        }
+    }
+}
+
+/// The [`mz_zip_error`] for `err`.
+fn zip_error(err: &ZipError) -> mz_zip_error {
+    match err {
+        // Reading memory only fails at the end of a truncated archive.
+        ZipError::Io(_) | ZipError::InvalidArchive(_) => MZ_ZIP_INVALID_HEADER_OR_CORRUPTED,
+        ZipError::TooLarge(_) | ZipError::LimitExceeded(_) => MZ_ZIP_FILE_TOO_LARGE,
+        ZipError::NoEntry => MZ_ZIP_INVALID_PARAMETER,
+        ZipError::UnsupportedMethod(_) => MZ_ZIP_UNSUPPORTED_METHOD,
+        ZipError::Decompress(_) => MZ_ZIP_DECOMPRESSION_FAILED,
+        ZipError::CrcMismatch { .. } => MZ_ZIP_CRC_CHECK_FAILED,
+        ZipError::Encrypted | ZipError::InvalidPassword => MZ_ZIP_UNSUPPORTED_ENCRYPTION,
+        ZipError::Compress(_) => MZ_ZIP_COMPRESSION_FAILED,
+    }
+}
+
+/// Record `err` as the last error of `zip`, and return false.
+fn fail(zip: &mut mz_zip_archive, err: mz_zip_error) -> mz_bool {
+    zip.m_last_error = err;
+    MZ_FALSE
+}
+
+/// Get the archive, or `None` if the pointer is null.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+unsafe fn archive_mut<'a>(zip: *mut mz_zip_archive) -> Option<&'a mut mz_zip_archive> {
+    unsafe { zip.as_mut() }
+}
+
+/// Get the state of an open archive, or `None` if it has none.
+///
+/// # Safety
+///
+/// `zip.m_pState` has to be null or set by one of the init functions.
+unsafe fn zip_state<'a>(zip: &mz_zip_archive) -> Option<&'a mut ZipState> {
+    unsafe { zip.m_pState.cast::<ZipState>().as_mut() }
+}
+
+/// Get the reader of an archive open for reading.
+///
+/// # Safety
+///
+/// As for [`zip_state`].
+unsafe fn reader<'a>(zip: &mz_zip_archive) -> Option<&'a mut ZipReader<Cursor<Vec<u8>>>> {
+    match unsafe { zip_state(zip) } {
+        Some(ZipState::Reader(reader)) => Some(reader),
+        _ => None,
+    }
+}
+
+/// Give `zip` the new `state`, if it isn't open already.
+fn open(zip: &mut mz_zip_archive, state: ZipState, mode: mz_zip_mode, ty: mz_zip_type) {
+    zip.m_total_files = 0;
+    zip.m_zip_mode = mode;
+    zip.m_zip_type = ty;
+    zip.m_last_error = MZ_ZIP_NO_ERROR;
+    zip.m_pState = Box::into_raw(Box::new(state)).cast();
+}
+
+/// Drop the state of `zip`, and mark it as not open.
+///
+/// # Safety
+///
+/// As for [`zip_state`].
+unsafe fn close(zip: &mut mz_zip_archive) {
+    if !zip.m_pState.is_null() {
+        drop(unsafe { Box::from_raw(zip.m_pState.cast::<ZipState>()) });
+    }
+    zip.m_pState = ptr::null_mut();
+    zip.m_zip_mode = MZ_ZIP_MODE_INVALID;
+    zip.m_zip_type = MZ_ZIP_TYPE_INVALID;
+}
+
+/// Get the C string at `s` as a `str`, or `None` if it is null or not UTF-8.
+///
+/// # Safety
+///
+/// `s` has to be null or point to a nul-terminated string.
+unsafe fn c_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+/// Copy `data` to memory that is freed with [`mz_free`].
+fn into_heap(data: &[u8]) -> *mut c_void {
+    // The length is kept in front of the data, for `mz_free`.
+    let Ok(layout) = heap_layout(data.len()) else {
+        return ptr::null_mut();
+    };
+    let base = unsafe { crate::alloc::alloc::alloc(layout) };
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+    unsafe {
+        base.cast::<usize>().write(data.len());
+        let data_ptr = base.add(HEAP_HEADER_LEN);
+        ptr::copy_nonoverlapping(data.as_ptr(), data_ptr, data.len());
+        data_ptr.cast()
+    }
+}
+
+/// The space in front of the memory returned by [`into_heap`].
+const HEAP_HEADER_LEN: usize = 16;
+
+fn heap_layout(len: usize) -> Result<Layout, core::alloc::LayoutError> {
+    Layout::from_size_align(len.saturating_add(HEAP_HEADER_LEN), HEAP_HEADER_LEN)
+}
+
+/// Free memory returned by [`mz_zip_reader_extract_to_heap`],
+/// [`mz_zip_reader_extract_file_to_heap`] or [`mz_zip_writer_finalize_heap_archive`].
+///
+/// # Safety
+///
+/// `p` has to be null or returned by one of those functions, and not freed already.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_free(p: *mut c_void) {
+    if p.is_null() {
+        return;
+    }
+    unsafe {
+        let base = p.cast::<u8>().sub(HEAP_HEADER_LEN);
+        let layout = heap_layout(base.cast::<usize>().read()).unwrap();
+        crate::alloc::alloc::dealloc(base, layout);
+    }
+}
+
+/// Seconds since the Unix epoch of the MS-DOS date and time in `dos`, taken as UTC.
+fn dos_to_unix(dos: u32) -> i64 {
+    let (date, time) = (dos >> 16, dos & 0xFFFF);
+    let year = 1980 + i64::from(date >> 9);
+    let month = i64::from((date >> 5) & 0xF).clamp(1, 12);
+    let day = i64::from(date & 0x1F).max(1);
+    let secs = (time >> 11) * 3600 + ((time >> 5) & 0x3F) * 60 + (time & 0x1F) * 2;
+    days_from_civil(year, month, day) * 86400 + i64::from(secs)
+}
+
+/// The MS-DOS date and time of `secs` since the Unix epoch, in UTC, clamped to the years
+/// 1980-2107 that can be stored.
+fn unix_to_dos(secs: u64) -> u32 {
+    let days = (secs / 86400) as i64;
+    let time = (secs % 86400) as u32;
+    let (year, month, day) = civil_from_days(days);
+    if year < 1980 {
+        return (1 << 5 | 1) << 16;
+    }
+    let year = (year - 1980).min(127) as u32;
+    let date = year << 9 | (month as u32) << 5 | day as u32;
+    (date << 16) | ((time / 3600) << 11) | ((time / 60 % 60) << 5) | ((time % 60) / 2)
+}
+
+/// The number of days from the Unix epoch to the date, in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of the number of days from the Unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Open `data` for reading.
+fn init_reader(zip: &mut mz_zip_archive, data: Vec<u8>, ty: mz_zip_type) -> mz_bool {
+    let size = data.len() as u64;
+    match block_on(ZipReader::new(Cursor::new(data))) {
+        Ok(reader) => {
+            let total_files = reader.entries().len() as u32;
+            open(zip, ZipState::Reader(reader), MZ_ZIP_MODE_READING, ty);
+            zip.m_archive_size = size;
+            zip.m_total_files = total_files;
+            MZ_TRUE
+        }
+        Err(err) => fail(zip, zip_error(&err)),
+    }
+}
+
+/// Whether `zip` can be opened, i.e. it is zeroed or was ended.
+fn can_open(zip: &mz_zip_archive) -> bool {
+    zip.m_pState.is_null() && zip.m_zip_mode == MZ_ZIP_MODE_INVALID
+}
+
+/// Zero `zip`, as needed before opening it.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to writable memory for an `mz_zip_archive`, which is not
+/// open.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_zero_struct(zip: *mut mz_zip_archive) {
+    if !zip.is_null() {
+        unsafe { ptr::write_bytes(zip, 0, 1) };
+    }
+}
+
+/// Open the archive of `size` bytes at `mem` for reading. The data is copied, so it doesn't
+/// have to be kept.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid, zeroed `mz_zip_archive`, and `mem` has to be
+/// valid for `size` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_init_mem(
+    zip: *mut mz_zip_archive,
+    mem: *const c_void,
+    size: usize,
+    _flags: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let Some(data) = (unsafe { input_slice(mem.cast(), size) }) else {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    };
+    if !can_open(zip) {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    }
+    init_reader(zip, data.to_vec(), MZ_ZIP_TYPE_MEMORY)
+}
+
+/// Open the archive in the file at `filename` for reading.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid, zeroed `mz_zip_archive`, and `filename` has to
+/// be null or point to a nul-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_init_file(
+    zip: *mut mz_zip_archive,
+    filename: *const c_char,
+    _flags: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    if !can_open(zip) {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    }
+    let Some(filename) = (unsafe { c_str(filename) }) else {
+        return fail(zip, MZ_ZIP_INVALID_FILENAME);
+    };
+    match std::fs::read(filename) {
+        Ok(data) => init_reader(zip, data, MZ_ZIP_TYPE_FILE),
+        Err(_) => fail(zip, MZ_ZIP_FILE_OPEN_FAILED),
+    }
+}
+
+/// Get the number of entries of the archive, or 0 if it isn't open for reading.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_get_num_files(zip: *mut mz_zip_archive) -> c_uint {
+    match unsafe { archive_mut(zip) } {
+        Some(zip) if zip.m_zip_mode == MZ_ZIP_MODE_READING => zip.m_total_files,
+        _ => 0,
+    }
+}
+
+/// Whether the entry at `file_index` is a directory.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_is_file_a_directory(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    match unsafe { reader(zip) }.and_then(|reader| reader.entries().get(file_index as usize)) {
+        Some(entry) => entry.is_dir().into(),
+        None => fail(zip, MZ_ZIP_INVALID_PARAMETER),
+    }
+}
+
+/// Whether the entry at `file_index` is encrypted.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_is_file_encrypted(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    match unsafe { reader(zip) }.and_then(|reader| reader.entries().get(file_index as usize)) {
+        Some(entry) => entry.is_encrypted().into(),
+        None => fail(zip, MZ_ZIP_INVALID_PARAMETER),
+    }
+}
+
+/// Copy the name of the entry at `file_index` into the `filename_buf_size` bytes at
+/// `filename`, truncated to fit and nul-terminated.
+///
+/// Returns the number of bytes copied including the nul, or the size needed for the whole
+/// name if `filename_buf_size` is 0, and 0 on failure.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `filename` has to be valid
+/// for `filename_buf_size` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_get_filename(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+    filename: *mut c_char,
+    filename_buf_size: c_uint,
+) -> c_uint {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return 0;
+    };
+    let Some(buf) = (unsafe { output_slice(filename.cast(), filename_buf_size as usize) }) else {
+        fail(zip, MZ_ZIP_INVALID_PARAMETER);
+        return 0;
+    };
+    let Some(entry) =
+        unsafe { reader(zip) }.and_then(|reader| reader.entries().get(file_index as usize))
+    else {
+        if let Some(first) = buf.first_mut() {
+            *first = 0;
+        }
+        fail(zip, MZ_ZIP_INVALID_PARAMETER);
+        return 0;
+    };
+    let mut len = entry.name.len();
+    if !buf.is_empty() {
+        len = len.min(buf.len() - 1);
+        buf[..len].copy_from_slice(&entry.name.as_bytes()[..len]);
+        buf[len] = 0;
+    }
+    len as c_uint + 1
+}
+
+/// Whether `name` is `wanted`, as compared by [`mz_zip_reader_locate_file`].
+fn name_matches(name: &str, wanted: &str, flags: c_uint) -> bool {
+    let name = if flags & MZ_ZIP_FLAG_IGNORE_PATH != 0 {
+        name.rsplit(['/', '\\']).next().unwrap_or(name)
+    } else {
+        name
+    };
+    if flags & MZ_ZIP_FLAG_CASE_SENSITIVE != 0 {
+        name == wanted
+    } else {
+        name.eq_ignore_ascii_case(wanted)
+    }
+}
+
+/// Get the index of the entry called `name`, or -1 if there is none.
+///
+/// Names are compared ignoring ASCII case unless [`MZ_ZIP_FLAG_CASE_SENSITIVE`] is set, and
+/// only after the last `/` with [`MZ_ZIP_FLAG_IGNORE_PATH`]. Entries have no comments, so
+/// none match a non-empty `comment`.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `name` and `comment` have
+/// to be null or point to nul-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_locate_file(
+    zip: *mut mz_zip_archive,
+    name: *const c_char,
+    comment: *const c_char,
+    flags: c_uint,
+) -> c_int {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return -1;
+    };
+    let (Some(archive), Some(name)) = (unsafe { (reader(zip), c_str(name)) }) else {
+        fail(zip, MZ_ZIP_INVALID_PARAMETER);
+        return -1;
+    };
+    let has_comment = !comment.is_null() && unsafe { *comment } != 0;
+    let found = archive
+        .entries()
+        .iter()
+        .position(|entry| name_matches(&entry.name, name, flags));
+    match found {
+        Some(index) if !has_comment => index as c_int,
+        _ => {
+            fail(zip, MZ_ZIP_FILE_NOT_FOUND);
+            -1
+        }
+    }
+}
+
+/// Fill in `*stat` for the entry at `file_index`.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `stat` has to be null or
+/// point to writable memory for an `mz_zip_archive_file_stat`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_file_stat(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+    stat: *mut mz_zip_archive_file_stat,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let entry = unsafe { reader(zip) }.and_then(|reader| reader.entries().get(file_index as usize));
+    let (Some(entry), false) = (entry, stat.is_null()) else {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    };
+
+    let supported = CompressionMethod::from_u16(entry.method).is_some()
+        || (cfg!(feature = "deflate64") && entry.method == 9);
+    // Zeroed is valid for every field.
+    let mut out: mz_zip_archive_file_stat = unsafe { mem::zeroed() };
+    out.m_file_index = file_index;
+    out.m_version_needed = if entry.method == 0 { 10 } else { 20 };
+    out.m_bit_flag = entry.flags;
+    out.m_method = entry.method;
+    out.m_time = dos_to_unix(entry.modified);
+    out.m_crc32 = entry.crc32;
+    out.m_comp_size = entry.compressed_size;
+    out.m_uncomp_size = entry.uncompressed_size;
+    out.m_external_attr = if entry.is_dir() { 0x10 } else { 0 };
+    out.m_local_header_ofs = entry.header_offset;
+    out.m_is_directory = entry.is_dir().into();
+    out.m_is_encrypted = entry.is_encrypted().into();
+    out.m_is_supported = (supported && !entry.is_encrypted()).into();
+    let len = entry.name.len().min(MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE - 1);
+    for (dst, &src) in out.m_filename.iter_mut().zip(&entry.name.as_bytes()[..len]) {
+        *dst = src as c_char;
+    }
+    unsafe { stat.write(out) };
+    MZ_TRUE
+}
+
+/// Extract the entry at `file_index`, passing the data to `callback`.
+///
+/// # Safety
+///
+/// As for [`zip_state`].
+unsafe fn extract(
+    zip: &mut mz_zip_archive,
+    file_index: c_uint,
+    flags: c_uint,
+    callback: impl FnMut(&[u8]) + Send,
+) -> Result<(), mz_zip_error> {
+    if flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0 {
+        return Err(MZ_ZIP_UNSUPPORTED_FEATURE);
+    }
+    let reader = unsafe { reader(zip) }.ok_or(MZ_ZIP_INVALID_PARAMETER)?;
+    block_on(reader.extract_with(file_index as usize, callback))
+        .map(|_| ())
+        .map_err(|err| zip_error(&err))
+}
+
+/// Extract the entry at `file_index` to memory.
+///
+/// # Safety
+///
+/// As for [`zip_state`].
+unsafe fn extract_to_vec(
+    zip: &mut mz_zip_archive,
+    file_index: c_uint,
+    flags: c_uint,
+) -> Result<Vec<u8>, mz_zip_error> {
+    let mut data = Vec::new();
+    unsafe { extract(zip, file_index, flags, |buf| data.extend_from_slice(buf)) }?;
+    Ok(data)
+}
+
+/// Extract the entry at `file_index` into the `buf_size` bytes at `buf`.
+///
+/// Fails with `MZ_ZIP_BUF_TOO_SMALL` if the data doesn't fit.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `buf` has to be valid for
+/// `buf_size` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_extract_to_mem(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+    buf: *mut c_void,
+    buf_size: usize,
+    flags: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let Some(out) = (unsafe { output_slice(buf.cast(), buf_size) }) else {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    };
+    let entry = unsafe { reader(zip) }.and_then(|reader| reader.entries().get(file_index as usize));
+    match entry {
+        Some(entry) if entry.uncompressed_size > buf_size as u64 => {
+            return fail(zip, MZ_ZIP_BUF_TOO_SMALL);
+        }
+        Some(_) => {}
+        None => return fail(zip, MZ_ZIP_INVALID_PARAMETER),
+    }
+
+    let mut pos = 0;
+    let mut overflow = false;
+    let res = unsafe {
+        extract(zip, file_index, flags, |data| {
+            match out.get_mut(pos..pos + data.len()) {
+                Some(dst) => dst.copy_from_slice(data),
+                None => overflow = true,
+            }
+            pos += data.len();
+        })
+    };
+    match res {
+        // The size in the central directory was wrong.
+        Ok(()) if overflow => fail(zip, MZ_ZIP_UNEXPECTED_DECOMPRESSED_SIZE),
+        Ok(()) => MZ_TRUE,
+        Err(err) => fail(zip, err),
+    }
+}
+
+/// Extract the entry called `filename`, found as with [`mz_zip_reader_locate_file`], into the
+/// `buf_size` bytes at `buf`.
+///
+/// # Safety
+///
+/// As for [`mz_zip_reader_locate_file`] and [`mz_zip_reader_extract_to_mem`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_extract_file_to_mem(
+    zip: *mut mz_zip_archive,
+    filename: *const c_char,
+    buf: *mut c_void,
+    buf_size: usize,
+    flags: c_uint,
+) -> mz_bool {
+    let index = unsafe { mz_zip_reader_locate_file(zip, filename, ptr::null(), flags) };
+    if index < 0 {
+        return MZ_FALSE;
+    }
+    unsafe { mz_zip_reader_extract_to_mem(zip, index as c_uint, buf, buf_size, flags) }
+}
+
+/// Extract the entry at `file_index` to newly allocated memory, which is freed with
+/// [`mz_free`], and set `*size` to its size.
+///
+/// Returns null on failure.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `size` has to be null or
+/// point to a writable `size_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_extract_to_heap(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+    size: *mut usize,
+    flags: c_uint,
+) -> *mut c_void {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return ptr::null_mut();
+    };
+    if let Some(size) = unsafe { size.as_mut() } {
+        *size = 0;
+    }
+    match unsafe { extract_to_vec(zip, file_index, flags) } {
+        Ok(data) => {
+            let p = into_heap(&data);
+            if p.is_null() {
+                fail(zip, MZ_ZIP_ALLOC_FAILED);
+            } else if let Some(size) = unsafe { size.as_mut() } {
+                *size = data.len();
+            }
+            p
+        }
+        Err(err) => {
+            fail(zip, err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Extract the entry called `filename`, found as with [`mz_zip_reader_locate_file`], to
+/// newly allocated memory, as [`mz_zip_reader_extract_to_heap`].
+///
+/// # Safety
+///
+/// As for [`mz_zip_reader_locate_file`] and [`mz_zip_reader_extract_to_heap`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_extract_file_to_heap(
+    zip: *mut mz_zip_archive,
+    filename: *const c_char,
+    size: *mut usize,
+    flags: c_uint,
+) -> *mut c_void {
+    let index = unsafe { mz_zip_reader_locate_file(zip, filename, ptr::null(), flags) };
+    if index < 0 {
+        return ptr::null_mut();
+    }
+    unsafe { mz_zip_reader_extract_to_heap(zip, index as c_uint, size, flags) }
+}
+
+/// Extract the entry at `file_index` to the file at `dst_filename`.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `dst_filename` has to be
+/// null or point to a nul-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_extract_to_file(
+    zip: *mut mz_zip_archive,
+    file_index: c_uint,
+    dst_filename: *const c_char,
+    flags: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let Some(dst_filename) = (unsafe { c_str(dst_filename) }) else {
+        return fail(zip, MZ_ZIP_INVALID_FILENAME);
+    };
+    match unsafe { extract_to_vec(zip, file_index, flags) } {
+        Ok(data) => match std::fs::write(dst_filename, data) {
+            Ok(()) => MZ_TRUE,
+            Err(_) => fail(zip, MZ_ZIP_FILE_WRITE_FAILED),
+        },
+        Err(err) => fail(zip, err),
+    }
+}
+
+/// Close an archive opened for reading.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_reader_end(zip: *mut mz_zip_archive) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    if unsafe { reader(zip) }.is_none() {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    }
+    unsafe { close(zip) };
+    MZ_TRUE
+}
+
+/// Open `zip` for writing an archive after `size_to_reserve_at_beginning` zero bytes.
+fn init_writer(
+    zip: &mut mz_zip_archive,
+    reserve: u64,
+    file: Option<String>,
+    ty: mz_zip_type,
+) -> mz_bool {
+    if !can_open(zip) {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    }
+    let mut cursor = Cursor::new(crate::alloc::vec![0; reserve as usize]);
+    cursor.set_position(reserve);
+    open(
+        zip,
+        ZipState::Writer {
+            writer: ZipWriter::new(cursor),
+            file,
+        },
+        MZ_ZIP_MODE_WRITING,
+        ty,
+    );
+    zip.m_archive_size = reserve;
+    MZ_TRUE
+}
+
+/// Open `zip` for writing an archive to memory, after `size_to_reserve_at_beginning` zero
+/// bytes.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid, zeroed `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_init_heap(
+    zip: *mut mz_zip_archive,
+    size_to_reserve_at_beginning: usize,
+    _initial_allocation_size: usize,
+) -> mz_bool {
+    match unsafe { archive_mut(zip) } {
+        Some(zip) => init_writer(
+            zip,
+            size_to_reserve_at_beginning as u64,
+            None,
+            MZ_ZIP_TYPE_HEAP,
+        ),
+        None => MZ_FALSE,
+    }
+}
+
+/// Open `zip` for writing an archive to the file at `filename`, after
+/// `size_to_reserve_at_beginning` zero bytes. The file is written when the archive is
+/// finalized.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid, zeroed `mz_zip_archive`, and `filename` has to
+/// be null or point to a nul-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_init_file(
+    zip: *mut mz_zip_archive,
+    filename: *const c_char,
+    size_to_reserve_at_beginning: u64,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let Some(filename) = (unsafe { c_str(filename) }) else {
+        return fail(zip, MZ_ZIP_INVALID_FILENAME);
+    };
+    init_writer(
+        zip,
+        size_to_reserve_at_beginning,
+        Some(filename.into()),
+        MZ_ZIP_TYPE_FILE,
+    )
+}
+
+/// Add an entry called `name` with `data`, compressed at the level in the low 4 bits of
+/// `level_and_flags`.
+///
+/// # Safety
+///
+/// As for [`zip_state`].
+unsafe fn add_entry(
+    zip: &mut mz_zip_archive,
+    name: &str,
+    data: &[u8],
+    level_and_flags: c_uint,
+) -> mz_bool {
+    let Some(ZipState::Writer { writer, .. }) = (unsafe { zip_state(zip) }) else {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    };
+    let level_and_flags = if (level_and_flags as c_int) < 0 {
+        MZ_DEFAULT_LEVEL
+    } else {
+        level_and_flags
+    };
+    if level_and_flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0 {
+        return fail(zip, MZ_ZIP_UNSUPPORTED_FEATURE);
+    }
+    if name.starts_with('/') {
+        return fail(zip, MZ_ZIP_INVALID_FILENAME);
+    }
+
+    let level = (level_and_flags & 0xF).min(10) as u8;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let options = EntryOptions {
+        method: if level == 0 {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        },
+        level,
+        modified: unix_to_dos(now),
+        ..EntryOptions::default()
+    };
+    match block_on(writer.add_entry(name, data, &options)) {
+        Ok(()) => {
+            zip.m_total_files = writer.len() as u32;
+            zip.m_archive_size = writer.get_ref().position();
+            MZ_TRUE
+        }
+        Err(err) => fail(zip, zip_error(&err)),
+    }
+}
+
+/// Add an entry called `archive_name` with the `buf_size` bytes at `buf`, compressed at the
+/// level in the low 4 bits of `level_and_flags`, or the default level if it is negative.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, `archive_name` has to be null
+/// or point to a nul-terminated string, and `buf` has to be valid for `buf_size` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_add_mem(
+    zip: *mut mz_zip_archive,
+    archive_name: *const c_char,
+    buf: *const c_void,
+    buf_size: usize,
+    level_and_flags: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let Some(name) = (unsafe { c_str(archive_name) }) else {
+        return fail(zip, MZ_ZIP_INVALID_FILENAME);
+    };
+    let Some(data) = (unsafe { input_slice(buf.cast(), buf_size) }) else {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    };
+    unsafe { add_entry(zip, name, data, level_and_flags) }
+}
+
+/// Add an entry called `archive_name` with the contents of the file at `src_filename`.
+///
+/// Entry comments are not supported, so `comment_size` has to be 0.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `archive_name` and
+/// `src_filename` have to be null or point to nul-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_add_file(
+    zip: *mut mz_zip_archive,
+    archive_name: *const c_char,
+    src_filename: *const c_char,
+    _comment: *const c_void,
+    comment_size: u16,
+    level_and_flags: c_uint,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    if comment_size != 0 {
+        return fail(zip, MZ_ZIP_UNSUPPORTED_FEATURE);
+    }
+    let (Some(name), Some(src_filename)) = (unsafe { (c_str(archive_name), c_str(src_filename)) })
+    else {
+        return fail(zip, MZ_ZIP_INVALID_FILENAME);
+    };
+    match std::fs::read(src_filename) {
+        Ok(data) => unsafe { add_entry(zip, name, &data, level_and_flags) },
+        Err(_) => fail(zip, MZ_ZIP_FILE_OPEN_FAILED),
+    }
+}
+
+/// Write the central directory, and the archive to its file if it was opened with
+/// [`mz_zip_writer_init_file`]. Returns the archive for [`mz_zip_writer_finalize_heap_archive`].
+///
+/// # Safety
+///
+/// As for [`zip_state`].
+unsafe fn finalize(zip: &mut mz_zip_archive) -> Result<Vec<u8>, mz_zip_error> {
+    let Some(state @ ZipState::Writer { .. }) = (unsafe { zip_state(zip) }) else {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    };
+    let ZipState::Writer { writer, file } = mem::replace(state, ZipState::Finalized) else {
+        unreachable!();
+    };
+    let data = block_on(writer.finish())
+        .map_err(|err| zip_error(&err))?
+        .into_inner();
+    zip.m_zip_mode = MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED;
+    zip.m_archive_size = data.len() as u64;
+    if let Some(file) = file {
+        std::fs::write(file, &data).map_err(|_| MZ_ZIP_FILE_WRITE_FAILED)?;
+    }
+    Ok(data)
+}
+
+/// Finish the archive by writing the central directory. An archive opened with
+/// [`mz_zip_writer_init_file`] is written to its file.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_finalize_archive(zip: *mut mz_zip_archive) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    match unsafe { finalize(zip) } {
+        Ok(_) => MZ_TRUE,
+        Err(err) => fail(zip, err),
+    }
+}
+
+/// Finish an archive opened with [`mz_zip_writer_init_heap`], and set `*buf` to it and
+/// `*size` to its size. It is freed with [`mz_free`].
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`, and `buf` and `size` have to be
+/// null or point to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_finalize_heap_archive(
+    zip: *mut mz_zip_archive,
+    buf: *mut *mut c_void,
+    size: *mut usize,
+) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    let (Some(buf), Some(size)) = (unsafe { (buf.as_mut(), size.as_mut()) }) else {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    };
+    if zip.m_zip_type != MZ_ZIP_TYPE_HEAP {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    }
+    let data = match unsafe { finalize(zip) } {
+        Ok(data) => data,
+        Err(err) => return fail(zip, err),
+    };
+    let heap = into_heap(&data);
+    if heap.is_null() {
+        return fail(zip, MZ_ZIP_ALLOC_FAILED);
+    }
+    (*buf, *size) = (heap, data.len());
+    MZ_TRUE
+}
+
+/// Close an archive opened for writing, whether or not it was finalized.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_writer_end(zip: *mut mz_zip_archive) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    match unsafe { zip_state(zip) } {
+        Some(ZipState::Writer { .. } | ZipState::Finalized) => {
+            unsafe { close(zip) };
+            MZ_TRUE
+        }
+        _ => fail(zip, MZ_ZIP_INVALID_PARAMETER),
+    }
+}
+
+/// Close an archive opened for reading or writing.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_end(zip: *mut mz_zip_archive) -> mz_bool {
+    let Some(zip) = (unsafe { archive_mut(zip) }) else {
+        return MZ_FALSE;
+    };
+    if zip.m_pState.is_null() {
+        return fail(zip, MZ_ZIP_INVALID_PARAMETER);
+    }
+    unsafe { close(zip) };
+    MZ_TRUE
+}
+
+/// Get the error of the last failed call on `zip`.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_get_last_error(zip: *mut mz_zip_archive) -> mz_zip_error {
+    match unsafe { archive_mut(zip) } {
+        Some(zip) => zip.m_last_error,
+        None => MZ_ZIP_INVALID_PARAMETER,
+    }
+}
+
+/// Get the error of the last failed call on `zip`, and reset it to `MZ_ZIP_NO_ERROR`.
+///
+/// # Safety
+///
+/// `zip` has to be null or point to a valid `mz_zip_archive`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mz_zip_clear_last_error(zip: *mut mz_zip_archive) -> mz_zip_error {
+    match unsafe { archive_mut(zip) } {
+        Some(zip) => mem::replace(&mut zip.m_last_error, MZ_ZIP_NO_ERROR),
+        None => MZ_ZIP_INVALID_PARAMETER,
+    }
+}
+
+/// Get a description of `err`.
+#[unsafe(no_mangle)]
+pub extern "C" fn mz_zip_get_error_string(err: mz_zip_error) -> *const c_char {
+    usize::try_from(err)
+        .ok()
+        .and_then(|err| ERROR_STRINGS.get(err))
+        .unwrap_or(&c"unknown error")
+        .as_ptr()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::mem::{offset_of, size_of};
+    use core::slice;
+
+    fn zeroed_archive() -> mz_zip_archive {
+        let mut zip = mem::MaybeUninit::uninit();
+        unsafe {
+            mz_zip_zero_struct(zip.as_mut_ptr());
+            zip.assume_init()
+        }
+    }
+
+    /// The offsets of miniz 3.0 on 64-bit targets.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn layout() {
+        assert_eq!(size_of::<mz_zip_archive>(), 112);
+        assert_eq!(offset_of!(mz_zip_archive, m_total_files), 16);
+        assert_eq!(offset_of!(mz_zip_archive, m_last_error), 28);
+        assert_eq!(offset_of!(mz_zip_archive, m_file_offset_alignment), 32);
+        assert_eq!(offset_of!(mz_zip_archive, m_pIO_opaque), 96);
+        assert_eq!(offset_of!(mz_zip_archive, m_pState), 104);
+        assert_eq!(size_of::<mz_zip_archive_file_stat>(), 1112);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_central_dir_ofs), 8);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_method), 22);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_crc32), 24);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_internal_attr), 48);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_external_attr), 52);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_local_header_ofs), 56);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_is_supported), 76);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_filename), 80);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_comment), 592);
+        assert_eq!(offset_of!(mz_zip_archive_file_stat, m_time), 1104);
+    }
+
+    #[test]
+    fn write_and_read() {
+        let data = crate::test_util::text(50_000);
+
+        let mut zip = zeroed_archive();
+        unsafe {
+            assert_eq!(mz_zip_writer_init_heap(&mut zip, 0, 0), MZ_TRUE);
+            assert_eq!(
+                mz_zip_writer_add_mem(&mut zip, c"dir/".as_ptr(), ptr::null(), 0, 0),
+                MZ_TRUE
+            );
+            assert_eq!(
+                mz_zip_writer_add_mem(
+                    &mut zip,
+                    c"dir/Data.bin".as_ptr(),
+                    data.as_ptr().cast(),
+                    data.len(),
+                    6
+                ),
+                MZ_TRUE
+            );
+            assert_eq!(
+                mz_zip_writer_add_mem(
+                    &mut zip,
+                    c"stored.txt".as_ptr(),
+                    b"stored".as_ptr().cast(),
+                    6,
+                    0
+                ),
+                MZ_TRUE
+            );
+            assert_eq!(zip.m_total_files, 3);
+        }
+        let mut archive = ptr::null_mut();
+        let mut archive_len = 0;
+        unsafe {
+            assert_eq!(
+                mz_zip_writer_finalize_heap_archive(&mut zip, &mut archive, &mut archive_len),
+                MZ_TRUE
+            );
+            assert_eq!(mz_zip_writer_end(&mut zip), MZ_TRUE);
+        }
+
+        let mut zip = zeroed_archive();
+        unsafe {
+            assert_eq!(
+                mz_zip_reader_init_mem(&mut zip, archive, archive_len, 0),
+                MZ_TRUE
+            );
+            mz_free(archive);
+            assert_eq!(mz_zip_reader_get_num_files(&mut zip), 3);
+            assert_eq!(mz_zip_reader_is_file_a_directory(&mut zip, 0), MZ_TRUE);
+
+            // Names are compared ignoring case, and the path if asked to.
+            assert_eq!(
+                mz_zip_reader_locate_file(&mut zip, c"DIR/data.bin".as_ptr(), ptr::null(), 0),
+                1
+            );
+            let flags = MZ_ZIP_FLAG_CASE_SENSITIVE;
+            assert_eq!(
+                mz_zip_reader_locate_file(&mut zip, c"DIR/data.bin".as_ptr(), ptr::null(), flags),
+                -1
+            );
+            assert_eq!(mz_zip_get_last_error(&mut zip), MZ_ZIP_FILE_NOT_FOUND);
+            let flags = MZ_ZIP_FLAG_IGNORE_PATH;
+            assert_eq!(
+                mz_zip_reader_locate_file(&mut zip, c"data.bin".as_ptr(), ptr::null(), flags),
+                1
+            );
+
+            let mut name = [0; 8];
+            assert_eq!(
+                mz_zip_reader_get_filename(&mut zip, 1, name.as_mut_ptr(), 8),
+                8
+            );
+            assert_eq!(CStr::from_ptr(name.as_ptr()), c"dir/Dat");
+
+            let mut stat: mz_zip_archive_file_stat = mem::zeroed();
+            assert_eq!(mz_zip_reader_file_stat(&mut zip, 1, &mut stat), MZ_TRUE);
+            assert_eq!(stat.m_uncomp_size, data.len() as u64);
+            assert_eq!(stat.m_method, 8);
+            assert_eq!(stat.m_is_supported, MZ_TRUE);
+            assert_eq!(CStr::from_ptr(stat.m_filename.as_ptr()), c"dir/Data.bin");
+
+            let mut out = vec![0u8; data.len()];
+            assert_eq!(
+                mz_zip_reader_extract_to_mem(&mut zip, 1, out.as_mut_ptr().cast(), out.len(), 0),
+                MZ_TRUE
+            );
+            assert_eq!(out, data);
+            assert_eq!(
+                mz_zip_reader_extract_to_mem(&mut zip, 1, out.as_mut_ptr().cast(), 100, 0),
+                MZ_FALSE
+            );
+            assert_eq!(mz_zip_get_last_error(&mut zip), MZ_ZIP_BUF_TOO_SMALL);
+
+            let mut size = 0;
+            let stored =
+                mz_zip_reader_extract_file_to_heap(&mut zip, c"stored.txt".as_ptr(), &mut size, 0);
+            assert_eq!(slice::from_raw_parts(stored.cast::<u8>(), size), b"stored");
+            mz_free(stored);
+
+            assert_eq!(mz_zip_reader_end(&mut zip), MZ_TRUE);
+            assert_eq!(zip.m_zip_mode, MZ_ZIP_MODE_INVALID);
+        }
+    }
+
+    #[test]
+    fn dos_time() {
+        // 2024-02-29 13:37:42
+        let dos = unix_to_dos(1_709_213_862);
+        assert_eq!(dos >> 16, (44 << 9) | (2 << 5) | 29);
+        assert_eq!(dos_to_unix(dos), 1_709_213_862);
+        assert_eq!(unix_to_dos(0), (1 << 5 | 1) << 16);
+    }
+}
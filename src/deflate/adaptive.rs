@@ -0,0 +1,88 @@
+//! Estimation of how well data compresses, used to pick the compression level with
+//! [`AdaptiveLevel`](super::core::AdaptiveLevel).
+//!
+//! The estimate is made with a single greedy pass over the sample that finds 4 byte matches
+//! through a small hash table, and prices the remaining literals at the order-0 entropy of
+//! their byte values. It is a lot cheaper than compressing the sample, and close enough to
+//! tell text from already compressed data.
+
+use core::convert::TryInto;
+
+use super::core::LZ_DICT_SIZE;
+
+const HASH_BITS: u32 = 12;
+/// Estimated cost in bits of a match, for the length and distance codes and extra bits.
+const MATCH_COST_BITS: u64 = 24;
+/// Fractional bits of the fixed point values returned by [`log2_fixed`].
+const LOG2_FRAC_BITS: u32 = 8;
+
+/// Estimate the size of `sample` once compressed, in 256ths of its size, capped at 256.
+///
+/// Only the first 32 KiB are looked at.
+pub(crate) fn estimate_ratio(sample: &[u8]) -> u32 {
+    let sample = &sample[..sample.len().min(LZ_DICT_SIZE)];
+    if sample.is_empty() {
+        return 256;
+    }
+
+    // Positions plus one, so 0 means empty.
+    let mut table = [0u16; 1 << HASH_BITS];
+    let mut counts = [0u32; 256];
+    let mut matches = 0u64;
+    let mut pos = 0;
+    while pos + 4 <= sample.len() {
+        let bytes = read_u32(sample, pos);
+        let hash = (bytes.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize;
+        let candidate = usize::from(table[hash]);
+        table[hash] = (pos + 1) as u16;
+
+        if candidate != 0 && read_u32(sample, candidate - 1) == bytes {
+            let start = candidate - 1;
+            let mut len = 4;
+            while pos + len < sample.len() && len < 258 && sample[start + len] == sample[pos + len]
+            {
+                len += 1;
+            }
+            matches += 1;
+            pos += len;
+        } else {
+            counts[usize::from(sample[pos])] += 1;
+            pos += 1;
+        }
+    }
+    for &byte in &sample[pos..] {
+        counts[usize::from(byte)] += 1;
+    }
+
+    let literals: u32 = counts.iter().sum();
+    let literal_bits = if literals > 0 {
+        let log_total = log2_fixed(literals);
+        counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| u64::from(count) * u64::from(log_total - log2_fixed(count)))
+            .sum::<u64>()
+            >> LOG2_FRAC_BITS
+    } else {
+        0
+    };
+
+    let bits = literal_bits + matches * MATCH_COST_BITS;
+    let ratio = bits * 256 / (sample.len() as u64 * 8);
+    ratio.min(256) as u32
+}
+
+#[inline]
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Base 2 logarithm of `x` (which must not be 0), with [`LOG2_FRAC_BITS`] fractional bits.
+///
+/// The fraction is interpolated linearly between powers of two, which is off by at most 0.09.
+fn log2_fixed(x: u32) -> u32 {
+    let int = 31 - x.leading_zeros();
+    // Bits below the leading one, shifted to the top, then take the highest ones.
+    let frac = (x << (31 - int) << 1) >> (32 - LOG2_FRAC_BITS);
+    (int << LOG2_FRAC_BITS) | frac
+}
@@ -1,10 +1,15 @@
 //! Buffer wrappers implementing default so we can allocate the buffers with `Box::default()`
 //! to avoid stack copies. Box::new() doesn't at the moment, and using a vec means we would lose
 //! static length info.
+//!
+//! The buffers can also be provided by the caller as `&'static mut`, see [`Storage`].
 
 use crate::deflate::core::{LZ_DICT_SIZE, MAX_MATCH_LEN};
+#[cfg(feature = "with-alloc")]
 use alloc::boxed::Box;
+#[cfg(feature = "with-alloc")]
 use alloc::vec;
+use core::ops::{Deref, DerefMut};
 
 /// Size of the buffer of lz77 encoded data.
 pub const LZ_CODE_BUF_SIZE: usize = 64 * 1024;
@@ -25,10 +30,72 @@ pub const fn update_hash(current_hash: u16, byte: u8) -> u16 {
     ((current_hash << LZ_HASH_SHIFT) ^ byte as u16) & (LZ_HASH_SIZE as u16 - 1)
 }
 
+/// A large buffer of the compressor, either allocated by it, or provided by the caller to
+/// compress without allocating.
+pub enum Storage<T: 'static> {
+    #[cfg(feature = "with-alloc")]
+    Boxed(Box<T>),
+    Static(&'static mut T),
+}
+
+#[cfg(feature = "with-alloc")]
+impl<T: Copy + Default, const N: usize> Storage<[T; N]> {
+    /// Allocate a zeroed array on the heap, without going through the stack.
+    pub fn boxed_array() -> Self {
+        let Ok(b) = vec![T::default(); N].into_boxed_slice().try_into() else {
+            unreachable!()
+        };
+        Storage::Boxed(b)
+    }
+}
+
+impl<T> Deref for Storage<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        match self {
+            #[cfg(feature = "with-alloc")]
+            Storage::Boxed(b) => b,
+            Storage::Static(r) => r,
+        }
+    }
+}
+
+impl<T> DerefMut for Storage<T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            #[cfg(feature = "with-alloc")]
+            Storage::Boxed(b) => b,
+            Storage::Static(r) => r,
+        }
+    }
+}
+
+#[cfg(feature = "with-alloc")]
+impl<T: Default> Default for Storage<T> {
+    fn default() -> Self {
+        Storage::Boxed(Box::default())
+    }
+}
+
+/// Cloning copies the buffer to the heap, also if it was provided by the caller.
+#[cfg(feature = "with-alloc")]
+impl<T: Clone> Clone for Storage<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Storage::Boxed(b) => Storage::Boxed(b.clone()),
+            Storage::Static(r) => Storage::Boxed(Box::new((**r).clone())),
+        }
+    }
+}
+
+#[cfg_attr(feature = "with-alloc", derive(Clone))]
 pub struct HashBuffers {
-    pub dict: Box<[u8; LZ_DICT_FULL_SIZE]>,
-    pub next: Box<[u16; LZ_DICT_SIZE]>,
-    pub hash: Box<[u16; LZ_DICT_SIZE]>,
+    pub dict: Storage<[u8; LZ_DICT_FULL_SIZE]>,
+    pub next: Storage<[u16; LZ_DICT_SIZE]>,
+    pub hash: Storage<[u16; LZ_DICT_SIZE]>,
 }
 
 impl HashBuffers {
@@ -40,27 +107,32 @@ impl HashBuffers {
     }
 }
 
+#[cfg(feature = "with-alloc")]
 impl Default for HashBuffers {
     fn default() -> HashBuffers {
         HashBuffers {
-            dict: vec![0; LZ_DICT_FULL_SIZE]
-                .into_boxed_slice()
-                .try_into()
-                .unwrap(),
-            next: vec![0; LZ_DICT_SIZE].into_boxed_slice().try_into().unwrap(),
-            hash: vec![0; LZ_DICT_SIZE].into_boxed_slice().try_into().unwrap(),
+            dict: Storage::boxed_array(),
+            next: Storage::boxed_array(),
+            hash: Storage::boxed_array(),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct LocalBuf {
     pub b: [u8; OUT_BUF_SIZE],
 }
 
-impl Default for LocalBuf {
-    fn default() -> LocalBuf {
+impl LocalBuf {
+    pub const fn new() -> LocalBuf {
         LocalBuf {
             b: [0; OUT_BUF_SIZE],
         }
     }
 }
+
+impl Default for LocalBuf {
+    fn default() -> LocalBuf {
+        LocalBuf::new()
+    }
+}
@@ -0,0 +1,625 @@
+//! Snapshotting of the full [`CompressorOxide`] state.
+//!
+//! A checkpoint contains everything the compressor needs to carry on exactly where it left
+//! off: the window and hash chains, the pending LZ codes and symbol counts of the current
+//! block, buffered output that has not been handed to the caller yet, and the partially
+//! filled bit buffer. Resuming from a checkpoint produces byte-for-byte the same output as an
+//! uninterrupted run.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::buffer::{HashBuffers, LZ_CODE_BUF_SIZE, LZ_DICT_FULL_SIZE, OUT_BUF_SIZE, Storage};
+use super::core::{
+    AdaptiveLevel, BlockBoundary, CompressionStats, CompressorOxide, DictOxide, HuffmanOxide,
+    LZ_DICT_SIZE, LZOxide, MAX_HASH_LEN, MAX_MATCH_LEN, MAX_SUPPORTED_HUFF_CODESIZE, MIN_MATCH_LEN,
+    ParamsOxide, TDEFLFlush, TDEFLStatus, probes_from_chain,
+};
+use super::match_compare::MATCH_COMPARE_BYTES;
+
+/// Magic bytes at the start of every checkpoint.
+const CHECKPOINT_MAGIC: [u8; 4] = *b"MZCK";
+/// Version of the checkpoint layout.
+const CHECKPOINT_VERSION: u8 = 9;
+/// Highest compression level, the one of [`CompressionLevel::UberCompression`].
+///
+/// [`CompressionLevel::UberCompression`]: super::CompressionLevel::UberCompression
+const MAX_LEVEL: u8 = 10;
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.out.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.out.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.out.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.out.extend_from_slice(v);
+    }
+
+    fn u16s(&mut self, v: &[u16]) {
+        for x in v {
+            self.out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.u64()?.try_into().ok()
+    }
+
+    fn bytes_into(&mut self, dst: &mut [u8]) -> Option<()> {
+        dst.copy_from_slice(self.take(dst.len())?);
+        Some(())
+    }
+
+    fn u16s_into(&mut self, dst: &mut [u16]) -> Option<()> {
+        let src = self.take(dst.len() * 2)?;
+        for (d, s) in dst.iter_mut().zip(src.chunks_exact(2)) {
+            *d = u16::from_le_bytes([s[0], s[1]]);
+        }
+        Some(())
+    }
+}
+
+const fn status_to_u8(status: TDEFLStatus) -> u8 {
+    match status {
+        TDEFLStatus::BadParam => 0,
+        TDEFLStatus::PutBufFailed => 1,
+        TDEFLStatus::Okay => 2,
+        TDEFLStatus::Done => 3,
+    }
+}
+
+const fn status_from_u8(v: u8) -> Option<TDEFLStatus> {
+    match v {
+        0 => Some(TDEFLStatus::BadParam),
+        1 => Some(TDEFLStatus::PutBufFailed),
+        2 => Some(TDEFLStatus::Okay),
+        3 => Some(TDEFLStatus::Done),
+        _ => None,
+    }
+}
+
+impl CompressorOxide {
+    /// Serialize the complete compressor state into a byte vector.
+    ///
+    /// The returned checkpoint can be stored and later passed to
+    /// [`CompressorOxide::from_checkpoint()`] to continue compressing, even in a different
+    /// process. Input that has already been passed to [`compress`](super::core::compress)
+    /// does not need to be supplied again, and any output that was buffered internally
+    /// when the checkpoint was taken will be emitted by the restored compressor.
+    ///
+    /// The checkpoint is a few hundred KiB, as it includes the 32 KiB window, the hash
+    /// chains and the internal output buffer.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut w = Writer {
+            out: Vec::with_capacity(
+                LZ_DICT_FULL_SIZE + LZ_DICT_SIZE * 4 + LZ_CODE_BUF_SIZE + OUT_BUF_SIZE + 4096,
+            ),
+        };
+        w.bytes(&CHECKPOINT_MAGIC);
+        w.u8(CHECKPOINT_VERSION);
+
+        let p = &self.params;
+        w.u32(p.flags);
+        w.u8(p.greedy_parsing as u8);
+        w.u32(p.max_lazy);
+        w.u32(p.block_index);
+        w.u32(p.saved_match_dist);
+        w.u32(p.saved_match_len);
+        w.u8(p.saved_lit);
+        w.u8(p.flush as u8);
+        w.u32(p.flush_ofs);
+        w.u32(p.flush_remaining);
+        w.u8(p.finished as u8);
+        w.u32(p.adler32);
+        w.usize(p.src_pos);
+        w.usize(p.out_buf_ofs);
+        w.u8(status_to_u8(p.prev_return_status));
+        w.u32(p.saved_bit_buffer);
+        w.u32(p.saved_bits_in);
+        w.u32(p.incompressible_blocks);
+        match p.adaptive {
+            Some(adaptive) => {
+                w.u8(1);
+                w.u8(adaptive.min_level);
+                w.u8(adaptive.max_level);
+                w.usize(adaptive.sample_size);
+                w.usize(adaptive.interval);
+            }
+            None => w.u8(0),
+        }
+        w.u64(p.adaptive_next);
+        w.u64(p.block_in);
+        w.u64(p.block_out);
+        match &p.boundaries {
+            Some(boundaries) => {
+                w.u8(1);
+                w.usize(boundaries.len());
+                for boundary in boundaries {
+                    w.u64(boundary.in_offset);
+                    w.u64(boundary.out_bit_offset);
+                    w.u8(boundary.flush as u8);
+                }
+            }
+            None => w.u8(0),
+        }
+        w.u64(p.flush_interval);
+        w.u64(p.next_flush_at);
+        w.u64(p.full_flush_in);
+        match &p.stats {
+            Some(stats) => {
+                w.u8(1);
+                w.u64(stats.literals);
+                w.u64(stats.matches);
+                w.u64(stats.match_bytes);
+                w.u64(stats.match_distances);
+                w.u64(stats.stored_blocks);
+                w.u64(stats.static_blocks);
+                w.u64(stats.dynamic_blocks);
+                w.u64(stats.input_bytes);
+                w.u64(stats.match_searches);
+                w.u64(stats.chain_probes);
+                w.u64(stats.hash_collisions);
+                w.u64(stats.lazy_rejected);
+            }
+            None => w.u8(0),
+        }
+        w.u64(p.total_in);
+        w.u64(p.total_out);
+        w.bytes(&p.local_buf.b);
+
+        let lz = &self.lz;
+        w.usize(lz.code_position);
+        w.usize(lz.flag_position);
+        w.u32(lz.total_bytes);
+        w.u32(lz.num_flags_left);
+        w.bytes(&lz.codes[..]);
+
+        for table in &self.huff.count {
+            w.u16s(table);
+        }
+        for table in &self.huff.codes {
+            w.u16s(table);
+        }
+        for table in &self.huff.code_sizes {
+            w.bytes(table);
+        }
+
+        let d = &self.dict;
+        w.u32(d.max_probes[0]);
+        w.u32(d.max_probes[1]);
+        w.usize(d.code_buf_dict_pos);
+        w.usize(d.lookahead_size);
+        w.usize(d.lookahead_pos);
+        w.usize(d.size);
+        w.u32(d.good_length);
+        w.u32(d.nice_length);
+        w.u32(d.max_chain);
+        w.u8(d.hash_len);
+        w.usize(d.insert_pos);
+        w.bytes(&d.b.dict[..]);
+        w.u16s(&d.b.next[..]);
+        w.u16s(&d.b.hash[..]);
+
+        w.out
+    }
+
+    /// Restore a compressor from a checkpoint created by [`CompressorOxide::checkpoint()`].
+    ///
+    /// Returns `None` if the data is truncated, has trailing bytes, was produced by an
+    /// incompatible version, or contains values that are out of range.
+    ///
+    /// A metrics sink is not part of the checkpoint, so it has to be set again on the restored
+    /// compressor. Neither is a decision trace being recorded or replayed.
+    pub fn from_checkpoint(data: &[u8]) -> Option<CompressorOxide> {
+        let mut r = Reader { data };
+        if r.take(4)? != CHECKPOINT_MAGIC || r.u8()? != CHECKPOINT_VERSION {
+            return None;
+        }
+
+        let mut params = ParamsOxide {
+            flags: r.u32()?,
+            greedy_parsing: r.bool()?,
+            max_lazy: r.u32()?,
+            block_index: r.u32()?,
+            saved_match_dist: r.u32()?,
+            saved_match_len: r.u32()?,
+            saved_lit: r.u8()?,
+            flush: TDEFLFlush::new(r.u8()?.into()).ok()?,
+            flush_ofs: r.u32()?,
+            flush_remaining: r.u32()?,
+            finished: r.bool()?,
+            adler32: r.u32()?,
+            src_pos: r.usize()?,
+            out_buf_ofs: r.usize()?,
+            prev_return_status: status_from_u8(r.u8()?)?,
+            saved_bit_buffer: r.u32()?,
+            saved_bits_in: r.u32()?,
+            incompressible_blocks: r.u32()?,
+            adaptive: if r.bool()? {
+                Some(AdaptiveLevel {
+                    min_level: r.u8()?,
+                    max_level: r.u8()?,
+                    sample_size: r.usize()?,
+                    interval: r.usize()?,
+                })
+            } else {
+                None
+            },
+            adaptive_next: r.u64()?,
+            block_in: r.u64()?,
+            block_out: r.u64()?,
+            boundaries: if r.bool()? {
+                let len = r.usize()?;
+                // Each boundary takes 17 bytes, don't trust the length for the allocation.
+                let mut boundaries = Vec::with_capacity(len.min(r.data.len() / 17));
+                for _ in 0..len {
+                    boundaries.push(BlockBoundary {
+                        in_offset: r.u64()?,
+                        out_bit_offset: r.u64()?,
+                        flush: TDEFLFlush::new(r.u8()?.into()).ok()?,
+                    });
+                }
+                Some(boundaries)
+            } else {
+                None
+            },
+            flush_interval: r.u64()?,
+            next_flush_at: r.u64()?,
+            full_flush_in: r.u64()?,
+            stats: if r.bool()? {
+                Some(CompressionStats {
+                    literals: r.u64()?,
+                    matches: r.u64()?,
+                    match_bytes: r.u64()?,
+                    match_distances: r.u64()?,
+                    stored_blocks: r.u64()?,
+                    static_blocks: r.u64()?,
+                    dynamic_blocks: r.u64()?,
+                    input_bytes: r.u64()?,
+                    match_searches: r.u64()?,
+                    chain_probes: r.u64()?,
+                    hash_collisions: r.u64()?,
+                    lazy_rejected: r.u64()?,
+                    // Times from another run of the program say nothing about this one.
+                    #[cfg(feature = "block-timing")]
+                    time: Default::default(),
+                    #[cfg(feature = "block-timing")]
+                    slowest_block: Default::default(),
+                })
+            } else {
+                None
+            },
+            total_in: r.u64()?,
+            total_out: r.u64()?,
+            local_buf: Storage::default(),
+            timer: Default::default(),
+            tracer: None,
+        };
+        r.bytes_into(&mut params.local_buf.b)?;
+        let saved_match = params.saved_match_len != 0;
+        let adaptive_ok = params
+            .adaptive
+            .is_none_or(|a| a.min_level <= MAX_LEVEL && a.max_level <= MAX_LEVEL);
+        if params.saved_bits_in > 32
+            || params.flush_ofs as usize > OUT_BUF_SIZE
+            || params.flush_remaining as usize > OUT_BUF_SIZE - params.flush_ofs as usize
+            || (params.flush_interval != 0 && params.next_flush_at < params.total_in)
+            || (saved_match
+                && !(u32::from(MIN_MATCH_LEN)..=MAX_MATCH_LEN as u32)
+                    .contains(&params.saved_match_len))
+            || params.saved_match_dist as usize > LZ_DICT_SIZE
+            || (saved_match && params.saved_match_dist == 0)
+            // The positions in the buffers of the last call are at most what it consumed and
+            // produced.
+            || params.src_pos as u64 > params.total_in
+            || params.out_buf_ofs as u64 > params.total_out
+            // Nothing is output before the first block is.
+            || params.block_index == u32::MAX
+            || (params.block_index == 0 && (params.total_out != 0 || params.flush_remaining != 0))
+            || !adaptive_ok
+        {
+            return None;
+        }
+
+        let mut lz = LZOxide {
+            code_position: r.usize()?,
+            flag_position: r.usize()?,
+            total_bytes: r.u32()?,
+            num_flags_left: r.u32()?,
+            codes: Storage::boxed_array(),
+        };
+        r.bytes_into(&mut lz.codes[..])?;
+        if lz.code_position >= LZ_CODE_BUF_SIZE
+            || lz.flag_position >= LZ_CODE_BUF_SIZE
+            || !(1..=8).contains(&lz.num_flags_left)
+            || !lz_codes_valid(&lz)
+        {
+            return None;
+        }
+
+        let mut huff = Storage::<HuffmanOxide>::default();
+        for table in huff.count.iter_mut() {
+            r.u16s_into(table)?;
+        }
+        for table in huff.codes.iter_mut() {
+            r.u16s_into(table)?;
+        }
+        for table in huff.code_sizes.iter_mut() {
+            r.bytes_into(table)?;
+        }
+        // The codes are written to the bit buffer with their sizes, so a code that is longer
+        // than deflate allows or doesn't fit in its size would corrupt it.
+        let codes_ok = huff
+            .codes
+            .iter()
+            .zip(&huff.code_sizes)
+            .all(|(codes, sizes)| {
+                codes.iter().zip(sizes.iter()).all(|(&code, &size)| {
+                    usize::from(size) <= MAX_SUPPORTED_HUFF_CODESIZE && u32::from(code) >> size == 0
+                })
+            });
+        if !codes_ok {
+            return None;
+        }
+
+        let mut dict = DictOxide {
+            max_probes: [r.u32()?, r.u32()?],
+            code_buf_dict_pos: r.usize()?,
+            lookahead_size: r.usize()?,
+            lookahead_pos: r.usize()?,
+            size: r.usize()?,
+            good_length: r.u32()?,
+            nice_length: r.u32()?,
+            max_chain: r.u32()?,
+            hash_len: r.u8()?,
+            insert_pos: r.usize()?,
+            // Depends on the build configuration rather than the compressor state, so it is
+            // not stored.
+            loop_len: (256 / MATCH_COMPARE_BYTES) as u8,
+            b: HashBuffers::default(),
+            #[cfg(feature = "invariant-checks")]
+            chains_from: 0,
+        };
+        r.bytes_into(&mut dict.b.dict[..])?;
+        r.u16s_into(&mut dict.b.next[..])?;
+        r.u16s_into(&mut dict.b.hash[..])?;
+        // The data added to the window is at most the input plus a preset dictionary.
+        let dict_end = dict.lookahead_pos.checked_add(dict.lookahead_size)?;
+        let max_dict_end = params.total_in.checked_add(LZ_DICT_SIZE as u64)?;
+        if dict.lookahead_size > LZ_DICT_SIZE
+            || dict.size > LZ_DICT_SIZE
+            || !(u32::from(MIN_MATCH_LEN)..=MAX_MATCH_LEN as u32).contains(&dict.nice_length)
+            || !(MIN_MATCH_LEN..=MAX_HASH_LEN).contains(&dict.hash_len)
+            || dict_end as u64 > max_dict_end
+            || dict.code_buf_dict_pos > dict.lookahead_pos
+            || (lz.total_bytes as usize) > dict.lookahead_pos - dict.code_buf_dict_pos
+            || dict.insert_pos > dict_end
+            || dict.max_chain > u32::from(u16::MAX)
+            || dict.max_probes != probes_from_chain(dict.max_chain)
+        {
+            return None;
+        }
+
+        if !r.data.is_empty() {
+            return None;
+        }
+        // Whether the chains were complete isn't stored, so only check the ones from here on.
+        #[cfg(feature = "invariant-checks")]
+        dict.restart_chains();
+
+        Some(CompressorOxide {
+            lz,
+            params,
+            huff,
+            dict,
+            #[cfg(feature = "std")]
+            metrics: None,
+        })
+    }
+}
+
+/// Walk the LZ codes of the current block like `compress_lz_codes` does, checking that the
+/// groups of codes end where the flag position says and that every match distance is inside
+/// the window.
+fn lz_codes_valid(lz: &LZOxide) -> bool {
+    let mut pos = 0;
+    while pos <= lz.flag_position {
+        let last = pos == lz.flag_position;
+        // Each flag byte is followed by 8 codes, except the one still being filled, whose
+        // flags are at the top of the byte.
+        let (mut flags, count) = if last {
+            (
+                u32::from(lz.codes[pos]) >> lz.num_flags_left,
+                8 - lz.num_flags_left,
+            )
+        } else {
+            (u32::from(lz.codes[pos]), 8)
+        };
+        pos += 1;
+        for _ in 0..count {
+            // A literal is one byte, a match the length and the distance minus one as a
+            // little-endian u16.
+            let len = if flags & 1 == 1 { 3 } else { 1 };
+            if pos + len > lz.code_position
+                || (len == 3
+                    && usize::from(u16::from_le_bytes([lz.codes[pos + 1], lz.codes[pos + 2]]))
+                        >= LZ_DICT_SIZE)
+            {
+                return false;
+            }
+            pos += len;
+            flags >>= 1;
+        }
+        if last {
+            return pos == lz.code_position;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deflate::core::compress;
+    use crate::deflate::core::deflate_flags::TDEFL_WRITE_ZLIB_HEADER;
+    use crate::inflate::decompress_to_vec_zlib;
+    use alloc::vec;
+
+    fn compress_chunk(d: &mut CompressorOxide, input: &[u8], flush: TDEFLFlush) -> Vec<u8> {
+        let mut out = vec![0; input.len() * 2 + 1024];
+        let (status, in_consumed, out_written) = compress(d, input, &mut out, flush);
+        assert!(status != TDEFLStatus::BadParam);
+        assert_eq!(in_consumed, input.len());
+        out.truncate(out_written);
+        out
+    }
+
+    #[test]
+    fn resume_from_checkpoint_matches_uninterrupted() {
+        let data = crate::test_util::text(200_000);
+        let (first, second) = data.split_at(70_000);
+
+        let mut reference = CompressorOxide::new(TDEFL_WRITE_ZLIB_HEADER | 32);
+        let mut expected = compress_chunk(&mut reference, first, TDEFLFlush::None);
+        expected.extend(compress_chunk(&mut reference, second, TDEFLFlush::Finish));
+
+        let mut d = CompressorOxide::new(TDEFL_WRITE_ZLIB_HEADER | 32);
+        let mut actual = compress_chunk(&mut d, first, TDEFLFlush::None);
+        let snapshot = d.checkpoint();
+        drop(d);
+
+        let mut restored = CompressorOxide::from_checkpoint(&snapshot).unwrap();
+        actual.extend(compress_chunk(&mut restored, second, TDEFLFlush::Finish));
+
+        assert_eq!(actual, expected);
+        assert_eq!(restored.total_in(), data.len() as u64);
+        assert_eq!(restored.total_out(), actual.len() as u64);
+        assert_eq!(decompress_to_vec_zlib(&actual).unwrap(), data);
+    }
+
+    #[test]
+    fn reject_invalid_checkpoint() {
+        let snapshot = CompressorOxide::default().checkpoint();
+        assert!(CompressorOxide::from_checkpoint(&snapshot).is_some());
+        assert!(CompressorOxide::from_checkpoint(&snapshot[..snapshot.len() - 1]).is_none());
+        let mut bad = snapshot.clone();
+        bad[0] = b'X';
+        assert!(CompressorOxide::from_checkpoint(&bad).is_none());
+        let mut extra = snapshot;
+        extra.push(0);
+        assert!(CompressorOxide::from_checkpoint(&extra).is_none());
+    }
+
+    #[test]
+    fn reject_out_of_range_fields() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut d = CompressorOxide::new(TDEFL_WRITE_ZLIB_HEADER | 32);
+        compress_chunk(&mut d, &data[..20_000], TDEFLFlush::None);
+        let snapshot = d.checkpoint();
+        assert!(CompressorOxide::from_checkpoint(&snapshot).is_some());
+
+        let with = |pos: usize, bytes: &[u8]| {
+            let mut bad = snapshot.clone();
+            bad[pos..pos + bytes.len()].copy_from_slice(bytes);
+            CompressorOxide::from_checkpoint(&bad)
+        };
+        // The fields at the start of the parameters.
+        let (block_index, match_dist, match_len, src_pos) = (14, 18, 22, 41);
+        for len in [1, 2, 259] {
+            assert!(with(match_len, &u32::to_le_bytes(len)).is_none());
+        }
+        let saved_match = [3, 0, 0, 0, 0, 0, 0, 0];
+        assert!(with(match_dist, &saved_match).is_some());
+        assert!(with(match_dist, &[0, 0, 0, 0, 3, 0, 0, 0]).is_none());
+        assert!(with(match_dist, &u32::to_le_bytes(40_000)).is_none());
+        assert!(with(block_index, &u32::MAX.to_le_bytes()).is_none());
+        assert!(with(src_pos, &u64::MAX.to_le_bytes()).is_none());
+
+        // The fields at the end of the dictionary state, before its buffers.
+        let b = &d.dict.b;
+        let end = snapshot.len() - b.dict.len() - 2 * (b.next.len() + b.hash.len());
+        let (insert_pos, max_chain, lookahead_pos, code_buf_dict_pos, max_probes) =
+            (end - 8, end - 13, end - 37, end - 53, end - 61);
+        assert!(with(insert_pos, &u64::MAX.to_le_bytes()).is_none());
+        assert!(with(max_chain, &u32::MAX.to_le_bytes()).is_none());
+        assert!(with(max_probes, &u32::MAX.to_le_bytes()).is_none());
+        assert!(with(lookahead_pos, &u64::MAX.to_le_bytes()).is_none());
+        assert!(with(lookahead_pos, &0u64.to_le_bytes()).is_none());
+        assert!(with(code_buf_dict_pos, &u64::MAX.to_le_bytes()).is_none());
+
+        // The LZ codes and Huffman tables come before. Find the distance of the first match
+        // in the LZ codes, going through the groups of a flag byte and 8 codes.
+        let symbols = d.huff.code_sizes.len() * d.huff.code_sizes[0].len();
+        let code_sizes = max_probes - symbols;
+        let lz_codes = code_sizes - 4 * symbols - LZ_CODE_BUF_SIZE;
+        let mut pos = 0;
+        let dist = 'find: loop {
+            let flags = d.lz.codes[pos];
+            pos += 1;
+            for i in 0..8 {
+                if flags >> i & 1 == 1 {
+                    break 'find lz_codes + pos + 1;
+                }
+                pos += 1;
+            }
+        };
+        assert!(dist < lz_codes + d.lz.code_position);
+        assert!(with(dist, &[0xFF, 0x7F]).is_some());
+        assert!(with(dist, &[0x00, 0x80]).is_none());
+        assert!(with(lz_codes, &[d.lz.codes[0] ^ 1]).is_none());
+        assert!(with(code_sizes, &[16]).is_none());
+    }
+}
@@ -1,20 +1,35 @@
 //! Streaming compression functionality.
 
+#[cfg(feature = "with-alloc")]
 use alloc::boxed::Box;
+#[cfg(feature = "with-alloc")]
+use alloc::vec::Vec;
 use core::convert::TryInto;
 use core::{cmp, mem};
 
 use super::super::*;
 use super::deflate_flags::*;
 use super::CompressionLevel;
+use crate::deflate::adaptive::estimate_ratio;
 use crate::deflate::buffer::{
-    update_hash, HashBuffers, LocalBuf, LZ_CODE_BUF_MASK, LZ_CODE_BUF_SIZE, LZ_DICT_FULL_SIZE,
-    LZ_HASH_BITS, LZ_HASH_SHIFT, LZ_HASH_SIZE, OUT_BUF_SIZE,
+    update_hash, HashBuffers, LocalBuf, Storage, LZ_CODE_BUF_MASK, LZ_CODE_BUF_SIZE,
+    LZ_DICT_FULL_SIZE, LZ_HASH_BITS, LZ_HASH_SHIFT, LZ_HASH_SIZE, OUT_BUF_SIZE,
 };
+use crate::deflate::match_compare::{matching_bytes, MATCH_COMPARE_BYTES};
+#[cfg(feature = "with-alloc")]
+use crate::deflate::optimal::compress_optimal;
 use crate::deflate::stored::compress_stored;
+#[cfg(feature = "with-alloc")]
+use crate::deflate::trace::{TracedBlock, Tracer};
+#[cfg(feature = "with-alloc")]
+use crate::inflate::analyze::BlockType;
 use crate::deflate::zlib;
 use crate::shared::{update_adler32, HUFFMAN_LENGTH_ORDER, MZ_ADLER32_INIT};
+#[cfg(feature = "std")]
+use crate::metrics::MetricsSink;
 use crate::DataFormat;
+#[cfg(feature = "block-timing")]
+use std::time::{Duration, Instant};
 
 // Currently not bubbled up outside this module, so can fill in with more
 // context eventually if needed.
@@ -23,14 +38,14 @@ pub(crate) struct Error {}
 
 pub(crate) const MAX_PROBES_MASK: u32 = 0xFFF;
 
-const MAX_SUPPORTED_HUFF_CODESIZE: usize = 15;
+pub(crate) const MAX_SUPPORTED_HUFF_CODESIZE: usize = 15;
 
 // Length code for length values - 256.
 // We use an offset to help with bound check avoidance as we can mask values to 32
 // and it also saves some memory as we can use a u8 instead of a u16.
 // Conventiently our table is large enough that we can get away with using an
 // offset of 256 which results in very efficient code.
-const LEN_SYM: [u8; 256] = [
+pub(crate) const LEN_SYM: [u8; 256] = [
     1, 2, 3, 4, 5, 6, 7, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 13, 13, 14, 14, 14, 14, 15, 15,
     15, 15, 16, 16, 16, 16, 17, 17, 17, 17, 17, 17, 17, 17, 18, 18, 18, 18, 18, 18, 18, 18, 19, 19,
     19, 19, 19, 19, 19, 19, 20, 20, 20, 20, 20, 20, 20, 20, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21,
@@ -44,11 +59,11 @@ const LEN_SYM: [u8; 256] = [
     28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 29,
 ];
 
-const LEN_SYM_OFFSET: usize = 256;
+pub(crate) const LEN_SYM_OFFSET: usize = 256;
 
 /// Number of extra bits for length values.
 #[rustfmt::skip]
-const LEN_EXTRA: [u8; 256] = [
+pub(crate) const LEN_EXTRA: [u8; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1,
     2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
     3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
@@ -69,7 +84,7 @@ const LEN_EXTRA: [u8; 256] = [
 
 /// Distance codes for distances smaller than 512.
 #[rustfmt::skip]
-const SMALL_DIST_SYM: [u8; 512] = [
+pub(crate) const SMALL_DIST_SYM: [u8; 512] = [
      0,  1,  2,  3,  4,  4,  5,  5,  6,  6,  6,  6,  7,  7,  7,  7,
      8,  8,  8,  8,  8,  8,  8,  8,  9,  9,  9,  9,  9,  9,  9,  9,
     10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10,
@@ -106,7 +121,7 @@ const SMALL_DIST_SYM: [u8; 512] = [
 
 /// Number of extra bits for distances smaller than 512.
 #[rustfmt::skip]
-const SMALL_DIST_EXTRA: [u8; 512] = [
+pub(crate) const SMALL_DIST_EXTRA: [u8; 512] = [
     0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
     4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
     5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
@@ -127,7 +142,7 @@ const SMALL_DIST_EXTRA: [u8; 512] = [
 
 /// Base values to calculate distances above 512.
 #[rustfmt::skip]
-const LARGE_DIST_SYM: [u8; 128] = [
+pub(crate) const LARGE_DIST_SYM: [u8; 128] = [
      0,  0, 18, 19, 20, 20, 21, 21, 22, 22, 22, 22, 23, 23, 23, 23,
     24, 24, 24, 24, 24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 25,
     26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26,
@@ -140,7 +155,7 @@ const LARGE_DIST_SYM: [u8; 128] = [
 
 /// Number of extra bits distances above 512.
 #[rustfmt::skip]
-const LARGE_DIST_EXTRA: [u8; 128] = [
+pub(crate) const LARGE_DIST_EXTRA: [u8; 128] = [
      0,  0,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 10, 10, 10, 10,
     11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11,
     12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12,
@@ -175,8 +190,12 @@ pub mod deflate_flags {
     /// Should we use greedy parsing (as opposed to lazy parsing where look ahead one or more
     /// bytes to check for better matches.)
     pub const TDEFL_GREEDY_PARSING_FLAG: u32 = 0x0000_4000;
-    /// Used in miniz to skip zero-initializing hash and dict. We don't do this here, so
-    /// this flag is ignored.
+    /// Used in miniz to skip zero-initializing hash and dict. We don't do this here.
+    ///
+    /// Instead, this allows output that differs from miniz, by defaulting to a longer hash in
+    /// the match finder (see [`MatchParams::hash_len`](super::MatchParams::hash_len)), and by
+    /// outputting a few raw blocks without searching for matches after a block that barely
+    /// compressed. Without it, the output is the same as that of miniz.
     pub const TDEFL_NONDETERMINISTIC_PARSING_FLAG: u32 = 0x0000_8000;
     /// Only look for matches with a distance of 0.
     pub const TDEFL_RLE_MATCHES: u32 = 0x0001_0000;
@@ -187,13 +206,25 @@ pub mod deflate_flags {
     pub const TDEFL_FORCE_ALL_STATIC_BLOCKS: u32 = 0x0004_0000;
     /// Force the compressor to only output raw/uncompressed blocks.
     pub const TDEFL_FORCE_ALL_RAW_BLOCKS: u32 = 0x0008_0000;
+    /// Use the quick compression path: a small hash table, greedy matching with a single probe
+    /// and only static blocks. Much faster than level 1, at the cost of a lower compression
+    /// ratio. Takes precedence over the probe count and parsing flags.
+    pub const TDEFL_QUICK_PARSING_FLAG: u32 = 0x0010_0000;
+    /// Choose matches and literals with optimal parsing over a cost model, rather than
+    /// lazily. Several times slower than the highest regular level, for a few percent smaller
+    /// output. Used for levels above 10.
+    pub const TDEFL_OPTIMAL_PARSING_FLAG: u32 = 0x0020_0000;
+    /// Always search for matches and try to compress every block, even when the previous
+    /// blocks did not compress. Only has an effect with [`TDEFL_NONDETERMINISTIC_PARSING_FLAG`],
+    /// without which every block is compressed anyway.
+    pub const TDEFL_NO_INCOMPRESSIBLE_DETECTION: u32 = 0x0040_0000;
 }
 
 /// Strategy setting for compression.
 ///
 /// The non-default settings offer some special-case compression variants.
 #[repr(i32)]
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum CompressionStrategy {
     /// Don't use any of the special strategies.
     Default = 0,
@@ -206,6 +237,9 @@ pub enum CompressionStrategy {
     /// Only use static/fixed blocks. (Blocks using the default huffman codes
     /// specified in the deflate specification.)
     Fixed = 4,
+    /// Use the quick compression path, meant for callers who only need light compression.
+    /// See [`deflate_flags::TDEFL_QUICK_PARSING_FLAG`].
+    Quick = 5,
 }
 
 impl From<CompressionStrategy> for i32 {
@@ -216,7 +250,7 @@ impl From<CompressionStrategy> for i32 {
 }
 
 /// A list of deflate flush types.
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum TDEFLFlush {
     /// Normal operation.
     ///
@@ -262,7 +296,8 @@ impl TDEFLFlush {
 
 /// Return status of compression.
 #[repr(i32)]
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TDEFLStatus {
     /// Usage error.
     ///
@@ -272,7 +307,9 @@ pub enum TDEFLStatus {
 
     /// Error putting data into output buffer.
     ///
-    /// This usually indicates a too-small buffer.
+    /// This usually indicates a too-small buffer. With [`compress_to_output`], it means that the
+    /// callback returned `false` to stop the compression, which is not a failure of the
+    /// compressor itself.
     PutBufFailed = -1,
 
     /// Compression succeeded normally.
@@ -284,16 +321,52 @@ pub enum TDEFLStatus {
     Done = 1,
 }
 
+impl TDEFLStatus {
+    /// Get the numeric code of the status, for passing it through C or WASM bindings.
+    ///
+    /// The codes are stable, the same as in miniz, and negative for failures.
+    pub const fn to_code(self) -> i32 {
+        self as i32
+    }
+
+    /// Get the status for a numeric code from [`to_code`][Self::to_code], or `None` if it isn't
+    /// one.
+    pub const fn from_code(code: i32) -> Option<TDEFLStatus> {
+        match code {
+            -2 => Some(TDEFLStatus::BadParam),
+            -1 => Some(TDEFLStatus::PutBufFailed),
+            0 => Some(TDEFLStatus::Okay),
+            1 => Some(TDEFLStatus::Done),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for TDEFLStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            TDEFLStatus::BadParam => "Compressor used after an error or the end of the stream",
+            TDEFLStatus::PutBufFailed => "Failed to write the compressed output",
+            TDEFLStatus::Okay => "Compression succeeded",
+            TDEFLStatus::Done => "Finished compression",
+        })
+    }
+}
+
+impl core::error::Error for TDEFLStatus {}
+
 const MAX_HUFF_SYMBOLS: usize = 288;
 /// Size of hash chain for fast compression mode.
 const LEVEL1_HASH_SIZE_MASK: u32 = 4095;
+/// Number of bits in the hash used by the quick compression path.
+const QUICK_HASH_BITS: u32 = 12;
 /// The number of huffman tables used by the compressor.
 /// Literal/length, Distances and Length of the huffman codes for the other two tables.
 const MAX_HUFF_TABLES: usize = 3;
 /// Literal/length codes
-const MAX_HUFF_SYMBOLS_0: usize = 288;
+pub(crate) const MAX_HUFF_SYMBOLS_0: usize = 288;
 /// Distance codes.
-const MAX_HUFF_SYMBOLS_1: usize = 32;
+pub(crate) const MAX_HUFF_SYMBOLS_1: usize = 32;
 /// Huffman length values.
 const MAX_HUFF_SYMBOLS_2: usize = 19;
 /// Size of the chained hash table.
@@ -322,13 +395,20 @@ const fn read_u16_le<const N: usize>(slice: &[u8; N], pos: usize) -> u16 {
 }
 
 /// Main compression struct.
+///
+/// Cloning a compressor forks the complete state, so the same input can be compressed in
+/// different ways (e.g. with different flush modes or levels) and the better result kept.
+#[cfg_attr(feature = "with-alloc", derive(Clone))]
 pub struct CompressorOxide {
     pub(crate) lz: LZOxide,
     pub(crate) params: ParamsOxide,
     /// Put HuffmanOxide on the heap with default trick to avoid
     /// excessive stack copies.
-    pub(crate) huff: Box<HuffmanOxide>,
+    pub(crate) huff: Storage<HuffmanOxide>,
     pub(crate) dict: DictOxide,
+    /// Where to report the bytes going through, flushes and errors, if anywhere.
+    #[cfg(feature = "std")]
+    pub(crate) metrics: Option<std::sync::Arc<dyn MetricsSink>>,
 }
 
 impl CompressorOxide {
@@ -336,20 +416,123 @@ impl CompressorOxide {
     ///
     /// # Notes
     /// This function may be changed to take different parameters in the future.
+    #[cfg(feature = "with-alloc")]
     pub fn new(flags: u32) -> Self {
         CompressorOxide {
-            lz: LZOxide::new(),
-            params: ParamsOxide::new(flags),
-            huff: Box::default(),
-            dict: DictOxide::new(flags),
+            lz: LZOxide::new(Storage::boxed_array()),
+            params: ParamsOxide::new(flags, Storage::default()),
+            huff: Storage::default(),
+            dict: DictOxide::new(flags, HashBuffers::default()),
+            #[cfg(feature = "std")]
+            metrics: None,
         }
     }
 
+    /// Create a new `CompressorOxide` with the given flags, keeping all of its large buffers
+    /// in `buffers` instead of allocating them.
+    ///
+    /// This allows compressing without `alloc`, e.g. with the buffers in a `static`. Note
+    /// that the optimal parsing of [`CompressionLevel::UltraCompression`] needs to allocate,
+    /// so it falls back to the parsing of [`CompressionLevel::UberCompression`] without the
+    /// `with-alloc` feature.
+    ///
+    /// Cloning the compressor allocates copies of the buffers.
+    pub fn with_buffers(flags: u32, buffers: &'static mut CompressorBuffers) -> Self {
+        let CompressorBuffers {
+            huff,
+            local_buf,
+            codes,
+            dict,
+            next,
+            hash,
+        } = buffers;
+        let mut compressor = CompressorOxide {
+            lz: LZOxide::new(Storage::Static(codes)),
+            params: ParamsOxide::new(flags, Storage::Static(local_buf)),
+            huff: Storage::Static(huff),
+            dict: DictOxide::new(
+                flags,
+                HashBuffers {
+                    dict: Storage::Static(dict),
+                    next: Storage::Static(next),
+                    hash: Storage::Static(hash),
+                },
+            ),
+            #[cfg(feature = "std")]
+            metrics: None,
+        };
+        // The buffers may have been used before.
+        compressor.reset();
+        compressor
+    }
+
+    /// Start configuring a new compressor with a [`CompressorBuilder`].
+    pub fn builder<'a>() -> CompressorBuilder<'a> {
+        CompressorBuilder::new()
+    }
+
     /// Get the adler32 checksum of the currently encoded data.
     pub const fn adler32(&self) -> u32 {
         self.params.adler32
     }
 
+    /// Get the total number of input bytes consumed since the compressor was created or reset.
+    pub const fn total_in(&self) -> u64 {
+        self.params.total_in
+    }
+
+    /// Get the total number of compressed bytes output since the compressor was created or reset.
+    pub const fn total_out(&self) -> u64 {
+        self.params.total_out
+    }
+
+    /// Get the number of compressed bytes that are buffered inside the compressor and will be
+    /// emitted on the next call to [`compress`](fn.compress.html) or [`compress_to_output`]
+    /// before any new data.
+    ///
+    /// Input that has been consumed but not yet encoded into a block is not included, as
+    /// its compressed size is not known until the block is flushed.
+    pub const fn pending_bytes(&self) -> usize {
+        self.params.flush_remaining as usize
+    }
+
+    /// Report the bytes consumed and produced, the completed flushes and the errors of this
+    /// compressor to `metrics`, or stop reporting them with `None`.
+    ///
+    /// The sink is kept when the compressor is reset, and shared with its clones.
+    #[cfg(feature = "std")]
+    pub fn set_metrics(&mut self, metrics: Option<std::sync::Arc<dyn MetricsSink>>) {
+        self.metrics = metrics;
+    }
+
+    /// Report a call to [`compress`] or [`compress_to_output`] that consumed `in_bytes` of
+    /// `in_len` bytes and produced `out_bytes` to the metrics sink, if there is one.
+    #[cfg(feature = "std")]
+    fn record_metrics(
+        &self,
+        status: TDEFLStatus,
+        flush: TDEFLFlush,
+        (in_len, in_bytes, out_bytes): (usize, usize, u64),
+    ) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        if in_bytes > 0 {
+            metrics.bytes_consumed(in_bytes as u64);
+        }
+        if out_bytes > 0 {
+            metrics.bytes_produced(out_bytes);
+        }
+        match status {
+            TDEFLStatus::BadParam => metrics.error(MZError::Param),
+            TDEFLStatus::PutBufFailed => metrics.error(MZError::Stream),
+            _ if flush != TDEFLFlush::None && in_bytes == in_len && self.pending_bytes() == 0 => {
+                metrics.flushed()
+            }
+            _ => {}
+        }
+    }
+
     /// Get the return status of the previous [`compress`](fn.compress.html)
     /// call with this compressor.
     pub const fn prev_return_status(&self) -> TDEFLStatus {
@@ -377,14 +560,30 @@ impl CompressorOxide {
     ///
     /// This avoids re-allocating data.
     pub fn reset(&mut self) {
-        // LZ buf and huffman has no settings or dynamic memory
-        // that needs to be saved, so we simply replace them.
-        self.lz = LZOxide::new();
+        // The output buffer is not cleared otherwise, and may hold stored blocks.
+        #[cfg(feature = "zeroize")]
+        self.wipe();
+        // LZ buf and huffman has no settings that need to be saved, so we simply clear them.
+        self.lz.reset();
         self.params.reset();
-        *self.huff = HuffmanOxide::default();
+        *self.huff = HuffmanOxide::new();
         self.dict.reset();
     }
 
+    /// Overwrite all the buffers with zeroes, in a way the compiler can't leave out.
+    #[cfg(feature = "zeroize")]
+    fn wipe(&mut self) {
+        use zeroize::Zeroize;
+        self.lz.codes.zeroize();
+        self.params.local_buf.b.zeroize();
+        self.huff.count.zeroize();
+        self.huff.codes.zeroize();
+        self.huff.code_sizes.zeroize();
+        self.dict.b.dict.zeroize();
+        self.dict.b.next.zeroize();
+        self.dict.b.hash.zeroize();
+    }
+
     /// Set the compression level of the compressor.
     ///
     /// Using this to change level after compression has started is supported.
@@ -420,27 +619,440 @@ impl CompressorOxide {
             data_format.to_window_bits(),
             CompressionStrategy::Default as i32,
         );
+        self.change_flags(flags);
+    }
+
+    /// Get the parameters currently used by the match finder.
+    pub fn match_params(&self) -> MatchParams {
+        MatchParams {
+            good_length: self.dict.good_length as u16,
+            max_lazy: self.params.max_lazy as u16,
+            nice_length: self.dict.nice_length as u16,
+            max_chain: self.dict.max_chain as u16,
+            hash_len: self.dict.hash_len,
+        }
+    }
+
+    /// Override the match finder parameters of the current compression level.
+    ///
+    /// Changing these after compression has started is supported.
+    /// # Notes
+    /// The parameters are reset to the defaults of the level when the level is changed.
+    pub fn set_match_params(&mut self, match_params: MatchParams) {
+        self.dict.good_length = u32::from(match_params.good_length);
+        self.dict.nice_length = u32::from(match_params.nice_length)
+            .clamp(u32::from(MIN_MATCH_LEN), MAX_MATCH_LEN as u32);
+        self.dict.max_chain = u32::from(match_params.max_chain);
+        self.dict.max_probes = probes_from_chain(self.dict.max_chain);
+        self.dict.hash_len = match_params.hash_len.clamp(MIN_MATCH_LEN, MAX_HASH_LEN);
+        self.params.max_lazy = u32::from(match_params.max_lazy);
+        #[cfg(feature = "invariant-checks")]
+        self.dict.restart_chains();
+    }
+
+    /// Load `dictionary` into the compressor as if it had been compressed just before the
+    /// following data, so that the data can refer back to it. Only the last 32 KiB are used.
+    ///
+    /// Must be called before compressing anything, after setting the match parameters. The
+    /// dictionary is not part of the output or the checksum, and the zlib header doesn't
+    /// indicate that one was used, so the data can only be decompressed following the same
+    /// dictionary. It is therefore mainly of use with raw deflate data.
+    ///
+    /// To compress a lot of data with the same dictionary, `PreparedDictionary` avoids
+    /// hashing it each time.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let hash = self.dictionary_hash();
+        let (len, insert_pos) = load_dictionary(&mut self.dict.b, dictionary, hash);
+        self.dict.set_dictionary_len(len, insert_pos);
+    }
+
+    /// Load a [`PreparedDictionary`], which is the same as calling
+    /// [`set_dictionary`](Self::set_dictionary) with it, except that it only needs to be
+    /// copied.
+    ///
+    /// If the dictionary was prepared for different settings than the ones the compressor
+    /// uses, it is hashed again.
+    #[cfg(feature = "with-alloc")]
+    pub fn set_prepared_dictionary(&mut self, dictionary: &PreparedDictionary) {
+        if dictionary.hash != self.dictionary_hash() {
+            self.set_dictionary(dictionary.as_bytes());
+            return;
+        }
+        self.dict.b.dict.copy_from_slice(&dictionary.b.dict[..]);
+        self.dict.b.next.copy_from_slice(&dictionary.b.next[..]);
+        self.dict.b.hash.copy_from_slice(&dictionary.b.hash[..]);
+        self.dict
+            .set_dictionary_len(dictionary.len, dictionary.insert_pos);
+    }
+
+    fn dictionary_hash(&self) -> DictionaryHash {
+        DictionaryHash::new(self.params.flags, self.dict.hash_len)
+    }
+
+    /// Pick the compression level from samples of the input, or stop doing so with `None`.
+    ///
+    /// The first sample is taken from the input of the next call to
+    /// [`compress`](fn.compress.html) with at least 1 KiB of input (or with a flush), and
+    /// further ones every [`AdaptiveLevel::interval`] bytes after it. Changing the level
+    /// this way keeps the data format and strategy, but resets the match parameters.
+    pub fn set_adaptive_level(&mut self, adaptive: Option<AdaptiveLevel>) {
+        self.params.adaptive = adaptive;
+        self.params.adaptive_next = self.params.total_in;
+    }
+
+    /// Get the settings for picking the compression level, if enabled.
+    pub const fn adaptive_level(&self) -> Option<AdaptiveLevel> {
+        self.params.adaptive
+    }
+
+    /// Start or stop recording the end of each block, except the last one of the stream.
+    ///
+    /// The boundaries are kept until taken with [`take_boundaries`](Self::take_boundaries),
+    /// or the compressor is reset. Stopping drops the ones not taken yet.
+    #[cfg(feature = "with-alloc")]
+    pub fn set_record_boundaries(&mut self, record: bool) {
+        if record != self.params.boundaries.is_some() {
+            self.params.boundaries = record.then(Vec::new);
+        }
+    }
+
+    /// Get the block boundaries recorded so far.
+    #[cfg(feature = "with-alloc")]
+    pub fn boundaries(&self) -> &[BlockBoundary] {
+        self.params.boundaries.as_deref().unwrap_or(&[])
+    }
+
+    /// Insert a full flush after every `interval` bytes of input, or stop doing so with 0.
+    ///
+    /// A full flush ends the block at a byte boundary and forgets the previous data, so
+    /// decompression can start over from it, e.g. to resynchronize after losing part of the
+    /// stream, or to resume an interrupted download. Points are counted from the current
+    /// position, and each one costs a few bytes and some compression, so intervals of less
+    /// than a few hundred KiB are not recommended.
+    pub fn set_full_flush_interval(&mut self, interval: usize) {
+        self.params.flush_interval = interval as u64;
+        self.params.next_flush_at = self.params.total_in + interval as u64;
+    }
+
+    /// Get the number of input bytes between full flush points, 0 if disabled.
+    pub const fn full_flush_interval(&self) -> usize {
+        self.params.flush_interval as usize
+    }
+
+    /// Take the block boundaries recorded so far, leaving recording enabled if it was.
+    #[cfg(feature = "with-alloc")]
+    pub fn take_boundaries(&mut self) -> Vec<BlockBoundary> {
+        self.params
+            .boundaries
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Start or stop collecting [`CompressionStats`] of the blocks output.
+    ///
+    /// The statistics are kept when the stream is finished, until the compressor is reset.
+    /// Stopping drops them. Collecting them takes an extra pass over the matches found for
+    /// each block.
+    pub fn set_collect_stats(&mut self, collect: bool) {
+        if collect != self.params.stats.is_some() {
+            self.params.stats = collect.then(CompressionStats::default);
+        }
+    }
+
+    /// Get the statistics of the blocks output so far, if they are being collected.
+    pub const fn stats(&self) -> Option<CompressionStats> {
+        self.params.stats
+    }
+
+    /// Switch to the settings of `level` in the middle of the stream, keeping the other
+    /// flags.
+    fn switch_level(&mut self, level: u8) {
+        let level_flags = create_comp_flags_from_zip_params(level.into(), 0, 0);
+        let flags = (self.params.flags & !LEVEL_FLAGS) | (level_flags & LEVEL_FLAGS);
+        if flags != self.params.flags {
+            self.change_flags(flags);
+        }
+    }
+
+    /// Change the flags, bringing the state left by the compression function of the old
+    /// flags into a form the one of the new flags can carry on from.
+    pub(crate) fn change_flags(&mut self, flags: u32) {
+        let path = CompressPath::from_flags(flags);
+        if CompressPath::from_flags(self.params.flags) == CompressPath::Stored
+            && path != CompressPath::Stored
+        {
+            // The stored path only counts the bytes of the current block, so add them as
+            // literals for the other paths.
+            let len = self.lz.total_bytes as usize;
+            self.lz.total_bytes = 0;
+            for i in 0..len {
+                let pos = (self.dict.code_buf_dict_pos + i) & LZ_DICT_SIZE_MASK;
+                record_literal(&mut self.huff, &mut self.lz, self.dict.b.dict[pos]);
+            }
+        }
+        if self.params.saved_match_len != 0 && path != CompressPath::Normal {
+            // Only the default path knows about a byte held back while checking for a longer
+            // match, so output it as a literal.
+            record_literal(&mut self.huff, &mut self.lz, self.params.saved_lit);
+            self.params.saved_match_len = 0;
+        }
         self.params.update_flags(flags);
         self.dict.update_flags(flags);
     }
 }
 
+/// Sample the input if it is time to, and switch to the level suited to it.
+fn adapt_level(d: &mut CompressorOxide, in_buf: &[u8], flush: TDEFLFlush) {
+    let adaptive = match d.params.adaptive {
+        Some(adaptive) => adaptive,
+        None => return,
+    };
+    if d.params.total_in < d.params.adaptive_next {
+        return;
+    }
+    let sample = &in_buf[..in_buf.len().min(adaptive.sample_size)];
+    let enough = sample.len() >= cmp::min(adaptive.sample_size, ADAPTIVE_MIN_SAMPLE);
+    // Wait for more input unless the stream is being flushed.
+    if sample.is_empty() || (!enough && flush == TDEFLFlush::None) {
+        return;
+    }
+
+    d.params.adaptive_next = match adaptive.interval {
+        0 => u64::MAX,
+        interval => d.params.total_in + interval as u64,
+    };
+    d.switch_level(adaptive.level_for(estimate_ratio(sample)));
+}
+
+#[cfg(feature = "with-alloc")]
 impl Default for CompressorOxide {
     /// Initialize the compressor with a level of 4, zlib wrapper and
     /// the default strategy.
     fn default() -> Self {
-        CompressorOxide {
-            lz: LZOxide::new(),
-            params: ParamsOxide::new(DEFAULT_FLAGS),
-            huff: Box::default(),
-            dict: DictOxide::new(DEFAULT_FLAGS),
+        CompressorOxide::new(DEFAULT_FLAGS)
+    }
+}
+
+/// The large buffers of a [`CompressorOxide`], around 330 KiB, to create one that doesn't
+/// allocate with [`CompressorOxide::with_buffers`] or
+/// [`CompressorBuilder::build_with_buffers`].
+///
+/// [`CompressorBuffers::new`] is a `const fn`, so the buffers can be put in a `static`
+/// without being copied over the stack, e.g. with the `static_cell` crate on embedded
+/// targets.
+///
+/// ```
+/// use miniz_oxide::deflate::core::{compress, CompressorBuffers, CompressorOxide};
+/// use miniz_oxide::deflate::core::{TDEFLFlush, TDEFLStatus};
+///
+/// // Without a heap, this would be a `static` instead.
+/// let buffers = Box::leak(Box::<CompressorBuffers>::default());
+/// let mut compressor = CompressorOxide::builder().level(6).build_with_buffers(buffers);
+///
+/// let mut output = [0; 100];
+/// let (status, _, len) = compress(&mut compressor, &[7; 1000], &mut output, TDEFLFlush::Finish);
+/// assert_eq!(status, TDEFLStatus::Done);
+/// assert!(len < 100);
+/// ```
+pub struct CompressorBuffers {
+    huff: HuffmanOxide,
+    local_buf: LocalBuf,
+    codes: [u8; LZ_CODE_BUF_SIZE],
+    dict: [u8; LZ_DICT_FULL_SIZE],
+    next: [u16; LZ_DICT_SIZE],
+    hash: [u16; LZ_DICT_SIZE],
+}
+
+impl CompressorBuffers {
+    /// Create zeroed buffers.
+    pub const fn new() -> Self {
+        CompressorBuffers {
+            huff: HuffmanOxide::new(),
+            local_buf: LocalBuf::new(),
+            codes: [0; LZ_CODE_BUF_SIZE],
+            dict: [0; LZ_DICT_FULL_SIZE],
+            next: [0; LZ_DICT_SIZE],
+            hash: [0; LZ_DICT_SIZE],
+        }
+    }
+}
+
+impl Default for CompressorBuffers {
+    fn default() -> Self {
+        CompressorBuffers::new()
+    }
+}
+
+/// Wipe the buffers, also the ones provided with [`CompressorOxide::with_buffers`], so no
+/// uncompressed data is left behind in memory.
+#[cfg(feature = "zeroize")]
+impl Drop for CompressorOxide {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+/// Builder for a [`CompressorOxide`] with all its settings given up front, instead of set one
+/// by one after creating it.
+///
+/// The defaults are a zlib wrapper, level 6 and the default strategy, without dictionary or
+/// full flushes.
+///
+/// ```
+/// use miniz_oxide::DataFormat;
+/// use miniz_oxide::deflate::core::{CompressionStrategy, CompressorOxide};
+///
+/// let compressor = CompressorOxide::builder()
+///     .format(DataFormat::Raw)
+///     .level(9)
+///     .strategy(CompressionStrategy::Filtered)
+///     .build_boxed();
+/// assert_eq!(compressor.data_format(), DataFormat::Raw);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompressorBuilder<'a> {
+    level: i32,
+    window_bits: i32,
+    strategy: CompressionStrategy,
+    dictionary: Option<&'a [u8]>,
+    full_flush_interval: usize,
+}
+
+impl<'a> CompressorBuilder<'a> {
+    /// Create a builder with the default settings.
+    pub fn new() -> Self {
+        CompressorBuilder {
+            level: CompressionLevel::DefaultLevel as i32,
+            window_bits: DataFormat::Zlib.to_window_bits(),
+            strategy: CompressionStrategy::Default,
+            dictionary: None,
+            full_flush_interval: 0,
+        }
+    }
+
+    /// Set how the compressed data is wrapped.
+    pub fn format(mut self, data_format: DataFormat) -> Self {
+        self.window_bits = data_format.to_window_bits();
+        self
+    }
+
+    /// Set the format the way zlib does: above 0 for a zlib wrapper, otherwise raw deflate.
+    ///
+    /// The window is always 32 KiB, so only the sign is used.
+    pub fn window_bits(mut self, window_bits: i32) -> Self {
+        self.window_bits = window_bits;
+        self
+    }
+
+    /// Set the compression level from 0 to 10, or 11 for
+    /// [`UltraCompression`](CompressionLevel::UltraCompression).
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = level.into();
+        self
+    }
+
+    /// Set the compression level.
+    pub fn compression_level(mut self, level: CompressionLevel) -> Self {
+        self.level = level as i32;
+        self
+    }
+
+    /// Set the compression strategy.
+    pub fn strategy(mut self, strategy: CompressionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Load `dictionary` before compressing. See [`CompressorOxide::set_dictionary`].
+    pub fn dictionary(mut self, dictionary: &'a [u8]) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// Insert a full flush after every `interval` bytes of input, or none with 0. See
+    /// [`CompressorOxide::set_full_flush_interval`].
+    pub fn full_flush_interval(mut self, interval: usize) -> Self {
+        self.full_flush_interval = interval;
+        self
+    }
+
+    /// Create the compressor.
+    #[cfg(feature = "with-alloc")]
+    pub fn build(&self) -> CompressorOxide {
+        let mut compressor = CompressorOxide::default();
+        self.configure(&mut compressor);
+        compressor
+    }
+
+    /// Create the compressor on the heap, which avoids copying it around on the stack.
+    #[cfg(feature = "with-alloc")]
+    pub fn build_boxed(&self) -> Box<CompressorOxide> {
+        let mut compressor = Box::<CompressorOxide>::default();
+        self.configure(&mut compressor);
+        compressor
+    }
+
+    /// Create a compressor that keeps its large buffers in `buffers`, so it doesn't allocate.
+    /// See [`CompressorOxide::with_buffers`].
+    pub fn build_with_buffers(&self, buffers: &'static mut CompressorBuffers) -> CompressorOxide {
+        let mut compressor = CompressorOxide::with_buffers(DEFAULT_FLAGS, buffers);
+        self.configure(&mut compressor);
+        compressor
+    }
+
+    fn configure(&self, compressor: &mut CompressorOxide) {
+        compressor.change_flags(create_comp_flags_from_zip_params(
+            self.level,
+            self.window_bits,
+            self.strategy as i32,
+        ));
+        compressor.set_full_flush_interval(self.full_flush_interval);
+        if let Some(dictionary) = self.dictionary {
+            compressor.set_dictionary(dictionary);
+        }
+    }
+}
+
+impl Default for CompressorBuilder<'_> {
+    fn default() -> Self {
+        CompressorBuilder::new()
+    }
+}
+
+/// What the callback of [`compress_to_output`] did with the output it was passed.
+///
+/// Callbacks can also return a `bool`, where `true` is [`Accepted`](Self::Accepted) and
+/// `false` is [`Abort`](Self::Abort).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PutBufResult {
+    /// All of the output was taken.
+    Accepted,
+    /// Only the first `n` bytes were taken, and there is no room for more for now.
+    ///
+    /// Compression pauses, and [`compress_to_output`] returns [`TDEFLStatus::Okay`] with the
+    /// input consumed so far. The rest of the output is kept, see
+    /// [`CompressorOxide::pending_bytes`], and passed to the callback first on the next call,
+    /// which only consumes input once it has all been taken.
+    Pause(usize),
+    /// Stop compressing. [`compress_to_output`] returns [`TDEFLStatus::PutBufFailed`], and the
+    /// compressor has to be reset before it is used again.
+    Abort,
+}
+
+impl From<bool> for PutBufResult {
+    fn from(success: bool) -> Self {
+        if success {
+            PutBufResult::Accepted
+        } else {
+            PutBufResult::Abort
         }
     }
 }
 
 /// Callback function and user used in `compress_to_output`.
 pub struct CallbackFunc<'a> {
-    pub put_buf_func: &'a mut dyn FnMut(&[u8]) -> bool,
+    pub put_buf_func: &'a mut dyn FnMut(&[u8]) -> PutBufResult,
 }
 
 impl CallbackFunc<'_> {
@@ -452,13 +1064,28 @@ impl CallbackFunc<'_> {
         // TODO: As this could be unsafe since
         // we can't verify the function pointer
         // this whole function should maybe be unsafe as well.
-        let call_success = (self.put_buf_func)(&params.local_buf.b[0..saved_output.pos]);
-
-        if !call_success {
-            params.prev_return_status = TDEFLStatus::PutBufFailed;
-            return params.prev_return_status as i32;
-        }
+        params.flush_ofs = 0;
+        params.flush_remaining = saved_output.pos as u32;
+        self.put_pending(params)
+    }
 
+    /// Pass the output kept in the local buffer to the callback, keeping what it doesn't take.
+    ///
+    /// Returns the number of bytes still kept, or [`TDEFLStatus::PutBufFailed`] if the
+    /// callback aborted.
+    fn put_pending(&mut self, params: &mut ParamsOxide) -> i32 {
+        let start = params.flush_ofs as usize;
+        let pending = &params.local_buf.b[start..start + params.flush_remaining as usize];
+        let taken = match (self.put_buf_func)(pending) {
+            PutBufResult::Accepted => pending.len(),
+            PutBufResult::Pause(n) => cmp::min(n, pending.len()),
+            PutBufResult::Abort => {
+                params.prev_return_status = TDEFLStatus::PutBufFailed;
+                return params.prev_return_status as i32;
+            }
+        };
+        params.flush_ofs += taken as u32;
+        params.flush_remaining -= taken as u32;
         params.flush_remaining as i32
     }
 }
@@ -693,6 +1320,7 @@ impl BitBuffer {
 /// NOTE: Only the literal/lengths have enough symbols to actually use
 /// the full array. It's unclear why it's defined like this in miniz,
 /// it could be for cache/alignment reasons.
+#[derive(Clone)]
 pub(crate) struct HuffmanOxide {
     /// Number of occurrences of each symbol.
     pub count: [[u16; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
@@ -703,9 +1331,9 @@ pub(crate) struct HuffmanOxide {
 }
 
 /// Tables used for literal/lengths in `HuffmanOxide`.
-const LITLEN_TABLE: usize = 0;
+pub(crate) const LITLEN_TABLE: usize = 0;
 /// Tables for distances.
-const DIST_TABLE: usize = 1;
+pub(crate) const DIST_TABLE: usize = 1;
 /// Tables for the run-length encoded huffman lengths for literals/lengths/distances.
 const HUFF_CODES_TABLE: usize = 2;
 
@@ -778,15 +1406,19 @@ fn write(src: &[u8], dst: &mut [u8], dst_pos: &mut usize) -> Result<()> {
 
 impl Default for HuffmanOxide {
     fn default() -> Self {
+        HuffmanOxide::new()
+    }
+}
+
+impl HuffmanOxide {
+    pub(crate) const fn new() -> Self {
         HuffmanOxide {
             count: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
             codes: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
             code_sizes: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
         }
     }
-}
 
-impl HuffmanOxide {
     fn radix_sort_symbols<'a>(
         symbols0: &'a mut [SymFreq],
         symbols1: &'a mut [SymFreq],
@@ -906,7 +1538,7 @@ impl HuffmanOxide {
         }
     }
 
-    fn optimize_table(
+    pub(crate) fn optimize_table(
         &mut self,
         table_num: usize,
         table_len: usize,
@@ -1121,48 +1753,603 @@ impl HuffmanOxide {
                 packed_code_size_index += 1;
             }
         }
-
-        Ok(())
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "with-alloc", derive(Clone))]
+pub(crate) struct DictOxide {
+    /// The maximum number of checks in the hash chain, for the initial,
+    /// and the lazy match respectively.
+    pub max_probes: [u32; 2],
+    /// Buffer of input data.
+    /// Padded with 1 byte to simplify matching code in `compress_fast`.
+    pub b: HashBuffers,
+
+    pub code_buf_dict_pos: usize,
+    pub lookahead_size: usize,
+    pub lookahead_pos: usize,
+    pub size: usize,
+    pub loop_len: u8,
+    /// Only `max_probes[1]` probes are done once a match at least this long has been found.
+    pub good_length: u32,
+    /// Stop searching once a match at least this long has been found.
+    pub nice_length: u32,
+    /// The number of hash chain entries `max_probes` was computed from.
+    pub max_chain: u32,
+    /// Number of bytes hashed for the hash chains.
+    pub hash_len: u8,
+    /// The next position to add to the hash chains, when they are updated as the positions are
+    /// reached rather than as data is added, which is when `hash_len` is more than 3.
+    pub insert_pos: usize,
+    /// The first position the hash chains are known to be complete from, as changing the
+    /// flags or the hash length leaves the chains of the earlier positions stale.
+    #[cfg(feature = "invariant-checks")]
+    pub chains_from: usize,
+}
+
+/// Default for [`MatchParams::good_length`].
+const DEFAULT_GOOD_LENGTH: u32 = 32;
+/// Default for [`MatchParams::max_lazy`].
+const DEFAULT_MAX_LAZY: u32 = 128;
+/// Maximum value of [`MatchParams::hash_len`].
+pub(crate) const MAX_HASH_LEN: u8 = 5;
+/// With `hash_len` above 3 and greedy parsing, positions inside matches longer than this aren't
+/// added to the hash chains.
+const MAX_INSERT_MATCH_LEN: usize = 32;
+
+/// Parameters used by the match finder.
+///
+/// These default to values derived from the compression level, and can be changed with
+/// [`CompressorOxide::set_match_params`], similar to zlib's `deflateTune`.
+///
+/// They are only used by the default compression path, so they have no effect at level 0 and
+/// level 1, or with the huffman-only, RLE and quick strategies.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MatchParams {
+    /// Once a match at least this long has been found, only search a quarter of `max_chain`
+    /// for a longer one.
+    pub good_length: u16,
+    /// Use matches at least this long right away, instead of first checking whether there is
+    /// a longer match at the next byte. Has no effect with greedy parsing.
+    pub max_lazy: u16,
+    /// Stop searching once a match at least this long has been found. Clamped to 3..=258.
+    pub nice_length: u16,
+    /// The maximum number of hash chain entries to check for each match.
+    pub max_chain: u16,
+    /// Number of bytes hashed to find the candidates for a match. Clamped to 3..=5.
+    ///
+    /// With 3, every position is added to the hash chains as input is added, like in miniz.
+    /// With 4 or 5, the chains are shorter, as fewer positions with different data share a
+    /// hash value, but matches of 3 bytes are mostly not found. Positions are then added as
+    /// they are reached, and with greedy parsing, positions inside long matches are left out,
+    /// which makes repetitive data faster to compress.
+    ///
+    /// Defaults to 3, unless [`TDEFL_NONDETERMINISTIC_PARSING_FLAG`] is set, in which case it
+    /// defaults to 5 with greedy parsing and 4 otherwise.
+    pub hash_len: u8,
+}
+
+/// Inputs shorter than this are not sampled by [`AdaptiveLevel`] unless the stream is being
+/// flushed, as the estimate would be unreliable.
+const ADAPTIVE_MIN_SAMPLE: usize = 1024;
+/// Samples estimated to compress to at least this many 256ths of their size use
+/// [`AdaptiveLevel::min_level`].
+const ADAPTIVE_INCOMPRESSIBLE_RATIO: u32 = 240;
+/// Samples estimated to compress to at most this many 256ths of their size use
+/// [`AdaptiveLevel::max_level`].
+const ADAPTIVE_COMPRESSIBLE_RATIO: u32 = 128;
+/// The bits of the flags that [`create_comp_flags_from_zip_params`] sets from the level.
+const LEVEL_FLAGS: u32 = MAX_PROBES_MASK
+    | TDEFL_GREEDY_PARSING_FLAG
+    | TDEFL_FORCE_ALL_RAW_BLOCKS
+    | TDEFL_OPTIMAL_PARSING_FLAG;
+
+/// Settings for picking the compression level from samples of the input, set with
+/// [`CompressorOxide::set_adaptive_level`].
+///
+/// Useful when compressing data of unknown type, such as in a proxy, where time spent on
+/// images or archives is wasted. Each sample is scanned for repeated strings and its byte
+/// distribution is measured, which takes a small fraction of the time compressing it would.
+/// Data estimated to compress poorly uses `min_level`, data estimated to compress to half
+/// its size or better uses `max_level`, and data in between a level in between.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AdaptiveLevel {
+    /// Level used for data that hardly compresses.
+    pub min_level: u8,
+    /// Level used for data that compresses well. Raised to `min_level` if lower.
+    pub max_level: u8,
+    /// Number of bytes sampled. At most 32 KiB are used.
+    pub sample_size: usize,
+    /// Number of input bytes after which to sample again, or 0 to only sample the start of
+    /// the stream.
+    pub interval: usize,
+}
+
+impl AdaptiveLevel {
+    /// Level to use for data estimated to compress to `ratio` 256ths of its size.
+    fn level_for(&self, ratio: u32) -> u8 {
+        let max_level = cmp::max(self.min_level, self.max_level);
+        if ratio >= ADAPTIVE_INCOMPRESSIBLE_RATIO {
+            self.min_level
+        } else if ratio <= ADAPTIVE_COMPRESSIBLE_RATIO {
+            max_level
+        } else {
+            let range = ADAPTIVE_INCOMPRESSIBLE_RATIO - ADAPTIVE_COMPRESSIBLE_RATIO;
+            let step = u32::from(max_level - self.min_level)
+                * (ADAPTIVE_INCOMPRESSIBLE_RATIO - ratio)
+                / range;
+            self.min_level + step as u8
+        }
+    }
+}
+
+impl Default for AdaptiveLevel {
+    /// Levels 1 to 6, sampling 16 KiB every MiB.
+    fn default() -> Self {
+        AdaptiveLevel {
+            min_level: 1,
+            max_level: 6,
+            sample_size: 16 * 1024,
+            interval: 1024 * 1024,
+        }
+    }
+}
+
+/// The end of a block in the compressed output, recorded when enabled with
+/// [`CompressorOxide::set_record_boundaries`].
+///
+/// Decompression can start again at a boundary given the 32 KiB of input before it, so the
+/// boundaries can be turned into an index for random access into the stream without
+/// decompressing it.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BlockBoundary {
+    /// Number of input bytes before the boundary.
+    pub in_offset: u64,
+    /// Position of the boundary in the compressed output, in bits. Includes the zlib header,
+    /// if any.
+    pub out_bit_offset: u64,
+    /// The flush that ended the block, or [`TDEFLFlush::None`] if the block was full. Sync and
+    /// full flushes put the boundary after the empty raw block, at a byte boundary.
+    pub flush: TDEFLFlush,
+}
+
+/// Statistics of the blocks output by a [`CompressorOxide`], collected when enabled with
+/// [`CompressorOxide::set_collect_stats`], e.g. to compare levels and strategies on real
+/// data.
+///
+/// Literals and matches are only counted in the compressed blocks, as stored blocks hold the
+/// input as is. The match finder counts are only collected by the levels that follow hash
+/// chains, which are the levels from 2 up with the default strategy.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompressionStats {
+    /// Number of literals output.
+    pub literals: u64,
+    /// Number of matches output.
+    pub matches: u64,
+    /// Total length of the matches.
+    pub match_bytes: u64,
+    /// Total distance of the matches.
+    pub match_distances: u64,
+    /// Number of blocks output as stored (uncompressed) blocks.
+    pub stored_blocks: u64,
+    /// Number of blocks output with the static Huffman codes.
+    pub static_blocks: u64,
+    /// Number of blocks output with their own Huffman codes.
+    pub dynamic_blocks: u64,
+    /// Number of input bytes in the blocks output.
+    pub input_bytes: u64,
+    /// Number of searches of the hash chains for a match.
+    pub match_searches: u64,
+    /// Number of hash chain entries looked at by the searches.
+    pub chain_probes: u64,
+    /// Number of the hash chain entries looked at that didn't start with the bytes searched
+    /// for, but were in the chain because their hash is the same.
+    pub hash_collisions: u64,
+    /// Number of matches that were found, but dropped for a longer match starting at the next
+    /// byte.
+    pub lazy_rejected: u64,
+    /// Total time spent on the blocks output, with the `block-timing` feature.
+    #[cfg(feature = "block-timing")]
+    pub time: BlockTiming,
+    /// The times of the block that took the longest to output, with the `block-timing`
+    /// feature.
+    #[cfg(feature = "block-timing")]
+    pub slowest_block: BlockTiming,
+}
+
+impl CompressionStats {
+    /// The number of blocks output, not counting the empty stored blocks of sync and full
+    /// flushes.
+    pub const fn blocks(&self) -> u64 {
+        self.stored_blocks + self.static_blocks + self.dynamic_blocks
+    }
+
+    /// The average length of the matches, or 0 if there are none.
+    pub fn average_match_length(&self) -> f64 {
+        ratio(self.match_bytes, self.matches)
+    }
+
+    /// The average distance of the matches, or 0 if there are none.
+    pub fn average_match_distance(&self) -> f64 {
+        ratio(self.match_distances, self.matches)
+    }
+
+    /// The average number of input bytes per block, or 0 if there are no blocks.
+    pub fn bytes_per_block(&self) -> f64 {
+        ratio(self.input_bytes, self.blocks())
+    }
+
+    /// The average number of hash chain entries looked at per search, or 0 if there were no
+    /// searches.
+    pub fn average_chain_length(&self) -> f64 {
+        ratio(self.chain_probes, self.match_searches)
+    }
+
+    #[cfg(feature = "block-timing")]
+    fn add_block_timing(&mut self, block: BlockTiming) {
+        self.time.input_bytes += block.input_bytes;
+        self.time.match_finding += block.match_finding;
+        self.time.tree_building += block.tree_building;
+        self.time.symbol_coding += block.symbol_coding;
+        if block.total() > self.slowest_block.total() {
+            self.slowest_block = block;
+        }
+    }
+
+    /// Add the literals and matches in the first `len` bytes of the LZ code buffer `codes`.
+    fn count_codes(&mut self, codes: &[u8; LZ_CODE_BUF_SIZE], len: usize) {
+        let len = cmp::min(len, codes.len());
+        let mut flags = 1;
+        let mut i = 0;
+        while i < len {
+            if flags == 1 {
+                flags = u32::from(codes[i]) | 0x100;
+                i += 1;
+                continue;
+            }
+            if flags & 1 == 1 {
+                let match_len = u64::from(codes[i]) + u64::from(MIN_MATCH_LEN);
+                let match_dist = u64::from(codes[(i + 1) & LZ_CODE_BUF_MASK])
+                    | (u64::from(codes[(i + 2) & LZ_CODE_BUF_MASK]) << 8);
+                self.matches += 1;
+                self.match_bytes += match_len;
+                self.match_distances += match_dist + 1;
+                i += 3;
+            } else {
+                self.literals += 1;
+                i += 1;
+            }
+            flags >>= 1;
+        }
+    }
+}
+
+/// Time spent on a block, or on all of them in [`CompressionStats::time`], with the
+/// `block-timing` feature.
+///
+/// Only the time spent in the compression calls counts, not the time between them.
+#[cfg(feature = "block-timing")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BlockTiming {
+    /// Offset in the input of the start of the block, which is 0 for the total.
+    pub in_offset: u64,
+    /// Number of input bytes in the block.
+    pub input_bytes: u64,
+    /// Time spent taking in the input and looking for matches in it, or copying it for
+    /// stored blocks.
+    pub match_finding: Duration,
+    /// Time spent building the Huffman codes and writing them in the block header.
+    pub tree_building: Duration,
+    /// Time spent writing the literals and matches with the codes, or the input for stored
+    /// blocks.
+    pub symbol_coding: Duration,
+}
+
+#[cfg(feature = "block-timing")]
+impl BlockTiming {
+    /// The total time spent on the block.
+    pub fn total(&self) -> Duration {
+        self.match_finding + self.tree_building + self.symbol_coding
+    }
+}
+
+/// What the time spent on a block is counted as.
+#[derive(Copy, Clone)]
+pub(crate) enum BlockPart {
+    MatchFinding,
+    TreeBuilding,
+    SymbolCoding,
+}
+
+/// Times the work on the current block with the `block-timing` feature. Without it, this is
+/// empty and does nothing.
+#[derive(Clone, Default)]
+pub(crate) struct BlockTimer {
+    /// When the time was last counted up to, if timing.
+    #[cfg(feature = "block-timing")]
+    mark: Option<Instant>,
+    #[cfg(feature = "block-timing")]
+    block: BlockTiming,
+}
+
+impl BlockTimer {
+    /// Whether the time is being counted.
+    #[inline(always)]
+    pub(crate) fn is_running(&self) -> bool {
+        #[cfg(feature = "block-timing")]
+        return self.mark.is_some();
+        #[cfg(not(feature = "block-timing"))]
+        false
+    }
+
+    /// Start timing, counting the time as spent on the part passed to the next call.
+    #[inline(always)]
+    pub(crate) fn start(&mut self) {
+        #[cfg(feature = "block-timing")]
+        {
+            self.mark = Some(Instant::now());
+        }
+    }
+
+    /// Count the time since the last call as spent on `part`, and carry on timing.
+    #[inline(always)]
+    pub(crate) fn lap(&mut self, part: BlockPart) {
+        #[cfg(feature = "block-timing")]
+        {
+            let now = Instant::now();
+            if let Some(mark) = self.mark.replace(now) {
+                *self.part_mut(part) += now - mark;
+            }
+        }
+        #[cfg(not(feature = "block-timing"))]
+        let _ = part;
+    }
+
+    /// Count the time since the last call as spent on `part`, and stop timing.
+    #[inline(always)]
+    pub(crate) fn stop(&mut self, part: BlockPart) {
+        #[cfg(feature = "block-timing")]
+        if let Some(mark) = self.mark.take() {
+            *self.part_mut(part) += mark.elapsed();
+        }
+        #[cfg(not(feature = "block-timing"))]
+        let _ = part;
+    }
+
+    #[cfg(feature = "block-timing")]
+    fn part_mut(&mut self, part: BlockPart) -> &mut Duration {
+        match part {
+            BlockPart::MatchFinding => &mut self.block.match_finding,
+            BlockPart::TreeBuilding => &mut self.block.tree_building,
+            BlockPart::SymbolCoding => &mut self.block.symbol_coding,
+        }
+    }
+
+    /// Take the times of the block that was just output, starting from 0 for the next one.
+    #[cfg(feature = "block-timing")]
+    fn end_block(&mut self, in_offset: u64, input_bytes: u32) -> BlockTiming {
+        BlockTiming {
+            in_offset,
+            input_bytes: input_bytes.into(),
+            ..mem::take(&mut self.block)
+        }
+    }
+}
+
+/// Counts the work done by the match finder.
+///
+/// The match finder takes it as a type parameter so that `()`, which counts nothing, compiles
+/// to nothing.
+pub(crate) trait SearchCounter {
+    /// A search of the hash chains started.
+    fn search(&mut self);
+
+    /// A hash chain entry was looked at. `collision` tells whether it doesn't start with the
+    /// bytes searched for.
+    fn probe(&mut self, collision: impl FnOnce() -> bool);
+}
+
+impl SearchCounter for () {
+    #[inline(always)]
+    fn search(&mut self) {}
+
+    #[inline(always)]
+    fn probe(&mut self, _collision: impl FnOnce() -> bool) {}
+}
+
+impl SearchCounter for CompressionStats {
+    fn search(&mut self) {
+        self.match_searches += 1;
+    }
+
+    fn probe(&mut self, collision: impl FnOnce() -> bool) {
+        self.chain_probes += 1;
+        self.hash_collisions += u64::from(collision());
+    }
+}
+
+fn ratio(total: u64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+/// How the positions of a dictionary are added to the hash tables, which depends on the
+/// compression function in use.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum DictionaryHash {
+    /// Hash chains, with a hash of the given number of bytes.
+    Chains(u8),
+    /// The table of [`compress_fast`].
+    Fast,
+    /// The table of [`compress_quick`].
+    Quick,
+}
+
+impl DictionaryHash {
+    fn new(flags: u32, hash_len: u8) -> DictionaryHash {
+        match CompressPath::from_flags(flags) {
+            CompressPath::Quick => DictionaryHash::Quick,
+            CompressPath::Fast => DictionaryHash::Fast,
+            // Only the default path uses a longer hash.
+            CompressPath::Stored | CompressPath::Optimal => DictionaryHash::Chains(MIN_MATCH_LEN),
+            CompressPath::Normal => DictionaryHash::Chains(hash_len),
+        }
+    }
+}
+
+/// A preset dictionary that has been hashed, so it can be loaded into many compressors with
+/// [`CompressorOxide::set_prepared_dictionary`] by copying it, which is faster than hashing it
+/// again for each of them when compressing many small pieces of data.
+///
+/// Holds about 160 KiB.
+#[cfg(feature = "with-alloc")]
+#[derive(Clone)]
+pub struct PreparedDictionary {
+    b: HashBuffers,
+    len: usize,
+    hash: DictionaryHash,
+    insert_pos: usize,
+}
+
+#[cfg(feature = "with-alloc")]
+impl PreparedDictionary {
+    /// Prepare `dictionary` for compressors created with [`CompressorOxide::new`] with the
+    /// given `flags`. Only the last 32 KiB are used.
+    pub fn new(dictionary: &[u8], flags: u32) -> PreparedDictionary {
+        PreparedDictionary::with_hash(
+            dictionary,
+            DictionaryHash::new(flags, hash_len_from_flags(flags)),
+        )
+    }
+
+    /// Prepare `dictionary` for compressors with the same settings as `compressor`, including
+    /// the match parameters.
+    pub fn for_compressor(dictionary: &[u8], compressor: &CompressorOxide) -> PreparedDictionary {
+        PreparedDictionary::with_hash(dictionary, compressor.dictionary_hash())
+    }
+
+    fn with_hash(dictionary: &[u8], hash: DictionaryHash) -> PreparedDictionary {
+        let mut b = HashBuffers::default();
+        let (len, insert_pos) = load_dictionary(&mut b, dictionary, hash);
+        PreparedDictionary {
+            b,
+            len,
+            hash,
+            insert_pos,
+        }
+    }
+
+    /// The part of the dictionary that is used.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.b.dict[..self.len]
+    }
+}
+
+/// Copy the last `LZ_DICT_SIZE` bytes of `dictionary` to the start of `b`, and add them to the
+/// hash tables.
+///
+/// Returns the number of bytes used, and the first position that wasn't added to the hash
+/// tables as the bytes after it are needed to hash it. With a 3 byte hash, the remaining
+/// positions are added once the bytes after them are added. With a longer hash, they are added
+/// as they are reached.
+fn load_dictionary(b: &mut HashBuffers, dictionary: &[u8], hash: DictionaryHash) -> (usize, usize) {
+    let dictionary = &dictionary[dictionary.len().saturating_sub(LZ_DICT_SIZE)..];
+    for (pos, &c) in dictionary.iter().enumerate() {
+        b.dict[pos] = c;
+        if pos < MAX_MATCH_LEN - 1 {
+            b.dict[LZ_DICT_SIZE + pos] = c;
+        }
+    }
+
+    let bytes_hashed = match hash {
+        DictionaryHash::Chains(hash_len) => hash_len,
+        DictionaryHash::Fast => 3,
+        DictionaryHash::Quick => 4,
+    };
+    let num_hashed = dictionary
+        .len()
+        .saturating_sub(usize::from(bytes_hashed) - 1);
+    for pos in 0..num_hashed {
+        let first = u32::from_le_bytes(b.dict[pos..pos + 4].try_into().unwrap());
+        match hash {
+            DictionaryHash::Chains(hash_len) => {
+                let hash = if hash_len <= MIN_MATCH_LEN {
+                    ((u32::from(b.dict[pos]) << (LZ_HASH_SHIFT * 2))
+                        ^ (u32::from(b.dict[pos + 1]) << LZ_HASH_SHIFT)
+                        ^ u32::from(b.dict[pos + 2])) as usize
+                        & (LZ_HASH_SIZE - 1)
+                } else {
+                    hash_bytes(&b.dict, pos, hash_len)
+                };
+                b.next[pos] = b.hash[hash];
+                b.hash[hash] = pos as u16;
+            }
+            // These only keep the latest position for each hash.
+            DictionaryHash::Fast => b.hash[fast_hash(first & 0xFF_FFFF)] = pos as u16,
+            DictionaryHash::Quick => b.hash[quick_hash(first)] = pos as u16,
+        }
     }
+    (dictionary.len(), num_hashed)
 }
 
-pub(crate) struct DictOxide {
-    /// The maximum number of checks in the hash chain, for the initial,
-    /// and the lazy match respectively.
-    pub max_probes: [u32; 2],
-    /// Buffer of input data.
-    /// Padded with 1 byte to simplify matching code in `compress_fast`.
-    pub b: HashBuffers,
+/// Hash the `hash_len` bytes at `pos` in `dict`.
+#[inline]
+fn hash_bytes(dict: &[u8; LZ_DICT_FULL_SIZE], pos: usize, hash_len: u8) -> usize {
+    // Help evade bounds/panic code check by masking the position value.
+    let pos = pos & LZ_DICT_SIZE_MASK;
+    let bytes = u64::from_le_bytes(dict[pos..pos + 8].try_into().unwrap());
+    let bytes = bytes << (64 - 8 * u32::from(hash_len));
+    (bytes.wrapping_mul(0x9E37_79B1_85EB_CA87) >> (64 - LZ_HASH_BITS)) as usize
+}
 
-    pub code_buf_dict_pos: usize,
-    pub lookahead_size: usize,
-    pub lookahead_pos: usize,
-    pub size: usize,
-    loop_len: u8,
+pub(crate) const fn probes_from_chain(max_chain: u32) -> [u32; 2] {
+    [1 + max_chain.div_ceil(3), 1 + (max_chain >> 2).div_ceil(3)]
 }
 
-const fn probes_from_flags(flags: u32) -> [u32; 2] {
-    [
-        1 + ((flags & 0xFFF) + 2) / 3,
-        1 + (((flags & 0xFFF) >> 2) + 2) / 3,
-    ]
+/// Default for [`MatchParams::hash_len`].
+const fn hash_len_from_flags(flags: u32) -> u8 {
+    if flags & TDEFL_NONDETERMINISTIC_PARSING_FLAG == 0 {
+        MIN_MATCH_LEN
+    } else if flags & TDEFL_GREEDY_PARSING_FLAG != 0 {
+        5
+    } else {
+        4
+    }
 }
 
 impl DictOxide {
-    fn new(flags: u32) -> Self {
+    fn new(flags: u32, b: HashBuffers) -> Self {
         DictOxide {
-            max_probes: probes_from_flags(flags),
-            b: HashBuffers::default(),
+            max_probes: probes_from_chain(flags & MAX_PROBES_MASK),
+            b,
             code_buf_dict_pos: 0,
             lookahead_size: 0,
             lookahead_pos: 0,
             size: 0,
-            loop_len: 32,
+            loop_len: (256 / MATCH_COMPARE_BYTES) as u8,
+            good_length: DEFAULT_GOOD_LENGTH,
+            nice_length: MAX_MATCH_LEN as u32,
+            max_chain: flags & MAX_PROBES_MASK,
+            hash_len: hash_len_from_flags(flags),
+            insert_pos: 0,
+            #[cfg(feature = "invariant-checks")]
+            chains_from: 0,
         }
     }
 
-    fn update_flags(&mut self, flags: u32) {
-        self.max_probes = probes_from_flags(flags);
+    pub(crate) fn update_flags(&mut self, flags: u32) {
+        self.max_chain = flags & MAX_PROBES_MASK;
+        self.max_probes = probes_from_chain(self.max_chain);
+        self.good_length = DEFAULT_GOOD_LENGTH;
+        self.nice_length = MAX_MATCH_LEN as u32;
+        self.hash_len = hash_len_from_flags(flags);
+        #[cfg(feature = "invariant-checks")]
+        self.restart_chains();
     }
 
     fn reset(&mut self) {
@@ -1171,6 +2358,65 @@ impl DictOxide {
         self.lookahead_size = 0;
         self.lookahead_pos = 0;
         self.size = 0;
+        self.insert_pos = 0;
+        #[cfg(feature = "invariant-checks")]
+        {
+            self.chains_from = 0;
+        }
+    }
+
+    /// Only expect complete hash chains from the positions that are added from now on.
+    #[cfg(feature = "invariant-checks")]
+    pub(crate) fn restart_chains(&mut self) {
+        self.chains_from = (self.lookahead_pos + self.lookahead_size).saturating_sub(2);
+    }
+
+    /// Hash the `hash_len` bytes at `pos` in the dictionary.
+    #[inline]
+    fn hash_at(&self, pos: usize) -> usize {
+        hash_bytes(&self.b.dict, pos, self.hash_len)
+    }
+
+    /// Set the positions after loading a dictionary of `len` bytes at the start of the buffers.
+    fn set_dictionary_len(&mut self, len: usize, insert_pos: usize) {
+        self.lookahead_pos = len;
+        self.code_buf_dict_pos = len;
+        self.size = len;
+        self.insert_pos = insert_pos;
+    }
+
+    /// Add the positions up to and including `lookahead_pos` that haven't been added yet to
+    /// the hash chains, if they are updated as positions are reached.
+    ///
+    /// Returns false if `lookahead_pos` isn't in the hash chains, as there are fewer than
+    /// `hash_len` bytes left to hash, in which case there is no point looking for a match.
+    #[inline]
+    pub(crate) fn update_hash_chains(
+        &mut self,
+        lookahead_pos: usize,
+        lookahead_size: usize,
+    ) -> bool {
+        let hash_len = usize::from(self.hash_len);
+        if hash_len <= MIN_MATCH_LEN.into() {
+            // All the positions were added when the data was added.
+            return true;
+        }
+        if lookahead_size < hash_len {
+            return false;
+        }
+
+        // Positions that are no longer in the dictionary can't be matched against.
+        let start = cmp::max(
+            self.insert_pos,
+            (lookahead_pos + lookahead_size).saturating_sub(LZ_DICT_SIZE),
+        );
+        for pos in start..=lookahead_pos {
+            let hash = self.hash_at(pos);
+            self.b.next[pos & LZ_DICT_SIZE_MASK] = self.b.hash[hash];
+            self.b.hash[hash] = pos as u16;
+        }
+        self.insert_pos = cmp::max(self.insert_pos, lookahead_pos + 1);
+        true
     }
 
     /// Do an unaligned read of the data at `pos` in the dictionary and treat it as if it was of
@@ -1201,17 +2447,102 @@ impl DictOxide {
         u64::from_le_bytes(bytes)
     }
 
+    /// Compare the [`MATCH_COMPARE_BYTES`] bytes at `p` and `q` in the dictionary and return how
+    /// many of the leading bytes are equal.
+    #[inline]
+    fn matching_bytes(&self, p: usize, q: usize) -> u32 {
+        // Help evade bounds/panic code check by masking the position values.
+        let p = p & LZ_DICT_SIZE_MASK;
+        let q = q & LZ_DICT_SIZE_MASK;
+        matching_bytes(
+            self.b.dict[p..p + MATCH_COMPARE_BYTES].try_into().unwrap(),
+            self.b.dict[q..q + MATCH_COMPARE_BYTES].try_into().unwrap(),
+        )
+    }
+
+    /// Find the matches for the data at `lookahead_pos` that are longer than all closer ones,
+    /// and store them as `(length, distance)` in `matches`, shortest first.
+    ///
+    /// Follows the hash chain for up to `max_chain` entries, and stops at the first match that
+    /// is at least `nice_length` long. If `matches` fills up, later matches replace the last
+    /// one. Returns the number of matches stored.
+    #[cfg(feature = "with-alloc")]
+    pub(crate) fn find_all_matches(
+        &self,
+        lookahead_pos: usize,
+        max_dist: usize,
+        max_match_len: usize,
+        matches: &mut [(u16, u16)],
+        counter: &mut impl SearchCounter,
+    ) -> usize {
+        let max_match_len = cmp::min(MAX_MATCH_LEN, max_match_len);
+        let nice_len = cmp::min(max_match_len, self.nice_length as usize);
+        if max_match_len < MIN_MATCH_LEN.into() || matches.is_empty() {
+            return 0;
+        }
+
+        let pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+        let mut probe_pos = pos;
+        let mut best_len = usize::from(MIN_MATCH_LEN) - 1;
+        let mut num_found = 0;
+        counter.search();
+        for _ in 0..self.max_chain {
+            let next_probe_pos = usize::from(self.b.next[probe_pos]);
+            let dist = (lookahead_pos - next_probe_pos) & 0xFFFF;
+            if next_probe_pos == 0 || dist == 0 || dist > max_dist {
+                break;
+            }
+            probe_pos = next_probe_pos & LZ_DICT_SIZE_MASK;
+            counter.probe(|| self.is_collision(pos, probe_pos));
+
+            // Only a match that is longer than the best one so far is of interest, so check
+            // the byte that would make it longer first.
+            if self.b.dict[probe_pos + best_len] != self.b.dict[pos + best_len] {
+                continue;
+            }
+            let mut len = 0;
+            while len < max_match_len {
+                let matching = self.matching_bytes(pos + len, probe_pos + len) as usize;
+                len += matching;
+                if matching < MATCH_COMPARE_BYTES {
+                    break;
+                }
+            }
+            let len = cmp::min(len, max_match_len);
+
+            if len > best_len {
+                best_len = len;
+                if num_found == matches.len() {
+                    num_found -= 1;
+                }
+                matches[num_found] = (len as u16, dist as u16);
+                num_found += 1;
+                if len >= nice_len {
+                    break;
+                }
+            }
+        }
+        num_found
+    }
+
+    /// Whether the hashed bytes at `probe_pos` differ from the ones at `pos`.
+    fn is_collision(&self, pos: usize, probe_pos: usize) -> bool {
+        let len = usize::from(self.hash_len);
+        self.b.dict[pos..pos + len] != self.b.dict[probe_pos..probe_pos + len]
+    }
+
     /// Try to find a match for the data at lookahead_pos in the dictionary that is
     /// longer than `match_len`.
     /// Returns a tuple containing (match_distance, match_length). Will be equal to the input
     /// values if no better matches were found.
-    fn find_match(
+    pub(crate) fn find_match(
         &self,
         lookahead_pos: usize,
         max_dist: usize,
         max_match_len: u32,
         mut match_dist: u32,
         mut match_len: u32,
+        counter: &mut impl SearchCounter,
     ) -> (u32, u32) {
         // Clamp the match len and max_match_len to be valid. (It should be when this is called, but
         // do it for now just in case for safety reasons.)
@@ -1220,16 +2551,19 @@ impl DictOxide {
         // TODO: Statically verify these so we don't need to do this.
         let max_match_len = cmp::min(MAX_MATCH_LEN as u32, max_match_len);
         match_len = cmp::max(match_len, 1);
+        // Length at which we stop looking for a longer match.
+        let nice_len = cmp::min(max_match_len, self.nice_length);
 
-        // If we already have a match of the full length don't bother searching for another one.
-        if max_match_len <= match_len {
+        // If we already have a match that is long enough don't bother searching for another one.
+        if nice_len <= match_len {
             return (match_dist, match_len);
         }
 
         let pos = lookahead_pos & LZ_DICT_SIZE_MASK;
         let mut probe_pos = pos;
+        counter.search();
         // Number of probes into the hash chains.
-        let mut num_probes_left = if match_len < 32 {
+        let mut num_probes_left = if match_len < self.good_length {
             self.max_probes[0]
         } else {
             self.max_probes[1]
@@ -1277,6 +2611,7 @@ impl DictOxide {
                     // Mask the position value to get the position in the hash chain of the next
                     // position to match against.
                     probe_pos = next_probe_pos & LZ_DICT_SIZE_MASK;
+                    counter.probe(|| self.is_collision(pos, probe_pos));
 
                     if read_u16_le(&self.b.dict, probe_pos + match_len as usize - 1) == c01 {
                         break 'found;
@@ -1302,24 +2637,19 @@ impl DictOxide {
             // the compiler is too smart and ends up unrolling the loop which causes the performance to get worse
             // Using a variable instead of a constant here to prevent it seems to at least get back some of the performance loss.
             for _ in 0..self.loop_len as i32 {
-                let p_data: u64 = self.read_unaligned_u64(p);
-                let q_data: u64 = self.read_unaligned_u64(q);
-                // Compare of 8 bytes at a time by using unaligned loads of 64-bit integers.
-                let xor_data = p_data ^ q_data;
-                if xor_data == 0 {
-                    p += 8;
-                    q += 8;
+                let matching = self.matching_bytes(p, q);
+                if matching as usize == MATCH_COMPARE_BYTES {
+                    p += MATCH_COMPARE_BYTES;
+                    q += MATCH_COMPARE_BYTES;
                 } else {
-                    // If not all of the last 8 bytes matched, check how may of them did.
-                    let trailing = xor_data.trailing_zeros();
-
-                    let probe_len = p - pos + (trailing as usize >> 3);
+                    // If not all of the compared bytes matched, check how may of them did.
+                    let probe_len = p - pos + matching as usize;
                     if probe_len > match_len as usize {
                         match_dist = dist as u32;
                         match_len = cmp::min(max_match_len, probe_len as u32);
-                        if match_len >= max_match_len {
-                            // We found a match that had the maximum allowed length,
-                            // so there is now point searching further.
+                        if match_len >= nice_len {
+                            // We found a match that is long enough, so there is no point
+                            // searching further.
                             return (match_dist, match_len);
                         }
                         // We found a better match, so save the last two bytes for further match
@@ -1339,9 +2669,12 @@ impl DictOxide {
     }
 }
 
+#[cfg_attr(feature = "with-alloc", derive(Clone))]
 pub(crate) struct ParamsOxide {
     pub flags: u32,
     pub greedy_parsing: bool,
+    /// Matches at least this long are used without checking for a longer one at the next byte.
+    pub max_lazy: u32,
     pub block_index: u32,
 
     pub saved_match_dist: u32,
@@ -1363,14 +2696,49 @@ pub(crate) struct ParamsOxide {
     pub saved_bit_buffer: u32,
     pub saved_bits_in: u32,
 
-    pub local_buf: Box<LocalBuf>,
+    /// Number of upcoming small blocks to output after a block that didn't compress. All but
+    /// the last are output as raw blocks without searching for matches, and the last is
+    /// compressed to check whether the data compresses again.
+    pub incompressible_blocks: u32,
+
+    /// Settings for picking the level from samples of the input, if enabled.
+    pub adaptive: Option<AdaptiveLevel>,
+    /// Value of `total_in` at which the next sample is taken.
+    pub adaptive_next: u64,
+
+    /// Number of input bytes in the blocks output so far.
+    pub block_in: u64,
+    /// Number of whole bytes output by the blocks so far, not counting `saved_bits_in`.
+    pub block_out: u64,
+    /// Block boundaries recorded since they were last taken, if enabled.
+    #[cfg(feature = "with-alloc")]
+    pub boundaries: Option<Vec<BlockBoundary>>,
+
+    /// Number of input bytes between full flush points, or 0 if disabled.
+    pub flush_interval: u64,
+    /// Value of `total_in` at the next full flush point.
+    pub next_flush_at: u64,
+    /// Value of `block_in` at the last full flush.
+    pub full_flush_in: u64,
+    /// Statistics of the blocks output, if enabled.
+    pub stats: Option<CompressionStats>,
+    pub timer: BlockTimer,
+    /// Records or replays the decisions, if enabled.
+    #[cfg(feature = "with-alloc")]
+    pub tracer: Option<Box<Tracer>>,
+
+    pub total_in: u64,
+    pub total_out: u64,
+
+    pub local_buf: Storage<LocalBuf>,
 }
 
 impl ParamsOxide {
-    fn new(flags: u32) -> Self {
+    fn new(flags: u32, local_buf: Storage<LocalBuf>) -> Self {
         ParamsOxide {
             flags,
             greedy_parsing: flags & TDEFL_GREEDY_PARSING_FLAG != 0,
+            max_lazy: DEFAULT_MAX_LAZY,
             block_index: 0,
             saved_match_dist: 0,
             saved_match_len: 0,
@@ -1385,13 +2753,30 @@ impl ParamsOxide {
             prev_return_status: TDEFLStatus::Okay,
             saved_bit_buffer: 0,
             saved_bits_in: 0,
-            local_buf: Box::default(),
+            incompressible_blocks: 0,
+            adaptive: None,
+            adaptive_next: 0,
+            block_in: 0,
+            block_out: 0,
+            #[cfg(feature = "with-alloc")]
+            boundaries: None,
+            flush_interval: 0,
+            next_flush_at: 0,
+            full_flush_in: 0,
+            stats: None,
+            timer: BlockTimer::default(),
+            #[cfg(feature = "with-alloc")]
+            tracer: None,
+            total_in: 0,
+            total_out: 0,
+            local_buf,
         }
     }
 
-    fn update_flags(&mut self, flags: u32) {
+    pub(crate) fn update_flags(&mut self, flags: u32) {
         self.flags = flags;
         self.greedy_parsing = self.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
+        self.max_lazy = DEFAULT_MAX_LAZY;
     }
 
     /// Reset state, saving settings.
@@ -1410,12 +2795,65 @@ impl ParamsOxide {
         self.prev_return_status = TDEFLStatus::Okay;
         self.saved_bit_buffer = 0;
         self.saved_bits_in = 0;
-        self.local_buf.b = [0; OUT_BUF_SIZE];
+        self.incompressible_blocks = 0;
+        self.adaptive_next = 0;
+        self.block_in = 0;
+        self.block_out = 0;
+        #[cfg(feature = "with-alloc")]
+        if let Some(boundaries) = &mut self.boundaries {
+            boundaries.clear();
+        }
+        self.next_flush_at = self.flush_interval;
+        self.full_flush_in = 0;
+        if let Some(stats) = &mut self.stats {
+            *stats = CompressionStats::default();
+        }
+        self.timer = BlockTimer::default();
+        #[cfg(feature = "with-alloc")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.reset();
+        }
+        self.total_in = 0;
+        self.total_out = 0;
+        self.local_buf.b.fill(0);
+    }
+
+    /// Whether the current block is output as a raw block without searching for matches, as
+    /// the data didn't compress.
+    #[inline]
+    pub fn skip_matches(&self) -> bool {
+        self.incompressible_blocks > 1
+    }
+
+    /// Make a lazy matching choice, where `heuristic` is the choice to make unless a trace is
+    /// being replayed, and `allowed` is whether `true` can be chosen.
+    #[inline]
+    fn lazy_choice(&mut self, heuristic: bool, allowed: bool) -> bool {
+        #[cfg(feature = "with-alloc")]
+        if let Some(tracer) = &mut self.tracer {
+            return tracer.choose(heuristic, allowed);
+        }
+        let _ = allowed;
+        heuristic
+    }
+
+    /// Decide whether to end the block after `total_bytes` bytes, where `heuristic` is the
+    /// decision to make unless a trace is being replayed, and `must` is whether the block
+    /// can't be longer.
+    #[inline]
+    fn split_block(&mut self, heuristic: bool, must: bool, total_bytes: u32) -> bool {
+        #[cfg(feature = "with-alloc")]
+        if let Some(tracer) = &mut self.tracer {
+            return tracer.split(heuristic, must, total_bytes);
+        }
+        let _ = (must, total_bytes);
+        heuristic
     }
 }
 
+#[cfg_attr(feature = "with-alloc", derive(Clone))]
 pub(crate) struct LZOxide {
-    pub codes: [u8; LZ_CODE_BUF_SIZE],
+    pub codes: Storage<[u8; LZ_CODE_BUF_SIZE]>,
     pub code_position: usize,
     pub flag_position: usize,
 
@@ -1425,9 +2863,9 @@ pub(crate) struct LZOxide {
 }
 
 impl LZOxide {
-    const fn new() -> Self {
+    fn new(codes: Storage<[u8; LZ_CODE_BUF_SIZE]>) -> Self {
         LZOxide {
-            codes: [0; LZ_CODE_BUF_SIZE],
+            codes,
             code_position: 1,
             flag_position: 0,
             total_bytes: 0,
@@ -1435,6 +2873,14 @@ impl LZOxide {
         }
     }
 
+    fn reset(&mut self) {
+        self.codes.fill(0);
+        self.code_position = 1;
+        self.flag_position = 0;
+        self.total_bytes = 0;
+        self.num_flags_left = 8;
+    }
+
     fn write_code(&mut self, val: u8) {
         // Perf - go via u16 to help evade bounds check
         // TODO: see if we can use u16 for flag_position in general.
@@ -1582,14 +3028,18 @@ fn compress_block(
     output: &mut OutputBufferOxide,
     lz: &LZOxide,
     static_block: bool,
+    timer: &mut BlockTimer,
 ) -> Result<bool> {
     if static_block {
         huff.start_static_block(output);
     } else {
         huff.start_dynamic_block(output)?;
     }
+    timer.lap(BlockPart::TreeBuilding);
 
-    compress_lz_codes(huff, output, &lz.codes, lz.code_position)
+    let res = compress_lz_codes(huff, output, &lz.codes, lz.code_position);
+    timer.lap(BlockPart::SymbolCoding);
+    res
 }
 
 pub(crate) fn flush_block(
@@ -1598,6 +3048,10 @@ pub(crate) fn flush_block(
     flush: TDEFLFlush,
 ) -> Result<i32> {
     let mut saved_buffer;
+    let skip_matches = d.params.skip_matches();
+    // Blocks are also output after the input has been taken in, outside of the timing of it.
+    let timing = d.params.timer.is_running();
+    d.params.timer.lap(BlockPart::MatchFinding);
     {
         let mut output = callback
             .out
@@ -1605,13 +3059,27 @@ pub(crate) fn flush_block(
         output.bit_buffer = d.params.saved_bit_buffer;
         output.bits_in = d.params.saved_bits_in;
 
+        let force_raw = d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
         // TODO: Don't think this second condition should be here but need to verify.
-        let use_raw_block = (d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0)
-            && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos) <= d.dict.size;
-        debug_assert_eq!(
-            use_raw_block,
-            d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0
-        );
+        let stored_possible = (d.dict.lookahead_pos - d.dict.code_buf_dict_pos) <= d.dict.size;
+        let use_raw_block = (force_raw || skip_matches) && stored_possible;
+        // A trace being replayed decides the type of the block.
+        #[cfg(feature = "with-alloc")]
+        let replayed = d.params.tracer.as_mut().and_then(|tracer| {
+            tracer.replay_block(flush, d.lz.total_bytes, stored_possible)
+        });
+        #[cfg(feature = "with-alloc")]
+        let use_raw_block = replayed.map_or(use_raw_block, |t| t == BlockType::Stored);
+        // Blocks started at another level can have codes to fall back to.
+        debug_assert!(use_raw_block || !force_raw || d.lz.code_position > 1);
+        if d.params.incompressible_blocks > 0 {
+            d.params.incompressible_blocks -= 1;
+            // Random data has close to the same number of each byte value. If this block has
+            // clearly more of some than others, try compressing the next block right away.
+            if skip_matches && literals_skewed(&d.huff.count[0][..256], d.lz.total_bytes) {
+                d.params.incompressible_blocks = 1;
+            }
+        }
 
         assert!(d.params.flush_remaining == 0);
         d.params.flush_ofs = 0;
@@ -1631,10 +3099,14 @@ pub(crate) fn flush_block(
 
         saved_buffer = output.save();
 
+        let use_static = (d.params.flags
+            & (TDEFL_FORCE_ALL_STATIC_BLOCKS | TDEFL_QUICK_PARSING_FLAG)
+            != 0)
+            || (d.lz.total_bytes < 48);
+        #[cfg(feature = "with-alloc")]
+        let use_static = replayed.map_or(use_static, |t| t == BlockType::Static);
         let comp_success = if !use_raw_block {
-            let use_static =
-                (d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0) || (d.lz.total_bytes < 48);
-            compress_block(&mut d.huff, &mut output, &d.lz, use_static)?
+            compress_block(&mut d.huff, &mut output, &d.lz, use_static, &mut d.params.timer)?
         } else {
             false
         };
@@ -1648,9 +3120,23 @@ pub(crate) fn flush_block(
         // As a static block will have an overhead of at most 1 bit per byte
         // (as literals are either 8 or 9 bytes), a raw block will
         // never take up less space if the number of input bytes are less than 32.
+        let compressed_len = output.inner_pos - saved_buffer.pos;
         let expanded = (d.lz.total_bytes > 32)
-            && (output.inner_pos - saved_buffer.pos + 1 >= (d.lz.total_bytes as usize))
-            && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos <= d.dict.size);
+            && (compressed_len + 1 >= (d.lz.total_bytes as usize))
+            && stored_possible;
+        #[cfg(feature = "with-alloc")]
+        let expanded = expanded && replayed.is_none();
+
+        // If the block saved less than 1/64 of its size, the data is likely already compressed,
+        // so don't spend time searching for matches in the next few blocks.
+        if !use_raw_block
+            && d.params.flags & TDEFL_NONDETERMINISTIC_PARSING_FLAG != 0
+            && d.params.flags & TDEFL_NO_INCOMPRESSIBLE_DETECTION == 0
+            && d.lz.total_bytes >= INCOMPRESSIBLE_MIN_BLOCK_BYTES
+            && compressed_len * 64 >= d.lz.total_bytes as usize * 63
+        {
+            d.params.incompressible_blocks = INCOMPRESSIBLE_SKIP_BLOCKS;
+        }
 
         if use_raw_block || expanded {
             output.load(saved_buffer);
@@ -1679,7 +3165,35 @@ pub(crate) fn flush_block(
             }
         } else if !comp_success {
             output.load(saved_buffer);
-            compress_block(&mut d.huff, &mut output, &d.lz, true)?;
+            compress_block(&mut d.huff, &mut output, &d.lz, true, &mut d.params.timer)?;
+        }
+
+        if let Some(stats) = &mut d.params.stats {
+            if use_raw_block || expanded {
+                stats.stored_blocks += 1;
+            } else {
+                stats.count_codes(&d.lz.codes, d.lz.code_position);
+                if use_static || !comp_success {
+                    stats.static_blocks += 1;
+                } else {
+                    stats.dynamic_blocks += 1;
+                }
+            }
+            stats.input_bytes += u64::from(d.lz.total_bytes);
+        }
+        #[cfg(feature = "with-alloc")]
+        if let Some(tracer) = &mut d.params.tracer {
+            tracer.record_block(TracedBlock {
+                input_bytes: d.lz.total_bytes,
+                block_type: if use_raw_block || expanded {
+                    BlockType::Stored
+                } else if use_static || !comp_success {
+                    BlockType::Static
+                } else {
+                    BlockType::Dynamic
+                },
+                flush,
+            });
         }
 
         if flush != TDEFLFlush::None {
@@ -1702,6 +3216,19 @@ pub(crate) fn flush_block(
             }
         }
 
+        if timing {
+            d.params.timer.lap(BlockPart::SymbolCoding);
+        } else {
+            d.params.timer.stop(BlockPart::SymbolCoding);
+        }
+        #[cfg(feature = "block-timing")]
+        {
+            let block = d.params.timer.end_block(d.params.block_in, d.lz.total_bytes);
+            if let Some(stats) = &mut d.params.stats {
+                stats.add_block_timing(block);
+            }
+        }
+
         d.huff.count[0][..MAX_HUFF_SYMBOLS_0].fill(0);
         d.huff.count[1][..MAX_HUFF_SYMBOLS_1].fill(0);
 
@@ -1710,6 +3237,7 @@ pub(crate) fn flush_block(
         d.lz.flag_position = 0;
         d.lz.num_flags_left = 8;
         d.dict.code_buf_dict_pos += d.lz.total_bytes as usize;
+        d.params.block_in += u64::from(d.lz.total_bytes);
         d.lz.total_bytes = 0;
         d.params.block_index += 1;
 
@@ -1717,11 +3245,33 @@ pub(crate) fn flush_block(
 
         d.params.saved_bit_buffer = saved_buffer.bit_buffer;
         d.params.saved_bits_in = saved_buffer.bits_in;
+        d.params.block_out += saved_buffer.pos as u64;
+        #[cfg(feature = "invariant-checks")]
+        super::invariants::check_block_boundary(d, flush);
+        #[cfg(feature = "with-alloc")]
+        if flush != TDEFLFlush::Finish
+            && let Some(boundaries) = &mut d.params.boundaries
+        {
+            boundaries.push(BlockBoundary {
+                in_offset: d.params.block_in,
+                out_bit_offset: d.params.block_out * 8 + u64::from(saved_buffer.bits_in),
+                flush,
+            });
+        }
     }
 
     Ok(callback.flush_output(saved_buffer, &mut d.params))
 }
 
+/// Returns true if the byte counts of a block of `total` literals are clearly further from
+/// uniform than those of random data, going by the sum of the squared counts.
+fn literals_skewed(counts: &[u16], total: u32) -> bool {
+    let total = u64::from(total);
+    let sum_squares: u64 = counts.iter().map(|&n| u64::from(n) * u64::from(n)).sum();
+    // For random data, this is about `total * total / 256 + total`.
+    sum_squares * 256 * 2 > total * total * 3
+}
+
 pub(crate) fn record_literal(h: &mut HuffmanOxide, lz: &mut LZOxide, lit: u8) {
     lz.total_bytes += 1;
     lz.write_code(lit);
@@ -1732,7 +3282,12 @@ pub(crate) fn record_literal(h: &mut HuffmanOxide, lz: &mut LZOxide, lit: u8) {
     h.count[0][lit as usize] += 1;
 }
 
-fn record_match(h: &mut HuffmanOxide, lz: &mut LZOxide, match_len: u32, mut match_dist: u32) {
+pub(crate) fn record_match(
+    h: &mut HuffmanOxide,
+    lz: &mut LZOxide,
+    match_len: u32,
+    mut match_dist: u32,
+) {
     debug_assert!(match_len >= MIN_MATCH_LEN.into());
     debug_assert!(match_dist >= 1);
     debug_assert!(match_dist as usize <= LZ_DICT_SIZE);
@@ -1774,9 +3329,24 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
 
     while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size != 0) {
         let src_buf_left = in_buf.len() - src_pos;
-        let num_bytes_to_process = cmp::min(src_buf_left, MAX_MATCH_LEN - lookahead_size);
+        // The lookahead can be longer after switching from another compression level.
+        let num_bytes_to_process =
+            cmp::min(src_buf_left, MAX_MATCH_LEN.saturating_sub(lookahead_size));
+
+        if d.dict.hash_len > MIN_MATCH_LEN {
+            // The positions are added to the hash chains as they are reached instead.
+            let dictb = &mut d.dict.b;
+            for &c in &in_buf[src_pos..src_pos + num_bytes_to_process] {
+                let dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+                dictb.dict[dst_pos] = c;
+                if dst_pos < MAX_MATCH_LEN - 1 {
+                    dictb.dict[LZ_DICT_SIZE + dst_pos] = c;
+                }
+                lookahead_size += 1;
+            }
 
-        if lookahead_size + d.dict.size >= usize::from(MIN_MATCH_LEN) - 1
+            src_pos += num_bytes_to_process;
+        } else if lookahead_size + d.dict.size >= usize::from(MIN_MATCH_LEN) - 1
             && num_bytes_to_process > 0
         {
             let dictb = &mut d.dict.b;
@@ -1862,15 +3432,31 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
                     cur_match_dist = 1
                 }
             }
-        } else {
+        } else if d.params.skip_matches() {
+            // The block will be output as a raw block, so there is no point looking for
+            // matches.
+            cur_match_len = 0;
+        } else if d.dict.update_hash_chains(lookahead_pos, lookahead_size) {
             // Try to find a match for the bytes at the current position.
-            let dist_len = d.dict.find_match(
-                lookahead_pos,
-                d.dict.size,
-                lookahead_size as u32,
-                cur_match_dist,
-                cur_match_len,
-            );
+            let (max_dist, max_match_len) = (d.dict.size, lookahead_size as u32);
+            let dist_len = match &mut d.params.stats {
+                Some(stats) => d.dict.find_match(
+                    lookahead_pos,
+                    max_dist,
+                    max_match_len,
+                    cur_match_dist,
+                    cur_match_len,
+                    stats,
+                ),
+                None => d.dict.find_match(
+                    lookahead_pos,
+                    max_dist,
+                    max_match_len,
+                    cur_match_dist,
+                    cur_match_len,
+                    &mut (),
+                ),
+            };
             cur_match_dist = dist_len.0;
             cur_match_len = dist_len.1;
         }
@@ -1883,9 +3469,16 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         }
 
         if saved_match_len != 0 {
-            if cur_match_len > saved_match_len {
+            // Drop the match found at the previous byte for a longer one at this byte.
+            if d
+                .params
+                .lazy_choice(cur_match_len > saved_match_len, cur_match_dist != 0)
+            {
+                if let Some(stats) = &mut d.params.stats {
+                    stats.lazy_rejected += 1;
+                }
                 record_literal(&mut d.huff, &mut d.lz, saved_lit);
-                if cur_match_len >= 128 {
+                if !d.params.lazy_choice(cur_match_len < d.params.max_lazy, true) {
                     record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
                     saved_match_len = 0;
                     len_to_move = cur_match_len as usize;
@@ -1905,12 +3498,14 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
                 &mut d.lz,
                 d.dict.b.dict[cmp::min(cur_pos, d.dict.b.dict.len() - 1)],
             );
-        } else if d.params.greedy_parsing
-            || (d.params.flags & TDEFL_RLE_MATCHES != 0)
-            || cur_match_len >= 128
-        {
+        } else if !d.params.lazy_choice(
+            !d.params.greedy_parsing
+                && (d.params.flags & TDEFL_RLE_MATCHES == 0)
+                && cur_match_len < d.params.max_lazy,
+            true,
+        ) {
             // If we are using lazy matching, check for matches at the next byte if the current
-            // match was shorter than 128 bytes.
+            // match was shorter than `max_lazy` bytes.
             record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
             len_to_move = cur_match_len as usize;
         } else {
@@ -1923,12 +3518,21 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         assert!(lookahead_size >= len_to_move);
         lookahead_size -= len_to_move;
         d.dict.size = cmp::min(d.dict.size + len_to_move, LZ_DICT_SIZE);
+        if d.params.greedy_parsing && len_to_move > MAX_INSERT_MATCH_LEN {
+            // Skip adding the positions inside a long match to the hash chains.
+            d.dict.insert_pos = cmp::max(d.dict.insert_pos, lookahead_pos);
+        }
 
         let lz_buf_tight = d.lz.code_position > LZ_CODE_BUF_SIZE - 8;
         let fat = ((d.lz.code_position * 115) >> 7) >= d.lz.total_bytes as usize;
         let buf_fat = (d.lz.total_bytes > 31 * 1024) && fat;
+        // Keep the blocks small while the data doesn't compress, so that checking whether it
+        // compresses again is cheap, and compressible data following it is found quickly.
+        let raw_full = d.params.incompressible_blocks > 0
+            && d.lz.total_bytes >= INCOMPRESSIBLE_MIN_BLOCK_BYTES;
 
-        if lz_buf_tight || buf_fat {
+        let split = lz_buf_tight || buf_fat || raw_full;
+        if d.params.split_block(split, lz_buf_tight, d.lz.total_bytes) {
             d.params.src_pos = src_pos;
             // These values are used in flush_block, so we need to write them back here.
             d.dict.lookahead_size = lookahead_size;
@@ -1956,6 +3560,23 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
 
 const COMP_FAST_LOOKAHEAD_SIZE: usize = 4096;
 
+/// Hash of the first three bytes at a position used by [`compress_fast`].
+#[inline]
+fn fast_hash(trigram: u32) -> usize {
+    ((trigram ^ (trigram >> (24 - (LZ_HASH_BITS - 8)))) & LEVEL1_HASH_SIZE_MASK) as usize
+}
+
+/// Hash of the first four bytes at a position used by [`compress_quick`].
+#[inline]
+fn quick_hash(first: u32) -> usize {
+    (first.wrapping_mul(0x9E37_79B1) >> (32 - QUICK_HASH_BITS)) as usize
+}
+/// Blocks with at least this many input bytes are checked for whether the data compresses.
+pub(crate) const INCOMPRESSIBLE_MIN_BLOCK_BYTES: u32 = 8 * 1024;
+/// Number of blocks after one that didn't compress before trying to compress again. Each of
+/// these is `INCOMPRESSIBLE_MIN_BLOCK_BYTES` long.
+const INCOMPRESSIBLE_SKIP_BLOCKS: u32 = 8;
+
 fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
     let mut src_pos = d.params.src_pos;
     let mut lookahead_size = d.dict.lookahead_size;
@@ -2002,13 +3623,14 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
 
             let first_trigram = d.dict.read_unaligned_u32(cur_pos) & 0xFF_FFFF;
 
-            let hash = (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8))))
-                & LEVEL1_HASH_SIZE_MASK;
+            let hash = fast_hash(first_trigram);
 
-            let mut probe_pos = usize::from(d.dict.b.hash[hash as usize]);
-            d.dict.b.hash[hash as usize] = lookahead_pos as u16;
+            let mut probe_pos = usize::from(d.dict.b.hash[hash]);
+            d.dict.b.hash[hash] = lookahead_pos as u16;
 
-            let mut cur_match_dist = (lookahead_pos - probe_pos) as u16;
+            // Wraps if the entry was made by another compression level for a position in the
+            // lookahead, which gives a distance that is too large.
+            let mut cur_match_dist = lookahead_pos.wrapping_sub(probe_pos) as u16;
             if cur_match_dist as usize <= d.dict.size {
                 probe_pos &= LZ_DICT_SIZE_MASK;
 
@@ -2157,8 +3779,148 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
     true
 }
 
+/// Quick compression path, similar to `compress_fast` but trading compression ratio for speed.
+///
+/// Matches are found using a small hash table of 4-byte sequences with a single probe and
+/// greedy parsing, and only static blocks are output, so symbol frequencies don't need to be
+/// counted.
+fn compress_quick(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
+    let mut src_pos = d.params.src_pos;
+    let mut lookahead_size = d.dict.lookahead_size;
+    let mut lookahead_pos = d.dict.lookahead_pos;
+
+    let mut cur_pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+    let in_buf = match callback.in_buf {
+        None => return true,
+        Some(in_buf) => in_buf,
+    };
+
+    debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
+
+    while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size > 0) {
+        let mut dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+        let mut num_bytes_to_process = cmp::min(
+            in_buf.len() - src_pos,
+            COMP_FAST_LOOKAHEAD_SIZE - lookahead_size,
+        );
+        lookahead_size += num_bytes_to_process;
+
+        while num_bytes_to_process != 0 {
+            let n = cmp::min(LZ_DICT_SIZE - dst_pos, num_bytes_to_process);
+            d.dict.b.dict[dst_pos..dst_pos + n].copy_from_slice(&in_buf[src_pos..src_pos + n]);
+
+            if dst_pos < MAX_MATCH_LEN - 1 {
+                let m = cmp::min(n, MAX_MATCH_LEN - 1 - dst_pos);
+                d.dict.b.dict[dst_pos + LZ_DICT_SIZE..dst_pos + LZ_DICT_SIZE + m]
+                    .copy_from_slice(&in_buf[src_pos..src_pos + m]);
+            }
+
+            src_pos += n;
+            dst_pos = (dst_pos + n) & LZ_DICT_SIZE_MASK;
+            num_bytes_to_process -= n;
+        }
+
+        d.dict.size = cmp::min(LZ_DICT_SIZE - lookahead_size, d.dict.size);
+        if d.params.flush == TDEFLFlush::None && lookahead_size < COMP_FAST_LOOKAHEAD_SIZE {
+            break;
+        }
+
+        let mut dict_size = d.dict.size;
+        // Keep enough lookahead for a full match until the input is flushed.
+        let min_lookahead = if d.params.flush == TDEFLFlush::None {
+            MAX_MATCH_LEN
+        } else {
+            1
+        };
+
+        while lookahead_size >= min_lookahead {
+            let mut cur_match_len = 1;
+
+            if lookahead_size >= 4 {
+                let first = d.dict.read_unaligned_u32(cur_pos);
+                let hash = quick_hash(first);
+
+                let probe_pos = usize::from(d.dict.b.hash[hash]);
+                d.dict.b.hash[hash] = lookahead_pos as u16;
+
+                let dist = lookahead_pos.wrapping_sub(probe_pos) as u16 as usize;
+                if dist != 0
+                    && dist <= dict_size
+                    && d.dict.read_unaligned_u32(probe_pos) == first
+                {
+                    // The first 4 bytes were tested, so start comparing after those.
+                    let mut len = 4;
+                    let max_len = cmp::min(lookahead_size, MAX_MATCH_LEN);
+                    while len < max_len {
+                        let xor_data = d.dict.read_unaligned_u64(cur_pos + len)
+                            ^ d.dict.read_unaligned_u64(probe_pos + len);
+                        if xor_data != 0 {
+                            len += (xor_data.trailing_zeros() >> 3) as usize;
+                            break;
+                        }
+                        len += 8;
+                    }
+                    cur_match_len = cmp::min(len, max_len) as u32;
+
+                    let match_dist = dist - 1;
+                    d.lz.write_code((cur_match_len - u32::from(MIN_MATCH_LEN)) as u8);
+                    d.lz.write_code(match_dist as u8);
+                    d.lz.write_code((match_dist >> 8) as u8);
+                    *d.lz.get_flag() >>= 1;
+                    *d.lz.get_flag() |= 0x80;
+                }
+            }
+
+            if cur_match_len == 1 {
+                d.lz.write_code(d.dict.b.dict[cur_pos]);
+                *d.lz.get_flag() >>= 1;
+            }
+
+            d.lz.consume_flag();
+            d.lz.total_bytes += cur_match_len;
+            lookahead_pos += cur_match_len as usize;
+            dict_size = cmp::min(dict_size + cur_match_len as usize, LZ_DICT_SIZE);
+            cur_pos = (cur_pos + cur_match_len as usize) & LZ_DICT_SIZE_MASK;
+            lookahead_size -= cur_match_len as usize;
+
+            if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+                // These values are used in flush_block, so we need to write them back here.
+                d.dict.lookahead_size = lookahead_size;
+                d.dict.lookahead_pos = lookahead_pos;
+                d.dict.size = dict_size;
+
+                let n = match flush_block(d, callback, TDEFLFlush::None) {
+                    Err(_) => {
+                        d.params.src_pos = src_pos;
+                        d.params.prev_return_status = TDEFLStatus::PutBufFailed;
+                        return false;
+                    }
+                    Ok(status) => status,
+                };
+                if n != 0 {
+                    d.params.src_pos = src_pos;
+                    return n > 0;
+                }
+                debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
+
+                lookahead_size = d.dict.lookahead_size;
+                lookahead_pos = d.dict.lookahead_pos;
+            }
+
+        }
+        d.dict.size = dict_size;
+    }
+
+    d.params.src_pos = src_pos;
+    d.dict.lookahead_size = lookahead_size;
+    d.dict.lookahead_pos = lookahead_pos;
+    true
+}
+
 fn flush_output_buffer(c: &mut CallbackOxide, p: &mut ParamsOxide) -> (TDEFLStatus, usize, usize) {
     let mut res = (TDEFLStatus::Okay, p.src_pos, 0);
+    // Output that a paused callback didn't take is passed to it at the start of the next
+    // call instead, in `compress_step`.
     if let CallbackOut::Buf(ref mut cb) = c.out {
         let n = cmp::min(cb.out_buf.len() - p.out_buf_ofs, p.flush_remaining as usize);
         if n != 0 {
@@ -2199,45 +3961,152 @@ pub fn compress(
     out_buf: &mut [u8],
     flush: TDEFLFlush,
 ) -> (TDEFLStatus, usize, usize) {
-    compress_inner(
+    let res = compress_inner(
         d,
         &mut CallbackOxide::new_callback_buf(in_buf, out_buf),
         flush,
-    )
+    );
+    d.params.total_in += res.1 as u64;
+    d.params.total_out += res.2 as u64;
+    #[cfg(feature = "std")]
+    d.record_metrics(res.0, flush, (in_buf.len(), res.1, res.2 as u64));
+    res
+}
+
+/// The function that does the compression for a set of flags.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum CompressPath {
+    Stored,
+    Quick,
+    Optimal,
+    Fast,
+    Normal,
+}
+
+impl CompressPath {
+    pub(crate) fn from_flags(flags: u32) -> CompressPath {
+        let one_probe = flags & MAX_PROBES_MASK == 1;
+        let greedy = flags & TDEFL_GREEDY_PARSING_FLAG != 0;
+        let filter_or_rle = flags & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS) != 0;
+
+        let raw = flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
+
+        let quick = flags & TDEFL_QUICK_PARSING_FLAG != 0;
+        let optimal =
+            flags & (TDEFL_OPTIMAL_PARSING_FLAG | TDEFL_RLE_MATCHES) == TDEFL_OPTIMAL_PARSING_FLAG;
+
+        if raw {
+            CompressPath::Stored
+        } else if quick && !filter_or_rle {
+            CompressPath::Quick
+        } else if optimal && !filter_or_rle && cfg!(feature = "with-alloc") {
+            CompressPath::Optimal
+        } else if one_probe && greedy && !filter_or_rle {
+            CompressPath::Fast
+        } else {
+            CompressPath::Normal
+        }
+    }
 }
 
 /// Main compression function. Callbacks output.
 ///
+/// The callback returns a [`PutBufResult`], or a `bool`, to say whether it took the output.
+/// It can pause the compression when it has no room, e.g. in a fixed ring buffer, and the
+/// compression is resumed by calling this again once it has, with the input that wasn't
+/// consumed.
+///
 /// # Returns
 /// Returns a tuple containing the current status of the compressor, the current position
 /// in the input buffer.
 ///
 /// The caller is responsible for ensuring the `CallbackFunc` struct will not cause undefined
 /// behaviour.
-pub fn compress_to_output(
+pub fn compress_to_output<R: Into<PutBufResult>>(
     d: &mut CompressorOxide,
     in_buf: &[u8],
     flush: TDEFLFlush,
-    mut callback_func: impl FnMut(&[u8]) -> bool,
+    mut callback_func: impl FnMut(&[u8]) -> R,
 ) -> (TDEFLStatus, usize) {
+    let mut out_bytes = 0;
     let res = compress_inner(
         d,
         &mut CallbackOxide::new_callback_func(
             in_buf,
             CallbackFunc {
-                put_buf_func: &mut callback_func,
+                put_buf_func: &mut |buf: &[u8]| {
+                    let res = callback_func(buf).into();
+                    out_bytes += match res {
+                        PutBufResult::Accepted => buf.len() as u64,
+                        PutBufResult::Pause(n) => cmp::min(n, buf.len()) as u64,
+                        PutBufResult::Abort => 0,
+                    };
+                    res
+                },
             },
         ),
         flush,
     );
+    d.params.total_in += res.1 as u64;
+    d.params.total_out += out_bytes;
+    #[cfg(feature = "std")]
+    d.record_metrics(res.0, flush, (in_buf.len(), res.1, out_bytes));
 
     (res.0, res.1)
 }
 
+/// Compress the input of `callback`, cutting it at the full flush points set with
+/// [`CompressorOxide::set_full_flush_interval`], if any.
 fn compress_inner(
     d: &mut CompressorOxide,
     callback: &mut CallbackOxide,
     flush: TDEFLFlush,
+) -> (TDEFLStatus, usize, usize) {
+    let interval = d.params.flush_interval;
+    if interval == 0 {
+        return compress_step(d, callback, flush);
+    }
+
+    let in_buf = callback.in_buf.unwrap_or(&[]);
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    loop {
+        let rest = &in_buf[in_pos..];
+        let until_point = d.params.next_flush_at - (d.params.total_in + in_pos as u64);
+        // Input that ends right at a point when finishing doesn't need the flush.
+        let before_point = (rest.len() as u64) < until_point
+            || (flush == TDEFLFlush::Finish && rest.len() as u64 == until_point);
+        let (chunk, chunk_flush) = if before_point {
+            (rest, flush)
+        } else {
+            (&rest[..until_point as usize], TDEFLFlush::Full)
+        };
+
+        callback.in_buf = Some(chunk);
+        let (status, chunk_in, chunk_out) = compress_step(d, callback, chunk_flush);
+        in_pos += chunk_in;
+        out_pos += chunk_out;
+
+        // Carry on after the point once the flush is complete, otherwise the next call
+        // finishes it.
+        let flushed =
+            d.params.full_flush_in == d.params.next_flush_at && d.params.flush_remaining == 0;
+        if before_point || status != TDEFLStatus::Okay || chunk_in != chunk.len() || !flushed {
+            return (status, in_pos, out_pos);
+        }
+        d.params.next_flush_at += interval;
+        if let CallbackOut::Buf(ref mut cb) = callback.out {
+            let out_buf = mem::take(&mut cb.out_buf);
+            cb.out_buf = &mut out_buf[chunk_out..];
+        }
+    }
+}
+
+/// Compress the input of `callback`, flushing as requested by `flush`.
+fn compress_step(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    flush: TDEFLFlush,
 ) -> (TDEFLStatus, usize, usize) {
     d.params.out_buf_ofs = 0;
     d.params.src_pos = 0;
@@ -2252,24 +4121,38 @@ fn compress_inner(
     }
 
     if d.params.flush_remaining != 0 || d.params.finished {
+        let aborted = match callback.out {
+            CallbackOut::Func(ref mut cf) if d.params.flush_remaining != 0 => {
+                cf.put_pending(&mut d.params) < 0
+            }
+            _ => false,
+        };
+        if aborted {
+            return (d.params.prev_return_status, 0, 0);
+        }
         let res = flush_output_buffer(callback, &mut d.params);
         d.params.prev_return_status = res.0;
         return res;
     }
 
-    let one_probe = d.params.flags & MAX_PROBES_MASK == 1;
-    let greedy = d.params.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
-    let filter_or_rle = d.params.flags & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS) != 0;
-
-    let raw = d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
+    if d.params.adaptive.is_some() {
+        adapt_level(d, callback.buf().unwrap_or(&[]), flush);
+    }
 
-    let compress_success = if raw {
-        compress_stored(d, callback)
-    } else if one_probe && greedy && !filter_or_rle {
-        compress_fast(d, callback)
-    } else {
-        compress_normal(d, callback)
+    d.params.timer.start();
+    let compress_success = match CompressPath::from_flags(d.params.flags) {
+        CompressPath::Stored => compress_stored(d, callback),
+        CompressPath::Quick => compress_quick(d, callback),
+        #[cfg(feature = "with-alloc")]
+        CompressPath::Optimal => compress_optimal(d, callback),
+        CompressPath::Fast => compress_fast(d, callback),
+        // The optimal path isn't picked without `with-alloc`.
+        #[cfg(not(feature = "with-alloc"))]
+        CompressPath::Optimal | CompressPath::Normal => compress_normal(d, callback),
+        #[cfg(feature = "with-alloc")]
+        CompressPath::Normal => compress_normal(d, callback),
     };
+    d.params.timer.stop(BlockPart::MatchFinding);
 
     if !compress_success {
         return (
@@ -2312,6 +4195,7 @@ fn compress_inner(
                     d.dict.b.hash.fill(0);
                     d.dict.b.next.fill(0);
                     d.dict.size = 0;
+                    d.params.full_flush_in = d.params.block_in;
                 }
             }
         }
@@ -2327,7 +4211,8 @@ fn compress_inner(
 /// Mainly intended for use with transition from c libraries as it deals with raw integers.
 ///
 /// # Parameters
-/// `level` determines compression level. Clamped to maximum of 10. Negative values result in
+/// `level` determines compression level. Levels above 10 use the settings of level 10 with
+/// optimal parsing (`CompressionLevel::UltraCompression`). Negative values result in
 /// `CompressionLevel::DefaultLevel`.
 /// `window_bits`: Above 0, wraps the stream in a zlib wrapper, 0 or negative for a raw deflate
 /// stream.
@@ -2348,6 +4233,10 @@ pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy:
     };
     let mut comp_flags = u32::from(NUM_PROBES[num_probes]) | greedy;
 
+    if level > 10 {
+        comp_flags |= TDEFL_OPTIMAL_PARSING_FLAG;
+    }
+
     if window_bits > 0 {
         comp_flags |= TDEFL_WRITE_ZLIB_HEADER;
     }
@@ -2360,6 +4249,8 @@ pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy:
         comp_flags &= !MAX_PROBES_MASK;
     } else if strategy == CompressionStrategy::Fixed as i32 {
         comp_flags |= TDEFL_FORCE_ALL_STATIC_BLOCKS;
+    } else if strategy == CompressionStrategy::Quick as i32 {
+        comp_flags |= TDEFL_QUICK_PARSING_FLAG;
     } else if strategy == CompressionStrategy::RLE as i32 {
         comp_flags |= TDEFL_RLE_MATCHES;
     }
@@ -0,0 +1,188 @@
+//! Checks of the compressor state at block boundaries, with the `invariant-checks` feature.
+//!
+//! These look for state that has been corrupted, e.g. by restoring a checkpoint or loading a
+//! dictionary the wrong way, close to where it happened rather than when it shows up as a
+//! bad stream much later. A failed check panics with what is wrong. Checking the hash chains
+//! walks the whole window, so this is only meant for tests and debugging.
+
+use super::buffer::{LZ_HASH_SHIFT, LZ_HASH_SIZE};
+use super::core::{
+    CompressPath, CompressorOxide, LZ_DICT_SIZE, LZ_DICT_SIZE_MASK, MAX_MATCH_LEN, MIN_MATCH_LEN,
+    TDEFLFlush,
+};
+
+/// Check the state of `d` right after a block was ended with `flush`.
+pub(crate) fn check_block_boundary(d: &CompressorOxide, flush: TDEFLFlush) {
+    check_bit_buffer(d, flush);
+    check_window(d);
+    let path = CompressPath::from_flags(d.params.flags);
+    if matches!(path, CompressPath::Normal | CompressPath::Optimal)
+        && d.dict.hash_len == MIN_MATCH_LEN
+    {
+        check_hash_chains(d);
+    }
+}
+
+/// The bits left over from the block fit in a byte, with nothing set above them, and a flush
+/// leaves none.
+fn check_bit_buffer(d: &CompressorOxide, flush: TDEFLFlush) {
+    let (bits_in, bit_buffer) = (d.params.saved_bits_in, d.params.saved_bit_buffer);
+    assert!(bits_in < 8, "{bits_in} bits left in the bit buffer");
+    assert_eq!(
+        bit_buffer >> bits_in,
+        0,
+        "bits set above the {bits_in} in the bit buffer: {bit_buffer:#x}"
+    );
+    if flush != TDEFLFlush::None {
+        assert_eq!(bits_in, 0, "bits left in the bit buffer after a flush");
+    }
+}
+
+/// The positions are in range, and the copy of the start of the window after its end, which
+/// lets matches run past the end without wrapping, is up to date.
+fn check_window(d: &CompressorOxide) {
+    let dict = &d.dict;
+    assert!(
+        dict.size + dict.lookahead_size <= LZ_DICT_SIZE,
+        "window of {} bytes and lookahead of {} don't fit",
+        dict.size,
+        dict.lookahead_size
+    );
+    assert!(
+        dict.code_buf_dict_pos <= dict.lookahead_pos,
+        "coded up to {} past the lookahead position {}",
+        dict.code_buf_dict_pos,
+        dict.lookahead_pos
+    );
+    let b = &dict.b.dict;
+    let mirror = &b[LZ_DICT_SIZE..LZ_DICT_SIZE + MAX_MATCH_LEN - 1];
+    if let Some(i) = (0..mirror.len()).find(|&i| mirror[i] != b[i]) {
+        panic!("window byte {i} differs from its copy after the end of the window");
+    }
+}
+
+/// Every position in the window that has been added to the hash chains can be reached from
+/// the head of the chain of its hash.
+///
+/// Chain links hold positions truncated to 16 bits, and an empty chain is the same as a link to
+/// position 0, so a link can look like one to an unrelated position in the window. That is why
+/// a link to a position with a different hash is taken as the end of the chain rather than an
+/// error.
+fn check_hash_chains(d: &CompressorOxide) {
+    let dict = &d.dict;
+    let b = &dict.b;
+    let end = dict.lookahead_pos + dict.lookahead_size;
+    // A position is added once the two bytes after it are there.
+    let Some(hashed_end) = end.checked_sub(usize::from(MIN_MATCH_LEN) - 1) else {
+        return;
+    };
+    let start = dict.lookahead_pos.saturating_sub(dict.size).max(dict.chains_from);
+    if start >= hashed_end {
+        return;
+    }
+    let hash = |pos: usize| {
+        let byte = |i: usize| usize::from(b.dict[(pos + i) & LZ_DICT_SIZE_MASK]);
+        ((byte(0) << (LZ_HASH_SHIFT * 2)) ^ (byte(1) << LZ_HASH_SHIFT) ^ byte(2))
+            & (LZ_HASH_SIZE - 1)
+    };
+    // Turn a truncated position into the latest one at or before `before`.
+    let untruncate = |pos: u16, before: usize| {
+        before.checked_sub(before.wrapping_sub(pos.into()) & usize::from(u16::MAX))
+    };
+
+    let mut reached = [0u64; LZ_DICT_SIZE / 64];
+    for (h, &head) in b.hash.iter().enumerate() {
+        let mut pos = untruncate(head, hashed_end - 1);
+        while let Some(p) = pos.filter(|&p| p >= start && hash(p) == h) {
+            let (word, bit) = ((p & LZ_DICT_SIZE_MASK) / 64, p % 64);
+            if reached[word] & (1 << bit) != 0 {
+                break;
+            }
+            reached[word] |= 1 << bit;
+            pos = p
+                .checked_sub(1)
+                .and_then(|before| untruncate(b.next[p & LZ_DICT_SIZE_MASK], before));
+        }
+    }
+
+    if let Some(pos) = (start..hashed_end)
+        .find(|&pos| reached[(pos & LZ_DICT_SIZE_MASK) / 64] & (1 << (pos % 64)) == 0)
+    {
+        panic!(
+            "position {pos} with hash {:#x} can't be reached from the head of its hash chain",
+            hash(pos)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "with-alloc"))]
+mod test {
+    use crate::DataFormat;
+    use crate::deflate::core::{CompressorOxide, TDEFLFlush, compress};
+    use crate::inflate::decompress_to_vec;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// Compress `data` in parts of `part` bytes with a sync flush after every other one.
+    fn compress_parts(d: &mut CompressorOxide, data: &[u8], part: usize) -> Vec<u8> {
+        let mut output = vec![0; data.len() * 2 + 1000];
+        let mut out_pos = 0;
+        let parts = data.chunks(part).count();
+        for (i, chunk) in data.chunks(part).enumerate() {
+            let flush = match i {
+                _ if i + 1 == parts => TDEFLFlush::Finish,
+                _ if i % 2 == 1 => TDEFLFlush::Sync,
+                _ => TDEFLFlush::None,
+            };
+            let (status, in_pos, written) = compress(d, chunk, &mut output[out_pos..], flush);
+            assert!(status as i32 >= 0);
+            assert_eq!(in_pos, chunk.len());
+            out_pos += written;
+        }
+        output.truncate(out_pos);
+        output
+    }
+
+    #[test]
+    fn levels_keep_invariants() {
+        let data = crate::test_util::random(200_000, 8);
+        for level in 0..=10 {
+            let mut d = CompressorOxide::default();
+            d.set_format_and_level(DataFormat::Raw, level);
+            let compressed = compress_parts(&mut d, &data, 30_000);
+            assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn dictionary_level_change_and_checkpoint_keep_invariants() {
+        let data = crate::test_util::random(150_000, 8);
+        let mut d = CompressorOxide::default();
+        d.set_format_and_level(DataFormat::Raw, 6);
+        d.set_dictionary(&data[..40_000]);
+        let mut output = vec![0; 200_000];
+        let (_, _, len) = compress(&mut d, &data[40_000..70_000], &mut output, TDEFLFlush::Sync);
+        d.set_format_and_level(DataFormat::Raw, 1);
+        compress(
+            &mut d,
+            &data[70_000..90_000],
+            &mut output[len..],
+            TDEFLFlush::Sync,
+        );
+        d.set_format_and_level(DataFormat::Raw, 9);
+
+        let mut d = CompressorOxide::from_checkpoint(&d.checkpoint()).unwrap();
+        compress_parts(&mut d, &data[90_000..], 20_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be reached from the head of its hash chain")]
+    fn detects_broken_hash_chains() {
+        let data = crate::test_util::random(50_000, 8);
+        let mut d = CompressorOxide::default();
+        let mut output = vec![0; 100_000];
+        compress(&mut d, &data[..20_000], &mut output, TDEFLFlush::None);
+        d.dict.b.hash.fill(0);
+        compress(&mut d, &data[20_000..], &mut output, TDEFLFlush::Sync);
+    }
+}
@@ -0,0 +1,97 @@
+//! Finding the first mismatching byte when extending a match.
+//!
+//! With the `unsafe-perf` feature this uses 16-byte vector compares (SSE2 on x86_64, NEON on
+//! aarch64). Otherwise 8 bytes are compared at a time as 64-bit integers, which is as fast as
+//! the safe alternatives that were tried.
+
+#[cfg(all(
+    feature = "unsafe-perf",
+    not(feature = "safe"),
+    any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_feature = "neon")
+    )
+))]
+mod imp {
+    /// Number of bytes compared by [`matching_bytes`].
+    pub const MATCH_COMPARE_BYTES: usize = 16;
+
+    /// Return the number of leading bytes that are equal in `a` and `b`.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn matching_bytes(a: &[u8; 16], b: &[u8; 16]) -> u32 {
+        use core::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+        // SAFETY: Both arrays are 16 bytes long and `_mm_loadu_si128` has no alignment
+        // requirement. SSE2 is always available on x86_64.
+        let mask = unsafe {
+            let a = _mm_loadu_si128(a.as_ptr().cast::<__m128i>());
+            let b = _mm_loadu_si128(b.as_ptr().cast::<__m128i>());
+            _mm_movemask_epi8(_mm_cmpeq_epi8(a, b)) as u32
+        };
+        // Bit 16 of the inverted mask is always set, so this gives 16 if all bytes matched.
+        (!mask).trailing_zeros()
+    }
+
+    /// Return the number of leading bytes that are equal in `a` and `b`.
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    pub fn matching_bytes(a: &[u8; 16], b: &[u8; 16]) -> u32 {
+        use core::arch::aarch64::{
+            vceqq_u8, vget_lane_u64, vld1q_u8, vreinterpret_u64_u8, vreinterpretq_u16_u8,
+            vshrn_n_u16,
+        };
+        // SAFETY: Both arrays are 16 bytes long and `vld1q_u8` has no alignment requirement.
+        // NEON is checked for by the `cfg` on this module.
+        let mask = unsafe {
+            let eq = vceqq_u8(vld1q_u8(a.as_ptr()), vld1q_u8(b.as_ptr()));
+            // NEON has no movemask, so narrow the compare result to 4 bits per byte instead.
+            vget_lane_u64::<0>(vreinterpret_u64_u8(vshrn_n_u16::<4>(vreinterpretq_u16_u8(
+                eq,
+            ))))
+        };
+        (!mask).trailing_zeros() / 4
+    }
+}
+
+#[cfg(not(all(
+    feature = "unsafe-perf",
+    not(feature = "safe"),
+    any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_feature = "neon")
+    )
+)))]
+mod imp {
+    /// Number of bytes compared by [`matching_bytes`].
+    pub const MATCH_COMPARE_BYTES: usize = 8;
+
+    /// Return the number of leading bytes that are equal in `a` and `b`.
+    #[inline]
+    pub fn matching_bytes(a: &[u8; 8], b: &[u8; 8]) -> u32 {
+        let xor_data = u64::from_le_bytes(*a) ^ u64::from_le_bytes(*b);
+        // Gives 64 / 8 = 8 if all bytes matched.
+        xor_data.trailing_zeros() >> 3
+    }
+}
+
+pub(crate) use imp::{matching_bytes, MATCH_COMPARE_BYTES};
+
+#[cfg(test)]
+mod test {
+    use super::{matching_bytes, MATCH_COMPARE_BYTES};
+
+    #[test]
+    fn mismatch_position() {
+        let a = [7u8; MATCH_COMPARE_BYTES];
+        assert_eq!(matching_bytes(&a, &a) as usize, MATCH_COMPARE_BYTES);
+        for i in 0..MATCH_COMPARE_BYTES {
+            let mut b = a;
+            b[i] = 8;
+            // Bytes after the first mismatch don't matter.
+            if i + 1 < MATCH_COMPARE_BYTES {
+                b[MATCH_COMPARE_BYTES - 1] = 0;
+            }
+            assert_eq!(matching_bytes(&a, &b) as usize, i);
+        }
+    }
+}
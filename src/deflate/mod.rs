@@ -1,14 +1,40 @@
 //! This module contains functionality for compression.
 
+#[cfg(feature = "with-alloc")]
 use crate::alloc::vec;
+#[cfg(feature = "with-alloc")]
 use crate::alloc::vec::Vec;
+#[cfg(feature = "with-alloc")]
+use crate::DataFormat;
+#[cfg(feature = "std")]
+use thiserror::Error;
 
+mod adaptive;
 mod buffer;
+#[cfg(feature = "with-alloc")]
+mod checkpoint;
 pub mod core;
+#[cfg(feature = "invariant-checks")]
+mod invariants;
+mod match_compare;
+#[cfg(feature = "with-alloc")]
+mod optimal;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
+#[cfg(feature = "with-alloc")]
+pub mod png;
+#[cfg(feature = "std")]
+pub mod pool;
 mod stored;
 pub mod stream;
+#[cfg(feature = "with-alloc")]
+pub mod trace;
 mod zlib;
 use self::core::*;
+#[cfg(feature = "parallel")]
+pub use self::parallel::{compress_to_vec_parallel, compress_to_vec_zlib_parallel};
 
 /// How much processing the compressor should do to compress the data.
 /// `NoCompression` and `Bestspeed` have special meanings, the other levels determine the number
@@ -24,12 +50,41 @@ pub enum CompressionLevel {
     BestCompression = 9,
     /// Even more checks, can be very slow.
     UberCompression = 10,
+    /// Choose matches with optimal parsing over a cost model. Several times slower than
+    /// `UberCompression`, for when a few percent smaller output is worth the time, e.g. for
+    /// static assets.
+    UltraCompression = 11,
     /// Default compromise between speed and compression.
     DefaultLevel = 6,
     /// Use the default compression level.
     DefaultCompression = -1,
 }
 
+/// Errors from the streaming compression functions in [`stream`].
+#[cfg(feature = "std")]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CompressError {
+    /// Reading the input or writing the output failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The compressor stopped with the given status, e.g. [`TDEFLStatus::BadParam`] when it
+    /// was used after an earlier failure.
+    #[error("Failed to compress: {0:?}")]
+    Status(TDEFLStatus),
+}
+
+#[cfg(feature = "std")]
+impl From<CompressError> for std::io::Error {
+    /// Unwrap IO errors, and keep the others as the source of an IO error.
+    fn from(err: CompressError) -> Self {
+        match err {
+            CompressError::Io(err) => err,
+            err => std::io::Error::other(err),
+        }
+    }
+}
+
 // Missing safe rust analogue (this and mem-to-mem are quite similar)
 /*
 fn tdefl_compress(
@@ -109,9 +164,11 @@ fn tdefl_compress_mem_to_mem(
 ) -> usize*/
 
 /// Compress the input data to a vector, using the specified compression level (0-10).
+#[cfg(feature = "with-alloc")]
 pub fn compress_to_vec(input: &[u8], level: u8) -> Vec<u8> {
     compress_to_vec_inner(input, level, 0, 0)
 }
+#[cfg(feature = "with-alloc")]
 pub fn compress_to_vec_callback(
     input: &[u8],
     level: u8,
@@ -123,16 +180,171 @@ pub fn compress_to_vec_callback(
 
 /// Compress the input data to a vector, using the specified compression level (0-10), and with a
 /// zlib wrapper.
+#[cfg(feature = "with-alloc")]
 pub fn compress_to_vec_zlib(input: &[u8], level: u8) -> Vec<u8> {
     compress_to_vec_inner(input, level, 1, 0)
 }
 
+/// Compress the input data into `output`, using the specified compression level (0-10).
+///
+/// `output` is cleared first, and its allocation is reused, growing it as needed, so
+/// compressing many messages with the same vector only allocates until it is large enough.
+/// The output is the same as from [`compress_to_vec`].
+#[cfg(feature = "with-alloc")]
+pub fn compress_into_vec(input: &[u8], level: u8, output: &mut Vec<u8>) {
+    compress_into_vec_inner(input, level, 0, 0, output)
+}
+
+/// Compress the input data into `output`, using the specified compression level (0-10), and
+/// with a zlib wrapper. See [`compress_into_vec`].
+#[cfg(feature = "with-alloc")]
+pub fn compress_into_vec_zlib(input: &[u8], level: u8, output: &mut Vec<u8>) {
+    compress_into_vec_inner(input, level, 1, 0, output)
+}
+
+/// Settings for [`compress_to_vec_with`]. The defaults give the same output as
+/// [`compress_to_vec`] with level 6.
+#[cfg(feature = "with-alloc")]
+pub struct CompressOptions<'a> {
+    /// How the compressed data is wrapped.
+    pub format: DataFormat,
+    /// The compression level (0-10).
+    pub level: u8,
+    /// The compression strategy.
+    pub strategy: CompressionStrategy,
+    /// Data the input can refer back to, as with [`CompressorOxide::set_dictionary`]. The
+    /// same dictionary is needed to decompress the output.
+    pub dictionary: Option<&'a [u8]>,
+    /// Called with the number of input bytes taken in, every 64 KiB or so, to report
+    /// progress.
+    pub callback: Option<&'a mut dyn FnMut(usize)>,
+}
+
+#[cfg(feature = "with-alloc")]
+impl Default for CompressOptions<'_> {
+    fn default() -> Self {
+        CompressOptions {
+            format: DataFormat::Raw,
+            level: 6,
+            strategy: CompressionStrategy::Default,
+            dictionary: None,
+            callback: None,
+        }
+    }
+}
+
+/// Compress the input data to a vector, with the settings of `options`.
+///
+/// This covers the other `compress_to_vec` functions, and new settings are added to
+/// [`CompressOptions`] rather than as new functions.
+#[cfg(feature = "with-alloc")]
+pub fn compress_to_vec_with(input: &[u8], options: CompressOptions<'_>) -> Vec<u8> {
+    let flags = create_comp_flags_from_zip_params(
+        options.level.into(),
+        options.format.to_window_bits(),
+        options.strategy.into(),
+    );
+    let mut compressor = CompressorOxide::new(flags);
+    if let Some(dictionary) = options.dictionary {
+        compressor.set_dictionary(dictionary);
+    }
+    let mut callback = options.callback;
+    // Compressing a piece at a time gives the same output, and a chance to report progress.
+    compress_slices(&mut compressor, input.chunks(64 * 1024), |len| {
+        if let Some(callback) = callback.as_mut() {
+            callback(len)
+        }
+    })
+}
+
+/// Compress the input data given as a sequence of slices, e.g. the chunks of a rope, to a
+/// vector, using the specified compression level (0-10).
+///
+/// The output is a single deflate stream, the same as [`compress_to_vec`] gives for the
+/// slices joined together, without copying them into one buffer first.
+#[cfg(feature = "with-alloc")]
+pub fn compress_slice_iter_to_vec<'inp>(
+    it: impl IntoIterator<Item = &'inp [u8]>,
+    level: u8,
+) -> Vec<u8> {
+    compress_slice_iter_to_vec_inner(it, level, 0)
+}
+
+/// Compress the input data given as a sequence of slices to a vector, using the specified
+/// compression level (0-10), and with a zlib wrapper.
+#[cfg(feature = "with-alloc")]
+pub fn compress_slice_iter_to_vec_zlib<'inp>(
+    it: impl IntoIterator<Item = &'inp [u8]>,
+    level: u8,
+) -> Vec<u8> {
+    compress_slice_iter_to_vec_inner(it, level, 1)
+}
+
+/// Get the size of the input data once compressed with the specified compression level (0-10),
+/// without storing the output.
+///
+/// The size is exactly that of the output of [`compress_to_vec`] with the same level, but
+/// no output buffer is allocated.
+#[cfg(feature = "with-alloc")]
+pub fn compressed_size(input: &[u8], level: u8) -> usize {
+    let flags = create_comp_flags_from_zip_params(level.into(), 0, 0);
+    compressed_size_with(&mut CompressorOxide::new(flags), input)
+}
+
+/// Get the size of the input data once compressed with the specified compression level (0-10),
+/// and with a zlib wrapper, without storing the output.
+///
+/// The size is exactly that of the output of [`compress_to_vec_zlib`] with the same level.
+#[cfg(feature = "with-alloc")]
+pub fn compressed_size_zlib(input: &[u8], level: u8) -> usize {
+    let flags = create_comp_flags_from_zip_params(level.into(), 1, 0);
+    compressed_size_with(&mut CompressorOxide::new(flags), input)
+}
+
+/// Compress the input data with `compressor` and end the stream, returning the size of the
+/// output without storing it.
+///
+/// This allows getting the size for any settings of the compressor, e.g. with a strategy,
+/// tuned match parameters or a preset dictionary. Output the compressor has buffered from
+/// earlier calls is included in the size.
+pub fn compressed_size_with(compressor: &mut CompressorOxide, input: &[u8]) -> usize {
+    let mut size = 0;
+    let (status, bytes_in) = compress_to_output(compressor, input, TDEFLFlush::Finish, |buf| {
+        size += buf.len();
+        true
+    });
+    // Not supposed to happen unless there is a bug.
+    assert!(
+        status == TDEFLStatus::Done && bytes_in == input.len(),
+        "Bug! Unexpectedly failed to compress!"
+    );
+    size
+}
+
 /// Simple function to compress data to a vec.
-fn compress_to_vec_inner(mut input: &[u8], level: u8, window_bits: i32, strategy: i32) -> Vec<u8> {
+#[cfg(feature = "with-alloc")]
+fn compress_to_vec_inner(input: &[u8], level: u8, window_bits: i32, strategy: i32) -> Vec<u8> {
+    let mut output = Vec::new();
+    compress_into_vec_inner(input, level, window_bits, strategy, &mut output);
+    output
+}
+
+/// Compress data to `output`, replacing what it held.
+#[cfg(feature = "with-alloc")]
+fn compress_into_vec_inner(
+    mut input: &[u8],
+    level: u8,
+    window_bits: i32,
+    strategy: i32,
+    output: &mut Vec<u8>,
+) {
     // The comp flags function sets the zlib flag if the window_bits parameter is > 0.
     let flags = create_comp_flags_from_zip_params(level.into(), window_bits, strategy);
     let mut compressor = CompressorOxide::new(flags);
-    let mut output = vec![0; ::core::cmp::max(input.len() / 2, 2)];
+    // Use all of the capacity there already is.
+    output.clear();
+    let len = ::core::cmp::max(input.len() / 2, 2);
+    output.resize(::core::cmp::max(output.capacity(), len), 0);
 
     let mut out_pos = 0;
     loop {
@@ -161,9 +373,71 @@ fn compress_to_vec_inner(mut input: &[u8], level: u8, window_bits: i32, strategy
             _ => panic!("Bug! Unexpectedly failed to compress!"),
         }
     }
+}
 
-    output
+/// Compress the slices of `it` one after the other, only finishing the stream with the last.
+#[cfg(feature = "with-alloc")]
+fn compress_slice_iter_to_vec_inner<'inp>(
+    it: impl IntoIterator<Item = &'inp [u8]>,
+    level: u8,
+    window_bits: i32,
+) -> Vec<u8> {
+    let flags = create_comp_flags_from_zip_params(level.into(), window_bits, 0);
+    compress_slices(&mut CompressorOxide::new(flags), it, |_| {})
 }
+
+/// Compress the slices of `it` with `compressor` and finish the stream, passing the length of
+/// each slice to `consumed` once it is taken in.
+#[cfg(feature = "with-alloc")]
+fn compress_slices<'inp>(
+    compressor: &mut CompressorOxide,
+    it: impl IntoIterator<Item = &'inp [u8]>,
+    mut consumed: impl FnMut(usize),
+) -> Vec<u8> {
+    let mut it = it.into_iter().peekable();
+    let mut output = Vec::new();
+    let mut out_pos = 0;
+    loop {
+        // An empty iterator still gives an empty, finished stream.
+        let mut input = it.next().unwrap_or(&[]);
+        let len = input.len();
+        let has_more = it.peek().is_some();
+        let flush = if has_more {
+            TDEFLFlush::None
+        } else {
+            TDEFLFlush::Finish
+        };
+        loop {
+            if output.len() - out_pos < 30 {
+                output.resize(::core::cmp::max(output.len() * 2, input.len() / 2 + 64), 0);
+            }
+            let (status, bytes_in, bytes_out) =
+                compress(compressor, input, &mut output[out_pos..], flush);
+            out_pos += bytes_out;
+
+            match status {
+                TDEFLStatus::Done => {
+                    consumed(len);
+                    output.truncate(out_pos);
+                    return output;
+                }
+                TDEFLStatus::Okay if bytes_in <= input.len() => {
+                    input = &input[bytes_in..];
+                    // Go on to the next slice once this one is taken in; otherwise the output
+                    // is full and grows above.
+                    if has_more && input.is_empty() {
+                        consumed(len);
+                        break;
+                    }
+                }
+                // Not supposed to happen unless there is a bug.
+                _ => panic!("Bug! Unexpectedly failed to compress!"),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "with-alloc")]
 fn compress_to_vec_inner_callback(
     mut input: &[u8],
     level: u8,
@@ -209,11 +483,19 @@ fn compress_to_vec_inner_callback(
 
     output
 }
-#[cfg(test)]
+#[cfg(all(test, feature = "with-alloc"))]
 mod test {
-    use super::{compress_to_vec, compress_to_vec_inner, CompressionStrategy};
-    use crate::inflate::decompress_to_vec;
+    use super::{
+        compress_into_vec, compress_into_vec_zlib, compress_slice_iter_to_vec,
+        compress_slice_iter_to_vec_zlib, compress_to_vec, compress_to_vec_inner,
+        compress_to_vec_zlib, CompressionStrategy,
+    };
+    use super::{compress_to_vec_with, compressed_size_with, CompressOptions};
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_with, DecompressOptions};
+    use crate::DataFormat;
+    use alloc::format;
     use alloc::vec;
+    use alloc::vec::Vec;
 
     /// Test deflate example.
     ///
@@ -251,6 +533,81 @@ mod test {
         assert_eq!(test_data, d.as_slice());
     }
 
+    #[test]
+    fn compress_quick() {
+        let test_data = b"Deflate late";
+
+        let res = compress_to_vec_inner(test_data, 1, 0, CompressionStrategy::Quick as i32);
+        let d = decompress_to_vec(res.as_slice()).expect("Failed to decompress!");
+        assert_eq!(test_data, d.as_slice());
+
+        // Enough data to fill several blocks, with both long matches and runs of
+        // hard to compress data.
+        let mut test_data = crate::test_util::text(300_000);
+        let noise = crate::test_util::random(300_000, 256);
+        for start in (0..300_000).step_by(64) {
+            test_data[start..start + 8].copy_from_slice(&noise[start..start + 8]);
+        }
+        let res = compress_to_vec_inner(&test_data, 1, 0, CompressionStrategy::Quick as i32);
+        // Only static blocks are used.
+        assert_eq!((res[0] >> 1) & 3, 1);
+        assert!(res.len() < test_data.len() / 2);
+        let d = decompress_to_vec(res.as_slice()).expect("Failed to decompress!");
+        assert_eq!(test_data, d);
+    }
+
+    #[test]
+    fn compress_ultra() {
+        let mut test_data = crate::test_util::text(60_000);
+        let noise = crate::test_util::random(60_000, 256);
+        for start in (0..60_000).step_by(40) {
+            test_data[start..start + 3].copy_from_slice(&noise[start..start + 3]);
+        }
+
+        let ultra = compress_to_vec(&test_data, 11);
+        let d = decompress_to_vec(ultra.as_slice()).expect("Failed to decompress!");
+        assert_eq!(test_data, d);
+        assert!(ultra.len() < compress_to_vec(&test_data, 10).len());
+
+        let res = compress_to_vec(b"Deflate late", 11);
+        let d = decompress_to_vec(res.as_slice()).expect("Failed to decompress!");
+        assert_eq!(d, b"Deflate late");
+    }
+
+    #[test]
+    fn incompressible_data() {
+        use super::core::deflate_flags::{
+            TDEFL_NONDETERMINISTIC_PARSING_FLAG, TDEFL_NO_INCOMPRESSIBLE_DETECTION,
+        };
+        use super::core::{compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush};
+
+        // Random data followed by compressible data.
+        let mut test_data = crate::test_util::random(200_000, 256);
+        test_data.extend(crate::test_util::text(300_000));
+
+        let mut buf = vec![0; test_data.len() * 2];
+        let mut compress_with = |flags| {
+            let mut d = CompressorOxide::new(flags);
+            let (_, _, len) = compress(&mut d, &test_data, &mut buf, TDEFLFlush::Finish);
+            buf[..len].to_vec()
+        };
+        for level in [6, 11] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            // Only on request, as it changes the output.
+            let res = compress_with(flags | TDEFL_NONDETERMINISTIC_PARSING_FLAG);
+            let d = decompress_to_vec(res.as_slice()).expect("Failed to decompress!");
+            assert_eq!(test_data, d);
+            // The compressible part should still compress.
+            assert!(res.len() < 200_000 + 300_000 / 4);
+            assert_ne!(res, compress_with(flags));
+
+            let flags = flags | TDEFL_NONDETERMINISTIC_PARSING_FLAG | TDEFL_NO_INCOMPRESSIBLE_DETECTION;
+            let res = compress_with(flags);
+            let d = decompress_to_vec(res.as_slice()).expect("Failed to decompress!");
+            assert_eq!(test_data, d);
+        }
+    }
+
     /// Test that a raw block compresses fine.
     #[test]
     fn compress_raw() {
@@ -273,6 +630,479 @@ mod test {
         assert_eq!(encoded, res.as_slice());
     }
 
+    #[test]
+    fn cloned_compressor_continues_identically() {
+        use super::core::{compress, CompressorOxide, TDEFLFlush};
+
+        let data = crate::test_util::text(50_000);
+        let (first, second) = data.split_at(20_000);
+        let mut buf = vec![0; data.len() * 2];
+
+        let mut d = CompressorOxide::default();
+        let (_, _, head_len) = compress(&mut d, first, &mut buf, TDEFLFlush::None);
+        let head = buf[..head_len].to_vec();
+
+        let mut fork = d.clone();
+        let (_, _, a_len) = compress(&mut d, second, &mut buf, TDEFLFlush::Finish);
+        let a = buf[..a_len].to_vec();
+        let (_, _, b_len) = compress(&mut fork, second, &mut buf, TDEFLFlush::Finish);
+        assert_eq!(a, &buf[..b_len]);
+        assert_eq!(fork.total_in(), data.len() as u64);
+        assert_eq!(fork.total_out(), (head_len + b_len) as u64);
+
+        let mut stream = head;
+        stream.extend_from_slice(&a);
+        let d = crate::inflate::decompress_to_vec_zlib(&stream).expect("Failed to decompress!");
+        assert_eq!(d, data);
+    }
+
+    #[test]
+    fn pending_bytes_drain() {
+        use super::core::{compress, CompressorOxide, TDEFLFlush, TDEFLStatus};
+
+        let data = crate::test_util::text(20_000);
+        let mut d = CompressorOxide::default();
+        let mut out = Vec::new();
+        let mut buf = [0; 64];
+
+        let (status, consumed, written) = compress(&mut d, &data, &mut buf, TDEFLFlush::Finish);
+        assert!(status == TDEFLStatus::Okay);
+        assert_eq!(consumed, data.len());
+        assert!(d.pending_bytes() > 0);
+        out.extend_from_slice(&buf[..written]);
+
+        loop {
+            let pending = d.pending_bytes();
+            let (status, _, written) = compress(&mut d, &[], &mut buf, TDEFLFlush::Finish);
+            assert_eq!(written, pending.min(buf.len()));
+            out.extend_from_slice(&buf[..written]);
+            if status == TDEFLStatus::Done {
+                break;
+            }
+        }
+        assert_eq!(d.pending_bytes(), 0);
+
+        let d = crate::inflate::decompress_to_vec_zlib(&out).expect("Failed to decompress!");
+        assert_eq!(d, data);
+    }
+
+    #[test]
+    fn match_params() {
+        use super::core::{compress, CompressorOxide, MatchParams, TDEFLFlush};
+
+        let data = crate::test_util::text(100_000);
+        let mut buf = vec![0; data.len() * 2];
+
+        let mut d = CompressorOxide::default();
+        d.set_compression_level_raw(6);
+        let defaults = d.match_params();
+        assert_eq!(defaults.good_length, 32);
+        assert_eq!(defaults.max_lazy, 128);
+        assert_eq!(defaults.nice_length, 258);
+        assert_eq!(defaults.hash_len, 3);
+        let (_, _, default_len) = compress(&mut d, &data, &mut buf, TDEFLFlush::Finish);
+
+        let tuned = MatchParams {
+            good_length: 4,
+            max_lazy: 8,
+            nice_length: 16,
+            max_chain: 4,
+            hash_len: 4,
+        };
+        let mut d = CompressorOxide::default();
+        d.set_compression_level_raw(6);
+        d.set_match_params(tuned);
+        assert_eq!(d.match_params(), tuned);
+        let (_, _, tuned_len) = compress(&mut d, &data, &mut buf, TDEFLFlush::Finish);
+        assert_ne!(tuned_len, default_len);
+        let decomp =
+            crate::inflate::decompress_to_vec_zlib(&buf[..tuned_len]).expect("Failed to decompress!");
+        assert_eq!(decomp, data);
+
+        // Changing the level restores its defaults.
+        d.set_compression_level_raw(6);
+        assert_eq!(d.match_params(), defaults);
+    }
+
+    #[test]
+    fn hash_len() {
+        use super::core::deflate_flags::TDEFL_NONDETERMINISTIC_PARSING_FLAG;
+        use super::core::{
+            compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
+        };
+
+        // Some repeated text, followed by long runs, to get both short and long matches.
+        let mut data = Vec::new();
+        for i in 0..2000u32 {
+            data.extend_from_slice(
+                format!("{} bottles of {} on the wall, ", i % 97, i % 7).as_bytes(),
+            );
+        }
+        data.extend(core::iter::repeat_n(b'x', 10_000));
+        let copy = data[..20_000].to_vec();
+        data.extend_from_slice(&copy);
+        let mut buf = vec![0; data.len() * 2];
+
+        for level in [2, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            assert_eq!(CompressorOxide::new(flags).match_params().hash_len, 3);
+            let d = CompressorOxide::new(flags | TDEFL_NONDETERMINISTIC_PARSING_FLAG);
+            assert_eq!(d.match_params().hash_len, if level <= 3 { 5 } else { 4 });
+
+            for hash_len in 3..=5 {
+                let mut d = CompressorOxide::new(flags);
+                let mut params = d.match_params();
+                params.hash_len = hash_len;
+                d.set_match_params(params);
+                // Compress in pieces, with a sync flush, to also check positions that couldn't
+                // be hashed before the flush are added later.
+                let (_, in1, out1) = compress(&mut d, &data[..50_001], &mut buf, TDEFLFlush::Sync);
+                let (_, in2, out2) =
+                    compress(&mut d, &data[in1..], &mut buf[out1..], TDEFLFlush::Finish);
+                assert_eq!(in1 + in2, data.len());
+                let decomp = decompress_to_vec(&buf[..out1 + out2]).expect("Failed to decompress!");
+                assert_eq!(decomp, data);
+                assert!(out1 + out2 < data.len() / 10);
+            }
+        }
+    }
+
+    #[test]
+    fn preset_dictionary() {
+        use super::core::{
+            compress, create_comp_flags_from_zip_params, CompressorOxide, PreparedDictionary,
+            TDEFLFlush,
+        };
+
+        let mut dictionary = Vec::new();
+        for i in 0..3000u32 {
+            dictionary
+                .extend_from_slice(format!("<item id=\"{}\" kind=\"{}\"/>", i, i % 13).as_bytes());
+        }
+        let data = b"<item id=\"123\" kind=\"5\"/><item id=\"2999\" kind=\"9\"/>".repeat(3);
+        for level in [0, 1, 6, 9, 11] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            let prepared = PreparedDictionary::new(&dictionary, flags);
+            assert_eq!(
+                prepared.as_bytes(),
+                &dictionary[dictionary.len() - 32 * 1024..]
+            );
+            let mut buf = vec![0; 1000];
+
+            // Compressing the dictionary with a sync flush, followed by the data compressed
+            // with the dictionary, gives a valid stream of both.
+            let mut d = CompressorOxide::new(flags);
+            let mut prefix = vec![0; dictionary.len() * 2];
+            let (_, _, prefix_len) = compress(&mut d, &dictionary, &mut prefix, TDEFLFlush::Sync);
+            prefix.truncate(prefix_len);
+
+            let mut d = CompressorOxide::new(flags);
+            d.set_prepared_dictionary(&prepared);
+            let (_, _, len) = compress(&mut d, &data, &mut buf, TDEFLFlush::Finish);
+            let with_dict = buf[..len].to_vec();
+            prefix.extend_from_slice(&with_dict);
+            let decomp = decompress_to_vec(&prefix).expect("Failed to decompress!");
+            assert_eq!(decomp[..dictionary.len()], dictionary[..]);
+            assert_eq!(decomp[dictionary.len()..], data[..]);
+            if level > 0 {
+                assert!(len < compress_to_vec(&data, level as u8).len());
+            }
+
+            // Preparing the dictionary gives the same output as setting it directly, also when
+            // it was prepared for different settings.
+            let other_flags =
+                create_comp_flags_from_zip_params(if level == 1 { 6 } else { 1 }, 0, 0);
+            for prepared in [
+                &prepared,
+                &PreparedDictionary::new(&dictionary, other_flags),
+            ] {
+                let mut d = CompressorOxide::new(flags);
+                d.set_dictionary(&dictionary);
+                let (_, _, len) = compress(&mut d, &data, &mut buf, TDEFLFlush::Finish);
+                assert_eq!(buf[..len], with_dict[..]);
+                let mut d = CompressorOxide::new(flags);
+                d.set_prepared_dictionary(prepared);
+                let (_, _, len) = compress(&mut d, &data, &mut buf, TDEFLFlush::Finish);
+                assert_eq!(buf[..len], with_dict[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_level() {
+        use super::adaptive::estimate_ratio;
+        use super::core::{
+            compress, create_comp_flags_from_zip_params, AdaptiveLevel, CompressorOxide,
+            TDEFLFlush, TDEFLStatus,
+        };
+
+        // Random data followed by text.
+        let mut test_data = crate::test_util::random(100_000, 256);
+        let text_start = test_data.len();
+        test_data.extend_from_slice(&crate::test_util::text(200_000));
+        assert!(estimate_ratio(&test_data[..text_start]) >= 240);
+        assert!(estimate_ratio(&test_data[text_start..]) <= 128);
+        assert_eq!(estimate_ratio(&[]), 256);
+
+        let level_flags =
+            |level| CompressorOxide::new(create_comp_flags_from_zip_params(level, 0, 0)).flags();
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 0, 0));
+        d.set_adaptive_level(Some(AdaptiveLevel {
+            min_level: 0,
+            max_level: 9,
+            sample_size: 16 * 1024,
+            interval: 64 * 1024,
+        }));
+
+        let mut buf = vec![0; test_data.len() * 2];
+        let mut out_pos = 0;
+        for chunk in test_data.chunks(5000) {
+            let (status, bytes_in, bytes_out) =
+                compress(&mut d, chunk, &mut buf[out_pos..], TDEFLFlush::None);
+            assert!(status == TDEFLStatus::Okay && bytes_in == chunk.len());
+            out_pos += bytes_out;
+            if d.total_in() == text_start as u64 {
+                assert_eq!(d.flags(), level_flags(0));
+            }
+        }
+        let (status, _, bytes_out) = compress(&mut d, &[], &mut buf[out_pos..], TDEFLFlush::Finish);
+        assert!(status == TDEFLStatus::Done);
+        out_pos += bytes_out;
+        assert_eq!(d.flags(), level_flags(9));
+
+        let decomp = decompress_to_vec(&buf[..out_pos]).expect("Failed to decompress!");
+        assert_eq!(decomp, test_data);
+    }
+
+    #[test]
+    fn compressed_size() {
+        use super::core::{create_comp_flags_from_zip_params, CompressorOxide};
+        use super::{compress_to_vec_zlib, compressed_size_with, compressed_size_zlib};
+
+        let mut test_data = vec![];
+        for i in 0..200_000u32 {
+            test_data.push((i % 251) as u8 ^ (i / 3000) as u8);
+        }
+        for level in [0, 1, 2, 6, 9, 10, 11] {
+            for data in [&test_data[..], &test_data[..100], &[]] {
+                assert_eq!(
+                    super::compressed_size(data, level),
+                    compress_to_vec(data, level).len()
+                );
+                assert_eq!(
+                    compressed_size_zlib(data, level),
+                    compress_to_vec_zlib(data, level).len()
+                );
+            }
+        }
+
+        let flags = create_comp_flags_from_zip_params(6, 0, CompressionStrategy::RLE as i32);
+        let mut d = CompressorOxide::new(flags);
+        let size = compressed_size_with(&mut d, &test_data);
+        assert_eq!(
+            size,
+            compress_to_vec_inner(&test_data, 6, 0, CompressionStrategy::RLE as i32).len()
+        );
+        assert_eq!(d.total_out(), size as u64);
+    }
+
+    #[test]
+    fn block_boundaries() {
+        use super::core::{compress_to_output, CompressorOxide, TDEFLFlush, TDEFLStatus};
+
+        let test_data = crate::test_util::random(300_000, 16);
+        for level in [0, 1, 6, 10] {
+            let mut d = CompressorOxide::default();
+            d.set_compression_level_raw(level);
+            d.set_record_boundaries(true);
+            let mut output = Vec::new();
+            let mut sync_points = vec![];
+            for (i, chunk) in test_data.chunks(100_000).enumerate() {
+                let flush = if (i + 1) * 100_000 >= test_data.len() {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::Sync
+                };
+                let (status, _) = compress_to_output(&mut d, chunk, flush, |buf| {
+                    output.extend_from_slice(buf);
+                    true
+                });
+                assert!(status != TDEFLStatus::BadParam);
+                if flush == TDEFLFlush::Sync {
+                    sync_points.push((d.total_in(), d.total_out() * 8));
+                }
+            }
+            let decoded = crate::inflate::decompress_to_vec_zlib(&output).unwrap();
+            assert_eq!(decoded, test_data);
+
+            let boundaries = d.boundaries();
+            assert!(boundaries.len() >= sync_points.len());
+            for pair in boundaries.windows(2) {
+                assert!(pair[0].in_offset <= pair[1].in_offset);
+                assert!(pair[0].out_bit_offset < pair[1].out_bit_offset);
+            }
+            let synced: Vec<_> = boundaries
+                .iter()
+                .filter(|b| b.flush == TDEFLFlush::Sync)
+                .map(|b| (b.in_offset, b.out_bit_offset))
+                .collect();
+            assert_eq!(synced, sync_points);
+            assert!(boundaries.last().unwrap().out_bit_offset < output.len() as u64 * 8);
+
+            d.reset();
+            assert!(d.boundaries().is_empty());
+            compress_to_output(&mut d, &test_data, TDEFLFlush::Sync, |_| true);
+            assert!(!d.take_boundaries().is_empty());
+            d.set_record_boundaries(false);
+            compress_to_output(&mut d, &test_data, TDEFLFlush::Sync, |_| true);
+            assert!(d.boundaries().is_empty());
+        }
+    }
+
+    #[test]
+    fn compression_stats() {
+        use super::core::{CompressionStats, CompressorOxide};
+
+        let test_data = crate::test_util::text(300_000);
+        for level in [0, 1, 6, 10] {
+            let mut d = CompressorOxide::default();
+            d.set_compression_level_raw(level);
+            assert_eq!(d.stats(), None);
+            d.set_collect_stats(true);
+            compressed_size_with(&mut d, &test_data);
+            let stats = d.stats().unwrap();
+            assert_eq!(stats.input_bytes, test_data.len() as u64);
+            assert_eq!(
+                stats.bytes_per_block(),
+                test_data.len() as f64 / stats.blocks() as f64
+            );
+            if level == 0 {
+                assert_eq!(stats.blocks(), stats.stored_blocks);
+                assert_eq!(stats.literals + stats.matches, 0);
+                assert_eq!(stats.average_match_length(), 0.0);
+            } else {
+                assert_eq!(stats.stored_blocks, 0);
+                assert_eq!(stats.literals + stats.match_bytes, test_data.len() as u64);
+                assert!((3.0..=258.0).contains(&stats.average_match_length()));
+                assert!((1.0..=32768.0).contains(&stats.average_match_distance()));
+            }
+            if level == 6 {
+                assert!(stats.dynamic_blocks > 0);
+            }
+
+            d.reset();
+            assert_eq!(d.stats(), Some(CompressionStats::default()));
+            d.set_collect_stats(false);
+            assert_eq!(d.stats(), None);
+        }
+    }
+
+    #[test]
+    fn match_finder_stats() {
+        use super::core::CompressorOxide;
+
+        let test_data = crate::test_util::text(200_000);
+        let stats_at = |level: u8, max_chain: Option<u16>| {
+            let mut d = CompressorOxide::default();
+            d.set_compression_level_raw(level);
+            if let Some(max_chain) = max_chain {
+                let mut params = d.match_params();
+                params.max_chain = max_chain;
+                d.set_match_params(params);
+            }
+            d.set_collect_stats(true);
+            compressed_size_with(&mut d, &test_data);
+            d.stats().unwrap()
+        };
+
+        // The fastest level only keeps the latest position of each hash.
+        let fast = stats_at(1, None);
+        assert_eq!(fast.match_searches, 0);
+        assert_eq!(fast.average_chain_length(), 0.0);
+
+        for level in [6, 11] {
+            let stats = stats_at(level, None);
+            assert!(stats.match_searches > 0);
+            assert!(stats.chain_probes > 0);
+            assert!(stats.hash_collisions <= stats.chain_probes);
+            assert_eq!(stats.lazy_rejected > 0, level == 6);
+        }
+
+        let short = stats_at(6, Some(4));
+        let long = stats_at(6, Some(1000));
+        assert!(long.average_chain_length() > short.average_chain_length());
+    }
+
+    #[cfg(feature = "block-timing")]
+    #[test]
+    fn block_timing() {
+        use super::core::CompressorOxide;
+
+        let test_data = crate::test_util::text(300_000);
+        let mut d = CompressorOxide::default();
+        d.set_collect_stats(true);
+        compressed_size_with(&mut d, &test_data);
+        let stats = d.stats().unwrap();
+        assert_eq!(stats.time.input_bytes, test_data.len() as u64);
+        assert!(stats.time.match_finding > stats.time.tree_building);
+        assert!(stats.time.symbol_coding > core::time::Duration::ZERO);
+        let slowest = stats.slowest_block;
+        assert!(slowest.input_bytes > 0 && slowest.total() <= stats.time.total());
+        assert!(slowest.in_offset + slowest.input_bytes <= test_data.len() as u64);
+
+        d.reset();
+        assert_eq!(d.stats().unwrap().time.total(), core::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn put_buf_pause_and_abort() {
+        use super::core::{
+            compress_to_output, CompressorOxide, PutBufResult, TDEFLFlush, TDEFLStatus,
+        };
+
+        let test_data = crate::test_util::random(200_000, 16);
+        let mut d = CompressorOxide::default();
+        // A ring with room for 1000 bytes, drained between calls.
+        let mut ring = Vec::new();
+        let mut output = Vec::new();
+        let mut input = &test_data[..];
+        let mut pauses = 0;
+        loop {
+            let (status, bytes_in) = compress_to_output(&mut d, input, TDEFLFlush::Finish, |buf| {
+                let n = buf.len().min(1000 - ring.len());
+                ring.extend_from_slice(&buf[..n]);
+                if n < buf.len() {
+                    pauses += 1;
+                    PutBufResult::Pause(n)
+                } else {
+                    PutBufResult::Accepted
+                }
+            });
+            input = &input[bytes_in..];
+            output.append(&mut ring);
+            match status {
+                TDEFLStatus::Done => break,
+                TDEFLStatus::Okay => {}
+                status => panic!("{status:?}"),
+            }
+        }
+        assert!(pauses > 10);
+        assert_eq!(d.pending_bytes(), 0);
+        assert_eq!(d.total_out(), output.len() as u64);
+        assert_eq!(
+            crate::inflate::decompress_to_vec_zlib(&output).unwrap(),
+            test_data
+        );
+
+        d.reset();
+        let (status, _) = compress_to_output(&mut d, &test_data, TDEFLFlush::Finish, |_| {
+            PutBufResult::Abort
+        });
+        assert_eq!(status, TDEFLStatus::PutBufFailed);
+        let (status, _) = compress_to_output(&mut d, &test_data, TDEFLFlush::Finish, |_| true);
+        assert_eq!(status, TDEFLStatus::BadParam);
+    }
+
     #[test]
     fn short() {
         let test_data = [10, 10, 10, 10, 10, 55];
@@ -286,4 +1116,183 @@ mod test {
         // as neither checks matches against the byte at index 0.)
         assert!(c.len() <= 6);
     }
+
+    #[test]
+    fn slice_iter() {
+        let data = crate::test_util::text(200_000);
+        let expected = compress_to_vec(&data, 6);
+        for chunk_size in [1, 7, 1000, 65536, data.len()] {
+            let output = compress_slice_iter_to_vec(data.chunks(chunk_size), 6);
+            assert_eq!(output, expected);
+        }
+        // Empty slices anywhere are skipped over.
+        let pieces = [&[][..], &data[..100], &[], &data[100..], &[]];
+        assert_eq!(compress_slice_iter_to_vec(pieces, 6), expected);
+        assert_eq!(
+            compress_slice_iter_to_vec_zlib(data.chunks(4096), 9),
+            compress_to_vec_zlib(&data, 9)
+        );
+
+        let empty = compress_slice_iter_to_vec(core::iter::empty(), 6);
+        assert_eq!(empty, compress_to_vec(&[], 6));
+        assert!(decompress_to_vec(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn into_vec() {
+        let data = crate::test_util::text(50_000);
+        let mut output = Vec::new();
+        compress_into_vec(&data, 6, &mut output);
+        assert_eq!(output, compress_to_vec(&data, 6));
+
+        // Smaller messages reuse the allocation, and only get their own output.
+        let capacity = output.capacity();
+        let pointer = output.as_ptr();
+        compress_into_vec_zlib(&data[..1000], 9, &mut output);
+        assert_eq!(output, compress_to_vec_zlib(&data[..1000], 9));
+        assert_eq!((output.capacity(), output.as_ptr()), (capacity, pointer));
+        compress_into_vec(&[], 6, &mut output);
+        assert_eq!(output, compress_to_vec(&[], 6));
+    }
+
+    #[test]
+    fn builder() {
+        use super::core::{compress_to_output, CompressorOxide, TDEFLFlush};
+
+        let data = crate::test_util::text(100_000);
+        let compress = |compressor: &mut CompressorOxide, input: &[u8]| {
+            let mut output = Vec::new();
+            compress_to_output(compressor, input, TDEFLFlush::Finish, |buf| {
+                output.extend_from_slice(buf);
+                true
+            });
+            output
+        };
+
+        let mut compressor = CompressorOxide::builder().build_boxed();
+        assert_eq!(compress(&mut compressor, &data), compress_to_vec_zlib(&data, 6));
+        let mut compressor = CompressorOxide::builder()
+            .window_bits(-15)
+            .level(9)
+            .strategy(CompressionStrategy::Filtered)
+            .build();
+        assert_eq!(
+            compress(&mut compressor, &data),
+            compress_to_vec_inner(&data, 9, -15, CompressionStrategy::Filtered as i32)
+        );
+
+        let dictionary = &data[..40_000];
+        let mut compressor = CompressorOxide::builder()
+            .format(DataFormat::Raw)
+            .dictionary(dictionary)
+            .build_boxed();
+        let options = CompressOptions {
+            dictionary: Some(dictionary),
+            ..CompressOptions::default()
+        };
+        assert_eq!(
+            compress(&mut compressor, &data[40_000..]),
+            compress_to_vec_with(&data[40_000..], options)
+        );
+
+        let mut compressor = CompressorOxide::builder()
+            .format(DataFormat::Raw)
+            .full_flush_interval(10_000)
+            .build_boxed();
+        assert_eq!(compressor.full_flush_interval(), 10_000);
+        assert_eq!(decompress_to_vec(&compress(&mut compressor, &data)).unwrap(), data);
+    }
+
+    #[test]
+    fn caller_provided_buffers() {
+        use super::core::{compress, CompressorBuffers, CompressorOxide, TDEFLFlush, TDEFLStatus};
+        use alloc::boxed::Box;
+
+        let data = crate::test_util::text(100_000);
+        // Small pieces, so the state is carried over between calls.
+        let run = |compressor: &mut CompressorOxide, level: u8| {
+            compressor.set_compression_level_raw(level);
+            compressor.reset();
+            let mut output = vec![0; data.len()];
+            let mut out_pos = 0;
+            for (i, chunk) in data.chunks(7_000).enumerate() {
+                let flush = if (i + 1) * 7_000 >= data.len() {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::None
+                };
+                let (status, bytes_in, bytes_out) =
+                    compress(compressor, chunk, &mut output[out_pos..], flush);
+                assert_ne!(status, TDEFLStatus::BadParam);
+                assert_eq!(bytes_in, chunk.len());
+                out_pos += bytes_out;
+            }
+            output.truncate(out_pos);
+            output
+        };
+
+        let builder = CompressorOxide::builder().format(DataFormat::Raw);
+        let buffers = Box::leak(Box::<CompressorBuffers>::default());
+        let mut compressor = builder.build_with_buffers(buffers);
+        let mut boxed = builder.build_boxed();
+        for level in [1, 6, 11] {
+            let output = run(&mut compressor, level);
+            assert_eq!(output, run(&mut boxed, level));
+            assert_eq!(decompress_to_vec(&output).unwrap(), data);
+        }
+
+        // A clone keeps working on its own copy of the buffers.
+        let mut clone = compressor.clone();
+        assert_eq!(run(&mut clone, 6), run(&mut compressor, 6));
+    }
+
+    #[test]
+    fn options() {
+        let data = crate::test_util::text(300_000);
+        assert_eq!(
+            compress_to_vec_with(&data, CompressOptions::default()),
+            compress_to_vec(&data, 6)
+        );
+
+        let mut progress = 0;
+        let mut callback = |len| progress += len;
+        let options = CompressOptions {
+            format: DataFormat::Zlib,
+            level: 9,
+            callback: Some(&mut callback),
+            ..CompressOptions::default()
+        };
+        assert_eq!(
+            compress_to_vec_with(&data, options),
+            compress_to_vec_zlib(&data, 9)
+        );
+        assert_eq!(progress, data.len());
+
+        // Data compressed with a dictionary needs it to be decompressed.
+        let dictionary = crate::test_util::random(20_000, 256);
+        let dictionary = &dictionary[..];
+        let message = [&dictionary[5000..5300], &dictionary[12_000..12_300]].concat();
+        let message = &message[..];
+        let compressed = compress_to_vec_with(
+            message,
+            CompressOptions {
+                dictionary: Some(dictionary),
+                ..CompressOptions::default()
+            },
+        );
+        assert!(compressed.len() < compress_to_vec(message, 6).len() / 4);
+        let mut progress = 0;
+        let mut callback = |len| progress += len;
+        let options = DecompressOptions {
+            dictionary: Some(dictionary),
+            callback: Some(&mut callback),
+            ..DecompressOptions::default()
+        };
+        assert_eq!(
+            decompress_to_vec_with(&compressed, options).unwrap(),
+            message
+        );
+        assert_eq!(progress, compressed.len());
+        assert!(decompress_to_vec(&compressed).is_err());
+    }
 }
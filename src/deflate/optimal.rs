@@ -0,0 +1,411 @@
+//! Optimal parsing for the highest compression level.
+//!
+//! Rather than deciding between a literal and a match one byte at a time, the longest match
+//! is found for every position in a chunk of input, and the sequence of literals and matches
+//! with the lowest cost in bits is then picked using dynamic programming. The costs start out
+//! as the lengths of the fixed huffman codes, and are refined a few times from the symbol
+//! frequencies of the previous parse of the chunk, similar to what zopfli does.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp;
+
+use crate::deflate::buffer::{update_hash, LZ_CODE_BUF_SIZE, LZ_HASH_SHIFT, LZ_HASH_SIZE};
+use crate::deflate::core::{
+    flush_block, record_literal, record_match, CallbackOxide, CompressorOxide, HuffmanOxide,
+    TDEFLFlush, TDEFLStatus, DIST_TABLE, INCOMPRESSIBLE_MIN_BLOCK_BYTES, LARGE_DIST_EXTRA,
+    LARGE_DIST_SYM, LEN_EXTRA, LEN_SYM, LEN_SYM_OFFSET, LITLEN_TABLE, LZ_DICT_SIZE,
+    LZ_DICT_SIZE_MASK, MAX_HUFF_SYMBOLS_0, MAX_HUFF_SYMBOLS_1, MAX_MATCH_LEN, MIN_MATCH_LEN,
+    SMALL_DIST_EXTRA, SMALL_DIST_SYM,
+};
+
+/// Number of bytes of input that are parsed together.
+///
+/// The window available for matches is reduced by this much, so it is kept fairly small.
+const OPTIMAL_LOOKAHEAD: usize = 4096;
+/// Number of times each chunk is parsed, each time with costs from the previous parse.
+const OPTIMAL_ITERATIONS: usize = 3;
+/// Matches at least this long are only considered at their full length, as trying every
+/// shorter length makes long runs of repeated data very slow to parse.
+const OPTIMAL_LONG_MATCH: usize = 128;
+/// Maximum number of matches kept for each position.
+const MAX_CANDIDATES: usize = 8;
+
+/// Estimated cost in bits of each literal/length and distance symbol, not counting extra bits.
+struct CostModel {
+    lit_len: [u32; MAX_HUFF_SYMBOLS_0],
+    dist: [u32; MAX_HUFF_SYMBOLS_1],
+}
+
+impl CostModel {
+    /// Costs using the code lengths of the fixed huffman codes.
+    fn fixed() -> Self {
+        let mut lit_len = [8; MAX_HUFF_SYMBOLS_0];
+        lit_len[144..256].fill(9);
+        lit_len[256..280].fill(7);
+        CostModel {
+            lit_len,
+            dist: [5; MAX_HUFF_SYMBOLS_1],
+        }
+    }
+
+    /// Costs using the code lengths of huffman codes built from the given symbol counts.
+    ///
+    /// One is added to every count so that symbols that were not used in the previous parse
+    /// still get a finite cost.
+    fn from_counts(
+        scratch: &mut HuffmanOxide,
+        lit_len_counts: &[u16; MAX_HUFF_SYMBOLS_0],
+        dist_counts: &[u16; MAX_HUFF_SYMBOLS_1],
+    ) -> Self {
+        scratch.count[LITLEN_TABLE].fill(0);
+        scratch.count[DIST_TABLE].fill(0);
+        for (count, &used) in scratch.count[LITLEN_TABLE]
+            .iter_mut()
+            .zip(&lit_len_counts[..286])
+        {
+            *count = used.saturating_add(1);
+        }
+        for (count, &used) in scratch.count[DIST_TABLE].iter_mut().zip(&dist_counts[..30]) {
+            *count = used.saturating_add(1);
+        }
+        scratch.optimize_table(LITLEN_TABLE, MAX_HUFF_SYMBOLS_0, 15, false);
+        scratch.optimize_table(DIST_TABLE, MAX_HUFF_SYMBOLS_1, 15, false);
+
+        let mut model = CostModel {
+            lit_len: [0; MAX_HUFF_SYMBOLS_0],
+            dist: [0; MAX_HUFF_SYMBOLS_1],
+        };
+        for (cost, &size) in model
+            .lit_len
+            .iter_mut()
+            .zip(&scratch.code_sizes[LITLEN_TABLE])
+        {
+            *cost = u32::from(size);
+        }
+        for (cost, &size) in model.dist.iter_mut().zip(&scratch.code_sizes[DIST_TABLE]) {
+            *cost = u32::from(size);
+        }
+        model
+    }
+
+    /// Cost of a match of length `len`, not counting the distance.
+    fn len_cost(&self, len: usize) -> u32 {
+        let (sym, extra) = len_symbol(len);
+        self.lit_len[sym] + extra
+    }
+
+    /// Cost of a match distance of `dist`.
+    fn dist_cost(&self, dist: u32) -> u32 {
+        let (sym, extra) = dist_symbol(dist);
+        self.dist[sym] + extra
+    }
+}
+
+/// Get the literal/length symbol and number of extra bits for a match length.
+fn len_symbol(len: usize) -> (usize, u32) {
+    let index = len - usize::from(MIN_MATCH_LEN);
+    (
+        (LEN_SYM[index] as usize & 31) + LEN_SYM_OFFSET,
+        u32::from(LEN_EXTRA[index]),
+    )
+}
+
+/// Get the distance symbol and number of extra bits for a match distance.
+fn dist_symbol(dist: u32) -> (usize, u32) {
+    let dist = (dist - 1) as usize;
+    if dist < 512 {
+        (
+            usize::from(SMALL_DIST_SYM[dist]),
+            u32::from(SMALL_DIST_EXTRA[dist]),
+        )
+    } else {
+        (
+            usize::from(LARGE_DIST_SYM[(dist >> 8) & 127]),
+            u32::from(LARGE_DIST_EXTRA[(dist >> 8) & 127]),
+        )
+    }
+}
+
+/// Buffers used while parsing a chunk of up to [`OPTIMAL_LOOKAHEAD`] bytes.
+struct Parser {
+    /// Length and distance of the matches found at each position, [`MAX_CANDIDATES`] entries
+    /// per position. Each match is longer and further away than the one before it.
+    matches: Vec<(u16, u16)>,
+    /// Number of matches found at each position.
+    num_matches: Vec<u8>,
+    /// Lowest cost found so far to reach each position.
+    cost: Vec<u32>,
+    /// The literal (length 1) or match used to reach each position with the lowest cost.
+    steps: Vec<(u16, u16)>,
+    /// The chosen literals and matches, in order.
+    path: Vec<(u16, u16)>,
+    scratch: Box<HuffmanOxide>,
+}
+
+impl Parser {
+    fn new() -> Self {
+        let len = OPTIMAL_LOOKAHEAD + 1;
+        Parser {
+            matches: vec![(0, 0); len * MAX_CANDIDATES],
+            num_matches: vec![0; len],
+            cost: vec![0; len],
+            steps: vec![(0, 0); len],
+            path: Vec::with_capacity(len),
+            scratch: Box::default(),
+        }
+    }
+
+    /// Find the matches for each of the `len` positions at the start of the lookahead.
+    fn find_matches(&mut self, d: &mut CompressorOxide, len: usize) {
+        let dict = &d.dict;
+        let lookahead_pos = dict.lookahead_pos;
+        for (i, matches) in self
+            .matches
+            .chunks_exact_mut(MAX_CANDIDATES)
+            .take(len)
+            .enumerate()
+        {
+            let pos = lookahead_pos + i;
+            let max_dist = cmp::min(dict.size + i, LZ_DICT_SIZE);
+            self.num_matches[i] = match &mut d.params.stats {
+                Some(stats) => dict.find_all_matches(pos, max_dist, len - i, matches, stats),
+                None => dict.find_all_matches(pos, max_dist, len - i, matches, &mut ()),
+            } as u8;
+        }
+    }
+
+    /// Find the cheapest way to encode the `len` bytes at the start of the lookahead with
+    /// the given costs, and store it in `path`.
+    fn parse(&mut self, d: &CompressorOxide, len: usize, model: &CostModel) {
+        let mut len_costs = [0; MAX_MATCH_LEN + 1];
+        for (l, cost) in len_costs.iter_mut().enumerate().skip(MIN_MATCH_LEN.into()) {
+            *cost = model.len_cost(l);
+        }
+
+        self.cost[0] = 0;
+        self.cost[1..=len].fill(u32::MAX);
+        for i in 0..len {
+            let base = self.cost[i];
+
+            let lit = d.dict.b.dict[(d.dict.lookahead_pos + i) & LZ_DICT_SIZE_MASK];
+            let lit_cost = base + model.lit_len[usize::from(lit)];
+            if lit_cost < self.cost[i + 1] {
+                self.cost[i + 1] = lit_cost;
+                self.steps[i + 1] = (1, 0);
+            }
+
+            // Each match is used for the lengths that a closer match can't reach.
+            let mut shortest = usize::from(MIN_MATCH_LEN);
+            let num_matches = usize::from(self.num_matches[i]);
+            for &(match_len, dist) in &self.matches[i * MAX_CANDIDATES..][..num_matches] {
+                let match_len = usize::from(match_len);
+                let base = base + model.dist_cost(u32::from(dist));
+                if match_len >= OPTIMAL_LONG_MATCH {
+                    shortest = match_len;
+                }
+                for (l, &len_cost) in len_costs
+                    .iter()
+                    .enumerate()
+                    .take(match_len + 1)
+                    .skip(shortest)
+                {
+                    let cost = base + len_cost;
+                    if cost < self.cost[i + l] {
+                        self.cost[i + l] = cost;
+                        self.steps[i + l] = (l as u16, dist);
+                    }
+                }
+                shortest = match_len + 1;
+            }
+        }
+
+        self.path.clear();
+        let mut pos = len;
+        while pos > 0 {
+            let step = self.steps[pos];
+            self.path.push(step);
+            pos -= usize::from(step.0);
+        }
+        self.path.reverse();
+    }
+
+    /// Build a cost model from the symbol frequencies of the current path.
+    fn model_from_path(&mut self, d: &CompressorOxide) -> CostModel {
+        let mut lit_len_counts = [0u16; MAX_HUFF_SYMBOLS_0];
+        let mut dist_counts = [0u16; MAX_HUFF_SYMBOLS_1];
+        // There is always an end of block symbol, as this is what the costs are used for.
+        lit_len_counts[256] = 1;
+        let mut pos = d.dict.lookahead_pos;
+        for &(len, dist) in &self.path {
+            let sym = if len == 1 {
+                usize::from(d.dict.b.dict[pos & LZ_DICT_SIZE_MASK])
+            } else {
+                let (sym, _) = dist_symbol(dist.into());
+                dist_counts[sym] = dist_counts[sym].saturating_add(1);
+                len_symbol(len.into()).0
+            };
+            lit_len_counts[sym] = lit_len_counts[sym].saturating_add(1);
+            pos += usize::from(len);
+        }
+        CostModel::from_counts(&mut self.scratch, &lit_len_counts, &dist_counts)
+    }
+}
+
+pub(crate) fn compress_optimal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
+    let in_buf = match callback.buf() {
+        None => return true,
+        Some(in_buf) => in_buf,
+    };
+
+    // Make sure this is cleared in case compression level is switched later.
+    d.params.saved_match_len = 0;
+    let mut src_pos = d.params.src_pos;
+    let mut lookahead_size = d.dict.lookahead_size;
+    let mut lookahead_pos = d.dict.lookahead_pos;
+    let mut parser = Parser::new();
+    // The costs from the previous chunk are a better starting point than the fixed codes.
+    let mut model = CostModel::fixed();
+
+    while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size != 0) {
+        let src_buf_left = in_buf.len() - src_pos;
+        let num_bytes_to_process = cmp::min(src_buf_left, OPTIMAL_LOOKAHEAD - lookahead_size);
+
+        if lookahead_size + d.dict.size >= usize::from(MIN_MATCH_LEN) - 1
+            && num_bytes_to_process > 0
+        {
+            let dictb = &mut d.dict.b;
+
+            let mut dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+            let first_ins_pos = lookahead_pos + lookahead_size - 2;
+            // Start the hash value from the first two bytes
+            let mut hash = update_hash(
+                u16::from(dictb.dict[first_ins_pos & LZ_DICT_SIZE_MASK]),
+                dictb.dict[(first_ins_pos + 1) & LZ_DICT_SIZE_MASK],
+            );
+
+            lookahead_size += num_bytes_to_process;
+
+            for (ins_pos, &c) in
+                (first_ins_pos..).zip(&in_buf[src_pos..src_pos + num_bytes_to_process])
+            {
+                // Add byte to input buffer.
+                dictb.dict[dst_pos] = c;
+                if dst_pos < MAX_MATCH_LEN - 1 {
+                    dictb.dict[LZ_DICT_SIZE + dst_pos] = c;
+                }
+
+                // Generate hash from the current byte,
+                hash = update_hash(hash, c);
+                dictb.next[ins_pos & LZ_DICT_SIZE_MASK] = dictb.hash[hash as usize];
+                // and insert it into the hash chain.
+                dictb.hash[hash as usize] = ins_pos as u16;
+                dst_pos = (dst_pos + 1) & LZ_DICT_SIZE_MASK;
+            }
+            src_pos += num_bytes_to_process;
+        } else {
+            let dictb = &mut d.dict.b;
+            for &c in &in_buf[src_pos..src_pos + num_bytes_to_process] {
+                let dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+                dictb.dict[dst_pos] = c;
+                if dst_pos < MAX_MATCH_LEN - 1 {
+                    dictb.dict[LZ_DICT_SIZE + dst_pos] = c;
+                }
+
+                lookahead_size += 1;
+                if lookahead_size + d.dict.size >= MIN_MATCH_LEN.into() {
+                    let ins_pos = lookahead_pos + lookahead_size - 3;
+                    let hash = ((u32::from(dictb.dict[ins_pos & LZ_DICT_SIZE_MASK])
+                        << (LZ_HASH_SHIFT * 2))
+                        ^ ((u32::from(dictb.dict[(ins_pos + 1) & LZ_DICT_SIZE_MASK])
+                            << LZ_HASH_SHIFT)
+                            ^ u32::from(c)))
+                        & (LZ_HASH_SIZE as u32 - 1);
+
+                    dictb.next[ins_pos & LZ_DICT_SIZE_MASK] = dictb.hash[hash as usize];
+                    dictb.hash[hash as usize] = ins_pos as u16;
+                }
+            }
+
+            src_pos += num_bytes_to_process;
+        }
+
+        d.dict.size = cmp::min(LZ_DICT_SIZE - lookahead_size, d.dict.size);
+        if d.params.flush == TDEFLFlush::None && lookahead_size < OPTIMAL_LOOKAHEAD {
+            break;
+        }
+
+        // Parse the whole lookahead, but unless we are flushing, leave the end of it to be
+        // parsed again with the following input, as the best choice there may depend on it.
+        d.dict.lookahead_pos = lookahead_pos;
+        if d.params.skip_matches() {
+            // The data is output as raw blocks, so only literals are needed.
+            parser.num_matches[..lookahead_size].fill(0);
+            parser.parse(d, lookahead_size, &model);
+        } else {
+            parser.find_matches(d, lookahead_size);
+            for _ in 0..OPTIMAL_ITERATIONS {
+                parser.parse(d, lookahead_size, &model);
+                model = parser.model_from_path(d);
+            }
+        }
+        let parse_end = if d.params.flush == TDEFLFlush::None {
+            lookahead_size - MAX_MATCH_LEN
+        } else {
+            lookahead_size
+        };
+
+        let mut parsed = 0;
+        for &(len, dist) in &parser.path {
+            if parsed >= parse_end {
+                break;
+            }
+            let len = usize::from(len);
+            if len == 1 {
+                let lit = d.dict.b.dict[lookahead_pos & LZ_DICT_SIZE_MASK];
+                record_literal(&mut d.huff, &mut d.lz, lit);
+            } else {
+                record_match(&mut d.huff, &mut d.lz, len as u32, u32::from(dist));
+            }
+            parsed += len;
+
+            lookahead_pos += len;
+            lookahead_size -= len;
+            d.dict.size = cmp::min(d.dict.size + len, LZ_DICT_SIZE);
+
+            let lz_buf_tight = d.lz.code_position > LZ_CODE_BUF_SIZE - 8;
+            let fat = ((d.lz.code_position * 115) >> 7) >= d.lz.total_bytes as usize;
+            let buf_fat = (d.lz.total_bytes > 31 * 1024) && fat;
+            let raw_full = d.params.incompressible_blocks > 0
+                && d.lz.total_bytes >= INCOMPRESSIBLE_MIN_BLOCK_BYTES;
+
+            if lz_buf_tight || buf_fat || raw_full {
+                let skipped_matches = d.params.skip_matches();
+                d.params.src_pos = src_pos;
+                // These values are used in flush_block, so we need to write them back here.
+                d.dict.lookahead_size = lookahead_size;
+                d.dict.lookahead_pos = lookahead_pos;
+
+                let n = flush_block(d, callback, TDEFLFlush::None)
+                    .unwrap_or(TDEFLStatus::PutBufFailed as i32);
+                if n != 0 {
+                    return n > 0;
+                }
+
+                lookahead_size = d.dict.lookahead_size;
+                lookahead_pos = d.dict.lookahead_pos;
+
+                // The rest of the path was parsed for the other kind of block, so parse again.
+                if skipped_matches != d.params.skip_matches() {
+                    break;
+                }
+            }
+        }
+    }
+
+    d.params.src_pos = src_pos;
+    d.dict.lookahead_size = lookahead_size;
+    d.dict.lookahead_pos = lookahead_pos;
+    true
+}
@@ -0,0 +1,158 @@
+//! Compression of large inputs using several threads, like pigz.
+//!
+//! The input is split into chunks that are compressed on the rayon thread pool. Each chunk is
+//! compressed with the 32 KiB of input before it loaded as a dictionary, so matches can still
+//! refer across chunk boundaries, and ends with a sync flush so the chunks can be joined into
+//! a single deflate stream that any decompressor can read.
+
+use alloc::vec::Vec;
+
+use rayon::prelude::*;
+
+use super::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
+    TDEFLStatus, LZ_DICT_SIZE,
+};
+use super::zlib;
+use crate::shared::{combine_adler32, update_adler32, MZ_ADLER32_INIT};
+
+/// Compress the input data to a vector with several threads, using the specified compression
+/// level (0-10), in chunks of `chunk_size` bytes.
+///
+/// The output is a single raw deflate stream, like that of
+/// [`compress_to_vec`](super::compress_to_vec), though slightly larger than it, as each chunk
+/// ends with an empty raw block and starts with new huffman tables. Chunks of a few hundred
+/// KiB or more keep this small. A `chunk_size` of 0 is treated as 1.
+pub fn compress_to_vec_parallel(input: &[u8], level: u8, chunk_size: usize) -> Vec<u8> {
+    compress_chunks(input, level, chunk_size, false)
+}
+
+/// Compress the input data to a vector with several threads, using the specified compression
+/// level (0-10), in chunks of `chunk_size` bytes, and with a zlib wrapper.
+///
+/// See [`compress_to_vec_parallel`] for details.
+pub fn compress_to_vec_zlib_parallel(input: &[u8], level: u8, chunk_size: usize) -> Vec<u8> {
+    compress_chunks(input, level, chunk_size, true)
+}
+
+/// Compress `input` in chunks on the thread pool, and join the raw deflate streams, adding a
+/// zlib header and checksum if `zlib` is set.
+fn compress_chunks(input: &[u8], level: u8, chunk_size: usize, zlib: bool) -> Vec<u8> {
+    let flags = create_comp_flags_from_zip_params(level.into(), 0, 0);
+    let chunk_size = chunk_size.max(1);
+    let num_chunks = input.len().div_ceil(chunk_size).max(1);
+
+    let chunks: Vec<(Vec<u8>, u32)> = (0..num_chunks)
+        .into_par_iter()
+        .map(|i| {
+            let start = i * chunk_size;
+            let end = input.len().min(start + chunk_size);
+            let chunk = &input[start..end];
+            let dictionary = &input[start.saturating_sub(LZ_DICT_SIZE)..start];
+            let flush = if i + 1 == num_chunks {
+                TDEFLFlush::Finish
+            } else {
+                TDEFLFlush::Sync
+            };
+            let adler = if zlib {
+                update_adler32(MZ_ADLER32_INIT, chunk)
+            } else {
+                MZ_ADLER32_INIT
+            };
+            (compress_chunk(flags, dictionary, chunk, flush), adler)
+        })
+        .collect();
+
+    let mut output =
+        Vec::with_capacity(chunks.iter().map(|(data, _)| data.len()).sum::<usize>() + 6);
+    if zlib {
+        output.extend_from_slice(&zlib::header_from_flags(flags));
+    }
+    let mut adler = MZ_ADLER32_INIT;
+    for (i, (data, chunk_adler)) in chunks.iter().enumerate() {
+        output.extend_from_slice(data);
+        let len = input.len().min((i + 1) * chunk_size) - i * chunk_size;
+        adler = combine_adler32(adler, *chunk_adler, len);
+    }
+    if zlib {
+        output.extend_from_slice(&adler.to_be_bytes());
+    }
+    output
+}
+
+/// Compress one chunk, following `dictionary`. Ends the deflate stream if `flush` is
+/// [`TDEFLFlush::Finish`], and otherwise ends it at a byte boundary with a sync flush.
+fn compress_chunk(flags: u32, dictionary: &[u8], chunk: &[u8], flush: TDEFLFlush) -> Vec<u8> {
+    let mut compressor = CompressorOxide::new(flags);
+    compressor.set_dictionary(dictionary);
+
+    let mut output = Vec::with_capacity(chunk.len() / 2);
+    let (status, bytes_in) = compress_to_output(&mut compressor, chunk, flush, |buf| {
+        output.extend_from_slice(buf);
+        true
+    });
+    // Not supposed to happen unless there is a bug.
+    let done = if flush == TDEFLFlush::Finish {
+        TDEFLStatus::Done
+    } else {
+        TDEFLStatus::Okay
+    };
+    assert!(
+        status == done && bytes_in == chunk.len(),
+        "Bug! Unexpectedly failed to compress!"
+    );
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_to_vec_parallel, compress_to_vec_zlib_parallel};
+    use crate::deflate::compress_to_vec;
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+    use crate::shared::{combine_adler32, update_adler32, MZ_ADLER32_INIT};
+
+    #[test]
+    fn parallel_roundtrip() {
+        let data = crate::test_util::text(300_000);
+
+        for level in [0, 1, 6, 9] {
+            for chunk_size in [0, 1000, 64 * 1024, 1 << 20] {
+                let input = if chunk_size < 100 {
+                    &data[..1000]
+                } else {
+                    &data[..]
+                };
+                let res = compress_to_vec_parallel(input, level, chunk_size);
+                let d = decompress_to_vec(&res).expect("Failed to decompress!");
+                assert_eq!(d, input);
+
+                let res = compress_to_vec_zlib_parallel(input, level, chunk_size);
+                let d = decompress_to_vec_zlib(&res).expect("Failed to decompress!");
+                assert_eq!(d, input);
+            }
+        }
+
+        // The dictionary keeps the size close to that of compressing in one piece.
+        let single = compress_to_vec(&data, 6).len();
+        let parallel = compress_to_vec_parallel(&data, 6, 64 * 1024).len();
+        assert!(parallel < single + single / 20);
+
+        let res = compress_to_vec_zlib_parallel(&[], 6, 1000);
+        assert!(decompress_to_vec_zlib(&res).unwrap().is_empty());
+    }
+
+    #[test]
+    fn combine_adler() {
+        let data = crate::test_util::text(100_000);
+        let full = update_adler32(MZ_ADLER32_INIT, &data);
+        for split in [0, 1, 5552, 65521, 70_000, data.len()] {
+            let (a, b) = data.split_at(split);
+            let combined = combine_adler32(
+                update_adler32(MZ_ADLER32_INIT, a),
+                update_adler32(MZ_ADLER32_INIT, b),
+                b.len(),
+            );
+            assert_eq!(combined, full);
+        }
+    }
+}
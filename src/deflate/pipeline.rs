@@ -0,0 +1,296 @@
+//! Compression with reading, compressing and writing decoupled by bounded channels.
+//!
+//! [`copy_encode`](super::stream::copy_encode) reads a chunk, compresses it and writes the
+//! output before it reads the next one, so a slow writer holds up reading and the other way
+//! around. [`compress_pipelined`] runs the three stages concurrently instead, with up to
+//! `capacity` chunks queued between them. When the writer falls behind, the queues fill up and
+//! reading pauses until it catches up, so memory use stays bounded.
+
+use alloc::vec::Vec;
+use core::future::poll_fn;
+use core::pin::pin;
+use core::task::Poll;
+
+use async_channel::{Receiver, Sender, bounded};
+use binrw::io::read::Read;
+use binrw::io::write::Write;
+
+use super::CompressError;
+use super::core::CompressorOxide;
+use super::stream::{EncodeOptions, deflate};
+use crate::{DataFormat, MZFlush, MZStatus, StreamSummary};
+
+/// Compress everything read from `input` to `writer` with the settings of `options`, reading,
+/// compressing and writing concurrently.
+///
+/// At most `capacity` chunks of `options.chunk_size` bytes are queued between the reader and
+/// the compressor, and as many between the compressor and the writer. A `capacity` of 0 is
+/// treated as 1. The buffers are handed back to the reader and the compressor once used, so
+/// they are only allocated until there are enough of them in flight.
+///
+/// The stages run inside the returned future, so its executor only needs to poll one task.
+/// The output is the same as from [`copy_encode_with`](super::stream::copy_encode_with). On
+/// an error the other stages stop as well.
+pub fn compress_pipelined<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: EncodeOptions,
+    capacity: usize,
+) -> impl Future<Output = Result<StreamSummary, CompressError>> + Send + 'a {
+    async move {
+        let capacity = capacity.max(1);
+        let chunk_size = options.chunk_size.max(1);
+        let (input_tx, input_rx) = bounded(capacity);
+        let (output_tx, output_rx) = bounded(capacity);
+        // Room for every buffer that can be in flight, so handing one back never waits.
+        let (free_input_tx, free_input_rx) = bounded(capacity + 2);
+        let (free_output_tx, free_output_rx) = bounded(capacity + 2);
+
+        let read = read_chunks(input, chunk_size, input_tx, free_input_rx);
+        let compress = compress_chunks(
+            options,
+            chunk_size,
+            input_rx,
+            free_input_tx,
+            output_tx,
+            free_output_rx,
+        );
+        let write = write_chunks(writer, output_rx, free_output_tx);
+        let ((bytes_read, chunks), checksum, bytes_written) =
+            try_join3(read, compress, write).await?;
+        Ok(StreamSummary {
+            bytes_read,
+            bytes_written,
+            checksum,
+            chunks,
+        })
+    }
+}
+
+/// Read `input` in chunks of up to `chunk_size` bytes, and send them to the compressor.
+///
+/// Returns the number of bytes and chunks read.
+async fn read_chunks<R: Read + Send>(
+    input: &mut R,
+    chunk_size: usize,
+    chunks: Sender<Vec<u8>>,
+    free: Receiver<Vec<u8>>,
+) -> Result<(u64, u64), CompressError> {
+    let (mut bytes_read, mut count) = (0, 0);
+    loop {
+        let mut chunk = free.try_recv().unwrap_or_default();
+        chunk.resize(chunk_size, 0);
+        let read = input.read(&mut chunk).await?;
+        if read == 0 {
+            // Dropping the sender tells the compressor that this was the end.
+            return Ok((bytes_read, count));
+        }
+        chunk.truncate(read);
+        bytes_read += read as u64;
+        count += 1;
+        if chunks.send(chunk).await.is_err() {
+            // The compressor stopped, and its error is the one returned.
+            return Ok((bytes_read, count));
+        }
+    }
+}
+
+/// Compress the chunks from the reader, and send the output to the writer in chunks of up to
+/// `chunk_size` bytes.
+///
+/// Returns the checksum of the data for the zlib format.
+async fn compress_chunks(
+    options: EncodeOptions,
+    chunk_size: usize,
+    chunks: Receiver<Vec<u8>>,
+    free_chunks: Sender<Vec<u8>>,
+    output: Sender<Vec<u8>>,
+    free_output: Receiver<Vec<u8>>,
+) -> Result<Option<u32>, CompressError> {
+    let mut compressor = CompressorOxide::builder()
+        .format(options.format)
+        .compression_level(options.level)
+        .build_boxed();
+    let mut out = Vec::new();
+    loop {
+        let (chunk, flush) = match chunks.recv().await {
+            Ok(chunk) => (chunk, MZFlush::None),
+            Err(_) => (Vec::new(), MZFlush::Finish),
+        };
+        let mut pos = 0;
+        let status = loop {
+            if out.is_empty() {
+                out = free_output.try_recv().unwrap_or_default();
+            }
+            // Every call gets a whole chunk of room for the output, as in `copy_encode_with`,
+            // since the output of the compressor depends on the room it is given.
+            out.resize(chunk_size, 0);
+            let res = deflate(&mut compressor, &chunk[pos..], &mut out, flush);
+            let Ok(status) = res.status else {
+                return Err(CompressError::Status(compressor.prev_return_status()));
+            };
+            pos += res.bytes_consumed;
+            if res.bytes_written > 0 {
+                let mut full = core::mem::take(&mut out);
+                full.truncate(res.bytes_written);
+                if output.send(full).await.is_err() {
+                    // The writer failed, and its error is the one returned.
+                    return Ok(None);
+                }
+            }
+            if status == MZStatus::StreamEnd || (pos == chunk.len() && flush == MZFlush::None) {
+                break status;
+            }
+        };
+        // The reader may have stopped already.
+        let _ = free_chunks.try_send(chunk);
+        if status == MZStatus::StreamEnd {
+            return Ok((options.format != DataFormat::Raw).then(|| compressor.adler32()));
+        }
+    }
+}
+
+/// Write the output from the compressor to `writer`, and flush it at the end.
+///
+/// Returns the number of bytes written.
+async fn write_chunks<W: Write + Send>(
+    writer: &mut W,
+    output: Receiver<Vec<u8>>,
+    free: Sender<Vec<u8>>,
+) -> Result<u64, CompressError> {
+    let mut bytes_written = 0;
+    while let Ok(chunk) = output.recv().await {
+        writer.write_all(&chunk).await?;
+        bytes_written += chunk.len() as u64;
+        let _ = free.try_send(chunk);
+    }
+    writer.flush().await?;
+    Ok(bytes_written)
+}
+
+/// Run three futures concurrently until all of them are done, or one of them fails.
+pub(crate) async fn try_join3<A, B, C, E>(
+    a: impl Future<Output = Result<A, E>>,
+    b: impl Future<Output = Result<B, E>>,
+    c: impl Future<Output = Result<C, E>>,
+) -> Result<(A, B, C), E> {
+    let (mut a, mut b, mut c) = (pin!(a), pin!(b), pin!(c));
+    let (mut a_out, mut b_out, mut c_out) = (None, None, None);
+    poll_fn(|cx| {
+        if a_out.is_none()
+            && let Poll::Ready(out) = a.as_mut().poll(cx)
+        {
+            a_out = Some(out?);
+        }
+        if b_out.is_none()
+            && let Poll::Ready(out) = b.as_mut().poll(cx)
+        {
+            b_out = Some(out?);
+        }
+        if c_out.is_none()
+            && let Poll::Ready(out) = c.as_mut().poll(cx)
+        {
+            c_out = Some(out?);
+        }
+        match (a_out.take(), b_out.take(), c_out.take()) {
+            (Some(a), Some(b), Some(c)) => Poll::Ready(Ok((a, b, c))),
+            outs => {
+                (a_out, b_out, c_out) = outs;
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::compress_pipelined;
+    use crate::DataFormat;
+    use crate::deflate::stream::{EncodeOptions, copy_encode_with};
+    use crate::deflate::{CompressError, CompressionLevel};
+    use binrw::io::write::Write;
+    use std::io::{Cursor, ErrorKind};
+
+    /// A writer that only takes a few bytes at a time, and fails after `limit` bytes.
+    struct Slow {
+        data: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+            async move {
+                tokio::task::yield_now().await;
+                if self.data.len() >= self.limit {
+                    return Err(ErrorKind::StorageFull.into());
+                }
+                let n = buf.len().min(700);
+                self.data.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+        }
+
+        fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+            async { Ok(()) }
+        }
+    }
+
+    #[tokio::test]
+    async fn same_output_as_copy_encode() {
+        let data = crate::test_util::text(300_000);
+        for (format, chunk_size, capacity) in [
+            (DataFormat::Raw, 32 * 1024, 4),
+            (DataFormat::Zlib, 1000, 0),
+            (DataFormat::Zlib, 1 << 20, 2),
+        ] {
+            let options = EncodeOptions {
+                level: CompressionLevel::BestSpeed,
+                format,
+                chunk_size,
+            };
+            let mut expected = Cursor::new(Vec::new());
+            copy_encode_with(&mut Cursor::new(&data), &mut expected, options)
+                .await
+                .unwrap();
+            let expected = expected.into_inner();
+
+            let mut slow = Slow {
+                data: Vec::new(),
+                limit: usize::MAX,
+            };
+            let summary = compress_pipelined(&mut Cursor::new(&data), &mut slow, options, capacity)
+                .await
+                .unwrap();
+            assert!(slow.data == expected);
+            assert_eq!(summary.bytes_read, data.len() as u64);
+            assert_eq!(summary.bytes_written, expected.len() as u64);
+            assert_eq!(summary.chunks, data.len().div_ceil(chunk_size) as u64);
+            let adler32 = crate::shared::update_adler32(1, &data);
+            assert_eq!(
+                summary.checksum,
+                (format == DataFormat::Zlib).then_some(adler32)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn writer_error_stops_the_pipeline() {
+        let data = vec![7; 1_000_000];
+        let mut slow = Slow {
+            data: Vec::new(),
+            limit: 1000,
+        };
+        let options = EncodeOptions {
+            level: CompressionLevel::NoCompression,
+            ..EncodeOptions::default()
+        };
+        let err = compress_pipelined(&mut Cursor::new(&data), &mut slow, options, 2)
+            .await
+            .unwrap_err();
+        match err {
+            CompressError::Io(err) => assert_eq!(err.kind(), ErrorKind::StorageFull),
+            err => panic!("unexpected error {err:?}"),
+        }
+    }
+}
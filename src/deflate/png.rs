@@ -0,0 +1,115 @@
+//! A minimal PNG encoder, the equivalent of miniz's `tdefl_write_image_to_png_file_in_memory`,
+//! so small tools can write out an image without pulling in an image crate.
+//!
+//! The image is written as a single IDAT chunk with no filtering, like miniz does, which is
+//! fine for screenshots and other generated images but leaves photos larger than a real PNG
+//! encoder would.
+
+use crate::alloc::vec::Vec;
+
+use super::compress_to_vec_zlib;
+use crate::shared::update_crc32;
+
+/// The PNG file signature.
+const SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+/// Colour type of 8-bit RGBA in the IHDR chunk.
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// Encode an image of `width` by `height` pixels as a PNG file, compressing the pixel data at
+/// `level` (0-10).
+///
+/// `pixels` holds the rows from top to bottom, with 4 bytes of red, green, blue and alpha per
+/// pixel.
+///
+/// # Panics
+///
+/// If `pixels` is not `width * height * 4` bytes long, or `width` or `height` is 0 or more
+/// than 2^31 - 1, which PNG doesn't allow.
+pub fn encode_rgba(width: u32, height: u32, pixels: &[u8], level: u8) -> Vec<u8> {
+    assert!(
+        (1..=i32::MAX as u32).contains(&width) && (1..=i32::MAX as u32).contains(&height),
+        "invalid PNG dimensions {width}x{height}"
+    );
+    let stride = width as usize * 4;
+    assert_eq!(
+        Some(pixels.len()),
+        stride.checked_mul(height as usize),
+        "pixel data doesn't match the dimensions"
+    );
+
+    // Each row starts with its filter type, 0 for none.
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks_exact(stride) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let idat = compress_to_vec_zlib(&raw, level);
+
+    let mut ihdr = [0; 13];
+    ihdr[..4].copy_from_slice(&width.to_be_bytes());
+    ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, then default compression, filtering and no interlacing.
+    ihdr[8] = 8;
+    ihdr[9] = COLOR_TYPE_RGBA;
+
+    let mut png = Vec::with_capacity(SIGNATURE.len() + 3 * 12 + ihdr.len() + idat.len());
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &idat);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Append a chunk of type `ty` with `data` to `png`.
+fn write_chunk(png: &mut Vec<u8>, ty: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(ty);
+    png.extend_from_slice(data);
+    let crc = update_crc32(update_crc32(0, ty), data);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::inflate::decompress_to_vec_zlib;
+
+    #[test]
+    fn encode() {
+        let (width, height) = (37, 21);
+        let pixels: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        let png = encode_rgba(width, height, &pixels, 6);
+        assert_eq!(png[..8], SIGNATURE);
+
+        // Split the chunks, checking their CRC.
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (ty, data) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, update_crc32(update_crc32(0, ty), data));
+            chunks.push((ty, data));
+            rest = &rest[12 + len..];
+        }
+        let types: Vec<_> = chunks.iter().map(|(ty, _)| *ty).collect();
+        assert_eq!(types, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 37, 0, 0, 0, 21, 8, 6, 0, 0, 0]);
+
+        let raw = decompress_to_vec_zlib(chunks[1].1).unwrap();
+        let stride = width as usize * 4 + 1;
+        assert_eq!(raw.len(), stride * height as usize);
+        for (row, expected) in raw.chunks(stride).zip(pixels.chunks(stride - 1)) {
+            assert_eq!(row[0], 0);
+            assert_eq!(&row[1..], expected);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrong_size() {
+        encode_rgba(2, 2, &[0; 15], 6);
+    }
+}
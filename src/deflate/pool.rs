@@ -0,0 +1,169 @@
+//! A thread-safe pool of reusable compressors.
+//!
+//! [`CompressorOxide`] allocates several hundred KiB of buffers, so creating one per request
+//! can be expensive in servers. A [`CompressorPool`] hands out compressors that are reset and
+//! returned to the pool when dropped.
+
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use super::core::CompressorOxide;
+
+/// A pool of [`CompressorOxide`] instances sharing the same flags.
+pub struct CompressorPool {
+    flags: u32,
+    max_idle: usize,
+    idle: Mutex<Vec<CompressorOxide>>,
+}
+
+impl CompressorPool {
+    /// Create an empty pool. Compressors are created on demand with the given `flags`.
+    pub fn new(flags: u32) -> CompressorPool {
+        CompressorPool {
+            flags,
+            max_idle: usize::MAX,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Limit the number of idle compressors kept around. Compressors returned while the pool
+    /// is full are dropped.
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Take a compressor from the pool, creating a new one if none is available.
+    ///
+    /// The compressor is reset to the settings of the pool's flags and returned to the pool
+    /// when the guard is dropped.
+    pub fn get(&self) -> PooledCompressor<'_> {
+        let compressor = self
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| CompressorOxide::new(self.flags));
+        PooledCompressor {
+            pool: self,
+            compressor: Some(compressor),
+        }
+    }
+
+    /// Number of idle compressors currently held by the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// A compressor checked out from a [`CompressorPool`].
+pub struct PooledCompressor<'a> {
+    pool: &'a CompressorPool,
+    compressor: Option<CompressorOxide>,
+}
+
+impl Deref for PooledCompressor<'_> {
+    type Target = CompressorOxide;
+
+    fn deref(&self) -> &CompressorOxide {
+        self.compressor.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledCompressor<'_> {
+    fn deref_mut(&mut self) -> &mut CompressorOxide {
+        self.compressor.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledCompressor<'_> {
+    fn drop(&mut self) {
+        if let Some(mut compressor) = self.compressor.take() {
+            // Bring the compressor back to the settings it was created with before taking the
+            // lock, as anything set while checked out would otherwise apply to the next user.
+            compressor.reset();
+            compressor.params.update_flags(self.pool.flags);
+            compressor.dict.update_flags(self.pool.flags);
+            compressor.set_adaptive_level(None);
+            compressor.set_record_boundaries(false);
+            compressor.set_full_flush_interval(0);
+            compressor.set_collect_stats(false);
+            compressor.set_metrics(None);
+            compressor.take_trace();
+
+            let mut idle = self.pool.idle.lock().unwrap_or_else(|e| e.into_inner());
+            if idle.len() < self.pool.max_idle {
+                idle.push(compressor);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CompressorPool;
+    use crate::deflate::core::{
+        AdaptiveLevel, CompressorOxide, DEFAULT_FLAGS, MatchParams, TDEFLFlush, TDEFLStatus,
+        compress,
+    };
+    use crate::inflate::decompress_to_vec_zlib;
+    use crate::metrics::MetricsSink;
+    use alloc::vec;
+    use std::sync::Arc;
+
+    #[test]
+    fn reuse_compressor() {
+        let pool = CompressorPool::new(DEFAULT_FLAGS).max_idle(1);
+        let data = b"Hello, pooled compressor! Hello, pooled compressor!";
+        for _ in 0..3 {
+            let mut d = pool.get();
+            let mut out = vec![0; 128];
+            let (status, _, written) = compress(&mut d, data, &mut out, TDEFLFlush::Finish);
+            assert!(status == TDEFLStatus::Done);
+            assert_eq!(decompress_to_vec_zlib(&out[..written]).unwrap(), data);
+        }
+        assert_eq!(pool.idle_count(), 1);
+
+        let a = pool.get();
+        let b = pool.get();
+        drop(a);
+        drop(b);
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn settings_restored() {
+        struct Sink;
+        impl MetricsSink for Sink {}
+
+        let pool = CompressorPool::new(DEFAULT_FLAGS);
+        let fresh = CompressorOxide::new(DEFAULT_FLAGS);
+        let mut d = pool.get();
+        d.set_compression_level_raw(9);
+        d.set_match_params(MatchParams {
+            good_length: 4,
+            nice_length: 16,
+            max_chain: 8,
+            max_lazy: 4,
+            hash_len: 4,
+        });
+        d.set_adaptive_level(Some(AdaptiveLevel::default()));
+        d.set_record_boundaries(true);
+        d.set_full_flush_interval(1000);
+        d.set_collect_stats(true);
+        d.set_metrics(Some(Arc::new(Sink)));
+        d.record_trace();
+        drop(d);
+
+        let d = pool.get();
+        assert_eq!(d.flags(), fresh.flags());
+        assert_eq!(d.match_params(), fresh.match_params());
+        assert_eq!(d.adaptive_level(), None);
+        assert!(d.params.boundaries.is_none());
+        assert_eq!(d.full_flush_interval(), 0);
+        assert_eq!(d.stats(), None);
+        assert!(d.metrics.is_none());
+        assert!(d.params.tracer.is_none());
+    }
+}
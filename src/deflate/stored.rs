@@ -15,6 +15,15 @@ pub(crate) fn compress_stored(d: &mut CompressorOxide, callback: &mut CallbackOx
     // TODO: It's possible we don't need this or could do this elsewhere later
     // but just do this here to avoid causing issues for now.
     d.params.saved_match_len = 0;
+    if d.lz.code_position > 1 {
+        // The block was started at another level, and the lookahead may be too long for it
+        // to be output as a raw block, so end it with the codes it has.
+        let n =
+            flush_block(d, callback, TDEFLFlush::None).unwrap_or(TDEFLStatus::PutBufFailed as i32);
+        if n != 0 {
+            return n > 0;
+        }
+    }
     let mut bytes_written = d.lz.total_bytes;
     let mut src_pos = d.params.src_pos;
     let mut lookahead_size = d.dict.lookahead_size;
@@ -22,7 +31,9 @@ pub(crate) fn compress_stored(d: &mut CompressorOxide, callback: &mut CallbackOx
 
     while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size != 0) {
         let src_buf_left = in_buf.len() - src_pos;
-        let num_bytes_to_process = cmp::min(src_buf_left, MAX_MATCH_LEN - lookahead_size);
+        // The lookahead can be longer after switching from another compression level.
+        let num_bytes_to_process =
+            cmp::min(src_buf_left, MAX_MATCH_LEN.saturating_sub(lookahead_size));
 
         if lookahead_size + d.dict.size >= usize::from(MIN_MATCH_LEN) - 1
             && num_bytes_to_process > 0
@@ -4,27 +4,135 @@
 //!
 //! There is no DeflateState as the needed state is contained in the compressor struct itself.
 
-use crate::deflate::CompressionLevel;
+#[cfg(feature = "bytes")]
+use crate::deflate::core::{PutBufResult, compress_to_output};
 use crate::deflate::core::{CompressorOxide, TDEFLFlush, TDEFLStatus, compress};
+#[cfg(feature = "std")]
+use crate::deflate::{CompressError, CompressionLevel};
+#[cfg(feature = "std")]
+use crate::{DataFormat, StreamSummary};
+#[cfg(feature = "std")]
 use crate::error::Error;
-use crate::{DataFormat, MZError, MZFlush, MZStatus, StreamResult};
+#[cfg(feature = "std")]
+use crate::shared::{ScratchBuffer, Segments, WriteVectored};
+use crate::{MZError, MZFlush, MZStatus, StreamResult};
+#[cfg(feature = "std")]
 use binrw::io::read::Read;
+#[cfg(feature = "std")]
 use binrw::io::write::Write;
+#[cfg(feature = "bytes")]
+use bytes::{Buf, BufMut};
+#[cfg(feature = "std")]
+use std::io::IoSlice;
 
+/// Compress everything read from `input` to `writer`, wrapped in `data_format` like the output
+/// of the one-shot functions, e.g. [`DataFormat::Zlib`] for the output of
+/// [`compress_to_vec_zlib`](crate::deflate::compress_to_vec_zlib).
+///
+/// Returns a summary of the data that went through. Use [`copy_encode_with`] to set the size
+/// of the buffers.
+#[cfg(feature = "std")]
 pub fn compress_stream_callback<'a, R: Read + Send + 'a, W: Write + Send>(
     input: &'a mut R,
     writer: &'a mut W,
     compression_level: CompressionLevel,
-) -> impl Future<Output = Result<(), Error>> + Send + 'a {
+    data_format: DataFormat,
+) -> impl Future<Output = Result<StreamSummary, CompressError>> + Send + 'a {
+    let options = EncodeOptions {
+        level: compression_level,
+        format: data_format,
+        ..EncodeOptions::default()
+    };
+    encode_stream(input, writer, options)
+}
+
+/// Compress everything read from `input` to `writer` in the given format, e.g. to compress
+/// one file to another in one call.
+///
+/// Only two 32 KiB buffers are used, whatever the size of the input. Use [`copy_encode_with`]
+/// for other sizes.
+///
+/// Returns the number of compressed bytes written.
+#[cfg(feature = "std")]
+pub fn copy_encode<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    compression_level: CompressionLevel,
+    data_format: DataFormat,
+) -> impl Future<Output = Result<u64, CompressError>> + Send + 'a {
+    let options = EncodeOptions {
+        level: compression_level,
+        format: data_format,
+        ..EncodeOptions::default()
+    };
+    copy_encode_with(input, writer, options)
+}
+
+/// Settings for [`copy_encode_with`]. The defaults are those of [`copy_encode`] with
+/// [`DataFormat::Raw`].
+#[cfg(feature = "std")]
+#[derive(Copy, Clone)]
+pub struct EncodeOptions {
+    /// The compression level.
+    pub level: CompressionLevel,
+    /// How the compressed data is wrapped.
+    pub format: DataFormat,
+    /// The size of the input and the output buffer, i.e. the most that is read from the input
+    /// or written to the writer at once. Larger buffers mean fewer calls, e.g. fewer syscalls
+    /// for files and sockets, and smaller ones less memory. At least one byte is used.
+    pub chunk_size: usize,
+}
+
+#[cfg(feature = "std")]
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            level: CompressionLevel::DefaultLevel,
+            format: DataFormat::Raw,
+            chunk_size: 32 * 1024,
+        }
+    }
+}
+
+/// Compress everything read from `input` to `writer`, with the settings of `options`.
+///
+/// The input and output buffers are allocated once, and reused until the end of the input.
+///
+/// Returns the number of compressed bytes written.
+#[cfg(feature = "std")]
+pub fn copy_encode_with<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: EncodeOptions,
+) -> impl Future<Output = Result<u64, CompressError>> + Send + 'a {
+    async move { Ok(encode_stream(input, writer, options).await?.bytes_written) }
+}
+
+/// Backend of [`compress_stream_callback`] and [`copy_encode_with`].
+#[cfg(feature = "std")]
+pub(crate) fn encode_stream<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: EncodeOptions,
+) -> impl Future<Output = Result<StreamSummary, CompressError>> + Send + 'a {
     async move {
-        let mut compressor = Box::<CompressorOxide>::default();
-        compressor.set_format_and_level(DataFormat::Raw, compression_level as u8);
+        let mut compressor = CompressorOxide::builder()
+            .format(options.format)
+            .compression_level(options.level)
+            .build_boxed();
         let mut flush: MZFlush = MZFlush::None;
 
-        let mut input_buffer = vec![0; 32 * 1024];
+        // Both buffers are allocated once and reused for every iteration, with the valid
+        // parts tracked by `input_offset`/`input_end` and `bytes_written`. They get memory
+        // that is already zeroed from the allocator, so this doesn't need to touch the buffers
+        // up front either.
+        let chunk_size = options.chunk_size.max(1);
+        let mut input_buffer = ScratchBuffer::new(chunk_size);
+        let mut data = ScratchBuffer::new(chunk_size);
         let mut input_offset = 0;
         let mut input_end = 0;
         let mut is_eof = false;
+        let mut summary = StreamSummary::default();
 
         loop {
             if input_offset == input_end && !is_eof {
@@ -33,10 +141,12 @@ pub fn compress_stream_callback<'a, R: Read + Send + 'a, W: Write + Send>(
                 if input_end == 0 {
                     is_eof = true;
                     flush = MZFlush::Finish;
+                } else {
+                    summary.bytes_read += input_end as u64;
+                    summary.chunks += 1;
                 }
             }
 
-            let mut data = vec![0; 32 * 1024];
             let res = deflate(
                 &mut compressor,
                 &input_buffer[input_offset..input_end],
@@ -49,18 +159,96 @@ pub fn compress_stream_callback<'a, R: Read + Send + 'a, W: Write + Send>(
                     if res.bytes_written > 0 {
                         let data = &data[..res.bytes_written];
                         writer.write_all(data).await?;
+                        summary.bytes_written += data.len() as u64;
                     }
                     if status == MZStatus::StreamEnd {
-                        return Ok(());
+                        writer.flush().await?;
+                        summary.checksum = (options.format != DataFormat::Raw)
+                            .then(|| compressor.adler32());
+                        return Ok(summary);
                     }
                 }
-                Err(e) => {
-                    return Err(Error::Msg(format!("{:?}", e)));
+                Err(_) => {
+                    return Err(CompressError::Status(compressor.prev_return_status()));
                 }
             }
         }
     }
 }
+/// Compress the buffers in `input` one after the other, as if they were one, and write the
+/// compressed data to `writer` with [`WriteVectored::write_vectored`].
+///
+/// The output is collected in 16 KiB buffers, which are written out together whenever 256 KiB
+/// have been collected and at the end, so the many small pieces produced when e.g. every
+/// message is compressed with [`MZFlush::Sync`] go out in one syscall rather than one each.
+///
+/// `flush` applies to the last buffer, and the status is the same as for [`deflate`]. As all
+/// the output is written out, the compressor only stops before the end of `input` on an error.
+/// The writer is flushed as well unless `flush` is [`MZFlush::None`].
+#[cfg(feature = "std")]
+pub fn deflate_vectored<'a, W: WriteVectored>(
+    compressor: &'a mut CompressorOxide,
+    input: &'a [IoSlice<'a>],
+    writer: &'a mut W,
+    flush: MZFlush,
+) -> impl Future<Output = Result<StreamResult, Error>> + Send + 'a {
+    async move {
+        let mut segments = Segments::new();
+        let mut res = StreamResult {
+            bytes_consumed: 0,
+            bytes_written: 0,
+            status: Ok(MZStatus::Ok),
+        };
+        // Go through the loop once with no buffers too, e.g. to finish the stream.
+        'buffers: for i in 0..input.len().max(1) {
+            let mut next_in: &[u8] = input.get(i).map_or(&[], |buf| buf);
+            // Only the last buffer is compressed with `flush`, the others are followed by more.
+            let buf_flush = if i + 1 >= input.len() {
+                flush
+            } else {
+                MZFlush::None
+            };
+            loop {
+                let out = segments.spare();
+                if out.is_empty() {
+                    segments.write_to(writer).await?;
+                    continue;
+                }
+                let step = deflate(compressor, next_in, out, buf_flush);
+                segments.advance(step.bytes_written);
+                next_in = &next_in[step.bytes_consumed..];
+                res.bytes_consumed += step.bytes_consumed;
+                res.bytes_written += step.bytes_written;
+                res.status = step.status;
+                match step.status {
+                    // All of this buffer has been taken in, and nothing is waiting to be
+                    // written out.
+                    Ok(MZStatus::Ok)
+                        if next_in.is_empty()
+                            && buf_flush != MZFlush::Finish
+                            && compressor.pending_bytes() == 0 =>
+                    {
+                        break;
+                    }
+                    Ok(MZStatus::Ok) => {}
+                    // No progress without more input, e.g. for an empty buffer.
+                    Err(MZError::Buf) if next_in.is_empty() => break,
+                    _ => break 'buffers,
+                }
+            }
+        }
+        if res.status == Err(MZError::Buf) && (res.bytes_consumed > 0 || res.bytes_written > 0) {
+            res.status = Ok(MZStatus::Ok);
+        }
+
+        segments.write_to(writer).await?;
+        if flush != MZFlush::None {
+            writer.flush().await?;
+        }
+        Ok(res)
+    }
+}
+
 /// Try to compress from input to output with the given [`CompressorOxide`].
 ///
 /// # Errors
@@ -150,7 +338,112 @@ pub fn deflate(
     }
 }
 
-#[cfg(test)]
+/// Try to compress from `input` to `output` with the given [`CompressorOxide`], advancing both
+/// past what was consumed and written.
+///
+/// `input` can be made of several chunks, e.g. a chain of `Bytes`, which are compressed one
+/// after the other without copying them together first, and the compressed data is copied
+/// straight into `output`, which grows as needed if it is a `BytesMut` or `Vec<u8>`. When
+/// `output` is full, the rest is kept in the compressor and written first on the next call.
+///
+/// `flush` and the errors are the same as for [`deflate`].
+///
+/// ```
+/// use bytes::{Buf, Bytes, BytesMut};
+/// use miniz_oxide::deflate::core::CompressorOxide;
+/// use miniz_oxide::deflate::stream::deflate_buf;
+/// use miniz_oxide::{MZFlush, MZStatus};
+///
+/// let mut input = Bytes::from_static(b"Hello, ").chain(Bytes::from_static(b"world!"));
+/// let mut output = BytesMut::new();
+/// let mut compressor = CompressorOxide::default();
+/// let res = deflate_buf(&mut compressor, &mut input, &mut output, MZFlush::Finish);
+/// assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+/// assert_eq!(
+///     miniz_oxide::inflate::decompress_to_vec_zlib(&output).unwrap(),
+///     b"Hello, world!"
+/// );
+/// ```
+#[cfg(feature = "bytes")]
+pub fn deflate_buf<B: Buf, M: BufMut>(
+    compressor: &mut CompressorOxide,
+    input: &mut B,
+    output: &mut M,
+    flush: MZFlush,
+) -> StreamResult {
+    if !output.has_remaining_mut() {
+        return StreamResult::error(MZError::Buf);
+    }
+
+    if compressor.prev_return_status() == TDEFLStatus::Done {
+        return if flush == MZFlush::Finish {
+            StreamResult {
+                bytes_written: 0,
+                bytes_consumed: 0,
+                status: Ok(MZStatus::StreamEnd),
+            }
+        } else {
+            StreamResult::error(MZError::Buf)
+        };
+    }
+
+    let mut bytes_written = 0;
+    let mut bytes_consumed = 0;
+
+    let status = loop {
+        let chunk = input.chunk();
+        // Only the last chunk is compressed with `flush`, the others are followed by more.
+        let chunk_flush = if chunk.len() == input.remaining() {
+            flush
+        } else {
+            MZFlush::None
+        };
+        let (defl_status, in_bytes) =
+            compress_to_output(compressor, chunk, TDEFLFlush::from(chunk_flush), |buf| {
+                let n = buf.len().min(output.remaining_mut());
+                output.put_slice(&buf[..n]);
+                bytes_written += n;
+                if n == buf.len() {
+                    PutBufResult::Accepted
+                } else {
+                    PutBufResult::Pause(n)
+                }
+            });
+        input.advance(in_bytes);
+        bytes_consumed += in_bytes;
+
+        match defl_status {
+            TDEFLStatus::BadParam => break Err(MZError::Param),
+            TDEFLStatus::PutBufFailed => break Err(MZError::Stream),
+            TDEFLStatus::Done => break Ok(MZStatus::StreamEnd),
+            TDEFLStatus::Okay => (),
+        };
+
+        // The output is full, so wait for more space.
+        if !output.has_remaining_mut() || compressor.pending_bytes() != 0 {
+            break Ok(MZStatus::Ok);
+        }
+
+        if !input.has_remaining() && (flush != MZFlush::Finish) {
+            let total_changed = bytes_written > 0 || bytes_consumed > 0;
+
+            break if (flush != MZFlush::None) || total_changed {
+                Ok(MZStatus::Ok)
+            } else {
+                // No more input data, not flushing, and nothing was consumed or written,
+                // so couldn't make any progress.
+                Err(MZError::Buf)
+            };
+        }
+    };
+    StreamResult {
+        bytes_consumed,
+        bytes_written,
+        status,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use super::deflate;
     use crate::deflate::CompressorOxide;
@@ -159,6 +452,86 @@ mod test {
     use alloc::boxed::Box;
     use alloc::vec;
 
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn buf_chain_roundtrip() {
+        use super::deflate_buf;
+        use crate::inflate::stream::{InflateState, inflate_buf};
+        use crate::DataFormat;
+        use alloc::vec::Vec;
+        use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+        let data = crate::test_util::random(100_000, 16);
+        let mut input = Bytes::copy_from_slice(&data[..10])
+            .chain(Bytes::copy_from_slice(&data[10..60_000]))
+            .chain(Bytes::copy_from_slice(&data[60_000..]));
+
+        // Take the output 1000 bytes at a time, so the compressor has to hold on to the rest.
+        let mut compressor = Box::<CompressorOxide>::default();
+        let mut output = BytesMut::new().limit(1000);
+        let mut chunks = Vec::new();
+        loop {
+            let res = deflate_buf(&mut compressor, &mut input, &mut output, MZFlush::Finish);
+            chunks.push(output.get_mut().split().freeze());
+            output.set_limit(1000);
+            if res.status == Ok(MZStatus::StreamEnd) {
+                break;
+            }
+            assert_eq!(res.status, Ok(MZStatus::Ok));
+        }
+        assert!(chunks.len() > 10);
+        assert!(!input.has_remaining());
+
+        // Decompress the chunks as one chain.
+        let mut compressed = chunks
+            .into_iter()
+            .fold(Box::new(Bytes::new()) as Box<dyn Buf>, |chain, chunk| {
+                Box::new(chain.chain(chunk))
+            });
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut decompressed = Vec::new();
+        let res = inflate_buf(&mut state, &mut compressed, &mut decompressed, MZFlush::None);
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        assert_eq!(decompressed, data);
+        assert_eq!(state.total_out(), data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn copy_encode_errors() {
+        use super::copy_encode;
+        use crate::DataFormat;
+        use crate::deflate::{CompressError, CompressionLevel};
+        use binrw::io::write::Write;
+        use std::io::{Cursor, ErrorKind};
+
+        struct Full;
+        impl Write for Full {
+            fn write(
+                &mut self,
+                _buf: &[u8],
+            ) -> impl Future<Output = std::io::Result<usize>> + Send {
+                async { Err(ErrorKind::StorageFull.into()) }
+            }
+            fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+                async { Ok(()) }
+            }
+        }
+
+        // The cause of an IO failure is kept, rather than only its message.
+        let err = copy_encode(
+            &mut Cursor::new(vec![7; 100_000]),
+            &mut Full,
+            CompressionLevel::BestSpeed,
+            DataFormat::Raw,
+        )
+        .await
+        .unwrap_err();
+        match err {
+            CompressError::Io(err) => assert_eq!(err.kind(), ErrorKind::StorageFull),
+            err => panic!("unexpected error {err:?}"),
+        }
+    }
+
     #[test]
     fn test_state() {
         let data = b"Hello zlib!";
@@ -172,4 +545,111 @@ mod test {
         assert_eq!(decomp[..], data[..]);
         assert_eq!(res.bytes_consumed, data.len());
     }
+
+    #[test]
+    fn full_flush_interval() {
+        use crate::deflate::core::{compress_to_output, TDEFLFlush};
+        use crate::inflate::decompress_to_vec;
+        use alloc::vec::Vec;
+
+        let data = crate::test_util::text(300_000);
+        let points: Vec<u64> = (1..=6).map(|i| i * 50_000).collect();
+
+        // Odd input and output sizes, so the points fall in the middle of both.
+        let mut compressor = Box::<CompressorOxide>::default();
+        compressor.set_full_flush_interval(50_000);
+        compressor.set_record_boundaries(true);
+        let mut compressed = Vec::new();
+        let mut out = vec![0; 777];
+        let mut chunks = data.chunks(12_345).peekable();
+        let mut input: &[u8] = chunks.next().unwrap();
+        loop {
+            let flush = if input.is_empty() && chunks.peek().is_none() {
+                MZFlush::Finish
+            } else {
+                MZFlush::None
+            };
+            let res = deflate(&mut compressor, input, &mut out, flush);
+            compressed.extend_from_slice(&out[..res.bytes_written]);
+            input = &input[res.bytes_consumed..];
+            if res.status.expect("Failed to compress!") == MZStatus::StreamEnd {
+                break;
+            }
+            if input.is_empty() {
+                input = chunks.next().unwrap_or(&[]);
+            }
+        }
+        assert_eq!(decompress_to_vec_zlib(&compressed).unwrap(), data);
+
+        let full: Vec<_> = compressor
+            .boundaries()
+            .iter()
+            .filter(|b| b.flush == TDEFLFlush::Full)
+            .copied()
+            .collect();
+        assert_eq!(full.iter().map(|b| b.in_offset).collect::<Vec<_>>(), points);
+        // Decompression can start over at each point without the data before it.
+        for boundary in &full {
+            assert_eq!(boundary.out_bit_offset % 8, 0);
+            let start = (boundary.out_bit_offset / 8) as usize;
+            let rest = decompress_to_vec(&compressed[start..]).unwrap();
+            assert_eq!(rest, data[boundary.in_offset as usize..]);
+        }
+
+        // The same with the output passed to a callback.
+        compressor.reset();
+        assert_eq!(compressor.full_flush_interval(), 50_000);
+        let mut compressed = Vec::new();
+        compress_to_output(&mut compressor, &data, TDEFLFlush::Finish, |buf| {
+            compressed.extend_from_slice(buf);
+            true
+        });
+        assert_eq!(decompress_to_vec_zlib(&compressed).unwrap(), data);
+        let full = compressor
+            .boundaries()
+            .iter()
+            .filter(|b| b.flush == TDEFLFlush::Full)
+            .count();
+        // No flush right before the end of the stream.
+        assert_eq!(full, points.len() - 1);
+    }
+
+    #[tokio::test]
+    async fn stream_callback_roundtrip() {
+        use super::compress_stream_callback;
+        use crate::DataFormat;
+        use crate::deflate::{CompressionLevel, compress_to_vec, compress_to_vec_zlib};
+        use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+        use std::io::Cursor;
+
+        // Large enough to need several passes through the reused buffers.
+        let data = crate::test_util::text(200_000);
+        let mut compressed = Vec::new();
+        for format in [DataFormat::Raw, DataFormat::Zlib] {
+            let mut input = Cursor::new(data.clone());
+            let mut output = Cursor::new(Vec::new());
+            let summary = compress_stream_callback(
+                &mut input,
+                &mut output,
+                CompressionLevel::DefaultLevel,
+                format,
+            )
+            .await
+            .unwrap();
+            let output = output.into_inner();
+            assert_eq!(summary.bytes_read, data.len() as u64);
+            assert_eq!(summary.bytes_written, output.len() as u64);
+            assert_eq!(summary.chunks, data.len().div_ceil(32 * 1024) as u64);
+            let adler32 = crate::shared::update_adler32(1, &data);
+            assert_eq!(summary.checksum, (format == DataFormat::Zlib).then_some(adler32));
+            compressed.push(output);
+        }
+        let decomp = decompress_to_vec(&compressed[0]).expect("Failed to decompress!");
+        assert_eq!(decomp, data);
+        let decomp = decompress_to_vec_zlib(&compressed[1]).expect("Failed to decompress!");
+        assert_eq!(decomp, data);
+        // The same as the one-shot functions.
+        assert_eq!(compressed[0], compress_to_vec(&data, 6));
+        assert_eq!(compressed[1], compress_to_vec_zlib(&data, 6));
+    }
 }
@@ -0,0 +1,474 @@
+//! Recording the decisions of the compressor, and replaying them to get the same output.
+//!
+//! Besides the matches it finds, the output of the compressor depends on where it ends the
+//! blocks, which type of block it uses for each, and whether it takes each match right away
+//! or checks for a longer one at the next byte first (lazy matching). These decisions come
+//! from heuristics that can change between versions. A [`DecisionTrace`] recorded with
+//! [`CompressorOxide::record_trace`] holds all of them in a compact form, and
+//! [`CompressorOxide::replay_trace`] makes another compressor, e.g. of another version, take
+//! the same ones. With the same input, level and flushes, and a match finder that finds the
+//! same matches, the output is then bit-identical, which narrows a difference in output down
+//! to the match finder.
+//!
+//! Ending the blocks early and the lazy matching choices are only replayed by levels 2 to 10
+//! with the default strategy, as the others make these decisions in other ways. The others
+//! only replay the block types.
+//!
+//! ```
+//! use miniz_oxide::deflate::core::{compress, CompressorOxide, TDEFLFlush};
+//! use miniz_oxide::deflate::trace::DecisionTrace;
+//!
+//! let data = b"Decisions, decisions, decisions.".repeat(1000);
+//! let mut output = vec![0; 10_000];
+//!
+//! let mut recorder = CompressorOxide::default();
+//! recorder.record_trace();
+//! let (_, _, len) = compress(&mut recorder, &data, &mut output, TDEFLFlush::Finish);
+//! let trace = recorder.take_trace().unwrap();
+//!
+//! // The trace can be stored, e.g. in a bug report.
+//! let trace = DecisionTrace::from_bytes(&trace.to_bytes()).unwrap();
+//! let mut replayer = CompressorOxide::default();
+//! replayer.replay_trace(trace);
+//! let mut replayed = vec![0; 10_000];
+//! let (_, _, replayed_len) = compress(&mut replayer, &data, &mut replayed, TDEFLFlush::Finish);
+//! assert!(!replayer.trace_diverged());
+//! assert_eq!(replayed[..replayed_len], output[..len]);
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::core::{CompressorOxide, TDEFLFlush};
+use crate::inflate::analyze::BlockType;
+
+/// Magic bytes at the start of a serialized trace.
+const TRACE_MAGIC: [u8; 4] = *b"MZTR";
+/// Version of the serialized trace layout.
+const TRACE_VERSION: u8 = 1;
+
+/// A block output by the compressor, as recorded in a [`DecisionTrace`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TracedBlock {
+    /// Number of input bytes in the block.
+    pub input_bytes: u32,
+    /// How the block was encoded.
+    pub block_type: BlockType,
+    /// The flush that ended the block, or [`TDEFLFlush::None`] if the compressor ended it.
+    pub flush: TDEFLFlush,
+}
+
+/// The decisions made by a compressor, recorded with [`CompressorOxide::record_trace`].
+///
+/// See the [module documentation](self) for what is recorded and how it is used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecisionTrace {
+    blocks: Vec<TracedBlock>,
+    /// The lazy matching choices, one bit each, starting from the lowest bit of the first byte.
+    choices: Vec<u8>,
+    num_choices: usize,
+}
+
+impl DecisionTrace {
+    /// The blocks in the order they were output.
+    pub fn blocks(&self) -> &[TracedBlock] {
+        &self.blocks
+    }
+
+    /// Number of lazy matching choices recorded, one for every match found.
+    pub const fn num_choices(&self) -> usize {
+        self.num_choices
+    }
+
+    /// Serialize the trace, to be read back with [`DecisionTrace::from_bytes`].
+    ///
+    /// It takes a few bytes per block and a bit per match.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + self.blocks.len() * 4 + self.choices.len());
+        out.extend_from_slice(&TRACE_MAGIC);
+        out.push(TRACE_VERSION);
+        write_varint(&mut out, self.blocks.len() as u64);
+        for block in &self.blocks {
+            write_varint(&mut out, block.input_bytes.into());
+            let block_type = match block.block_type {
+                BlockType::Stored => 0,
+                BlockType::Static => 1,
+                BlockType::Dynamic => 2,
+            };
+            out.push(block_type | (block.flush as u8) << 2);
+        }
+        write_varint(&mut out, self.num_choices as u64);
+        out.extend_from_slice(&self.choices);
+        out
+    }
+
+    /// Read a trace serialized with [`DecisionTrace::to_bytes`].
+    ///
+    /// Returns `None` if the data is truncated, has trailing bytes, was produced by an
+    /// incompatible version, or contains values that are out of range.
+    pub fn from_bytes(data: &[u8]) -> Option<DecisionTrace> {
+        let rest = data.strip_prefix(&TRACE_MAGIC)?;
+        let (&version, mut rest) = rest.split_first()?;
+        if version != TRACE_VERSION {
+            return None;
+        }
+        let num_blocks = read_varint(&mut rest)?;
+        let mut blocks = Vec::new();
+        for _ in 0..num_blocks {
+            let input_bytes = read_varint(&mut rest)?.try_into().ok()?;
+            let (&kind, tail) = rest.split_first()?;
+            rest = tail;
+            let block_type = match kind & 3 {
+                0 => BlockType::Stored,
+                1 => BlockType::Static,
+                2 => BlockType::Dynamic,
+                _ => return None,
+            };
+            let flush = TDEFLFlush::new((kind >> 2).into()).ok()?;
+            blocks.push(TracedBlock {
+                input_bytes,
+                block_type,
+                flush,
+            });
+        }
+        let num_choices: usize = read_varint(&mut rest)?.try_into().ok()?;
+        if rest.len() != num_choices.div_ceil(8) {
+            return None;
+        }
+        Some(DecisionTrace {
+            blocks,
+            choices: rest.to_vec(),
+            num_choices,
+        })
+    }
+
+    fn push_choice(&mut self, choice: bool) {
+        if self.num_choices.is_multiple_of(8) {
+            self.choices.push(0);
+        }
+        *self.choices.last_mut().unwrap() |= u8::from(choice) << (self.num_choices % 8);
+        self.num_choices += 1;
+    }
+
+    fn choice(&self, i: usize) -> Option<bool> {
+        (i < self.num_choices).then(|| self.choices[i / 8] >> (i % 8) & 1 == 1)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        v |= u64::from(byte & 0x7F) << shift;
+        if byte < 0x80 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Records or replays the decisions of a compressor.
+#[derive(Clone)]
+pub(crate) enum Tracer {
+    Record(DecisionTrace),
+    Replay {
+        trace: DecisionTrace,
+        /// The next block of the trace.
+        block: usize,
+        /// The next lazy matching choice of the trace.
+        choice: usize,
+        diverged: bool,
+    },
+}
+
+impl Tracer {
+    /// Make a lazy matching choice, where `heuristic` is the choice made without a trace, and
+    /// `allowed` is whether `true` can be chosen.
+    pub(crate) fn choose(&mut self, heuristic: bool, allowed: bool) -> bool {
+        match self {
+            Tracer::Record(trace) => {
+                trace.push_choice(heuristic);
+                heuristic
+            }
+            Tracer::Replay {
+                trace,
+                choice,
+                diverged: false,
+                ..
+            } => match trace.choice(*choice) {
+                Some(traced) if allowed || !traced => {
+                    *choice += 1;
+                    traced
+                }
+                _ => self.diverge(heuristic),
+            },
+            Tracer::Replay { .. } => heuristic,
+        }
+    }
+
+    /// Decide whether to end the block after `total_bytes` bytes, where `heuristic` is the
+    /// decision made without a trace, and `must` is whether the block can't be longer.
+    pub(crate) fn split(&mut self, heuristic: bool, must: bool, total_bytes: u32) -> bool {
+        let Tracer::Replay {
+            trace,
+            block,
+            diverged: false,
+            ..
+        } = self
+        else {
+            return heuristic;
+        };
+        match trace.blocks.get(*block) {
+            Some(traced) if traced.flush == TDEFLFlush::None => {
+                if total_bytes == traced.input_bytes {
+                    true
+                } else if total_bytes < traced.input_bytes && !must {
+                    false
+                } else {
+                    self.diverge(heuristic)
+                }
+            }
+            // The block is ended by a flush.
+            Some(traced) if total_bytes <= traced.input_bytes && !must => false,
+            _ => self.diverge(heuristic),
+        }
+    }
+
+    /// The type of the block of `total_bytes` bytes ended by `flush` that is about to be
+    /// output, if it is decided by the trace. `stored_possible` is whether the block can be
+    /// output as a stored block.
+    pub(crate) fn replay_block(
+        &mut self,
+        flush: TDEFLFlush,
+        total_bytes: u32,
+        stored_possible: bool,
+    ) -> Option<BlockType> {
+        let Tracer::Replay {
+            trace,
+            block,
+            diverged: false,
+            ..
+        } = self
+        else {
+            return None;
+        };
+        match trace.blocks.get(*block) {
+            Some(traced)
+                if traced.flush == flush
+                    && traced.input_bytes == total_bytes
+                    && (traced.block_type != BlockType::Stored || stored_possible) =>
+            {
+                *block += 1;
+                Some(traced.block_type)
+            }
+            _ => self.diverge(None),
+        }
+    }
+
+    /// Add the block that was just output to the trace being recorded.
+    pub(crate) fn record_block(&mut self, block: TracedBlock) {
+        if let Tracer::Record(trace) = self {
+            trace.blocks.push(block);
+        }
+    }
+
+    /// Start over for a new stream.
+    pub(crate) fn reset(&mut self) {
+        match self {
+            Tracer::Record(trace) => *trace = DecisionTrace::default(),
+            Tracer::Replay {
+                block,
+                choice,
+                diverged,
+                ..
+            } => {
+                *block = 0;
+                *choice = 0;
+                *diverged = false;
+            }
+        }
+    }
+
+    /// Stop following the trace, as it can't be followed any more, and return `fallback`.
+    fn diverge<T>(&mut self, fallback: T) -> T {
+        if let Tracer::Replay { diverged, .. } = self {
+            *diverged = true;
+        }
+        fallback
+    }
+}
+
+impl CompressorOxide {
+    /// Start recording the decisions of the compressor in a new [`DecisionTrace`], in place
+    /// of any trace being recorded or replayed.
+    ///
+    /// Must be called before compressing anything. Resetting the compressor starts the trace
+    /// over.
+    pub fn record_trace(&mut self) {
+        self.params.tracer = Some(Box::new(Tracer::Record(DecisionTrace::default())));
+    }
+
+    /// Stop recording or replaying, and return the trace recorded, if it was recording.
+    pub fn take_trace(&mut self) -> Option<DecisionTrace> {
+        match *self.params.tracer.take()? {
+            Tracer::Record(trace) => Some(trace),
+            Tracer::Replay { .. } => None,
+        }
+    }
+
+    /// Take the decisions recorded in `trace` instead of the ones the heuristics would take,
+    /// in place of any trace being recorded or replayed.
+    ///
+    /// Must be called before compressing anything, after setting the level, as the trace only
+    /// fits the stream it was recorded for. Resetting the compressor starts the trace over.
+    ///
+    /// The compressor carries on with its own decisions from the first one it can't take as
+    /// recorded, e.g. as the data or the flushes differ, or as it found different matches.
+    /// [`trace_diverged`](Self::trace_diverged) tells if that happened.
+    pub fn replay_trace(&mut self, trace: DecisionTrace) {
+        self.params.tracer = Some(Box::new(Tracer::Replay {
+            trace,
+            block: 0,
+            choice: 0,
+            diverged: false,
+        }));
+    }
+
+    /// Whether the trace being replayed hit a decision that couldn't be taken as recorded.
+    ///
+    /// Also true if the stream went on after the last block of the trace.
+    pub fn trace_diverged(&self) -> bool {
+        matches!(
+            self.params.tracer.as_deref(),
+            Some(Tracer::Replay { diverged: true, .. })
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deflate::core::{CompressionStrategy, compress, create_comp_flags_from_zip_params};
+    use crate::inflate::decompress_to_vec_zlib;
+    use crate::test_util::{random, text};
+    use alloc::vec;
+
+    /// Text made of a few words, with a run of random bytes every KiB.
+    fn data(len: usize) -> Vec<u8> {
+        let mut data = text(len);
+        let noise = random(len, 256);
+        for start in (0..len).step_by(1024) {
+            let end = len.min(start + 256);
+            data[start..end].copy_from_slice(&noise[start..end]);
+        }
+        data
+    }
+
+    /// Compress `data` in parts of `part` bytes, with a sync flush after every sixth one.
+    fn compress_parts(d: &mut CompressorOxide, data: &[u8], part: usize) -> Vec<u8> {
+        let mut output = vec![0; data.len() * 2 + 1000];
+        let mut out_pos = 0;
+        let parts = data.chunks(part).count();
+        for (i, chunk) in data.chunks(part).enumerate() {
+            let flush = match i {
+                _ if i + 1 == parts => TDEFLFlush::Finish,
+                _ if i % 6 == 5 => TDEFLFlush::Sync,
+                _ => TDEFLFlush::None,
+            };
+            let (_, in_pos, written) = compress(d, chunk, &mut output[out_pos..], flush);
+            assert_eq!(in_pos, chunk.len());
+            out_pos += written;
+        }
+        output.truncate(out_pos);
+        output
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let data = data(600_000);
+        for level in [1, 3, 6, 9, 11] {
+            let mut d = CompressorOxide::default();
+            d.set_compression_level_raw(level);
+            d.record_trace();
+            let recorded = compress_parts(&mut d, &data, 40_000);
+            let trace = d.take_trace().unwrap();
+            // Some blocks are ended by the compressor rather than by a flush.
+            assert!(trace.blocks().iter().any(|b| b.flush == TDEFLFlush::None));
+            assert_eq!(
+                trace
+                    .blocks()
+                    .iter()
+                    .map(|b| u64::from(b.input_bytes))
+                    .sum::<u64>(),
+                data.len() as u64
+            );
+            assert_eq!(trace.num_choices() > 0, (2..=10).contains(&level));
+            assert_eq!(
+                DecisionTrace::from_bytes(&trace.to_bytes()),
+                Some(trace.clone())
+            );
+
+            let mut d = CompressorOxide::default();
+            d.set_compression_level_raw(level);
+            d.replay_trace(trace.clone());
+            assert_eq!(compress_parts(&mut d, &data, 40_000), recorded);
+            assert!(!d.trace_diverged());
+
+            // Resetting starts the trace over.
+            d.reset();
+            d.set_compression_level_raw(level);
+            assert_eq!(compress_parts(&mut d, &data, 40_000), recorded);
+            assert!(!d.trace_diverged());
+        }
+    }
+
+    #[test]
+    fn replay_overrides_heuristics() {
+        let data = data(200_000);
+        let mut d = CompressorOxide::default();
+        d.set_compression_level_raw(6);
+        d.record_trace();
+        let recorded = compress_parts(&mut d, &data, 200_000);
+        let trace = d.take_trace().unwrap();
+
+        // The lazy matching choices and the block ends of level 6 with the matches of level 9,
+        // which looks further for them.
+        let mut d = CompressorOxide::default();
+        d.set_compression_level_raw(9);
+        d.replay_trace(trace.clone());
+        let replayed = compress_parts(&mut d, &data, 200_000);
+        assert_eq!(decompress_to_vec_zlib(&replayed).unwrap(), data);
+        assert_ne!(replayed, recorded);
+
+        // Filtered matches give other choices from the start.
+        let flags = create_comp_flags_from_zip_params(6, 15, CompressionStrategy::Filtered as i32);
+        let mut d = CompressorOxide::new(flags);
+        d.replay_trace(trace);
+        let replayed = compress_parts(&mut d, &data, 200_000);
+        assert_eq!(decompress_to_vec_zlib(&replayed).unwrap(), data);
+        assert!(d.trace_diverged());
+    }
+
+    #[test]
+    fn rejects_bad_bytes() {
+        let mut d = CompressorOxide::default();
+        d.record_trace();
+        compress_parts(&mut d, &data(50_000), 10_000);
+        let bytes = d.take_trace().unwrap().to_bytes();
+        assert!(DecisionTrace::from_bytes(&bytes).is_some());
+        assert!(DecisionTrace::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(DecisionTrace::from_bytes(&[&bytes[..], &[0]].concat()).is_none());
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] += 1;
+        assert!(DecisionTrace::from_bytes(&wrong_version).is_none());
+    }
+}
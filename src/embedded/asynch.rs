@@ -0,0 +1,225 @@
+//! Compression and decompression through the async [`embedded_io_async`] traits, for async
+//! executors on embedded targets such as embassy.
+//!
+//! These work as [`DeflateWriter`][super::DeflateWriter] and
+//! [`InflateReader`][super::InflateReader], with the same buffers, only awaiting the wrapped
+//! writer or reader instead of blocking on it.
+
+use embedded_io_async::{ErrorType, Read, Write};
+
+use super::{Decoder, Encoder, Error};
+use crate::deflate::CompressionLevel;
+use crate::{DataFormat, MZFlush};
+
+/// A writer that compresses everything written to it, and writes the compressed data to the
+/// wrapped writer.
+///
+/// [`finish`][Self::finish] must be called at the end to write the rest of the compressed
+/// data, it isn't done on drop as the errors couldn't be reported.
+pub struct DeflateWriter<W> {
+    inner: W,
+    encoder: Encoder,
+}
+
+impl<W: Write> DeflateWriter<W> {
+    /// Create a writer that compresses to `inner` in the given format.
+    pub fn new(inner: W, compression_level: CompressionLevel, data_format: DataFormat) -> Self {
+        DeflateWriter {
+            inner,
+            encoder: Encoder::new(compression_level, data_format),
+        }
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    ///
+    /// Writing to it directly will corrupt the compressed data.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Write the rest of the compressed data, ending the stream, and return the wrapped writer.
+    pub async fn finish(mut self) -> Result<W, Error<W::Error>> {
+        while !self.run(&[], MZFlush::Finish).await?.1 {}
+        self.inner.flush().await.map_err(Error::Io)?;
+        Ok(self.inner)
+    }
+
+    /// Compress `input` with `flush`, and write the output to the wrapped writer.
+    async fn run(
+        &mut self,
+        input: &[u8],
+        flush: MZFlush,
+    ) -> Result<(usize, bool), Error<W::Error>> {
+        let (consumed, written, done) = self.encoder.run(input, flush)?;
+        self.inner
+            .write_all(&self.encoder.buffer[..written])
+            .await
+            .map_err(Error::Io)?;
+        Ok((consumed, done))
+    }
+}
+
+impl<W: Write> ErrorType for DeflateWriter<W> {
+    type Error = Error<W::Error>;
+}
+
+impl<W: Write> Write for DeflateWriter<W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // The output buffer may fill up before any input is taken.
+            let (consumed, _) = self.run(buf, MZFlush::None).await?;
+            if consumed > 0 {
+                return Ok(consumed);
+            }
+        }
+    }
+
+    /// Write out everything written so far with a sync flush, so it can be decompressed
+    /// without the rest of the stream, and flush the wrapped writer.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        while !self.run(&[], MZFlush::Sync).await?.1 {}
+        self.inner.flush().await.map_err(Error::Io)
+    }
+}
+
+/// A reader that decompresses the data read from the wrapped reader.
+///
+/// Data after the end of the compressed stream may have been read from the wrapped reader,
+/// but is ignored.
+pub struct InflateReader<R> {
+    inner: R,
+    decoder: Decoder,
+}
+
+impl<R: Read> InflateReader<R> {
+    /// Create a reader that decompresses the data in the given format from `inner`.
+    pub fn new(inner: R, data_format: DataFormat) -> Self {
+        InflateReader {
+            inner,
+            decoder: Decoder::new(data_format),
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Return the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> ErrorType for InflateReader<R> {
+    type Error = Error<R::Error>;
+}
+
+impl<R: Read> Read for InflateReader<R> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        while self.decoder.is_empty() {
+            if self.decoder.is_done || buf.is_empty() {
+                return Ok(0);
+            }
+            if let Some(input) = self.decoder.input_buf() {
+                let len = self.inner.read(input).await.map_err(Error::Io)?;
+                self.decoder.input_read(len);
+            }
+            self.decoder.fill()?;
+        }
+        Ok(self.decoder.copy_to(buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeflateWriter, InflateReader};
+    use crate::DataFormat;
+    use crate::deflate::CompressionLevel;
+    use crate::embedded::Error;
+    use crate::inflate::TINFLStatus;
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use embedded_io_async::{ErrorType, Read, Write};
+
+    /// Run a future whose IO is always ready to completion, without an executor.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    struct VecWriter(Vec<u8>);
+
+    impl ErrorType for VecWriter {
+        type Error = Infallible;
+    }
+
+    impl Write for VecWriter {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    async fn read_to_end<R: Read>(mut reader: R) -> Result<Vec<u8>, R::Error> {
+        let mut out = Vec::new();
+        let mut buf = [0; 1000];
+        loop {
+            match reader.read(&mut buf).await? {
+                0 => return Ok(out),
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = crate::test_util::text(200_000);
+        block_on(async {
+            for format in [DataFormat::Raw, DataFormat::Zlib] {
+                let mut writer = DeflateWriter::new(
+                    VecWriter(Vec::new()),
+                    CompressionLevel::DefaultLevel,
+                    format,
+                );
+                for chunk in data.chunks(7_777) {
+                    writer.write_all(chunk).await.unwrap();
+                }
+                writer.flush().await.unwrap();
+                let compressed = writer.finish().await.unwrap().0;
+
+                let reader = InflateReader::new(&compressed[..], format);
+                assert!(read_to_end(reader).await.unwrap() == data);
+            }
+        });
+    }
+
+    #[test]
+    fn errors() {
+        let data = [7u8; 1000];
+        let compressed = crate::deflate::compress_to_vec_zlib(&data, 6);
+        block_on(async {
+            let reader = InflateReader::new(&compressed[..compressed.len() - 4], DataFormat::Zlib);
+            assert!(matches!(
+                read_to_end(reader).await,
+                Err(Error::Decompress(TINFLStatus::FailedCannotMakeProgress))
+            ));
+        });
+    }
+}
@@ -0,0 +1,406 @@
+//! Compression and decompression through the blocking [`embedded_io`] traits, e.g. to compress
+//! logs or decompress firmware updates on a microcontroller.
+//!
+//! [`DeflateWriter`] compresses everything written to it into another writer, and
+//! [`InflateReader`] decompresses the data read from another reader. The [`asynch`] module has
+//! the same for the async traits of [`embedded_io_async`], e.g. for embassy.
+
+use crate::alloc::boxed::Box;
+use crate::alloc::vec;
+use embedded_io::{ErrorKind, ErrorType, Read, Write};
+
+use crate::deflate::CompressionLevel;
+use crate::deflate::core::{CompressorOxide, TDEFLStatus};
+use crate::deflate::stream::deflate;
+use crate::inflate::core::{DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress, inflate_flags};
+use crate::inflate::{TINFLStatus, format_flags};
+use crate::shared::ScratchBuffer;
+use crate::{DataFormat, MZFlush, MZStatus};
+
+#[cfg(feature = "embedded-io-async")]
+pub mod asynch;
+
+/// Size of the buffer for compressed data in [`DeflateWriter`] and [`InflateReader`].
+const BUFFER_SIZE: usize = 4 * 1024;
+
+/// Errors from [`DeflateWriter`] and [`InflateReader`], and their async counterparts.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error<E> {
+    /// Reading from or writing to the wrapped reader or writer failed.
+    Io(E),
+    /// The compressed data failed to decompress, with the status of the decompressor, e.g.
+    /// [`TINFLStatus::FailedCannotMakeProgress`] for truncated data.
+    Decompress(TINFLStatus),
+    /// The compressor stopped with the given status, e.g. [`TDEFLStatus::BadParam`] when it
+    /// was used after an earlier failure.
+    Compress(TDEFLStatus),
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(err) => err.kind(),
+            Error::Decompress(TINFLStatus::BadParam) => ErrorKind::InvalidInput,
+            Error::Decompress(_) => ErrorKind::InvalidData,
+            Error::Compress(_) => ErrorKind::Other,
+        }
+    }
+}
+
+/// The compressor and its output buffer, without the writer, so it is shared by the blocking
+/// and async writers.
+struct Encoder {
+    compressor: Box<CompressorOxide>,
+    buffer: Box<[u8]>,
+}
+
+impl Encoder {
+    fn new(compression_level: CompressionLevel, data_format: DataFormat) -> Self {
+        Encoder {
+            compressor: CompressorOxide::builder()
+                .format(data_format)
+                .compression_level(compression_level)
+                .build_boxed(),
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+        }
+    }
+
+    /// Compress `input` with `flush` into the buffer.
+    ///
+    /// Returns the number of bytes consumed, the number of bytes written to the buffer, and
+    /// whether the buffer wasn't filled, i.e. the compressor has nothing more to write for
+    /// now, or the stream ended.
+    fn run<E>(&mut self, input: &[u8], flush: MZFlush) -> Result<(usize, usize, bool), Error<E>> {
+        let res = deflate(&mut self.compressor, input, &mut self.buffer, flush);
+        match res.status {
+            Ok(status) => Ok((
+                res.bytes_consumed,
+                res.bytes_written,
+                status == MZStatus::StreamEnd
+                    || (flush != MZFlush::Finish && res.bytes_written < self.buffer.len()),
+            )),
+            Err(_) => Err(Error::Compress(self.compressor.prev_return_status())),
+        }
+    }
+}
+
+/// A writer that compresses everything written to it, and writes the compressed data to the
+/// wrapped writer.
+///
+/// [`finish`][Self::finish] must be called at the end to write the rest of the compressed
+/// data, it isn't done on drop as the errors couldn't be reported.
+pub struct DeflateWriter<W> {
+    inner: W,
+    encoder: Encoder,
+}
+
+impl<W: Write> DeflateWriter<W> {
+    /// Create a writer that compresses to `inner` in the given format.
+    pub fn new(inner: W, compression_level: CompressionLevel, data_format: DataFormat) -> Self {
+        DeflateWriter {
+            inner,
+            encoder: Encoder::new(compression_level, data_format),
+        }
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    ///
+    /// Writing to it directly will corrupt the compressed data.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Write the rest of the compressed data, ending the stream, and return the wrapped writer.
+    pub fn finish(mut self) -> Result<W, Error<W::Error>> {
+        while !self.run(&[], MZFlush::Finish)?.1 {}
+        self.inner.flush().map_err(Error::Io)?;
+        Ok(self.inner)
+    }
+
+    /// Compress `input` with `flush`, and write the output to the wrapped writer.
+    ///
+    /// Returns the number of bytes consumed, and whether the compressor has nothing more to
+    /// write for now, as [`Encoder::run`].
+    fn run(&mut self, input: &[u8], flush: MZFlush) -> Result<(usize, bool), Error<W::Error>> {
+        let (consumed, written, done) = self.encoder.run(input, flush)?;
+        self.inner
+            .write_all(&self.encoder.buffer[..written])
+            .map_err(Error::Io)?;
+        Ok((consumed, done))
+    }
+}
+
+impl<W: Write> ErrorType for DeflateWriter<W> {
+    type Error = Error<W::Error>;
+}
+
+impl<W: Write> Write for DeflateWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // The output buffer may fill up before any input is taken.
+            let (consumed, _) = self.run(buf, MZFlush::None)?;
+            if consumed > 0 {
+                return Ok(consumed);
+            }
+        }
+    }
+
+    /// Write out everything written so far with a sync flush, so it can be decompressed
+    /// without the rest of the stream, and flush the wrapped writer.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        while !self.run(&[], MZFlush::Sync)?.1 {}
+        self.inner.flush().map_err(Error::Io)
+    }
+}
+
+/// The decompressor with its input buffer and window, without the reader, so it is shared by
+/// the blocking and async readers.
+struct Decoder {
+    decomp: Box<DecompressorOxide>,
+    flags: u32,
+    /// The compressed data read so far, with the part not decompressed yet at `in_pos..in_end`.
+    input: Box<[u8]>,
+    in_pos: usize,
+    in_end: usize,
+    is_eof: bool,
+    /// The decompressed data, with the part not read yet at `out_pos..out_end`.
+    window: ScratchBuffer,
+    out_pos: usize,
+    out_end: usize,
+    is_done: bool,
+}
+
+impl Decoder {
+    fn new(data_format: DataFormat) -> Self {
+        Decoder {
+            decomp: Box::default(),
+            flags: format_flags(data_format),
+            input: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            in_pos: 0,
+            in_end: 0,
+            is_eof: false,
+            window: ScratchBuffer::new(TINFL_LZ_DICT_SIZE),
+            out_pos: 0,
+            out_end: 0,
+            is_done: false,
+        }
+    }
+
+    /// Whether there is no decompressed data left to read, and [`fill`][Self::fill] has to be
+    /// called, or the stream ended if `is_done` is set.
+    fn is_empty(&self) -> bool {
+        self.out_pos == self.out_end
+    }
+
+    /// The buffer to read more compressed data into before calling [`fill`][Self::fill], if
+    /// all of the input read so far was used.
+    fn input_buf(&mut self) -> Option<&mut [u8]> {
+        if self.in_pos == self.in_end && !self.is_eof {
+            Some(&mut self.input)
+        } else {
+            None
+        }
+    }
+
+    /// Record that `len` bytes were read into [`input_buf`][Self::input_buf].
+    fn input_read(&mut self, len: usize) {
+        self.in_pos = 0;
+        self.in_end = len;
+        self.is_eof = len == 0;
+    }
+
+    /// Decompress more data into the window.
+    fn fill<E>(&mut self) -> Result<(), Error<E>> {
+        if self.out_end == self.window.len() {
+            self.out_pos = 0;
+            self.out_end = 0;
+        }
+
+        let more_input = if self.is_eof {
+            0
+        } else {
+            inflate_flags::TINFL_FLAG_HAS_MORE_INPUT
+        };
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut self.decomp,
+            &self.input[self.in_pos..self.in_end],
+            &mut self.window,
+            self.out_end,
+            self.flags | more_input,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        self.in_pos += in_consumed;
+        self.out_end += out_consumed;
+
+        match status {
+            TINFLStatus::Done => self.is_done = true,
+            TINFLStatus::HasMoreOutput => {}
+            // All the input was used, so more is read next time.
+            TINFLStatus::NeedsMoreInput if !self.is_eof => {}
+            status => return Err(Error::Decompress(status)),
+        }
+        Ok(())
+    }
+
+    /// Copy as much of the decompressed data as fits into `buf`.
+    fn copy_to(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.out_end - self.out_pos);
+        buf[..len].copy_from_slice(&self.window[self.out_pos..self.out_pos + len]);
+        self.out_pos += len;
+        len
+    }
+}
+
+/// A reader that decompresses the data read from the wrapped reader.
+///
+/// Data after the end of the compressed stream may have been read from the wrapped reader,
+/// but is ignored.
+pub struct InflateReader<R> {
+    inner: R,
+    decoder: Decoder,
+}
+
+impl<R: Read> InflateReader<R> {
+    /// Create a reader that decompresses the data in the given format from `inner`.
+    pub fn new(inner: R, data_format: DataFormat) -> Self {
+        InflateReader {
+            inner,
+            decoder: Decoder::new(data_format),
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Return the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> ErrorType for InflateReader<R> {
+    type Error = Error<R::Error>;
+}
+
+impl<R: Read> Read for InflateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        while self.decoder.is_empty() {
+            if self.decoder.is_done || buf.is_empty() {
+                return Ok(0);
+            }
+            if let Some(input) = self.decoder.input_buf() {
+                let len = self.inner.read(input).map_err(Error::Io)?;
+                self.decoder.input_read(len);
+            }
+            self.decoder.fill()?;
+        }
+        Ok(self.decoder.copy_to(buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeflateWriter, Error, InflateReader};
+    use crate::DataFormat;
+    use crate::deflate::CompressionLevel;
+    use crate::inflate::TINFLStatus;
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+    use embedded_io::{ErrorType, Read, Write};
+
+    struct VecWriter(Vec<u8>);
+
+    impl ErrorType for VecWriter {
+        type Error = Infallible;
+    }
+
+    impl Write for VecWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn read_to_end<R: Read>(mut reader: R) -> Result<Vec<u8>, R::Error> {
+        let mut out = Vec::new();
+        let mut buf = [0; 1000];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(out),
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = crate::test_util::text(200_000);
+        for format in [DataFormat::Raw, DataFormat::Zlib] {
+            let mut writer = DeflateWriter::new(
+                VecWriter(Vec::new()),
+                CompressionLevel::DefaultLevel,
+                format,
+            );
+            for chunk in data.chunks(7_777) {
+                writer.write_all(chunk).unwrap();
+            }
+            let compressed = writer.finish().unwrap().0;
+
+            let reader = InflateReader::new(&compressed[..], format);
+            assert!(read_to_end(reader).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let mut writer = DeflateWriter::new(
+            VecWriter(Vec::new()),
+            CompressionLevel::DefaultLevel,
+            DataFormat::Raw,
+        );
+        writer.write_all(b"Hello, flush!").unwrap();
+        writer.flush().unwrap();
+        // Everything so far can be decompressed before the end of the stream.
+        let compressed = writer.get_ref().0.clone();
+        let mut reader = InflateReader::new(&compressed[..], DataFormat::Raw);
+        let mut buf = [0; 13];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"Hello, flush!");
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let data = [7u8; 1000];
+        let compressed = crate::deflate::compress_to_vec_zlib(&data, 6);
+
+        let reader = InflateReader::new(&compressed[..compressed.len() - 4], DataFormat::Zlib);
+        assert!(matches!(
+            read_to_end(reader),
+            Err(Error::Decompress(TINFLStatus::FailedCannotMakeProgress))
+        ));
+        let reader = InflateReader::new(&compressed[..], DataFormat::Raw);
+        assert!(matches!(
+            read_to_end(reader),
+            Err(Error::Decompress(TINFLStatus::Failed))
+        ));
+    }
+}
@@ -1,8 +1,15 @@
+use crate::gzip::GzipError;
+use crate::inflate::core::DecompressorOxide;
+use crate::inflate::{Adler32Mismatch, TINFLStatus, ZlibHeaderError};
 use thiserror::Error;
 
+/// Errors from the streaming functions that read or write through IO traits.
+///
+/// New variants may be added, e.g. for new kinds of limits, so matches need a wildcard arm.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("{0}")]
+    #[error(transparent)]
     Err(Box<dyn std::error::Error + Send + Sync + 'static>),
     #[error("{0}")]
     Msg(String),
@@ -10,4 +17,53 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error(transparent)]
     BinError(#[from] binrw::Error),
+    /// The compressed data failed to decompress, with the status of the decompressor, e.g.
+    /// [`TINFLStatus::FailedCannotMakeProgress`] for truncated data.
+    #[error("Failed to decompress: {0:?}")]
+    Decompress(TINFLStatus),
+    /// The decompressed data doesn't match the adler32 checksum at the end of the zlib stream.
+    #[error(transparent)]
+    Adler32Mismatch(Adler32Mismatch),
+    /// The zlib header is invalid.
+    #[error(transparent)]
+    ZlibHeader(ZlibHeaderError),
+    /// The stream needs a preset dictionary, which is not supported here.
+    #[error("A preset dictionary is needed")]
+    NeedDict,
+    /// The gzip header or trailer is invalid, or the data doesn't match the trailer.
+    #[error(transparent)]
+    Gzip(GzipError),
+}
+
+impl Error {
+    /// The error for decompression with `decomp` stopping with `status`, with the checksums if
+    /// they didn't match, or what is wrong with the zlib header if it is invalid.
+    pub(crate) fn decompress(decomp: &DecompressorOxide, status: TINFLStatus) -> Self {
+        match (
+            status,
+            decomp.adler32_mismatch(),
+            decomp.zlib_header_error(),
+        ) {
+            (TINFLStatus::Adler32Mismatch, Some(mismatch), _) => Error::Adler32Mismatch(mismatch),
+            (TINFLStatus::Failed, _, Some(err)) => Error::ZlibHeader(err),
+            _ => Error::Decompress(status),
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    /// Unwrap IO errors, and keep the others as the source of an IO error, so the cause of a
+    /// failure in a `Read` or `Write` implementation is not lost.
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::Gzip(err) => return err.into(),
+            Error::Decompress(status) => status.io_error_kind(),
+            Error::Adler32Mismatch(_) | Error::ZlibHeader(_) | Error::NeedDict => {
+                std::io::ErrorKind::InvalidData
+            }
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
+    }
 }
@@ -0,0 +1,881 @@
+//! Support for the [gzip](https://www.rfc-editor.org/rfc/rfc1952) wrapper.
+//!
+//! [`compress_to_vec_gzip`] and [`decompress_to_vec_gzip`] work like their zlib counterparts,
+//! and [`GzHeader`] holds the metadata of the header: the file name, comment, modification
+//! time and so on.
+//!
+//! [`recompress_gzip`] compresses gzip data again at another level, keeping the headers.
+//!
+//! [`compress_to_vec_with_range_map`] compresses data to a gzip stream with a full flush every
+//! so many bytes, and a [`RangeMap`] of where those points are. The deflate data after each
+//! point doesn't depend on anything before it, so a server can keep files compressed and
+//! still answer HTTP `Range` requests for the uncompressed content by reading and
+//! decompressing only the part of the file around the range.
+
+use crate::alloc::vec;
+use crate::alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::deflate::core::{
+    CompressionStrategy, CompressorOxide, TDEFLFlush, TDEFLStatus, compress_to_output,
+    create_comp_flags_from_zip_params,
+};
+use crate::inflate::core::{DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress, inflate_flags};
+use crate::inflate::{DecompressError, TINFLStatus, decompress_error};
+use crate::shared::update_crc32;
+use thiserror::Error;
+
+/// The two magic bytes at the start of a gzip member.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+/// Compression method for deflate, the only one defined.
+const CM_DEFLATE: u8 = 8;
+/// Extra flags value for the slowest compression.
+const XFL_BEST: u8 = 2;
+/// Extra flags value for the fastest compression.
+const XFL_FAST: u8 = 4;
+/// Operating system field value for "unknown".
+const OS_UNKNOWN: u8 = 255;
+/// Size of a gzip header without optional fields.
+const HEADER_LEN: usize = 10;
+/// Size of the CRC-32 and length trailer.
+const TRAILER_LEN: usize = 8;
+
+/// Magic bytes at the start of [`RangeMap::to_bytes()`].
+const RANGE_MAP_MAGIC: [u8; 4] = *b"MZRM";
+
+/// Header flag: the data is probably text.
+const FTEXT: u8 = 1 << 0;
+/// Header flag: the header ends with a CRC16.
+const FHCRC: u8 = 1 << 1;
+/// Header flag: the header has an extra field.
+const FEXTRA: u8 = 1 << 2;
+/// Header flag: the header has a zero-terminated file name.
+const FNAME: u8 = 1 << 3;
+/// Header flag: the header has a zero-terminated comment.
+const FCOMMENT: u8 = 1 << 4;
+/// Header flags that must be zero.
+const FRESERVED: u8 = 0b1110_0000;
+
+/// Errors from decompressing gzip data.
+#[derive(Debug, Error)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum GzipError {
+    /// The input ended before the end of the header or the trailer.
+    #[error("Truncated gzip header or trailer")]
+    Truncated,
+    /// The header doesn't start with the gzip magic bytes.
+    #[error("Not gzip data: starts with {0:02x?}")]
+    BadMagic([u8; 2]),
+    /// The compression method in the header is not 8 (deflate).
+    #[error("Unsupported gzip compression method {0}")]
+    BadCompressionMethod(u8),
+    /// The header flags byte has reserved bits set.
+    #[error("Unsupported gzip header flags {0:#04x}")]
+    UnsupportedGzipFlag(u8),
+    /// The header is longer than a decompressor reading it from a stream accepts.
+    #[error("Gzip header too long")]
+    HeaderTooLong,
+    /// The CRC16 of the header doesn't match the header.
+    #[error("Gzip header CRC mismatch: expected {expected:#06x}, computed {computed:#06x}")]
+    HeaderCrcMismatch { expected: u16, computed: u16 },
+    /// The deflate data failed to decompress.
+    #[error("Failed to decompress the gzip data")]
+    Deflate(#[from] DecompressError),
+    /// The CRC-32 in the trailer doesn't match the decompressed data.
+    #[error("Gzip CRC-32 mismatch: expected {expected:#010x}, computed {computed:#010x}")]
+    CrcMismatch { expected: u32, computed: u32 },
+    /// The length in the trailer doesn't match the length of the decompressed data.
+    #[error("Gzip length mismatch")]
+    LengthMismatch,
+}
+
+#[cfg(feature = "std")]
+impl From<GzipError> for std::io::Error {
+    /// Create an IO error of a matching kind, with the error as its source.
+    fn from(err: GzipError) -> Self {
+        let kind = match &err {
+            GzipError::Truncated => std::io::ErrorKind::UnexpectedEof,
+            GzipError::Deflate(err) => err.status.io_error_kind(),
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+/// The metadata in a gzip header.
+///
+/// The extra flags byte isn't kept, it is written from the compression level.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GzHeader {
+    /// Modification time of the original file as a unix timestamp, or 0 if unknown.
+    pub mtime: u32,
+    /// Operating system the file was compressed on, 255 if unknown.
+    pub os: u8,
+    /// Whether the data is probably text.
+    pub text: bool,
+    /// The extra field. Only the first 65535 bytes are written.
+    pub extra: Option<Vec<u8>>,
+    /// The original file name, in ISO 8859-1. Only the bytes before the first zero byte are
+    /// written.
+    pub filename: Option<Vec<u8>>,
+    /// A comment, in ISO 8859-1. Only the bytes before the first zero byte are written.
+    pub comment: Option<Vec<u8>>,
+    /// Whether the header ends with a CRC16 of the header (the `FHCRC` flag).
+    pub header_crc: bool,
+}
+
+impl Default for GzHeader {
+    fn default() -> Self {
+        GzHeader {
+            mtime: 0,
+            os: OS_UNKNOWN,
+            text: false,
+            extra: None,
+            filename: None,
+            comment: None,
+            header_crc: false,
+        }
+    }
+}
+
+impl GzHeader {
+    /// Append the header for data compressed at `level` to `out`.
+    fn write(&self, level: u8, out: &mut Vec<u8>) {
+        let start = out.len();
+        let xfl = match level {
+            1 => XFL_FAST,
+            9.. => XFL_BEST,
+            _ => 0,
+        };
+        let mut flags = 0;
+        for (flag, set) in [
+            (FTEXT, self.text),
+            (FHCRC, self.header_crc),
+            (FEXTRA, self.extra.is_some()),
+            (FNAME, self.filename.is_some()),
+            (FCOMMENT, self.comment.is_some()),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        out.extend_from_slice(&GZIP_MAGIC);
+        out.extend_from_slice(&[CM_DEFLATE, flags]);
+        out.extend_from_slice(&self.mtime.to_le_bytes());
+        out.extend_from_slice(&[xfl, self.os]);
+        if let Some(extra) = &self.extra {
+            let extra = &extra[..extra.len().min(usize::from(u16::MAX))];
+            out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            out.extend_from_slice(extra);
+        }
+        for field in [&self.filename, &self.comment].into_iter().flatten() {
+            out.extend(field.iter().take_while(|&&byte| byte != 0));
+            out.push(0);
+        }
+        if self.header_crc {
+            let crc = update_crc32(0, &out[start..]) as u16;
+            out.extend_from_slice(&crc.to_le_bytes());
+        }
+    }
+
+    /// Parse the gzip header at the start of `input`, and verify its CRC16 if it has one.
+    ///
+    /// Returns the header and its length in bytes.
+    pub fn parse(input: &[u8]) -> Result<(GzHeader, usize), GzipError> {
+        let fixed = input.get(..HEADER_LEN).ok_or(GzipError::Truncated)?;
+        let flags = fixed[3];
+        if fixed[..2] != GZIP_MAGIC {
+            return Err(GzipError::BadMagic([fixed[0], fixed[1]]));
+        }
+        if fixed[2] != CM_DEFLATE {
+            return Err(GzipError::BadCompressionMethod(fixed[2]));
+        }
+        if flags & FRESERVED != 0 {
+            return Err(GzipError::UnsupportedGzipFlag(flags));
+        }
+        let mut header = GzHeader {
+            mtime: u32::from_le_bytes(fixed[4..8].try_into().unwrap()),
+            os: fixed[9],
+            text: flags & FTEXT != 0,
+            extra: None,
+            filename: None,
+            comment: None,
+            header_crc: flags & FHCRC != 0,
+        };
+
+        let mut pos = HEADER_LEN;
+        if flags & FEXTRA != 0 {
+            let len = input.get(pos..pos + 2).ok_or(GzipError::Truncated)?;
+            let len = usize::from(u16::from_le_bytes([len[0], len[1]]));
+            let extra = input.get(pos + 2..pos + 2 + len);
+            header.extra = Some(extra.ok_or(GzipError::Truncated)?.to_vec());
+            pos += 2 + len;
+        }
+        for (flag, field) in [
+            (FNAME, &mut header.filename),
+            (FCOMMENT, &mut header.comment),
+        ] {
+            if flags & flag != 0 {
+                let len = input[pos..]
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or(GzipError::Truncated)?;
+                *field = Some(input[pos..pos + len].to_vec());
+                pos += len + 1;
+            }
+        }
+        if header.header_crc {
+            let crc = input.get(pos..pos + 2).ok_or(GzipError::Truncated)?;
+            let expected = u16::from_le_bytes([crc[0], crc[1]]);
+            let computed = update_crc32(0, &input[..pos]) as u16;
+            if expected != computed {
+                return Err(GzipError::HeaderCrcMismatch { expected, computed });
+            }
+            pos += 2;
+        }
+        Ok((header, pos))
+    }
+}
+
+/// A point of a [`RangeMap`] where decompression can start without any earlier data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RangePoint {
+    /// Offset in the uncompressed data.
+    pub uncompressed: u64,
+    /// Offset of the matching deflate data in the gzip file.
+    pub compressed: u64,
+}
+
+/// Map from ranges of uncompressed data to ranges of a gzip file created by
+/// [`compress_to_vec_with_range_map`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeMap {
+    /// The start of the deflate data, then one point per full flush, in order.
+    points: Vec<RangePoint>,
+    uncompressed_len: u64,
+    /// Length of the gzip file, including the trailer.
+    compressed_len: u64,
+}
+
+/// Where to find a range of uncompressed data in a gzip file, from [`RangeMap::locate`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MappedRange {
+    /// The bytes of the gzip file to read.
+    pub compressed: Range<u64>,
+    /// How many bytes the decompressed data of that part starts before the range.
+    pub skip: u64,
+    /// Length of the range, possibly shortened to the end of the data.
+    pub len: u64,
+}
+
+impl RangeMap {
+    /// Version tag written after the magic bytes by [`RangeMap::to_bytes()`].
+    pub const ENCODING_VERSION: u8 = 1;
+
+    /// Get the points decompression can start from, beginning with the start of the deflate
+    /// data.
+    pub fn points(&self) -> &[RangePoint] {
+        &self.points
+    }
+
+    /// Get the length of the uncompressed data.
+    pub const fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// Get the length of the gzip file.
+    pub const fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+
+    /// Find the part of the gzip file to read to get the uncompressed bytes in `range`.
+    ///
+    /// The end of the range is cut to the end of the data. Returns `None` if the range is
+    /// empty or starts past the end of the data.
+    pub fn locate(&self, range: Range<u64>) -> Option<MappedRange> {
+        let end = range.end.min(self.uncompressed_len);
+        if range.start >= end {
+            return None;
+        }
+        // The first point always has offset 0.
+        let first = self
+            .points
+            .partition_point(|point| point.uncompressed <= range.start)
+            - 1;
+        let start = self.points[first];
+        // Stop at the first point at or after the end, or after the last block of the data.
+        let stop = self.points[first + 1..]
+            .iter()
+            .find(|point| point.uncompressed >= end)
+            .map_or(self.compressed_len - TRAILER_LEN as u64, |point| {
+                point.compressed
+            });
+        Some(MappedRange {
+            compressed: start.compressed..stop,
+            skip: range.start - start.uncompressed,
+            len: end - range.start,
+        })
+    }
+
+    /// Serialize the map, e.g. to store it next to the gzip file.
+    ///
+    /// The format is the magic bytes `MZRM`, [`Self::ENCODING_VERSION`], the uncompressed and
+    /// compressed lengths as little endian `u64`s, the number of points as a little endian
+    /// `u32`, then the offsets of each point as two little endian `u64`s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(25 + self.points.len() * 16);
+        out.extend_from_slice(&RANGE_MAP_MAGIC);
+        out.push(Self::ENCODING_VERSION);
+        out.extend_from_slice(&self.uncompressed_len.to_le_bytes());
+        out.extend_from_slice(&self.compressed_len.to_le_bytes());
+        out.extend_from_slice(&(self.points.len() as u32).to_le_bytes());
+        for point in &self.points {
+            out.extend_from_slice(&point.uncompressed.to_le_bytes());
+            out.extend_from_slice(&point.compressed.to_le_bytes());
+        }
+        out
+    }
+
+    /// Deserialize a map written by [`RangeMap::to_bytes()`].
+    ///
+    /// Returns `None` if the bytes are not a valid map.
+    pub fn from_bytes(bytes: &[u8]) -> Option<RangeMap> {
+        let (head, rest) = bytes.split_at_checked(25)?;
+        if head[..4] != RANGE_MAP_MAGIC || head[4] != Self::ENCODING_VERSION {
+            return None;
+        }
+        let uncompressed_len = u64::from_le_bytes(head[5..13].try_into().ok()?);
+        let compressed_len = u64::from_le_bytes(head[13..21].try_into().ok()?);
+        let count = u32::from_le_bytes(head[21..25].try_into().ok()?) as usize;
+        if rest.len() != count.checked_mul(16)? {
+            return None;
+        }
+        let points: Vec<RangePoint> = rest
+            .chunks_exact(16)
+            .map(|chunk| RangePoint {
+                uncompressed: u64::from_le_bytes(chunk[..8].try_into().unwrap()),
+                compressed: u64::from_le_bytes(chunk[8..].try_into().unwrap()),
+            })
+            .collect();
+
+        // `locate` relies on the points starting at 0 and increasing, within the lengths.
+        let first = points.first()?;
+        let valid = first.uncompressed == 0
+            && first.compressed == HEADER_LEN as u64
+            && points.windows(2).all(|pair| {
+                pair[0].uncompressed < pair[1].uncompressed
+                    && pair[0].compressed < pair[1].compressed
+            })
+            && points.last().is_some_and(|last| {
+                last.uncompressed <= uncompressed_len
+                    && last.compressed <= compressed_len.saturating_sub(TRAILER_LEN as u64)
+            });
+        valid.then_some(RangeMap {
+            points,
+            uncompressed_len,
+            compressed_len,
+        })
+    }
+}
+
+impl MappedRange {
+    /// Decompress the range from `compressed`, the bytes of the gzip file in
+    /// [`self.compressed`](Self::compressed).
+    ///
+    /// The checksum in the trailer covers the whole file, so it is not checked.
+    ///
+    /// Returns a [`Result`] containing the [`Vec`] of the bytes in the range on success, and a
+    /// [struct][DecompressError] containing the status and so far decompressed data on failure.
+    pub fn decompress(&self, compressed: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        let size = self
+            .skip
+            .checked_add(self.len)
+            .and_then(|size| usize::try_from(size).ok());
+        let Some(size) = size else {
+            return decompress_error(TINFLStatus::BadParam, Vec::new());
+        };
+
+        // The part may end in the middle of the stream, so let it run out of input.
+        let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF
+            | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+        let mut decomp = DecompressorOxide::new();
+        let mut out = vec![0; size];
+        let (status, _, out_pos) = decompress(
+            &mut decomp,
+            compressed,
+            &mut out,
+            0,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        out.truncate(out_pos);
+
+        match status {
+            TINFLStatus::Done | TINFLStatus::HasMoreOutput | TINFLStatus::NeedsMoreInput
+                if out_pos == size =>
+            {
+                out.drain(..self.skip as usize);
+                Ok(out)
+            }
+            TINFLStatus::Done | TINFLStatus::NeedsMoreInput => {
+                decompress_error(TINFLStatus::FailedCannotMakeProgress, out)
+            }
+            status => decompress_error(status, out),
+        }
+    }
+}
+
+/// Compress the input data to a vector, using the specified compression level (0-10), and with a
+/// gzip wrapper with an empty header.
+pub fn compress_to_vec_gzip(input: &[u8], level: u8) -> Vec<u8> {
+    compress_to_vec_gzip_with_header(input, level, &GzHeader::default())
+}
+
+/// Compress the input data to a vector, using the specified compression level (0-10), and with a
+/// gzip wrapper with the given header.
+pub fn compress_to_vec_gzip_with_header(input: &[u8], level: u8, header: &GzHeader) -> Vec<u8> {
+    let flags = create_comp_flags_from_zip_params(level.into(), 0, 0);
+    compress_member(&mut CompressorOxide::new(flags), input, level, header)
+}
+
+/// Compress `input` to a gzip member with `compressor`, set up for `level`.
+fn compress_member(
+    compressor: &mut CompressorOxide,
+    input: &[u8],
+    level: u8,
+    header: &GzHeader,
+) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + HEADER_LEN + TRAILER_LEN);
+    header.write(level, &mut output);
+    let (status, bytes_in) = compress_to_output(compressor, input, TDEFLFlush::Finish, |buf| {
+        output.extend_from_slice(buf);
+        true
+    });
+    // Not supposed to happen unless there is a bug.
+    assert!(
+        status == TDEFLStatus::Done && bytes_in == input.len(),
+        "Bug! Unexpectedly failed to compress!"
+    );
+    output.extend_from_slice(&update_crc32(0, input).to_le_bytes());
+    output.extend_from_slice(&(input.len() as u32).to_le_bytes());
+    output
+}
+
+/// Decompress the first member of the gzip data in `input` to a vector.
+///
+/// The header CRC16 is verified if the header has one, and the CRC-32 and length in the
+/// trailer are always verified. Use [`GzHeader::parse`] to get the metadata in the header.
+///
+/// NOTE: Like [`decompress_to_vec`](crate::inflate::decompress_to_vec), this does not bound
+/// the output.
+pub fn decompress_to_vec_gzip(input: &[u8]) -> Result<Vec<u8>, GzipError> {
+    let (_, header_len) = GzHeader::parse(input)?;
+    let (output, body_len) = inflate_member(&input[header_len..])?;
+    let trailer = input
+        .get(header_len + body_len..header_len + body_len + TRAILER_LEN)
+        .ok_or(GzipError::Truncated)?;
+    check_trailer(trailer, update_crc32(0, &output), output.len() as u64)?;
+    Ok(output)
+}
+
+/// Compress the gzip data in `input` again, using the specified compression level (0-10) and
+/// strategy, and keeping the header of each member.
+///
+/// Each member is decompressed straight into the compressor, so the uncompressed data is
+/// never held in memory as a whole, and its CRC-32 and length are verified. Anything after
+/// the last member that is not another member is left out, as [`decompress_to_vec_gzip`]
+/// ignores it too.
+pub fn recompress_gzip(
+    input: &[u8],
+    level: u8,
+    strategy: CompressionStrategy,
+) -> Result<Vec<u8>, GzipError> {
+    let flags = create_comp_flags_from_zip_params(level.into(), 0, strategy as i32);
+    let mut compressor = CompressorOxide::new(flags);
+    let mut output = Vec::with_capacity(input.len());
+    let mut pos = 0;
+    loop {
+        let (header, header_len) = GzHeader::parse(&input[pos..])?;
+        header.write(level, &mut output);
+        pos += header_len;
+
+        let (crc32, len, body_len) =
+            recompress_member(&mut compressor, &input[pos..], &mut output)?;
+        pos += body_len;
+        let trailer = input
+            .get(pos..pos + TRAILER_LEN)
+            .ok_or(GzipError::Truncated)?;
+        check_trailer(trailer, crc32, len)?;
+        output.extend_from_slice(trailer);
+        pos += TRAILER_LEN;
+
+        if !input[pos..].starts_with(&GZIP_MAGIC) {
+            return Ok(output);
+        }
+        compressor.reset();
+    }
+}
+
+/// Decompress the raw deflate data at the start of `input` and compress it again with
+/// `compressor` to `output`, one window at a time.
+///
+/// Returns the CRC-32 and length of the data, and the length of the deflate data.
+fn recompress_member(
+    compressor: &mut CompressorOxide,
+    input: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(u32, u64, usize), DecompressError> {
+    let mut decomp = DecompressorOxide::new();
+    let mut window = vec![0; TINFL_LZ_DICT_SIZE];
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let mut crc32 = 0;
+    let mut len = 0;
+    loop {
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut decomp,
+            &input[in_pos..],
+            &mut window,
+            out_pos,
+            0,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        in_pos += in_consumed;
+        let data = &window[out_pos..out_pos + out_consumed];
+        crc32 = update_crc32(crc32, data);
+        len += data.len() as u64;
+        let (deflate_status, bytes_in) =
+            compress_to_output(compressor, data, TDEFLFlush::None, |buf| {
+                output.extend_from_slice(buf);
+                true
+            });
+        // Not supposed to happen unless there is a bug.
+        assert!(
+            deflate_status == TDEFLStatus::Okay && bytes_in == data.len(),
+            "Bug! Unexpectedly failed to compress!"
+        );
+        out_pos = (out_pos + out_consumed) & (TINFL_LZ_DICT_SIZE - 1);
+
+        match status {
+            TINFLStatus::Done => break,
+            TINFLStatus::HasMoreOutput => {}
+            status => return decompress_error(status, Vec::new()),
+        }
+    }
+
+    let (status, _) = compress_to_output(compressor, &[], TDEFLFlush::Finish, |buf| {
+        output.extend_from_slice(buf);
+        true
+    });
+    assert!(
+        status == TDEFLStatus::Done,
+        "Bug! Unexpectedly failed to compress!"
+    );
+    Ok((crc32, len, in_pos))
+}
+
+/// Check the CRC-32 and length in `trailer` against those of the decompressed data.
+pub(crate) fn check_trailer(trailer: &[u8], crc32: u32, len: u64) -> Result<(), GzipError> {
+    let expected = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    if expected != crc32 {
+        return Err(GzipError::CrcMismatch {
+            expected,
+            computed: crc32,
+        });
+    }
+    // The length is stored modulo 2^32.
+    if trailer[4..] != (len as u32).to_le_bytes() {
+        return Err(GzipError::LengthMismatch);
+    }
+    Ok(())
+}
+
+/// Decompress the raw deflate data at the start of `input` to a vector.
+///
+/// Returns the data and the length of the deflate data.
+fn inflate_member(input: &[u8]) -> Result<(Vec<u8>, usize), DecompressError> {
+    let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let mut decomp = DecompressorOxide::new();
+    let mut out = vec![0; 32 * 1024];
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    loop {
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut decomp,
+            &input[in_pos..],
+            &mut out,
+            out_pos,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        in_pos += in_consumed;
+        out_pos += out_consumed;
+
+        match status {
+            TINFLStatus::Done => {
+                out.truncate(out_pos);
+                return Ok((out, in_pos));
+            }
+            TINFLStatus::HasMoreOutput => {
+                let new_len = out.len().saturating_mul(2);
+                out.resize(new_len, 0);
+            }
+            status => {
+                out.truncate(out_pos);
+                return decompress_error(status, out).map(|out| (out, in_pos));
+            }
+        }
+    }
+}
+
+/// Compress `input` to a gzip stream using the specified compression level (0-10), with a
+/// full flush every `interval` bytes of input, and return it with a [`RangeMap`] of the
+/// flush points.
+///
+/// Each full flush costs a few bytes and resets the dictionary, so `interval` should be at
+/// least a few hundred KiB to keep the loss in compression small. With an `interval` of 0
+/// there are no flushes, and the map only has the start of the data.
+pub fn compress_to_vec_with_range_map(
+    input: &[u8],
+    level: u8,
+    interval: usize,
+) -> (Vec<u8>, RangeMap) {
+    let flags = create_comp_flags_from_zip_params(level.into(), 0, 0);
+    let mut compressor = CompressorOxide::new(flags);
+    compressor.set_full_flush_interval(interval);
+    compressor.set_record_boundaries(true);
+    let output = compress_member(&mut compressor, input, level, &GzHeader::default());
+
+    let mut points = vec![RangePoint {
+        uncompressed: 0,
+        compressed: HEADER_LEN as u64,
+    }];
+    // Full flushes end with an empty raw block, so they are at byte boundaries.
+    points.extend(
+        compressor
+            .boundaries()
+            .iter()
+            .filter(|boundary| boundary.flush == TDEFLFlush::Full)
+            .map(|boundary| RangePoint {
+                uncompressed: boundary.in_offset,
+                compressed: HEADER_LEN as u64 + boundary.out_bit_offset / 8,
+            }),
+    );
+    let map = RangeMap {
+        points,
+        uncompressed_len: input.len() as u64,
+        compressed_len: output.len() as u64,
+    };
+    (output, map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CM_DEFLATE, GzHeader, GzipError, HEADER_LEN, RangeMap, TRAILER_LEN, compress_to_vec_gzip,
+        compress_to_vec_gzip_with_header, compress_to_vec_with_range_map, decompress_to_vec_gzip,
+        recompress_gzip,
+    };
+    use crate::deflate::core::CompressionStrategy;
+    use crate::inflate::decompress_to_vec;
+    use crate::shared::update_crc32;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn crc32() {
+        assert_eq!(update_crc32(0, b""), 0);
+        assert_eq!(update_crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            update_crc32(update_crc32(0, b"1234"), b"56789"),
+            0xCBF4_3926
+        );
+    }
+
+    #[test]
+    fn header_crc() {
+        let header = GzHeader {
+            mtime: 0x1234_5678,
+            os: 3,
+            filename: Some(b"a.txt".to_vec()),
+            comment: Some(b"hi".to_vec()),
+            header_crc: true,
+            ..GzHeader::default()
+        };
+        let data = b"Hello, hello, hello!";
+        let encoded = compress_to_vec_gzip_with_header(data, 6, &header);
+        // Same as the header written by zlib.
+        let expected = [
+            31, 139, 8, 26, 120, 86, 52, 18, 0, 3, 97, 46, 116, 120, 116, 0, 104, 105, 0, 223, 113,
+        ];
+        assert_eq!(encoded[..expected.len()], expected);
+        assert_eq!(GzHeader::parse(&encoded).unwrap(), (header, expected.len()));
+        assert_eq!(decompress_to_vec_gzip(&encoded).unwrap(), data);
+
+        let mut corrupted = encoded.clone();
+        corrupted[12] ^= 1;
+        assert!(matches!(
+            decompress_to_vec_gzip(&corrupted),
+            Err(GzipError::HeaderCrcMismatch {
+                expected: 0x71DF,
+                ..
+            })
+        ));
+        assert!(matches!(
+            GzHeader::parse(&encoded[..expected.len() - 1]),
+            Err(GzipError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn gzip() {
+        let data = b"Test data for gzip, test data for gzip, test data for gzip.";
+        let encoded = compress_to_vec_gzip(data, 9);
+        assert_eq!(encoded[..HEADER_LEN], [31, 139, 8, 0, 0, 0, 0, 0, 2, 255]);
+        assert_eq!(
+            GzHeader::parse(&encoded).unwrap(),
+            (GzHeader::default(), HEADER_LEN)
+        );
+
+        // Data after the member is ignored.
+        let mut with_more = encoded.clone();
+        with_more.extend_from_slice(&encoded);
+        assert_eq!(decompress_to_vec_gzip(&with_more).unwrap(), data);
+
+        let mut corrupted = encoded.clone();
+        let len = corrupted.len();
+        corrupted[len - TRAILER_LEN] ^= 1;
+        assert!(matches!(
+            decompress_to_vec_gzip(&corrupted),
+            Err(GzipError::CrcMismatch { .. })
+        ));
+        corrupted[len - TRAILER_LEN] ^= 1;
+        corrupted[len - 1] ^= 1;
+        assert!(matches!(
+            decompress_to_vec_gzip(&corrupted),
+            Err(GzipError::LengthMismatch)
+        ));
+        assert!(matches!(
+            decompress_to_vec_gzip(&encoded[..len - 1]),
+            Err(GzipError::Truncated)
+        ));
+        corrupted[0] = 0;
+        assert!(matches!(
+            decompress_to_vec_gzip(&corrupted),
+            Err(GzipError::BadMagic([0, 0x8b]))
+        ));
+        let mut corrupted = encoded.clone();
+        corrupted[2] = 9;
+        assert!(matches!(
+            decompress_to_vec_gzip(&corrupted),
+            Err(GzipError::BadCompressionMethod(9))
+        ));
+        corrupted[2] = CM_DEFLATE;
+        corrupted[3] |= 0x80;
+        let err = decompress_to_vec_gzip(&corrupted).unwrap_err();
+        assert!(matches!(err, GzipError::UnsupportedGzipFlag(0x80)));
+        assert_eq!(err.to_string(), "Unsupported gzip header flags 0x80");
+
+        // The deflate error is the source, so both are printed in a chain.
+        let err = decompress_to_vec_gzip(&encoded[..HEADER_LEN + 4]).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to decompress the gzip data");
+        let source = core::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "Truncated input stream");
+        assert!(
+            source
+                .downcast_ref::<crate::inflate::DecompressError>()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn recompress() {
+        let data = crate::test_util::text(200_000);
+        let header = GzHeader {
+            filename: Some(b"data.txt".to_vec()),
+            mtime: 1_234_567_890,
+            header_crc: true,
+            ..GzHeader::default()
+        };
+        let mut members = compress_to_vec_gzip_with_header(&data, 1, &header);
+        let first_len = members.len();
+        members.extend_from_slice(&compress_to_vec_gzip(b"second member", 0));
+
+        let recompressed = recompress_gzip(&members, 9, CompressionStrategy::Default).unwrap();
+        assert_eq!(GzHeader::parse(&recompressed).unwrap().0, header);
+        // The extra flags follow the new level.
+        assert_eq!(recompressed[8], 2);
+        assert!(decompress_to_vec_gzip(&recompressed).unwrap() == data);
+        // The second member follows, compressed the same way as on its own.
+        let second = compress_to_vec_gzip(b"second member", 9);
+        assert!(recompressed.ends_with(&second));
+        assert!(recompressed.len() - second.len() < first_len);
+
+        let mut corrupted = members.clone();
+        corrupted[first_len - TRAILER_LEN] ^= 1;
+        assert!(matches!(
+            recompress_gzip(&corrupted, 9, CompressionStrategy::Default),
+            Err(GzipError::CrcMismatch { .. })
+        ));
+        assert!(matches!(
+            recompress_gzip(&members[..first_len - 1], 9, CompressionStrategy::Default),
+            Err(GzipError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn range_map() {
+        let data = crate::test_util::random(300_000, 10);
+
+        let (gzip, map) = compress_to_vec_with_range_map(&data, 6, 64 * 1024);
+        assert_eq!(gzip[..3], [0x1F, 0x8B, 8]);
+        let body = &gzip[HEADER_LEN..gzip.len() - TRAILER_LEN];
+        assert_eq!(decompress_to_vec(body).unwrap(), data);
+        let trailer = &gzip[gzip.len() - TRAILER_LEN..];
+        assert_eq!(trailer[..4], update_crc32(0, &data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+
+        let offsets: Vec<u64> = map.points().iter().map(|p| p.uncompressed).collect();
+        assert_eq!(offsets, [0, 65536, 131072, 196608, 262144]);
+        assert_eq!(map.compressed_len(), gzip.len() as u64);
+        assert_eq!(RangeMap::from_bytes(&map.to_bytes()), Some(map.clone()));
+
+        for (start, end) in [
+            (0, 1),
+            (0, 300_000),
+            (65_535, 65_537),
+            (65_536, 131_072),
+            (100_000, 250_000),
+            (299_999, 400_000),
+        ] {
+            let mapped = map.locate(start..end).unwrap();
+            let part = &gzip[mapped.compressed.start as usize..mapped.compressed.end as usize];
+            let end = end.min(data.len() as u64);
+            assert_eq!(
+                mapped.decompress(part).unwrap(),
+                data[start as usize..end as usize]
+            );
+        }
+        // Ranges inside one interval only read that part.
+        let mapped = map.locate(70_000..80_000).unwrap();
+        assert_eq!(mapped.compressed.start, map.points()[1].compressed);
+        assert_eq!(mapped.compressed.end, map.points()[2].compressed);
+        assert_eq!(mapped.skip, 70_000 - 65_536);
+
+        assert_eq!(map.locate(300_000..300_001), None);
+        assert_eq!(map.locate(5..5), None);
+
+        // Too little input is an error rather than a short range.
+        let mapped = map.locate(0..131_072).unwrap();
+        let part = &gzip[mapped.compressed.start as usize..mapped.compressed.end as usize - 1000];
+        assert!(mapped.decompress(part).is_err());
+    }
+}
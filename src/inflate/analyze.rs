@@ -0,0 +1,530 @@
+//! Walking a deflate stream to describe how it is encoded instead of decompressing it, as the
+//! groundwork for tools like infgen that show the blocks, Huffman codes and symbols of a
+//! stream.
+//!
+//! [`analyze`] returns a [`StreamAnalysis`] with the position, type, code lengths and symbol
+//! counts of every block. With the `serde` feature it can be serialized, e.g. to JSON.
+
+use crate::alloc::vec;
+use crate::alloc::vec::Vec;
+
+use super::ZlibHeaderError;
+use super::core::{DIST_BASE, LENGTH_BASE, LENGTH_EXTRA, num_extra_bits_for_distance_code};
+use crate::DataFormat;
+use crate::shared::HUFFMAN_LENGTH_ORDER;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Description of a whole deflate stream, from [`analyze`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StreamAnalysis {
+    /// The two bytes of the zlib header (CMF and FLG), or `None` for a raw stream.
+    pub zlib_header: Option<[u8; 2]>,
+    /// The blocks in stream order.
+    pub blocks: Vec<BlockAnalysis>,
+    /// The adler32 checksum in the zlib trailer, or `None` for a raw stream. It is not
+    /// checked against the data.
+    pub adler32: Option<u32>,
+    /// Number of bytes the stream decompresses to.
+    pub uncompressed_len: u64,
+    /// Number of input bytes in the stream, including the zlib header and trailer. Anything
+    /// after them is not part of the stream.
+    pub compressed_len: u64,
+}
+
+/// The way the data of a block is encoded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BlockType {
+    /// Stored without compression.
+    Stored,
+    /// Compressed with the fixed Huffman codes of the deflate spec.
+    Static,
+    /// Compressed with Huffman codes sent in the block header.
+    Dynamic,
+}
+
+/// Description of a single block of a deflate stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlockAnalysis {
+    /// Position of the block header in bits from the start of the input, including the zlib
+    /// header if there is one.
+    pub start_bit: u64,
+    /// Position of the end of the block in bits, which is where the next block starts.
+    pub end_bit: u64,
+    /// Whether this is the last block of the stream.
+    pub is_final: bool,
+    /// How the data of the block is encoded.
+    pub block_type: BlockType,
+    /// Number of bytes the block decompresses to.
+    pub uncompressed_len: u64,
+    /// The lengths of the codes for the code lengths, in symbol order (0-18). Only dynamic
+    /// blocks have these, it is empty for the others.
+    pub code_length_lengths: Vec<u8>,
+    /// The length of the code of each literal/length symbol, 0 for the ones not used. Empty
+    /// for stored blocks, and the fixed lengths for static blocks.
+    pub literal_length_lengths: Vec<u8>,
+    /// The length of the code of each distance symbol, like `literal_length_lengths`.
+    pub distance_lengths: Vec<u8>,
+    /// How often each literal/length symbol occurs in the block, indexed by symbol.
+    pub literal_length_counts: Vec<u64>,
+    /// How often each distance symbol occurs in the block, indexed by symbol.
+    pub distance_counts: Vec<u64>,
+}
+
+impl BlockAnalysis {
+    /// Number of literals in the block.
+    pub fn literals(&self) -> u64 {
+        self.literal_length_counts.iter().take(256).sum()
+    }
+
+    /// Number of matches in the block.
+    pub fn matches(&self) -> u64 {
+        self.distance_counts.iter().sum()
+    }
+}
+
+/// Why [`analyze`] failed, with the blocks that were complete before the failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AnalyzeError {
+    /// What is wrong with the stream.
+    pub kind: AnalyzeErrorKind,
+    /// Position in bits from the start of the input where it was found.
+    pub bit_offset: u64,
+    /// The blocks before the one that failed.
+    pub blocks: Vec<BlockAnalysis>,
+}
+
+/// What is wrong with a stream that [`analyze`] failed on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AnalyzeErrorKind {
+    /// The zlib header is invalid.
+    ZlibHeader(ZlibHeaderError),
+    /// The input ended before the end of the stream.
+    UnexpectedEnd,
+    /// A block header has the reserved block type 3.
+    InvalidBlockType,
+    /// The length of a stored block doesn't match its one's complement.
+    StoredLengthMismatch,
+    /// A dynamic block has more than 286 literal/length codes or 30 distance codes.
+    TooManyCodes,
+    /// The code lengths of a dynamic block don't make valid Huffman codes.
+    InvalidCodeLengths,
+    /// The bits in the stream don't match any code.
+    InvalidCode,
+    /// A code for one of the literal/length or distance symbols deflate doesn't use.
+    InvalidSymbol,
+    /// A match reaches back before the start of the data.
+    DistanceTooFar,
+}
+
+impl ::core::fmt::Display for AnalyzeError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        let message = match self.kind {
+            AnalyzeErrorKind::ZlibHeader(err) => return err.fmt(f),
+            AnalyzeErrorKind::UnexpectedEnd => "Unexpected end of input",
+            AnalyzeErrorKind::InvalidBlockType => "Invalid block type",
+            AnalyzeErrorKind::StoredLengthMismatch => "Stored block length mismatch",
+            AnalyzeErrorKind::TooManyCodes => "Too many Huffman codes",
+            AnalyzeErrorKind::InvalidCodeLengths => "Invalid Huffman code lengths",
+            AnalyzeErrorKind::InvalidCode => "Invalid Huffman code",
+            AnalyzeErrorKind::InvalidSymbol => "Invalid literal/length or distance symbol",
+            AnalyzeErrorKind::DistanceTooFar => "Match distance too far back",
+        };
+        write!(f, "{message} at bit {}", self.bit_offset)
+    }
+}
+
+impl ::core::error::Error for AnalyzeError {}
+
+/// Walk the deflate stream in `data`, wrapped as `format`, and describe every block.
+///
+/// The stream is checked like it would be when decompressing, apart from the adler32
+/// checksum, but the data itself is not produced.
+pub fn analyze(data: &[u8], format: DataFormat) -> Result<StreamAnalysis, AnalyzeError> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut analysis = StreamAnalysis {
+        zlib_header: None,
+        blocks: Vec::new(),
+        adler32: None,
+        uncompressed_len: 0,
+        compressed_len: 0,
+    };
+    let fail = |kind, bit_offset, blocks| AnalyzeError {
+        kind,
+        bit_offset,
+        blocks,
+    };
+
+    if format != DataFormat::Raw {
+        let header = bits.bytes(2).map_err(|kind| fail(kind, 0, Vec::new()))?;
+        let header = [header[0], header[1]];
+        if let Some(err) = zlib_header_error(header) {
+            return Err(fail(AnalyzeErrorKind::ZlibHeader(err), 0, Vec::new()));
+        }
+        analysis.zlib_header = Some(header);
+    }
+
+    loop {
+        let block = match analyze_block(&mut bits, analysis.uncompressed_len) {
+            Ok(block) => block,
+            Err(kind) => return Err(fail(kind, bits.pos, analysis.blocks)),
+        };
+        analysis.uncompressed_len += block.uncompressed_len;
+        let is_final = block.is_final;
+        analysis.blocks.push(block);
+        if is_final {
+            break;
+        }
+    }
+
+    bits.align();
+    if analysis.zlib_header.is_some() {
+        match bits.bytes(4) {
+            Ok(trailer) => analysis.adler32 = Some(u32::from_be_bytes(trailer.try_into().unwrap())),
+            Err(kind) => return Err(fail(kind, bits.pos, analysis.blocks)),
+        }
+    }
+    analysis.compressed_len = bits.pos / 8;
+    Ok(analysis)
+}
+
+/// Check the zlib header like [`DecompressorOxide::zlib_header_error`] does.
+///
+/// [`DecompressorOxide::zlib_header_error`]: super::core::DecompressorOxide::zlib_header_error
+fn zlib_header_error([cmf, flg]: [u8; 2]) -> Option<ZlibHeaderError> {
+    if !(u32::from(cmf) * 256 + u32::from(flg)).is_multiple_of(31) {
+        Some(ZlibHeaderError::BadHeaderCheck { cmf, flg })
+    } else if cmf & 15 != 8 {
+        Some(ZlibHeaderError::BadCompressionMethod(cmf))
+    } else if cmf >> 4 > 7 {
+        Some(ZlibHeaderError::WindowTooLarge(cmf))
+    } else if flg & 0b0010_0000 != 0 {
+        Some(ZlibHeaderError::PresetDictionary(flg))
+    } else {
+        None
+    }
+}
+
+/// Read the block at the position of `bits`, after `out_len` bytes of decompressed data.
+fn analyze_block(bits: &mut Bits, out_len: u64) -> Result<BlockAnalysis, AnalyzeErrorKind> {
+    let start_bit = bits.pos;
+    let is_final = bits.bits(1)? == 1;
+    let mut block = BlockAnalysis {
+        start_bit,
+        end_bit: 0,
+        is_final,
+        block_type: BlockType::Stored,
+        uncompressed_len: 0,
+        code_length_lengths: Vec::new(),
+        literal_length_lengths: Vec::new(),
+        distance_lengths: Vec::new(),
+        literal_length_counts: Vec::new(),
+        distance_counts: Vec::new(),
+    };
+
+    match bits.bits(2)? {
+        0 => {
+            bits.align();
+            let header = bits.bytes(4)?;
+            let len = u16::from_le_bytes([header[0], header[1]]);
+            if len != !u16::from_le_bytes([header[2], header[3]]) {
+                return Err(AnalyzeErrorKind::StoredLengthMismatch);
+            }
+            bits.bytes(len.into())?;
+            block.uncompressed_len = len.into();
+        }
+        1 => {
+            block.block_type = BlockType::Static;
+            let mut literal_length = vec![8; 288];
+            literal_length[144..256].fill(9);
+            literal_length[256..280].fill(7);
+            // Static blocks have codes for all 32 distance symbols, even if two are invalid.
+            block.literal_length_lengths = literal_length;
+            block.distance_lengths = vec![5; 32];
+            decode_symbols(bits, &mut block, out_len)?;
+        }
+        2 => {
+            block.block_type = BlockType::Dynamic;
+            read_code_lengths(bits, &mut block)?;
+            decode_symbols(bits, &mut block, out_len)?;
+        }
+        _ => return Err(AnalyzeErrorKind::InvalidBlockType),
+    }
+
+    block.end_bit = bits.pos;
+    Ok(block)
+}
+
+/// Read the Huffman code lengths from the header of a dynamic block.
+fn read_code_lengths(bits: &mut Bits, block: &mut BlockAnalysis) -> Result<(), AnalyzeErrorKind> {
+    let literal_length = bits.bits(5)? as usize + 257;
+    let distance = bits.bits(5)? as usize + 1;
+    let code_length = bits.bits(4)? as usize + 4;
+    if literal_length > 286 || distance > 30 {
+        return Err(AnalyzeErrorKind::TooManyCodes);
+    }
+
+    let mut code_length_lengths = vec![0; HUFFMAN_LENGTH_ORDER.len()];
+    for &symbol in &HUFFMAN_LENGTH_ORDER[..code_length] {
+        code_length_lengths[usize::from(symbol)] = bits.bits(3)? as u8;
+    }
+    let code_length_code =
+        Huffman::new(&code_length_lengths).ok_or(AnalyzeErrorKind::InvalidCodeLengths)?;
+
+    // The literal/length and distance lengths are sent as one sequence, and repeats can run
+    // from one into the other.
+    let mut lengths = Vec::with_capacity(literal_length + distance);
+    while lengths.len() < literal_length + distance {
+        let (value, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let &previous = lengths.last().ok_or(AnalyzeErrorKind::InvalidCodeLengths)?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > literal_length + distance {
+            return Err(AnalyzeErrorKind::InvalidCodeLengths);
+        }
+        lengths.extend((0..repeat).map(|_| value));
+    }
+    // A block without an end of block code could never end.
+    if lengths[256] == 0 {
+        return Err(AnalyzeErrorKind::InvalidCodeLengths);
+    }
+
+    block.code_length_lengths = code_length_lengths;
+    block.distance_lengths = lengths.split_off(literal_length);
+    block.literal_length_lengths = lengths;
+    Ok(())
+}
+
+/// Decode the symbols of a compressed block up to and including the end of block code, with
+/// the code lengths in `block`.
+fn decode_symbols(
+    bits: &mut Bits,
+    block: &mut BlockAnalysis,
+    out_len: u64,
+) -> Result<(), AnalyzeErrorKind> {
+    let literal_length_code =
+        Huffman::new(&block.literal_length_lengths).ok_or(AnalyzeErrorKind::InvalidCodeLengths)?;
+    let distance_code =
+        Huffman::new(&block.distance_lengths).ok_or(AnalyzeErrorKind::InvalidCodeLengths)?;
+    block.literal_length_counts = vec![0; block.literal_length_lengths.len()];
+    block.distance_counts = vec![0; block.distance_lengths.len()];
+
+    loop {
+        let symbol = usize::from(literal_length_code.decode(bits)?);
+        block.literal_length_counts[symbol] += 1;
+        match symbol {
+            0..=255 => block.uncompressed_len += 1,
+            256 => return Ok(()),
+            257..=285 => {
+                let code = symbol - 257;
+                let len = u32::from(LENGTH_BASE[code]) + bits.bits(LENGTH_EXTRA[code].into())?;
+
+                let symbol = usize::from(distance_code.decode(bits)?);
+                block.distance_counts[symbol] += 1;
+                if symbol >= DIST_BASE.len() {
+                    return Err(AnalyzeErrorKind::InvalidSymbol);
+                }
+                let extra = num_extra_bits_for_distance_code(symbol as u8);
+                let dist = u32::from(DIST_BASE[symbol]) + bits.bits(extra.into())?;
+                if u64::from(dist) > out_len + block.uncompressed_len {
+                    return Err(AnalyzeErrorKind::DistanceTooFar);
+                }
+                block.uncompressed_len += u64::from(len);
+            }
+            _ => return Err(AnalyzeErrorKind::InvalidSymbol),
+        }
+    }
+}
+
+/// Reads the input a bit at a time, least significant bit of each byte first.
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Position of the next bit.
+    pos: u64,
+}
+
+impl<'a> Bits<'a> {
+    /// Read `n` (up to 16) bits as a number, the first one the least significant.
+    fn bits(&mut self, n: u32) -> Result<u32, AnalyzeErrorKind> {
+        let mut value = 0;
+        for i in 0..n {
+            let &byte = self
+                .data
+                .get((self.pos / 8) as usize)
+                .ok_or(AnalyzeErrorKind::UnexpectedEnd)?;
+            value |= u32::from(byte >> (self.pos % 8) & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary.
+    fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(8);
+    }
+
+    /// Read `n` whole bytes, the position has to be at a byte boundary.
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], AnalyzeErrorKind> {
+        let start = (self.pos / 8) as usize;
+        let bytes = self
+            .data
+            .get(start..start + n)
+            .ok_or(AnalyzeErrorKind::UnexpectedEnd)?;
+        self.pos += n as u64 * 8;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time like zlib's puff does. That is slow, but
+/// simple and plenty for analysis.
+struct Huffman {
+    /// Number of codes of each length.
+    count: [u16; 16],
+    /// The symbols ordered by their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code from the code length of each symbol, or return `None` if there are more
+    /// codes than fit in the lengths. Codes that don't fit are fine, and only an error if
+    /// their bits are found in the stream.
+    fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut count = [0; 16];
+        for &len in lengths {
+            count[usize::from(len)] += 1;
+        }
+        // The number of codes of each length left over by the shorter ones.
+        let mut left = 1i32;
+        for &count in &count[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return None;
+            }
+        }
+
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let offset = &mut offsets[usize::from(len)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        Some(Huffman { count, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, AnalyzeErrorKind> {
+        // The first code of the current length, and the index of its symbol.
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.count[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(AnalyzeErrorKind::InvalidCode)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deflate::{compress_to_vec, compress_to_vec_zlib};
+
+    #[test]
+    fn analyze_compressed() {
+        let data = crate::test_util::random(200_000, 8);
+        let compressed = compress_to_vec(&data, 6);
+        let analysis = analyze(&compressed, DataFormat::Raw).unwrap();
+
+        assert_eq!(analysis.zlib_header, None);
+        assert_eq!(analysis.uncompressed_len, data.len() as u64);
+        assert_eq!(analysis.compressed_len, compressed.len() as u64);
+        assert!(analysis.blocks.len() > 1);
+        let mut pos = 0;
+        for (i, block) in analysis.blocks.iter().enumerate() {
+            assert_eq!(block.start_bit, pos);
+            assert_eq!(block.is_final, i == analysis.blocks.len() - 1);
+            assert_eq!(block.literal_length_counts[256], 1);
+            pos = block.end_bit;
+        }
+        let literals: u64 = analysis.blocks.iter().map(|b| b.literals()).sum();
+        let matches: u64 = analysis.blocks.iter().map(|b| b.matches()).sum();
+        assert!(literals > 0 && matches > 0);
+    }
+
+    #[test]
+    fn analyze_zlib() {
+        let text = b"Hello, zlib!";
+        let stored = compress_to_vec_zlib(text, 0);
+        let analysis = analyze(&stored, DataFormat::Zlib).unwrap();
+        assert_eq!(analysis.zlib_header, Some([stored[0], stored[1]]));
+        assert_eq!(analysis.blocks.len(), 1);
+        assert_eq!(analysis.blocks[0].block_type, BlockType::Stored);
+        assert_eq!(analysis.blocks[0].start_bit, 16);
+        let trailer = stored[stored.len() - 4..].try_into().unwrap();
+        assert_eq!(analysis.adler32, Some(u32::from_be_bytes(trailer)));
+
+        let encoded = [
+            120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
+        ];
+        let analysis = analyze(&encoded, DataFormat::Zlib).unwrap();
+        let block = &analysis.blocks[0];
+        assert_eq!(block.block_type, BlockType::Static);
+        assert_eq!(block.literal_length_counts[usize::from(b'l')], 3);
+        assert_eq!((block.literals(), block.matches()), (12, 0));
+        assert_eq!(analysis.compressed_len, encoded.len() as u64);
+    }
+
+    #[test]
+    fn analyze_errors() {
+        use crate::deflate::core::{CompressorOxide, TDEFLFlush, compress};
+
+        // A sync flush ends the first part of the zlib stream with an empty stored block.
+        let data = b"first part ".repeat(1000);
+        let mut compressor = CompressorOxide::default();
+        let mut compressed = vec![0; 10_000];
+        let (_, _, first) = compress(&mut compressor, &data, &mut compressed, TDEFLFlush::Sync);
+        let (_, _, second) = compress(
+            &mut compressor,
+            &data,
+            &mut compressed[first..],
+            TDEFLFlush::Finish,
+        );
+        let complete = analyze(&compressed[..first + second], DataFormat::Zlib).unwrap();
+        assert_eq!(complete.blocks[1].block_type, BlockType::Stored);
+        assert_eq!(complete.blocks[1].end_bit, first as u64 * 8);
+
+        let err = analyze(&compressed[..first], DataFormat::Zlib).unwrap_err();
+        assert_eq!(err.kind, AnalyzeErrorKind::UnexpectedEnd);
+        assert_eq!(err.bit_offset, first as u64 * 8);
+        assert_eq!(err.blocks, complete.blocks[..2]);
+
+        let err = analyze(&[0b111], DataFormat::Raw).unwrap_err();
+        assert_eq!(
+            (err.kind, err.bit_offset),
+            (AnalyzeErrorKind::InvalidBlockType, 3)
+        );
+        let err = analyze(&[0x78, 0x9d], DataFormat::Zlib).unwrap_err();
+        assert!(matches!(err.kind, AnalyzeErrorKind::ZlibHeader(_)));
+    }
+}
@@ -0,0 +1,487 @@
+//! Decompression in `const` contexts, so compressed data can be embedded in a program and
+//! decompressed by the compiler, e.g. lookup tables in firmware that is short on flash.
+//!
+//! The decompressed data ends up in a `static` or `const` array like any other, so nothing is
+//! decompressed at runtime and no decompressor state is needed.
+//!
+//! ```
+//! use miniz_oxide::inflate::const_inflate::decompress_to_array_zlib;
+//!
+//! // Usually `include_bytes!("table.zlib")`.
+//! const COMPRESSED: &[u8] = &[
+//!     120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
+//! ];
+//! static TABLE: [u8; 12] = decompress_to_array_zlib(COMPRESSED);
+//!
+//! assert_eq!(&TABLE, b"Hello, zlib!");
+//! ```
+//!
+//! The decoder here is kept simple rather than fast, and decodes the huffman codes a bit at a
+//! time. It is meant for data of up to some tens of KiB; for more, the compiler may stop the
+//! evaluation unless the `long_running_const_eval` lint is allowed.
+
+use super::TINFLStatus;
+use super::core::{DIST_BASE, LENGTH_BASE, LENGTH_EXTRA, num_extra_bits_for_distance_code};
+use crate::DataFormat;
+use crate::shared::HUFFMAN_LENGTH_ORDER;
+
+/// `?` for `const fn`, where the `Try` trait can't be used.
+macro_rules! try_const {
+    ($e:expr) => {
+        match $e {
+            Ok(value) => value,
+            Err(status) => return Err(status),
+        }
+    };
+}
+
+/// Decompress raw deflate data into an array of exactly `N` bytes.
+///
+/// # Panics
+///
+/// Panics if the data is invalid or truncated, or doesn't decompress to exactly `N` bytes,
+/// which is a compile error when evaluated in a `const` or `static`.
+pub const fn decompress_to_array<const N: usize>(input: &[u8]) -> [u8; N] {
+    match try_decompress_to_array(input, DataFormat::Raw) {
+        Ok(out) => out,
+        Err(status) => panic!("{}", status.message()),
+    }
+}
+
+/// Decompress zlib wrapped data into an array of exactly `N` bytes, checking the adler32
+/// checksum.
+///
+/// # Panics
+///
+/// Panics if the data is invalid or truncated, or doesn't decompress to exactly `N` bytes,
+/// which is a compile error when evaluated in a `const` or `static`.
+pub const fn decompress_to_array_zlib<const N: usize>(input: &[u8]) -> [u8; N] {
+    match try_decompress_to_array(input, DataFormat::Zlib) {
+        Ok(out) => out,
+        Err(status) => panic!("{}", status.message()),
+    }
+}
+
+/// Decompress data in `data_format` into an array of exactly `N` bytes.
+///
+/// Returns [`TINFLStatus::HasMoreOutput`] if the data decompresses to more than `N` bytes and
+/// [`TINFLStatus::BadParam`] if to less. Otherwise fails like [`decompress_to_vec`] would.
+///
+/// [`decompress_to_vec`]: super::decompress_to_vec
+pub const fn try_decompress_to_array<const N: usize>(
+    input: &[u8],
+    data_format: DataFormat,
+) -> Result<[u8; N], TINFLStatus> {
+    let zlib = !matches!(data_format, DataFormat::Raw);
+    let mut input = Input {
+        data: input,
+        pos: 0,
+        bit_buf: 0,
+        num_bits: 0,
+    };
+
+    if zlib {
+        let cmf = try_const!(input.byte()) as u32;
+        let flg = try_const!(input.byte()) as u32;
+        if !(cmf * 256 + flg).is_multiple_of(31)
+            || cmf & 15 != 8
+            || cmf >> 4 > 7
+            || flg & 0x20 != 0
+        {
+            return Err(TINFLStatus::Failed);
+        }
+    }
+
+    let mut out = [0; N];
+    let mut out_pos = 0;
+    loop {
+        let last = try_const!(input.bits(1));
+        out_pos = match try_const!(input.bits(2)) {
+            0 => try_const!(stored(&mut input, &mut out, out_pos)),
+            1 => try_const!(codes(&mut input, &mut out, out_pos, &FIXED_TABLES)),
+            2 => {
+                let tables = try_const!(dynamic_tables(&mut input));
+                try_const!(codes(&mut input, &mut out, out_pos, &tables))
+            }
+            _ => return Err(TINFLStatus::Failed),
+        };
+        if last != 0 {
+            break;
+        }
+    }
+
+    if out_pos != N {
+        return Err(TINFLStatus::BadParam);
+    }
+
+    if zlib {
+        // The checksum starts at the next byte.
+        input.align();
+        let mut expected = 0;
+        let mut i = 0;
+        while i < 4 {
+            expected = (expected << 8) | try_const!(input.byte()) as u32;
+            i += 1;
+        }
+        if matches!(data_format, DataFormat::Zlib) && expected != adler32(&out) {
+            return Err(TINFLStatus::Adler32Mismatch);
+        }
+    }
+
+    Ok(out)
+}
+
+/// The input and the bits read from it but not used yet.
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    num_bits: u32,
+}
+
+impl Input<'_> {
+    /// Read `n` bits, at most 16.
+    ///
+    /// Bytes are only taken from the input when the bits are needed, so fewer than 8 bits are
+    /// left over afterwards, and they all belong to the byte before `pos`.
+    const fn bits(&mut self, n: u32) -> Result<u32, TINFLStatus> {
+        while self.num_bits < n {
+            let byte = try_const!(self.byte());
+            self.bit_buf |= (byte as u32) << self.num_bits;
+            self.num_bits += 8;
+        }
+        let value = self.bit_buf & ((1 << n) - 1);
+        self.bit_buf >>= n;
+        self.num_bits -= n;
+        Ok(value)
+    }
+
+    /// Read a whole byte, ignoring the bits left over.
+    const fn byte(&mut self) -> Result<u8, TINFLStatus> {
+        if self.pos >= self.data.len() {
+            return Err(TINFLStatus::FailedCannotMakeProgress);
+        }
+        self.pos += 1;
+        Ok(self.data[self.pos - 1])
+    }
+
+    /// Skip the rest of the current byte.
+    const fn align(&mut self) {
+        self.bit_buf = 0;
+        self.num_bits = 0;
+    }
+}
+
+/// A huffman code, as the number of codes of each length and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: [u16; 288],
+}
+
+impl Huffman {
+    /// Build the code from the code length of each symbol.
+    ///
+    /// Codes that use more codes than there are are invalid, but incomplete ones are allowed,
+    /// as for a single distance code. Decoding an unused code then fails.
+    const fn new(lengths: &[u8]) -> Result<Huffman, TINFLStatus> {
+        let mut counts = [0u16; 16];
+        let mut i = 0;
+        while i < lengths.len() {
+            counts[lengths[i] as usize] += 1;
+            i += 1;
+        }
+
+        let mut left: i32 = 1;
+        let mut offsets = [0u16; 16];
+        let mut len = 1;
+        while len < 16 {
+            left = (left << 1) - counts[len] as i32;
+            if left < 0 {
+                return Err(TINFLStatus::Failed);
+            }
+            if len < 15 {
+                offsets[len + 1] = offsets[len] + counts[len];
+            }
+            len += 1;
+        }
+
+        let mut symbols = [0u16; 288];
+        i = 0;
+        while i < lengths.len() {
+            let len = lengths[i] as usize;
+            if len != 0 {
+                symbols[offsets[len] as usize] = i as u16;
+                offsets[len] += 1;
+            }
+            i += 1;
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Decode a symbol, one bit at a time.
+    ///
+    /// The codes of each length are consecutive, so after each bit it only takes a comparison
+    /// to tell whether the code read so far is complete.
+    const fn decode(&self, input: &mut Input) -> Result<u16, TINFLStatus> {
+        // The code read so far, the first code of the current length, and the index of the
+        // symbol of that first code.
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        let mut len = 1;
+        while len < 16 {
+            code |= try_const!(input.bits(1)) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+            len += 1;
+        }
+        Err(TINFLStatus::Failed)
+    }
+}
+
+/// The literal/length and distance codes of a block.
+struct Tables {
+    literal: Huffman,
+    dist: Huffman,
+}
+
+/// The codes of the fixed huffman blocks.
+const FIXED_TABLES: Tables = {
+    let mut lengths = [8u8; 288];
+    let mut i = 144;
+    while i < 288 {
+        lengths[i] = match i {
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+        i += 1;
+    }
+    match (Huffman::new(&lengths), Huffman::new(&[5; 30])) {
+        (Ok(literal), Ok(dist)) => Tables { literal, dist },
+        _ => panic!("Invalid fixed huffman code lengths"),
+    }
+};
+
+/// Read the code lengths at the start of a dynamic huffman block and build its codes.
+const fn dynamic_tables(input: &mut Input) -> Result<Tables, TINFLStatus> {
+    let num_literal = try_const!(input.bits(5)) as usize + 257;
+    let num_dist = try_const!(input.bits(5)) as usize + 1;
+    let num_code_lengths = try_const!(input.bits(4)) as usize + 4;
+    if num_literal > 286 || num_dist > 30 {
+        return Err(TINFLStatus::Failed);
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    let mut i = 0;
+    while i < num_code_lengths {
+        code_length_lengths[HUFFMAN_LENGTH_ORDER[i] as usize] = try_const!(input.bits(3)) as u8;
+        i += 1;
+    }
+    let code_lengths = try_const!(Huffman::new(&code_length_lengths));
+
+    let total = num_literal + num_dist;
+    let mut lengths = [0u8; 286 + 30];
+    i = 0;
+    while i < total {
+        let symbol = try_const!(code_lengths.decode(input));
+        if symbol < 16 {
+            lengths[i] = symbol as u8;
+            i += 1;
+            continue;
+        }
+        let (value, repeat) = match symbol {
+            16 => {
+                if i == 0 {
+                    return Err(TINFLStatus::Failed);
+                }
+                (lengths[i - 1], 3 + try_const!(input.bits(2)) as usize)
+            }
+            17 => (0, 3 + try_const!(input.bits(3)) as usize),
+            _ => (0, 11 + try_const!(input.bits(7)) as usize),
+        };
+        if i + repeat > total {
+            return Err(TINFLStatus::Failed);
+        }
+        let end = i + repeat;
+        while i < end {
+            lengths[i] = value;
+            i += 1;
+        }
+    }
+
+    // Without an end of block code, the block can't end.
+    if lengths[256] == 0 {
+        return Err(TINFLStatus::Failed);
+    }
+    let (literal_lengths, rest) = lengths.split_at(num_literal);
+    let (dist_lengths, _) = rest.split_at(num_dist);
+    Ok(Tables {
+        literal: try_const!(Huffman::new(literal_lengths)),
+        dist: try_const!(Huffman::new(dist_lengths)),
+    })
+}
+
+/// Copy a stored block to `out` at `out_pos`, and return the new position.
+const fn stored(
+    input: &mut Input,
+    out: &mut [u8],
+    mut out_pos: usize,
+) -> Result<usize, TINFLStatus> {
+    input.align();
+    let mut header = [0u8; 4];
+    let mut i = 0;
+    while i < 4 {
+        header[i] = try_const!(input.byte());
+        i += 1;
+    }
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(TINFLStatus::Failed);
+    }
+
+    let end = out_pos + len as usize;
+    if end > out.len() {
+        return Err(TINFLStatus::HasMoreOutput);
+    }
+    while out_pos < end {
+        out[out_pos] = try_const!(input.byte());
+        out_pos += 1;
+    }
+    Ok(out_pos)
+}
+
+/// Decode the literals and matches of a huffman block to `out` at `out_pos`, and return the new
+/// position.
+const fn codes(
+    input: &mut Input,
+    out: &mut [u8],
+    mut out_pos: usize,
+    tables: &Tables,
+) -> Result<usize, TINFLStatus> {
+    loop {
+        let symbol = try_const!(tables.literal.decode(input)) as usize;
+        if symbol < 256 {
+            if out_pos >= out.len() {
+                return Err(TINFLStatus::HasMoreOutput);
+            }
+            out[out_pos] = symbol as u8;
+            out_pos += 1;
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(out_pos);
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= 29 {
+            return Err(TINFLStatus::Failed);
+        }
+        let len = LENGTH_BASE[symbol] as usize
+            + try_const!(input.bits(LENGTH_EXTRA[symbol] as u32)) as usize;
+
+        let symbol = try_const!(tables.dist.decode(input)) as usize;
+        if symbol >= 30 {
+            return Err(TINFLStatus::Failed);
+        }
+        let num_extra = num_extra_bits_for_distance_code(symbol as u8) as u32;
+        let dist = DIST_BASE[symbol] as usize + try_const!(input.bits(num_extra)) as usize;
+        if dist > out_pos {
+            return Err(TINFLStatus::Failed);
+        }
+
+        let end = out_pos + len;
+        if end > out.len() {
+            return Err(TINFLStatus::HasMoreOutput);
+        }
+        while out_pos < end {
+            out[out_pos] = out[out_pos - dist];
+            out_pos += 1;
+        }
+    }
+}
+
+/// The adler32 checksum of `data`.
+const fn adler32(data: &[u8]) -> u32 {
+    const BASE: u32 = 65521;
+    let mut a = 1;
+    let mut b = 0;
+    let mut i = 0;
+    while i < data.len() {
+        a = (a + data[i] as u32) % BASE;
+        b = (b + a) % BASE;
+        i += 1;
+    }
+    (b << 16) | a
+}
+
+#[cfg(all(test, feature = "with-alloc"))]
+mod test {
+    use super::{decompress_to_array, decompress_to_array_zlib, try_decompress_to_array};
+    use crate::DataFormat;
+    use crate::deflate::{compress_to_vec, compress_to_vec_zlib};
+    use crate::inflate::TINFLStatus;
+
+    const ENCODED: [u8; 20] = [
+        120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
+    ];
+    static DECODED: [u8; 12] = decompress_to_array_zlib(&ENCODED);
+    const RAW: [u8; 12] = decompress_to_array(ENCODED.split_at(2).1);
+
+    #[test]
+    fn compile_time() {
+        assert_eq!(&DECODED, b"Hello, zlib!");
+        assert_eq!(&RAW, b"Hello, zlib!");
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::test_util::text(5000);
+        // Stored, fixed and dynamic huffman blocks.
+        for level in [0, 1, 6, 10] {
+            let encoded = compress_to_vec(&data, level);
+            let out = try_decompress_to_array::<5000>(&encoded, DataFormat::Raw).unwrap();
+            assert!(out[..] == data[..], "level {level}");
+            let encoded = compress_to_vec_zlib(&data, level);
+            let out = try_decompress_to_array::<5000>(&encoded, DataFormat::Zlib).unwrap();
+            assert!(out[..] == data[..], "level {level}");
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let raw = &ENCODED[2..];
+        assert_eq!(
+            try_decompress_to_array::<13>(raw, DataFormat::Raw),
+            Err(TINFLStatus::BadParam)
+        );
+        assert_eq!(
+            try_decompress_to_array::<11>(raw, DataFormat::Raw),
+            Err(TINFLStatus::HasMoreOutput)
+        );
+        assert_eq!(
+            try_decompress_to_array::<12>(&ENCODED[..10], DataFormat::Zlib),
+            Err(TINFLStatus::FailedCannotMakeProgress)
+        );
+        assert_eq!(
+            try_decompress_to_array::<12>(raw, DataFormat::Zlib),
+            Err(TINFLStatus::Failed)
+        );
+
+        let mut bad_checksum = ENCODED;
+        bad_checksum[19] ^= 1;
+        assert_eq!(
+            try_decompress_to_array::<12>(&bad_checksum, DataFormat::Zlib),
+            Err(TINFLStatus::Adler32Mismatch)
+        );
+        assert!(
+            try_decompress_to_array::<12>(&bad_checksum, DataFormat::ZLibIgnoreChecksum).is_ok()
+        );
+    }
+}
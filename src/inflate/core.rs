@@ -7,7 +7,7 @@ use ::core::cell::Cell;
 use ::core::cmp;
 use ::core::convert::TryInto;
 
-use self::output_buffer::{InputWrapper, OutputBuffer};
+use self::output_buffer::{copy_match_wide, InputWrapper, OutputBuffer};
 
 #[cfg(feature = "serde")]
 use crate::serde::big_array::BigArray;
@@ -16,6 +16,11 @@ use serde::{Deserialize, Serialize};
 
 pub const TINFL_LZ_DICT_SIZE: usize = 32_768;
 
+/// The size of the window of Deflate64, see
+/// [`TINFL_FLAG_DEFLATE64`][inflate_flags::TINFL_FLAG_DEFLATE64].
+#[cfg(feature = "deflate64")]
+pub const TINFL_DEFLATE64_DICT_SIZE: usize = 65_536;
+
 /// A struct containing huffman code lengths and the huffman code tree used by the decompressor.
 #[cfg_attr(not(feature = "rustc-dep-of-std"), derive(Clone))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -41,6 +46,54 @@ impl HuffmanTable {
         }
     }
 
+    /// Build the table of a code that assigns every bit sequence, from its code lengths.
+    const fn from_complete_code(code_sizes: &[u8]) -> HuffmanTable {
+        let mut table = HuffmanTable::new();
+        let mut i = 0;
+        while i < table.look_up.len() {
+            table.look_up[i] = INVALID_CODE;
+            i += 1;
+        }
+
+        // The first code of each length.
+        let mut next_code = [0u16; 16];
+        let mut code = 0;
+        let mut len = 1;
+        while len < next_code.len() {
+            let mut count = 0;
+            let mut symbol = 0;
+            while symbol < code_sizes.len() {
+                count += (code_sizes[symbol] as usize == len - 1) as u16;
+                symbol += 1;
+            }
+            if len > 1 {
+                code = (code + count) << 1;
+            }
+            next_code[len] = code;
+            len += 1;
+        }
+
+        let mut tree_next = -1;
+        let mut symbol = 0;
+        while symbol < code_sizes.len() {
+            let code_size = code_sizes[symbol];
+            let rev_code = next_code[code_size as usize].reverse_bits() >> (16 - code_size);
+            next_code[code_size as usize] += 1;
+            if !insert_code(
+                &mut table.look_up,
+                &mut table.tree,
+                symbol,
+                code_size,
+                rev_code,
+                &mut tree_next,
+            ) {
+                panic!("the fixed huffman tables don't fit");
+            }
+            symbol += 1;
+        }
+        table
+    }
+
     /// Look for a symbol in the fast lookup table.
     /// The symbol is stored in the lower 9 bits, the length in the next 6.
     /// If the returned value is negative, the code wasn't found in the
@@ -97,8 +150,177 @@ impl HuffmanTable {
     }
 }
 
+/// The huffman tables of a [`DecompressorOxide`].
+///
+/// Serialized the same way as three full `HuffmanTable`s, the layout from before the code
+/// length codes got a table of their own and the fixed tables were built at compile time,
+/// so states saved by earlier versions can still be loaded and the other way around.
+#[cfg_attr(not(feature = "rustc-dep-of-std"), derive(Clone))]
+struct Tables {
+    /// Huffman tables for the literal/length and distance codes.
+    huffman: [HuffmanTable; MAX_HUFF_TREES],
+    /// Lookup table for the code length codes.
+    ///
+    /// These codes are too short to ever need a tree, so they get a smaller table of their own
+    /// rather than a full `HuffmanTable`.
+    code_length: [i16; CODE_LENGTH_LOOKUP_SIZE],
+    /// Whether the current block uses the fixed huffman codes, which are decoded with
+    /// `FIXED_TABLES` rather than `huffman`, so those only have to be built for dynamic blocks.
+    fixed: bool,
+}
+
+impl ::core::ops::Index<usize> for Tables {
+    type Output = HuffmanTable;
+
+    #[inline]
+    fn index(&self, table: usize) -> &HuffmanTable {
+        &self.huffman[table]
+    }
+}
+
+impl ::core::ops::IndexMut<usize> for Tables {
+    #[inline]
+    fn index_mut(&mut self, table: usize) -> &mut HuffmanTable {
+        &mut self.huffman[table]
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Tables {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        // The code lengths fill the whole fast lookup table of a full table, repeating every
+        // `CODE_LENGTH_LOOKUP_SIZE` entries, and don't use its tree.
+        let mut code_length = HuffmanTable::new();
+        for (i, entry) in code_length.look_up.iter_mut().enumerate() {
+            *entry = self.code_length[i & (CODE_LENGTH_LOOKUP_SIZE - 1)];
+        }
+        // The tables of a fixed block are written out in full, so they are in place when the
+        // state is loaded again.
+        let huffman = if self.fixed {
+            &FIXED_TABLES
+        } else {
+            &self.huffman
+        };
+        let mut tuple = serializer.serialize_tuple(MAX_HUFF_TABLES)?;
+        tuple.serialize_element(&huffman[LITLEN_TABLE])?;
+        tuple.serialize_element(&huffman[DIST_TABLE])?;
+        tuple.serialize_element(&code_length)?;
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Tables {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let [litlen, dist, code_length] =
+            <[HuffmanTable; MAX_HUFF_TABLES]>::deserialize(deserializer)?;
+        let mut tables = Tables {
+            huffman: [litlen, dist],
+            code_length: [0; CODE_LENGTH_LOOKUP_SIZE],
+            fixed: false,
+        };
+        tables
+            .code_length
+            .copy_from_slice(&code_length.look_up[..CODE_LENGTH_LOOKUP_SIZE]);
+        Ok(tables)
+    }
+}
+
+/// The tables of the fixed huffman codes, built at compile time so that blocks using them
+/// don't need to build any tables.
+static FIXED_TABLES: [HuffmanTable; MAX_HUFF_TREES] = [
+    HuffmanTable::from_complete_code(&fixed_litlen_code_sizes()),
+    HuffmanTable::from_complete_code(&[5; MAX_HUFF_SYMBOLS_1]),
+];
+
+/// The code lengths of the fixed literal/length code.
+const fn fixed_litlen_code_sizes() -> [u8; MAX_HUFF_SYMBOLS_0] {
+    let mut sizes = [8; MAX_HUFF_SYMBOLS_0];
+    let mut i = 144;
+    while i < 256 {
+        sizes[i] = 9;
+        i += 1;
+    }
+    while i < 280 {
+        sizes[i] = 7;
+        i += 1;
+    }
+    sizes
+}
+
+/// Marks fast lookup table entries that no code starts with.
+const INVALID_CODE: i16 = (1 << 9) | 286;
+
+/// Add the code of `symbol`, `code_size` bits long and bit reversed as `rev_code`, to the
+/// fast lookup table, or to the tree if it's longer than `FAST_LOOKUP_BITS` bits.
+///
+/// `tree_next` is the next free node of the tree. Returns false if the tree is full.
+const fn insert_code(
+    look_up: &mut [i16],
+    tree: &mut [i16],
+    symbol: usize,
+    code_size: u8,
+    mut rev_code: u16,
+    tree_next: &mut i16,
+) -> bool {
+    if code_size <= FAST_LOOKUP_BITS {
+        let k = ((code_size as i16) << 9) | symbol as i16;
+        while (rev_code as usize) < look_up.len() {
+            look_up[rev_code as usize] = k;
+            rev_code += 1 << code_size;
+        }
+        return true;
+    }
+
+    // Only the literal/length and distance tables get here, and those have a
+    // full size lookup table.
+    let fast_index = (rev_code & (FAST_LOOKUP_SIZE - 1)) as usize;
+    if fast_index >= look_up.len() {
+        return false;
+    }
+    let mut tree_cur = look_up[fast_index];
+    if tree_cur == INVALID_CODE {
+        look_up[fast_index] = *tree_next;
+        tree_cur = *tree_next;
+        *tree_next -= 2;
+    }
+
+    rev_code >>= FAST_LOOKUP_BITS - 1;
+    let mut len = FAST_LOOKUP_BITS + 1;
+    while len < code_size {
+        rev_code >>= 1;
+        tree_cur -= (rev_code & 1) as i16;
+        let tree_index = (-tree_cur - 1) as usize;
+        if tree_index >= tree.len() {
+            return false;
+        }
+        if tree[tree_index] == 0 {
+            tree[tree_index] = *tree_next;
+            tree_cur = *tree_next;
+            *tree_next -= 2;
+        } else {
+            tree_cur = tree[tree_index];
+        }
+        len += 1;
+    }
+
+    rev_code >>= 1;
+    tree_cur -= (rev_code & 1) as i16;
+    let tree_index = (-tree_cur - 1) as usize;
+    if tree_index >= tree.len() {
+        return false;
+    }
+    tree[tree_index] = symbol as i16;
+    true
+}
+
 /// The number of huffman tables used.
 const MAX_HUFF_TABLES: usize = 3;
+/// The number of huffman tables that need a full fast lookup table and tree
+/// (literal/length and distance).
+const MAX_HUFF_TREES: usize = 2;
 /// The length of the first (literal/length) huffman table.
 const MAX_HUFF_SYMBOLS_0: usize = 288;
 /// The length of the second (distance) huffman table.
@@ -106,10 +328,32 @@ const MAX_HUFF_SYMBOLS_1: usize = 32;
 /// The length of the last (huffman code length) huffman table.
 const MAX_HUFF_SYMBOLS_2: usize = 19;
 /// The maximum length of a code that can be looked up in the fast lookup table.
+///
+/// Longer codes are found by walking the tree, so `reduced-memory` trades some speed for
+/// tables a quarter of the size.
+#[cfg(not(feature = "reduced-memory"))]
 const FAST_LOOKUP_BITS: u8 = 10;
+#[cfg(feature = "reduced-memory")]
+const FAST_LOOKUP_BITS: u8 = 8;
 /// The size of the fast lookup table.
 const FAST_LOOKUP_SIZE: u16 = 1 << FAST_LOOKUP_BITS;
 const MAX_HUFF_TREE_SIZE: usize = MAX_HUFF_SYMBOLS_0 * 2;
+/// Code length codes are at most 7 bits long, so they always fit in a 128 entry lookup table.
+const CODE_LENGTH_LOOKUP_BITS: u8 = 7;
+const CODE_LENGTH_LOOKUP_SIZE: usize = 1 << CODE_LENGTH_LOOKUP_BITS;
+/// The number of bits used to index the literal pair table.
+const LITERAL_PAIR_BITS: u32 = 12;
+/// The size of the literal pair table.
+///
+/// Without `literal-pairs`, or with `reduced-memory`, the table has a single entry that is
+/// never filled in, which leaves the 16KiB table out of the decompressor and turns off
+/// decoding two literals at once.
+#[cfg(all(feature = "literal-pairs", not(feature = "reduced-memory")))]
+const LITERAL_PAIR_SIZE: usize = 1 << LITERAL_PAIR_BITS;
+#[cfg(not(all(feature = "literal-pairs", not(feature = "reduced-memory"))))]
+const LITERAL_PAIR_SIZE: usize = 1;
+/// Maximum number of literals staged before being copied to the output in one go.
+const LITERAL_RUN_MAX: usize = 8;
 const LITLEN_TABLE: usize = 0;
 const DIST_TABLE: usize = 1;
 const HUFFLEN_TABLE: usize = 2;
@@ -164,8 +408,20 @@ pub mod inflate_flags {
     /// Return [`TINFLStatus::BlockBoundary`][super::TINFLStatus::BlockBoundary]
     /// on reaching the boundary between deflate blocks. Calling [`decompress()`][super::decompress]
     /// again will resume decompression of the next block.
+    ///
+    /// Works with both wrapping and non-wrapping output buffers, and only needs `core`, so
+    /// decoders with a fixed 32 KiB ring buffer can suspend at block boundaries too.
     #[cfg(feature = "block-boundary")]
     pub const TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY: u32 = 128;
+
+    /// Decode Deflate64 (Enhanced Deflate, method 9 in zip archives) rather than deflate.
+    ///
+    /// Deflate64 has a 64 KiB window, uses length code 285 for lengths of 3 to 65538 with 16
+    /// extra bits, and distance codes 30 and 31 for distances up to 65536 with 14 extra bits.
+    /// A wrapping output buffer has to be at least
+    /// [`TINFL_DEFLATE64_DICT_SIZE`][super::TINFL_DEFLATE64_DICT_SIZE] bytes.
+    #[cfg(feature = "deflate64")]
+    pub const TINFL_FLAG_DEFLATE64: u32 = 256;
 }
 
 use self::inflate_flags::*;
@@ -194,7 +450,11 @@ enum HuffmanTableType {
 /// ([`TINFL_FLAG_PARSE_ZLIB_HEADER`], [`TINFL_FLAG_COMPUTE_ADLER32`]).
 /// When deserializing, you can reconstruct `bit_buf` from the previous byte in the input file
 /// (if you still have access to it), so `num_bits` is the only field that is always required.
-#[derive(Clone)]
+///
+/// With the `serde` feature enabled the struct can be serialized with any serde format,
+/// otherwise [`BlockBoundaryState::to_bytes()`] and [`BlockBoundaryState::from_bytes()`]
+/// provide a fixed, versioned little-endian encoding that is stable across platforms.
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg(feature = "block-boundary")]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BlockBoundaryState {
@@ -229,6 +489,51 @@ impl Default for BlockBoundaryState {
     }
 }
 
+#[cfg(feature = "block-boundary")]
+impl BlockBoundaryState {
+    /// Version tag written as the first byte of [`BlockBoundaryState::to_bytes()`].
+    pub const ENCODING_VERSION: u8 = 1;
+    /// Length of the byte encoding produced by [`BlockBoundaryState::to_bytes()`].
+    pub const ENCODED_LEN: usize = 15;
+
+    /// Encode the state into a fixed-size byte array.
+    ///
+    /// Layout: version, `num_bits`, `bit_buf`, then `z_header0`, `z_header1` and
+    /// `check_adler32` as little-endian `u32`s.
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut out = [0; Self::ENCODED_LEN];
+        out[0] = Self::ENCODING_VERSION;
+        out[1] = self.num_bits;
+        out[2] = self.bit_buf;
+        out[3..7].copy_from_slice(&self.z_header0.to_le_bytes());
+        out[7..11].copy_from_slice(&self.z_header1.to_le_bytes());
+        out[11..15].copy_from_slice(&self.check_adler32.to_le_bytes());
+        out
+    }
+
+    /// Decode a state previously produced by [`BlockBoundaryState::to_bytes()`].
+    ///
+    /// Returns `None` if the slice has the wrong length, an unknown version tag,
+    /// or a `num_bits` value outside of `0..=7`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<BlockBoundaryState> {
+        if bytes.len() != Self::ENCODED_LEN || bytes[0] != Self::ENCODING_VERSION {
+            return None;
+        }
+        let num_bits = bytes[1];
+        if num_bits > 7 {
+            return None;
+        }
+        let read_u32 = |pos: usize| u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+        Some(BlockBoundaryState {
+            num_bits,
+            bit_buf: bytes[2],
+            z_header0: read_u32(3),
+            z_header1: read_u32(7),
+            check_adler32: read_u32(11),
+        })
+    }
+}
+
 /// Main decompression struct.
 ///
 #[cfg_attr(not(feature = "rustc-dep-of-std"), derive(Clone))]
@@ -263,7 +568,7 @@ pub struct DecompressorOxide {
     /// Buffer of input data.
     bit_buf: BitBuffer,
     /// Huffman tables.
-    tables: [HuffmanTable; MAX_HUFF_TABLES],
+    tables: Tables,
 
     #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
     code_size_literal: [u8; MAX_HUFF_SYMBOLS_0],
@@ -276,6 +581,257 @@ pub struct DecompressorOxide {
     // MAX_HUFF_SYMBOLS_0 + MAX_HUFF_SYMBOLS_1 + 137
     // Extended to 512 to allow masking to help evade bounds checks.
     len_codes: [u8; LEN_CODES_SIZE],
+    /// Lookup table for decoding two literals at once in `decompress_fast`.
+    ///
+    /// Each non-zero entry holds the first literal in bits 0-7, the second in bits 8-15
+    /// and the combined code length from bit 16. Zero means the bits don't start
+    /// with two literals short enough to fit. The table is derived from the literal/length
+    /// table so it is not serialized; an all-zero table just disables the fast path.
+    #[cfg_attr(feature = "serde", serde(skip, default = "empty_literal_pairs"))]
+    literal_pairs: [u32; LITERAL_PAIR_SIZE],
+    /// Why the last call to `decompress` returned `BadParam`, if it did.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    param_error: Option<ParamError>,
+    /// Number of bytes decompressed since the start of the stream.
+    #[cfg_attr(feature = "serde", serde(default))]
+    total_out: u64,
+    /// The checksums if the stream ended with an adler32 mismatch.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    adler32_mismatch: Option<Adler32Mismatch>,
+    /// Counts of the blocks and matches decoded since the start of the stream.
+    #[cfg_attr(feature = "serde", serde(default))]
+    stats: DecodeStats,
+}
+
+/// Counts of the blocks and matches decoded by a [`DecompressorOxide`] since the start of the
+/// stream, from [`DecompressorOxide::stats`], e.g. to check what an encoder actually
+/// produced.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DecodeStats {
+    /// Number of stored (uncompressed) blocks.
+    pub stored_blocks: u64,
+    /// Number of blocks with the static Huffman codes.
+    pub static_blocks: u64,
+    /// Number of blocks with their own Huffman codes.
+    pub dynamic_blocks: u64,
+    /// Number of sets of Huffman tables built from the codes of dynamic blocks, which is less
+    /// than `dynamic_blocks` if the codes of the last one are invalid or not read yet.
+    pub dynamic_trees: u64,
+    /// The longest match distance seen, or 0 if there were no matches.
+    pub max_match_distance: u32,
+}
+
+impl DecodeStats {
+    /// The number of blocks started, of any type.
+    pub const fn blocks(&self) -> u64 {
+        self.stored_blocks + self.static_blocks + self.dynamic_blocks
+    }
+}
+
+/// The code assigned to a symbol of a Huffman table, from [`HuffmanTables`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HuffmanCode {
+    /// The symbol, a literal/length code (0-287) or distance code (0-31).
+    pub symbol: u16,
+    /// The number of bits in the code, 1-15.
+    pub length: u8,
+    /// The bits of the code, most significant bit first as in RFC 1951. The bits are sent in
+    /// the reverse order in the stream.
+    pub code: u16,
+}
+
+/// Whether the code lengths of a Huffman table assign every possible bit sequence, from
+/// [`HuffmanTables::literal_length_completeness`] and
+/// [`HuffmanTables::distance_completeness`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Completeness {
+    /// Every bit sequence starts with a code.
+    Complete,
+    /// Some bit sequences don't start with any code, and are "invalid code" errors if they
+    /// are found in the stream. This is allowed, but only a table with a single code should
+    /// need it.
+    Incomplete,
+    /// There are more codes than fit in the lengths, so the table can't be built.
+    OverSubscribed,
+}
+
+impl Completeness {
+    fn of(lengths: &[u8]) -> Self {
+        // Each code of length `n` takes up 2^(15 - n) of the 2^15 sequences of 15 bits.
+        let used: u32 = lengths
+            .iter()
+            .filter(|&&len| len != 0)
+            .map(|&len| 1 << (15 - u32::from(len.min(15))))
+            .sum();
+        match used.cmp(&(1 << 15)) {
+            cmp::Ordering::Equal => Completeness::Complete,
+            cmp::Ordering::Less => Completeness::Incomplete,
+            cmp::Ordering::Greater => Completeness::OverSubscribed,
+        }
+    }
+}
+
+/// The literal/length and distance code lengths of the Huffman tables the decompressor built
+/// last, from [`DecompressorOxide::huffman_tables`], to see how a stream was encoded or why
+/// it failed with an invalid code.
+///
+/// The [`Display`](::core::fmt::Display) impl lists every code with the symbol it stands for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HuffmanTables<'a> {
+    literal_length: &'a [u8],
+    distance: &'a [u8],
+}
+
+impl<'a> HuffmanTables<'a> {
+    /// The code length of each literal/length symbol, 0 for the ones not used.
+    pub const fn literal_length_lengths(&self) -> &'a [u8] {
+        self.literal_length
+    }
+
+    /// The code length of each distance symbol, 0 for the ones not used.
+    pub const fn distance_lengths(&self) -> &'a [u8] {
+        self.distance
+    }
+
+    /// The codes of the literal/length symbols used, in symbol order.
+    pub fn literal_length_codes(&self) -> HuffmanCodes<'a> {
+        HuffmanCodes::new(self.literal_length)
+    }
+
+    /// The codes of the distance symbols used, in symbol order.
+    pub fn distance_codes(&self) -> HuffmanCodes<'a> {
+        HuffmanCodes::new(self.distance)
+    }
+
+    /// Whether the literal/length code lengths assign every bit sequence.
+    pub fn literal_length_completeness(&self) -> Completeness {
+        Completeness::of(self.literal_length)
+    }
+
+    /// Whether the distance code lengths assign every bit sequence.
+    pub fn distance_completeness(&self) -> Completeness {
+        Completeness::of(self.distance)
+    }
+}
+
+impl ::core::fmt::Display for HuffmanTables<'_> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        let tables = [
+            ("literal/length", self.literal_length, self.literal_length_completeness()),
+            ("distance", self.distance, self.distance_completeness()),
+        ];
+        for (i, (name, lengths, completeness)) in tables.into_iter().enumerate() {
+            let completeness = match completeness {
+                Completeness::Complete => "complete",
+                Completeness::Incomplete => "incomplete",
+                Completeness::OverSubscribed => "over-subscribed",
+            };
+            writeln!(f, "{name} codes ({} symbols, {completeness}):", lengths.len())?;
+            for code in HuffmanCodes::new(lengths) {
+                let (symbol, width) = (code.symbol as usize, usize::from(code.length));
+                write!(f, "  {:0width$b}{:pad$}  ", code.code, "", pad = 15 - width)?;
+                match (i, symbol) {
+                    (0, 0..=255) => writeln!(f, "literal {symbol:#04x}")?,
+                    (0, 256) => writeln!(f, "end of block")?,
+                    (0, 257..=285) => writeln!(
+                        f,
+                        "length {} + {} bits",
+                        LENGTH_BASE[symbol - 257],
+                        LENGTH_EXTRA[symbol - 257]
+                    )?,
+                    (1, 0..=29) => writeln!(
+                        f,
+                        "distance {} + {} bits",
+                        DIST_BASE[symbol],
+                        num_extra_bits_for_distance_code(symbol as u8)
+                    )?,
+                    _ => writeln!(f, "symbol {symbol} (invalid outside Deflate64)")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the canonical codes of a Huffman table, from
+/// [`HuffmanTables::literal_length_codes`] and [`HuffmanTables::distance_codes`].
+///
+/// The codes of an over-subscribed table don't fit in their lengths, so they are cut to the
+/// low bits and some of them are the same.
+#[derive(Clone, Debug)]
+pub struct HuffmanCodes<'a> {
+    lengths: &'a [u8],
+    symbol: usize,
+    next_code: [u32; 16],
+}
+
+impl<'a> HuffmanCodes<'a> {
+    fn new(lengths: &'a [u8]) -> Self {
+        let mut count = [0u32; 16];
+        for &len in lengths {
+            count[usize::from(len.min(15))] += 1;
+        }
+        // RFC 1951 section 3.2.2: the first code of each length follows the codes one bit
+        // shorter.
+        let mut next_code = [0; 16];
+        let mut code = 0;
+        for len in 1..16 {
+            code = (code + count[len - 1] * u32::from(len > 1)) << 1;
+            next_code[len] = code;
+        }
+        HuffmanCodes {
+            lengths,
+            symbol: 0,
+            next_code,
+        }
+    }
+}
+
+impl Iterator for HuffmanCodes<'_> {
+    type Item = HuffmanCode;
+
+    fn next(&mut self) -> Option<HuffmanCode> {
+        while let Some(&len) = self.lengths.get(self.symbol) {
+            let symbol = self.symbol;
+            self.symbol += 1;
+            if len != 0 {
+                let length = len.min(15);
+                let next = &mut self.next_code[usize::from(length)];
+                let code = *next & ((1 << length) - 1);
+                *next += 1;
+                return Some(HuffmanCode {
+                    symbol: symbol as u16,
+                    length,
+                    code: code as u16,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Why [`decompress()`] returned [`TINFLStatus::BadParam`], from
+/// [`DecompressorOxide::param_error`].
+///
+/// The decompressor is left as it was, so the call can be made again with valid parameters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ParamError {
+    /// The output buffer wraps around, but its size is not a power of two.
+    OutputSizeNotPowerOfTwo,
+    /// `out_pos` is past the end of the output buffer.
+    OutPosOutOfBounds,
+    /// The output buffer filled up in the middle of a match, and the rest of the match would
+    /// now be copied from before the start of the output buffer, e.g. because `out_pos` went
+    /// back in a non-wrapping buffer, or a wrapping buffer got smaller.
+    MatchOutOfBounds,
+}
+
+#[cfg(feature = "serde")]
+const fn empty_literal_pairs() -> [u32; LITERAL_PAIR_SIZE] {
+    [0; LITERAL_PAIR_SIZE]
 }
 
 impl DecompressorOxide {
@@ -284,11 +840,69 @@ impl DecompressorOxide {
         DecompressorOxide::default()
     }
 
+    /// The literal/length or distance table of the current block.
+    #[inline]
+    fn table(&self, table: usize) -> &HuffmanTable {
+        if self.tables.fixed {
+            &FIXED_TABLES[table]
+        } else {
+            &self.tables[table]
+        }
+    }
+
+    /// Look for a symbol in the fast lookup table of the given huffman table.
+    ///
+    /// See `HuffmanTable::fast_lookup`.
+    #[inline]
+    fn fast_lookup(&self, table: usize, bit_buf: BitBuffer) -> i16 {
+        if table == HUFFLEN_TABLE {
+            self.tables.code_length[(bit_buf as usize) & (CODE_LENGTH_LOOKUP_SIZE - 1)]
+        } else {
+            self.table(table).fast_lookup(bit_buf)
+        }
+    }
+
+    /// Get why the last call to [`decompress()`] returned [`TINFLStatus::BadParam`], or `None`
+    /// if it didn't.
+    pub const fn param_error(&self) -> Option<ParamError> {
+        self.param_error
+    }
+
+    /// Get the checksum from the stream and the one computed from the output if the stream
+    /// ended with [`TINFLStatus::Adler32Mismatch`], or `None` if it didn't.
+    pub const fn adler32_mismatch(&self) -> Option<Adler32Mismatch> {
+        self.adler32_mismatch
+    }
+
+    /// Get the counts of the blocks and matches decoded since the start of the stream.
+    pub const fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
+    /// Get the code lengths of the last literal/length and distance Huffman tables built, or
+    /// `None` if no compressed block has been started yet.
+    ///
+    /// If a dynamic block failed because its code lengths don't make valid tables, these are
+    /// its lengths. If it failed before all of them were read, the number of symbols is that
+    /// of the failed block but the lengths are still those of the block before.
+    pub fn huffman_tables(&self) -> Option<HuffmanTables<'_>> {
+        let literal_length = usize::from(self.table_sizes[LITLEN_TABLE]);
+        if literal_length == 0 {
+            return None;
+        }
+        let distance = usize::from(self.table_sizes[DIST_TABLE]);
+        Some(HuffmanTables {
+            literal_length: &self.code_size_literal[..literal_length.min(MAX_HUFF_SYMBOLS_0)],
+            distance: &self.code_size_dist[..distance.min(MAX_HUFF_SYMBOLS_1)],
+        })
+    }
+
     /// Set the current state to `Start`.
     #[inline]
     pub fn init(&mut self) {
         // The rest of the data is reset or overwritten when used.
         self.state = core::State::Start;
+        self.stats = DecodeStats::default();
     }
 
     /// Returns the adler32 checksum of the currently decompressed data.
@@ -303,6 +917,27 @@ impl DecompressorOxide {
         }
     }
 
+    /// Get what is wrong with the zlib header if decompression failed because of it, or `None`
+    /// if it didn't.
+    pub fn zlib_header_error(&self) -> Option<ZlibHeaderError> {
+        if self.state != State::BadZlibHeader {
+            return None;
+        }
+        let (cmf, flg) = (self.z_header0 as u8, self.z_header1 as u8);
+        Some(if !(self.z_header0 * 256 + self.z_header1).is_multiple_of(31) {
+            ZlibHeaderError::BadHeaderCheck { cmf, flg }
+        } else if cmf & 15 != 8 {
+            ZlibHeaderError::BadCompressionMethod(cmf)
+        } else if cmf >> 4 > 7 {
+            ZlibHeaderError::WindowTooLarge(cmf)
+        } else if flg & 0b0010_0000 != 0 {
+            ZlibHeaderError::PresetDictionary(flg)
+        } else {
+            // The only other check is against the size of the output buffer.
+            ZlibHeaderError::WindowLargerThanBuffer(cmf)
+        })
+    }
+
     /// Returns the adler32 that was read from the zlib header if it exists.
     #[inline]
     #[cfg(not(feature = "rustc-dep-of-std"))]
@@ -356,7 +991,9 @@ impl DecompressorOxide {
     ///
     /// When calling [`decompress()`], the 32KiB of `out` preceding `out_pos` must be
     /// initialized with the same data that it contained when `block_boundary_state()`
-    /// was called.
+    /// was called. With a wrapping output buffer, that data wraps around to the end of the
+    /// buffer like the rest of the output, so keeping the buffer and `out_pos` as they were
+    /// is enough.
     #[cfg(feature = "block-boundary")]
     pub fn from_block_boundary_state(st: &BlockBoundaryState) -> Self {
         DecompressorOxide {
@@ -391,16 +1028,21 @@ impl Default for DecompressorOxide {
             table_sizes: [0; MAX_HUFF_TABLES],
             bit_buf: 0,
             // TODO:(oyvindln) Check that copies here are optimized out in release mode.
-            tables: [
-                HuffmanTable::new(),
-                HuffmanTable::new(),
-                HuffmanTable::new(),
-            ],
+            tables: Tables {
+                huffman: [HuffmanTable::new(), HuffmanTable::new()],
+                code_length: [0; CODE_LENGTH_LOOKUP_SIZE],
+                fixed: false,
+            },
             code_size_literal: [0; MAX_HUFF_SYMBOLS_0],
             code_size_dist: [0; MAX_HUFF_SYMBOLS_1],
             code_size_huffman: [0; MAX_HUFF_SYMBOLS_2],
             raw_header: [0; 4],
             len_codes: [0; LEN_CODES_SIZE],
+            literal_pairs: [0; LITERAL_PAIR_SIZE],
+            param_error: None,
+            total_out: 0,
+            adler32_mismatch: None,
+            stats: DecodeStats::default(),
         }
     }
 }
@@ -485,37 +1127,99 @@ use self::State::*;
 /// The base is used together with the value of the extra bits to decode the actual
 /// length/distance values in a match.
 #[rustfmt::skip]
-const LENGTH_BASE: [u16; 32] = [
+pub(crate) const LENGTH_BASE: [u16; 32] = [
     3,  4,  5,  6,  7,  8,  9,  10,  11,  13,  15,  17,  19,  23,  27,  31,
     35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258, 512, 512, 512
 ];
 
 /// Number of extra bits for each length code.
 #[rustfmt::skip]
-const LENGTH_EXTRA: [u8; 32] = [
+pub(crate) const LENGTH_EXTRA: [u8; 32] = [
     0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
     3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0, 0, 0, 0
 ];
 
 /// Base length for each distance code.
 #[rustfmt::skip]
-const DIST_BASE: [u16; 30] = [
+pub(crate) const DIST_BASE: [u16; 30] = [
     1,    2,    3,    4,    5,    7,      9,      13,     17,     25,    33,
     49,   65,   97,   129,  193,  257,    385,    513,    769,    1025,  1537,
     2049, 3073, 4097, 6145, 8193, 12_289, 16_385, 24_577
 ];
 
+/// Base length and number of extra bits of length code 285 in Deflate64.
+const DEFLATE64_LENGTH_BASE: u16 = 3;
+const DEFLATE64_LENGTH_EXTRA: u8 = 16;
+
+/// Base distance of the distance codes 30 and 31 of Deflate64, which have 14 extra bits
+/// like the two codes before them.
+const DEFLATE64_DIST_BASE: [u16; 2] = [32_769, 49_153];
+
+/// Returns `true` if `flags` asks for Deflate64, which is never the case without the
+/// `deflate64` feature so the checks compile away.
+#[inline(always)]
+const fn is_deflate64(flags: u32) -> bool {
+    #[cfg(feature = "deflate64")]
+    {
+        flags & TINFL_FLAG_DEFLATE64 != 0
+    }
+    #[cfg(not(feature = "deflate64"))]
+    {
+        let _ = flags;
+        false
+    }
+}
+
 /// Get the number of extra bits used for a distance code.
 /// (Code numbers above `NUM_DISTANCE_CODES` will give some garbage
 /// value.)
 #[inline(always)]
-const fn num_extra_bits_for_distance_code(code: u8) -> u8 {
+pub(crate) const fn num_extra_bits_for_distance_code(code: u8) -> u8 {
     // TODO: Need to verify that this is faster on all platforms.
     // This can be easily calculated without a lookup.
     let c = code >> 1;
     c.saturating_sub(1)
 }
 
+/// Flag set in `LENGTH_DECODE` and `DIST_DECODE` entries that don't describe a length or
+/// distance, so `decompress_fast` can catch all of them with a single check.
+const DECODE_SPECIAL: u32 = 1 << 31;
+
+/// Base value and number of extra bits for each length code, packed as `base | extra << 16`
+/// so both come from one lookup.
+///
+/// Indexed by `symbol - 257` masked to 5 bits, which puts the end of block symbol at 31
+/// and the two unused symbols at 29 and 30; those are marked with `DECODE_SPECIAL`.
+const LENGTH_DECODE: [u32; 32] = {
+    let mut table = [DECODE_SPECIAL; 32];
+    let mut i = 0;
+    while i < 29 {
+        table[i] = LENGTH_BASE[i] as u32 | (LENGTH_EXTRA[i] as u32) << 16;
+        i += 1;
+    }
+    table
+};
+
+/// `LENGTH_DECODE` for Deflate64, with length code 285 marked with `DECODE_SPECIAL` as its
+/// lengths may not fit in the output budget of `decompress_fast`.
+const LENGTH_DECODE_DEFLATE64: [u32; 32] = {
+    let mut table = LENGTH_DECODE;
+    table[28] = DECODE_SPECIAL;
+    table
+};
+
+/// Base value and number of extra bits for each distance code, packed like
+/// `LENGTH_DECODE`, with the two unused codes marked with `DECODE_SPECIAL`.
+const DIST_DECODE: [u32; 32] = {
+    let mut table = [DECODE_SPECIAL; 32];
+    let mut i = 0;
+    while i < DIST_BASE.len() {
+        table[i] = DIST_BASE[i] as u32 | (num_extra_bits_for_distance_code(i as u8) as u32) << 16;
+        i += 1;
+    }
+    table
+};
+
 /// The mask used when indexing the base/extra arrays.
 const BASE_EXTRA_MASK: usize = 32 - 1;
 
@@ -537,14 +1241,26 @@ fn read_u16_le(iter: &mut InputWrapper) -> u16 {
 ///
 /// On 64-bit platform, we use a 64-bit value so this will
 /// result in there being at least 32 bits in the bit buffer.
-/// This function assumes that there is at least 4 bytes left in the input buffer.
+/// When there are at least 8 bytes of input left, as many whole bytes as fit are loaded
+/// in one go (leaving 56-63 bits in the buffer), so refills are needed about half as often.
+/// Otherwise this falls back to reading 4 bytes, so it only assumes that there is at least
+/// 4 bytes left in the input buffer.
 #[inline(always)]
 #[cfg(target_pointer_width = "64")]
 fn fill_bit_buffer(l: &mut LocalVars, in_iter: &mut InputWrapper) {
-    // Read four bytes into the buffer at once.
     if l.num_bits < 30 {
-        l.bit_buf |= BitBuffer::from(in_iter.read_u32_le()) << l.num_bits;
-        l.num_bits += 32;
+        if let Some(eight_bytes) = in_iter.peek_u64_le() {
+            // Only take the bytes that fit entirely, keeping the bits above `num_bits` zeroed.
+            let n = (63 - l.num_bits) >> 3;
+            let mask = (1 << (n * 8)) - 1;
+            l.bit_buf |= (eight_bytes & mask) << l.num_bits;
+            l.num_bits += n * 8;
+            in_iter.advance(n as usize);
+        } else {
+            // Read four bytes into the buffer at once.
+            l.bit_buf |= BitBuffer::from(in_iter.read_u32_le()) << l.num_bits;
+            l.num_bits += 32;
+        }
     }
 }
 
@@ -632,7 +1348,7 @@ where
             //  * until the */
             // /* bit buffer contains >=15 bits (deflate's max. Huffman code size). */
             loop {
-                let mut temp = i32::from(r.tables[table].fast_lookup(l.bit_buf));
+                let mut temp = i32::from(r.fast_lookup(table, l.bit_buf));
                 if temp >= 0 {
                     let code_len = (temp >> 9) as u32;
                     // TODO: Is there any point to check for code_len != 0 here still?
@@ -643,7 +1359,7 @@ where
                     let mut code_len = u32::from(FAST_LOOKUP_BITS);
                     loop {
                         temp = i32::from(
-                            r.tables[table].tree
+                            r.table(table).tree
                                 [(!temp + ((l.bit_buf >> code_len) & 1) as i32) as usize],
                         );
                         code_len += 1;
@@ -687,7 +1403,7 @@ where
     }
 
     // We now have at least 15 bits in the input buffer.
-    let mut symbol = i32::from(r.tables[table].fast_lookup(l.bit_buf));
+    let mut symbol = i32::from(r.fast_lookup(table, l.bit_buf));
     let code_len;
     // If the symbol was found in the fast lookup table.
     if symbol >= 0 {
@@ -698,7 +1414,7 @@ where
         // Mask out the length value.
         symbol &= 511;
     } else {
-        let res = r.tables[table].tree_lookup(symbol, l.bit_buf, FAST_LOOKUP_BITS);
+        let res = r.table(table).tree_lookup(symbol, l.bit_buf, FAST_LOOKUP_BITS);
         symbol = res.0;
         code_len = res.1;
     };
@@ -777,6 +1493,61 @@ const fn end_of_input(flags: u32) -> Action {
     })
 }
 
+/// Try to copy a whole stored block in one go, bypassing the byte-wise header states.
+///
+/// This is only done when the block header and contents are fully available, either in
+/// the input or as whole bytes left in the bit buffer, and the contents fit in the output.
+/// Returns `false` without consuming anything otherwise, in which case the block is handled
+/// by the regular states.
+#[inline]
+fn copy_stored_block(
+    l: &mut LocalVars,
+    in_iter: &mut InputWrapper,
+    out_buf: &mut OutputBuffer,
+) -> bool {
+    if l.num_bits & 7 != 0 {
+        return false;
+    }
+    let bit_buf_bytes = l.bit_buf.to_le_bytes();
+    let buffered = &bit_buf_bytes[..(l.num_bits >> 3) as usize];
+    let input = in_iter.as_slice();
+    if buffered.len() + input.len() < 4 {
+        return false;
+    }
+
+    let mut header = [0; 4];
+    for (i, h) in header.iter_mut().enumerate() {
+        *h = match buffered.get(i) {
+            Some(&byte) => byte,
+            None => input[i - buffered.len()],
+        };
+    }
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let check = u16::from_le_bytes([header[2], header[3]]);
+    if length != !check {
+        return false;
+    }
+
+    let length = usize::from(length);
+    let buffered_data = buffered.get(4..).unwrap_or(&[]);
+    if buffered_data.len() > length {
+        // Part of the next block is in the bit buffer, leave that to the regular path.
+        return false;
+    }
+    let header_from_input = 4usize.saturating_sub(buffered.len());
+    let data_from_input = length - buffered_data.len();
+    if header_from_input + data_from_input > input.len() || length > out_buf.bytes_left() {
+        return false;
+    }
+
+    out_buf.write_slice(buffered_data);
+    out_buf.write_slice(&input[header_from_input..header_from_input + data_from_input]);
+    in_iter.advance(header_from_input + data_from_input);
+    l.bit_buf = 0;
+    l.num_bits = 0;
+    true
+}
+
 #[inline]
 fn undo_bytes(l: &mut LocalVars, max: u32) -> u32 {
     let res = cmp::min(l.num_bits >> 3, max);
@@ -846,33 +1617,33 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Option<Action> {
     loop {
         let bt = r.block_type as usize;
 
-        let code_sizes = match bt {
-            LITLEN_TABLE => &mut r.code_size_literal[..],
-            DIST_TABLE => &mut r.code_size_dist,
-            HUFFLEN_TABLE => &mut r.code_size_huffman,
+        let (code_sizes, look_up, tree): (&mut [u8], &mut [i16], &mut [i16]) = match bt {
+            LITLEN_TABLE | DIST_TABLE => {
+                let code_sizes = if bt == LITLEN_TABLE {
+                    &mut r.code_size_literal[..]
+                } else {
+                    &mut r.code_size_dist[..]
+                };
+                let table = &mut r.tables[bt];
+                (code_sizes, &mut table.look_up, &mut table.tree)
+            }
+            HUFFLEN_TABLE => (&mut r.code_size_huffman, &mut r.tables.code_length, &mut []),
             _ => return None,
         };
-        let table = &mut r.tables[bt];
 
         let mut total_symbols = [0u16; 16];
         // Next code - we use the odd length here to simplify a loop later.
         let mut next_code = [0u32; 17];
-        const INVALID_CODE: i16 = (1 << 9) | 286;
         // Set the values in the fast table to return a
         // non-zero length and an invalid symbol instead of zero
         // so that we do not have to have a check for a zero
         // code length in the hot code path later
         // and can instead error out on the invalid symbol check
         // on bogus input.
-        table.look_up.fill(INVALID_CODE);
-        // If we are initializing the huffman code length we can skip
-        // this since these codes can't be longer than 3 bits
-        // and thus only use the fast table and this table won't be accessed so
-        // there is no point clearing it.
-        // TODO: Avoid creating this table at all.
-        if bt != HUFFLEN_TABLE {
-            table.tree.fill(0);
-        }
+        look_up.fill(INVALID_CODE);
+        // The code length table has no tree since its codes can't be longer than 7 bits
+        // and thus only use the fast table.
+        tree.fill(0);
 
         let table_size = r.table_sizes[bt] as usize;
         if table_size > code_sizes.len() {
@@ -918,10 +1689,10 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Option<Action> {
         }
 
         let mut tree_next = -1;
-        for symbol_index in 0..table_size {
+        for (symbol_index, &code_size) in code_sizes[..table_size].iter().enumerate() {
             // Code sizes are limited to 15 according to the spec
             // It's already checked earlier but the compiler might not be smart enough to know that.
-            let code_size = code_sizes[symbol_index] & 15;
+            let code_size = code_size & 15;
             if code_size == 0 {
                 continue;
             }
@@ -931,7 +1702,7 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Option<Action> {
 
             let n = (cur_code & (u32::MAX >> (32 - code_size))) as u16;
 
-            let mut rev_code = if n < 512 {
+            let rev_code = if n < 512 {
                 // Using a lookup table
                 // for a small speedup here,
                 // Seems to only really make a difference on very short
@@ -942,46 +1713,9 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Option<Action> {
                 n.reverse_bits()
             } >> (16 - code_size);
 
-            if code_size <= FAST_LOOKUP_BITS {
-                let k = (i16::from(code_size) << 9) | symbol_index as i16;
-                while rev_code < FAST_LOOKUP_SIZE {
-                    table.look_up[rev_code as usize] = k;
-                    rev_code += 1 << code_size;
-                }
-                continue;
-            }
-
-            let mut tree_cur = table.look_up[(rev_code & (FAST_LOOKUP_SIZE - 1)) as usize];
-            if tree_cur == INVALID_CODE {
-                table.look_up[(rev_code & (FAST_LOOKUP_SIZE - 1)) as usize] = tree_next;
-                tree_cur = tree_next;
-                tree_next -= 2;
-            }
-
-            rev_code >>= FAST_LOOKUP_BITS - 1;
-            for _ in FAST_LOOKUP_BITS + 1..code_size {
-                rev_code >>= 1;
-                tree_cur -= (rev_code & 1) as i16;
-                let tree_index = (-tree_cur - 1) as usize;
-                if tree_index >= table.tree.len() {
-                    return None;
-                }
-                if table.tree[tree_index] == 0 {
-                    table.tree[tree_index] = tree_next;
-                    tree_cur = tree_next;
-                    tree_next -= 2;
-                } else {
-                    tree_cur = table.tree[tree_index];
-                }
-            }
-
-            rev_code >>= 1;
-            tree_cur -= (rev_code & 1) as i16;
-            let tree_index = (-tree_cur - 1) as usize;
-            if tree_index >= table.tree.len() {
+            if !insert_code(look_up, tree, symbol_index, code_size, rev_code, &mut tree_next) {
                 return None;
             }
-            table.tree[tree_index] = symbol_index as i16;
         }
 
         if r.block_type == HUFFLEN_TABLE as u8 {
@@ -990,6 +1724,7 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Option<Action> {
         }
 
         if r.block_type == LITLEN_TABLE as u8 {
+            init_literal_pairs(r);
             break;
         }
         r.block_type -= 1;
@@ -1000,6 +1735,35 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Option<Action> {
     Some(Action::Jump(DecodeLitlen))
 }
 
+/// Fill in the literal pair table from the fast lookup table of the literal/length table.
+///
+/// An entry is only created if both codes are literals found in the fast lookup table
+/// and their combined length fits in `LITERAL_PAIR_BITS`, so the index bits fully
+/// determine both symbols.
+fn init_literal_pairs(r: &mut DecompressorOxide) {
+    if LITERAL_PAIR_SIZE == 1 {
+        return;
+    }
+    let look_up = &r.tables[LITLEN_TABLE].look_up;
+    let fast_mask = FAST_LOOKUP_SIZE as usize - 1;
+    for (i, entry) in r.literal_pairs.iter_mut().enumerate() {
+        *entry = 0;
+        let first = look_up[i & fast_mask];
+        if first < 0 || (first & 511) >= 256 {
+            continue;
+        }
+        let first_len = (first >> 9) as u32;
+        let second = look_up[(i >> first_len) & fast_mask];
+        if second < 0 || (second & 511) >= 256 {
+            continue;
+        }
+        let total_len = first_len + (second >> 9) as u32;
+        if total_len <= LITERAL_PAIR_BITS {
+            *entry = (first & 255) as u32 | (((second & 255) as u32) << 8) | (total_len << 16);
+        }
+    }
+}
+
 // A helper macro for generating the state machine.
 //
 // As Rust doesn't have fallthrough on matches, we have to return to the match statement
@@ -1036,6 +1800,18 @@ fn transfer(
     match_len: usize,
     out_buf_size_mask: usize,
 ) {
+    // In a wrapping output buffer, the source of the match can run past the end of the
+    // buffer and continue at the start. Copy the part up to the end first, then the rest,
+    // so the wrap is handled once per match instead of masking the position of every byte.
+    // The destination never wraps, so the source is after it and a plain copy is fine for
+    // the first part, even if the two overlap.
+    if out_buf_size_mask != usize::MAX && source_pos + match_len > out_slice.len() {
+        let first = out_slice.len() - source_pos;
+        out_slice.copy_within(source_pos.., out_pos);
+        transfer(out_slice, 0, out_pos + first, match_len - first, usize::MAX);
+        return;
+    }
+
     // special case that comes up surprisingly often. in the case that `source_pos`
     // is 1 less than `out_pos`, we can say that the entire range will be the same
     // value and optimize this to be a simple `memset`
@@ -1045,24 +1821,16 @@ fn transfer(
         out_pos - source_pos
     };
 
-    // The last 3 bytes can wrap as those are dealt with separately at the end.
-    // Use wrapping_sub rather than saturating for performance reasons here as
-    // if source_pos + match_len  is < 3 we just want to jump to the end
-    // condition anyhow.
-    let not_wrapping = (out_buf_size_mask == usize::MAX)
-        || ((source_pos + match_len).wrapping_sub(3) < out_slice.len());
-
     let end_pos = ((match_len >> 2) * 4) + out_pos;
-    if not_wrapping && source_diff == 1 && out_pos > source_pos {
+    if source_diff == 1 && out_pos > source_pos {
         let end = (match_len >> 2) * 4 + out_pos;
         let init = out_slice[out_pos - 1];
         out_slice[out_pos..end].fill(init);
         out_pos = end;
         source_pos = end - 1;
     // if the difference between `source_pos` and `out_pos` is greater than 3,
-    // and we are not wrapping, we
-    // can do slightly better than the naive case by copying everything at once
-    } else if not_wrapping && out_pos > source_pos && (out_pos - source_pos >= 4) {
+    // we can do slightly better than the naive case by copying everything at once
+    } else if out_pos > source_pos && (out_pos - source_pos >= 4) {
         let end_pos = cmp::min(end_pos, out_slice.len().saturating_sub(3));
         while out_pos < end_pos {
             out_slice.copy_within(source_pos..=source_pos + 3, out_pos);
@@ -1076,12 +1844,12 @@ fn transfer(
             // makes the compiler able to optimize better.
             // Ideally we would find a safe way to remove them entirely.
             assert!(out_pos + 3 < out_slice.len());
-            assert!((source_pos + 3) & out_buf_size_mask < out_slice.len());
+            assert!(source_pos + 3 < out_slice.len());
 
-            out_slice[out_pos] = out_slice[source_pos & out_buf_size_mask];
-            out_slice[out_pos + 1] = out_slice[(source_pos + 1) & out_buf_size_mask];
-            out_slice[out_pos + 2] = out_slice[(source_pos + 2) & out_buf_size_mask];
-            out_slice[out_pos + 3] = out_slice[(source_pos + 3) & out_buf_size_mask];
+            out_slice[out_pos] = out_slice[source_pos];
+            out_slice[out_pos + 1] = out_slice[source_pos + 1];
+            out_slice[out_pos + 2] = out_slice[source_pos + 2];
+            out_slice[out_pos + 3] = out_slice[source_pos + 3];
             source_pos += 4;
             out_pos += 4;
         }
@@ -1089,19 +1857,19 @@ fn transfer(
 
     match match_len & 3 {
         0 => (),
-        1 => out_slice[out_pos] = out_slice[source_pos & out_buf_size_mask],
+        1 => out_slice[out_pos] = out_slice[source_pos],
         2 => {
             assert!(out_pos + 1 < out_slice.len());
-            assert!((source_pos + 1) & out_buf_size_mask < out_slice.len());
-            out_slice[out_pos] = out_slice[source_pos & out_buf_size_mask];
-            out_slice[out_pos + 1] = out_slice[(source_pos + 1) & out_buf_size_mask];
+            assert!(source_pos + 1 < out_slice.len());
+            out_slice[out_pos] = out_slice[source_pos];
+            out_slice[out_pos + 1] = out_slice[source_pos + 1];
         }
         3 => {
             assert!(out_pos + 2 < out_slice.len());
-            assert!((source_pos + 2) & out_buf_size_mask < out_slice.len());
-            out_slice[out_pos] = out_slice[source_pos & out_buf_size_mask];
-            out_slice[out_pos + 1] = out_slice[(source_pos + 1) & out_buf_size_mask];
-            out_slice[out_pos + 2] = out_slice[(source_pos + 2) & out_buf_size_mask];
+            assert!(source_pos + 2 < out_slice.len());
+            out_slice[out_pos] = out_slice[source_pos];
+            out_slice[out_pos + 1] = out_slice[source_pos + 1];
+            out_slice[out_pos + 2] = out_slice[source_pos + 2];
         }
         _ => unreachable!(),
     }
@@ -1140,6 +1908,21 @@ fn apply_match(
         return;
     }
 
+    // Matches that overlap their source at a distance of 8 or more can still be copied in
+    // wide chunks. Writing past the end of the match is fine unless the output buffer wraps,
+    // as then the bytes after the match are still part of the history.
+    if match_len > dist
+        && copy_match_wide(
+            out_slice,
+            source_pos,
+            out_pos,
+            match_len,
+            out_buf_size_mask == usize::MAX,
+        )
+    {
+        return;
+    }
+
     if cfg!(not(any(target_arch = "x86", target_arch = "x86_64"))) {
         // The copy from slice code seems to not give any added performance at least on
         // armv7 so transfer manually
@@ -1187,7 +1970,13 @@ fn decompress_fast(
     // Make a local copy of the most used variables, to avoid having to update and read from values
     // in a random memory location and to encourage more register use.
     let mut l = *local_vars;
+    let mut max_match_distance = r.stats.max_match_distance;
     let mut state;
+    let length_decode = if is_deflate64(flags) {
+        &LENGTH_DECODE_DEFLATE64
+    } else {
+        &LENGTH_DECODE
+    };
 
     let status: TINFLStatus = 'o: loop {
         state = State::DecodeLitlen;
@@ -1205,7 +1994,47 @@ fn decompress_fast(
 
             fill_bit_buffer(&mut l, in_iter);
 
-            let (symbol, code_len) = r.tables[LITLEN_TABLE].lookup(l.bit_buf);
+            // Try to decode two literals with a single lookup. The pair table is only built
+            // for dynamic blocks.
+            let pair = r.literal_pairs[(l.bit_buf as usize) & (LITERAL_PAIR_SIZE - 1)];
+            if pair != 0 && !r.tables.fixed {
+                // Keep decoding pairs into a small staging buffer for as long as the bits
+                // already in the bit buffer allow, then write the whole run with one copy.
+                // This stays within the input budget above as no refill happens here, and
+                // within the output budget as the run is at most `LITERAL_RUN_MAX` bytes.
+                let mut run = [0u8; LITERAL_RUN_MAX];
+                let mut run_len = 0;
+                let mut pair = pair;
+                loop {
+                    let code_len = pair >> 16;
+                    l.bit_buf >>= code_len;
+                    l.num_bits -= code_len;
+                    run[run_len] = pair as u8;
+                    run[run_len + 1] = (pair >> 8) as u8;
+                    run_len += 2;
+                    if run_len == LITERAL_RUN_MAX || l.num_bits < LITERAL_PAIR_BITS {
+                        break;
+                    }
+                    pair = r.literal_pairs[(l.bit_buf as usize) & (LITERAL_PAIR_SIZE - 1)];
+                    if pair == 0 {
+                        break;
+                    }
+                }
+                // There is always room for the whole staging buffer, so copy all of it
+                // (a fixed size copy is cheaper) and only keep the decoded part. Not if the
+                // output buffer wraps, as then the bytes after the run are still part of the
+                // history.
+                if out_buf_size_mask == usize::MAX {
+                    let position = out_buf.position();
+                    out_buf.write_slice(&run);
+                    out_buf.set_position(position + run_len);
+                } else {
+                    out_buf.write_slice(&run[..run_len]);
+                }
+                continue;
+            }
+
+            let (symbol, code_len) = r.table(LITLEN_TABLE).lookup(l.bit_buf);
             l.counter = symbol as u32;
             l.bit_buf >>= code_len;
             l.num_bits -= code_len;
@@ -1220,7 +2049,7 @@ fn decompress_fast(
                     fill_bit_buffer(&mut l, in_iter);
                 }
 
-                let (symbol, code_len) = r.tables[LITLEN_TABLE].lookup(l.bit_buf);
+                let (symbol, code_len) = r.table(LITLEN_TABLE).lookup(l.bit_buf);
                 l.bit_buf >>= code_len;
                 l.num_bits -= code_len;
                 // The previous symbol was a literal, so write it directly and check
@@ -1239,34 +2068,40 @@ fn decompress_fast(
 
         // Mask the top bits since they may contain length info.
         l.counter &= 511;
-        if l.counter == 256 {
-            // We hit the end of block symbol.
-            state.begin(BlockDone);
-            break 'o TINFLStatus::Done;
-        } else if l.counter > 285 {
-            // Invalid code.
-            // We already verified earlier that the code is > 256.
-            state.begin(InvalidLitlen);
-            break 'o TINFLStatus::Failed;
+        // The symbol is a length code, the end of block symbol or an invalid code.
+        // The latter two are flagged in the table so they only need one check here.
+        // # Optimization
+        // Mask the value to avoid bounds checks, the compiler isn't able to know that the
+        // value won't wrap around here.
+        let length_entry = length_decode[(l.counter.wrapping_sub(257)) as usize & BASE_EXTRA_MASK];
+        if length_entry & DECODE_SPECIAL != 0 {
+            if l.counter == 256 {
+                // We hit the end of block symbol.
+                state.begin(BlockDone);
+                break 'o TINFLStatus::Done;
+            } else if l.counter == 285 && is_deflate64(flags) {
+                // Leave the long Deflate64 match to the main loop.
+                state.begin(HuffDecodeOuterLoop1);
+                break 'o TINFLStatus::Done;
+            } else {
+                // Invalid code.
+                // We already verified earlier that the code is > 256.
+                state.begin(InvalidLitlen);
+                break 'o TINFLStatus::Failed;
+            }
         } else {
-            // The symbol was a length code.
-            // # Optimization
-            // Mask the value to avoid bounds checks
-            // While the maximum is checked, the compiler isn't able to know that the
-            // value won't wrap around here.
-            l.num_extra = LENGTH_EXTRA[(l.counter - 257) as usize & BASE_EXTRA_MASK];
-            l.counter = u32::from(LENGTH_BASE[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
             // Length and distance codes have a number of extra bits depending on
             // the base, which together with the base gives us the exact value.
+            // Taking zero extra bits leaves everything unchanged, so there is no need
+            // to branch on whether there are any.
 
             // We need to make sure we have at least 33 (so min 5 bytes) bits in the buffer at this spot.
             fill_bit_buffer(&mut l, in_iter);
-            if l.num_extra != 0 {
-                let extra_bits = l.bit_buf & ((1 << l.num_extra) - 1);
-                l.bit_buf >>= l.num_extra;
-                l.num_bits -= u32::from(l.num_extra);
-                l.counter += extra_bits as u32;
-            }
+            let num_extra = length_entry >> 16;
+            let extra_bits = l.bit_buf & ((1 << num_extra) - 1);
+            l.bit_buf >>= num_extra;
+            l.num_bits -= num_extra;
+            l.counter = (length_entry & 0xffff) + extra_bits as u32;
 
             // We found a length code, so a distance code should follow.
 
@@ -1274,25 +2109,32 @@ fn decompress_fast(
                 fill_bit_buffer(&mut l, in_iter);
             }
 
-            let (mut symbol, code_len) = r.tables[DIST_TABLE].lookup(l.bit_buf);
-            symbol &= 511;
+            let (symbol, code_len) = r.table(DIST_TABLE).lookup(l.bit_buf);
             l.bit_buf >>= code_len;
             l.num_bits -= code_len;
-            if symbol > 29 {
+            // Distance symbols are below 32, apart from the invalid code marker (286)
+            // which ends up at 30 once masked, so this also catches that.
+            let dist_entry = DIST_DECODE[symbol as usize & BASE_EXTRA_MASK];
+            if dist_entry & DECODE_SPECIAL != 0 {
+                if (symbol == 30 || symbol == 31) && is_deflate64(flags) {
+                    // Let the main loop read the extra bits of the Deflate64 distance.
+                    l.num_extra = num_extra_bits_for_distance_code(symbol as u8);
+                    l.dist = u32::from(DEFLATE64_DIST_BASE[symbol as usize & 1]);
+                    state.begin(ReadExtraBitsDistance);
+                    break 'o TINFLStatus::Done;
+                }
                 state.begin(InvalidDist);
                 break 'o TINFLStatus::Failed;
             }
 
-            l.num_extra = num_extra_bits_for_distance_code(symbol as u8);
-            l.dist = u32::from(DIST_BASE[symbol as usize]);
-
-            if l.num_extra != 0 {
-                fill_bit_buffer(&mut l, in_iter);
-                let extra_bits = l.bit_buf & ((1 << l.num_extra) - 1);
-                l.bit_buf >>= l.num_extra;
-                l.num_bits -= u32::from(l.num_extra);
-                l.dist += extra_bits as u32;
-            }
+            // Up to 13 extra bits may be needed here, which the bit buffer might not have
+            // left after the length and distance codes.
+            fill_bit_buffer(&mut l, in_iter);
+            let num_extra = dist_entry >> 16;
+            let extra_bits = l.bit_buf & ((1 << num_extra) - 1);
+            l.bit_buf >>= num_extra;
+            l.num_bits -= num_extra;
+            l.dist = (dist_entry & 0xffff) + extra_bits as u32;
 
             let position = out_buf.position();
             if (l.dist as usize > out_buf.position()
@@ -1304,6 +2146,7 @@ fn decompress_fast(
                 state.begin(DistanceOutOfBounds);
                 break TINFLStatus::Failed;
             }
+            max_match_distance = cmp::max(max_match_distance, l.dist);
 
             apply_match(
                 out_buf.get_mut(),
@@ -1318,6 +2161,7 @@ fn decompress_fast(
     };
 
     *local_vars = l;
+    r.stats.max_match_distance = max_match_distance;
     (status, state)
 }
 
@@ -1340,6 +2184,7 @@ fn decompress_fast(
 ///   wrapping manner, and it's size is required to be a power of 2.
 /// * The decompression function normally needs access to 32KiB of the previously decompressed data
 ///   (or to the beginning of the decompressed data if less than 32KiB has been decompressed.)
+///   Deflate64 needs 64KiB.
 ///     - If this data is not available, decompression may fail.
 ///     - Some deflate compressors allow specifying a window size which limits match distances to
 ///       less than this, or alternatively an RLE mode where matches will only refer to the previous byte
@@ -1377,8 +2222,21 @@ pub fn decompress(
     // Ensure the output buffer's size is a power of 2, unless the output buffer
     // is large enough to hold the entire output file (in which case it doesn't
     // matter).
-    // Also make sure that the output buffer position is not past the end of the output buffer.
-    if (out_buf_size_mask.wrapping_add(1) & out_buf_size_mask) != 0 || out_pos > out.len() {
+    // Also make sure that the output buffer position is not past the end of the output buffer,
+    // and that the rest of a match that didn't fit last time can still be copied.
+    r.param_error = if (out_buf_size_mask.wrapping_add(1) & out_buf_size_mask) != 0 {
+        Some(ParamError::OutputSizeNotPowerOfTwo)
+    } else if out_pos > out.len() {
+        Some(ParamError::OutPosOutOfBounds)
+    } else if r.state == State::WriteLenBytesToEnd
+        && (r.dist as usize > out.len()
+            || (flags & TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF != 0 && r.dist as usize > out_pos))
+    {
+        Some(ParamError::MatchOutOfBounds)
+    } else {
+        None
+    };
+    if r.param_error.is_some() {
         return (TINFLStatus::BadParam, 0, 0);
     }
 
@@ -1410,6 +2268,9 @@ pub fn decompress(
                 r.z_header1 = 0;
                 r.z_adler32 = 1;
                 r.check_adler32 = 1;
+                r.total_out = 0;
+                r.adler32_mismatch = None;
+                r.stats = DecodeStats::default();
                 if flags & TINFL_FLAG_PARSE_ZLIB_HEADER != 0 {
                     Action::Jump(State::ReadZlibCmf)
                 } else {
@@ -1436,13 +2297,23 @@ pub fn decompress(
                 read_bits(&mut l, 3, &mut in_iter, flags, |l, bits| {
                     r.finish = (bits & 1) as u8;
                     r.block_type = ((bits >> 1) & 3) as u8;
+                    match r.block_type {
+                        0 => r.stats.stored_blocks += 1,
+                        1 => r.stats.static_blocks += 1,
+                        2 => r.stats.dynamic_blocks += 1,
+                        _ => {}
+                    }
                     match r.block_type {
                         0 => Action::Jump(BlockTypeNoCompression),
                         1 => {
+                            // The fixed tables are built already.
                             start_static_table(r);
-                            init_tree(r, l).unwrap_or(Action::End(TINFLStatus::Failed))
+                            r.tables.fixed = true;
+                            l.counter = 0;
+                            Action::Jump(DecodeLitlen)
                         },
                         2 => {
+                            r.tables.fixed = false;
                             l.counter = 0;
                             Action::Jump(ReadTableSizes)
                         },
@@ -1462,7 +2333,10 @@ pub fn decompress(
 
             // Check that the raw block header is correct.
             RawHeader => generate_state!(state, 'state_machine, {
-                if l.counter < 4 {
+                if l.counter == 0 && copy_stored_block(&mut l, &mut in_iter, &mut out_buf) {
+                    // The whole block was available, so it has already been copied.
+                    Action::Jump(BlockDone)
+                } else if l.counter < 4 {
                     // Read block length and block length check.
                     if l.num_bits != 0 {
                         read_bits(&mut l, 8, &mut in_iter, flags, |l, bits| {
@@ -1579,7 +2453,9 @@ pub fn decompress(
                     // NOTE this the final sizes after adding back predefined values, not
                     // raw value in the data.
                     // See miniz_oxide issue #130 and https://github.com/madler/zlib/issues/82.
-                    if r.table_sizes[LITLEN_TABLE] <= 286 && r.table_sizes[DIST_TABLE] <= 30 {
+                    // Deflate64 uses all 32 distance codes.
+                    let max_dist = if is_deflate64(flags) { 32 } else { 30 };
+                    if r.table_sizes[LITLEN_TABLE] <= 286 && r.table_sizes[DIST_TABLE] <= max_dist {
                         Action::Jump(ReadHufflenTableCodeSize)
                     }
                     else {
@@ -1646,7 +2522,11 @@ pub fn decompress(
                         .copy_from_slice(&r.len_codes[dist_table_start..dist_table_end]);
 
                     r.block_type -= 1;
-                    init_tree(r, &mut l).unwrap_or(Action::End(TINFLStatus::Failed))
+                    let action = init_tree(r, &mut l);
+                    if action.is_some() {
+                        r.stats.dynamic_trees += 1;
+                    }
+                    action.unwrap_or(Action::End(TINFLStatus::Failed))
                 }
             }),
 
@@ -1728,7 +2608,7 @@ pub fn decompress(
                 } else {
                     fill_bit_buffer(&mut l, &mut in_iter);
 
-                    let (symbol, code_len) = r.tables[LITLEN_TABLE].lookup(l.bit_buf);
+                    let (symbol, code_len) = r.table(LITLEN_TABLE).lookup(l.bit_buf);
 
                     l.counter = symbol as u32;
                     l.bit_buf >>= code_len;
@@ -1744,7 +2624,7 @@ pub fn decompress(
                             fill_bit_buffer(&mut l, &mut in_iter);
                         }
 
-                        let (symbol, code_len) = r.tables[LITLEN_TABLE].lookup(l.bit_buf);
+                        let (symbol, code_len) = r.table(LITLEN_TABLE).lookup(l.bit_buf);
 
                             l.bit_buf >>= code_len;
                             l.num_bits -= code_len;
@@ -1789,6 +2669,10 @@ pub fn decompress(
                     // Invalid code.
                     // We already verified earlier that the code is > 256.
                     Action::Jump(InvalidLitlen)
+                } else if l.counter == 285 && is_deflate64(flags) {
+                    l.num_extra = DEFLATE64_LENGTH_EXTRA;
+                    l.counter = u32::from(DEFLATE64_LENGTH_BASE);
+                    Action::Jump(ReadExtraBitsLitlen)
                 } else {
                     // # Optimization
                     // Mask the value to avoid bounds checks
@@ -1827,6 +2711,11 @@ pub fn decompress(
                     // overflow if it's converted after the check.
                     let symbol = symbol as usize;
                     if symbol > 29 {
+                        if symbol < 32 && is_deflate64(flags) {
+                            l.num_extra = num_extra_bits_for_distance_code(symbol as u8);
+                            l.dist = u32::from(DEFLATE64_DIST_BASE[symbol & 1]);
+                            return Action::Jump(ReadExtraBitsDistance)
+                        }
                         // Invalid distance code.
                         return Action::Jump(InvalidDist)
                     }
@@ -1857,6 +2746,7 @@ pub fn decompress(
                     // the start of the decoded data, so we can't continue.
                     Action::Jump(DistanceOutOfBounds)
                 } else {
+                    r.stats.max_match_distance = cmp::max(r.stats.max_match_distance, l.dist);
                     let out_pos = out_buf.position();
                     let source_pos = out_buf.position()
                         .wrapping_sub(l.dist as usize) & out_buf_size_mask;
@@ -2010,6 +2900,7 @@ pub fn decompress(
     r.num_extra = l.num_extra;
 
     r.bit_buf &= ((1 as BitBuffer) << r.num_bits) - 1;
+    r.total_out += (out_buf.position() - out_pos) as u64;
 
     // If this is a zlib stream, and update the adler32 checksum with the decompressed bytes if
     // requested.
@@ -2032,6 +2923,11 @@ pub fn decompress(
                 && r.check_adler32 != r.z_adler32
             {
                 status = TINFLStatus::Adler32Mismatch;
+                r.adler32_mismatch = Some(Adler32Mismatch {
+                    expected: r.z_adler32,
+                    computed: r.check_adler32,
+                    bytes_decoded: r.total_out,
+                });
             }
         }
     }
@@ -2160,6 +3056,12 @@ mod test {
 
         assert_eq!(masked_lookup(dt, 0), (0, 5));
         assert_eq!(masked_lookup(dt, 20), (5, 5));
+
+        // The tables built at compile time have to be the same.
+        for (table, fixed) in d.tables.huffman.iter().zip(FIXED_TABLES.iter()) {
+            assert_eq!(table.look_up, fixed.look_up);
+            assert_eq!(table.tree, fixed.tree);
+        }
     }
 
     // Only run this test with alloc enabled as it uses a larger buffer.
@@ -2313,6 +3215,83 @@ mod test {
         assert!(res == (TINFLStatus::HasMoreOutput, 2, 0));
     }
 
+    #[cfg(feature = "with-alloc")]
+    #[test]
+    fn bad_param_reasons() {
+        let data = b"abcdefgh".repeat(100);
+        let encoded = crate::deflate::compress_to_vec(&data, 6);
+        let flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let mut out = [0u8; 40];
+
+        let mut r = DecompressorOxide::new();
+        let res = decompress(
+            &mut r,
+            &encoded,
+            &mut out[..20],
+            0,
+            0,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(res.0, TINFLStatus::BadParam);
+        assert_eq!(r.param_error(), Some(ParamError::OutputSizeNotPowerOfTwo));
+        let res = decompress(
+            &mut r,
+            &encoded,
+            &mut out,
+            41,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(res.0, TINFLStatus::BadParam);
+        assert_eq!(r.param_error(), Some(ParamError::OutPosOutOfBounds));
+
+        // Stop in the middle of the first match, which copies from 8 bytes back.
+        let mut r = DecompressorOxide::new();
+        let (status, in_consumed, out_written) = decompress(
+            &mut r,
+            &encoded,
+            &mut out[..20],
+            0,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(status, TINFLStatus::HasMoreOutput);
+        assert_eq!(r.param_error(), None);
+        assert_eq!(r.state, State::WriteLenBytesToEnd);
+        let encoded = &encoded[in_consumed..];
+
+        // Going back to the start of a non-wrapping buffer would need to copy from before it.
+        let res = decompress(&mut r, encoded, &mut out, 0, flags, &mut 0, &mut 0, |_| {});
+        assert_eq!(res, (TINFLStatus::BadParam, 0, 0));
+        assert_eq!(r.param_error(), Some(ParamError::MatchOutOfBounds));
+        // Neither can a wrapping buffer smaller than the distance.
+        let res = decompress(&mut r, encoded, &mut out[..4], 0, 0, &mut 0, &mut 0, |_| {});
+        assert_eq!(res, (TINFLStatus::BadParam, 0, 0));
+        assert_eq!(r.param_error(), Some(ParamError::MatchOutOfBounds));
+
+        // The decompressor is left as it was, so it can carry on from the right position.
+        let (status, _, written) = decompress(
+            &mut r,
+            encoded,
+            &mut out,
+            out_written,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(status, TINFLStatus::HasMoreOutput);
+        assert_eq!(r.param_error(), None);
+        assert_eq!(out_written + written, out.len());
+        assert_eq!(&out[..], &data[..out.len()]);
+    }
+
     #[test]
     fn dist_extra_bits() {
         use self::num_extra_bits_for_distance_code;
@@ -2358,6 +3337,466 @@ mod test {
         assert!(status != BadTotalSymbols);
     }
 
+    #[cfg(feature = "block-boundary")]
+    #[test]
+    fn block_boundary_state_bytes_roundtrip() {
+        let st = BlockBoundaryState {
+            num_bits: 5,
+            bit_buf: 0b10110,
+            z_header0: 0x78,
+            z_header1: 0x9c,
+            check_adler32: 0x1b65_0413,
+        };
+        let bytes = st.to_bytes();
+        assert_eq!(BlockBoundaryState::from_bytes(&bytes), Some(st));
+
+        assert_eq!(BlockBoundaryState::from_bytes(&bytes[1..]), None);
+        let mut bad_version = bytes;
+        bad_version[0] = 0;
+        assert_eq!(BlockBoundaryState::from_bytes(&bad_version), None);
+        let mut bad_bits = bytes;
+        bad_bits[1] = 8;
+        assert_eq!(BlockBoundaryState::from_bytes(&bad_bits), None);
+    }
+
+    #[cfg(all(
+        feature = "with-alloc",
+        feature = "literal-pairs",
+        not(feature = "reduced-memory")
+    ))]
+    #[test]
+    fn literal_pairs() {
+        // Random data over a small alphabet gives short literal codes and few matches.
+        let data = crate::test_util::random(20_000, 4);
+        let encoded = crate::deflate::compress_to_vec(&data, 6);
+
+        let mut r = DecompressorOxide::new();
+        let mut out = vec![0; data.len()];
+        let (status, in_pos, out_pos) = decompress(
+            &mut r,
+            &encoded,
+            &mut out,
+            0,
+            TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(in_pos, encoded.len());
+        assert_eq!(&out[..out_pos], &data[..]);
+        assert!(r.literal_pairs.iter().any(|&p| p != 0));
+
+        // Every pair entry has to agree with two single lookups.
+        for (i, &pair) in r.literal_pairs.iter().enumerate() {
+            if pair == 0 {
+                continue;
+            }
+            let bits = i as BitBuffer;
+            let (first, first_len) = r.tables[LITLEN_TABLE].lookup(bits);
+            let (second, second_len) = r.tables[LITLEN_TABLE].lookup(bits >> first_len);
+            assert_eq!(pair as u8, (first & 511) as u8);
+            assert_eq!((pair >> 8) as u8, (second & 511) as u8);
+            assert_eq!(pair >> 16, first_len + second_len);
+        }
+    }
+
+    #[cfg(feature = "with-alloc")]
+    #[test]
+    fn wrapping_buffer_far_matches() {
+        // A dynamic block written by hand, as the compressor doesn't produce matches at the
+        // full window distance. All the codes are 5 bits long, so two literals fit in a pair
+        // table entry.
+        struct Bits {
+            out: Vec<u8>,
+            acc: u64,
+            len: u32,
+        }
+        impl Bits {
+            fn put(&mut self, value: u32, len: u32) {
+                self.acc |= u64::from(value) << self.len;
+                self.len += len;
+                while self.len >= 8 {
+                    self.out.push(self.acc as u8);
+                    self.acc >>= 8;
+                    self.len -= 8;
+                }
+            }
+            /// Huffman codes are sent starting from the most significant bit.
+            fn code(&mut self, code: u32, len: u32) {
+                self.put(code.reverse_bits() >> (32 - len), len);
+            }
+            fn zeros(&mut self, mut n: u32) {
+                while n >= 11 {
+                    let run = n.min(138);
+                    self.code(3, 2);
+                    self.put(run - 11, 7);
+                    n -= run;
+                }
+                for _ in 0..n {
+                    self.code(0, 2);
+                }
+            }
+        }
+
+        // Literals 0-13 and 'a'-'p', the end of block and length 258, in code order.
+        let symbols: Vec<u16> = (0..14).chain(97..113).chain([256, 285]).collect();
+        let mut bits = Bits {
+            out: Vec::new(),
+            acc: 0,
+            len: 0,
+        };
+        // Last block, dynamic, 286 literal/length codes, 30 distance codes, 18 code length
+        // codes of which 0, 1, 5 and 18 have codes of 2 bits.
+        bits.put(1, 1);
+        bits.put(2, 2);
+        bits.put(29, 5);
+        bits.put(29, 5);
+        bits.put(14, 4);
+        for len in [0, 0, 2, 2, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2] {
+            bits.put(len, 3);
+        }
+        let five = |bits: &mut Bits, n| (0..n).for_each(|_| bits.code(2, 2));
+        five(&mut bits, 14);
+        bits.zeros(83);
+        five(&mut bits, 16);
+        bits.zeros(143);
+        five(&mut bits, 1);
+        bits.zeros(28);
+        five(&mut bits, 1);
+        // Distance codes 0 and 29 of 1 bit.
+        bits.code(1, 2);
+        bits.zeros(28);
+        bits.code(1, 2);
+
+        // A window of literals, then short runs of literals each followed by a match at the
+        // full window distance, which starts at the byte after the run in the ring buffer.
+        let mut picks = crate::test_util::random(TINFL_LZ_DICT_SIZE + 1200, 30).into_iter();
+        let mut data = Vec::new();
+        let mut literals = |bits: &mut Bits, data: &mut Vec<u8>, n| {
+            for _ in 0..n {
+                let i = usize::from(picks.next().unwrap());
+                bits.code(i as u32, 5);
+                data.push(symbols[i] as u8);
+            }
+        };
+        literals(&mut bits, &mut data, TINFL_LZ_DICT_SIZE);
+        for run in (0..300).map(|i| 2 + 2 * (i % 3)) {
+            literals(&mut bits, &mut data, run);
+            bits.code(31, 5);
+            bits.code(1, 1);
+            bits.put(TINFL_LZ_DICT_SIZE as u32 - 24_577, 13);
+            let start = data.len() - TINFL_LZ_DICT_SIZE;
+            data.extend_from_within(start..start + 258);
+        }
+        bits.code(30, 5);
+        bits.put(0, 7);
+        let encoded = bits.out;
+
+        let mut window = vec![0; TINFL_LZ_DICT_SIZE];
+        let mut r = DecompressorOxide::new();
+        let mut output = Vec::new();
+        let (mut in_pos, mut out_pos) = (0, 0);
+        loop {
+            let (status, read, written) = decompress(
+                &mut r,
+                &encoded[in_pos..],
+                &mut window,
+                out_pos,
+                0,
+                &mut 0,
+                &mut 0,
+                |_| {},
+            );
+            in_pos += read;
+            output.extend_from_slice(&window[out_pos..out_pos + written]);
+            out_pos = (out_pos + written) & (TINFL_LZ_DICT_SIZE - 1);
+            match status {
+                TINFLStatus::Done => break,
+                TINFLStatus::HasMoreOutput => {}
+                status => panic!("{status:?}"),
+            }
+        }
+        assert_eq!(r.stats().max_match_distance, TINFL_LZ_DICT_SIZE as u32);
+        assert!(output == data);
+    }
+
+    #[cfg(feature = "with-alloc")]
+    #[test]
+    fn stored_block_fast_path() {
+        use crate::deflate::core::{compress, create_comp_flags_from_zip_params, TDEFLFlush};
+
+        // A huffman block followed by a sync flush (an empty stored block) and then
+        // incompressible data that ends up in stored blocks, so the stored block headers
+        // start out in the bit buffer.
+        let data = crate::test_util::random(100_000, 256);
+        let mut d = crate::deflate::core::CompressorOxide::new(
+            create_comp_flags_from_zip_params(6, 0, 0),
+        );
+        let mut encoded = vec![0; data.len() * 2];
+        let (_, _, head) = compress(&mut d, b"Deflate late", &mut encoded, TDEFLFlush::Sync);
+        let (_, _, tail) = compress(&mut d, &data, &mut encoded[head..], TDEFLFlush::Finish);
+        encoded.truncate(head + tail);
+
+        let mut r = DecompressorOxide::new();
+        let mut out = vec![0; data.len() + 12];
+        let (status, in_pos, out_pos) = decompress(
+            &mut r,
+            &encoded,
+            &mut out,
+            0,
+            TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(in_pos, encoded.len());
+        assert_eq!(&out[..12], b"Deflate late");
+        assert_eq!(&out[12..out_pos], &data[..]);
+
+        // Feeding the input in small pieces has to give the same result.
+        let mut r = DecompressorOxide::new();
+        let mut out_small = vec![0; out.len()];
+        let (mut in_pos, mut out_pos) = (0, 0);
+        loop {
+            let end = cmp::min(in_pos + 3, encoded.len());
+            let flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF
+                | if end < encoded.len() { TINFL_FLAG_HAS_MORE_INPUT } else { 0 };
+            let (status, read, written) = decompress(
+                &mut r,
+                &encoded[in_pos..end],
+                &mut out_small,
+                out_pos,
+                flags,
+                &mut 0,
+                &mut 0,
+                |_| {},
+            );
+            in_pos += read;
+            out_pos += written;
+            if status == TINFLStatus::Done {
+                break;
+            }
+            assert_eq!(status, TINFLStatus::NeedsMoreInput);
+        }
+        assert_eq!(out_small, out);
+    }
+
+    #[cfg(all(feature = "block-boundary", feature = "with-alloc"))]
+    #[test]
+    fn block_boundary_wrapping_buffer() {
+        let data = crate::test_util::random(200_000, 16);
+
+        for zlib in [false, true] {
+            let encoded = if zlib {
+                crate::deflate::compress_to_vec_zlib(&data, 6)
+            } else {
+                crate::deflate::compress_to_vec(&data, 6)
+            };
+            let flags = TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY
+                | if zlib { TINFL_FLAG_PARSE_ZLIB_HEADER } else { 0 };
+
+            // A wrapping buffer of the window size, drained whenever it fills up.
+            let mut window = vec![0; TINFL_LZ_DICT_SIZE];
+            let mut r = DecompressorOxide::new();
+            let mut output = Vec::new();
+            let (mut in_pos, mut out_pos) = (0, 0);
+            let mut boundaries = 0;
+            loop {
+                let (status, read, written) = decompress(
+                    &mut r,
+                    &encoded[in_pos..],
+                    &mut window,
+                    out_pos,
+                    flags,
+                    &mut 0,
+                    &mut 0,
+                    |_| {},
+                );
+                in_pos += read;
+                output.extend_from_slice(&window[out_pos..out_pos + written]);
+                out_pos = (out_pos + written) & (TINFL_LZ_DICT_SIZE - 1);
+                match status {
+                    TINFLStatus::Done => break,
+                    TINFLStatus::HasMoreOutput => {}
+                    TINFLStatus::BlockBoundary => {
+                        // Continue with a new decompressor from the saved state, with the
+                        // window still in the buffer, or every other time moved so that it
+                        // ends at the end of the buffer.
+                        let state = r.block_boundary_state().unwrap();
+                        r = DecompressorOxide::from_block_boundary_state(&state);
+                        if boundaries % 2 == 1 {
+                            window.rotate_left(out_pos);
+                            out_pos = 0;
+                        }
+                        boundaries += 1;
+                    }
+                    status => panic!("{status:?}"),
+                }
+            }
+            assert!(boundaries > 1);
+            assert_eq!(in_pos, encoded.len());
+            assert!(output == data);
+        }
+    }
+
+    #[test]
+    fn packed_decode_tables() {
+        for symbol in 257..=287u32 {
+            let entry = LENGTH_DECODE[symbol.wrapping_sub(257) as usize & BASE_EXTRA_MASK];
+            if symbol > 285 {
+                assert!(entry & DECODE_SPECIAL != 0);
+            } else {
+                let i = (symbol - 257) as usize;
+                assert_eq!(entry & 0xffff, u32::from(LENGTH_BASE[i]));
+                assert_eq!(entry >> 16, u32::from(LENGTH_EXTRA[i]));
+            }
+        }
+        assert!(LENGTH_DECODE[256u32.wrapping_sub(257) as usize & BASE_EXTRA_MASK] & DECODE_SPECIAL != 0);
+
+        for symbol in 0..32usize {
+            let entry = DIST_DECODE[symbol];
+            if symbol > 29 {
+                assert!(entry & DECODE_SPECIAL != 0);
+            } else {
+                assert_eq!(entry & 0xffff, u32::from(DIST_BASE[symbol]));
+                assert_eq!(entry >> 16, u32::from(num_extra_bits_for_distance_code(symbol as u8)));
+            }
+        }
+    }
+
+    #[cfg(feature = "with-alloc")]
+    #[test]
+    fn consecutive_fixed_blocks() {
+        use crate::deflate::core::{compress, create_comp_flags_from_zip_params, TDEFLFlush};
+
+        // Short sync flushed chunks are each emitted as a fixed huffman block, with a
+        // dynamic block in the middle to check switching between the fixed and built tables.
+        let long = crate::test_util::text(5000);
+        let chunks: [&[u8]; 4] = [b"Deflate late", b"Deflate later", &long, b"Deflate latest"];
+        let mut d = crate::deflate::core::CompressorOxide::new(
+            create_comp_flags_from_zip_params(6, 0, 0),
+        );
+        let mut encoded = vec![0; 20_000];
+        let mut pos = 0;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let flush = if i == chunks.len() - 1 {
+                TDEFLFlush::Finish
+            } else {
+                TDEFLFlush::Sync
+            };
+            let (_, _, written) = compress(&mut d, chunk, &mut encoded[pos..], flush);
+            pos += written;
+        }
+        encoded.truncate(pos);
+
+        let mut r = DecompressorOxide::new();
+        let mut out = vec![0; 6000];
+        let (status, _, out_pos) = decompress(
+            &mut r,
+            &encoded,
+            &mut out,
+            0,
+            TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(&out[..out_pos], &chunks.concat()[..]);
+        assert!(r.tables.fixed);
+    }
+
+    #[test]
+    fn wide_match_copy() {
+        let mut expected = [0u8; 600];
+        let mut actual = [0u8; 600];
+        for dist in 1..40 {
+            for match_len in 3..=258 {
+                for overcopy in [false, true] {
+                    for (i, (e, a)) in expected.iter_mut().zip(actual.iter_mut()).enumerate() {
+                        *e = (i * 7) as u8;
+                        *a = (i * 7) as u8;
+                    }
+                    let out_pos = 300;
+                    for i in 0..match_len {
+                        expected[out_pos + i] = expected[out_pos - dist + i];
+                    }
+                    let copied =
+                        copy_match_wide(&mut actual, out_pos - dist, out_pos, match_len, overcopy);
+                    assert_eq!(copied, dist >= 8);
+                    if copied {
+                        let end = out_pos + match_len;
+                        assert_eq!(&actual[..end], &expected[..end]);
+                        if !overcopy {
+                            assert_eq!(&actual[end..], &expected[end..]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping_match_copy() {
+        // Matches in a wrapping buffer whose source runs past the end, including ones that
+        // overlap their destination, compared to copying byte by byte with the mask.
+        const SIZE: usize = 64;
+        for out_pos in 0..SIZE {
+            for dist in 1..SIZE {
+                for match_len in 1..=SIZE - out_pos {
+                    let mut expected = [0u8; SIZE];
+                    for (i, e) in expected.iter_mut().enumerate() {
+                        *e = (i * 7) as u8;
+                    }
+                    let mut actual = expected;
+                    let source_pos = out_pos.wrapping_sub(dist) & (SIZE - 1);
+                    for i in 0..match_len {
+                        expected[out_pos + i] = expected[(source_pos + i) & (SIZE - 1)];
+                    }
+                    transfer(&mut actual, source_pos, out_pos, match_len, SIZE - 1);
+                    assert_eq!(actual, expected, "{out_pos} {dist} {match_len}");
+                }
+            }
+        }
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn fill_bit_buffer_whole_bytes() {
+        let input = [0xffu8; 16];
+        for num_bits in 0..30 {
+            let mut l = LocalVars {
+                bit_buf: (1 << num_bits) - 1,
+                num_bits,
+                dist: 0,
+                counter: 0,
+                num_extra: 0,
+            };
+            let mut in_iter = InputWrapper::from_slice(&input);
+            fill_bit_buffer(&mut l, &mut in_iter);
+            assert!(l.num_bits >= 56);
+            assert_eq!(l.num_bits % 8, num_bits % 8);
+            assert_eq!((input.len() - in_iter.bytes_left()) as u32 * 8, l.num_bits - num_bits);
+            // Bits that weren't counted must not have been loaded.
+            assert_eq!(l.bit_buf, ((1u128 << l.num_bits) - 1) as BitBuffer);
+        }
+
+        // With fewer than eight bytes left we fall back to a four byte read.
+        let mut l = LocalVars {
+            bit_buf: 0,
+            num_bits: 3,
+            dist: 0,
+            counter: 0,
+            num_extra: 0,
+        };
+        let mut in_iter = InputWrapper::from_slice(&input[..5]);
+        fill_bit_buffer(&mut l, &mut in_iter);
+        assert_eq!(l.num_bits, 35);
+        assert_eq!(in_iter.bytes_left(), 1);
+    }
+
     #[test]
     fn reverse_bits_lookup() {
         use super::reverse_bits;
@@ -2365,4 +3804,157 @@ mod test {
             assert_eq!(reverse_bits(i), i.reverse_bits());
         }
     }
+
+    #[cfg(all(feature = "deflate64", feature = "with-alloc"))]
+    #[test]
+    fn deflate64() {
+        // Writes bits least significant first, or Huffman codes most significant first.
+        struct Bits(Vec<u8>, u32);
+        impl Bits {
+            fn put(&mut self, value: u32, len: u32) {
+                for i in 0..len {
+                    if self.1.is_multiple_of(8) {
+                        self.0.push(0);
+                    }
+                    *self.0.last_mut().unwrap() |= ((value >> i) as u8 & 1) << (self.1 % 8);
+                    self.1 += 1;
+                }
+            }
+            fn put_code(&mut self, code: u32, len: u32) {
+                self.put(code.reverse_bits() >> (32 - len), len);
+            }
+        }
+
+        let literals = crate::test_util::random(40_000, 256);
+        let mut bits = Bits(Vec::new(), 0);
+        // A stored block with the literals, so the matches can reach back far enough.
+        bits.put(0, 3);
+        bits.1 = bits.0.len() as u32 * 8;
+        bits.0
+            .extend_from_slice(&(literals.len() as u16).to_le_bytes());
+        bits.0
+            .extend_from_slice(&(!(literals.len() as u16)).to_le_bytes());
+        bits.0.extend_from_slice(&literals);
+        bits.1 = bits.0.len() as u32 * 8;
+
+        // A final block with fixed codes, where length code 285 and distance codes 30 and 31
+        // are only valid in Deflate64.
+        let mut expected = literals.clone();
+        bits.put(1, 1);
+        bits.put(1, 2);
+        for (length_code, length_extra, length, dist_code, dist_extra, dist) in [
+            (285, 1000, 1003, 30, 5000, 37_769),
+            (285, 65_535, 65_538, 0, 0, 1),
+            (257, 0, 3, 31, 16_383, 65_536),
+            (284, 31, 258, 29, 8191, 32_768),
+        ] {
+            if length_code < 280 {
+                bits.put_code(length_code - 256, 7);
+            } else {
+                bits.put_code(0b1100_0000 + length_code - 280, 8);
+            }
+            let length_bits = if length_code == 285 {
+                16
+            } else {
+                u32::from(LENGTH_EXTRA[length_code as usize - 257])
+            };
+            bits.put(length_extra, length_bits);
+            bits.put_code(dist_code, 5);
+            bits.put(
+                dist_extra,
+                u32::from(num_extra_bits_for_distance_code(dist_code as u8)),
+            );
+            for _ in 0..length {
+                expected.push(expected[expected.len() - dist]);
+            }
+        }
+        // End of block.
+        bits.put_code(0, 7);
+        let encoded = bits.0;
+
+        assert!(crate::inflate::decompress_to_vec_deflate64(&encoded).unwrap() == expected);
+        assert!(crate::inflate::decompress_to_vec(&encoded).is_err());
+
+        // One byte at a time into a wrapping buffer of the Deflate64 window size.
+        let mut window = vec![0; TINFL_DEFLATE64_DICT_SIZE];
+        let mut r = DecompressorOxide::new();
+        let mut output = Vec::new();
+        let (mut in_pos, mut out_pos) = (0, 0);
+        loop {
+            let end = (in_pos + 1).min(encoded.len());
+            let flags = TINFL_FLAG_DEFLATE64
+                | if end < encoded.len() { TINFL_FLAG_HAS_MORE_INPUT } else { 0 };
+            let (status, read, written) = decompress(
+                &mut r,
+                &encoded[in_pos..end],
+                &mut window,
+                out_pos,
+                flags,
+                &mut 0,
+                &mut 0,
+                |_| {},
+            );
+            in_pos += read;
+            output.extend_from_slice(&window[out_pos..out_pos + written]);
+            out_pos = (out_pos + written) & (TINFL_DEFLATE64_DICT_SIZE - 1);
+            match status {
+                TINFLStatus::Done => break,
+                TINFLStatus::HasMoreOutput | TINFLStatus::NeedsMoreInput => {}
+                status => panic!("{status:?}"),
+            }
+        }
+        assert!(output == expected);
+    }
+
+    #[cfg(feature = "with-alloc")]
+    #[test]
+    fn huffman_tables() {
+        let encoded = [
+            120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
+        ];
+        let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let mut r = DecompressorOxide::new();
+        assert!(r.huffman_tables().is_none());
+        let mut out = [0; 32];
+        let (status, _, _) = tinfl_decompress_oxide(&mut r, &encoded, &mut out, flags);
+        assert_eq!(status, TINFLStatus::Done);
+
+        // The stream is a single static block.
+        let tables = r.huffman_tables().unwrap();
+        assert_eq!(tables.literal_length_completeness(), Completeness::Complete);
+        assert_eq!(tables.distance_completeness(), Completeness::Complete);
+        let codes: [HuffmanCode; 288] = ::core::array::from_fn(|i| {
+            tables.literal_length_codes().nth(i).unwrap()
+        });
+        let code = |symbol, length, code| HuffmanCode {
+            symbol,
+            length,
+            code,
+        };
+        assert_eq!(codes[0], code(0, 8, 0b0011_0000));
+        assert_eq!(codes[b'A' as usize], code(65, 8, 0b0111_0001));
+        assert_eq!(codes[144], code(144, 9, 0b1_1001_0000));
+        assert_eq!(codes[255], code(255, 9, 0b1_1111_1111));
+        assert_eq!(codes[256], code(256, 7, 0));
+        assert_eq!(codes[287], code(287, 8, 0b1100_0111));
+        assert!(tables
+            .distance_codes()
+            .enumerate()
+            .all(|(i, c)| c == code(i as u16, 5, i as u16)));
+
+        let dump = alloc::format!("{tables}");
+        assert!(dump.starts_with("literal/length codes (288 symbols, complete):\n"));
+        assert!(dump.contains("\n  01110001         literal 0x41\n"));
+        assert!(dump.contains("\n  0000000          end of block\n"));
+        assert!(dump.contains("\n  00001            distance 2 + 0 bits\n"));
+    }
+
+    #[test]
+    fn huffman_table_completeness() {
+        assert_eq!(Completeness::of(&[1, 0, 1]), Completeness::Complete);
+        assert_eq!(Completeness::of(&[1]), Completeness::Incomplete);
+        assert_eq!(Completeness::of(&[1, 2, 2, 2]), Completeness::OverSubscribed);
+        let codes = HuffmanCodes::new(&[2, 1, 3, 3]).map(|c| c.code);
+        assert!(codes.eq([0b10, 0b0, 0b110, 0b111]));
+    }
 }
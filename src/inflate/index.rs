@@ -0,0 +1,782 @@
+//! Indexes for random access into a compressed stream, like zlib's `zran` example or gztool.
+//!
+//! A [`SeekIndex`] holds [`InflateCheckpoint`]s taken at block boundaries roughly every
+//! `span` bytes of decompressed output. Each one has the position in the compressed input,
+//! the position in the output and the 32 KiB window before it, which is all that is needed to
+//! start decompressing from there instead of from the start of the stream.
+
+use crate::DataFormat;
+use crate::deflate::core::BlockBoundary;
+use crate::error::Error;
+use crate::inflate::core::{BlockBoundaryState, TINFL_LZ_DICT_SIZE};
+use crate::inflate::stream::{InflateCheckpoint, InflateState, WindowBuffer, inflate};
+use crate::shared::{MZ_ADLER32_INIT, update_adler32};
+use crate::{MZFlush, MZStatus};
+use binrw::io::read::Read;
+use binrw::io::seek::Seek;
+use binrw::io::write::Write;
+use std::io::SeekFrom;
+
+/// Magic bytes at the start of [`SeekIndex::to_bytes()`].
+const INDEX_MAGIC: [u8; 4] = *b"MZIX";
+
+/// A list of points a compressed stream can be decompressed from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeekIndex {
+    data_format: DataFormat,
+    span: u64,
+    points: Vec<InflateCheckpoint>,
+}
+
+impl SeekIndex {
+    /// Version tag written after the magic bytes by [`SeekIndex::to_bytes()`].
+    pub const ENCODING_VERSION: u8 = 1;
+
+    /// Create an empty index for a stream in `data_format`, that will take a point every
+    /// `span` bytes of decompressed output.
+    ///
+    /// Points are only taken at block boundaries, so they are usually a bit further apart
+    /// than `span`. A `span` of 0 takes a point at every block boundary.
+    pub fn new(data_format: DataFormat, span: u64) -> SeekIndex {
+        SeekIndex {
+            data_format,
+            span,
+            points: Vec::new(),
+        }
+    }
+
+    /// Add a point for the current position of `state` if it is at a block boundary and at
+    /// least `span` bytes were decompressed since the last point.
+    ///
+    /// Meant to be called after each call to [`inflate`] on a state that has
+    /// [`InflateState::set_stop_on_block_boundary()`] enabled and decompresses the stream
+    /// from the start. Returns whether a point was added.
+    pub fn record<D: WindowBuffer>(&mut self, state: &InflateState<D>) -> bool {
+        // There is never a point at the start of the stream, or two at the same place.
+        let last_out = self.points.last().map_or(0, |point| point.total_out);
+        if state.total_out() < last_out.saturating_add(self.span.max(1)) {
+            return false;
+        }
+        let Some(mut point) = InflateCheckpoint::capture(state) else {
+            return false;
+        };
+        // Near the start of the stream, only the end of the window holds output.
+        let used = window_len(point.total_out);
+        point.window.drain(..point.window.len() - used);
+        self.points.push(point);
+        true
+    }
+
+    /// Build an index from the block boundaries recorded while compressing the stream with
+    /// [`CompressorOxide::set_record_boundaries()`], without decompressing it.
+    ///
+    /// [`CompressorOxide::set_record_boundaries()`]: crate::deflate::core::CompressorOxide::set_record_boundaries
+    ///
+    /// `data` is the uncompressed data and `compressed` the compressed stream, at least up to
+    /// the last boundary. Points are picked from the boundaries like [`SeekIndex::record()`]
+    /// does. Returns `None` if a boundary is past the end of `data` or `compressed`.
+    pub fn from_boundaries(
+        data_format: DataFormat,
+        span: u64,
+        boundaries: &[BlockBoundary],
+        data: &[u8],
+        compressed: &[u8],
+    ) -> Option<SeekIndex> {
+        let mut index = SeekIndex::new(data_format, span);
+        let (z_header0, z_header1) = match data_format {
+            DataFormat::Raw => (0, 0),
+            _ => (
+                u32::from(*compressed.first()?),
+                u32::from(*compressed.get(1)?),
+            ),
+        };
+        let mut adler = MZ_ADLER32_INIT;
+        let mut adler_pos = 0;
+
+        for boundary in boundaries {
+            let last_out = index.points.last().map_or(0, |point| point.total_out);
+            if boundary.in_offset < last_out.saturating_add(span.max(1)) {
+                continue;
+            }
+            let total_out = usize::try_from(boundary.in_offset).ok()?;
+            let total_in = boundary.out_bit_offset.div_ceil(8);
+            let num_bits = (total_in * 8 - boundary.out_bit_offset) as u8;
+            let last_byte = *compressed.get(usize::try_from(total_in).ok()?.checked_sub(1)?)?;
+            let bit_buf = if num_bits > 0 {
+                last_byte >> (8 - num_bits)
+            } else {
+                0
+            };
+            if data_format == DataFormat::Zlib {
+                adler = update_adler32(adler, data.get(adler_pos..total_out)?);
+                adler_pos = total_out;
+            }
+
+            let window = data.get(total_out - window_len(boundary.in_offset)..total_out)?;
+            index.points.push(InflateCheckpoint {
+                boundary: BlockBoundaryState {
+                    num_bits,
+                    bit_buf,
+                    z_header0,
+                    z_header1,
+                    check_adler32: adler,
+                },
+                data_format,
+                window: window.to_vec(),
+                total_in,
+                total_out: boundary.in_offset,
+            });
+        }
+        Some(index)
+    }
+
+    /// The data format of the indexed stream.
+    pub const fn data_format(&self) -> DataFormat {
+        self.data_format
+    }
+
+    /// The minimum distance between points in the decompressed output.
+    pub const fn span(&self) -> u64 {
+        self.span
+    }
+
+    /// The points in the index, in the order they appear in the stream.
+    pub fn points(&self) -> &[InflateCheckpoint] {
+        &self.points
+    }
+
+    /// The last point at or before `offset` in the decompressed output, or `None` if
+    /// decompression has to start from the beginning of the stream.
+    pub fn find(&self, offset: u64) -> Option<&InflateCheckpoint> {
+        let pos = self
+            .points
+            .partition_point(|point| point.total_out <= offset);
+        pos.checked_sub(1).map(|pos| &self.points[pos])
+    }
+
+    /// Encode the index into bytes, to be stored next to the compressed stream.
+    ///
+    /// Layout: the magic bytes `MZIX`, the version, the data format, `span` as a little-endian
+    /// `u64` and the number of points as a little-endian `u32`. Each point follows with
+    /// `total_in` and `total_out` as little-endian `u64`s, the
+    /// [`BlockBoundaryState::to_bytes()`] encoding, and the length of the window as a
+    /// little-endian `u32` followed by the window.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            18 + self
+                .points
+                .iter()
+                .map(|point| 20 + BlockBoundaryState::ENCODED_LEN + point.window.len())
+                .sum::<usize>(),
+        );
+        out.extend_from_slice(&INDEX_MAGIC);
+        out.push(Self::ENCODING_VERSION);
+        out.push(format_to_byte(self.data_format));
+        out.extend_from_slice(&self.span.to_le_bytes());
+        out.extend_from_slice(&(self.points.len() as u32).to_le_bytes());
+        for point in &self.points {
+            out.extend_from_slice(&point.total_in.to_le_bytes());
+            out.extend_from_slice(&point.total_out.to_le_bytes());
+            out.extend_from_slice(&point.boundary.to_bytes());
+            out.extend_from_slice(&(point.window.len() as u32).to_le_bytes());
+            out.extend_from_slice(&point.window);
+        }
+        out
+    }
+
+    /// Decode an index previously produced by [`SeekIndex::to_bytes()`].
+    ///
+    /// Returns `None` if the bytes are truncated or have trailing data, the magic bytes or
+    /// version don't match, or a point is invalid. The positions of the points have to
+    /// increase both in the compressed input and the decompressed output, as they do in an
+    /// index built from a stream.
+    pub fn from_bytes(bytes: &[u8]) -> Option<SeekIndex> {
+        let mut reader = ByteReader { bytes };
+        if reader.take(4)? != INDEX_MAGIC || reader.take(1)?[0] != Self::ENCODING_VERSION {
+            return None;
+        }
+        let data_format = format_from_byte(reader.take(1)?[0])?;
+        let span = reader.u64()?;
+        let count = reader.u32()? as usize;
+
+        // Don't trust the count for the allocation, each point takes at least 39 bytes.
+        let mut points: Vec<InflateCheckpoint> = Vec::with_capacity(count.min(bytes.len() / 39));
+        for _ in 0..count {
+            let total_in = reader.u64()?;
+            let total_out = reader.u64()?;
+            let (last_in, last_out) = points
+                .last()
+                .map_or((0, 0), |point| (point.total_in, point.total_out));
+            if total_in <= last_in || total_out <= last_out {
+                return None;
+            }
+            let boundary =
+                BlockBoundaryState::from_bytes(reader.take(BlockBoundaryState::ENCODED_LEN)?)?;
+            let window_len = reader.u32()? as usize;
+            if total_out == 0 || window_len != self::window_len(total_out) {
+                return None;
+            }
+            let window = reader.take(window_len)?.to_vec();
+            points.push(InflateCheckpoint {
+                boundary,
+                data_format,
+                window,
+                total_in,
+                total_out,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            return None;
+        }
+        Some(SeekIndex {
+            data_format,
+            span,
+            points,
+        })
+    }
+}
+
+/// Decompress the whole stream from `input` and build an index of it, with a point every
+/// `span` bytes of decompressed output.
+///
+/// The decompressed data itself is thrown away. To build the index while using the output,
+/// call [`SeekIndex::record()`] after each call to [`inflate`] instead.
+pub fn build_index<'a, R: Read + Send + 'a>(
+    input: &'a mut R,
+    data_format: DataFormat,
+    span: u64,
+) -> impl Future<Output = Result<SeekIndex, Error>> + Send + 'a {
+    async move {
+        let mut index = SeekIndex::new(data_format, span);
+        let mut state = InflateState::new_boxed(data_format);
+        state.set_stop_on_block_boundary(true);
+        let mut sink = Discard { len: 0 };
+        let mut flush = MZFlush::None;
+
+        let mut input_buffer = vec![0; 32 * 1024];
+        let mut input_offset = 0;
+        let mut input_end = 0;
+
+        loop {
+            if input_offset == input_end && flush != MZFlush::Finish {
+                input_offset = 0;
+                input_end = input.read(input_buffer.as_mut_slice()).await?;
+                if input_end == 0 {
+                    flush = MZFlush::Finish;
+                }
+            }
+
+            let status = inflate(
+                &mut state,
+                &input_buffer[input_offset..input_end],
+                &mut sink,
+                flush,
+            )
+            .await?;
+            input_offset += status.bytes_consumed;
+            index.record(&state);
+            match status.status {
+                Ok(MZStatus::StreamEnd) => return Ok(index),
+                Ok(_) => {}
+                Err(_) => return Err(state.last_error()),
+            }
+        }
+    }
+}
+
+/// A reader that decompresses a stream on demand and can seek to any position in the
+/// decompressed data, using a [`SeekIndex`] of the stream.
+///
+/// Seeking jumps to the closest point of the index before the new position and decompresses
+/// forward from there, so reading from any position costs at most about `span` bytes of
+/// decompression, however large the stream is. Seeking forward within reach of the current
+/// position just keeps decompressing.
+pub struct IndexedReader<R> {
+    inner: R,
+    index: SeekIndex,
+    /// Where the compressed stream starts in `inner`.
+    start_offset: u64,
+    state: Box<InflateState>,
+    /// Output of the last decompression step, starting at `buffer_start` in the stream.
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    /// The position of the reader in the decompressed data.
+    pos: u64,
+    /// Set once `state` reached the end of the stream, which is then this long.
+    len: Option<u64>,
+    finished: bool,
+    input_buffer: Vec<u8>,
+    input_offset: usize,
+    input_end: usize,
+    input_eof: bool,
+}
+
+impl<R> IndexedReader<R> {
+    /// Create a reader for the compressed stream in `inner`, which `index` was built from.
+    pub fn new(inner: R, index: SeekIndex) -> Self {
+        let state = InflateState::new_boxed(index.data_format());
+        Self {
+            inner,
+            index,
+            start_offset: 0,
+            state,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            pos: 0,
+            len: None,
+            finished: false,
+            input_buffer: vec![0; 32 * 1024],
+            input_offset: 0,
+            input_end: 0,
+            input_eof: false,
+        }
+    }
+
+    /// Set where the compressed stream starts in the inner reader, e.g. after a gzip header.
+    /// Defaults to 0.
+    pub fn with_start_offset(mut self, offset: u64) -> Self {
+        self.start_offset = offset;
+        self
+    }
+
+    /// The index used for seeking.
+    pub fn index(&self) -> &SeekIndex {
+        &self.index
+    }
+
+    /// Consume the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: Read + Seek + Send> IndexedReader<R> {
+    /// Get `state` ready to decompress the data at `pos`, going back to a point of the index
+    /// if `pos` was already passed or a point is closer to it.
+    async fn prepare(&mut self, pos: u64) -> Result<(), Error> {
+        let decoded = self.buffer_start + self.buffer.len() as u64;
+        let point = self.index.find(pos);
+        let point_out = point.map_or(0, |point| point.total_out());
+        if pos >= self.buffer_start && point_out <= decoded {
+            return Ok(());
+        }
+
+        let (state, total_in) = match point {
+            Some(point) => {
+                let mut state = InflateCheckpoint::resume(point, None);
+                state.set_stop_on_block_boundary(false);
+                (state, point.total_in())
+            }
+            None => (InflateState::new_boxed(self.index.data_format()), 0),
+        };
+        self.state = state;
+        self.inner
+            .seek(SeekFrom::Start(self.start_offset + total_in))
+            .await?;
+        self.buffer.clear();
+        self.buffer_start = point_out;
+        self.finished = false;
+        self.input_offset = 0;
+        self.input_end = 0;
+        self.input_eof = false;
+        Ok(())
+    }
+
+    /// Replace the buffer with the next piece of output. Returns `false` at the end of the
+    /// stream.
+    async fn decompress_more(&mut self) -> Result<bool, Error> {
+        self.buffer_start += self.buffer.len() as u64;
+        self.buffer.clear();
+        let mut writer = VecWriter {
+            buf: &mut self.buffer,
+        };
+
+        while !self.finished {
+            if self.input_offset == self.input_end && !self.input_eof {
+                self.input_offset = 0;
+                self.input_end = self.inner.read(self.input_buffer.as_mut_slice()).await?;
+                self.input_eof = self.input_end == 0;
+            }
+            let flush = if self.input_eof {
+                MZFlush::Finish
+            } else {
+                MZFlush::None
+            };
+
+            let status = inflate(
+                &mut self.state,
+                &self.input_buffer[self.input_offset..self.input_end],
+                &mut writer,
+                flush,
+            )
+            .await?;
+            self.input_offset += status.bytes_consumed;
+            match status.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.finished = true;
+                    self.len = Some(self.buffer_start + writer.buf.len() as u64);
+                }
+                Ok(_) => {}
+                Err(_) => return Err(self.state.last_error()),
+            }
+            if !writer.buf.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The length of the decompressed data, decompressing from the last point of the index to
+    /// the end of the stream the first time.
+    async fn stream_len(&mut self) -> Result<u64, Error> {
+        if let Some(len) = self.len {
+            return Ok(len);
+        }
+        self.prepare(u64::MAX).await?;
+        while self.decompress_more().await? {}
+        Ok(self.len.unwrap_or(self.buffer_start))
+    }
+}
+
+impl<R: Read + Seek + Send> Read for IndexedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            self.prepare(self.pos).await?;
+            loop {
+                let end = self.buffer_start + self.buffer.len() as u64;
+                if self.pos < end {
+                    let start = (self.pos - self.buffer_start) as usize;
+                    let to_copy = (self.buffer.len() - start).min(buf.len());
+                    buf[..to_copy].copy_from_slice(&self.buffer[start..start + to_copy]);
+                    self.pos += to_copy as u64;
+                    return Ok(to_copy);
+                }
+                let decompressed = self.decompress_more().await?;
+                if !decompressed {
+                    return Ok(0);
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { Ok(()) }
+    }
+}
+
+impl<R: Read + Seek + Send> Seek for IndexedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> impl Future<Output = std::io::Result<u64>> + Send {
+        async move {
+            let new_pos = match pos {
+                SeekFrom::Start(offset) => Some(offset),
+                SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+                SeekFrom::End(offset) => {
+                    let len = self.stream_len().await?;
+                    len.checked_add_signed(offset)
+                }
+            };
+            // Decompression happens on the next read, so seeking past the end is allowed
+            // like for files, and then reads nothing.
+            self.pos = new_pos.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Seek to a negative position",
+                )
+            })?;
+            Ok(self.pos)
+        }
+    }
+}
+
+/// The number of bytes of window that are kept for a point `total_out` bytes into the output.
+fn window_len(total_out: u64) -> usize {
+    total_out.min(TINFL_LZ_DICT_SIZE as u64) as usize
+}
+
+fn format_to_byte(data_format: DataFormat) -> u8 {
+    match data_format {
+        DataFormat::Zlib => 0,
+        DataFormat::ZLibIgnoreChecksum => 1,
+        DataFormat::Raw => 2,
+    }
+}
+
+fn format_from_byte(byte: u8) -> Option<DataFormat> {
+    match byte {
+        0 => Some(DataFormat::Zlib),
+        1 => Some(DataFormat::ZLibIgnoreChecksum),
+        2 => Some(DataFormat::Raw),
+        _ => None,
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Writer that only counts the bytes written to it.
+struct Discard {
+    len: u64,
+}
+
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            self.len += buf.len() as u64;
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { Ok(()) }
+    }
+}
+
+impl Seek for Discard {
+    fn seek(&mut self, _pos: SeekFrom) -> impl Future<Output = std::io::Result<u64>> + Send {
+        async move { Ok(self.len) }
+    }
+}
+
+/// Writer that appends to a vector.
+struct VecWriter<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl Write for VecWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { Ok(()) }
+    }
+}
+
+impl Seek for VecWriter<'_> {
+    fn seek(&mut self, _pos: SeekFrom) -> impl Future<Output = std::io::Result<u64>> + Send {
+        async move { Ok(self.buf.len() as u64) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IndexedReader, SeekIndex, build_index};
+    use crate::deflate::core::{CompressorOxide, TDEFLFlush, TDEFLStatus, compress_to_output};
+    use crate::deflate::{compress_to_vec, compress_to_vec_zlib};
+    use crate::error::Error;
+    use crate::inflate::stream::{InflateCheckpoint, inflate};
+    use crate::{DataFormat, MZFlush, MZStatus};
+    use binrw::io::{Read, Seek};
+    use std::io::{Cursor, SeekFrom};
+
+    #[tokio::test]
+    async fn seek_index() {
+        let data = crate::test_util::random(400_000, 8);
+        let encoded = compress_to_vec_zlib(&data, 6);
+
+        let span = 64 * 1024;
+        let index = build_index(&mut Cursor::new(&encoded[..]), DataFormat::Zlib, span)
+            .await
+            .unwrap();
+        let points = index.points();
+        assert!(points.len() > 2);
+        assert!(points[0].total_out() >= span);
+        for pair in points.windows(2) {
+            assert!(pair[1].total_out() >= pair[0].total_out() + span);
+            assert!(pair[1].total_in() > pair[0].total_in());
+        }
+
+        assert!(index.find(span - 1).is_none());
+        for point in points {
+            assert_eq!(index.find(point.total_out()), Some(point));
+            assert_eq!(index.find(point.total_out() + 1), Some(point));
+        }
+
+        let bytes = index.to_bytes();
+        assert_eq!(SeekIndex::from_bytes(&bytes), Some(index.clone()));
+        assert!(SeekIndex::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        // Points out of order are rejected.
+        let mut swapped = index.clone();
+        swapped.points.swap(0, 1);
+        assert!(SeekIndex::from_bytes(&swapped.to_bytes()).is_none());
+
+        // Decompressing from any point gives the rest of the data.
+        for point in points {
+            let mut state = InflateCheckpoint::resume(point, None);
+            let mut out = Cursor::new(Vec::new());
+            let mut consumed = point.total_in() as usize;
+            loop {
+                let res = inflate(&mut state, &encoded[consumed..], &mut out, MZFlush::None)
+                    .await
+                    .unwrap();
+                consumed += res.bytes_consumed;
+                if res.status.expect("Failed to decompress!") == MZStatus::StreamEnd {
+                    break;
+                }
+            }
+            assert_eq!(out.get_ref()[..], data[point.total_out() as usize..]);
+        }
+    }
+    #[tokio::test]
+    async fn indexed_reader() {
+        let data = crate::test_util::random(400_000, 8);
+        // Raw deflate after a few bytes of header, like in a gzip file.
+        let mut file = b"head".to_vec();
+        file.extend_from_slice(&compress_to_vec(&data, 6));
+
+        let index = build_index(&mut Cursor::new(&file[4..]), DataFormat::Raw, 32 * 1024)
+            .await
+            .unwrap();
+        assert!(index.points().len() > 2);
+        let mut reader = IndexedReader::new(Cursor::new(file), index).with_start_offset(4);
+
+        let mut buf = vec![0; 5000];
+        for pos in [300_000, 10, 123_456, 123_000, 399_000, 0, 250_000] {
+            assert_eq!(reader.seek(SeekFrom::Start(pos)).await.unwrap(), pos);
+            let mut read = 0;
+            while read < buf.len() {
+                let n = reader.read(&mut buf[read..]).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                read += n;
+            }
+            let pos = pos as usize;
+            let expected = &data[pos..data.len().min(pos + buf.len())];
+            assert_eq!(&buf[..read], expected);
+        }
+
+        assert_eq!(
+            reader.seek(SeekFrom::End(-10)).await.unwrap(),
+            data.len() as u64 - 10
+        );
+        let n = reader.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &data[data.len() - 10..]);
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-20)).await.unwrap(), 399_980);
+        let n = reader.read(&mut buf[..4]).await.unwrap();
+        assert_eq!(&buf[..n], &data[399_980..399_984]);
+
+        assert!(reader.seek(SeekFrom::Current(-400_000)).await.is_err());
+        reader.seek(SeekFrom::Start(500_000)).await.unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn truncated_errors() {
+        let data = crate::test_util::random(400_000, 8);
+        let encoded = compress_to_vec(&data, 6);
+        let truncated = &encoded[..encoded.len() / 2];
+
+        let err = build_index(&mut Cursor::new(truncated), DataFormat::Raw, 32 * 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Decompress(_)));
+
+        // Reading keeps the decompression error as the source of the IO error.
+        let index = build_index(&mut Cursor::new(&encoded[..]), DataFormat::Raw, 32 * 1024)
+            .await
+            .unwrap();
+        let mut reader = IndexedReader::new(Cursor::new(truncated.to_vec()), index);
+        let mut buf = vec![0; 64 * 1024];
+        let err = loop {
+            match reader.read(&mut buf).await {
+                Ok(n) => assert!(n > 0),
+                Err(err) => break err,
+            }
+        };
+        let source = err.get_ref().and_then(|err| err.downcast_ref::<Error>());
+        assert!(matches!(source, Some(Error::Decompress(_))));
+    }
+
+    #[tokio::test]
+    async fn index_from_boundaries() {
+        let data = crate::test_util::random(400_000, 8);
+
+        for flush_every in [None, Some(50_000)] {
+            let mut compressor = CompressorOxide::default();
+            compressor.set_record_boundaries(true);
+            let mut encoded = Vec::new();
+            let chunk_size = flush_every.unwrap_or(data.len());
+            let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let flush = if i + 1 == chunks.len() {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::Sync
+                };
+                let (status, _) = compress_to_output(&mut compressor, chunk, flush, |out| {
+                    encoded.extend_from_slice(out);
+                    true
+                });
+                assert!(status != TDEFLStatus::BadParam);
+            }
+            let boundaries = compressor.take_boundaries();
+            assert!(compressor.boundaries().is_empty());
+
+            let index = SeekIndex::from_boundaries(
+                DataFormat::Zlib,
+                64 * 1024,
+                &boundaries,
+                &data,
+                &encoded,
+            )
+            .unwrap();
+            assert!(index.points().len() > 2);
+            if flush_every.is_none() {
+                // Same points as when decompressing, as all blocks end the same way.
+                let decoded =
+                    build_index(&mut Cursor::new(&encoded[..]), DataFormat::Zlib, 64 * 1024)
+                        .await
+                        .unwrap();
+                assert_eq!(index, decoded);
+            }
+
+            for point in index.points() {
+                let mut state = InflateCheckpoint::resume(point, None);
+                state.set_stop_on_block_boundary(false);
+                let mut out = Cursor::new(Vec::new());
+                let res = inflate(
+                    &mut state,
+                    &encoded[point.total_in() as usize..],
+                    &mut out,
+                    MZFlush::Finish,
+                )
+                .await
+                .unwrap();
+                assert!(res.status.expect("Failed to decompress!") == MZStatus::StreamEnd);
+                assert_eq!(out.get_ref()[..], data[point.total_out() as usize..]);
+            }
+        }
+
+        assert!(SeekIndex::from_boundaries(DataFormat::Raw, 0, &[], &[], &[]).is_some());
+    }
+}
@@ -243,10 +243,10 @@ impl<R: Read + Send> InflateReader<R> {
                         }
                     }
                     Ok(crate::MZStatus::NeedDict) => {
-                        return Err(Error::Msg("Need dictionary not supported".to_string()));
+                        return Err(Error::NeedDict);
                     }
                     Err(_) => {
-                        return Err(Error::Msg("Decompression error".to_string()));
+                        return Err(self.decomp_state.as_ref().unwrap().last_error());
                     }
                 }
                 continue;
@@ -268,7 +268,7 @@ impl<R: Read + Send> InflateReader<R> {
                     .await?;
                     return match status.status {
                         Ok(_) => Ok(true),
-                        Err(_) => Err(Error::Msg("Decompression error".to_string())),
+                        Err(_) => Err(self.decomp_state.as_ref().unwrap().last_error()),
                     };
                 }
             }
@@ -297,10 +297,10 @@ impl<R: Read + Send> InflateReader<R> {
                     // 否则继续循环读取更多输入
                 }
                 Ok(crate::MZStatus::NeedDict) => {
-                    return Err(Error::Msg("Need dictionary not supported".to_string()));
+                    return Err(Error::NeedDict);
                 }
                 Err(_) => {
-                    return Err(Error::Msg("Decompression error".to_string()));
+                    return Err(self.decomp_state.as_ref().unwrap().last_error());
                 }
             }
         }
@@ -324,7 +324,10 @@ impl<R: Read + Send> InflateReader<R> {
             self.buffer.clear();
             self.buffer_pos = 0;
             if !self.decompress_more().await? {
-                return Err(Error::Msg("Seek beyond decompressed data".to_string()));
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "seek beyond decompressed data",
+                )));
             }
             let available = self.buffer.len();
             let skip = available.min(bytes as usize);
@@ -342,9 +345,7 @@ impl<R: Read + Send> Read for InflateReader<R> {
                 match self.mode {
                     ReaderMode::Detecting => {
                         // 尝试检测
-                        let is_compressed = self.try_detect().await.map_err(|e| {
-                            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                        })?;
+                        let is_compressed = self.try_detect().await?;
 
                         if is_compressed {
                             self.mode = ReaderMode::Decompressing;
@@ -373,9 +374,7 @@ impl<R: Read + Send> Read for InflateReader<R> {
                         }
 
                         // 按需解压更多数据
-                        let decompressed = self.decompress_more().await.map_err(|e| {
-                            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                        })?;
+                        let decompressed = self.decompress_more().await?;
 
                         if !decompressed {
                             return Ok(0);
@@ -444,9 +443,7 @@ impl<R: Seek + Read + Send> Seek for InflateReader<R> {
                 match self.mode {
                     ReaderMode::Detecting => {
                         // 先检测
-                        let is_compressed = self.try_detect().await.map_err(|e| {
-                            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                        })?;
+                        let is_compressed = self.try_detect().await?;
                         if is_compressed {
                             self.mode = ReaderMode::Decompressing;
                         } else {
@@ -468,9 +465,7 @@ impl<R: Seek + Read + Send> Seek for InflateReader<R> {
 
                             // 如果目标位置超过已解压数据，尝试解压更多数据
                             while new_pos > self.buffer.len() {
-                                let decompressed = self.decompress_more().await.map_err(|e| {
-                                    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                                })?;
+                                let decompressed = self.decompress_more().await?;
                                 if !decompressed {
                                     break;
                                 }
@@ -514,11 +509,7 @@ impl<R: Seek + Read + Send> Seek for InflateReader<R> {
                         }
 
                         if new_pos > self.stream_pos {
-                            self.skip_forward(new_pos - self.stream_pos)
-                                .await
-                                .map_err(|e| {
-                                    std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
-                                })?;
+                            self.skip_forward(new_pos - self.stream_pos).await?;
                         }
 
                         return Ok(new_pos);
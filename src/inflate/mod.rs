@@ -1,12 +1,30 @@
 //! This module contains functionality for decompression.
 
-use std::error::Error;
+#[cfg(feature = "with-alloc")]
+use crate::alloc::{boxed::Box, string::String, vec, vec::Vec};
+use ::core::error::Error;
 
+#[cfg(feature = "with-alloc")]
+pub mod analyze;
+pub mod const_inflate;
 pub mod core;
+#[cfg(all(feature = "std", feature = "block-boundary"))]
+pub mod index;
+#[cfg(feature = "std")]
+pub mod inflate_reader;
 mod output_buffer;
+#[cfg(all(feature = "parallel", feature = "block-boundary"))]
+mod parallel;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(all(feature = "with-alloc", feature = "block-boundary"))]
+pub mod splice;
 pub mod stream;
-pub mod inflate_reader;
+#[cfg(feature = "with-alloc")]
 use self::core::*;
+#[cfg(all(feature = "parallel", feature = "block-boundary"))]
+pub use self::parallel::decompress_to_vec_parallel;
+use crate::DataFormat;
 
 const TINFL_STATUS_FAILED_CANNOT_MAKE_PROGRESS: i32 = -4;
 const TINFL_STATUS_BAD_PARAM: i32 = -3;
@@ -17,13 +35,22 @@ const TINFL_STATUS_NEEDS_MORE_INPUT: i32 = 1;
 const TINFL_STATUS_HAS_MORE_OUTPUT: i32 = 2;
 #[cfg(feature = "block-boundary")]
 const TINFL_STATUS_BLOCK_BOUNDARY: i32 = 3;
+const TINFL_STATUS_IO_ERROR: i32 = 10;
+const TINFL_STATUS_ABORTED: i32 = 11;
 
 /// Return status codes.
 #[repr(i8)]
 #[cfg_attr(not(feature = "rustc-dep-of-std"), derive(Hash, Debug))]
 #[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TINFLStatus {
-    IoError = 10,
+    /// Reading the input or writing the output failed.
+    IoError = TINFL_STATUS_IO_ERROR as i8,
+    /// Decompression was stopped by a callback of the caller, e.g. to cancel it.
+    ///
+    /// Unlike the failures, this says nothing about the data, which may decompress fine when
+    /// tried again.
+    Aborted = TINFL_STATUS_ABORTED as i8,
     /// More input data was expected, but the caller indicated that there was no more data, so the
     /// input stream is likely truncated.
     ///
@@ -34,7 +61,8 @@ pub enum TINFLStatus {
     /// would instead return a [`NeedsMoreInput`][Self::NeedsMoreInput] status.
     FailedCannotMakeProgress = TINFL_STATUS_FAILED_CANNOT_MAKE_PROGRESS as i8,
 
-    /// The output buffer is an invalid size; consider the `flags` parameter.
+    /// The output buffer is an invalid size; consider the `flags` parameter. See
+    /// [`DecompressorOxide::param_error`][self::core::DecompressorOxide::param_error] for the details.
     BadParam = TINFL_STATUS_BAD_PARAM as i8,
 
     /// The decompression went fine, but the adler32 checksum did not match the one
@@ -76,9 +104,38 @@ pub enum TINFLStatus {
 }
 
 impl TINFLStatus {
+    /// Get the status for a numeric code from [`to_code`][Self::to_code].
     pub fn from_i32(value: i32) -> Option<TINFLStatus> {
+        Self::from_code(value)
+    }
+
+    /// Get the numeric code of the status, for passing it through C or WASM bindings.
+    ///
+    /// The codes are stable, and the same as in miniz where it has the status:
+    ///
+    /// | Status | Code |
+    /// |--------|------|
+    /// | [`FailedCannotMakeProgress`][Self::FailedCannotMakeProgress] | -4 |
+    /// | [`BadParam`][Self::BadParam] | -3 |
+    /// | [`Adler32Mismatch`][Self::Adler32Mismatch] | -2 |
+    /// | [`Failed`][Self::Failed] | -1 |
+    /// | [`Done`][Self::Done] | 0 |
+    /// | [`NeedsMoreInput`][Self::NeedsMoreInput] | 1 |
+    /// | [`HasMoreOutput`][Self::HasMoreOutput] | 2 |
+    /// | `BlockBoundary` | 3 |
+    /// | [`IoError`][Self::IoError] | 10 |
+    /// | [`Aborted`][Self::Aborted] | 11 |
+    ///
+    /// Negative codes are failures.
+    pub const fn to_code(self) -> i32 {
+        self as i32
+    }
+
+    /// Get the status for a numeric code from [`to_code`][Self::to_code], or `None` if it isn't
+    /// one, including the code of `BlockBoundary` without the `block-boundary` feature.
+    pub const fn from_code(code: i32) -> Option<TINFLStatus> {
         use self::TINFLStatus::*;
-        match value {
+        match code {
             TINFL_STATUS_FAILED_CANNOT_MAKE_PROGRESS => Some(FailedCannotMakeProgress),
             TINFL_STATUS_BAD_PARAM => Some(BadParam),
             TINFL_STATUS_ADLER32_MISMATCH => Some(Adler32Mismatch),
@@ -88,47 +145,232 @@ impl TINFLStatus {
             TINFL_STATUS_HAS_MORE_OUTPUT => Some(HasMoreOutput),
             #[cfg(feature = "block-boundary")]
             TINFL_STATUS_BLOCK_BOUNDARY => Some(BlockBoundary),
+            TINFL_STATUS_IO_ERROR => Some(IoError),
+            TINFL_STATUS_ABORTED => Some(Aborted),
             _ => None,
         }
     }
+
+    /// Get the kind of IO error for a failure with this status: truncated input is
+    /// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof), corrupt data
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData), and output that doesn't fit
+    /// [`WriteZero`](std::io::ErrorKind::WriteZero).
+    #[cfg(feature = "std")]
+    pub fn io_error_kind(self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+        match self {
+            TINFLStatus::FailedCannotMakeProgress | TINFLStatus::NeedsMoreInput => {
+                ErrorKind::UnexpectedEof
+            }
+            TINFLStatus::Failed | TINFLStatus::Adler32Mismatch => ErrorKind::InvalidData,
+            TINFLStatus::BadParam => ErrorKind::InvalidInput,
+            TINFLStatus::HasMoreOutput => ErrorKind::WriteZero,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// A description of the status as an error.
+    const fn message(self) -> &'static str {
+        match self {
+            TINFLStatus::FailedCannotMakeProgress => "Truncated input stream",
+            TINFLStatus::BadParam => "Invalid output buffer size",
+            TINFLStatus::Adler32Mismatch => "Adler32 checksum mismatch",
+            TINFLStatus::Failed => "Invalid input data",
+            TINFLStatus::Done => "Finished decompression",
+            TINFLStatus::NeedsMoreInput => "Truncated input stream",
+            TINFLStatus::HasMoreOutput => "Output size exceeded the specified limit",
+            #[cfg(feature = "block-boundary")]
+            TINFLStatus::BlockBoundary => "Reached end of a deflate block",
+            TINFLStatus::IoError => "Io read or write error",
+            TINFLStatus::Aborted => "Aborted by the caller",
+        }
+    }
+}
+
+#[cfg(not(feature = "rustc-dep-of-std"))]
+impl ::core::fmt::Display for TINFLStatus {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+#[cfg(not(feature = "rustc-dep-of-std"))]
+impl Error for TINFLStatus {}
+
+#[cfg(feature = "std")]
+impl From<TINFLStatus> for std::io::Error {
+    /// Create an IO error of the [kind](TINFLStatus::io_error_kind) for the status.
+    fn from(status: TINFLStatus) -> Self {
+        std::io::Error::new(status.io_error_kind(), status.message())
+    }
+}
+
+/// Get the decompression flags for the wrapper of `data_format`.
+#[cfg_attr(not(any(feature = "std", feature = "embedded-io")), allow(dead_code))]
+pub(crate) const fn format_flags(data_format: DataFormat) -> u32 {
+    use self::core::inflate_flags;
+    match data_format {
+        DataFormat::Zlib => inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+        DataFormat::ZLibIgnoreChecksum => {
+            inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER | inflate_flags::TINFL_FLAG_IGNORE_ADLER32
+        }
+        DataFormat::Raw => 0,
+    }
+}
+
+/// The checksums of a zlib stream that ended with [`TINFLStatus::Adler32Mismatch`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Adler32Mismatch {
+    /// The adler32 checksum at the end of the stream.
+    pub expected: u32,
+    /// The adler32 checksum of the decompressed data.
+    pub computed: u32,
+    /// Number of bytes decompressed, all of which are covered by `computed`.
+    pub bytes_decoded: u64,
+}
+
+impl ::core::fmt::Display for Adler32Mismatch {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        write!(
+            f,
+            "Adler32 checksum mismatch: expected {:#010x}, computed {:#010x} after {} bytes",
+            self.expected, self.computed, self.bytes_decoded
+        )
+    }
+}
+
+impl Error for Adler32Mismatch {}
+
+/// What is wrong with a zlib header that failed to decompress with [`TINFLStatus::Failed`],
+/// with the offending header byte(s).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ZlibHeaderError {
+    /// The two header bytes (CMF and FLG) are not a multiple of 31 as a big-endian number.
+    BadHeaderCheck { cmf: u8, flg: u8 },
+    /// The compression method in the low 4 bits of CMF is not 8 (deflate).
+    BadCompressionMethod(u8),
+    /// The window size in the high 4 bits of CMF is larger than 32 KiB.
+    WindowTooLarge(u8),
+    /// The FDICT bit of FLG is set, but preset dictionaries are not supported here.
+    PresetDictionary(u8),
+    /// The window size in the high 4 bits of CMF is larger than the wrapping output buffer.
+    WindowLargerThanBuffer(u8),
+}
+
+impl ::core::fmt::Display for ZlibHeaderError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match *self {
+            ZlibHeaderError::BadHeaderCheck { cmf, flg } => {
+                write!(f, "Bad zlib header check: CMF {cmf:#04x}, FLG {flg:#04x}")
+            }
+            ZlibHeaderError::BadCompressionMethod(cmf) => {
+                write!(f, "Unsupported zlib compression method: CMF {cmf:#04x}")
+            }
+            ZlibHeaderError::WindowTooLarge(cmf) => {
+                write!(f, "Zlib window size too large: CMF {cmf:#04x}")
+            }
+            ZlibHeaderError::PresetDictionary(flg) => {
+                write!(f, "Zlib preset dictionary not supported: FLG {flg:#04x}")
+            }
+            ZlibHeaderError::WindowLargerThanBuffer(cmf) => {
+                write!(
+                    f,
+                    "Zlib window size larger than the output buffer: CMF {cmf:#04x}"
+                )
+            }
+        }
+    }
 }
 
+impl Error for ZlibHeaderError {}
+
 /// Struct return when decompress_to_vec functions fail.
+///
+/// Fields may be added, so it can only be created inside the crate.
+#[cfg(feature = "with-alloc")]
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct DecompressError {
     pub msg: String,
     /// Decompressor status on failure. See [TINFLStatus] for details.
     pub status: TINFLStatus,
     /// The currently decompressed data if any.
     pub output: Vec<u8>,
+    /// The checksums if the status is [`TINFLStatus::Adler32Mismatch`].
+    pub adler32_mismatch: Option<Adler32Mismatch>,
+    /// What is wrong with the zlib header if it is invalid.
+    pub zlib_header_error: Option<ZlibHeaderError>,
 }
 
+#[cfg(feature = "with-alloc")]
 impl alloc::fmt::Display for DecompressError {
     #[cold]
     fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
-        f.write_str(match self.status {
-            TINFLStatus::FailedCannotMakeProgress => "Truncated input stream",
-            TINFLStatus::BadParam => "Invalid output buffer size",
-            TINFLStatus::Adler32Mismatch => "Adler32 checksum mismatch",
-            TINFLStatus::Failed => "Invalid input data",
-            TINFLStatus::Done => "", // Unreachable
-            TINFLStatus::NeedsMoreInput => "Truncated input stream",
-            TINFLStatus::HasMoreOutput => "Output size exceeded the specified limit",
-            #[cfg(feature = "block-boundary")]
-            TINFLStatus::BlockBoundary => "Reached end of a deflate block",
-            TINFLStatus::IoError => "Io read or write error",
-        })
+        if let Some(mismatch) = &self.adler32_mismatch {
+            mismatch.fmt(f)
+        } else if let Some(err) = &self.zlib_header_error {
+            err.fmt(f)
+        } else {
+            f.write_str(self.status.message())
+        }
     }
 }
 
-/// Implement Error trait only if std feature is requested as it requires std.
+#[cfg(feature = "with-alloc")]
 impl Error for DecompressError {}
 
-fn decompress_error(status: TINFLStatus, output: Vec<u8>) -> Result<Vec<u8>, DecompressError> {
+/// Logs the status and the cause of the failure, and only the length of the output, as the
+/// data itself would be too much for a log.
+#[cfg(all(feature = "with-alloc", feature = "defmt"))]
+impl defmt::Format for DecompressError {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "DecompressError {{ status: {}, output: {} bytes, adler32_mismatch: {}, zlib_header_error: {} }}",
+            self.status,
+            self.output.len(),
+            self.adler32_mismatch,
+            self.zlib_header_error
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<DecompressError> for std::io::Error {
+    /// Create an IO error of the [kind](TINFLStatus::io_error_kind) for the status, with the
+    /// error as its source.
+    fn from(err: DecompressError) -> Self {
+        std::io::Error::new(err.status.io_error_kind(), err)
+    }
+}
+
+#[cfg(feature = "with-alloc")]
+pub(crate) fn decompress_error<T>(status: TINFLStatus, output: Vec<u8>) -> Result<T, DecompressError> {
     Err(DecompressError {
-        msg: "".to_string(),
+        msg: String::new(),
         status,
         output,
+        adler32_mismatch: None,
+        zlib_header_error: None,
+    })
+}
+
+/// Like [`decompress_error`], with the checksums from `decomp` if they didn't match, or what
+/// is wrong with the zlib header if it is invalid.
+#[cfg(feature = "with-alloc")]
+fn decompressor_error<T>(
+    decomp: &DecompressorOxide,
+    status: TINFLStatus,
+    output: Vec<u8>,
+) -> Result<T, DecompressError> {
+    decompress_error(status, output).map_err(|err| DecompressError {
+        adler32_mismatch: decomp.adler32_mismatch(),
+        zlib_header_error: decomp.zlib_header_error(),
+        ..err
     })
 }
 
@@ -171,6 +413,21 @@ pub fn decompress_to_vec_zlib(input: &[u8]) -> Result<Vec<u8>, DecompressError>
     )
 }
 
+/// Decompress the Deflate64-encoded data in `input` to a vector.
+///
+/// Deflate64 is a variant of deflate with a 64 KiB window and longer matches, found in some
+/// zip archives with compression method 9. See
+/// [`TINFL_FLAG_DEFLATE64`][inflate_flags::TINFL_FLAG_DEFLATE64].
+///
+/// NOTE: This function will not bound the output, like [`decompress_to_vec`].
+///
+/// Returns a [`Result`] containing the [`Vec`] of decompressed data on success, and a [struct][DecompressError] containing the status and so far decompressed data if any on failure.
+#[inline]
+#[cfg(all(feature = "with-alloc", feature = "deflate64"))]
+pub fn decompress_to_vec_deflate64(input: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    decompress_to_vec_inner(input, inflate_flags::TINFL_FLAG_DEFLATE64, usize::MAX)
+}
+
 /// Decompress the deflate-encoded data in `input` to a vector.
 ///
 /// The vector is grown to at most `max_size` bytes; if the data does not fit in that size,
@@ -206,17 +463,243 @@ pub fn decompress_to_vec_zlib_with_limit(
     decompress_to_vec_inner(input, inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER, max_size)
 }
 
+/// Decompress the deflate-encoded data in `input` to a vector, also returning how many bytes
+/// of `input` the compressed stream occupies.
+///
+/// Data after the end of the stream is ignored rather than an error, so this can be used
+/// to parse containers where other data follows the stream, e.g. packed git objects. The
+/// next record then starts at the returned offset.
+///
+/// NOTE: This function will not bound the output, like [`decompress_to_vec`].
+///
+/// Returns a [`Result`] containing the [`Vec`] of decompressed data and the length of the stream on success, and a [struct][DecompressError] containing the status and so far decompressed data if any on failure.
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_consumed(input: &[u8]) -> Result<(Vec<u8>, usize), DecompressError> {
+    decompress_to_vec_consumed_inner(input, 0, usize::MAX)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` to a vector, also
+/// returning how many bytes of `input` the stream occupies, including the wrapper. See
+/// [`decompress_to_vec_consumed`].
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_zlib_consumed(input: &[u8]) -> Result<(Vec<u8>, usize), DecompressError> {
+    decompress_to_vec_consumed_inner(
+        input,
+        inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+        usize::MAX,
+    )
+}
+
+/// Decompress the deflate-encoded data in `input` to a vector of at most `max_size` bytes,
+/// also returning how many bytes of `input` the stream occupies. See
+/// [`decompress_to_vec_consumed`] and [`decompress_to_vec_with_limit`].
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_consumed_with_limit(
+    input: &[u8],
+    max_size: usize,
+) -> Result<(Vec<u8>, usize), DecompressError> {
+    decompress_to_vec_consumed_inner(input, 0, max_size)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` to a vector of at
+/// most `max_size` bytes, also returning how many bytes of `input` the stream occupies. See
+/// [`decompress_to_vec_zlib_consumed`] and [`decompress_to_vec_zlib_with_limit`].
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_zlib_consumed_with_limit(
+    input: &[u8],
+    max_size: usize,
+) -> Result<(Vec<u8>, usize), DecompressError> {
+    decompress_to_vec_consumed_inner(input, inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER, max_size)
+}
+
+/// Decompress the deflate-encoded data in `input`, appending it to `output`.
+///
+/// The spare capacity of `output` is used before growing it, so decompressing many messages
+/// into the same vector only allocates until it is large enough. Clear it first to replace
+/// its contents rather than append to them.
+///
+/// Returns the status, which is [`TINFLStatus::Done`] on success, and the number of bytes
+/// appended. On failure, the data decompressed so far is left in `output`.
+///
+/// NOTE: This function will not bound the output, like [`decompress_to_vec`]. Use
+/// [`decompress_into_vec_with_limit`] for data that may not be trusted.
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_into_vec(input: &[u8], output: &mut Vec<u8>) -> (TINFLStatus, usize) {
+    decompress_into_vec_inner(input, 0, usize::MAX, output)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input`, appending it to
+/// `output`. See [`decompress_into_vec`].
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_into_vec_zlib(input: &[u8], output: &mut Vec<u8>) -> (TINFLStatus, usize) {
+    decompress_into_vec_inner(
+        input,
+        inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+        usize::MAX,
+        output,
+    )
+}
+
+/// Decompress the deflate-encoded data in `input`, appending at most `max_size` bytes to
+/// `output`. See [`decompress_into_vec`].
+///
+/// If the data does not fit in that size, the status is [`TINFLStatus::HasMoreOutput`].
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_into_vec_with_limit(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    max_size: usize,
+) -> (TINFLStatus, usize) {
+    decompress_into_vec_inner(input, 0, max_size, output)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input`, appending at most
+/// `max_size` bytes to `output`. See [`decompress_into_vec_with_limit`].
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_into_vec_zlib_with_limit(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    max_size: usize,
+) -> (TINFLStatus, usize) {
+    decompress_into_vec_inner(
+        input,
+        inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+        max_size,
+        output,
+    )
+}
+
+/// Settings for [`decompress_to_vec_with`]. The defaults are those of [`decompress_to_vec`].
+#[cfg(feature = "with-alloc")]
+pub struct DecompressOptions<'a> {
+    /// How the compressed data is wrapped.
+    pub format: DataFormat,
+    /// The largest size the output may grow to. If the data doesn't fit, the error has the
+    /// status [`TINFLStatus::HasMoreOutput`].
+    pub max_output_size: usize,
+    /// The dictionary the data was compressed with, which matches can refer back to.
+    pub dictionary: Option<&'a [u8]>,
+    /// Called with the number of input bytes taken in, every 64 KiB or so, to report
+    /// progress.
+    pub callback: Option<&'a mut dyn FnMut(usize)>,
+}
+
+#[cfg(feature = "with-alloc")]
+impl Default for DecompressOptions<'_> {
+    fn default() -> Self {
+        DecompressOptions {
+            format: DataFormat::Raw,
+            max_output_size: usize::MAX,
+            dictionary: None,
+            callback: None,
+        }
+    }
+}
+
+/// Decompress the data in `input` to a vector, with the settings of `options`.
+///
+/// This covers the other `decompress_to_vec` functions, and new settings are added to
+/// [`DecompressOptions`] rather than as new functions.
+///
+/// Returns a [`Result`] containing the [`Vec`] of decompressed data on success, and a [struct][DecompressError] containing the status and so far decompressed data if any on failure.
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_with(
+    input: &[u8],
+    options: DecompressOptions<'_>,
+) -> Result<Vec<u8>, DecompressError> {
+    let mut flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    match options.format {
+        DataFormat::Zlib => flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+        DataFormat::ZLibIgnoreChecksum => {
+            flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                | inflate_flags::TINFL_FLAG_IGNORE_ADLER32
+        }
+        DataFormat::Raw => {}
+    }
+    let mut callback = options.callback;
+
+    // The dictionary goes before the output, where matches can reach it, and is taken out at
+    // the end. Only the last 32 KiB can be reached.
+    let dictionary = options.dictionary.unwrap_or(&[]);
+    let dictionary = &dictionary[dictionary.len().saturating_sub(TINFL_LZ_DICT_SIZE)..];
+    let limit = dictionary.len().saturating_add(options.max_output_size);
+    let mut ret = dictionary.to_vec();
+    ret.resize(limit.min(dictionary.len() + 32768), 0);
+    let mut decomp = Box::<DecompressorOxide>::default();
+    let finish = |mut ret: Vec<u8>, out_pos: usize| {
+        ret.truncate(out_pos);
+        ret.drain(..dictionary.len());
+        ret
+    };
+
+    let mut in_pos = 0;
+    let mut out_pos = dictionary.len();
+    loop {
+        // Give the input a piece at a time, to report progress.
+        let in_end = input.len().min(in_pos + 64 * 1024);
+        let chunk_flags = if in_end < input.len() {
+            flags | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT
+        } else {
+            flags
+        };
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut decomp,
+            &input[in_pos..in_end],
+            &mut ret,
+            out_pos,
+            chunk_flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        in_pos += in_consumed;
+        out_pos += out_consumed;
+        if let Some(callback) = callback.as_mut().filter(|_| in_consumed > 0) {
+            callback(in_consumed);
+        }
+
+        match status {
+            TINFLStatus::Done => return Ok(finish(ret, out_pos)),
+            TINFLStatus::NeedsMoreInput if in_end < input.len() => {}
+            TINFLStatus::HasMoreOutput if ret.len() < limit => {
+                let new_len = ret.len().saturating_mul(2).min(limit);
+                ret.resize(new_len, 0);
+            }
+            status => return decompressor_error(&decomp, status, finish(ret, out_pos)),
+        }
+    }
+}
+
 /// Backend of various to-[`Vec`] decompressions.
 ///
 /// Returns [`Vec`] of decompressed data on success and the [error struct][DecompressError] with details on failure.
 #[cfg(feature = "with-alloc")]
 pub fn decompress_to_vec_inner(
-    mut input: &[u8],
+    input: &[u8],
     flags: u32,
     max_output_size: usize,
 ) -> Result<Vec<u8>, DecompressError> {
+    decompress_to_vec_consumed_inner(input, flags, max_output_size).map(|(output, _)| output)
+}
+
+/// Backend of the to-[`Vec`] decompressions, also returning the number of input bytes used.
+#[cfg(feature = "with-alloc")]
+fn decompress_to_vec_consumed_inner(
+    mut input: &[u8],
+    flags: u32,
+    max_output_size: usize,
+) -> Result<(Vec<u8>, usize), DecompressError> {
+    let input_len = input.len();
     let flags = flags | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
-    let mut ret: Vec<u8> = vec![0; 32768];
+    let mut ret: Vec<u8> = vec![0; input.len().saturating_mul(2).min(max_output_size)];
     // let mut ret = Cursor::new(vec![0;32768]);
     let mut decomp = Box::<DecompressorOxide>::default();
 
@@ -239,7 +722,7 @@ pub fn decompress_to_vec_inner(
         match status {
             TINFLStatus::Done => {
                 ret.truncate(out_pos);
-                return Ok(ret);
+                return Ok((ret, input_len - input.len() + in_consumed));
             }
 
             TINFLStatus::HasMoreOutput => {
@@ -259,7 +742,54 @@ pub fn decompress_to_vec_inner(
                 ret.resize(new_len, 0);
             }
 
-            _ => return decompress_error(status, ret),
+            _ => return decompressor_error(&decomp, status, ret),
+        }
+    }
+}
+
+/// Backend of the into-[`Vec`] decompressions, appending to `output`.
+#[cfg(feature = "with-alloc")]
+fn decompress_into_vec_inner(
+    mut input: &[u8],
+    flags: u32,
+    max_output_size: usize,
+    output: &mut Vec<u8>,
+) -> (TINFLStatus, usize) {
+    let flags = flags | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    // The data already there is not part of the stream, so the decompressor only gets the
+    // part after it, where matches can't reach before the start.
+    let start = output.len();
+    let limit = start.saturating_add(max_output_size);
+    let len = output.capacity().max(start + 32768).min(limit);
+    output.resize(len, 0);
+    let mut decomp = Box::<DecompressorOxide>::default();
+
+    let mut out_pos = 0;
+    loop {
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut decomp,
+            input,
+            &mut output[start..],
+            out_pos,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        out_pos += out_consumed;
+
+        match status {
+            // in_consumed is not expected to be out of bounds,
+            // but the check eliminates a panicking code path
+            TINFLStatus::HasMoreOutput if in_consumed <= input.len() && output.len() < limit => {
+                input = &input[in_consumed..];
+                let new_len = output.len().saturating_mul(2).min(limit);
+                output.resize(new_len, 0);
+            }
+            status => {
+                output.truncate(start + out_pos);
+                return (status, out_pos);
+            }
         }
     }
 }
@@ -327,7 +857,7 @@ fn decompress_to_vec_inner_callback(
                 }
             }
 
-            _ => return decompress_error(status, ret),
+            _ => return decompressor_error(&decomp, status, ret),
         }
     }
 }
@@ -387,9 +917,16 @@ fn decompress_to_vec_inner_callback(
 
 #[cfg(all(test, feature = "with-alloc"))]
 mod test {
+    use super::core::{decompress, inflate_flags, DecompressorOxide};
     use super::{
-        decompress_to_vec_zlib, decompress_to_vec_zlib_with_limit, DecompressError, TINFLStatus,
+        decompress_into_vec, decompress_into_vec_with_limit, decompress_into_vec_zlib,
+        decompress_into_vec_zlib_with_limit, decompress_to_vec_consumed,
+        decompress_to_vec_consumed_with_limit, decompress_to_vec_with, decompress_to_vec_zlib,
+        decompress_to_vec_zlib_consumed, decompress_to_vec_zlib_with_limit, DecompressError,
+        DecompressOptions, TINFLStatus, ZlibHeaderError,
     };
+    use crate::DataFormat;
+    use alloc::string::ToString;
     const ENCODED: [u8; 20] = [
         120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
     ];
@@ -418,6 +955,221 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_errors() {
+        use std::io::ErrorKind;
+
+        let err: std::io::Error = decompress_to_vec_zlib(&ENCODED[..10]).unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let source = err.into_inner().unwrap();
+        assert_eq!(
+            source.downcast_ref::<DecompressError>().unwrap().status,
+            TINFLStatus::FailedCannotMakeProgress
+        );
+
+        let mut corrupted = ENCODED;
+        corrupted[19] ^= 1;
+        let err = decompress_to_vec_zlib(&corrupted).unwrap_err();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::InvalidData);
+        let compressed = crate::deflate::compress_to_vec_zlib(&[7; 100_000], 6);
+        let err = decompress_to_vec_zlib_with_limit(&compressed, 40_000).unwrap_err();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::WriteZero);
+        assert_eq!(
+            std::io::Error::from(TINFLStatus::BadParam).kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            std::io::Error::from(crate::MZError::Data).kind(),
+            ErrorKind::InvalidData
+        );
+        // Cancelling is not mistaken for corrupt or truncated data.
+        let err = std::io::Error::from(TINFLStatus::Aborted);
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(err.to_string(), "Aborted by the caller");
+    }
+
+    #[test]
+    fn status_codes() {
+        use crate::deflate::core::TDEFLStatus;
+        use crate::{MZError, MZStatus};
+
+        for code in -20..20 {
+            if let Some(status) = TINFLStatus::from_code(code) {
+                assert_eq!(status.to_code(), code);
+            }
+            if let Some(status) = TDEFLStatus::from_code(code) {
+                assert_eq!(status.to_code(), code);
+            }
+            if let Some(result) = MZError::result_from_code(code) {
+                assert_eq!(MZError::result_to_code(result), code);
+            }
+        }
+        assert_eq!(TINFLStatus::from_code(10), Some(TINFLStatus::IoError));
+        assert_eq!(TINFLStatus::Aborted.to_code(), 11);
+        assert_eq!(TINFLStatus::from_code(-5), None);
+        assert_eq!(TDEFLStatus::PutBufFailed.to_code(), -1);
+        assert_eq!(MZError::from_code(-10_000), Some(MZError::Param));
+        assert_eq!(MZError::result_from_code(-3), Some(Err(MZError::Data)));
+        assert_eq!(MZError::result_from_code(1), Some(Ok(MZStatus::StreamEnd)));
+        assert_eq!(MZError::result_from_code(-7), None);
+    }
+
+    #[test]
+    fn core_error() {
+        use crate::deflate::core::TDEFLStatus;
+        use crate::MZError;
+        use ::core::error::Error;
+
+        // The error trait is there without `std`, so the errors can be boxed or passed on as a
+        // source in `no_std` code.
+        let errors: [&dyn Error; 4] = [
+            &TINFLStatus::FailedCannotMakeProgress,
+            &TDEFLStatus::PutBufFailed,
+            &MZError::Data,
+            &decompress_to_vec_zlib(&ENCODED[..10]).unwrap_err(),
+        ];
+        assert_eq!(errors[0].to_string(), "Truncated input stream");
+        assert_eq!(errors[1].to_string(), "Failed to write the compressed output");
+        assert_eq!(errors[2].to_string(), "Invalid or corrupted data");
+        assert_eq!(errors[3].to_string(), "Truncated input stream");
+    }
+
+    #[test]
+    fn consumed_length() {
+        // Records of compressed data followed by other data, like in a container.
+        let mut input = ENCODED.to_vec();
+        input.extend_from_slice(b"next record");
+        let (output, consumed) = decompress_to_vec_zlib_consumed(&input).unwrap();
+        assert_eq!(output, b"Hello, zlib!");
+        assert_eq!(consumed, ENCODED.len());
+
+        let data = crate::test_util::text(100_000);
+        let mut input = crate::deflate::compress_to_vec(&data, 6);
+        let len = input.len();
+        input.extend_from_slice(&ENCODED);
+        let (output, consumed) = decompress_to_vec_consumed(&input).unwrap();
+        assert!(output == data);
+        assert_eq!(consumed, len);
+        let (output, consumed) = decompress_to_vec_zlib_consumed(&input[consumed..]).unwrap();
+        assert_eq!(output, b"Hello, zlib!");
+        assert_eq!(consumed, ENCODED.len());
+
+        let err = decompress_to_vec_consumed_with_limit(&input, 1000).unwrap_err();
+        assert_eq!(err.status, TINFLStatus::HasMoreOutput);
+    }
+
+    #[test]
+    fn adler32_mismatch() {
+        let mut encoded = ENCODED;
+        encoded[ENCODED.len() - 1] ^= 0xff;
+        let err = decompress_to_vec_zlib(&encoded).unwrap_err();
+        assert_eq!(err.status, TINFLStatus::Adler32Mismatch);
+        let mismatch = err.adler32_mismatch.unwrap();
+        assert_eq!(mismatch.computed, 0x1b65_0413);
+        assert_eq!(mismatch.expected, 0x1b65_04ec);
+        assert_eq!(mismatch.bytes_decoded, 12);
+        assert_eq!(
+            err.to_string(),
+            "Adler32 checksum mismatch: expected 0x1b6504ec, computed 0x1b650413 after 12 bytes"
+        );
+
+        let err = decompress_to_vec_zlib(&ENCODED[..ENCODED.len() - 1]).unwrap_err();
+        assert_eq!(err.adler32_mismatch, None);
+    }
+
+    #[test]
+    fn zlib_header_errors() {
+        let check = |header: [u8; 2], expected: ZlibHeaderError| {
+            let mut encoded = ENCODED;
+            encoded[..2].copy_from_slice(&header);
+            let err = decompress_to_vec_zlib(&encoded).unwrap_err();
+            assert_eq!(err.status, TINFLStatus::Failed);
+            assert_eq!(err.zlib_header_error, Some(expected));
+        };
+        check(
+            [0x78, 0x9d],
+            ZlibHeaderError::BadHeaderCheck {
+                cmf: 0x78,
+                flg: 0x9d,
+            },
+        );
+        check([0x79, 0x94], ZlibHeaderError::BadCompressionMethod(0x79));
+        check([0x88, 0x98], ZlibHeaderError::WindowTooLarge(0x88));
+        check([0x78, 0xbb], ZlibHeaderError::PresetDictionary(0xbb));
+
+        let mut encoded = ENCODED;
+        encoded[1] = 0x9d;
+        let err = decompress_to_vec_zlib(&encoded).unwrap_err();
+        assert_eq!(err.to_string(), "Bad zlib header check: CMF 0x78, FLG 0x9d");
+
+        // A wrapping buffer smaller than the window.
+        let mut r = DecompressorOxide::new();
+        let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+        let (status, ..) = decompress(
+            &mut r,
+            &ENCODED,
+            &mut [0; 1024],
+            0,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_| {},
+        );
+        assert_eq!(status, TINFLStatus::Failed);
+        assert_eq!(
+            r.zlib_header_error(),
+            Some(ZlibHeaderError::WindowLargerThanBuffer(0x78))
+        );
+
+        // Other failures are not header errors.
+        let mut encoded = ENCODED;
+        encoded[2] |= 0b110;
+        let err = decompress_to_vec_zlib(&encoded).unwrap_err();
+        assert_eq!(err.status, TINFLStatus::Failed);
+        assert_eq!(err.zlib_header_error, None);
+    }
+
+    #[test]
+    fn decompress_vec_into() {
+        let mut output = b"prefix ".to_vec();
+        assert_eq!(
+            decompress_into_vec_zlib(&ENCODED[..], &mut output),
+            (TINFLStatus::Done, 12)
+        );
+        assert_eq!(output, b"prefix Hello, zlib!");
+
+        // The allocation is reused once it is large enough.
+        output.clear();
+        let data = crate::test_util::text(100_000);
+        let compressed = crate::deflate::compress_to_vec(&data, 6);
+        assert_eq!(
+            decompress_into_vec(&compressed, &mut output),
+            (TINFLStatus::Done, data.len())
+        );
+        assert!(output == data);
+        let (capacity, pointer) = (output.capacity(), output.as_ptr());
+        output.clear();
+        assert_eq!(
+            decompress_into_vec_zlib(&ENCODED[..], &mut output),
+            (TINFLStatus::Done, 12)
+        );
+        assert_eq!((output.capacity(), output.as_ptr()), (capacity, pointer));
+
+        // Only up to the limit is appended, and the output is kept on failure.
+        let mut output = b"prefix ".to_vec();
+        assert_eq!(
+            decompress_into_vec_zlib_with_limit(&ENCODED[..], &mut output, 8),
+            (TINFLStatus::HasMoreOutput, 8)
+        );
+        assert_eq!(output, b"prefix Hello, z");
+        output.truncate(7);
+        let (status, _) = decompress_into_vec_with_limit(&compressed[..100], &mut output, 1000);
+        assert_eq!(status, TINFLStatus::FailedCannotMakeProgress);
+        assert!(output.starts_with(b"prefix "));
+        assert!(output[7..] == data[..output.len() - 7]);
+    }
+
     // #[test]
     // fn test_decompress_slice_iter_to_slice() {
     //     // one slice
@@ -444,4 +1196,35 @@ mod test {
     //     let r = decompress_slice_iter_to_slice(&mut out, ENCODED.chunks(7), true, false);
     //     assert!(r.is_err());
     // }
+
+    #[test]
+    fn decompress_vec_with() {
+        let res = decompress_to_vec_with(
+            &ENCODED[..],
+            DecompressOptions {
+                format: DataFormat::Zlib,
+                ..DecompressOptions::default()
+            },
+        );
+        assert_eq!(res.unwrap(), b"Hello, zlib!");
+
+        let mut corrupted = ENCODED;
+        corrupted[19] ^= 1;
+        let options = |format, max_output_size| DecompressOptions {
+            format,
+            max_output_size,
+            ..DecompressOptions::default()
+        };
+        let res = decompress_to_vec_with(&corrupted, options(DataFormat::Zlib, usize::MAX));
+        assert_eq!(res.unwrap_err().status, TINFLStatus::Adler32Mismatch);
+        let res = decompress_to_vec_with(
+            &corrupted,
+            options(DataFormat::ZLibIgnoreChecksum, usize::MAX),
+        );
+        assert_eq!(res.unwrap(), b"Hello, zlib!");
+
+        let err = decompress_to_vec_with(&ENCODED, options(DataFormat::Zlib, 8)).unwrap_err();
+        assert_eq!(err.status, TINFLStatus::HasMoreOutput);
+        assert_eq!(err.output, b"Hello, z");
+    }
 }
@@ -31,7 +31,17 @@ impl<'a> OutputBuffer<'a> {
     /// Assumes that there is space.
     #[inline]
     pub fn write_byte(&mut self, byte: u8) {
-        self.slice[self.position] = byte;
+        #[cfg(all(feature = "unsafe-perf", not(feature = "safe")))]
+        {
+            debug_assert!(self.position < self.slice.len());
+            // SAFETY: Every caller checks that there is space left first: `decompress_fast`
+            // requires 259 bytes, the `DecodeLitlen` state 2 and the other states 1.
+            unsafe { *self.slice.get_unchecked_mut(self.position) = byte };
+        }
+        #[cfg(any(feature = "safe", not(feature = "unsafe-perf")))]
+        {
+            self.slice[self.position] = byte;
+        }
         self.position += 1;
     }
 
@@ -62,6 +72,62 @@ impl<'a> OutputBuffer<'a> {
     }
 }
 
+/// Copy a match of `match_len` bytes from `source_pos` to `out_pos` within `out_slice`, using
+/// 8 or 16 byte chunks depending on the distance between the two.
+///
+/// The source has to start before the destination, at a distance of at least 8, and neither
+/// range may wrap around the end of `out_slice`; returns `false` without copying anything
+/// otherwise, so the caller can fall back to a byte-wise copy.
+///
+/// Since each chunk is at most as long as the distance, its source bytes have already been
+/// written by the time it is copied, which gives the repeating-pattern semantics of LZ77
+/// matches even though the match as a whole overlaps its source.
+///
+/// If `overcopy` is set the last chunk is copied in full if there is room for it, which may
+/// clobber up to 15 bytes past the end of the match. This is only valid when those bytes are
+/// not part of the history, i.e. when the output buffer does not wrap around.
+#[inline]
+pub fn copy_match_wide(
+    out_slice: &mut [u8],
+    mut source_pos: usize,
+    mut out_pos: usize,
+    match_len: usize,
+    overcopy: bool,
+) -> bool {
+    let dist = out_pos.wrapping_sub(source_pos);
+    if source_pos >= out_pos || dist < 8 || out_pos + match_len > out_slice.len() {
+        return false;
+    }
+
+    let end = out_pos + match_len;
+    if dist >= 16 {
+        while out_pos + 16 <= end {
+            out_slice.copy_within(source_pos..source_pos + 16, out_pos);
+            source_pos += 16;
+            out_pos += 16;
+        }
+        if overcopy && out_pos < end && out_pos + 16 <= out_slice.len() {
+            out_slice.copy_within(source_pos..source_pos + 16, out_pos);
+            return true;
+        }
+    }
+    while out_pos + 8 <= end {
+        out_slice.copy_within(source_pos..source_pos + 8, out_pos);
+        source_pos += 8;
+        out_pos += 8;
+    }
+    if out_pos < end {
+        if overcopy && out_pos + 8 <= out_slice.len() {
+            out_slice.copy_within(source_pos..source_pos + 8, out_pos);
+        } else {
+            // Less than 8 bytes left, which is less than the distance, so the
+            // ranges can't overlap.
+            out_slice.copy_within(source_pos..source_pos + (end - out_pos), out_pos);
+        }
+    }
+    true
+}
+
 /// A wrapper for the output slice used when decompressing.
 ///
 /// Using this rather than `Cursor` lets us implement the writing methods directly on
@@ -107,6 +173,15 @@ impl<'a> InputWrapper<'a> {
         ret
     }
 
+    /// Read the next eight bytes as a little-endian `u64` without advancing, if there are
+    /// at least eight bytes left.
+    #[inline]
+    #[cfg(target_pointer_width = "64")]
+    pub fn peek_u64_le(&self) -> Option<u64> {
+        let eight_bytes: [u8; 8] = self.slice.get(..8)?.try_into().ok()?;
+        Some(u64::from_le_bytes(eight_bytes))
+    }
+
     #[inline(always)]
     pub const fn bytes_left(&self) -> usize {
         self.slice.len()
@@ -0,0 +1,213 @@
+//! Decompression of large streams using several threads, with the help of a [`SeekIndex`].
+//!
+//! Each point of the index has everything needed to start decompressing from it, so the
+//! stretches of the stream between points are decompressed at the same time on the rayon
+//! thread pool, and joined in order. This works with any index, but it only pays off when
+//! the points are at least a few hundred KiB apart.
+
+use alloc::vec::Vec;
+
+use rayon::prelude::*;
+
+use super::core::{DecompressorOxide, decompress, inflate_flags};
+use super::index::SeekIndex;
+use super::stream::InflateCheckpoint;
+use super::{DecompressError, TINFLStatus, decompress_error};
+use crate::DataFormat;
+
+/// Decompress the stream in `input` to a vector with several threads, using `index` built
+/// from the same stream to split the work.
+///
+/// The data format is that of the index. With [`DataFormat::Zlib`] the checksum is verified
+/// for each part, using the ones saved in the points of the index.
+///
+/// NOTE: Like [`decompress_to_vec`](super::decompress_to_vec), this does not bound the
+/// output.
+///
+/// Returns a [`Result`] containing the [`Vec`] of decompressed data on success, and a
+/// [struct][DecompressError] containing the status and the data of the parts before the one
+/// that failed on failure.
+pub fn decompress_to_vec_parallel(
+    input: &[u8],
+    index: &SeekIndex,
+) -> Result<Vec<u8>, DecompressError> {
+    let points = index.points();
+    let data_format = index.data_format();
+
+    let parts: Vec<Result<Vec<u8>, TINFLStatus>> = (0..points.len() + 1)
+        .into_par_iter()
+        .map(|i| {
+            let start = i.checked_sub(1).map(|i| &points[i]);
+            decompress_part(input, data_format, start, points.get(i))
+        })
+        .collect();
+
+    let mut output = Vec::with_capacity(
+        parts
+            .iter()
+            .map(|part| part.as_ref().map_or(0, |data| data.len()))
+            .sum(),
+    );
+    for part in parts {
+        match part {
+            Ok(data) => output.extend_from_slice(&data),
+            Err(status) => return decompress_error(status, output),
+        }
+    }
+    Ok(output)
+}
+
+/// Decompress the part of the stream from `start` (the beginning if `None`) to `end` (the end
+/// of the stream if `None`).
+fn decompress_part(
+    input: &[u8],
+    data_format: DataFormat,
+    start: Option<&InflateCheckpoint>,
+    end: Option<&InflateCheckpoint>,
+) -> Result<Vec<u8>, TINFLStatus> {
+    let mut flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    match data_format {
+        DataFormat::Zlib => flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+        DataFormat::ZLibIgnoreChecksum => {
+            flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                | inflate_flags::TINFL_FLAG_IGNORE_ADLER32
+        }
+        DataFormat::Raw => {}
+    }
+
+    let (mut decomp, window, total_in, total_out) = match start {
+        Some(point) => (
+            DecompressorOxide::from_block_boundary_state(point.boundary_state()),
+            point.window(),
+            point.total_in(),
+            point.total_out(),
+        ),
+        None => (DecompressorOxide::default(), &[][..], 0, 0),
+    };
+    let mut input = usize::try_from(total_in)
+        .ok()
+        .and_then(|total_in| input.get(total_in..))
+        .ok_or(TINFLStatus::FailedCannotMakeProgress)?;
+
+    // The window goes first in the buffer, so matches can refer back into it.
+    let mut out = Vec::new();
+    out.extend_from_slice(window);
+    let mut out_pos = window.len();
+    let size = match end {
+        Some(end) => {
+            let size = end
+                .total_out()
+                .checked_sub(total_out)
+                .ok_or(TINFLStatus::BadParam)?;
+            // A deflate stream expands by at most 1032 to 1 (a 258 byte match in two bits),
+            // so an index that claims more output than the input between the points can
+            // hold is wrong, and is not trusted for the allocation.
+            let in_len = end
+                .total_in()
+                .checked_sub(total_in)
+                .filter(|&in_len| in_len <= input.len() as u64)
+                .ok_or(TINFLStatus::BadParam)?;
+            if size > (in_len + 1).saturating_mul(1032) {
+                return Err(TINFLStatus::BadParam);
+            }
+            usize::try_from(size).map_err(|_| TINFLStatus::BadParam)?
+        }
+        None => 32 * 1024,
+    };
+    out.resize(out_pos + size, 0);
+
+    loop {
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut decomp,
+            input,
+            &mut out,
+            out_pos,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        input = &input[in_consumed..];
+        out_pos += out_consumed;
+
+        match (status, end) {
+            (TINFLStatus::Done, None) => break,
+            (TINFLStatus::HasMoreOutput, None) => {
+                let new_len = out.len().saturating_mul(2);
+                out.resize(new_len, 0);
+            }
+            // The part ends with the output of the block before the next point.
+            (TINFLStatus::HasMoreOutput | TINFLStatus::NeedsMoreInput, Some(end))
+                if out_pos == out.len() =>
+            {
+                if data_format == DataFormat::Zlib
+                    && decomp.adler32() != Some(end.boundary_state().check_adler32)
+                {
+                    return Err(TINFLStatus::Adler32Mismatch);
+                }
+                break;
+            }
+            (TINFLStatus::Done, Some(_)) => return Err(TINFLStatus::Failed),
+            (status, _) => return Err(status),
+        }
+    }
+
+    out.truncate(out_pos);
+    out.drain(..window.len());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::decompress_to_vec_parallel;
+    use crate::DataFormat;
+    use crate::deflate::{compress_to_vec, compress_to_vec_zlib};
+    use crate::inflate::TINFLStatus;
+    use crate::inflate::core::BlockBoundaryState;
+    use crate::inflate::index::{SeekIndex, build_index};
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn parallel_decompress() {
+        let data = crate::test_util::random(1_000_000, 10);
+
+        for zlib in [false, true] {
+            let (encoded, data_format) = if zlib {
+                (compress_to_vec_zlib(&data, 6), DataFormat::Zlib)
+            } else {
+                (compress_to_vec(&data, 6), DataFormat::Raw)
+            };
+            for span in [0, 100_000, 10_000_000] {
+                let index = build_index(&mut Cursor::new(&encoded[..]), data_format, span)
+                    .await
+                    .unwrap();
+                let res = decompress_to_vec_parallel(&encoded, &index).unwrap();
+                assert!(res == data);
+            }
+        }
+
+        // A corrupted part is caught by its checksum.
+        let mut encoded = compress_to_vec_zlib(&data, 6);
+        let index = build_index(&mut Cursor::new(&encoded[..]), DataFormat::Zlib, 100_000)
+            .await
+            .unwrap();
+        let second = index.points()[1].total_in() as usize;
+        encoded[second - 100] ^= 1;
+        let err = decompress_to_vec_parallel(&encoded, &index).unwrap_err();
+        assert!(err.status != TINFLStatus::Done);
+        assert_eq!(err.output, data[..index.points()[0].total_out() as usize]);
+
+        // An index that claims more output than the stream can hold is rejected before
+        // allocating for it.
+        let encoded = compress_to_vec(&data, 6);
+        let index = build_index(&mut Cursor::new(&encoded[..]), DataFormat::Raw, 100_000)
+            .await
+            .unwrap();
+        let mut bytes = index.to_bytes();
+        let last = bytes.len() - (20 + BlockBoundaryState::ENCODED_LEN + 32 * 1024);
+        bytes[last + 8..last + 16].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        let index = SeekIndex::from_bytes(&bytes).unwrap();
+        let err = decompress_to_vec_parallel(&encoded, &index).unwrap_err();
+        assert_eq!(err.status, TINFLStatus::BadParam);
+    }
+}
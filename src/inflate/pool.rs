@@ -0,0 +1,155 @@
+//! A thread-safe pool of reusable decompression states.
+//!
+//! [`InflateState`] carries a 32KiB window plus the decompressor tables, so it is worth
+//! reusing between requests. An [`InflatePool`] hands out states that are reset and returned
+//! to the pool when dropped.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use super::stream::{FullReset, InflateState};
+use crate::DataFormat;
+
+/// A pool of [`InflateState`] instances sharing the same data format.
+pub struct InflatePool {
+    data_format: DataFormat,
+    max_idle: usize,
+    idle: Mutex<Vec<Box<InflateState>>>,
+}
+
+impl InflatePool {
+    /// Create an empty pool. States are created on demand using `data_format`.
+    pub fn new(data_format: DataFormat) -> InflatePool {
+        InflatePool {
+            data_format,
+            max_idle: usize::MAX,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Limit the number of idle states kept around. States returned while the pool
+    /// is full are dropped.
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Take a state from the pool, creating a new one if none is available.
+    ///
+    /// The state is reset to the pool's data format and returned to the pool when the guard
+    /// is dropped.
+    pub fn get(&self) -> PooledInflateState<'_> {
+        let state = self
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| InflateState::new_boxed(self.data_format));
+        PooledInflateState {
+            pool: self,
+            state: Some(state),
+        }
+    }
+
+    /// Number of idle states currently held by the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// An [`InflateState`] checked out from an [`InflatePool`].
+pub struct PooledInflateState<'a> {
+    pool: &'a InflatePool,
+    state: Option<Box<InflateState>>,
+}
+
+impl Deref for PooledInflateState<'_> {
+    type Target = InflateState;
+
+    fn deref(&self) -> &InflateState {
+        self.state.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledInflateState<'_> {
+    fn deref_mut(&mut self) -> &mut InflateState {
+        self.state.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledInflateState<'_> {
+    fn drop(&mut self) {
+        if let Some(mut state) = self.state.take() {
+            // Zero the window so data from one user can't leak to the next, and drop anything
+            // set while checked out. Done before taking the lock so other threads don't wait.
+            state.reset_as(FullReset(self.pool.data_format));
+            state.set_metrics(None);
+            #[cfg(feature = "block-boundary")]
+            state.set_stop_on_block_boundary(false);
+
+            let mut idle = self.pool.idle.lock().unwrap_or_else(|e| e.into_inner());
+            if idle.len() < self.pool.max_idle {
+                idle.push(state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InflatePool;
+    use crate::inflate::stream::{FullReset, inflate};
+    use crate::metrics::MetricsSink;
+    use crate::{DataFormat, MZFlush, MZStatus};
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const ENCODED: [u8; 20] = [
+        120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
+    ];
+
+    #[tokio::test]
+    async fn reuse_state() {
+        let pool = InflatePool::new(DataFormat::Zlib);
+        for _ in 0..3 {
+            let mut state = pool.get();
+            let mut out = Cursor::new(Vec::new());
+            let res = inflate(&mut state, &ENCODED, &mut out, MZFlush::None)
+                .await
+                .unwrap();
+            let status = res.status.expect("Failed to decompress!");
+            assert_eq!(status, MZStatus::StreamEnd);
+            assert_eq!(out.get_ref(), b"Hello, zlib!");
+        }
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn settings_restored() {
+        struct Calls(AtomicU64);
+        impl MetricsSink for Calls {
+            fn bytes_consumed(&self, _bytes: u64) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let pool = InflatePool::new(DataFormat::Zlib);
+        let calls = Arc::new(Calls(AtomicU64::new(0)));
+        let mut state = pool.get();
+        state.reset_as(FullReset(DataFormat::Raw));
+        state.set_metrics(Some(calls.clone()));
+        #[cfg(feature = "block-boundary")]
+        state.set_stop_on_block_boundary(true);
+        drop(state);
+
+        let mut state = pool.get();
+        let mut out = Cursor::new(Vec::new());
+        let res = inflate(&mut state, &ENCODED, &mut out, MZFlush::None)
+            .await
+            .unwrap();
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        assert_eq!(out.get_ref(), b"Hello, zlib!");
+        assert_eq!(calls.0.load(Ordering::Relaxed), 0);
+    }
+}
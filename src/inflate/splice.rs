@@ -0,0 +1,206 @@
+//! Cutting and joining raw deflate streams without recompressing them.
+//!
+//! A deflate stream is a sequence of blocks, the last one marked with a flag in its header,
+//! and blocks generally don't start at byte boundaries. [`terminate_at_boundary`] ends a stream
+//! early at a block boundary by adding an empty final block, and [`concat_raw`] clears the
+//! flag of the final block of a stream and appends another stream at the bit it ended on, so
+//! precompressed fragments can be put together as one stream.
+
+use crate::alloc::vec;
+use crate::alloc::vec::Vec;
+
+use super::TINFLStatus;
+use super::core::{DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress, inflate_flags};
+
+/// An empty final block with fixed codes: the final flag, block type 1 and the end of block
+/// code, which is 7 zero bits.
+const EMPTY_FINAL_BLOCK: (u8, u64) = (0b011, 10);
+
+/// End the raw deflate stream in `input` at the block boundary `bit_offset` bits from the
+/// start, by adding an empty final block there.
+///
+/// Block boundaries can be recorded while compressing with
+/// [`CompressorOxide::set_record_boundaries`](crate::deflate::core::CompressorOxide::set_record_boundaries),
+/// or found while decompressing with
+/// [`TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY`](inflate_flags::TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY).
+/// The offset is not checked to be a block boundary, the result is only a valid stream if it
+/// is one.
+///
+/// Returns `None` if the offset is past the end of `input`.
+pub fn terminate_at_boundary(input: &[u8], bit_offset: u64) -> Option<Vec<u8>> {
+    let mut output = prefix(input, bit_offset)?;
+    let (bits, len) = EMPTY_FINAL_BLOCK;
+    append_bits(&mut output, bit_offset, &[bits, 0], len);
+    Some(output)
+}
+
+/// Join the raw deflate streams `first` and `second` into one stream, that decompresses to
+/// the data of `first` followed by the data of `second`.
+///
+/// `first` is decompressed to find its final block, but neither stream is recompressed.
+///
+/// Returns the status of decompressing `first` if it is not a complete, valid stream.
+pub fn concat_raw(first: &[u8], second: &[u8]) -> Result<Vec<u8>, TINFLStatus> {
+    let mut first = first.to_vec();
+    let end = clear_final_flag(&mut first)?;
+    let mut output = prefix(&first, end).ok_or(TINFLStatus::Failed)?;
+    append_bits(&mut output, end, second, second.len() as u64 * 8);
+    Ok(output)
+}
+
+/// Clear the flag of the final block of the raw deflate stream in `data`, and return the
+/// position of the end of that block in bits.
+fn clear_final_flag(data: &mut [u8]) -> Result<u64, TINFLStatus> {
+    let flags = inflate_flags::TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY;
+    let mut decomp = DecompressorOxide::new();
+    let mut window = vec![0; TINFL_LZ_DICT_SIZE];
+    let mut out_pos = 0;
+    let mut in_pos = 0;
+    // The position of the next block header, in bits.
+    let mut bit_pos = 0;
+    let mut cleared = false;
+
+    loop {
+        let byte = (bit_pos / 8) as usize;
+        let mask = 1 << (bit_pos % 8);
+        if !cleared && data.get(byte).is_some_and(|&byte| byte & mask != 0) {
+            data[byte] &= !mask;
+            cleared = true;
+            // The rest of a partly read byte is kept in the state, so it has to be changed
+            // there too.
+            if let Some(mut state) = decomp.block_boundary_state()
+                && state.num_bits != 0
+            {
+                state.bit_buf = data[byte] >> (8 - state.num_bits);
+                decomp = DecompressorOxide::from_block_boundary_state(&state);
+            }
+        }
+
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut decomp,
+            &data[in_pos..],
+            &mut window,
+            out_pos,
+            flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        in_pos += in_consumed;
+        out_pos = (out_pos + out_consumed) & (TINFL_LZ_DICT_SIZE - 1);
+
+        match status {
+            TINFLStatus::BlockBoundary => {
+                let num_bits = decomp
+                    .block_boundary_state()
+                    .map_or(0, |state| state.num_bits);
+                bit_pos = in_pos as u64 * 8 - u64::from(num_bits);
+                if cleared {
+                    return Ok(bit_pos);
+                }
+            }
+            TINFLStatus::HasMoreOutput => {}
+            // With the flag cleared the stream can't end.
+            TINFLStatus::Done => return Err(TINFLStatus::Failed),
+            status => return Err(status),
+        }
+    }
+}
+
+/// Copy the first `bit_len` bits of `input`, with the rest of the last byte cleared.
+fn prefix(input: &[u8], bit_len: u64) -> Option<Vec<u8>> {
+    let len = usize::try_from(bit_len.div_ceil(8)).ok()?;
+    let mut output = input.get(..len)?.to_vec();
+    if !bit_len.is_multiple_of(8)
+        && let Some(last) = output.last_mut()
+    {
+        *last &= (1 << (bit_len % 8)) - 1;
+    }
+    Some(output)
+}
+
+/// Append the first `len` bits of `bits` to `output`, which holds `bit_pos` bits of data.
+fn append_bits(output: &mut Vec<u8>, bit_pos: u64, bits: &[u8], len: u64) {
+    let shift = bit_pos % 8;
+    let bytes = &bits[..len.div_ceil(8) as usize];
+    if shift == 0 {
+        output.extend_from_slice(bytes);
+    } else {
+        for &byte in bytes {
+            *output.last_mut().unwrap() |= byte << shift;
+            output.push(byte >> (8 - shift));
+        }
+    }
+    // Drop the bytes left empty by the shift or the padding.
+    output.truncate((bit_pos + len).div_ceil(8) as usize);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{concat_raw, terminate_at_boundary};
+    use crate::deflate::compress_to_vec;
+    use crate::deflate::core::{
+        CompressorOxide, TDEFLFlush, compress_to_output, create_comp_flags_from_zip_params,
+    };
+    use crate::inflate::{TINFLStatus, decompress_to_vec};
+    use crate::test_util::random;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn terminate() {
+        let data = random(300_000, 10);
+        let mut compressor = CompressorOxide::new(create_comp_flags_from_zip_params(6, 0, 0));
+        compressor.set_record_boundaries(true);
+        let mut encoded = Vec::new();
+        compress_to_output(&mut compressor, &data, TDEFLFlush::Finish, |buf| {
+            encoded.extend_from_slice(buf);
+            true
+        });
+
+        let boundaries = compressor.boundaries();
+        assert!(boundaries.iter().any(|b| b.out_bit_offset % 8 != 0));
+        for boundary in boundaries {
+            let terminated = terminate_at_boundary(&encoded, boundary.out_bit_offset).unwrap();
+            let end = boundary.in_offset as usize;
+            assert_eq!(decompress_to_vec(&terminated).unwrap(), data[..end]);
+        }
+        assert_eq!(
+            decompress_to_vec(&terminate_at_boundary(&encoded, 0).unwrap()).unwrap(),
+            []
+        );
+        assert_eq!(
+            terminate_at_boundary(&encoded, encoded.len() as u64 * 8 + 1),
+            None
+        );
+    }
+
+    #[test]
+    fn concat() {
+        let parts = [
+            random(100_000, 10),
+            random(5, 10),
+            Vec::new(),
+            random(70_000, 10),
+        ];
+        for level in [0, 1, 6, 10] {
+            let mut joined = compress_to_vec(&parts[0], level);
+            for part in &parts[1..] {
+                joined = concat_raw(&joined, &compress_to_vec(part, level)).unwrap();
+            }
+            assert!(decompress_to_vec(&joined).unwrap() == parts.concat());
+        }
+
+        // A stream that was cut short can be continued.
+        let first = compress_to_vec(&parts[0], 6);
+        let cut = terminate_at_boundary(&first, 0).unwrap();
+        let joined = concat_raw(&cut, &first).unwrap();
+        assert!(decompress_to_vec(&joined).unwrap() == parts[0]);
+
+        let truncated = &first[..first.len() - 10];
+        assert!(concat_raw(truncated, &first).is_err());
+        assert_eq!(
+            concat_raw(&[], &first),
+            Err(TINFLStatus::FailedCannotMakeProgress)
+        );
+    }
+}
@@ -1,22 +1,102 @@
 //! Extra streaming decompression functionality.
 //!
 //! As of now this is mainly intended for use to build a higher-level wrapper.
+//!
+//! Without the `std` feature only [`InflateState`] and [`inflate_to_slice`] are available,
+//! which never allocate, and with `block-boundary` and `with-alloc`, [`InflateCheckpoint`].
+//! With the `bytes` feature, [`inflate_buf`] works on `bytes::Buf` and `bytes::BufMut`.
 
 #[cfg(feature = "with-alloc")]
 use crate::alloc::boxed::Box;
+#[cfg(any(
+    feature = "std",
+    all(feature = "with-alloc", feature = "block-boundary")
+))]
+use crate::alloc::vec::Vec;
+#[cfg(feature = "std")]
 use crate::error::Error;
+#[cfg(feature = "std")]
+use crate::gzip::{GzHeader, GzipError, check_trailer};
 use crate::inflate::TINFLStatus;
-use crate::inflate::core::{DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress, inflate_flags};
-use crate::{DataFormat, MZError, MZFlush, MZResult, MZStatus, StreamResult};
+#[cfg(feature = "std")]
+use crate::inflate::format_flags;
+#[cfg(all(feature = "with-alloc", feature = "block-boundary"))]
+use crate::inflate::core::BlockBoundaryState;
+use crate::inflate::core::{
+    DecodeStats, DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress, inflate_flags,
+};
+#[cfg(feature = "std")]
+use crate::MZResult;
+#[cfg(feature = "std")]
+use crate::metrics::MetricsSink;
+#[cfg(feature = "std")]
+use crate::shared::{ScratchBuffer, Segments, WriteVectored, update_crc32};
+#[cfg(feature = "std")]
+use crate::StreamSummary;
+use crate::{DataFormat, MZError, MZFlush, MZStatus, StreamResult};
+#[cfg(feature = "bytes")]
+use bytes::{Buf, BufMut};
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
 use binrw::io::read::Read;
+#[cfg(feature = "std")]
 use binrw::io::seek::Seek;
+#[cfg(feature = "std")]
 use binrw::io::write::Write;
-use std::io::SeekFrom;
+#[cfg(feature = "std")]
+use std::io::{IoSlice, SeekFrom};
+
+/// Storage for the window of an [`InflateState`].
+///
+/// Implemented for anything that can be viewed as a mutable byte slice, e.g. arrays,
+/// `Box<[u8]>`, `Vec<u8>` or a borrowed `&mut [u8]`, so the caller can decide where the
+/// memory lives.
+pub trait WindowBuffer: AsRef<[u8]> + AsMut<[u8]> {}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]> + ?Sized> WindowBuffer for T {}
+
+/// The window buffer of an [`InflateState`], which is wiped when dropped with the `zeroize`
+/// feature.
+///
+/// The buffer is only taken out by [`InflateState::into_window()`], which consumes the state.
+#[derive(Clone)]
+struct Window<D: WindowBuffer>(Option<D>);
+
+impl<D: WindowBuffer> Deref for Window<D> {
+    type Target = D;
+
+    #[inline]
+    fn deref(&self) -> &D {
+        match &self.0 {
+            Some(window) => window,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<D: WindowBuffer> DerefMut for Window<D> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut D {
+        match &mut self.0 {
+            Some(window) => window,
+            None => unreachable!(),
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<D: WindowBuffer> Drop for Window<D> {
+    fn drop(&mut self) {
+        if let Some(window) = &mut self.0 {
+            zeroize::Zeroize::zeroize(window.as_mut());
+        }
+    }
+}
 
 /// Tag that determines reset policy of [InflateState](struct.InflateState.html)
 pub trait ResetPolicy {
     /// Performs reset
-    fn reset(&self, state: &mut InflateState);
+    fn reset<D: WindowBuffer>(&self, state: &mut InflateState<D>);
 }
 
 /// Resets state, without performing expensive ops (e.g. zeroing buffer)
@@ -25,13 +105,15 @@ pub trait ResetPolicy {
 pub struct MinReset;
 
 impl ResetPolicy for MinReset {
-    fn reset(&self, state: &mut InflateState) {
+    fn reset<D: WindowBuffer>(&self, state: &mut InflateState<D>) {
         state.decompressor().init();
         state.dict_ofs = 0;
         state.dict_avail = 0;
         state.first_call = true;
         state.has_flushed = false;
         state.last_status = TINFLStatus::NeedsMoreInput;
+        state.total_in = 0;
+        state.total_out = 0;
     }
 }
 
@@ -40,9 +122,9 @@ pub struct ZeroReset;
 
 impl ResetPolicy for ZeroReset {
     #[inline]
-    fn reset(&self, state: &mut InflateState) {
+    fn reset<D: WindowBuffer>(&self, state: &mut InflateState<D>) {
         MinReset.reset(state);
-        state.dict = [0; TINFL_LZ_DICT_SIZE];
+        state.dict.as_mut().fill(0);
     }
 }
 
@@ -53,16 +135,32 @@ pub struct FullReset(pub DataFormat);
 
 impl ResetPolicy for FullReset {
     #[inline]
-    fn reset(&self, state: &mut InflateState) {
+    fn reset<D: WindowBuffer>(&self, state: &mut InflateState<D>) {
         ZeroReset.reset(state);
         state.data_format = self.0;
     }
 }
 
+/// A summary of the stream decoded by an [`InflateState`], from [`InflateState::stats()`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct InflateStats {
+    /// The blocks by type, the dynamic Huffman tables built and the longest match distance.
+    pub decoded: DecodeStats,
+    /// Number of compressed bytes consumed.
+    pub compressed_bytes: u64,
+    /// Number of decompressed bytes written.
+    pub decompressed_bytes: u64,
+}
+
 /// A struct that compbines a decompressor with extra data for streaming decompression.
 ///
+/// By default the 32KiB window is stored inline. Use [`InflateState::with_window()`] to supply
+/// the window buffer yourself.
+///
+/// With the `zeroize` feature, the window is wiped when the state is dropped, also if it was
+/// supplied by the caller, unless it was taken back with [`InflateState::into_window()`].
 #[derive(Clone)]
-pub struct InflateState {
+pub struct InflateState<D: WindowBuffer = [u8; TINFL_LZ_DICT_SIZE]> {
     /// Inner decompressor struct
     decomp: DecompressorOxide,
 
@@ -71,7 +169,7 @@ pub struct InflateState {
     /// Cursor-like class.
     /// We may also look into whether we need to keep a buffer here, or just one in the
     /// decompressor struct.
-    dict: [u8; TINFL_LZ_DICT_SIZE],
+    dict: Window<D>,
     /// Where in the buffer are we currently at?
     dict_ofs: usize,
     /// How many bytes of data to be flushed is there currently in the buffer?
@@ -84,22 +182,42 @@ pub struct InflateState {
     /// TODO: This should be stored in the decompressor.
     data_format: DataFormat,
     last_status: TINFLStatus,
+
+    /// Total number of input bytes consumed by `inflate`.
+    total_in: u64,
+    /// Total number of bytes written by `inflate`.
+    total_out: u64,
+
+    /// Whether `inflate` should return after each deflate block.
+    #[cfg(feature = "block-boundary")]
+    stop_on_block_boundary: bool,
+
+    /// Where to report the bytes going through, flushes and errors, if anywhere.
+    #[cfg(feature = "std")]
+    metrics: Option<std::sync::Arc<dyn MetricsSink>>,
 }
 
 impl Default for InflateState {
     fn default() -> Self {
         InflateState {
             decomp: DecompressorOxide::default(),
-            dict: [0; TINFL_LZ_DICT_SIZE],
+            dict: Window(Some([0; TINFL_LZ_DICT_SIZE])),
             dict_ofs: 0,
             dict_avail: 0,
             first_call: true,
             has_flushed: false,
             data_format: DataFormat::Raw,
             last_status: TINFLStatus::NeedsMoreInput,
+            total_in: 0,
+            total_out: 0,
+            #[cfg(feature = "block-boundary")]
+            stop_on_block_boundary: false,
+            #[cfg(feature = "std")]
+            metrics: None,
         }
     }
 }
+
 impl InflateState {
     /// Create a new state.
     ///
@@ -128,16 +246,6 @@ impl InflateState {
         b
     }
 
-    /// Access the innner decompressor.
-    pub fn decompressor(&mut self) -> &mut DecompressorOxide {
-        &mut self.decomp
-    }
-
-    /// Return the status of the last call to `inflate` with this `InflateState`.
-    pub const fn last_status(&self) -> TINFLStatus {
-        self.last_status
-    }
-
     /// Create a new state using miniz/zlib style window bits parameter.
     ///
     /// The decompressor does not support different window sizes. As such,
@@ -149,6 +257,84 @@ impl InflateState {
         b.data_format = DataFormat::from_window_bits(window_bits);
         b
     }
+}
+
+impl<D: WindowBuffer> InflateState<D> {
+    /// Create a new state using a caller-provided window buffer.
+    ///
+    /// The length of `window` must be a power of two between 256 and 32KiB. Buffers smaller
+    /// than 32KiB can only be used for streams that were compressed with a window no larger
+    /// than the buffer, e.g. to fit the decompressor in 8 or 16KiB of RAM. A match that
+    /// reaches further back than the buffer fails with [`MZError::Data`], as does a zlib
+    /// header asking for a larger window.
+    ///
+    /// Returns [`MZError::Param`] if the buffer has an unsupported length.
+    pub fn with_window(mut window: D, data_format: DataFormat) -> Result<InflateState<D>, MZError> {
+        let len = window.as_ref().len();
+        if !len.is_power_of_two() || !(256..=TINFL_LZ_DICT_SIZE).contains(&len) {
+            return Err(MZError::Param);
+        }
+        window.as_mut().fill(0);
+        Ok(InflateState {
+            decomp: DecompressorOxide::default(),
+            dict: Window(Some(window)),
+            dict_ofs: 0,
+            dict_avail: 0,
+            first_call: true,
+            has_flushed: false,
+            data_format,
+            last_status: TINFLStatus::NeedsMoreInput,
+            total_in: 0,
+            total_out: 0,
+            #[cfg(feature = "block-boundary")]
+            stop_on_block_boundary: false,
+            #[cfg(feature = "std")]
+            metrics: None,
+        })
+    }
+
+    /// Consume the state and return the window buffer.
+    pub fn into_window(mut self) -> D {
+        match self.dict.0.take() {
+            Some(window) => window,
+            None => unreachable!(),
+        }
+    }
+
+    /// Access the innner decompressor.
+    pub fn decompressor(&mut self) -> &mut DecompressorOxide {
+        &mut self.decomp
+    }
+
+    /// Return the status of the last call to `inflate` with this `InflateState`.
+    pub const fn last_status(&self) -> TINFLStatus {
+        self.last_status
+    }
+
+    /// The error for the last call to `inflate` with this `InflateState` failing.
+    #[cfg(feature = "std")]
+    pub(crate) fn last_error(&self) -> Error {
+        Error::decompress(&self.decomp, self.last_status)
+    }
+
+    /// Return the total number of input bytes consumed since the state was created or reset.
+    pub const fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the total number of bytes written since the state was created or reset.
+    pub const fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Get a summary of the stream decoded since the state was created or reset.
+    pub const fn stats(&self) -> InflateStats {
+        InflateStats {
+            decoded: self.decomp.stats(),
+            compressed_bytes: self.total_in,
+            decompressed_bytes: self.total_out,
+        }
+    }
 
     #[inline]
     /// Reset the decompressor without re-allocating memory, using the given
@@ -162,50 +348,493 @@ impl InflateState {
     pub fn reset_as<T: ResetPolicy>(&mut self, policy: T) {
         policy.reset(self)
     }
+
+    /// Report the bytes consumed and produced, the completed flushes and the errors of this
+    /// state to `metrics`, or stop reporting them with `None`.
+    ///
+    /// The sink is kept when the state is reset, and shared with its clones.
+    #[cfg(feature = "std")]
+    pub fn set_metrics(&mut self, metrics: Option<std::sync::Arc<dyn MetricsSink>>) {
+        self.metrics = metrics;
+    }
+
+    /// Report the result of a call with `flush` that consumed all of its input or not to the
+    /// metrics sink, if there is one.
+    #[cfg(feature = "std")]
+    fn record_metrics(&self, res: &StreamResult, flush: MZFlush, all_in: bool) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        if res.bytes_consumed > 0 {
+            metrics.bytes_consumed(res.bytes_consumed as u64);
+        }
+        if res.bytes_written > 0 {
+            metrics.bytes_produced(res.bytes_written as u64);
+        }
+        match res.status {
+            Err(MZError::Buf) => {}
+            Err(err) => metrics.error(err),
+            Ok(_) if flush != MZFlush::None && all_in && self.dict_avail == 0 => metrics.flushed(),
+            Ok(_) => {}
+        }
+    }
+
+    /// Make [`inflate`] return after every completed deflate block.
+    ///
+    /// When [`InflateState::last_status()`] is [`TINFLStatus::BlockBoundary`] all output has
+    /// been written and an [`InflateCheckpoint`] can be captured.
+    #[cfg(feature = "block-boundary")]
+    pub fn set_stop_on_block_boundary(&mut self, stop: bool) {
+        self.stop_on_block_boundary = stop;
+    }
+}
+
+/// A suspended decompression that can be resumed later with a fresh [`InflateState`].
+///
+/// Wraps a [`BlockBoundaryState`] together with the data format and the last 32KiB of
+/// decompressed output, so callers don't have to manage the window themselves.
+#[cfg(all(feature = "with-alloc", feature = "block-boundary"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InflateCheckpoint {
+    pub(crate) boundary: BlockBoundaryState,
+    pub(crate) data_format: DataFormat,
+    pub(crate) window: Vec<u8>,
+    pub(crate) total_in: u64,
+    pub(crate) total_out: u64,
+}
+
+#[cfg(all(feature = "with-alloc", feature = "block-boundary"))]
+impl InflateCheckpoint {
+    /// Capture a checkpoint from `state`.
+    ///
+    /// Returns `None` unless the last call to [`inflate`] stopped on a block boundary, which
+    /// requires [`InflateState::set_stop_on_block_boundary()`] to be enabled.
+    pub fn capture<D: WindowBuffer>(state: &InflateState<D>) -> Option<InflateCheckpoint> {
+        if state.last_status != TINFLStatus::BlockBoundary || state.dict_avail != 0 {
+            return None;
+        }
+        let boundary = state.decomp.block_boundary_state()?;
+        let dict = state.dict.as_ref();
+        let mut window = Vec::with_capacity(dict.len());
+        window.extend_from_slice(&dict[state.dict_ofs..]);
+        window.extend_from_slice(&dict[..state.dict_ofs]);
+        Some(InflateCheckpoint {
+            boundary,
+            data_format: state.data_format,
+            window,
+            total_in: state.total_in,
+            total_out: state.total_out,
+        })
+    }
+
+    /// Create a checkpoint from previously persisted parts without a window snapshot.
+    ///
+    /// The window then has to be passed to [`InflateCheckpoint::resume()`], and the
+    /// byte counters of the resumed state start from zero.
+    pub fn from_parts(boundary: BlockBoundaryState, data_format: DataFormat) -> InflateCheckpoint {
+        InflateCheckpoint {
+            boundary,
+            data_format,
+            window: Vec::new(),
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// The decompressor state at the block boundary.
+    pub fn boundary_state(&self) -> &BlockBoundaryState {
+        &self.boundary
+    }
+
+    /// The data format of the stream being decompressed.
+    pub const fn data_format(&self) -> DataFormat {
+        self.data_format
+    }
+
+    /// The window captured with the checkpoint. Empty if created with
+    /// [`InflateCheckpoint::from_parts()`].
+    pub fn window(&self) -> &[u8] {
+        &self.window
+    }
+
+    /// The number of compressed bytes consumed when the checkpoint was captured.
+    ///
+    /// Decompression resumes from this byte, after the
+    /// [`num_bits`](BlockBoundaryState::num_bits) bits of the byte before it that are kept in
+    /// the boundary state.
+    pub const fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// The number of bytes decompressed when the checkpoint was captured.
+    pub const fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Create a new state that continues decompression from `checkpoint`.
+    ///
+    /// `window_tail` should be the tail of the output produced so far; only the last
+    /// 32KiB are used. If `None`, the window captured with the checkpoint is used.
+    /// Input has to be supplied starting at the first byte that was not consumed when
+    /// the checkpoint was captured.
+    pub fn resume(
+        checkpoint: &InflateCheckpoint,
+        window_tail: Option<&[u8]>,
+    ) -> Box<InflateState> {
+        let tail = window_tail.unwrap_or(&checkpoint.window);
+        let tail = &tail[tail.len().saturating_sub(TINFL_LZ_DICT_SIZE)..];
+
+        let mut state = InflateState::new_boxed(checkpoint.data_format);
+        state.decomp = DecompressorOxide::from_block_boundary_state(&checkpoint.boundary);
+        state.dict[..tail.len()].copy_from_slice(tail);
+        state.dict_ofs = tail.len() & (TINFL_LZ_DICT_SIZE - 1);
+        state.first_call = false;
+        state.last_status = TINFLStatus::BlockBoundary;
+        state.total_in = checkpoint.total_in;
+        state.total_out = checkpoint.total_out;
+        state.stop_on_block_boundary = true;
+        state
+    }
 }
-pub fn decompress_stream<'a, R: Read + Send + 'a, W: Write + Seek + Send>(
+/// Decompress the raw deflate data read from `input` to `writer`.
+///
+/// Returns a summary of the data that went through. Use [`decompress_stream_with`] for other
+/// settings.
+#[cfg(feature = "std")]
+pub fn decompress_stream<'a, R: Read + Send + 'a, W: Write + Send>(
     input: &'a mut R,
     writer: &'a mut W,
-) -> impl Future<Output = Result<(), Error>> + Send + 'a {
+) -> impl Future<Output = Result<StreamSummary, Error>> + Send + 'a {
+    decompress_stream_with(input, writer, DecodeOptions::default())
+}
+
+/// Settings for [`decompress_stream_with`] and [`copy_decode_with`]. The defaults are those of
+/// [`decompress_stream`].
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug)]
+pub struct DecodeOptions {
+    /// How the compressed data is wrapped. Ignored if `gzip` is set.
+    pub format: DataFormat,
+    /// Whether the data is a gzip member: a gzip header, which is checked and skipped, the
+    /// deflate data, and a trailer with the CRC-32 and length of the data.
+    pub gzip: bool,
+    /// Whether to check the Adler-32 checksum at the end of zlib data, or the CRC-32 and
+    /// length at the end of gzip data. A mismatch is an error.
+    pub verify_checksum: bool,
+    /// The most bytes that may be written. More data is an error with the status
+    /// [`TINFLStatus::HasMoreOutput`], after exactly this many bytes have been written.
+    pub max_output_size: u64,
+    /// The size of the input buffer, i.e. the most that is read from the input at once. At
+    /// least one byte is used. The output is written from the 32 KiB window whatever this is.
+    pub chunk_size: usize,
+}
+
+#[cfg(feature = "std")]
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            format: DataFormat::Raw,
+            gzip: false,
+            verify_checksum: true,
+            max_output_size: u64::MAX,
+            chunk_size: 32 * 1024,
+        }
+    }
+}
+
+/// Decompress the data read from `input` to `writer`, with the settings of `options`.
+///
+/// The input buffer is allocated once, and reused until the end of the stream. Input after
+/// the end of the compressed stream may have been read, but is ignored.
+///
+/// Returns a summary of the data that went through.
+#[cfg(feature = "std")]
+pub fn decompress_stream_with<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: DecodeOptions,
+) -> impl Future<Output = Result<StreamSummary, Error>> + Send + 'a {
     async move {
-        let mut state = InflateState::new_boxed(DataFormat::Raw);
+        let format = match options.format {
+            _ if options.gzip => DataFormat::Raw,
+            DataFormat::Zlib if !options.verify_checksum => DataFormat::ZLibIgnoreChecksum,
+            format => format,
+        };
+        let mut state = InflateState::new_boxed(format);
         let mut flush: MZFlush = MZFlush::None;
+        let mut sink = StreamSink {
+            writer,
+            written: 0,
+            limit: options.max_output_size,
+            crc32: (options.gzip && options.verify_checksum).then_some(0),
+            over_limit: false,
+        };
 
-        let mut input_buffer = vec![0; 32 * 1024];
+        let mut input_buffer = ScratchBuffer::new(options.chunk_size.max(1));
         let mut input_offset = 0;
         let mut input_end = 0;
         let mut is_eof = false;
+        let mut summary = StreamSummary::default();
+
+        // The header can be longer than the buffer, so it is collected on its own first. It is
+        // parsed again each time its length doubles rather than after every read, and the
+        // data read after it is used before reading more.
+        let mut header = Vec::new();
+        let mut rest: &[u8] = &[];
+        if options.gzip {
+            let mut parse_at = 0;
+            let header_len = loop {
+                if header.len() >= parse_at || header.len() == MAX_GZIP_HEADER_LEN {
+                    match GzHeader::parse(&header) {
+                        Ok((_, len)) => break len,
+                        Err(GzipError::Truncated) if header.len() == MAX_GZIP_HEADER_LEN => {
+                            return Err(Error::Gzip(GzipError::HeaderTooLong));
+                        }
+                        Err(GzipError::Truncated) => parse_at = 2 * header.len(),
+                        Err(err) => return Err(Error::Gzip(err)),
+                    }
+                }
+                let len = (MAX_GZIP_HEADER_LEN - header.len()).min(input_buffer.len());
+                let read = input.read(&mut input_buffer[..len]).await?;
+                if read == 0 {
+                    // The header may have become complete since it was last parsed.
+                    match GzHeader::parse(&header) {
+                        Ok((_, len)) => break len,
+                        Err(err) => return Err(Error::Gzip(err)),
+                    }
+                }
+                summary.bytes_read += read as u64;
+                summary.chunks += 1;
+                header.extend_from_slice(&input_buffer[..read]);
+            };
+            rest = &header[header_len..];
+        }
 
         loop {
             if input_offset == input_end && !is_eof {
                 input_offset = 0;
-                input_end = input.read(input_buffer.as_mut_slice()).await?;
+                input_end = refill(input, &mut rest, &mut input_buffer, &mut summary).await?;
                 if input_end == 0 {
                     is_eof = true;
                     flush = MZFlush::Finish;
                 }
             }
 
-            let status = inflate(
+            let status = match inflate(
                 &mut state,
                 &input_buffer[input_offset..input_end],
-                writer,
+                &mut sink,
                 flush,
             )
-            .await?;
+            .await
+            {
+                Err(_) if sink.over_limit => {
+                    return Err(Error::Decompress(TINFLStatus::HasMoreOutput));
+                }
+                status => status?,
+            };
+            input_offset += status.bytes_consumed;
             match status.status {
-                Ok(MZStatus::StreamEnd) => return Ok(()),
-                Ok(MZStatus::Ok) => {
-                    input_offset += status.bytes_consumed;
-                    continue;
+                Ok(MZStatus::StreamEnd) => break,
+                Ok(MZStatus::Ok) => continue,
+                Ok(MZStatus::NeedDict) => return Err(Error::NeedDict),
+                Err(_) => return Err(state.last_error()),
+            }
+        }
+
+        summary.bytes_written = sink.written;
+        if options.gzip {
+            let mut trailer = [0; 8];
+            let mut len = 0;
+            while len < trailer.len() {
+                if input_offset == input_end {
+                    input_offset = 0;
+                    input_end = refill(input, &mut rest, &mut input_buffer, &mut summary).await?;
+                    if input_end == 0 {
+                        return Err(Error::Gzip(GzipError::Truncated));
+                    }
                 }
-                _ => {
-                    return Err(Error::Msg("mz error".to_string()));
+                let n = (trailer.len() - len).min(input_end - input_offset);
+                trailer[len..len + n].copy_from_slice(&input_buffer[input_offset..][..n]);
+                input_offset += n;
+                len += n;
+            }
+            if let Some(crc32) = sink.crc32 {
+                check_trailer(&trailer, crc32, sink.written).map_err(Error::Gzip)?;
+            }
+            summary.checksum = sink.crc32;
+        } else if format == DataFormat::Zlib {
+            summary.checksum = state.decomp.adler32();
+        }
+        sink.writer.flush().await?;
+        Ok(summary)
+    }
+}
+
+/// The longest gzip header [`decompress_stream_with`] reads: the fixed fields, the longest
+/// extra field, 64 KiB for the file name and comment, and the CRC16.
+#[cfg(feature = "std")]
+const MAX_GZIP_HEADER_LEN: usize = 10 + 2 + u16::MAX as usize + 64 * 1024 + 2;
+
+/// Fill `buffer` from `rest`, the data read after the gzip header, or read into it from `input`
+/// once that is used up, counting the read in `summary`.
+///
+/// Returns the number of bytes put in `buffer`, 0 at the end of the input.
+#[cfg(feature = "std")]
+async fn refill<R: Read>(
+    input: &mut R,
+    rest: &mut &[u8],
+    buffer: &mut [u8],
+    summary: &mut StreamSummary,
+) -> Result<usize, Error> {
+    if !rest.is_empty() {
+        let len = rest.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&rest[..len]);
+        *rest = &rest[len..];
+        return Ok(len);
+    }
+    let len = input.read(buffer).await?;
+    if len > 0 {
+        summary.bytes_read += len as u64;
+        summary.chunks += 1;
+    }
+    Ok(len)
+}
+
+/// The writer that [`decompress_stream_with`] passes to [`inflate`], which keeps the CRC-32
+/// of the data for gzip, and stops at the output limit.
+#[cfg(feature = "std")]
+struct StreamSink<'a, W> {
+    writer: &'a mut W,
+    written: u64,
+    limit: u64,
+    crc32: Option<u32>,
+    over_limit: bool,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write + Send> Write for StreamSink<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            let room = self.limit - self.written;
+            let buf = if buf.len() as u64 > room {
+                self.over_limit = true;
+                &buf[..room as usize]
+            } else {
+                buf
+            };
+            self.writer.write_all(buf).await?;
+            self.written += buf.len() as u64;
+            if let Some(crc32) = self.crc32.as_mut() {
+                *crc32 = update_crc32(*crc32, buf);
+            }
+            if self.over_limit {
+                return Err(std::io::Error::other("Output limit exceeded"));
+            }
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Send> Seek for StreamSink<'_, W> {
+    fn seek(&mut self, _pos: SeekFrom) -> impl Future<Output = std::io::Result<u64>> + Send {
+        // `inflate` only asks for the length of the output.
+        async move { Ok(self.written) }
+    }
+}
+
+/// Decompress all of `input` to `writer` in one call, without collecting the data in a
+/// [`Vec`] first, e.g. to extract a large blob straight to a file.
+///
+/// The data goes through a 32 KiB window, which is written out as it fills up. On failure,
+/// what was decompressed up to there has been written.
+///
+/// Returns the number of bytes written.
+#[cfg(feature = "std")]
+pub fn decompress_to_writer<'a, W: Write + Send>(
+    input: &'a [u8],
+    writer: &'a mut W,
+    data_format: DataFormat,
+) -> impl Future<Output = Result<u64, Error>> + Send + 'a {
+    async move {
+        let flags = format_flags(data_format);
+        let mut decomp = Box::<DecompressorOxide>::default();
+        let mut window = ScratchBuffer::new(TINFL_LZ_DICT_SIZE);
+        let mut in_pos = 0;
+        let mut out_pos = 0;
+        let mut total = 0;
+        loop {
+            let (status, in_consumed, out_consumed) = decompress(
+                &mut decomp,
+                &input[in_pos..],
+                &mut window,
+                out_pos,
+                flags,
+                &mut 0,
+                &mut 0,
+                |_v| {},
+            );
+            in_pos += in_consumed;
+            writer
+                .write_all(&window[out_pos..out_pos + out_consumed])
+                .await?;
+            total += out_consumed as u64;
+            out_pos = (out_pos + out_consumed) & (TINFL_LZ_DICT_SIZE - 1);
+
+            match status {
+                TINFLStatus::Done => {
+                    writer.flush().await?;
+                    return Ok(total);
                 }
+                TINFLStatus::HasMoreOutput => {}
+                status => return Err(Error::decompress(&decomp, status)),
             }
         }
     }
 }
+
+/// Decompress everything read from `input` to `writer`, e.g. to decompress one file to
+/// another in one call.
+///
+/// Only a 32 KiB input buffer and the 32 KiB window are used, whatever the size of the data.
+/// Use [`copy_decode_with`] for other settings. Input after the end of the compressed stream
+/// may have been read, but is ignored.
+///
+/// Returns the number of decompressed bytes written.
+#[cfg(feature = "std")]
+pub fn copy_decode<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    data_format: DataFormat,
+) -> impl Future<Output = Result<u64, Error>> + Send + 'a {
+    let options = DecodeOptions {
+        format: data_format,
+        ..DecodeOptions::default()
+    };
+    copy_decode_with(input, writer, options)
+}
+
+/// Decompress everything read from `input` to `writer`, with the settings of `options`, like
+/// [`decompress_stream_with`].
+///
+/// Returns the number of decompressed bytes written.
+#[cfg(feature = "std")]
+pub fn copy_decode_with<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: DecodeOptions,
+) -> impl Future<Output = Result<u64, Error>> + Send + 'a {
+    async move {
+        let summary = decompress_stream_with(input, writer, options).await?;
+        Ok(summary.bytes_written)
+    }
+}
+
 /// Try to decompress from `input` to `output` with the given [`InflateState`]
 ///
 /// # `flush`
@@ -229,130 +858,115 @@ pub fn decompress_stream<'a, R: Read + Send + 'a, W: Write + Seek + Send>(
 /// Returns [`MZError::Stream`] when called with [`MZFlush::Full`] (meaningless on
 /// decompression), or when called without [`MZFlush::Finish`] after an earlier call with
 /// [`MZFlush::Finish`] has been made.
-pub fn inflate<'a, W: Write + Seek + Send>(
-    state: &'a mut InflateState,
+#[cfg(feature = "std")]
+pub fn inflate<'a, D: WindowBuffer + Send, W: Write + Seek + Send>(
+    state: &'a mut InflateState<D>,
     input: &'a [u8],
     writer: &'a mut W,
     flush: MZFlush,
 ) -> impl Future<Output = Result<StreamResult, Error>> + Send + 'a {
     async move {
-        let mut bytes_consumed = 0;
-        let mut bytes_written = 0;
-        let mut next_in = input;
-
-        if flush == MZFlush::Full {
-            return Ok(StreamResult::error(MZError::Stream));
-        }
-
-        let mut decomp_flags = if state.data_format == DataFormat::Zlib {
-            inflate_flags::TINFL_FLAG_COMPUTE_ADLER32
-        } else {
-            inflate_flags::TINFL_FLAG_IGNORE_ADLER32
-        };
-
-        if (state.data_format == DataFormat::Zlib)
-            | (state.data_format == DataFormat::ZLibIgnoreChecksum)
-        {
-            decomp_flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
-        }
-
-        let first_call = state.first_call;
-        state.first_call = false;
-        if state.last_status == TINFLStatus::FailedCannotMakeProgress {
-            return Ok(StreamResult::error(MZError::Buf));
-        }
-        if (state.last_status as i32) < 0 {
-            return Ok(StreamResult::error(MZError::Data));
-        }
-
-        if state.has_flushed && (flush != MZFlush::Finish) {
-            return Ok(StreamResult::error(MZError::Stream));
-        }
-        state.has_flushed |= flush == MZFlush::Finish;
-
-        if (flush == MZFlush::Finish) && first_call {
-            decomp_flags |= inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let res = inflate_inner(state, input, writer, flush).await?;
+        state.total_in += res.bytes_consumed as u64;
+        state.total_out += res.bytes_written as u64;
+        state.record_metrics(&res, flush, res.bytes_consumed == input.len());
+        Ok(res)
+    }
+}
 
-            // The caller is indicating that they want to finish the compression and this is the first call with the current stream
-            // so we can simply write directly to the output buffer.
-            // If there is not enough space for all of the decompressed data we will end up with a failure regardless.
-            let status = decompress(
-                &mut state.decomp,
-                next_in,
-                &mut state.dict,
-                0,
-                decomp_flags,
-                &mut 0,
-                &mut 0,
-                |_v| {},
-            );
-            let in_bytes = status.1;
-            let out_bytes = status.2;
-            let status = status.0;
+#[cfg(feature = "std")]
+async fn inflate_inner<D: WindowBuffer + Send, W: Write + Seek + Send>(
+    state: &mut InflateState<D>,
+    input: &[u8],
+    writer: &mut W,
+    flush: MZFlush,
+) -> Result<StreamResult, Error> {
+    let mut bytes_consumed = 0;
+    let mut bytes_written = 0;
+    let mut next_in = input;
 
-            state.last_status = status;
+    let (decomp_flags, direct_output) = match begin_inflate(state, flush, true) {
+        Ok(res) => res,
+        Err(err) => return Ok(StreamResult::error(err)),
+    };
 
-            bytes_consumed += in_bytes;
-            bytes_written += out_bytes;
+    if direct_output {
+        // The caller is indicating that they want to finish the compression and this is the first call with the current stream
+        // so we can simply write directly to the output buffer.
+        // If there is not enough space for all of the decompressed data we will end up with a failure regardless.
+        let status = decompress(
+            &mut state.decomp,
+            next_in,
+            state.dict.as_mut(),
+            0,
+            decomp_flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        let in_bytes = status.1;
+        let out_bytes = status.2;
+        let status = status.0;
 
-            let ret_status = {
-                if status == TINFLStatus::FailedCannotMakeProgress {
-                    Err(MZError::Buf)
-                } else if (status as i32) < 0 {
-                    Err(MZError::Data)
-                } else if status != TINFLStatus::Done {
-                    state.last_status = TINFLStatus::Failed;
-                    Err(MZError::Buf)
-                } else {
-                    Ok(MZStatus::StreamEnd)
-                }
-            };
-            return Ok(StreamResult {
-                bytes_consumed,
-                bytes_written,
-                status: ret_status,
-            });
-        }
+        state.last_status = status;
 
-        if flush != MZFlush::Finish {
-            decomp_flags |= inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
-        }
+        bytes_consumed += in_bytes;
+        bytes_written += out_bytes;
 
-        if state.dict_avail != 0 {
-            bytes_written += push_dict_out(state, writer).await?;
-            return Ok(StreamResult {
-                bytes_consumed,
-                bytes_written,
-                status: Ok(
-                    if (state.last_status == TINFLStatus::Done) && (state.dict_avail == 0) {
-                        MZStatus::StreamEnd
-                    } else {
-                        MZStatus::Ok
-                    },
-                ),
-            });
-        }
+        let ret_status = {
+            if status == TINFLStatus::FailedCannotMakeProgress {
+                Err(MZError::Buf)
+            } else if (status as i32) < 0 {
+                Err(MZError::Data)
+            } else if status != TINFLStatus::Done {
+                state.last_status = TINFLStatus::Failed;
+                Err(MZError::Buf)
+            } else {
+                Ok(MZStatus::StreamEnd)
+            }
+        };
+        return Ok(StreamResult {
+            bytes_consumed,
+            bytes_written,
+            status: ret_status,
+        });
+    }
 
-        let status = inflate_loop(
-            state,
-            &mut next_in,
-            writer,
-            &mut bytes_consumed,
-            &mut bytes_written,
-            decomp_flags,
-            flush,
-        )
-        .await?;
-        Ok(StreamResult {
+    if state.dict_avail != 0 {
+        bytes_written += push_dict_out(state, writer).await?;
+        return Ok(StreamResult {
             bytes_consumed,
             bytes_written,
-            status,
-        })
+            status: Ok(
+                if (state.last_status == TINFLStatus::Done) && (state.dict_avail == 0) {
+                    MZStatus::StreamEnd
+                } else {
+                    MZStatus::Ok
+                },
+            ),
+        });
     }
+
+    let status = inflate_loop(
+        state,
+        &mut next_in,
+        writer,
+        &mut bytes_consumed,
+        &mut bytes_written,
+        decomp_flags,
+        flush,
+    )
+    .await?;
+    Ok(StreamResult {
+        bytes_consumed,
+        bytes_written,
+        status,
+    })
 }
 
-async fn inflate_loop<'a, W: Write + Seek + Send>(
-    state: &'a mut InflateState,
+#[cfg(feature = "std")]
+async fn inflate_loop<'a, D: WindowBuffer + Send, W: Write + Seek + Send>(
+    state: &'a mut InflateState<D>,
     next_in: &'a mut &[u8],
     next_out: &'a mut W,
     total_in: &'a mut usize,
@@ -365,7 +979,7 @@ async fn inflate_loop<'a, W: Write + Seek + Send>(
         let (status, in_consumed, out_consumed) = decompress(
             &mut state.decomp,
             next_in,
-            &mut state.dict,
+            state.dict.as_mut(),
             state.dict_ofs,
             decomp_flags,
             &mut 0,
@@ -391,6 +1005,12 @@ async fn inflate_loop<'a, W: Write + Seek + Send>(
             return Ok(Err(MZError::Data));
         }
 
+        // A block was completed and all of its output has been written.
+        #[cfg(feature = "block-boundary")]
+        if status == TINFLStatus::BlockBoundary {
+            return Ok(Ok(MZStatus::Ok));
+        }
+
         // The decompressor has flushed all it's data and is waiting for more input, but
         // there was no more input provided.
         if (status == TINFLStatus::NeedsMoreInput) && orig_in_len == 0 {
@@ -426,21 +1046,1056 @@ async fn inflate_loop<'a, W: Write + Seek + Send>(
     }
 }
 
-async fn push_dict_out<W: Write + Send>(
-    state: &mut InflateState,
+#[cfg(feature = "std")]
+async fn push_dict_out<D: WindowBuffer, W: Write + Send>(
+    state: &mut InflateState<D>,
     next_out: &mut W,
 ) -> Result<usize, Error> {
     let data_size = state.dict_avail;
-    let data = &state.dict[state.dict_ofs..state.dict_ofs + data_size];
+    let dict = state.dict.as_ref();
+    let data = &dict[state.dict_ofs..state.dict_ofs + data_size];
     let writeten = next_out.write(data).await?;
     state.dict_avail -= data_size;
-    state.dict_ofs = (state.dict_ofs + (data_size)) & (TINFL_LZ_DICT_SIZE - 1);
+    state.dict_ofs = (state.dict_ofs + (data_size)) & (dict.len() - 1);
     if data_size != writeten {
-        return Err(Error::Msg("write size mismatch".to_string()));
+        return Err(Error::Io(std::io::ErrorKind::WriteZero.into()));
     }
     Ok(writeten)
 }
 
+/// Check that another call to `inflate` with `flush` is allowed, and return the flags for
+/// `decompress`, and whether the whole stream is decompressed straight to the output, which
+/// is only done if `allow_direct` is set.
+fn begin_inflate<D: WindowBuffer>(
+    state: &mut InflateState<D>,
+    flush: MZFlush,
+    allow_direct: bool,
+) -> Result<(u32, bool), MZError> {
+    if flush == MZFlush::Full {
+        return Err(MZError::Stream);
+    }
+
+    let mut decomp_flags = if state.data_format == DataFormat::Zlib {
+        inflate_flags::TINFL_FLAG_COMPUTE_ADLER32
+    } else {
+        inflate_flags::TINFL_FLAG_IGNORE_ADLER32
+    };
+
+    if (state.data_format == DataFormat::Zlib)
+        | (state.data_format == DataFormat::ZLibIgnoreChecksum)
+    {
+        decomp_flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+    }
+
+    #[cfg(feature = "block-boundary")]
+    if state.stop_on_block_boundary {
+        decomp_flags |= inflate_flags::TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY;
+    }
+
+    let first_call = state.first_call;
+    state.first_call = false;
+    if state.last_status == TINFLStatus::FailedCannotMakeProgress {
+        return Err(MZError::Buf);
+    }
+    if (state.last_status as i32) < 0 {
+        return Err(MZError::Data);
+    }
+
+    if state.has_flushed && (flush != MZFlush::Finish) {
+        return Err(MZError::Stream);
+    }
+    state.has_flushed |= flush == MZFlush::Finish;
+
+    let direct_output = (flush == MZFlush::Finish) && first_call && allow_direct;
+    #[cfg(feature = "block-boundary")]
+    let direct_output = direct_output && !state.stop_on_block_boundary;
+
+    if direct_output {
+        decomp_flags |= inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    } else if flush != MZFlush::Finish {
+        decomp_flags |= inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+    }
+    Ok((decomp_flags, direct_output))
+}
+
+/// Try to decompress from `input` to the `output` slice with the given [`InflateState`].
+///
+/// This is the counterpart of [`inflate`] that doesn't need `std`, and never allocates: all
+/// the memory used is in `state`, `input` and `output`. Together with
+/// [`InflateState::with_window()`] the window can be put in a caller-provided buffer, e.g. a
+/// `static` one, to decompress with neither `std` nor `alloc`.
+///
+/// Call it again with the rest of the input, i.e. after the first
+/// [`bytes_consumed`](StreamResult::bytes_consumed) bytes, and more output space, until it
+/// returns [`MZStatus::StreamEnd`]. [`MZError::Buf`] only means that no progress could be
+/// made, so more input or output space is needed, unless it was called with
+/// [`MZFlush::Finish`].
+///
+/// `flush` and the errors are the same as for [`inflate`]. With [`MZFlush::Finish`] on the
+/// first call, the data is decompressed straight to `output`, which then has to be large
+/// enough for all of it.
+///
+/// ```
+/// use miniz_oxide::inflate::stream::{InflateState, inflate_to_slice};
+/// use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+///
+/// let compressed = miniz_oxide::deflate::compress_to_vec(&[7; 10_000], 6);
+/// let mut window = [0; 1 << 15];
+/// let mut state = InflateState::with_window(&mut window[..], DataFormat::Raw).unwrap();
+///
+/// let mut input = &compressed[..];
+/// let mut output = [0; 1000];
+/// let mut total = 0;
+/// loop {
+///     let res = inflate_to_slice(&mut state, input, &mut output, MZFlush::None);
+///     input = &input[res.bytes_consumed..];
+///     assert!(output[..res.bytes_written].iter().all(|&b| b == 7));
+///     total += res.bytes_written;
+///     if res.status == Ok(MZStatus::StreamEnd) {
+///         break;
+///     }
+/// }
+/// assert_eq!(total, 10_000);
+/// ```
+pub fn inflate_to_slice<D: WindowBuffer>(
+    state: &mut InflateState<D>,
+    input: &[u8],
+    output: &mut [u8],
+    flush: MZFlush,
+) -> StreamResult {
+    let res = inflate_to_slice_inner(state, input, output, flush);
+    state.total_in += res.bytes_consumed as u64;
+    state.total_out += res.bytes_written as u64;
+    #[cfg(feature = "std")]
+    state.record_metrics(&res, flush, res.bytes_consumed == input.len());
+    res
+}
+
+fn inflate_to_slice_inner<D: WindowBuffer>(
+    state: &mut InflateState<D>,
+    input: &[u8],
+    output: &mut [u8],
+    flush: MZFlush,
+) -> StreamResult {
+    let (decomp_flags, direct_output) = match begin_inflate(state, flush, true) {
+        Ok(res) => res,
+        Err(err) => return StreamResult::error(err),
+    };
+
+    if direct_output {
+        let (status, in_bytes, out_bytes) = decompress(
+            &mut state.decomp,
+            input,
+            output,
+            0,
+            decomp_flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        state.last_status = status;
+
+        let status = if status == TINFLStatus::FailedCannotMakeProgress {
+            Err(MZError::Buf)
+        } else if (status as i32) < 0 {
+            Err(MZError::Data)
+        } else if status != TINFLStatus::Done {
+            // The output slice was too small to hold all of the data.
+            state.last_status = TINFLStatus::Failed;
+            Err(MZError::Buf)
+        } else {
+            Ok(MZStatus::StreamEnd)
+        };
+        return StreamResult {
+            bytes_consumed: in_bytes,
+            bytes_written: out_bytes,
+            status,
+        };
+    }
+
+    let mut next_out = output;
+    inflate_from_window(state, input, &mut next_out, decomp_flags, flush)
+}
+
+/// Where [`inflate_from_window`] copies the data decompressed into the window.
+trait WindowOutput {
+    /// The number of bytes that can still be copied.
+    fn space(&self) -> usize;
+    /// Copy `data`, which is no longer than [`space`](Self::space).
+    fn put(&mut self, data: &[u8]);
+}
+
+impl WindowOutput for &mut [u8] {
+    fn space(&self) -> usize {
+        self.len()
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        self[..data.len()].copy_from_slice(data);
+        *self = &mut ::core::mem::take(self)[data.len()..];
+    }
+}
+
+/// A [`BufMut`] as a [`WindowOutput`].
+#[cfg(feature = "bytes")]
+struct BufOutput<'a, M: BufMut>(&'a mut M);
+
+#[cfg(feature = "bytes")]
+impl<M: BufMut> WindowOutput for BufOutput<'_, M> {
+    fn space(&self) -> usize {
+        self.0.remaining_mut()
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        self.0.put_slice(data);
+    }
+}
+
+/// Try to decompress from `input` to `output` with the given [`InflateState`], advancing both
+/// past what was consumed and written.
+///
+/// `input` can be made of several chunks, e.g. a chain of `Bytes`, which are decompressed one
+/// after the other without copying them together first, and the data is copied from the
+/// window straight into `output`, which grows as needed if it is a `BytesMut` or `Vec<u8>`.
+///
+/// This is otherwise the same as [`inflate_to_slice`], except that the data is never
+/// decompressed straight to `output`, so with [`MZFlush::Finish`] on the first call `output`
+/// doesn't have to be large enough for all of it.
+///
+/// ```
+/// use bytes::{Buf, Bytes, BytesMut};
+/// use miniz_oxide::inflate::stream::{InflateState, inflate_buf};
+/// use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+///
+/// let compressed = miniz_oxide::deflate::compress_to_vec(&[7; 10_000], 6);
+/// let (head, tail) = compressed.split_at(compressed.len() / 2);
+/// let mut input = Bytes::copy_from_slice(head).chain(Bytes::copy_from_slice(tail));
+/// let mut output = BytesMut::new();
+///
+/// let mut state = InflateState::new_boxed(DataFormat::Raw);
+/// let res = inflate_buf(&mut state, &mut input, &mut output, MZFlush::Finish);
+/// assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+/// assert_eq!(output, vec![7; 10_000]);
+/// ```
+#[cfg(feature = "bytes")]
+pub fn inflate_buf<D: WindowBuffer, B: Buf, M: BufMut>(
+    state: &mut InflateState<D>,
+    input: &mut B,
+    output: &mut M,
+    flush: MZFlush,
+) -> StreamResult {
+    let mut res = StreamResult {
+        bytes_consumed: 0,
+        bytes_written: 0,
+        status: Ok(MZStatus::Ok),
+    };
+    loop {
+        let chunk = input.chunk();
+        // Only the last chunk is decompressed with `flush`, the others are followed by more.
+        let last = chunk.len() == input.remaining();
+        let chunk_flush = if last { flush } else { MZFlush::None };
+        let step = match begin_inflate(state, chunk_flush, false) {
+            Ok((decomp_flags, _)) => inflate_from_window(
+                state,
+                chunk,
+                &mut BufOutput(output),
+                decomp_flags,
+                chunk_flush,
+            ),
+            Err(err) => StreamResult::error(err),
+        };
+        input.advance(step.bytes_consumed);
+        state.total_in += step.bytes_consumed as u64;
+        state.total_out += step.bytes_written as u64;
+        res.bytes_consumed += step.bytes_consumed;
+        res.bytes_written += step.bytes_written;
+        res.status = step.status;
+
+        // Carry on with the next chunk while there is room for the output.
+        let stop = step.status != Ok(MZStatus::Ok)
+            || (step.bytes_consumed == 0 && step.bytes_written == 0)
+            || !input.has_remaining()
+            || !output.has_remaining_mut();
+        #[cfg(feature = "block-boundary")]
+        let stop = stop || state.last_status == TINFLStatus::BlockBoundary;
+        if stop {
+            #[cfg(feature = "std")]
+            state.record_metrics(&res, flush, !input.has_remaining());
+            return res;
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl WindowOutput for Segments {
+    fn space(&self) -> usize {
+        Segments::space(self)
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        Segments::put(self, data);
+    }
+}
+
+/// Decompress the buffers in `input` one after the other, as if they were one, and write the
+/// decompressed data to `writer` with [`WriteVectored::write_vectored`].
+///
+/// The data is copied from the window into 16 KiB buffers, which are written out together
+/// whenever 256 KiB have been collected and at the end, rather than with a write for every
+/// piece that comes out of the window.
+///
+/// `flush` applies to the last buffer, and the status and errors are the same as for
+/// [`inflate_to_slice`], except that the data is never decompressed straight to the output.
+/// As all the output is written out, decompression only stops before the end of `input` at
+/// the end of the stream, at a block boundary if it was asked to stop there, or on an error.
+#[cfg(feature = "std")]
+pub fn inflate_vectored<'a, D: WindowBuffer + Send, W: WriteVectored>(
+    state: &'a mut InflateState<D>,
+    input: &'a [IoSlice<'a>],
+    writer: &'a mut W,
+    flush: MZFlush,
+) -> impl Future<Output = Result<StreamResult, Error>> + Send + 'a {
+    async move {
+        let mut segments = Segments::new();
+        let mut res = StreamResult {
+            bytes_consumed: 0,
+            bytes_written: 0,
+            status: Ok(MZStatus::Ok),
+        };
+        'buffers: for i in 0..input.len().max(1) {
+            let mut next_in: &[u8] = input.get(i).map_or(&[], |buf| buf);
+            let last = i + 1 >= input.len();
+            if next_in.is_empty() && !last {
+                continue;
+            }
+            // Only the last buffer is decompressed with `flush`, the others are followed by
+            // more.
+            let buf_flush = if last { flush } else { MZFlush::None };
+            loop {
+                if segments.space() == 0 {
+                    segments.write_to(writer).await?;
+                }
+                let step = match begin_inflate(state, buf_flush, false) {
+                    Ok((decomp_flags, _)) => inflate_from_window(
+                        state,
+                        next_in,
+                        &mut segments,
+                        decomp_flags,
+                        buf_flush,
+                    ),
+                    Err(err) => StreamResult::error(err),
+                };
+                next_in = &next_in[step.bytes_consumed..];
+                state.total_in += step.bytes_consumed as u64;
+                state.total_out += step.bytes_written as u64;
+                res.bytes_consumed += step.bytes_consumed;
+                res.bytes_written += step.bytes_written;
+                res.status = step.status;
+
+                #[cfg(feature = "block-boundary")]
+                if state.last_status == TINFLStatus::BlockBoundary && state.dict_avail == 0 {
+                    break 'buffers;
+                }
+                match step.status {
+                    // Everything decompressed from this buffer has been collected.
+                    Ok(MZStatus::Ok) if next_in.is_empty() && state.dict_avail == 0 => break,
+                    Ok(MZStatus::Ok) => {}
+                    // With `MZFlush::Finish` this means the output is full, so write it out
+                    // and carry on.
+                    Err(MZError::Buf) if segments.space() == 0 => {}
+                    _ => break 'buffers,
+                }
+            }
+        }
+        segments.write_to(writer).await?;
+        let all_in = res.bytes_consumed == input.iter().map(|buf| buf.len()).sum::<usize>();
+        state.record_metrics(&res, flush, all_in);
+        Ok(res)
+    }
+}
+
+/// Decompress from `input` through the window of `state` to `output`.
+fn inflate_from_window<D: WindowBuffer, O: WindowOutput>(
+    state: &mut InflateState<D>,
+    input: &[u8],
+    next_out: &mut O,
+    decomp_flags: u32,
+    flush: MZFlush,
+) -> StreamResult {
+    let mut bytes_consumed = 0;
+    let mut bytes_written = 0;
+    let mut next_in = input;
+
+    if state.dict_avail != 0 {
+        bytes_written += push_dict_to_output(state, next_out);
+        return StreamResult {
+            bytes_consumed,
+            bytes_written,
+            status: Ok(
+                if (state.last_status == TINFLStatus::Done) && (state.dict_avail == 0) {
+                    MZStatus::StreamEnd
+                } else {
+                    MZStatus::Ok
+                },
+            ),
+        };
+    }
+
+    let orig_in_len = next_in.len();
+    let status = loop {
+        let (status, in_consumed, out_consumed) = decompress(
+            &mut state.decomp,
+            next_in,
+            state.dict.as_mut(),
+            state.dict_ofs,
+            decomp_flags,
+            &mut 0,
+            &mut 0,
+            |_v| {},
+        );
+        state.last_status = status;
+
+        next_in = &next_in[in_consumed..];
+        bytes_consumed += in_consumed;
+
+        state.dict_avail = out_consumed;
+        bytes_written += push_dict_to_output(state, next_out);
+
+        // Finish was requested but we didn't end on an end block.
+        if status == TINFLStatus::FailedCannotMakeProgress {
+            break Err(MZError::Buf);
+        }
+        // The stream was corrupted, and decompression failed.
+        else if (status as i32) < 0 {
+            break Err(MZError::Data);
+        }
+
+        // A block was completed; what didn't fit in the output is copied on the next call.
+        #[cfg(feature = "block-boundary")]
+        if status == TINFLStatus::BlockBoundary {
+            break Ok(MZStatus::Ok);
+        }
+
+        // The decompressor has flushed all it's data and is waiting for more input, but
+        // there was no more input provided.
+        if (status == TINFLStatus::NeedsMoreInput) && orig_in_len == 0 {
+            break Err(MZError::Buf);
+        }
+
+        if flush == MZFlush::Finish {
+            if status == TINFLStatus::Done {
+                // There is not enough space in the output buffer to flush the remaining
+                // decompressed data in the internal buffer.
+                break if state.dict_avail != 0 {
+                    Err(MZError::Buf)
+                } else {
+                    Ok(MZStatus::StreamEnd)
+                };
+            // No more space in the output buffer, but we're not done.
+            } else if next_out.space() == 0 {
+                break Err(MZError::Buf);
+            }
+        } else {
+            // We're not expected to finish, so it's fine if we can't flush everything yet.
+            let empty_buf = next_in.is_empty() || next_out.space() == 0;
+            if (status == TINFLStatus::Done) || empty_buf || (state.dict_avail != 0) {
+                break if (status == TINFLStatus::Done) && (state.dict_avail == 0) {
+                    // No more data left, we're done.
+                    Ok(MZStatus::StreamEnd)
+                } else {
+                    // Ok for now, still waiting for more input data or output space.
+                    Ok(MZStatus::Ok)
+                };
+            }
+        }
+    };
+    StreamResult {
+        bytes_consumed,
+        bytes_written,
+        status,
+    }
+}
+
+/// Copy as much of the data decompressed into the window as fits into `next_out`.
+fn push_dict_to_output<D: WindowBuffer, O: WindowOutput>(
+    state: &mut InflateState<D>,
+    next_out: &mut O,
+) -> usize {
+    let dict = state.dict.as_ref();
+    let n = state.dict_avail.min(next_out.space());
+    next_out.put(&dict[state.dict_ofs..state.dict_ofs + n]);
+    state.dict_avail -= n;
+    state.dict_ofs = (state.dict_ofs + n) & (dict.len() - 1);
+    n
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{InflateState, copy_decode, decompress_to_writer, inflate, inflate_to_slice};
+    use crate::deflate::{compress_to_vec, compress_to_vec_zlib};
+    use crate::error::Error;
+    use crate::inflate::TINFLStatus;
+    use crate::{DataFormat, MZError, MZFlush, MZStatus, StreamTotals};
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn to_writer() {
+        let data = crate::test_util::text(200_000);
+        for (format, encoded) in [
+            (DataFormat::Raw, compress_to_vec(&data, 6)),
+            (DataFormat::Zlib, compress_to_vec_zlib(&data, 1)),
+        ] {
+            let mut out = Cursor::new(Vec::new());
+            let written = decompress_to_writer(&encoded, &mut out, format)
+                .await
+                .unwrap();
+            assert_eq!(written, data.len() as u64);
+            assert!(out.into_inner() == data);
+        }
+
+        let mut encoded = compress_to_vec_zlib(&data, 6);
+        let len = encoded.len();
+        encoded[len - 1] ^= 1;
+        let mut out = Cursor::new(Vec::new());
+        let err = decompress_to_writer(&encoded, &mut out, DataFormat::Zlib)
+            .await
+            .unwrap_err();
+        let Error::Adler32Mismatch(mismatch) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        let computed = crate::shared::update_adler32(1, &data);
+        assert_eq!(mismatch.expected, computed ^ 1);
+        assert_eq!(mismatch.computed, computed);
+        assert_eq!(mismatch.bytes_decoded, data.len() as u64);
+        assert!(out.into_inner() == data);
+        let mut out = Cursor::new(Vec::new());
+        decompress_to_writer(&encoded, &mut out, DataFormat::ZLibIgnoreChecksum)
+            .await
+            .unwrap();
+        assert!(out.into_inner() == data);
+    }
+
+    #[tokio::test]
+    async fn copy_round_trip() {
+        use crate::deflate::CompressionLevel;
+        use crate::deflate::stream::copy_encode;
+
+        let data = crate::test_util::text(300_000);
+        for format in [DataFormat::Raw, DataFormat::Zlib] {
+            let mut compressed = Cursor::new(Vec::new());
+            let written = copy_encode(
+                &mut Cursor::new(&data),
+                &mut compressed,
+                CompressionLevel::DefaultLevel,
+                format,
+            )
+            .await
+            .unwrap();
+            let compressed = compressed.into_inner();
+            assert_eq!(written, compressed.len() as u64);
+            assert!(compressed.len() < data.len());
+
+            let mut out = Cursor::new(Vec::new());
+            let written = copy_decode(&mut Cursor::new(&compressed), &mut out, format)
+                .await
+                .unwrap();
+            assert_eq!(written, data.len() as u64);
+            assert!(out.into_inner() == data);
+
+            // A truncated stream is an error rather than a short output.
+            let truncated = &compressed[..compressed.len() / 2];
+            let mut out = Cursor::new(Vec::new());
+            let err = copy_decode(&mut Cursor::new(truncated), &mut out, format)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Decompress(TINFLStatus::FailedCannotMakeProgress)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_chunk_sizes() {
+        use super::{DecodeOptions, copy_decode_with, decompress_stream_with};
+        use crate::deflate::stream::{EncodeOptions, copy_encode_with};
+
+        let data = crate::test_util::text(100_000);
+        let expected = compress_to_vec_zlib(&data, 6);
+        for chunk_size in [0, 1, 1000, 1 << 20] {
+            let options = EncodeOptions {
+                format: DataFormat::Zlib,
+                chunk_size,
+                ..EncodeOptions::default()
+            };
+            let mut compressed = Cursor::new(Vec::new());
+            copy_encode_with(&mut Cursor::new(&data), &mut compressed, options)
+                .await
+                .unwrap();
+            // The buffer sizes don't change the output.
+            let compressed = compressed.into_inner();
+            assert_eq!(compressed, expected);
+
+            let options = DecodeOptions {
+                format: DataFormat::Zlib,
+                chunk_size,
+                ..DecodeOptions::default()
+            };
+            let mut out = Cursor::new(Vec::new());
+            copy_decode_with(&mut Cursor::new(&compressed), &mut out, options)
+                .await
+                .unwrap();
+            assert!(out.into_inner() == data);
+            let mut out = Cursor::new(Vec::new());
+            let summary = decompress_stream_with(&mut Cursor::new(&compressed), &mut out, options)
+                .await
+                .unwrap();
+            assert!(out.into_inner() == data);
+            assert_eq!(summary.bytes_read, compressed.len() as u64);
+            assert_eq!(summary.bytes_written, data.len() as u64);
+            assert_eq!(
+                summary.chunks,
+                compressed.len().div_ceil(chunk_size.max(1)) as u64
+            );
+            let adler32 = crate::shared::update_adler32(1, &data);
+            assert_eq!(summary.checksum, Some(adler32));
+        }
+
+        // Raw deflate data has no checksum.
+        let compressed = compress_to_vec(&data, 6);
+        let mut out = Cursor::new(Vec::new());
+        let summary = super::decompress_stream(&mut Cursor::new(&compressed), &mut out)
+            .await
+            .unwrap();
+        assert_eq!(summary.bytes_written, data.len() as u64);
+        assert_eq!(summary.checksum, None);
+    }
+
+    #[tokio::test]
+    async fn decode_options() {
+        use super::{DecodeOptions, decompress_stream_with};
+        use crate::gzip::{GzHeader, GzipError, compress_to_vec_gzip_with_header};
+
+        let data = crate::test_util::text(100_000);
+        let decode = |compressed: Vec<u8>, options: DecodeOptions| async move {
+            let mut out = Cursor::new(Vec::new());
+            let res = decompress_stream_with(&mut Cursor::new(compressed), &mut out, options).await;
+            (res, out.into_inner())
+        };
+
+        // A header longer than the input buffer.
+        let header = GzHeader {
+            filename: Some(vec![b'f'; 5000]),
+            header_crc: true,
+            ..GzHeader::default()
+        };
+        let gzip = compress_to_vec_gzip_with_header(&data, 6, &header);
+        let options = DecodeOptions {
+            gzip: true,
+            chunk_size: 1000,
+            ..DecodeOptions::default()
+        };
+        let (res, out) = decode(gzip.clone(), options).await;
+        let summary = res.unwrap();
+        assert!(out == data);
+        assert_eq!(summary.bytes_read, gzip.len() as u64);
+        assert_eq!(
+            summary.checksum,
+            Some(crate::shared::update_crc32(0, &data))
+        );
+        // With a small buffer, much of what was read with the header is data.
+        let small = DecodeOptions {
+            chunk_size: 7,
+            ..options
+        };
+        let (res, out) = decode(gzip.clone(), small).await;
+        assert_eq!(res.unwrap().chunks, gzip.len().div_ceil(7) as u64);
+        assert!(out == data);
+        // The header isn't read past room for the longest extra field and 64 KiB of file name
+        // and comment.
+        let header = GzHeader {
+            filename: Some(vec![b'f'; 100_000]),
+            comment: Some(vec![b'c'; 40_000]),
+            ..GzHeader::default()
+        };
+        let long = compress_to_vec_gzip_with_header(&data, 6, &header);
+        let (res, _) = decode(long, small).await;
+        assert!(matches!(res, Err(Error::Gzip(GzipError::HeaderTooLong))));
+        let header = GzHeader {
+            extra: Some(vec![b'e'; u16::MAX.into()]),
+            filename: Some(vec![b'f'; 40_000]),
+            comment: Some(vec![b'c'; 20_000]),
+            header_crc: true,
+            ..GzHeader::default()
+        };
+        let long = compress_to_vec_gzip_with_header(&data, 6, &header);
+        let (res, out) = decode(long, small).await;
+        res.unwrap();
+        assert!(out == data);
+
+        let mut corrupt = gzip.clone();
+        let len = corrupt.len();
+        corrupt[len - 8] ^= 1;
+        let (res, _) = decode(corrupt.clone(), options).await;
+        assert!(matches!(
+            res,
+            Err(Error::Gzip(GzipError::CrcMismatch { .. }))
+        ));
+        let unchecked = DecodeOptions {
+            verify_checksum: false,
+            ..options
+        };
+        let (res, out) = decode(corrupt, unchecked).await;
+        assert_eq!(res.unwrap().checksum, None);
+        assert!(out == data);
+        let (res, _) = decode(gzip[..len - 3].to_vec(), options).await;
+        assert!(matches!(res, Err(Error::Gzip(GzipError::Truncated))));
+        let (res, _) = decode(compress_to_vec_zlib(&data, 6), options).await;
+        assert!(matches!(res, Err(Error::Gzip(GzipError::BadMagic(_)))));
+
+        let mut corrupt = compress_to_vec_zlib(&data, 6);
+        let len = corrupt.len();
+        corrupt[len - 1] ^= 1;
+        let zlib = DecodeOptions {
+            format: DataFormat::Zlib,
+            ..DecodeOptions::default()
+        };
+        let (res, _) = decode(corrupt.clone(), zlib).await;
+        assert!(matches!(res, Err(Error::Adler32Mismatch(_))));
+        let unchecked = DecodeOptions {
+            verify_checksum: false,
+            ..zlib
+        };
+        let (res, out) = decode(corrupt, unchecked).await;
+        assert_eq!(res.unwrap().checksum, None);
+        assert!(out == data);
+
+        // The output stops right at the limit.
+        for max_output_size in [0, 50_000, data.len() as u64 - 1] {
+            let options = DecodeOptions {
+                max_output_size,
+                ..zlib
+            };
+            let (res, out) = decode(compress_to_vec_zlib(&data, 6), options).await;
+            assert!(matches!(
+                res,
+                Err(Error::Decompress(TINFLStatus::HasMoreOutput))
+            ));
+            assert!(out == data[..max_output_size as usize]);
+        }
+        let options = DecodeOptions {
+            max_output_size: data.len() as u64,
+            ..zlib
+        };
+        let (res, out) = decode(compress_to_vec_zlib(&data, 6), options).await;
+        assert_eq!(res.unwrap().bytes_written, data.len() as u64);
+        assert!(out == data);
+    }
+
+    #[tokio::test]
+    async fn vectored_round_trip() {
+        use super::inflate_vectored;
+        use crate::WriteVectored;
+        use crate::deflate::core::CompressorOxide;
+        use crate::deflate::stream::deflate_vectored;
+        use binrw::io::write::Write;
+        use std::io::IoSlice;
+
+        /// Records the vectored writes, taking at most 3 buffers and 20 KiB at a time.
+        #[derive(Default)]
+        struct Gather {
+            data: Vec<u8>,
+            writes: usize,
+        }
+        impl Write for Gather {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> impl Future<Output = std::io::Result<usize>> + Send {
+                async move { self.write_vectored(&[IoSlice::new(buf)]).await }
+            }
+            fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+                async { Ok(()) }
+            }
+        }
+        impl WriteVectored for Gather {
+            fn write_vectored(
+                &mut self,
+                bufs: &[IoSlice<'_>],
+            ) -> impl Future<Output = std::io::Result<usize>> + Send {
+                async move {
+                    self.writes += 1;
+                    let start = self.data.len();
+                    for buf in bufs.iter().take(3) {
+                        let n = buf.len().min(start + 20_000 - self.data.len());
+                        self.data.extend_from_slice(&buf[..n]);
+                    }
+                    Ok(self.data.len() - start)
+                }
+            }
+        }
+
+        // Many small messages, each flushed, so the output comes in many small pieces.
+        let messages: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| format!("message {i}: {}", "x".repeat(i as usize % 50)).into_bytes())
+            .collect();
+        let mut compressor = CompressorOxide::default();
+        let mut compressed = Gather::default();
+        for batch in messages.chunks(100) {
+            let slices: Vec<_> = batch.iter().map(|m| IoSlice::new(m)).collect();
+            let res = deflate_vectored(&mut compressor, &slices, &mut compressed, MZFlush::Sync)
+                .await
+                .unwrap();
+            assert_eq!(res.status, Ok(MZStatus::Ok));
+            assert_eq!(res.bytes_consumed, batch.iter().map(Vec::len).sum::<usize>());
+        }
+        let res = deflate_vectored(&mut compressor, &[], &mut compressed, MZFlush::Finish)
+            .await
+            .unwrap();
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        // One write per batch, rather than one per message.
+        assert_eq!(compressed.writes, 21);
+
+        let data = messages.concat();
+        let encoded = compressed.data;
+        assert_eq!(crate::inflate::decompress_to_vec_zlib(&encoded).unwrap(), data);
+
+        let slices: Vec<_> = encoded.chunks(1000).map(IoSlice::new).collect();
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut out = Gather::default();
+        let res = inflate_vectored(&mut state, &slices, &mut out, MZFlush::Finish)
+            .await
+            .unwrap();
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        assert_eq!(res.bytes_consumed, encoded.len());
+        assert_eq!(res.bytes_written, data.len());
+        assert!(out.data == data);
+        assert_eq!(state.total_out(), data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn caller_provided_window() {
+        let data = crate::test_util::text(100_000);
+        let encoded = compress_to_vec_zlib(&data, 6);
+
+        let mut window = vec![0xAA; 32 * 1024];
+        let mut state = InflateState::with_window(&mut window[..], DataFormat::Zlib).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let mut totals = StreamTotals::default();
+        loop {
+            let input = &encoded[totals.bytes_consumed as usize..];
+            let res = inflate(&mut state, input, &mut out, MZFlush::None)
+                .await
+                .unwrap();
+            if totals.add(res).into_result().unwrap().is_stream_end() {
+                break;
+            }
+        }
+        assert_eq!(out.get_ref(), &data);
+        assert_eq!(totals.bytes_consumed, encoded.len() as u64);
+        assert_eq!(totals.bytes_written, data.len() as u64);
+
+        // Errors are turned into an `Err`.
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let res = inflate(&mut state, &[0xff, 0xff], &mut out, MZFlush::None)
+            .await
+            .unwrap();
+        assert!(!res.is_stream_end());
+        assert!(matches!(res.into_result(), Err(MZError::Data)));
+
+        assert!(matches!(
+            InflateState::with_window(vec![0; 1000], DataFormat::Raw),
+            Err(MZError::Param)
+        ));
+        assert!(matches!(
+            InflateState::with_window([0; 64 * 1024], DataFormat::Raw),
+            Err(MZError::Param)
+        ));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn wipe_window_on_drop() {
+        let data = b"secret token, secret token, secret token";
+        let encoded = compress_to_vec(data, 6);
+        let mut window = [0; 1024];
+        let mut state = InflateState::with_window(&mut window[..], DataFormat::Raw).unwrap();
+        let mut out = [0; 100];
+        let res = inflate_to_slice(&mut state, &encoded, &mut out, MZFlush::None);
+        assert_eq!(&out[..res.bytes_written], data);
+        drop(state);
+        assert!(window.iter().all(|&b| b == 0));
+
+        // Unless the window is taken back.
+        let mut state = InflateState::with_window(&mut window[..], DataFormat::Raw).unwrap();
+        inflate_to_slice(&mut state, &encoded, &mut out, MZFlush::None);
+        let window = state.into_window();
+        assert_eq!(&window[..data.len()], data);
+    }
+
+    #[test]
+    fn small_window() {
+        // A random block repeated, so the closest match is 3000 bytes back.
+        let block = crate::test_util::random(3000, 256);
+        let data = block.repeat(30);
+        let decode = |encoded: &[u8], window: &mut [u8]| -> Result<Vec<u8>, MZError> {
+            let mut state = InflateState::with_window(window, DataFormat::Raw).unwrap();
+            let mut out = Vec::new();
+            let mut buf = [0; 1000];
+            let mut consumed = 0;
+            loop {
+                let res = inflate_to_slice(&mut state, &encoded[consumed..], &mut buf, MZFlush::None);
+                out.extend_from_slice(&buf[..res.bytes_written]);
+                consumed += res.bytes_consumed;
+                if res.status? == MZStatus::StreamEnd {
+                    return Ok(out);
+                }
+            }
+        };
+
+        let mut window = [0; 8 * 1024];
+        let encoded = compress_to_vec(&data, 6);
+        assert!(decode(&encoded, &mut window).unwrap() == data);
+
+        // A match further back than the window is an error, not garbage output.
+        let mut far = block.clone();
+        far.extend_from_slice(&[0; 10_000]);
+        far.extend_from_slice(&block);
+        let encoded = compress_to_vec(&far, 6);
+        assert_eq!(decode(&encoded, &mut window), Err(MZError::Data));
+    }
+
+    #[test]
+    fn stats() {
+        use crate::deflate::compressed_size_with;
+        use crate::deflate::core::CompressorOxide;
+        use crate::deflate::stream::deflate;
+
+        let data = crate::test_util::text(300_000);
+        for level in [0, 1, 6] {
+            let mut compressor = CompressorOxide::default();
+            compressor.set_compression_level_raw(level);
+            compressor.set_collect_stats(true);
+            compressed_size_with(&mut compressor, &data);
+            let encoded_stats = compressor.stats().unwrap();
+
+            compressor.reset();
+            let mut encoded = vec![0; data.len() + 1000];
+            let res = deflate(&mut compressor, &data, &mut encoded, MZFlush::Finish);
+            encoded.truncate(res.bytes_written);
+
+            let mut state = InflateState::new_boxed(DataFormat::Zlib);
+            let mut out = vec![0; data.len()];
+            let res = inflate_to_slice(&mut state, &encoded, &mut out, MZFlush::None);
+            assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+            let stats = state.stats();
+            assert_eq!(stats.compressed_bytes, encoded.len() as u64);
+            assert_eq!(stats.decompressed_bytes, data.len() as u64);
+            let decoded = stats.decoded;
+            assert_eq!(decoded.stored_blocks, encoded_stats.stored_blocks);
+            assert_eq!(decoded.static_blocks, encoded_stats.static_blocks);
+            assert_eq!(decoded.dynamic_blocks, encoded_stats.dynamic_blocks);
+            assert_eq!(decoded.dynamic_trees, decoded.dynamic_blocks);
+            if level == 0 {
+                assert_eq!(decoded.max_match_distance, 0);
+            } else {
+                assert!((1000..=32768).contains(&decoded.max_match_distance));
+            }
+
+            state.reset(DataFormat::Zlib);
+            assert_eq!(state.stats(), Default::default());
+        }
+    }
+
+    #[test]
+    fn to_slice() {
+        let data = crate::test_util::text(100_000);
+        for (format, encoded) in [
+            (DataFormat::Raw, compress_to_vec(&data, 6)),
+            (DataFormat::Zlib, compress_to_vec_zlib(&data, 6)),
+        ] {
+            // Small pieces of input and output, so the window has to be carried over.
+            let mut window = [0; 32 * 1024];
+            let mut state = InflateState::with_window(&mut window[..], format).unwrap();
+            let mut out = Vec::new();
+            let mut buf = [0; 777];
+            let mut totals = StreamTotals::default();
+            loop {
+                let start = totals.bytes_consumed as usize;
+                let input = &encoded[start..encoded.len().min(start + 1000)];
+                let res = inflate_to_slice(&mut state, input, &mut buf, MZFlush::None);
+                out.extend_from_slice(&buf[..res.bytes_written]);
+                if totals.add(res).into_result().unwrap().is_stream_end() {
+                    break;
+                }
+            }
+            assert!(out == data);
+            assert_eq!(totals.bytes_consumed, encoded.len() as u64);
+            assert_eq!(state.total_out(), data.len() as u64);
+
+            // All at once, straight to the output.
+            let mut state = InflateState::new_boxed(format);
+            let mut out = vec![0; data.len()];
+            let res = inflate_to_slice(&mut state, &encoded, &mut out, MZFlush::Finish);
+            assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+            assert_eq!(res.bytes_written, data.len());
+            assert!(out == data);
+
+            // Too little output space to finish.
+            let mut state = InflateState::new_boxed(format);
+            let mut out = vec![0; data.len() - 1];
+            let res = inflate_to_slice(&mut state, &encoded, &mut out, MZFlush::Finish);
+            assert_eq!(res.status, Err(MZError::Buf));
+        }
+
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let res = inflate_to_slice(&mut state, &[0xff, 0xff], &mut [0; 10], MZFlush::None);
+        assert_eq!(res.status, Err(MZError::Data));
+    }
+
+    #[cfg(feature = "block-boundary")]
+    #[tokio::test]
+    async fn resume_from_checkpoint() {
+        use super::InflateCheckpoint;
+        use crate::inflate::TINFLStatus;
+
+        let data = crate::test_util::random(300_000, 8);
+        let encoded = compress_to_vec_zlib(&data, 6);
+
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        state.set_stop_on_block_boundary(true);
+        let mut out = Cursor::new(Vec::new());
+        let mut consumed = 0;
+        let mut checkpoints = 0;
+        loop {
+            let res = inflate(&mut state, &encoded[consumed..], &mut out, MZFlush::None)
+                .await
+                .unwrap();
+            consumed += res.bytes_consumed;
+            match res.status.expect("Failed to decompress!") {
+                MZStatus::StreamEnd => break,
+                _ if state.last_status() == TINFLStatus::BlockBoundary => {
+                    let checkpoint = InflateCheckpoint::capture(&state).unwrap();
+                    // Resume with the window supplied by the caller every other time.
+                    state = if checkpoints % 2 == 0 {
+                        InflateCheckpoint::resume(&checkpoint, None)
+                    } else {
+                        InflateCheckpoint::resume(&checkpoint, Some(out.get_ref()))
+                    };
+                    checkpoints += 1;
+                }
+                _ => {}
+            }
+        }
+
+        assert!(checkpoints > 1);
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(state.total_in(), encoded.len() as u64);
+        assert_eq!(state.total_out(), data.len() as u64);
+        assert_eq!(out.get_ref(), &data);
+    }
+}
+
 // #[cfg(all(test, feature = "with-alloc"))]
 // mod test {
 //     use super::{inflate, InflateState};
@@ -0,0 +1,203 @@
+//! One-shot functions with the semantics of zlib's `compress`, `compress2`, `compressBound`,
+//! `uncompress` and `uncompress2`, for porting C code that only uses zlib's simple API.
+//!
+//! As in zlib, `dest_len` gives the space available in `dest` on the way in (only the first
+//! `*dest_len` bytes of `dest` are used) and is set to the number of bytes produced on the way
+//! out, also when there is an error. The functions return zlib's status codes, which are the
+//! same as those of [`MZStatus::to_code`] and [`MZError::to_code`].
+//!
+//! ```
+//! use miniz_oxide::interop::{Z_OK, compress, compress_bound, uncompress};
+//!
+//! let data = b"Hello, hello, hello, zlib!";
+//! let mut compressed = vec![0; compress_bound(data.len())];
+//! let mut compressed_len = compressed.len();
+//! assert_eq!(compress(&mut compressed, &mut compressed_len, data), Z_OK);
+//!
+//! let mut out = [0; 26];
+//! let mut out_len = out.len();
+//! assert_eq!(uncompress(&mut out, &mut out_len, &compressed[..compressed_len]), Z_OK);
+//! assert_eq!(&out[..out_len], data);
+//! ```
+
+use crate::alloc::boxed::Box;
+
+use crate::deflate::core::{CompressorOxide, create_comp_flags_from_zip_params};
+use crate::deflate::stream::deflate;
+use crate::inflate::stream::{InflateState, inflate_to_slice};
+use crate::shared::MZ_DEFAULT_WINDOW_BITS;
+use crate::{DataFormat, MZError, MZFlush, MZStatus};
+
+/// Success.
+pub const Z_OK: i32 = MZStatus::Ok.to_code();
+/// The compressed data is invalid or truncated.
+pub const Z_DATA_ERROR: i32 = MZError::Data.to_code();
+/// The compression level is invalid.
+pub const Z_STREAM_ERROR: i32 = MZError::Stream.to_code();
+/// The output doesn't fit in `dest`.
+pub const Z_BUF_ERROR: i32 = MZError::Buf.to_code();
+
+/// The default compression level, level 6.
+pub const Z_DEFAULT_COMPRESSION: i32 = -1;
+
+/// Get an upper bound of the size of `source_len` bytes compressed with [`compress`] or
+/// [`compress2`], as zlib's `compressBound`.
+///
+/// This is miniz's bound, which is a little larger than zlib's.
+pub const fn compress_bound(source_len: usize) -> usize {
+    let stored = 128 + source_len + (source_len / (31 * 1024) + 1) * 5;
+    let compressed = 128 + source_len / 100 * 110 + source_len % 100 * 110 / 100;
+    if stored > compressed {
+        stored
+    } else {
+        compressed
+    }
+}
+
+/// Compress `source` into `dest` as zlib data at the default level.
+///
+/// Returns [`Z_OK`], or [`Z_BUF_ERROR`] if the compressed data doesn't fit in `*dest_len`
+/// bytes.
+pub fn compress(dest: &mut [u8], dest_len: &mut usize, source: &[u8]) -> i32 {
+    compress2(dest, dest_len, source, Z_DEFAULT_COMPRESSION)
+}
+
+/// Compress `source` into `dest` as zlib data at `level`, which is -1 for the default level
+/// or 0-9.
+///
+/// Returns [`Z_OK`], [`Z_BUF_ERROR`] if the compressed data doesn't fit in `*dest_len` bytes,
+/// or [`Z_STREAM_ERROR`] if the level is invalid.
+///
+/// # Panics
+///
+/// If `*dest_len` is larger than `dest`.
+pub fn compress2(dest: &mut [u8], dest_len: &mut usize, source: &[u8], level: i32) -> i32 {
+    let output = &mut dest[..*dest_len];
+    *dest_len = 0;
+    if !(Z_DEFAULT_COMPRESSION..=9).contains(&level) {
+        return Z_STREAM_ERROR;
+    }
+
+    let flags = create_comp_flags_from_zip_params(level, MZ_DEFAULT_WINDOW_BITS, 0);
+    let mut compressor = Box::new(CompressorOxide::new(flags));
+    let res = deflate(&mut compressor, source, output, MZFlush::Finish);
+    *dest_len = res.bytes_written;
+    match res.status {
+        Ok(MZStatus::StreamEnd) => Z_OK,
+        Ok(_) => Z_BUF_ERROR,
+        Err(err) => err.to_code(),
+    }
+}
+
+/// Decompress the zlib data in `source` into `dest`.
+///
+/// Returns [`Z_OK`], [`Z_BUF_ERROR`] if the decompressed data doesn't fit in `*dest_len`
+/// bytes, or [`Z_DATA_ERROR`] if the data is invalid or truncated.
+///
+/// # Panics
+///
+/// If `*dest_len` is larger than `dest`.
+pub fn uncompress(dest: &mut [u8], dest_len: &mut usize, source: &[u8]) -> i32 {
+    uncompress2(dest, dest_len, source, &mut source.len())
+}
+
+/// The same as [`uncompress`], but only the first `*source_len` bytes of `source` are used,
+/// and `*source_len` is set to the number of bytes consumed.
+///
+/// # Panics
+///
+/// If `*dest_len` is larger than `dest`, or `*source_len` is larger than `source`.
+pub fn uncompress2(
+    dest: &mut [u8],
+    dest_len: &mut usize,
+    source: &[u8],
+    source_len: &mut usize,
+) -> i32 {
+    let output = &mut dest[..*dest_len];
+    let input = &source[..*source_len];
+
+    let mut state = InflateState::new_boxed(DataFormat::Zlib);
+    let res = inflate_to_slice(&mut state, input, output, MZFlush::Finish);
+    *dest_len = res.bytes_written;
+    *source_len = res.bytes_consumed;
+    match res.status {
+        Ok(MZStatus::StreamEnd) => Z_OK,
+        // Running out of input with space left in the output means the data is truncated.
+        Err(MZError::Buf) if res.bytes_written < output.len() => Z_DATA_ERROR,
+        Ok(MZStatus::NeedDict) => Z_DATA_ERROR,
+        Ok(_) => Z_BUF_ERROR,
+        Err(err) => err.to_code(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::vec;
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::test_util::text(100_000);
+        for level in [-1, 0, 1, 9] {
+            let mut compressed = vec![0; compress_bound(data.len())];
+            let mut compressed_len = compressed.len();
+            assert_eq!(
+                compress2(&mut compressed, &mut compressed_len, &data, level),
+                Z_OK
+            );
+
+            // The source length is updated, and trailing data is left alone.
+            compressed.truncate(compressed_len);
+            compressed.extend_from_slice(b"trailer");
+            let mut out = vec![0; data.len() + 10];
+            let mut out_len = out.len();
+            let mut source_len = compressed.len();
+            let status = uncompress2(&mut out, &mut out_len, &compressed, &mut source_len);
+            assert_eq!(status, Z_OK);
+            assert_eq!(&out[..out_len], &data[..]);
+            assert_eq!(source_len, compressed_len);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = [7; 10_000];
+        let mut compressed = vec![0; compress_bound(data.len())];
+        let mut compressed_len = 10;
+        assert_eq!(
+            compress(&mut compressed, &mut compressed_len, &data),
+            Z_BUF_ERROR
+        );
+        assert_eq!(compressed_len, 10);
+        let mut compressed_len = compressed.len();
+        assert_eq!(
+            compress2(&mut compressed, &mut compressed_len, &data, 10),
+            Z_STREAM_ERROR
+        );
+        assert_eq!(compressed_len, 0);
+
+        let mut compressed_len = compressed.len();
+        assert_eq!(compress(&mut compressed, &mut compressed_len, &data), Z_OK);
+        let compressed = &compressed[..compressed_len];
+        let mut out = [0; 10_100];
+        let mut out_len = 100;
+        assert_eq!(uncompress(&mut out, &mut out_len, compressed), Z_BUF_ERROR);
+        assert_eq!(out_len, 100);
+        let mut out_len = out.len();
+        let truncated = &compressed[..compressed.len() - 2];
+        assert_eq!(uncompress(&mut out, &mut out_len, truncated), Z_DATA_ERROR);
+        let mut out_len = out.len();
+        assert_eq!(uncompress(&mut out, &mut out_len, b"garbage"), Z_DATA_ERROR);
+    }
+
+    #[test]
+    fn empty() {
+        let mut compressed = [0; 20];
+        let mut compressed_len = compressed.len();
+        assert_eq!(compress(&mut compressed, &mut compressed_len, &[]), Z_OK);
+        let mut out_len = 0;
+        let status = uncompress(&mut [], &mut out_len, &compressed[..compressed_len]);
+        assert_eq!(status, Z_OK);
+        assert_eq!(out_len, 0);
+    }
+}
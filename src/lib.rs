@@ -22,21 +22,75 @@ fn roundtrip(data: &[u8]) {
 # roundtrip(b"Test_data test data lalalal blabla");
 "##
 )]
-#![forbid(unsafe_code)]
-// #![cfg_attr(all(not(feature = "std"), not(feature = "serde")), no_std)]
+// Unsafe code is only allowed with `unsafe-perf` and in the C API and wasm exports, and `safe`
+// always takes precedence.
+#![cfg_attr(
+    any(
+        feature = "safe",
+        not(any(feature = "unsafe-perf", feature = "capi", feature = "wasm"))
+    ),
+    forbid(unsafe_code)
+)]
+#![cfg_attr(
+    all(
+        any(feature = "capi", feature = "wasm"),
+        not(feature = "unsafe-perf"),
+        not(feature = "safe")
+    ),
+    deny(unsafe_code)
+)]
+// The C API links `std` for the allocator and panic handler of the built C library.
+#![cfg_attr(
+    all(
+        not(feature = "std"),
+        not(feature = "serde"),
+        not(feature = "capi")
+    ),
+    no_std
+)]
 
 #[cfg(feature = "with-alloc")]
 extern crate alloc;
 
+#[cfg(all(feature = "capi", feature = "safe"))]
+compile_error!("the `capi` feature needs unsafe code and can't be used with `safe`");
+#[cfg(all(feature = "wasm", feature = "safe"))]
+compile_error!("the `wasm` feature needs unsafe code and can't be used with `safe`");
+
 #[cfg(feature = "with-alloc")]
+pub mod backend;
+#[cfg(feature = "capi")]
+#[allow(unsafe_code)]
+pub mod capi;
 pub mod deflate;
+#[cfg(feature = "embedded-io")]
+pub mod embedded;
+#[cfg(feature = "with-alloc")]
+pub mod gzip;
 pub mod inflate;
+#[cfg(feature = "with-alloc")]
+pub mod interop;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "offload")]
+pub mod offload;
 #[cfg(feature = "serde")]
 pub mod serde;
 mod shared;
+#[cfg(all(test, feature = "with-alloc"))]
+mod test_util;
+// The exports generated by wasm-bindgen are `#[no_mangle]`.
+#[cfg(feature = "wasm")]
+#[allow(unsafe_code)]
+pub mod wasm;
+#[cfg(feature = "std")]
 pub mod error;
+#[cfg(feature = "std")]
+pub mod zip;
 pub use crate::shared::update_adler32 as mz_adler32_oxide;
 pub use crate::shared::{MZ_ADLER32_INIT, MZ_DEFAULT_WINDOW_BITS};
+#[cfg(feature = "std")]
+pub use crate::shared::WriteVectored;
 
 /// A list of flush types.
 ///
@@ -85,6 +139,7 @@ impl MZFlush {
 /// [`deflate::stream::deflate()`] or [`inflate::stream::inflate()`].
 #[repr(i32)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum MZStatus {
     /// Operation succeeded.
     ///
@@ -108,7 +163,9 @@ pub enum MZStatus {
 /// These are emitted as the [`Err`] side of a [`MZResult`] in the [`StreamResult`] returned from
 /// [`deflate::stream::deflate()`] or [`inflate::stream::inflate()`].
 #[repr(i32)]
-#[derive(Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum MZError {
     /// Unused
     ErrNo = -1,
@@ -188,6 +245,118 @@ pub struct StreamResult {
     pub status: MZResult,
 }
 
+impl core::fmt::Display for MZError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            MZError::ErrNo => "System error",
+            MZError::Stream => "Stream error",
+            MZError::Data => "Invalid or corrupted data",
+            MZError::Mem => "Out of memory",
+            MZError::Buf => "No progress possible",
+            MZError::Version => "Incompatible version",
+            MZError::Param => "Invalid parameter",
+        })
+    }
+}
+
+impl core::error::Error for MZError {}
+
+impl MZStatus {
+    /// Get the numeric code of the status, for passing it through C or WASM bindings.
+    ///
+    /// The codes are stable and the same as zlib's `Z_OK`, `Z_STREAM_END` and `Z_NEED_DICT`.
+    pub const fn to_code(self) -> i32 {
+        self as i32
+    }
+
+    /// Get the status for a numeric code from [`to_code`][Self::to_code], or `None` if it isn't
+    /// one.
+    pub const fn from_code(code: i32) -> Option<MZStatus> {
+        match code {
+            0 => Some(MZStatus::Ok),
+            1 => Some(MZStatus::StreamEnd),
+            2 => Some(MZStatus::NeedDict),
+            _ => None,
+        }
+    }
+}
+
+impl MZError {
+    /// Get the numeric code of the error, for passing it through C or WASM bindings.
+    ///
+    /// The codes are stable and the same as zlib's, e.g. `Z_DATA_ERROR` for
+    /// [`Data`][Self::Data], except for [`Param`][Self::Param], which is miniz's `MZ_PARAM_ERROR`.
+    pub const fn to_code(self) -> i32 {
+        self as i32
+    }
+
+    /// Get the error for a numeric code from [`to_code`][Self::to_code], or `None` if it isn't
+    /// one.
+    pub const fn from_code(code: i32) -> Option<MZError> {
+        match code {
+            -1 => Some(MZError::ErrNo),
+            -2 => Some(MZError::Stream),
+            -3 => Some(MZError::Data),
+            -4 => Some(MZError::Mem),
+            -5 => Some(MZError::Buf),
+            -6 => Some(MZError::Version),
+            -10_000 => Some(MZError::Param),
+            _ => None,
+        }
+    }
+
+    /// Get the numeric code of a result of the streaming functions, the status code for `Ok`
+    /// and the error code for `Err`.
+    pub const fn result_to_code(result: MZResult) -> i32 {
+        match result {
+            Ok(status) => status.to_code(),
+            Err(err) => err.to_code(),
+        }
+    }
+
+    /// Get the result of the streaming functions for a numeric code from
+    /// [`result_to_code`][Self::result_to_code], or `None` if it isn't one.
+    pub const fn result_from_code(code: i32) -> Option<MZResult> {
+        if code >= 0 {
+            match MZStatus::from_code(code) {
+                Some(status) => Some(Ok(status)),
+                None => None,
+            }
+        } else {
+            match MZError::from_code(code) {
+                Some(err) => Some(Err(err)),
+                None => None,
+            }
+        }
+    }
+
+    /// Get the kind of IO error for this error: corrupt data is
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData), bad parameters
+    /// [`InvalidInput`](std::io::ErrorKind::InvalidInput), and no progress, which mostly means
+    /// truncated input, [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof).
+    #[cfg(feature = "std")]
+    pub fn io_error_kind(&self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+        match self {
+            MZError::Data => ErrorKind::InvalidData,
+            MZError::Buf => ErrorKind::UnexpectedEof,
+            MZError::Mem => ErrorKind::OutOfMemory,
+            MZError::Param => ErrorKind::InvalidInput,
+            MZError::Version => ErrorKind::Unsupported,
+            MZError::ErrNo | MZError::Stream => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<MZError> for std::io::Error {
+    /// Create an IO error of the [kind](MZError::io_error_kind) for the error, with the error as
+    /// its source.
+    fn from(err: MZError) -> Self {
+        std::io::Error::new(err.io_error_kind(), err)
+    }
+}
+
 impl StreamResult {
     #[inline]
     pub const fn error(error: MZError) -> StreamResult {
@@ -197,6 +366,87 @@ impl StreamResult {
             status: Err(error),
         }
     }
+
+    /// Returns `true` if the call reached the end of the stream.
+    #[inline]
+    pub const fn is_stream_end(&self) -> bool {
+        matches!(self.status, Ok(MZStatus::StreamEnd))
+    }
+
+    /// Turn a failed call into its error, so the result can be used with `?` while keeping the
+    /// byte counts of successful calls.
+    #[inline]
+    pub fn into_result(self) -> Result<StreamResult, MZError> {
+        match self.status {
+            Ok(_) => Ok(self),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The total number of bytes consumed and written over a number of calls to the inflate or
+/// deflate streaming functions.
+///
+/// ```
+/// use miniz_oxide::deflate::{core::CompressorOxide, stream::deflate};
+/// use miniz_oxide::{MZFlush, StreamTotals};
+///
+/// let data = b"Hello, streams! Hello, streams!";
+/// let mut compressor = Box::<CompressorOxide>::default();
+/// let mut out = [0; 8];
+/// let mut compressed = Vec::new();
+/// let mut totals = StreamTotals::default();
+/// loop {
+///     let input = &data[totals.bytes_consumed as usize..];
+///     let res = totals.add(deflate(&mut compressor, input, &mut out, MZFlush::Finish));
+///     compressed.extend_from_slice(&out[..res.bytes_written]);
+///     if res.into_result().unwrap().is_stream_end() {
+///         break;
+///     }
+/// }
+/// assert_eq!(totals.bytes_consumed, data.len() as u64);
+/// assert_eq!(totals.bytes_written, compressed.len() as u64);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StreamTotals {
+    /// The number of bytes consumed from the input slices.
+    pub bytes_consumed: u64,
+    /// The number of bytes written to the outputs.
+    pub bytes_written: u64,
+}
+
+impl StreamTotals {
+    /// Add the byte counts of `res` to the totals, and return it for checking the status.
+    #[inline]
+    pub fn add(&mut self, res: StreamResult) -> StreamResult {
+        *self += &res;
+        res
+    }
+}
+
+impl core::ops::AddAssign<&StreamResult> for StreamTotals {
+    #[inline]
+    fn add_assign(&mut self, res: &StreamResult) {
+        self.bytes_consumed += res.bytes_consumed as u64;
+        self.bytes_written += res.bytes_written as u64;
+    }
+}
+
+/// A summary of the data that went through one of the functions that stream everything from
+/// a reader to a writer, like
+/// [`compress_stream_callback`](crate::deflate::stream::compress_stream_callback) and
+/// [`decompress_stream`](crate::inflate::stream::decompress_stream).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StreamSummary {
+    /// The number of bytes read from the input.
+    pub bytes_read: u64,
+    /// The number of bytes written to the writer.
+    pub bytes_written: u64,
+    /// The checksum of the uncompressed data: the Adler-32 for zlib, or the CRC-32 for gzip.
+    /// `None` for raw deflate data, or when decompressing without checking the checksum.
+    pub checksum: Option<u32>,
+    /// The number of chunks read from the input, each at most the size of the input buffer.
+    pub chunks: u64,
 }
 
 impl core::convert::From<StreamResult> for MZResult {
@@ -207,6 +457,6 @@ impl core::convert::From<StreamResult> for MZResult {
 
 impl core::convert::From<&StreamResult> for MZResult {
     fn from(res: &StreamResult) -> Self {
-        res.status.clone()
+        res.status
     }
 }
@@ -0,0 +1,177 @@
+//! Hooks for counting the bytes going through a compressor or decompressor, and its flushes
+//! and errors, e.g. to feed Prometheus counters without wrapping every call.
+//!
+//! Attach a [`MetricsSink`] with
+//! [`CompressorOxide::set_metrics`](crate::deflate::core::CompressorOxide::set_metrics) or
+//! [`InflateState::set_metrics`](crate::inflate::stream::InflateState::set_metrics). The same
+//! sink can be shared by any number of them, so one set of counters can cover a whole
+//! service.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//!
+//! use miniz_oxide::deflate::core::{compress, CompressorOxide, TDEFLFlush};
+//! use miniz_oxide::metrics::MetricsSink;
+//!
+//! #[derive(Default)]
+//! struct Counters {
+//!     consumed: AtomicU64,
+//!     produced: AtomicU64,
+//! }
+//!
+//! impl MetricsSink for Counters {
+//!     fn bytes_consumed(&self, bytes: u64) {
+//!         self.consumed.fetch_add(bytes, Ordering::Relaxed);
+//!     }
+//!
+//!     fn bytes_produced(&self, bytes: u64) {
+//!         self.produced.fetch_add(bytes, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let counters = Arc::new(Counters::default());
+//! let mut compressor = CompressorOxide::default();
+//! compressor.set_metrics(Some(counters.clone()));
+//! let mut output = [0; 100];
+//! let (_, _, len) = compress(&mut compressor, &[7; 1000], &mut output, TDEFLFlush::Finish);
+//! assert_eq!(counters.consumed.load(Ordering::Relaxed), 1000);
+//! assert_eq!(counters.produced.load(Ordering::Relaxed), len as u64);
+//! ```
+
+use crate::MZError;
+
+/// Receives the counts of a compressor or decompressor as it is used.
+///
+/// The methods are called from inside the compression and decompression calls, so they
+/// should be quick, like adding to an atomic counter. They do nothing by default, so only the
+/// ones of interest have to be implemented.
+pub trait MetricsSink: Send + Sync {
+    /// `bytes` bytes of input were consumed.
+    fn bytes_consumed(&self, _bytes: u64) {}
+
+    /// `bytes` bytes of output were produced.
+    fn bytes_produced(&self, _bytes: u64) {}
+
+    /// A flush completed: a call with a flush mode other than `None` returned with all of its
+    /// input consumed and all of its output written.
+    fn flushed(&self) {}
+
+    /// A call failed.
+    ///
+    /// For the compressor, [`MZError::Param`] is a call after an earlier failure or after
+    /// the end of the stream, and [`MZError::Stream`] is output that couldn't be written.
+    /// For the decompressor the error is the one in the returned
+    /// [`StreamResult`](crate::StreamResult), apart from [`MZError::Buf`], which only means
+    /// that no progress could be made.
+    fn error(&self, _error: MZError) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deflate::core::CompressorOxide;
+    use crate::deflate::stream::deflate;
+    use crate::inflate::stream::{InflateState, inflate_to_slice};
+    use crate::{DataFormat, MZFlush, MZStatus};
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct Counters {
+        consumed: AtomicU64,
+        produced: AtomicU64,
+        flushes: AtomicU64,
+        errors: Mutex<Vec<MZError>>,
+    }
+
+    impl MetricsSink for Counters {
+        fn bytes_consumed(&self, bytes: u64) {
+            self.consumed.fetch_add(bytes, Ordering::Relaxed);
+        }
+
+        fn bytes_produced(&self, bytes: u64) {
+            self.produced.fetch_add(bytes, Ordering::Relaxed);
+        }
+
+        fn flushed(&self) {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn error(&self, error: MZError) {
+            self.errors.lock().unwrap().push(error);
+        }
+    }
+
+    impl Counters {
+        fn get(&self) -> (u64, u64, u64) {
+            (
+                self.consumed.load(Ordering::Relaxed),
+                self.produced.load(Ordering::Relaxed),
+                self.flushes.load(Ordering::Relaxed),
+            )
+        }
+    }
+
+    #[test]
+    fn compressor_metrics() {
+        let counters = Arc::new(Counters::default());
+        let mut compressor = CompressorOxide::default();
+        compressor.set_metrics(Some(counters.clone()));
+
+        let data = crate::test_util::text(50_000);
+        let mut output = vec![0; 100_000];
+        let mut out_pos = 0;
+        for (part, flush) in
+            data.chunks(20_000)
+                .zip([MZFlush::None, MZFlush::Sync, MZFlush::Finish])
+        {
+            let res = deflate(&mut compressor, part, &mut output[out_pos..], flush);
+            assert!(res.status.is_ok());
+            out_pos += res.bytes_written;
+        }
+        assert_eq!(counters.get(), (50_000, out_pos as u64, 2));
+
+        // Compressing after the end of the stream is an error.
+        let res = deflate(&mut compressor, &data, &mut output, MZFlush::None);
+        assert_eq!(res.status, Err(MZError::Buf));
+        crate::deflate::core::compress(
+            &mut compressor,
+            &data,
+            &mut output,
+            crate::deflate::core::TDEFLFlush::None,
+        );
+        assert_eq!(*counters.errors.lock().unwrap(), [MZError::Param]);
+    }
+
+    #[test]
+    fn decompressor_metrics() {
+        let data = b"metrics ".repeat(10_000);
+        let compressed = crate::deflate::compress_to_vec(&data, 6);
+        let counters = Arc::new(Counters::default());
+        let mut state = InflateState::new_boxed(DataFormat::Raw);
+        state.set_metrics(Some(counters.clone()));
+
+        let mut output = vec![0; 1000];
+        let mut input = &compressed[..];
+        loop {
+            let res = inflate_to_slice(&mut state, input, &mut output, MZFlush::Sync);
+            input = &input[res.bytes_consumed..];
+            if res.status == Ok(MZStatus::StreamEnd) {
+                break;
+            }
+        }
+        assert_eq!(
+            counters.get(),
+            (compressed.len() as u64, data.len() as u64, 1)
+        );
+
+        let mut corrupt = compressed.clone();
+        corrupt[0] |= 0b110;
+        state.reset(DataFormat::Raw);
+        let res = inflate_to_slice(&mut state, &corrupt, &mut output, MZFlush::Finish);
+        assert_eq!(res.status, Err(MZError::Data));
+        assert_eq!(*counters.errors.lock().unwrap(), [MZError::Data]);
+    }
+}
@@ -0,0 +1,294 @@
+//! Compression and decompression on tokio's blocking thread pool, with the IO staying on the
+//! executor.
+//!
+//! Compressing at a high level takes long enough to hold up the other tasks of an executor
+//! thread. [`compress_offloaded`] and [`decompress_offloaded`] do the work with
+//! [`tokio::task::spawn_blocking`], and only read the input and write the output on the task
+//! that awaits them. Chunks go between the two through bounded channels, so a slow writer
+//! pauses reading, as with [`compress_pipelined`](crate::deflate::pipeline::compress_pipelined).
+//!
+//! The settings and the results are the same as for
+//! [`copy_encode_with`](crate::deflate::stream::copy_encode_with) and
+//! [`decompress_stream_with`].
+
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+
+use async_channel::{Receiver, Sender, bounded};
+use binrw::io::read::Read;
+use binrw::io::write::Write;
+use tokio::task::JoinHandle;
+
+use crate::StreamSummary;
+use crate::deflate::CompressError;
+use crate::deflate::pipeline::try_join3;
+use crate::deflate::stream::{EncodeOptions, encode_stream};
+use crate::error::Error;
+use crate::inflate::stream::{DecodeOptions, decompress_stream_with};
+
+/// Compress everything read from `input` to `writer` with the settings of `options`, on the
+/// blocking thread pool.
+///
+/// At most `capacity` chunks of `options.chunk_size` bytes are queued on the way to the
+/// blocking thread and back. A `capacity` of 0 is treated as 1. Must be called from inside a
+/// tokio runtime.
+pub fn compress_offloaded<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: EncodeOptions,
+    capacity: usize,
+) -> impl Future<Output = Result<StreamSummary, CompressError>> + Send + 'a {
+    async move {
+        let (channels, mut reader, mut sink) = Channels::new(capacity);
+        let work = tokio::task::spawn_blocking(move || {
+            block_on(encode_stream(&mut reader, &mut sink, options))
+        });
+        channels.run(input, writer, options.chunk_size, work).await
+    }
+}
+
+/// Decompress everything read from `input` to `writer` with the settings of `options`, on the
+/// blocking thread pool.
+///
+/// At most `capacity` chunks are queued on the way to the blocking thread and back. A
+/// `capacity` of 0 is treated as 1. Must be called from inside a tokio runtime.
+pub fn decompress_offloaded<'a, R: Read + Send + 'a, W: Write + Send>(
+    input: &'a mut R,
+    writer: &'a mut W,
+    options: DecodeOptions,
+    capacity: usize,
+) -> impl Future<Output = Result<StreamSummary, Error>> + Send + 'a {
+    async move {
+        let (channels, mut reader, mut sink) = Channels::new(capacity);
+        let work = tokio::task::spawn_blocking(move || {
+            block_on(decompress_stream_with(&mut reader, &mut sink, options))
+        });
+        channels.run(input, writer, options.chunk_size, work).await
+    }
+}
+
+/// The executor's ends of the channels to and from the blocking thread. Used buffers are sent
+/// back on the `free_*` channels to be filled again.
+struct Channels {
+    input: Sender<Vec<u8>>,
+    free_input: Receiver<Vec<u8>>,
+    output: Receiver<Vec<u8>>,
+    free_output: Sender<Vec<u8>>,
+}
+
+impl Channels {
+    /// Create the channels, and the reader and writer for the blocking thread.
+    fn new(capacity: usize) -> (Channels, ChannelReader, ChannelWriter) {
+        let capacity = capacity.max(1);
+        let (input_tx, input_rx) = bounded(capacity);
+        let (output_tx, output_rx) = bounded(capacity);
+        // Room for every buffer that can be in flight, so handing one back never waits.
+        let (free_input_tx, free_input_rx) = bounded(capacity + 2);
+        let (free_output_tx, free_output_rx) = bounded(capacity + 2);
+        let channels = Channels {
+            input: input_tx,
+            free_input: free_input_rx,
+            output: output_rx,
+            free_output: free_output_tx,
+        };
+        let reader = ChannelReader {
+            chunks: input_rx,
+            free: free_input_tx,
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        let writer = ChannelWriter {
+            output: output_tx,
+            free: free_output_rx,
+        };
+        (channels, reader, writer)
+    }
+
+    /// Feed `input` to the blocking thread and write what comes back to `writer`, until `work`
+    /// is done.
+    async fn run<T, E: From<std::io::Error>>(
+        self,
+        input: &mut (impl Read + Send),
+        writer: &mut (impl Write + Send),
+        chunk_size: usize,
+        work: JoinHandle<Result<T, E>>,
+    ) -> Result<T, E> {
+        let Channels {
+            input: chunks,
+            free_input,
+            output,
+            free_output,
+        } = self;
+        let feed = async move {
+            loop {
+                let mut chunk = free_input.try_recv().unwrap_or_default();
+                chunk.resize(chunk_size.max(1), 0);
+                let read = input.read(&mut chunk).await?;
+                chunk.truncate(read);
+                // The blocking thread stops reading at the end of the compressed data.
+                if read == 0 || chunks.send(chunk).await.is_err() {
+                    return Ok(());
+                }
+            }
+        };
+        let drain = async move {
+            while let Ok(chunk) = output.recv().await {
+                writer.write_all(&chunk).await?;
+                let _ = free_output.try_send(chunk);
+            }
+            writer.flush().await?;
+            Ok(())
+        };
+        let work = async move {
+            match work.await {
+                Ok(res) => res,
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(err) => Err(std::io::Error::other(err).into()),
+            }
+        };
+        let ((), (), res) = try_join3(feed, drain, work).await?;
+        Ok(res)
+    }
+}
+
+/// Reads the chunks sent to the blocking thread. The end of the channel is the end of the
+/// input.
+struct ChannelReader {
+    chunks: Receiver<Vec<u8>>,
+    free: Sender<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            if self.pos == self.chunk.len() {
+                let Ok(chunk) = self.chunks.recv().await else {
+                    return Ok(0);
+                };
+                let used = std::mem::replace(&mut self.chunk, chunk);
+                let _ = self.free.try_send(used);
+                self.pos = 0;
+            }
+            let n = buf.len().min(self.chunk.len() - self.pos);
+            buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { Ok(()) }
+    }
+}
+
+/// Sends what the blocking thread writes back to the executor.
+struct ChannelWriter {
+    output: Sender<Vec<u8>>,
+    free: Receiver<Vec<u8>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            let mut chunk = self.free.try_recv().unwrap_or_default();
+            chunk.clear();
+            chunk.extend_from_slice(buf);
+            // The writer on the executor failed, and its error is the one returned.
+            self.output
+                .send(chunk)
+                .await
+                .map_err(|_| std::io::ErrorKind::BrokenPipe)?;
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { Ok(()) }
+    }
+}
+
+/// Run `future` to completion on the current thread, parking it while the future waits.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(Unpark(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_offloaded, decompress_offloaded};
+    use crate::DataFormat;
+    use crate::deflate::CompressionLevel;
+    use crate::deflate::stream::{EncodeOptions, copy_encode_with};
+    use crate::error::Error;
+    use crate::inflate::stream::DecodeOptions;
+    use std::io::Cursor;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn round_trip() {
+        let data = crate::test_util::text(300_000);
+        let options = EncodeOptions {
+            level: CompressionLevel::BestCompression,
+            format: DataFormat::Zlib,
+            chunk_size: 10_000,
+        };
+        let mut expected = Cursor::new(Vec::new());
+        copy_encode_with(&mut Cursor::new(&data), &mut expected, options)
+            .await
+            .unwrap();
+        let expected = expected.into_inner();
+
+        let mut compressed = Cursor::new(Vec::new());
+        let summary = compress_offloaded(&mut Cursor::new(&data), &mut compressed, options, 2)
+            .await
+            .unwrap();
+        let compressed = compressed.into_inner();
+        assert!(compressed == expected);
+        assert_eq!(summary.bytes_read, data.len() as u64);
+        assert_eq!(summary.bytes_written, compressed.len() as u64);
+
+        let options = DecodeOptions {
+            format: DataFormat::Zlib,
+            ..DecodeOptions::default()
+        };
+        // Data after the end of the stream is ignored.
+        let mut input = compressed.clone();
+        input.extend_from_slice(&[0; 100_000]);
+        let mut out = Cursor::new(Vec::new());
+        let summary = decompress_offloaded(&mut Cursor::new(&input), &mut out, options, 0)
+            .await
+            .unwrap();
+        assert!(out.into_inner() == data);
+        assert_eq!(summary.bytes_written, data.len() as u64);
+        assert_eq!(
+            summary.checksum,
+            Some(crate::shared::update_adler32(1, &data))
+        );
+
+        let mut corrupt = compressed;
+        let len = corrupt.len();
+        corrupt[len - 1] ^= 1;
+        let mut out = Cursor::new(Vec::new());
+        let err = decompress_offloaded(&mut Cursor::new(&corrupt), &mut out, options, 4)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Adler32Mismatch(_)));
+    }
+}
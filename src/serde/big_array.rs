@@ -1,7 +1,7 @@
 use serde::de::{Deserialize, Deserializer, Error, SeqAccess, Visitor};
 use serde::ser::{Serialize, SerializeTuple, Serializer};
-use std::fmt;
-use std::marker::PhantomData;
+use core::fmt;
+use core::marker::PhantomData;
 
 pub trait BigArray<'de>: Sized {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -65,6 +65,7 @@ macro_rules! big_array {
 }
 
 big_array! {
-    288, 512,
-    576, 1024,
+    128, 256, 288,
+    512, 576,
+    1024,
 }
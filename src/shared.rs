@@ -8,6 +8,220 @@ pub const HUFFMAN_LENGTH_ORDER: [u8; 19] = [
     16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
 ];
 
+/// Combine the adler32 checksums `adler1` and `adler2` of two pieces of data into the checksum
+/// of the data concatenated, where `len2` is the length of the second piece.
+#[cfg(feature = "parallel")]
+pub(crate) fn combine_adler32(adler1: u32, adler2: u32, len2: usize) -> u32 {
+    const BASE: u32 = 65521;
+    let rem = (len2 % BASE as usize) as u32;
+    let a1 = adler1 & 0xFFFF;
+    let b1 = adler1 >> 16;
+    let a2 = adler2 & 0xFFFF;
+    let b2 = adler2 >> 16;
+
+    // The first sum of the second piece started at 1 rather than continuing from `a1`, so add
+    // `a1 - 1`. That difference was also left out of each of the `len2` additions to the
+    // second sum.
+    let a = (a1 + a2 + BASE - 1) % BASE;
+    let b = ((u64::from(rem) * u64::from(a1)) % u64::from(BASE)) as u32;
+    let b = (b + b1 + b2 + BASE - rem) % BASE;
+    (b << 16) | a
+}
+
+/// Table for the byte-at-a-time CRC-32 (ISO-HDLC, as used by gzip and zip) computation.
+#[cfg(feature = "with-alloc")]
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Update the CRC-32 checksum `crc` with `data`. Start with 0 for a new checksum.
+#[cfg(feature = "with-alloc")]
+pub(crate) fn update_crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC32_TABLE[usize::from((crc as u8) ^ byte)] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// A buffer for data passing through the crate's own stream functions, which is wiped when
+/// dropped with the `zeroize` feature so no uncompressed data is left in freed memory.
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+pub(crate) struct ScratchBuffer(alloc::boxed::Box<[u8]>);
+
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+impl ScratchBuffer {
+    /// Allocate a zeroed buffer of `len` bytes.
+    pub(crate) fn new(len: usize) -> Self {
+        ScratchBuffer(alloc::vec![0; len].into_boxed_slice())
+    }
+}
+
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+impl core::ops::Deref for ScratchBuffer {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+impl core::ops::DerefMut for ScratchBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[cfg(all(any(feature = "std", feature = "embedded-io"), feature = "zeroize"))]
+impl Drop for ScratchBuffer {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut *self.0);
+    }
+}
+
+/// A writer that can write several buffers in one call, the async counterpart of
+/// [`std::io::Write::write_vectored`], for
+/// [`deflate_vectored`](crate::deflate::stream::deflate_vectored) and
+/// [`inflate_vectored`](crate::inflate::stream::inflate_vectored).
+///
+/// The default [`write_vectored`](Self::write_vectored) writes the first non-empty buffer with
+/// [`write`](binrw::io::write::Write::write), so an empty `impl` is enough for any writer.
+/// Sockets and files should override it with their vectored write, which puts everything
+/// out in one syscall.
+#[cfg(feature = "std")]
+pub trait WriteVectored: binrw::io::write::Write + Send {
+    /// Write from `bufs` in order, returning the number of bytes written, which may be less
+    /// than their total length.
+    fn write_vectored(
+        &mut self,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            let buf = bufs
+                .iter()
+                .find(|buf| !buf.is_empty())
+                .map_or(&[][..], |buf| &**buf);
+            self.write(buf).await
+        }
+    }
+}
+
+/// Write all of `bufs` with as few calls to [`WriteVectored::write_vectored`] as it takes.
+#[cfg(feature = "std")]
+async fn write_all_vectored<W: WriteVectored>(
+    writer: &mut W,
+    mut bufs: &mut [std::io::IoSlice<'_>],
+) -> std::io::Result<()> {
+    // Skip empty buffers up front, so a write of 0 bytes always means the writer is stuck.
+    std::io::IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match writer.write_vectored(bufs).await {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => std::io::IoSlice::advance_slices(&mut bufs, n),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Output collected in up to [`Segments::COUNT`] buffers of [`Segments::SIZE`] bytes, which
+/// are written out together with one vectored write once they are full, rather than with a
+/// write for every small piece produced.
+#[cfg(feature = "std")]
+pub(crate) struct Segments {
+    bufs: alloc::vec::Vec<ScratchBuffer>,
+    /// The number of bytes used in each buffer in use, all but the last are full.
+    lens: alloc::vec::Vec<usize>,
+}
+
+#[cfg(feature = "std")]
+impl Segments {
+    pub(crate) const SIZE: usize = 16 * 1024;
+    pub(crate) const COUNT: usize = 16;
+
+    pub(crate) fn new() -> Self {
+        Segments {
+            bufs: alloc::vec::Vec::new(),
+            lens: alloc::vec::Vec::new(),
+        }
+    }
+
+    /// The free part of the current buffer, which is only empty if all the buffers are full.
+    /// The buffers are allocated as they are needed.
+    pub(crate) fn spare(&mut self) -> &mut [u8] {
+        if self.lens.last().is_none_or(|&len| len == Self::SIZE) {
+            if self.lens.len() == Self::COUNT {
+                return &mut [];
+            }
+            if self.bufs.len() == self.lens.len() {
+                self.bufs.push(ScratchBuffer::new(Self::SIZE));
+            }
+            self.lens.push(0);
+        }
+        let i = self.lens.len() - 1;
+        &mut self.bufs[i][self.lens[i]..]
+    }
+
+    /// Mark `n` bytes of [`spare`](Self::spare) as used.
+    pub(crate) fn advance(&mut self, n: usize) {
+        if let Some(len) = self.lens.last_mut() {
+            *len += n;
+        }
+    }
+
+    /// The number of bytes that can still be collected.
+    pub(crate) fn space(&self) -> usize {
+        Self::SIZE * Self::COUNT - self.lens.iter().sum::<usize>()
+    }
+
+    /// Copy `data`, which is no longer than [`space`](Self::space).
+    pub(crate) fn put(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let spare = self.spare();
+            let n = spare.len().min(data.len());
+            spare[..n].copy_from_slice(&data[..n]);
+            self.advance(n);
+            data = &data[n..];
+        }
+    }
+
+    /// Write everything collected to `writer`, and start over.
+    pub(crate) async fn write_to<W: WriteVectored>(
+        &mut self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let mut slices: alloc::vec::Vec<_> = self
+            .bufs
+            .iter()
+            .zip(&self.lens)
+            .map(|(buf, &len)| std::io::IoSlice::new(&buf[..len]))
+            .collect();
+        write_all_vectored(writer, &mut slices).await?;
+        self.lens.clear();
+        Ok(())
+    }
+}
+
 #[doc(hidden)]
 #[cfg(not(feature = "simd"))]
 pub fn update_adler32(adler: u32, data: &[u8]) -> u32 {
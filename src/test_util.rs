@@ -0,0 +1,36 @@
+//! Input data shared by the unit tests.
+
+use alloc::vec::Vec;
+
+const WORDS: [&str; 16] = [
+    "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog", "deflate", "window", "match",
+    "literal", "huffman", "block", "stream", "zlib",
+];
+
+/// `len` bytes of text made of words picked at random from a short list. Compresses to
+/// about a sixth of its size at the default level, and better at higher levels.
+pub(crate) fn text(len: usize) -> Vec<u8> {
+    let mut seed = 12345u32;
+    let mut text = Vec::with_capacity(len + 8);
+    while text.len() < len {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        text.extend_from_slice(WORDS[(seed >> 28) as usize].as_bytes());
+        text.push(if seed & 0xF00 == 0 { b'\n' } else { b' ' });
+    }
+    text.truncate(len);
+    text
+}
+
+/// `len` pseudo-random bytes below `symbols`, which has to be at most 256.
+///
+/// There are few matches in the result, so it compresses to about `log2(symbols)` bits a
+/// byte, and not at all with 256 symbols.
+pub(crate) fn random(len: usize, symbols: u32) -> Vec<u8> {
+    let mut seed = 12345u32;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((u64::from(seed >> 8) * u64::from(symbols)) >> 24) as u8
+        })
+        .collect()
+}
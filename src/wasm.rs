@@ -0,0 +1,260 @@
+//! Chunked compression and decompression for JavaScript through
+//! [wasm-bindgen](https://github.com/rustwasm/wasm-bindgen).
+//!
+//! Each chunk pushed in as a `Uint8Array` gives back a `Uint8Array` with the data produced so
+//! far, so e.g. an upload can be compressed while it is read, with the totals or a progress
+//! callback to show how far along it is.
+//!
+//! ```js
+//! const compressor = new Compressor(6, true);
+//! for await (const chunk of file.stream()) {
+//!     send(compressor.push(chunk));
+//! }
+//! send(compressor.finish());
+//! ```
+
+use crate::alloc::boxed::Box;
+use crate::alloc::string::ToString;
+use crate::alloc::vec::Vec;
+use js_sys::Function;
+use wasm_bindgen::prelude::*;
+
+use crate::deflate::core::CompressorOxide;
+use crate::deflate::stream::deflate;
+use crate::inflate::stream::{InflateState, inflate_to_slice};
+use crate::{DataFormat, MZError, MZFlush, MZStatus};
+
+/// The size of the buffer that output is produced into before it is appended to the chunk.
+const OUT_BUF_SIZE: usize = 32 * 1024;
+
+const fn data_format(zlib: bool) -> DataFormat {
+    if zlib {
+        DataFormat::Zlib
+    } else {
+        DataFormat::Raw
+    }
+}
+
+/// Call the progress callback, if there is one, with the totals.
+fn report(callback: &Option<Function>, total_in: u64, total_out: u64) -> Result<(), JsError> {
+    if let Some(callback) = callback {
+        callback
+            .call2(
+                &JsValue::NULL,
+                &JsValue::from(total_in as f64),
+                &JsValue::from(total_out as f64),
+            )
+            .map_err(|_| JsError::new("The progress callback threw an exception"))?;
+    }
+    Ok(())
+}
+
+/// Compresses data pushed in a chunk at a time.
+#[wasm_bindgen]
+pub struct Compressor {
+    compressor: Box<CompressorOxide>,
+    total_in: u64,
+    total_out: u64,
+    finished: bool,
+    progress: Option<Function>,
+}
+
+#[wasm_bindgen]
+impl Compressor {
+    /// Create a compressor at `level` (0-10), which wraps the data in a zlib header if `zlib`
+    /// is set and produces raw deflate otherwise.
+    #[wasm_bindgen(constructor)]
+    pub fn new(level: u8, zlib: bool) -> Compressor {
+        Compressor {
+            compressor: CompressorOxide::builder()
+                .format(data_format(zlib))
+                .level(level)
+                .build_boxed(),
+            total_in: 0,
+            total_out: 0,
+            finished: false,
+            progress: None,
+        }
+    }
+
+    /// Compress `chunk`, and get what was produced. This can be empty, as the compressor
+    /// collects data before it outputs a block.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+        self.run(chunk, MZFlush::None)
+    }
+
+    /// Get what is left of the compressed data after the last chunk. The compressor can't be
+    /// used any more afterwards.
+    pub fn finish(&mut self) -> Result<Vec<u8>, JsError> {
+        self.run(&[], MZFlush::Finish)
+    }
+
+    /// Call `callback` with the total number of bytes taken in and put out after every chunk,
+    /// or stop calling it if `callback` is `undefined`.
+    #[wasm_bindgen(js_name = setProgressCallback)]
+    pub fn set_progress_callback(&mut self, callback: Option<Function>) {
+        self.progress = callback;
+    }
+
+    /// The total number of bytes taken in.
+    #[wasm_bindgen(getter, js_name = totalIn)]
+    pub fn total_in(&self) -> f64 {
+        self.total_in as f64
+    }
+
+    /// The total number of compressed bytes put out.
+    #[wasm_bindgen(getter, js_name = totalOut)]
+    pub fn total_out(&self) -> f64 {
+        self.total_out as f64
+    }
+}
+
+impl Compressor {
+    fn run(&mut self, mut input: &[u8], flush: MZFlush) -> Result<Vec<u8>, JsError> {
+        if self.finished {
+            return Err(JsError::new("The compressor has already finished"));
+        }
+        let mut ret = Vec::new();
+        let mut buf = [0; OUT_BUF_SIZE];
+        loop {
+            let res = deflate(&mut self.compressor, input, &mut buf, flush);
+            input = &input[res.bytes_consumed..];
+            ret.extend_from_slice(&buf[..res.bytes_written]);
+            self.total_in += res.bytes_consumed as u64;
+            self.total_out += res.bytes_written as u64;
+            match res.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.finished = true;
+                    break;
+                }
+                // All the input has been taken in, and the output didn't fill up, so nothing
+                // is waiting to be written.
+                Ok(_)
+                    if input.is_empty()
+                        && flush != MZFlush::Finish
+                        && res.bytes_written < buf.len() =>
+                {
+                    break;
+                }
+                Ok(_) => {}
+                // No more progress without more input.
+                Err(MZError::Buf) => break,
+                Err(err) => return Err(JsError::new(&err.to_string())),
+            }
+        }
+        report(&self.progress, self.total_in, self.total_out)?;
+        Ok(ret)
+    }
+}
+
+/// Decompresses data pushed in a chunk at a time.
+#[wasm_bindgen]
+pub struct Decompressor {
+    state: Box<InflateState>,
+    finished: bool,
+    progress: Option<Function>,
+}
+
+#[wasm_bindgen]
+impl Decompressor {
+    /// Create a decompressor for zlib data if `zlib` is set, and raw deflate otherwise.
+    #[wasm_bindgen(constructor)]
+    pub fn new(zlib: bool) -> Decompressor {
+        Decompressor {
+            state: InflateState::new_boxed(data_format(zlib)),
+            finished: false,
+            progress: None,
+        }
+    }
+
+    /// Decompress `chunk`, and get what was produced. Anything after the end of the
+    /// compressed data is ignored.
+    pub fn push(&mut self, mut chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+        let mut ret = Vec::new();
+        let mut buf = [0; OUT_BUF_SIZE];
+        while !self.finished {
+            let res = inflate_to_slice(&mut self.state, chunk, &mut buf, MZFlush::None);
+            chunk = &chunk[res.bytes_consumed..];
+            ret.extend_from_slice(&buf[..res.bytes_written]);
+            match res.status {
+                Ok(MZStatus::StreamEnd) => self.finished = true,
+                Ok(_) if chunk.is_empty() && res.bytes_written < buf.len() => break,
+                Ok(_) => {}
+                Err(MZError::Buf) => break,
+                Err(_) => return Err(JsError::new(&self.state.last_status().to_string())),
+            }
+        }
+        report(
+            &self.progress,
+            self.state.total_in(),
+            self.state.total_out(),
+        )?;
+        Ok(ret)
+    }
+
+    /// Check that the end of the compressed data was reached, i.e. it isn't truncated.
+    pub fn finish(&self) -> Result<(), JsError> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(JsError::new("The compressed data is truncated"))
+        }
+    }
+
+    /// Whether the end of the compressed data has been reached.
+    #[wasm_bindgen(getter)]
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Call `callback` with the total number of bytes taken in and put out after every chunk,
+    /// or stop calling it if `callback` is `undefined`.
+    #[wasm_bindgen(js_name = setProgressCallback)]
+    pub fn set_progress_callback(&mut self, callback: Option<Function>) {
+        self.progress = callback;
+    }
+
+    /// The total number of compressed bytes taken in.
+    #[wasm_bindgen(getter, js_name = totalIn)]
+    pub fn total_in(&self) -> f64 {
+        self.state.total_in() as f64
+    }
+
+    /// The total number of decompressed bytes put out.
+    #[wasm_bindgen(getter, js_name = totalOut)]
+    pub fn total_out(&self) -> f64 {
+        self.state.total_out() as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunked_roundtrip() {
+        let data = crate::test_util::text(200_000);
+
+        let mut compressor = Compressor::new(6, true);
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(10_000) {
+            compressed.extend(compressor.push(chunk).unwrap());
+        }
+        compressed.extend(compressor.finish().unwrap());
+        assert_eq!(compressor.total_in(), data.len() as f64);
+        assert_eq!(compressor.total_out(), compressed.len() as f64);
+        assert_eq!(
+            crate::inflate::decompress_to_vec_zlib(&compressed).unwrap(),
+            data
+        );
+
+        let mut decompressor = Decompressor::new(true);
+        let mut decompressed = Vec::new();
+        for chunk in compressed.chunks(1000) {
+            assert!(!decompressor.finished());
+            decompressed.extend(decompressor.push(chunk).unwrap());
+        }
+        assert!(decompressor.finished());
+        assert_eq!(decompressed, data);
+    }
+}
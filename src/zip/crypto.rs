@@ -0,0 +1,115 @@
+//! The traditional PKWARE encryption of zip entries, also known as ZipCrypto.
+//!
+//! It is a weak stream cipher, only supported to exchange archives with tools that still use
+//! it. The data of an encrypted entry starts with a 12-byte header, whose last byte is checked
+//! to reject a wrong password.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use crate::shared::update_crc32;
+
+/// Length of the encryption header before the data of an entry.
+pub(super) const ENCRYPTION_HEADER_LEN: usize = 12;
+
+/// The state of the cipher, initialized from the password.
+#[derive(Clone)]
+pub(super) struct ZipCrypto {
+    keys: [u32; 3],
+}
+
+impl ZipCrypto {
+    pub(super) fn new(password: &[u8]) -> Self {
+        let mut crypto = ZipCrypto {
+            keys: [0x1234_5678, 0x2345_6789, 0x3456_7890],
+        };
+        for &byte in password {
+            crypto.update_keys(byte);
+        }
+        crypto
+    }
+
+    fn update_keys(&mut self, byte: u8) {
+        // The keys are updated with the CRC-32 step, without the inversions of a checksum.
+        self.keys[0] = !update_crc32(!self.keys[0], &[byte]);
+        self.keys[1] = self.keys[1]
+            .wrapping_add(self.keys[0] & 0xFF)
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        self.keys[2] = !update_crc32(!self.keys[2], &[(self.keys[1] >> 24) as u8]);
+    }
+
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.keys[2] | 2) as u16;
+        (temp.wrapping_mul(temp ^ 1) >> 8) as u8
+    }
+
+    /// Decrypt `data` in place, continuing from the data before it.
+    pub(super) fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.stream_byte();
+            self.update_keys(*byte);
+        }
+    }
+
+    /// Encrypt `data` in place, continuing from the data before it.
+    pub(super) fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            let plain = *byte;
+            *byte ^= self.stream_byte();
+            self.update_keys(plain);
+        }
+    }
+
+    /// Encrypt a new header ending with `check`, to be written before the data.
+    pub(super) fn encrypt_header(&mut self, check: u8) -> [u8; ENCRYPTION_HEADER_LEN] {
+        // The other bytes only have to be unpredictable, which the random keys of the
+        // standard hasher are.
+        let random = RandomState::new();
+        let mut header = [0; ENCRYPTION_HEADER_LEN];
+        header[..8].copy_from_slice(&random.hash_one(0u8).to_le_bytes());
+        header[8..11].copy_from_slice(&random.hash_one(1u8).to_le_bytes()[..3]);
+        header[11] = check;
+        self.encrypt(&mut header);
+        header
+    }
+}
+
+/// The byte the encryption header of an entry ends with: the high byte of the CRC-32, or of
+/// the modification time when the CRC-32 is only known after the data.
+pub(super) const fn check_byte(crc32: u32, modified: u32, descriptor: bool) -> u8 {
+    if descriptor {
+        (modified >> 8) as u8
+    } else {
+        (crc32 >> 24) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ENCRYPTION_HEADER_LEN, ZipCrypto};
+
+    #[test]
+    fn round_trip() {
+        let data = crate::test_util::text(1000);
+        let mut encrypted = data.clone();
+        let mut cipher = ZipCrypto::new(b"password");
+        let header = cipher.encrypt_header(0xAB);
+        cipher.encrypt(&mut encrypted);
+        assert!(encrypted != data);
+
+        let mut decipher = ZipCrypto::new(b"password");
+        let mut decrypted_header = header;
+        decipher.decrypt(&mut decrypted_header);
+        assert_eq!(decrypted_header[ENCRYPTION_HEADER_LEN - 1], 0xAB);
+        // Decrypting in pieces gives the same result.
+        let (first, second) = encrypted.split_at_mut(333);
+        decipher.decrypt(first);
+        decipher.decrypt(second);
+        assert!(encrypted == data);
+
+        let mut wrong = header;
+        ZipCrypto::new(b"Password").decrypt(&mut wrong);
+        assert!(wrong != decrypted_header);
+    }
+}
@@ -0,0 +1,170 @@
+//! Reading and writing [zip](https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT)
+//! archives, with the deflate implementation of this crate.
+//!
+//! [`ZipWriter`] creates archives, or [`ZipStreamWriter`] when the output can't seek, and
+//! [`ZipReader`] lists the entries of an archive and extracts them, while [`list`] only reads
+//! the list. [`recompress`] copies an archive with its entries compressed again.
+//!
+//! Only what is needed for simple archives is supported: stored and deflated entries in a
+//! single-disk archive, with the ZIP64 extensions for entries and archives larger than 4 GiB
+//! or with more than 65535 entries.
+//!
+//! With the `deflate64` feature, entries compressed with Deflate64 can be extracted too.
+//!
+//! Entries encrypted with the traditional PKWARE cipher can be extracted with
+//! [`ZipReader::extract_with_password`], and written with [`EntryOptions::password`]. The
+//! cipher is weak, so this is only meant for tools that still expect it.
+//!
+//! Archives from untrusted sources can be extracted within [`ExtractLimits`], which reject
+//! zip bombs before they use up memory or disk space.
+
+use thiserror::Error;
+
+mod crypto;
+mod read;
+mod recompress;
+mod write;
+
+pub use self::read::{ExtractLimits, Limit, ZipEntry, ZipReader, list};
+pub use self::recompress::recompress;
+pub use self::write::{EntryOptions, ZipStreamWriter, ZipWriter};
+
+/// Signature of a local file header.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4B50;
+/// Signature of a central directory file header.
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4B50;
+/// Signature of the data descriptor after the data of an entry.
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4B50;
+/// Signature of the end of central directory record.
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4B50;
+/// Signature of the ZIP64 end of central directory record.
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4B50;
+/// Signature of the ZIP64 end of central directory locator.
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4B50;
+
+/// Size of a local file header without the name and extra field.
+const LOCAL_HEADER_LEN: usize = 30;
+/// Size of a central directory file header without the name, extra field and comment.
+const CENTRAL_HEADER_LEN: usize = 46;
+/// Size of the end of central directory record without the comment.
+const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+/// Size of the ZIP64 end of central directory record without the extensible data.
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LEN: usize = 56;
+/// Size of the ZIP64 end of central directory locator.
+const ZIP64_LOCATOR_LEN: usize = 20;
+
+/// Header id of the ZIP64 extended information extra field.
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// Value of a 32-bit size or offset field whose actual value is in the ZIP64 extra field.
+const ZIP64_MARKER: u32 = u32::MAX;
+/// Value of the 16-bit entry counts when the actual count is in the ZIP64 record.
+const ZIP64_COUNT_MARKER: u16 = u16::MAX;
+
+/// General purpose flag: the entry is encrypted.
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+/// General purpose flag: the CRC-32 and sizes are in a data descriptor after the data.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// General purpose flag: the name and comment are UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+
+/// Version 2.0 of the specification, the first with deflate.
+const VERSION_DEFLATE: u16 = 20;
+/// Version 1.0 of the specification, enough for stored entries.
+const VERSION_STORED: u16 = 10;
+/// Version 4.5 of the specification, the first with ZIP64.
+const VERSION_ZIP64: u16 = 45;
+
+/// MS-DOS date and time of 1980-01-01 00:00, the earliest that can be stored.
+const DOS_EPOCH: u32 = (1 << 5 | 1) << 16;
+
+/// Number of the Deflate64 compression method, which can be read with the `deflate64`
+/// feature.
+#[cfg(feature = "deflate64")]
+const METHOD_DEFLATE64: u16 = 9;
+
+/// How the data of an entry is compressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CompressionMethod {
+    /// No compression, the data is copied as is. See [`EntryOptions::stored`].
+    Stored,
+    /// Deflate, the method used by almost all archives.
+    Deflated,
+}
+
+impl CompressionMethod {
+    /// Get the method with the number used in the headers, if it is supported.
+    pub const fn from_u16(value: u16) -> Option<CompressionMethod> {
+        match value {
+            0 => Some(CompressionMethod::Stored),
+            8 => Some(CompressionMethod::Deflated),
+            _ => None,
+        }
+    }
+
+    /// Get the number used for the method in the headers.
+    pub const fn to_u16(self) -> u16 {
+        match self {
+            CompressionMethod::Stored => 0,
+            CompressionMethod::Deflated => 8,
+        }
+    }
+}
+
+/// Errors from reading or writing zip archives.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ZipError {
+    /// Reading or writing the underlying stream failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A value doesn't fit in the fields of the headers, e.g. a name of more than 65535 bytes,
+    /// or an entry of more than 4 GiB started without [`EntryOptions::large_file`].
+    #[error("Too large for a zip archive: {0}")]
+    TooLarge(&'static str),
+    /// There is no entry at the given index, or data was written without starting an entry
+    /// first.
+    #[error("No such entry")]
+    NoEntry,
+    /// The archive is malformed.
+    #[error("Invalid zip archive: {0}")]
+    InvalidArchive(&'static str),
+    /// The entry uses a compression method that is not supported.
+    #[error("Unsupported compression method {0}")]
+    UnsupportedMethod(u16),
+    /// The data of the entry failed to decompress.
+    #[error("Failed to decompress: {0:?}")]
+    Decompress(crate::inflate::TINFLStatus),
+    /// The CRC-32 of the data of the entry doesn't match the one in the central directory.
+    #[error("CRC-32 mismatch: expected {expected:#010x}, computed {computed:#010x}")]
+    CrcMismatch { expected: u32, computed: u32 },
+    /// The entry is encrypted, and no password was given.
+    #[error("Entry is encrypted")]
+    Encrypted,
+    /// The password doesn't decrypt the entry.
+    #[error("Wrong password")]
+    InvalidPassword,
+    /// Extracting the entry would go over one of the [`ExtractLimits`] of the reader.
+    #[error("Extraction limit exceeded: {0:?}")]
+    LimitExceeded(Limit),
+    /// The compressor failed, which is not supposed to happen.
+    #[error("Failed to compress")]
+    Compress(#[source] crate::MZError),
+}
+
+impl From<ZipError> for std::io::Error {
+    /// Unwrap IO errors, and keep the others as the source of an IO error.
+    fn from(err: ZipError) -> Self {
+        use std::io::ErrorKind;
+        let kind = match err {
+            ZipError::Io(err) => return err,
+            ZipError::Decompress(status) => status.io_error_kind(),
+            ZipError::InvalidArchive(_) | ZipError::CrcMismatch { .. } => ErrorKind::InvalidData,
+            ZipError::UnsupportedMethod(_) => ErrorKind::Unsupported,
+            ZipError::Encrypted | ZipError::InvalidPassword => ErrorKind::PermissionDenied,
+            ZipError::NoEntry => ErrorKind::NotFound,
+            ZipError::TooLarge(_) | ZipError::LimitExceeded(_) => ErrorKind::FileTooLarge,
+            _ => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,968 @@
+//! Reading zip archives.
+
+use crate::alloc::string::String;
+use crate::alloc::vec;
+use crate::alloc::vec::Vec;
+use binrw::io::read::Read;
+use binrw::io::seek::Seek;
+use binrw::io::write::Write;
+use std::io::{Cursor, SeekFrom};
+
+#[cfg(feature = "deflate64")]
+use super::METHOD_DEFLATE64;
+use super::crypto::{ENCRYPTION_HEADER_LEN, ZipCrypto, check_byte};
+use super::{
+    CENTRAL_HEADER_LEN, CENTRAL_HEADER_SIGNATURE, CompressionMethod, END_OF_CENTRAL_DIRECTORY_LEN,
+    END_OF_CENTRAL_DIRECTORY_SIGNATURE, FLAG_DATA_DESCRIPTOR, FLAG_ENCRYPTED, LOCAL_HEADER_LEN,
+    LOCAL_HEADER_SIGNATURE, ZIP64_END_OF_CENTRAL_DIRECTORY_LEN,
+    ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE, ZIP64_EXTRA_ID, ZIP64_LOCATOR_LEN,
+    ZIP64_LOCATOR_SIGNATURE, ZIP64_MARKER, ZipError,
+};
+use crate::inflate::TINFLStatus;
+#[cfg(feature = "deflate64")]
+use crate::inflate::core::TINFL_DEFLATE64_DICT_SIZE;
+use crate::inflate::core::{DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress, inflate_flags};
+use crate::shared::update_crc32;
+
+/// An entry of a zip archive, as described in the central directory.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ZipEntry {
+    /// The name of the entry, with `/` separating directories. Names that are not valid
+    /// UTF-8 have the invalid parts replaced.
+    pub name: String,
+    /// The number of the compression method, see [`CompressionMethod::from_u16`].
+    pub method: u16,
+    /// The general purpose flags.
+    pub flags: u16,
+    /// The modification time, as an MS-DOS date in the high 16 bits and time in the low 16
+    /// bits.
+    pub modified: u32,
+    /// The CRC-32 of the uncompressed data.
+    pub crc32: u32,
+    /// The size of the data in the archive.
+    pub compressed_size: u64,
+    /// The size of the data once decompressed.
+    pub uncompressed_size: u64,
+    /// The position of the local header of the entry in the reader.
+    pub header_offset: u64,
+}
+
+impl ZipEntry {
+    /// Returns `true` if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// Returns `true` if the entry is encrypted, and needs a password to be extracted.
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+}
+
+/// Limits on what a [`ZipReader`] extracts, to safely extract archives from untrusted
+/// sources, which may be zip bombs.
+///
+/// The sizes in the central directory are checked against the limits before an entry is
+/// extracted, and extraction stops as soon as an entry produces more than its size. Limits
+/// left to `None` are not checked, which is the default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExtractLimits {
+    /// The largest size an entry may decompress to.
+    pub max_entry_size: Option<u64>,
+    /// The largest total size of the entries extracted by the reader, including the ones
+    /// extracted from nested archives.
+    pub max_total_size: Option<u64>,
+    /// The largest ratio of the decompressed to the compressed size of an entry. Deflate
+    /// can't go past about 1032.
+    pub max_ratio: Option<u64>,
+    /// How deep archives may be opened within archives with [`ZipReader::open_nested`].
+    pub max_depth: Option<u32>,
+}
+
+/// The limit of [`ExtractLimits`] that an entry would go over.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Limit {
+    /// The entry is larger than [`ExtractLimits::max_entry_size`].
+    EntrySize { size: u64, limit: u64 },
+    /// The entry would bring the total over [`ExtractLimits::max_total_size`].
+    TotalSize { total: u64, limit: u64 },
+    /// The entry expands more than [`ExtractLimits::max_ratio`].
+    Ratio {
+        compressed_size: u64,
+        uncompressed_size: u64,
+        limit: u64,
+    },
+    /// The nested archive would be deeper than [`ExtractLimits::max_depth`].
+    Depth { limit: u32 },
+}
+
+/// Reads the entries of a zip archive from `R`.
+///
+/// The central directory is read when the reader is opened, then each entry can be
+/// decompressed to a [`Write`] sink or a callback, with its CRC-32 checked at the end.
+///
+/// Use [`set_limits`](Self::set_limits) to extract archives that may not be trusted.
+pub struct ZipReader<R> {
+    inner: R,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+    limits: ExtractLimits,
+    /// Number of archives this one is nested in.
+    depth: u32,
+    /// Total size of the entries extracted, including by the archives this one is nested in.
+    extracted: u64,
+}
+
+impl<R> ZipReader<R> {
+    /// Get the entries of the archive, in the order of the central directory.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Get the index of the entry called `name`, if any.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    /// Get the comment of the archive.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// Set the limits checked before each entry is extracted.
+    pub fn set_limits(&mut self, limits: ExtractLimits) {
+        self.limits = limits;
+    }
+
+    /// Get the limits checked before each entry is extracted.
+    pub fn limits(&self) -> &ExtractLimits {
+        &self.limits
+    }
+
+    /// Get the total size of the entries extracted so far, which is checked against
+    /// [`ExtractLimits::max_total_size`].
+    pub fn extracted(&self) -> u64 {
+        self.extracted
+    }
+
+    /// Check that the entry at `index` can be extracted within the limits.
+    fn check_limits(&self, index: usize) -> Result<(), ZipError> {
+        let entry = self.entries.get(index).ok_or(ZipError::NoEntry)?;
+        let limits = &self.limits;
+        let size = entry.uncompressed_size;
+        if let Some(limit) = limits.max_entry_size.filter(|&limit| size > limit) {
+            return Err(ZipError::LimitExceeded(Limit::EntrySize { size, limit }));
+        }
+        let total = self.extracted.saturating_add(size);
+        if let Some(limit) = limits.max_total_size.filter(|&limit| total > limit) {
+            return Err(ZipError::LimitExceeded(Limit::TotalSize { total, limit }));
+        }
+        let compressed_size = entry.compressed_size;
+        if let Some(limit) = limits
+            .max_ratio
+            .filter(|&limit| size > compressed_size.saturating_mul(limit))
+        {
+            return Err(ZipError::LimitExceeded(Limit::Ratio {
+                compressed_size,
+                uncompressed_size: size,
+                limit,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Consume the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: Read + Seek + Send> ZipReader<R> {
+    /// Open the archive in `inner`, reading its central directory.
+    ///
+    /// Archives with data before them, like self-extracting ones, are supported whether the
+    /// offsets in the archive include that data or not.
+    pub fn new(mut inner: R) -> impl Future<Output = Result<ZipReader<R>, ZipError>> + Send {
+        async move {
+            let (entries, comment) = read_central_directory(&mut inner).await?;
+            Ok(ZipReader {
+                inner,
+                entries,
+                comment,
+                limits: ExtractLimits::default(),
+                depth: 0,
+                extracted: 0,
+            })
+        }
+    }
+
+    /// Decompress the entry at `index` to `writer`, and check its CRC-32. Encrypted entries
+    /// need [`extract_with_password`](Self::extract_with_password).
+    ///
+    /// Returns the number of bytes written.
+    pub fn extract<'a, W: Write + Send>(
+        &'a mut self,
+        index: usize,
+        writer: &'a mut W,
+    ) -> impl Future<Output = Result<u64, ZipError>> + Send + 'a {
+        async move {
+            self.check_limits(index)?;
+            let entry = &self.entries[index];
+            let size = extract_entry(&mut self.inner, entry, None, writer).await?;
+            self.extracted += size;
+            Ok(size)
+        }
+    }
+
+    /// Decrypt and decompress the entry at `index` to `writer`, and check its CRC-32.
+    ///
+    /// Entries that are not encrypted are extracted as with [`extract`](Self::extract), and
+    /// a wrong password is usually detected before anything is written.
+    ///
+    /// Returns the number of bytes written.
+    pub fn extract_with_password<'a, W: Write + Send>(
+        &'a mut self,
+        index: usize,
+        password: &'a [u8],
+        writer: &'a mut W,
+    ) -> impl Future<Output = Result<u64, ZipError>> + Send + 'a {
+        async move {
+            self.check_limits(index)?;
+            let entry = &self.entries[index];
+            let size = extract_entry(&mut self.inner, entry, Some(password), writer).await?;
+            self.extracted += size;
+            Ok(size)
+        }
+    }
+
+    /// Decompress the entry at `index`, passing the data to `callback` piece by piece, and
+    /// check its CRC-32.
+    ///
+    /// The data is passed on as it is decompressed, so it has not been checked until this
+    /// returns.
+    pub fn extract_with<'a, F: FnMut(&[u8]) + Send + 'a>(
+        &'a mut self,
+        index: usize,
+        callback: F,
+    ) -> impl Future<Output = Result<u64, ZipError>> + Send + 'a {
+        async move {
+            let mut writer = CallbackWriter(callback);
+            self.extract(index, &mut writer).await
+        }
+    }
+
+    /// Extract the entry at `index` to memory and open it as an archive, with the same
+    /// limits. The nested reader starts from the total extracted so far, including the nested
+    /// archive itself, so [`ExtractLimits::max_total_size`] holds for what is extracted
+    /// through it too.
+    ///
+    /// Fails with [`Limit::Depth`] if the nested archive would be deeper than
+    /// [`ExtractLimits::max_depth`].
+    pub fn open_nested(
+        &mut self,
+        index: usize,
+    ) -> impl Future<Output = Result<ZipReader<Cursor<Vec<u8>>>, ZipError>> + Send + '_ {
+        async move {
+            if let Some(limit) = self.limits.max_depth.filter(|&limit| self.depth >= limit) {
+                return Err(ZipError::LimitExceeded(Limit::Depth { limit }));
+            }
+            let mut data = Vec::new();
+            self.extract_with(index, |buf| data.extend_from_slice(buf))
+                .await?;
+            let mut nested = ZipReader::new(Cursor::new(data)).await?;
+            nested.limits = self.limits;
+            nested.depth = self.depth + 1;
+            nested.extracted = self.extracted;
+            Ok(nested)
+        }
+    }
+}
+
+/// List the entries of the archive in `reader`, from its central directory.
+///
+/// Only the end of the archive is read, not the data of the entries, so this is cheap even
+/// for large archives. Use [`ZipReader`] to extract the entries as well.
+pub fn list<R: Read + Seek + Send>(
+    reader: &mut R,
+) -> impl Future<Output = Result<Vec<ZipEntry>, ZipError>> + Send + '_ {
+    async move {
+        let (entries, _) = read_central_directory(reader).await?;
+        Ok(entries)
+    }
+}
+
+/// Find the end of central directory record, and read the entries and comment of the archive.
+async fn read_central_directory<R: Read + Seek + Send>(
+    inner: &mut R,
+) -> Result<(Vec<ZipEntry>, Vec<u8>), ZipError> {
+    // The record is at the end, followed by a comment of up to 65535 bytes.
+    let len = inner.seek(SeekFrom::End(0)).await?;
+    let tail_len = len.min((END_OF_CENTRAL_DIRECTORY_LEN + usize::from(u16::MAX)) as u64);
+    let tail_start = len - tail_len;
+    inner.seek(SeekFrom::Start(tail_start)).await?;
+    let mut tail = vec![0; tail_len as usize];
+    read_exact(inner, &mut tail).await?;
+
+    let eocd = (0..(tail.len() + 1).saturating_sub(END_OF_CENTRAL_DIRECTORY_LEN))
+        .rev()
+        .find(|&pos| {
+            u32_at(&tail, pos) == END_OF_CENTRAL_DIRECTORY_SIGNATURE
+                && pos + END_OF_CENTRAL_DIRECTORY_LEN + usize::from(u16_at(&tail, pos + 20))
+                    == tail.len()
+        })
+        .ok_or(ZipError::InvalidArchive(
+            "no end of central directory record",
+        ))?;
+    let record = &tail[eocd..];
+    if u16_at(record, 4) != 0 || u16_at(record, 6) != 0 {
+        return Err(ZipError::InvalidArchive(
+            "multi-disk archives are not supported",
+        ));
+    }
+    let mut count = u64::from(u16_at(record, 10));
+    let mut cd_size = u64::from(u32_at(record, 12));
+    let mut cd_offset = u64::from(u32_at(record, 16));
+    let comment = record[END_OF_CENTRAL_DIRECTORY_LEN..].to_vec();
+
+    // The central directory ends where the record starts, or the ZIP64 record if there is
+    // one, which tells how far the offsets are from the positions in the reader if there is
+    // data before the archive.
+    let mut cd_end = tail_start + eocd as u64;
+    if let Some(zip64) = read_zip64_record(inner, cd_end).await? {
+        (count, cd_size, cd_offset, cd_end) = zip64;
+    }
+    let cd_pos = cd_end
+        .checked_sub(cd_size)
+        .ok_or(ZipError::InvalidArchive("central directory out of bounds"))?;
+    let shift = cd_pos
+        .checked_sub(cd_offset)
+        .ok_or(ZipError::InvalidArchive("central directory out of bounds"))?;
+
+    inner.seek(SeekFrom::Start(cd_pos)).await?;
+    let mut directory = vec![0; cd_size as usize];
+    read_exact(inner, &mut directory).await?;
+
+    let capacity = count.min((directory.len() / CENTRAL_HEADER_LEN) as u64);
+    let mut entries = Vec::with_capacity(capacity as usize);
+    let mut pos = 0;
+    for _ in 0..count {
+        let header = directory
+            .get(pos..pos + CENTRAL_HEADER_LEN)
+            .filter(|header| u32_at(header, 0) == CENTRAL_HEADER_SIGNATURE)
+            .ok_or(ZipError::InvalidArchive("bad central directory header"))?;
+        let name_len = usize::from(u16_at(header, 28));
+        let extra_len = usize::from(u16_at(header, 30));
+        let comment_len = usize::from(u16_at(header, 32));
+        let name_start = pos + CENTRAL_HEADER_LEN;
+        let extra_start = name_start + name_len;
+        let (name, extra) = directory
+            .get(name_start..extra_start)
+            .zip(directory.get(extra_start..extra_start + extra_len))
+            .ok_or(ZipError::InvalidArchive("bad central directory header"))?;
+
+        let mut sizes = [u32_at(header, 24), u32_at(header, 20), u32_at(header, 42)].map(u64::from);
+        read_zip64_extra(extra, &mut sizes)?;
+        let [uncompressed_size, compressed_size, header_offset] = sizes;
+        let header_offset = header_offset
+            .checked_add(shift)
+            .ok_or(ZipError::InvalidArchive("local header out of bounds"))?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(header, 10),
+            flags: u16_at(header, 8),
+            modified: u32_at(header, 12),
+            crc32: u32_at(header, 16),
+            compressed_size,
+            uncompressed_size,
+            header_offset,
+        });
+        pos += CENTRAL_HEADER_LEN + name_len + extra_len + comment_len;
+    }
+    Ok((entries, comment))
+}
+
+/// Read the ZIP64 end of central directory record, if the archive has one, given the
+/// position of the end of central directory record.
+///
+/// Returns the number of entries, and the size, offset and end of the central directory.
+async fn read_zip64_record<R: Read + Seek + Send>(
+    inner: &mut R,
+    eocd_pos: u64,
+) -> Result<Option<(u64, u64, u64, u64)>, ZipError> {
+    let Some(locator_pos) = eocd_pos.checked_sub(ZIP64_LOCATOR_LEN as u64) else {
+        return Ok(None);
+    };
+    inner.seek(SeekFrom::Start(locator_pos)).await?;
+    let mut locator = [0; ZIP64_LOCATOR_LEN];
+    read_exact(inner, &mut locator).await?;
+    if u32_at(&locator, 0) != ZIP64_LOCATOR_SIGNATURE {
+        return Ok(None);
+    }
+    if u32_at(&locator, 4) != 0 || u32_at(&locator, 16) > 1 {
+        return Err(ZipError::InvalidArchive(
+            "multi-disk archives are not supported",
+        ));
+    }
+
+    // The offset of the record is wrong if there is data before the archive, but it usually
+    // comes right before the locator.
+    let offset = u64_at(&locator, 8);
+    let before = locator_pos.checked_sub(ZIP64_END_OF_CENTRAL_DIRECTORY_LEN as u64);
+    let mut record = [0; ZIP64_END_OF_CENTRAL_DIRECTORY_LEN];
+    for pos in [Some(offset), before].into_iter().flatten() {
+        if pos >= locator_pos {
+            continue;
+        }
+        inner.seek(SeekFrom::Start(pos)).await?;
+        read_exact(inner, &mut record).await?;
+        if u32_at(&record, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+            continue;
+        }
+        if u32_at(&record, 16) != 0 || u32_at(&record, 20) != 0 {
+            return Err(ZipError::InvalidArchive(
+                "multi-disk archives are not supported",
+            ));
+        }
+        let count = u64_at(&record, 32);
+        let cd_size = u64_at(&record, 40);
+        let cd_offset = u64_at(&record, 48);
+        return Ok(Some((count, cd_size, cd_offset, pos)));
+    }
+    Err(ZipError::InvalidArchive(
+        "no ZIP64 end of central directory record",
+    ))
+}
+
+/// Replace the uncompressed size, compressed size and header offset in `values` that are set
+/// to [`ZIP64_MARKER`] with the ones in the ZIP64 extra field in `extra`, if any.
+fn read_zip64_extra(mut extra: &[u8], values: &mut [u64; 3]) -> Result<(), ZipError> {
+    while extra.len() >= 4 {
+        let id = u16_at(extra, 0);
+        let len = usize::from(u16_at(extra, 2));
+        let data = extra
+            .get(4..4 + len)
+            .ok_or(ZipError::InvalidArchive("bad extra field"))?;
+        if id == ZIP64_EXTRA_ID {
+            let mut pos = 0;
+            for value in values
+                .iter_mut()
+                .filter(|value| **value == u64::from(ZIP64_MARKER))
+            {
+                *value = data
+                    .get(pos..pos + 8)
+                    .map(|_| u64_at(data, pos))
+                    .ok_or(ZipError::InvalidArchive("bad ZIP64 extra field"))?;
+                pos += 8;
+            }
+            return Ok(());
+        }
+        extra = &extra[4 + len..];
+    }
+    Ok(())
+}
+
+/// Decompress `entry` from `inner` to `writer`, checking its size and CRC-32.
+async fn extract_entry<R: Read + Seek + Send, W: Write + Send>(
+    inner: &mut R,
+    entry: &ZipEntry,
+    password: Option<&[u8]>,
+    writer: &mut W,
+) -> Result<u64, ZipError> {
+    // Deflate64 can only be read, so it is not a `CompressionMethod`.
+    let (method, window_size, format_flags) = match CompressionMethod::from_u16(entry.method) {
+        Some(method) => (method, TINFL_LZ_DICT_SIZE, 0),
+        #[cfg(feature = "deflate64")]
+        None if entry.method == METHOD_DEFLATE64 => (
+            CompressionMethod::Deflated,
+            TINFL_DEFLATE64_DICT_SIZE,
+            inflate_flags::TINFL_FLAG_DEFLATE64,
+        ),
+        None => return Err(ZipError::UnsupportedMethod(entry.method)),
+    };
+
+    // The sizes in the local header may be left out, but the lengths of the name and extra
+    // field are needed to find the data.
+    inner.seek(SeekFrom::Start(entry.header_offset)).await?;
+    let mut header = [0; LOCAL_HEADER_LEN];
+    read_exact(inner, &mut header).await?;
+    if u32_at(&header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(ZipError::InvalidArchive("bad local header"));
+    }
+    let skip = u64::from(u16_at(&header, 26)) + u64::from(u16_at(&header, 28));
+    let data_pos = entry
+        .header_offset
+        .checked_add(LOCAL_HEADER_LEN as u64 + skip)
+        .ok_or(ZipError::InvalidArchive("local header out of bounds"))?;
+    inner.seek(SeekFrom::Start(data_pos)).await?;
+
+    let mut remaining = entry.compressed_size;
+    let mut cipher = None;
+    if entry.is_encrypted() {
+        let mut crypto = ZipCrypto::new(password.ok_or(ZipError::Encrypted)?);
+        let mut header = [0; ENCRYPTION_HEADER_LEN];
+        read_exact(inner, &mut header).await?;
+        remaining = remaining
+            .checked_sub(ENCRYPTION_HEADER_LEN as u64)
+            .ok_or(ZipError::InvalidArchive("encrypted entry too short"))?;
+        crypto.decrypt(&mut header);
+        let descriptor = entry.flags & FLAG_DATA_DESCRIPTOR != 0;
+        // Only one byte is checked, so a wrong password passes 1 time in 256, and is then
+        // caught by the CRC-32.
+        if header[ENCRYPTION_HEADER_LEN - 1] != check_byte(entry.crc32, entry.modified, descriptor)
+        {
+            return Err(ZipError::InvalidPassword);
+        }
+        cipher = Some(crypto);
+    }
+
+    let mut input = vec![0; 32 * 1024];
+    let mut crc32 = 0;
+    let mut size = 0;
+
+    match method {
+        CompressionMethod::Stored => {
+            while remaining > 0 {
+                let len = remaining.min(input.len() as u64) as usize;
+                read_exact(inner, &mut input[..len]).await?;
+                if let Some(cipher) = &mut cipher {
+                    cipher.decrypt(&mut input[..len]);
+                }
+                remaining -= len as u64;
+                crc32 = update_crc32(crc32, &input[..len]);
+                size += len as u64;
+                if size > entry.uncompressed_size {
+                    return Err(ZipError::InvalidArchive("entry size mismatch"));
+                }
+                writer.write_all(&input[..len]).await?;
+            }
+        }
+        CompressionMethod::Deflated => {
+            let mut decomp = DecompressorOxide::new();
+            let mut window = vec![0; window_size];
+            let mut out_pos = 0;
+            let (mut in_pos, mut in_end) = (0, 0);
+            loop {
+                if in_pos == in_end && remaining > 0 {
+                    in_end = remaining.min(input.len() as u64) as usize;
+                    read_exact(inner, &mut input[..in_end]).await?;
+                    if let Some(cipher) = &mut cipher {
+                        cipher.decrypt(&mut input[..in_end]);
+                    }
+                    remaining -= in_end as u64;
+                    in_pos = 0;
+                }
+                let flags = if remaining > 0 {
+                    format_flags | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT
+                } else {
+                    format_flags
+                };
+                let (status, in_consumed, out_consumed) = decompress(
+                    &mut decomp,
+                    &input[in_pos..in_end],
+                    &mut window,
+                    out_pos,
+                    flags,
+                    &mut 0,
+                    &mut 0,
+                    |_v| {},
+                );
+                in_pos += in_consumed;
+                let output = &window[out_pos..out_pos + out_consumed];
+                crc32 = update_crc32(crc32, output);
+                size += output.len() as u64;
+                // Stop before writing more than the size checked against the limits.
+                if size > entry.uncompressed_size {
+                    return Err(ZipError::InvalidArchive("entry size mismatch"));
+                }
+                writer.write_all(output).await?;
+                out_pos = (out_pos + out_consumed) & (window_size - 1);
+
+                match status {
+                    TINFLStatus::Done => break,
+                    TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => {}
+                    status => return Err(ZipError::Decompress(status)),
+                }
+            }
+        }
+    }
+
+    writer.flush().await?;
+    if size != entry.uncompressed_size {
+        return Err(ZipError::InvalidArchive("entry size mismatch"));
+    }
+    if crc32 != entry.crc32 {
+        return Err(ZipError::CrcMismatch {
+            expected: entry.crc32,
+            computed: crc32,
+        });
+    }
+    Ok(size)
+}
+
+/// Fill `buf` from `inner`, failing if the end is reached first.
+async fn read_exact<R: Read + Send>(inner: &mut R, mut buf: &mut [u8]) -> Result<(), ZipError> {
+    while !buf.is_empty() {
+        let read = inner.read(buf).await?;
+        if read == 0 {
+            return Err(ZipError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        buf = &mut buf[read..];
+    }
+    Ok(())
+}
+
+/// A [`Write`] passing everything to a callback.
+struct CallbackWriter<F>(F);
+
+impl<F: FnMut(&[u8]) + Send> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        (self.0)(buf);
+        async move { Ok(buf.len()) }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { Ok(()) }
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExtractLimits, Limit, ZipReader, list};
+    use crate::zip::{EntryOptions, ZipError, ZipWriter};
+    use std::io::Cursor;
+
+    async fn test_archive(text: &[u8]) -> Vec<u8> {
+        let stored = EntryOptions::stored();
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.set_comment(b"comment");
+        writer
+            .add_entry("text.txt", text, &EntryOptions::default())
+            .await
+            .unwrap();
+        writer.add_entry("dir/", &[], &stored).await.unwrap();
+        writer
+            .add_entry("dir/stored.bin", b"stored data", &stored)
+            .await
+            .unwrap();
+        writer.finish().await.unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn read_archive() {
+        let text = crate::test_util::text(200_000);
+        let archive = test_archive(&text).await;
+
+        let mut reader = ZipReader::new(Cursor::new(&archive[..])).await.unwrap();
+        assert_eq!(reader.comment(), b"comment");
+        assert_eq!(
+            list(&mut Cursor::new(&archive[..])).await.unwrap(),
+            reader.entries()
+        );
+        let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["text.txt", "dir/", "dir/stored.bin"]);
+        assert!(reader.entries()[1].is_dir());
+        assert_eq!(reader.entries()[0].uncompressed_size, text.len() as u64);
+
+        let mut out = Cursor::new(Vec::new());
+        assert_eq!(
+            reader.extract(0, &mut out).await.unwrap(),
+            text.len() as u64
+        );
+        assert!(out.into_inner() == text);
+
+        let index = reader.index_of("dir/stored.bin").unwrap();
+        let mut data = Vec::new();
+        reader
+            .extract_with(index, |buf| data.extend_from_slice(buf))
+            .await
+            .unwrap();
+        assert_eq!(data, b"stored data");
+        assert!(matches!(
+            reader.extract(3, &mut Cursor::new(Vec::new())).await,
+            Err(ZipError::NoEntry)
+        ));
+
+        // Offsets that don't count data before the archive are found anyway.
+        let mut prefixed = b"#!/bin/sh\n".to_vec();
+        prefixed.extend_from_slice(&archive);
+        let mut reader = ZipReader::new(Cursor::new(&prefixed[..])).await.unwrap();
+        let mut data = Vec::new();
+        reader
+            .extract_with(2, |buf| data.extend_from_slice(buf))
+            .await
+            .unwrap();
+        assert_eq!(data, b"stored data");
+    }
+
+    #[tokio::test]
+    async fn zip64() {
+        let text = b"ZIP64 ".repeat(1000);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let large = EntryOptions {
+            large_file: true,
+            ..EntryOptions::default()
+        };
+        writer.add_entry("large", &text, &large).await.unwrap();
+        let empty = EntryOptions::stored();
+        for i in 0..usize::from(u16::MAX) {
+            writer
+                .add_entry(&format!("{i}"), &[], &empty)
+                .await
+                .unwrap();
+        }
+        let archive = writer.finish().await.unwrap().into_inner();
+        // The entry count doesn't fit, so it is in the ZIP64 record.
+        let eocd = archive.len() - 22;
+        assert_eq!(archive[eocd + 8..eocd + 12], [0xFF; 4]);
+        assert_eq!(archive[eocd - 20..eocd - 16], 0x0706_4B50u32.to_le_bytes());
+
+        let mut prefixed = b"#!/bin/sh\n".to_vec();
+        prefixed.extend_from_slice(&archive);
+        for archive in [&archive, &prefixed] {
+            let mut reader = ZipReader::new(Cursor::new(&archive[..])).await.unwrap();
+            assert_eq!(reader.entries().len(), 65536);
+            assert_eq!(reader.entries()[65535].name, "65534");
+            assert_eq!(reader.entries()[0].uncompressed_size, text.len() as u64);
+            let mut data = Vec::new();
+            reader
+                .extract_with(0, |buf| data.extend_from_slice(buf))
+                .await
+                .unwrap();
+            assert!(data == text);
+        }
+    }
+
+    #[tokio::test]
+    async fn corrupted_archive() {
+        let archive = test_archive(b"Some text, some text").await;
+
+        let mut corrupted = archive.clone();
+        let pos = archive
+            .windows(11)
+            .position(|w| w == b"stored data")
+            .unwrap();
+        corrupted[pos] ^= 1;
+        // Listing doesn't look at the data.
+        assert_eq!(
+            list(&mut Cursor::new(&corrupted[..])).await.unwrap().len(),
+            3
+        );
+        let mut reader = ZipReader::new(Cursor::new(&corrupted[..])).await.unwrap();
+        assert!(
+            reader
+                .extract(0, &mut Cursor::new(Vec::new()))
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            reader.extract(2, &mut Cursor::new(Vec::new())).await,
+            Err(ZipError::CrcMismatch { .. })
+        ));
+
+        assert!(matches!(
+            ZipReader::new(Cursor::new(&archive[..archive.len() - 1])).await,
+            Err(ZipError::InvalidArchive(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn encrypted() {
+        // Written by Info-ZIP `zip -P pass`, deflated then stored.
+        let archive = include_bytes!("../../tests/test_data/zipcrypto.zip");
+        let mut reader = ZipReader::new(Cursor::new(&archive[..])).await.unwrap();
+        let expected: [&[u8]; 2] = [b"secret data secret data secret data\n", b"hi"];
+        for (index, text) in expected.into_iter().enumerate() {
+            assert!(reader.entries()[index].is_encrypted());
+            assert!(matches!(
+                reader.extract(index, &mut Cursor::new(Vec::new())).await,
+                Err(ZipError::Encrypted)
+            ));
+            assert!(matches!(
+                reader
+                    .extract_with_password(index, b"wrong", &mut Cursor::new(Vec::new()))
+                    .await,
+                Err(ZipError::InvalidPassword)
+            ));
+            let mut data = Cursor::new(Vec::new());
+            reader
+                .extract_with_password(index, b"pass", &mut data)
+                .await
+                .unwrap();
+            assert_eq!(data.into_inner(), text);
+        }
+
+        let text = crate::test_util::text(50_000);
+        let password = EntryOptions {
+            password: Some(b"secret".to_vec()),
+            ..EntryOptions::default()
+        };
+        let stored = EntryOptions {
+            password: Some(b"secret".to_vec()),
+            ..EntryOptions::stored()
+        };
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_entry("deflated", &text, &password)
+            .await
+            .unwrap();
+        writer.add_entry("stored", &text, &stored).await.unwrap();
+        writer
+            .add_entry("plain", b"not encrypted", &EntryOptions::default())
+            .await
+            .unwrap();
+        let archive = writer.finish().await.unwrap().into_inner();
+        assert!(!archive.windows(10).any(|w| w == &text[..10]));
+
+        let mut reader = ZipReader::new(Cursor::new(&archive[..])).await.unwrap();
+        for index in 0..2 {
+            assert!(reader.entries()[index].is_encrypted());
+            let mut data = Cursor::new(Vec::new());
+            reader
+                .extract_with_password(index, b"secret", &mut data)
+                .await
+                .unwrap();
+            assert!(data.into_inner() == text);
+        }
+        assert!(!reader.entries()[2].is_encrypted());
+        let mut data = Cursor::new(Vec::new());
+        reader
+            .extract_with_password(2, b"secret", &mut data)
+            .await
+            .unwrap();
+        assert_eq!(data.into_inner(), b"not encrypted");
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let zeros = vec![0; 1 << 20];
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_entry("zeros", &zeros, &EntryOptions::default())
+            .await
+            .unwrap();
+        writer
+            .add_entry("small", b"small", &EntryOptions::stored())
+            .await
+            .unwrap();
+        let inner = writer.finish().await.unwrap().into_inner();
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_entry("inner.zip", &inner, &EntryOptions::stored())
+            .await
+            .unwrap();
+        let outer = writer.finish().await.unwrap().into_inner();
+
+        let mut reader = ZipReader::new(Cursor::new(&inner[..])).await.unwrap();
+        let compressed_size = reader.entries()[0].compressed_size;
+        reader.set_limits(ExtractLimits {
+            max_entry_size: Some(1000),
+            ..ExtractLimits::default()
+        });
+        assert!(matches!(
+            reader.extract_with(0, |_| {}).await,
+            Err(ZipError::LimitExceeded(Limit::EntrySize {
+                size: 1_048_576,
+                limit: 1000
+            }))
+        ));
+        reader.set_limits(ExtractLimits {
+            max_ratio: Some(100),
+            ..ExtractLimits::default()
+        });
+        let error = reader.extract_with(0, |_| {}).await.unwrap_err();
+        assert!(matches!(
+            error,
+            ZipError::LimitExceeded(Limit::Ratio { compressed_size: size, limit: 100, .. })
+                if size == compressed_size
+        ));
+        reader.set_limits(ExtractLimits {
+            max_total_size: Some(12),
+            ..ExtractLimits::default()
+        });
+        assert_eq!(reader.extract_with(1, |_| {}).await.unwrap(), 5);
+        assert_eq!(reader.extract_with(1, |_| {}).await.unwrap(), 5);
+        assert!(matches!(
+            reader.extract_with(1, |_| {}).await,
+            Err(ZipError::LimitExceeded(Limit::TotalSize {
+                total: 15,
+                limit: 12
+            }))
+        ));
+        assert_eq!(reader.extracted(), 10);
+
+        // The limits and the total carry over to nested archives.
+        let mut reader = ZipReader::new(Cursor::new(&outer[..])).await.unwrap();
+        reader.set_limits(ExtractLimits {
+            max_total_size: Some(inner.len() as u64 + 8),
+            max_depth: Some(1),
+            ..ExtractLimits::default()
+        });
+        let mut nested = reader.open_nested(0).await.unwrap();
+        assert_eq!(nested.extracted(), inner.len() as u64);
+        assert!(nested.extract_with(1, |_| {}).await.is_ok());
+        assert!(matches!(
+            nested.extract_with(1, |_| {}).await,
+            Err(ZipError::LimitExceeded(Limit::TotalSize { .. }))
+        ));
+        assert!(matches!(
+            nested.open_nested(0).await,
+            Err(ZipError::LimitExceeded(Limit::Depth { limit: 1 }))
+        ));
+
+        // An entry that decompresses to more than its size stops there.
+        let mut lying = inner.clone();
+        let central = central_offset(&lying);
+        lying[central + 24..central + 28].copy_from_slice(&1000u32.to_le_bytes());
+        let mut reader = ZipReader::new(Cursor::new(&lying[..])).await.unwrap();
+        reader.set_limits(ExtractLimits {
+            max_entry_size: Some(1000),
+            ..ExtractLimits::default()
+        });
+        let mut written = 0;
+        assert!(matches!(
+            reader.extract_with(0, |buf| written += buf.len()).await,
+            Err(ZipError::InvalidArchive(_))
+        ));
+        assert!(written <= 1000);
+
+        // So does a stored entry with more data than its size.
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_entry("zeros", &zeros, &EntryOptions::stored())
+            .await
+            .unwrap();
+        let mut lying = writer.finish().await.unwrap().into_inner();
+        let central = central_offset(&lying);
+        lying[central + 24..central + 28].copy_from_slice(&1000u32.to_le_bytes());
+        let mut reader = ZipReader::new(Cursor::new(&lying[..])).await.unwrap();
+        let mut written = 0;
+        assert!(matches!(
+            reader.extract_with(0, |buf| written += buf.len()).await,
+            Err(ZipError::InvalidArchive(_))
+        ));
+        assert!(written <= 1000);
+    }
+
+    /// Get the offset of the central directory from the end of central directory record.
+    fn central_offset(archive: &[u8]) -> usize {
+        let eocd = archive.len() - 22;
+        u32::from_le_bytes(archive[eocd + 16..eocd + 20].try_into().unwrap()) as usize
+    }
+}
@@ -0,0 +1,113 @@
+//! Recompressing zip archives.
+
+use binrw::io::read::Read;
+use binrw::io::seek::Seek;
+use binrw::io::write::Write;
+
+use super::{CompressionMethod, EntryOptions, ZipError, ZipReader, ZipWriter};
+
+/// Copy every entry of the archive in `reader` to a new archive written to `writer`,
+/// compressing the data again with the method, level and strategy of `options`.
+///
+/// Each entry is decompressed straight into the compressor, so only a window of its data is
+/// held in memory. The names, modification times and archive comment are kept, and the
+/// CRC-32 and sizes are written anew; the CRC-32 of the original data is checked as it is
+/// read. Directories are stored, as they have no data.
+///
+/// Returns the inner writer once the central directory is written.
+pub fn recompress<'a, R: Read + Seek + Send, W: Write + Seek + Send + 'a>(
+    reader: &'a mut ZipReader<R>,
+    writer: W,
+    options: &'a EntryOptions,
+) -> impl Future<Output = Result<W, ZipError>> + Send + 'a {
+    async move {
+        let mut writer = ZipWriter::new(writer);
+        writer.set_comment(reader.comment());
+        for index in 0..reader.entries().len() {
+            let entry = &reader.entries()[index];
+            let mut entry_options = EntryOptions {
+                modified: entry.modified,
+                large_file: options.large_file || entry.uncompressed_size > u64::from(u32::MAX),
+                ..options.clone()
+            };
+            if entry.is_dir() {
+                entry_options.method = CompressionMethod::Stored;
+            }
+            let name = entry.name.clone();
+            writer.start_entry(&name, &entry_options).await?;
+            reader.extract(index, &mut writer).await?;
+        }
+        writer.finish().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::recompress;
+    use crate::deflate::core::CompressionStrategy;
+    use crate::zip::{CompressionMethod, EntryOptions, ZipReader, ZipWriter};
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn recompress_archive() {
+        let text = crate::test_util::text(300_000);
+        let stored = EntryOptions {
+            modified: 0x5A21_6000,
+            ..EntryOptions::stored()
+        };
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.set_comment(b"comment");
+        writer
+            .add_entry("stored.txt", &text, &stored)
+            .await
+            .unwrap();
+        writer.add_entry("dir/", &[], &stored).await.unwrap();
+        let fast = EntryOptions {
+            level: 1,
+            ..EntryOptions::default()
+        };
+        writer
+            .add_entry("dir/fast.txt", &text, &fast)
+            .await
+            .unwrap();
+        let archive = writer.finish().await.unwrap().into_inner();
+
+        for strategy in [CompressionStrategy::Default, CompressionStrategy::RLE] {
+            let mut reader = ZipReader::new(Cursor::new(&archive[..])).await.unwrap();
+            let options = EntryOptions {
+                level: 9,
+                strategy,
+                ..EntryOptions::default()
+            };
+            let output = recompress(&mut reader, Cursor::new(Vec::new()), &options)
+                .await
+                .unwrap()
+                .into_inner();
+
+            let mut recompressed = ZipReader::new(Cursor::new(&output[..])).await.unwrap();
+            assert_eq!(recompressed.comment(), b"comment");
+            for (old, new) in reader.entries().iter().zip(recompressed.entries()) {
+                assert_eq!((&old.name, old.modified), (&new.name, new.modified));
+                assert_eq!(old.crc32, new.crc32);
+                assert_eq!(old.uncompressed_size, new.uncompressed_size);
+            }
+            let entries = recompressed.entries();
+            assert_eq!(entries[0].method, CompressionMethod::Deflated.to_u16());
+            assert_eq!(entries[1].method, CompressionMethod::Stored.to_u16());
+            assert!(entries[0].compressed_size < text.len() as u64);
+            if strategy == CompressionStrategy::Default {
+                assert!(entries[0].compressed_size < text.len() as u64 / 4);
+                assert!(entries[2].compressed_size < reader.entries()[2].compressed_size);
+            }
+
+            for index in [0, 2] {
+                let mut data = Vec::new();
+                recompressed
+                    .extract_with(index, |buf| data.extend_from_slice(buf))
+                    .await
+                    .unwrap();
+                assert!(data == text);
+            }
+        }
+    }
+}
@@ -0,0 +1,914 @@
+//! Writing zip archives.
+
+use crate::alloc::boxed::Box;
+use crate::alloc::string::String;
+use crate::alloc::vec;
+use crate::alloc::vec::Vec;
+use binrw::io::seek::Seek;
+use binrw::io::write::Write;
+use std::io::SeekFrom;
+
+use super::crypto::{ENCRYPTION_HEADER_LEN, ZipCrypto, check_byte};
+use super::{
+    CENTRAL_HEADER_LEN, CENTRAL_HEADER_SIGNATURE, CompressionMethod, DATA_DESCRIPTOR_SIGNATURE,
+    DOS_EPOCH, END_OF_CENTRAL_DIRECTORY_LEN, END_OF_CENTRAL_DIRECTORY_SIGNATURE,
+    FLAG_DATA_DESCRIPTOR, FLAG_ENCRYPTED, FLAG_UTF8, LOCAL_HEADER_LEN, LOCAL_HEADER_SIGNATURE,
+    VERSION_DEFLATE, VERSION_STORED, VERSION_ZIP64, ZIP64_COUNT_MARKER,
+    ZIP64_END_OF_CENTRAL_DIRECTORY_LEN, ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE, ZIP64_EXTRA_ID,
+    ZIP64_LOCATOR_LEN, ZIP64_LOCATOR_SIGNATURE, ZIP64_MARKER, ZipError,
+};
+use crate::deflate::core::{
+    CompressionStrategy, CompressorOxide, create_comp_flags_from_zip_params,
+};
+use crate::deflate::stream::deflate;
+use crate::shared::update_crc32;
+use crate::{DataFormat, MZFlush, MZStatus};
+
+/// Offset of the CRC-32 in the local file header, followed by the sizes.
+const LOCAL_HEADER_CRC_OFFSET: u64 = 14;
+/// Size of the ZIP64 extra field in local headers, with both sizes.
+const LOCAL_ZIP64_EXTRA_LEN: usize = 20;
+
+/// Settings for an entry added to a [`ZipWriter`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntryOptions {
+    /// How to compress the data.
+    pub method: CompressionMethod,
+    /// The compression level (0-10) for [`CompressionMethod::Deflated`].
+    pub level: u8,
+    /// The compression strategy for [`CompressionMethod::Deflated`].
+    pub strategy: CompressionStrategy,
+    /// The modification time, as an MS-DOS date in the high 16 bits and time in the low 16
+    /// bits.
+    pub modified: u32,
+    /// Write the sizes of the entry in a ZIP64 extra field, so it can be larger than 4 GiB.
+    ///
+    /// The local header is written before the size is known, so this has to be set for
+    /// entries that may be that large. [`ZipWriter::add_entry`] sets it when needed.
+    pub large_file: bool,
+    /// Encrypt the entry with this password, using the traditional PKWARE cipher.
+    ///
+    /// The cipher is weak and only protects against casual access; it is here for tools
+    /// that can't read anything else. Encrypted entries always have a data descriptor, as
+    /// the password check in the encryption header uses the modification time instead of
+    /// the CRC-32 then.
+    pub password: Option<Vec<u8>>,
+}
+
+impl EntryOptions {
+    /// Options for an entry stored without compression, for data that is already compressed,
+    /// like images and videos, where deflate only wastes time.
+    ///
+    /// The CRC-32 is still computed as the data is written.
+    pub fn stored() -> Self {
+        EntryOptions {
+            method: CompressionMethod::Stored,
+            ..EntryOptions::default()
+        }
+    }
+}
+
+impl Default for EntryOptions {
+    fn default() -> Self {
+        EntryOptions {
+            method: CompressionMethod::Deflated,
+            level: 6,
+            strategy: CompressionStrategy::Default,
+            modified: DOS_EPOCH,
+            large_file: false,
+            password: None,
+        }
+    }
+}
+
+/// What the central directory needs to know about an entry.
+struct EntryRecord {
+    name: String,
+    method: CompressionMethod,
+    modified: u32,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    header_offset: u64,
+    /// The local header has a ZIP64 extra field for the sizes.
+    zip64: bool,
+    /// The CRC-32 and sizes are in a data descriptor after the data.
+    descriptor: bool,
+    /// The data is encrypted.
+    encrypted: bool,
+}
+
+/// The state shared by [`ZipWriter`] and [`ZipStreamWriter`]: the entries written so far,
+/// the one being written and its compressor.
+///
+/// The bytes written are counted, so the offsets are known without asking the output.
+struct Archive {
+    entries: Vec<EntryRecord>,
+    /// The entry being written.
+    current: Option<EntryRecord>,
+    compressor: Box<CompressorOxide>,
+    buffer: Vec<u8>,
+    /// The cipher of the entry being written, if it is encrypted.
+    cipher: Option<ZipCrypto>,
+    comment: Vec<u8>,
+    /// Position in the output of the next byte written.
+    position: u64,
+    /// Write a data descriptor after each entry, instead of leaving the local header to be
+    /// filled in.
+    descriptors: bool,
+}
+
+impl Archive {
+    fn new(descriptors: bool) -> Self {
+        Archive {
+            entries: Vec::new(),
+            current: None,
+            compressor: Box::default(),
+            buffer: vec![0; 32 * 1024],
+            cipher: None,
+            comment: Vec::new(),
+            position: 0,
+            descriptors,
+        }
+    }
+
+    fn set_comment(&mut self, comment: &[u8]) {
+        self.comment = comment[..comment.len().min(usize::from(u16::MAX))].to_vec();
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() + usize::from(self.current.is_some())
+    }
+
+    async fn write<W: Write + Send>(&mut self, inner: &mut W, data: &[u8]) -> Result<(), ZipError> {
+        inner.write_all(data).await?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write the local header of a new entry. The previous one has to be finished.
+    async fn start_entry<W: Write + Send>(
+        &mut self,
+        inner: &mut W,
+        name: &str,
+        options: &EntryOptions,
+    ) -> Result<(), ZipError> {
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| ZipError::TooLarge("entry name longer than 65535 bytes"))?;
+
+        let mut record = EntryRecord {
+            name: name.into(),
+            method: options.method,
+            modified: options.modified,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            header_offset: self.position,
+            zip64: options.large_file,
+            descriptor: self.descriptors || options.password.is_some(),
+            encrypted: options.password.is_some(),
+        };
+        // The CRC-32 and sizes are filled in or written in a data descriptor once the entry
+        // is done.
+        let extra_len = if record.zip64 {
+            LOCAL_ZIP64_EXTRA_LEN
+        } else {
+            0
+        };
+        let mut header = Vec::with_capacity(LOCAL_HEADER_LEN + name.len() + extra_len);
+        put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut header, version_needed(&record));
+        put_u16(&mut header, flags(&record));
+        put_u16(&mut header, record.method.to_u16());
+        put_u32(&mut header, record.modified);
+        put_u32(&mut header, 0);
+        let size = if record.zip64 { ZIP64_MARKER } else { 0 };
+        put_u32(&mut header, size);
+        put_u32(&mut header, size);
+        put_u16(&mut header, name_len);
+        put_u16(&mut header, extra_len as u16);
+        header.extend_from_slice(name.as_bytes());
+        if record.zip64 {
+            put_u16(&mut header, ZIP64_EXTRA_ID);
+            put_u16(&mut header, 16);
+            header.extend_from_slice(&[0; 16]);
+        }
+        self.write(inner, &header).await?;
+
+        self.cipher = None;
+        if let Some(password) = &options.password {
+            let mut cipher = ZipCrypto::new(password);
+            let header = cipher.encrypt_header(check_byte(0, record.modified, true));
+            self.write(inner, &header).await?;
+            record.compressed_size = ENCRYPTION_HEADER_LEN as u64;
+            self.cipher = Some(cipher);
+        }
+
+        if record.method == CompressionMethod::Deflated {
+            self.compressor.reset();
+            self.compressor
+                .change_flags(create_comp_flags_from_zip_params(
+                    options.level.into(),
+                    DataFormat::Raw.to_window_bits(),
+                    options.strategy as i32,
+                ));
+        }
+        self.current = Some(record);
+        Ok(())
+    }
+
+    /// Compress, encrypt if needed, and write `data` to the current entry.
+    async fn write_entry_data<W: Write + Send>(
+        &mut self,
+        inner: &mut W,
+        mut data: &[u8],
+    ) -> Result<(), ZipError> {
+        let entry = self.current.as_mut().ok_or(ZipError::NoEntry)?;
+        entry.crc32 = update_crc32(entry.crc32, data);
+        entry.uncompressed_size += data.len() as u64;
+
+        match (entry.method, &mut self.cipher) {
+            (CompressionMethod::Stored, None) => {
+                entry.compressed_size += data.len() as u64;
+                inner.write_all(data).await?;
+                self.position += data.len() as u64;
+            }
+            (CompressionMethod::Stored, Some(cipher)) => {
+                // The data is encrypted in a copy, a piece at a time.
+                while !data.is_empty() {
+                    let len = data.len().min(self.buffer.len());
+                    let piece = &mut self.buffer[..len];
+                    piece.copy_from_slice(&data[..len]);
+                    cipher.encrypt(piece);
+                    data = &data[len..];
+                    entry.compressed_size += len as u64;
+                    inner.write_all(piece).await?;
+                    self.position += len as u64;
+                }
+            }
+            (CompressionMethod::Deflated, mut cipher) => {
+                while !data.is_empty() {
+                    let res = deflate(&mut self.compressor, data, &mut self.buffer, MZFlush::None);
+                    res.status.map_err(ZipError::Compress)?;
+                    data = &data[res.bytes_consumed..];
+                    entry.compressed_size += res.bytes_written as u64;
+                    if let Some(cipher) = &mut cipher {
+                        cipher.encrypt(&mut self.buffer[..res.bytes_written]);
+                    }
+                    inner.write_all(&self.buffer[..res.bytes_written]).await?;
+                    self.position += res.bytes_written as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the end of the current entry, and its data descriptor if it has one.
+    ///
+    /// Returns the entry, if there was one, for its local header to be filled in.
+    async fn end_entry<W: Write + Send>(
+        &mut self,
+        inner: &mut W,
+    ) -> Result<Option<&EntryRecord>, ZipError> {
+        let Some(mut record) = self.current.take() else {
+            return Ok(None);
+        };
+
+        if record.method == CompressionMethod::Deflated {
+            loop {
+                let res = deflate(&mut self.compressor, &[], &mut self.buffer, MZFlush::Finish);
+                let status = res.status.map_err(ZipError::Compress)?;
+                record.compressed_size += res.bytes_written as u64;
+                if let Some(cipher) = &mut self.cipher {
+                    cipher.encrypt(&mut self.buffer[..res.bytes_written]);
+                }
+                inner.write_all(&self.buffer[..res.bytes_written]).await?;
+                self.position += res.bytes_written as u64;
+                if status == MZStatus::StreamEnd {
+                    break;
+                }
+            }
+        }
+
+        let too_large = |size| zip64_field(size, false).is_err();
+        if !record.zip64
+            && (too_large(record.compressed_size) || too_large(record.uncompressed_size))
+        {
+            return Err(ZipError::TooLarge(
+                "entry larger than 4 GiB without large_file",
+            ));
+        }
+
+        if record.descriptor {
+            let mut descriptor = Vec::with_capacity(24);
+            put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+            put_u32(&mut descriptor, record.crc32);
+            if record.zip64 {
+                put_u64(&mut descriptor, record.compressed_size);
+                put_u64(&mut descriptor, record.uncompressed_size);
+            } else {
+                put_u32(&mut descriptor, record.compressed_size as u32);
+                put_u32(&mut descriptor, record.uncompressed_size as u32);
+            }
+            self.write(inner, &descriptor).await?;
+        }
+
+        self.entries.push(record);
+        Ok(self.entries.last())
+    }
+
+    /// Write the central directory, starting at the current position.
+    async fn write_directory<W: Write + Send>(&mut self, inner: &mut W) -> Result<(), ZipError> {
+        let cd_offset = self.position;
+
+        let mut directory = Vec::with_capacity(self.entries.len() * CENTRAL_HEADER_LEN);
+        for entry in &self.entries {
+            // The values that don't fit, in this order, go in the ZIP64 extra field. The
+            // sizes are always there if the local header has them.
+            let uncompressed_size = zip64_field(entry.uncompressed_size, entry.zip64);
+            let compressed_size = zip64_field(entry.compressed_size, entry.zip64);
+            let header_offset = zip64_field(entry.header_offset, false);
+            let mut extra = Vec::new();
+            for value in [uncompressed_size, compressed_size, header_offset] {
+                if let Err(value) = value {
+                    put_u64(&mut extra, value);
+                }
+            }
+            let zip64 = !extra.is_empty();
+
+            put_u32(&mut directory, CENTRAL_HEADER_SIGNATURE);
+            // Made by the version needed on MS-DOS, which has the attributes below.
+            let version = if zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFLATE
+            };
+            put_u16(&mut directory, version);
+            put_u16(
+                &mut directory,
+                if zip64 {
+                    VERSION_ZIP64
+                } else {
+                    version_needed(entry)
+                },
+            );
+            put_u16(&mut directory, flags(entry));
+            put_u16(&mut directory, entry.method.to_u16());
+            put_u32(&mut directory, entry.modified);
+            put_u32(&mut directory, entry.crc32);
+            put_u32(&mut directory, compressed_size.unwrap_or(ZIP64_MARKER));
+            put_u32(&mut directory, uncompressed_size.unwrap_or(ZIP64_MARKER));
+            put_u16(&mut directory, entry.name.len() as u16);
+            let extra_len = if zip64 { 4 + extra.len() } else { 0 };
+            put_u16(&mut directory, extra_len as u16);
+            // Comment, disk number, internal and external attributes.
+            directory.extend_from_slice(&[0; 10]);
+            put_u32(&mut directory, header_offset.unwrap_or(ZIP64_MARKER));
+            directory.extend_from_slice(entry.name.as_bytes());
+            if zip64 {
+                put_u16(&mut directory, ZIP64_EXTRA_ID);
+                put_u16(&mut directory, extra.len() as u16);
+                directory.extend_from_slice(&extra);
+            }
+        }
+        let cd_size = directory.len() as u64;
+        let count = self.entries.len() as u64;
+
+        let count16 = u16::try_from(count)
+            .ok()
+            .filter(|&count| count != ZIP64_COUNT_MARKER);
+        let cd_size32 = zip64_field(cd_size, false);
+        let cd_offset32 = zip64_field(cd_offset, false);
+        if count16.is_none() || cd_size32.is_err() || cd_offset32.is_err() {
+            let record_offset = cd_offset + cd_size;
+            directory.reserve(ZIP64_END_OF_CENTRAL_DIRECTORY_LEN + ZIP64_LOCATOR_LEN);
+            put_u32(&mut directory, ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+            // The size of the rest of the record.
+            put_u64(
+                &mut directory,
+                ZIP64_END_OF_CENTRAL_DIRECTORY_LEN as u64 - 12,
+            );
+            put_u16(&mut directory, VERSION_ZIP64);
+            put_u16(&mut directory, VERSION_ZIP64);
+            // Number of this disk and of the disk with the central directory.
+            put_u32(&mut directory, 0);
+            put_u32(&mut directory, 0);
+            put_u64(&mut directory, count);
+            put_u64(&mut directory, count);
+            put_u64(&mut directory, cd_size);
+            put_u64(&mut directory, cd_offset);
+
+            put_u32(&mut directory, ZIP64_LOCATOR_SIGNATURE);
+            // The disk with the record, its offset and the number of disks.
+            put_u32(&mut directory, 0);
+            put_u64(&mut directory, record_offset);
+            put_u32(&mut directory, 1);
+        }
+
+        let count = count16.unwrap_or(ZIP64_COUNT_MARKER);
+        directory.reserve(END_OF_CENTRAL_DIRECTORY_LEN + self.comment.len());
+        put_u32(&mut directory, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        // Number of this disk and of the disk with the central directory.
+        put_u32(&mut directory, 0);
+        put_u16(&mut directory, count);
+        put_u16(&mut directory, count);
+        put_u32(&mut directory, cd_size32.unwrap_or(ZIP64_MARKER));
+        put_u32(&mut directory, cd_offset32.unwrap_or(ZIP64_MARKER));
+        put_u16(&mut directory, self.comment.len() as u16);
+        directory.extend_from_slice(&self.comment);
+        self.write(inner, &directory).await?;
+        inner.flush().await?;
+        Ok(())
+    }
+}
+
+/// Writes a zip archive to `W`, one entry at a time.
+///
+/// Entries are started with [`start_entry`](Self::start_entry), and their data written with
+/// [`write`](Write::write); or added in one go with [`add_entry`](Self::add_entry). Nothing
+/// is valid until [`finish`](Self::finish) writes the central directory.
+///
+/// The sizes and CRC-32 of each entry are filled in to its local header once it is done, so
+/// `W` has to be seekable. Use [`ZipStreamWriter`] otherwise.
+///
+/// The ZIP64 extensions are used where needed for offsets past 4 GiB and more than 65535
+/// entries, and for entries with [`EntryOptions::large_file`] set.
+pub struct ZipWriter<W> {
+    inner: W,
+    archive: Archive,
+    /// The position of `inner` was read into `archive`.
+    located: bool,
+}
+
+impl<W> ZipWriter<W> {
+    /// Create a writer for an archive starting at the current position of `inner`.
+    ///
+    /// Offsets in the archive are positions in `inner`, so an archive written after other
+    /// data, like the program of a self-extracting archive, is valid as is.
+    pub fn new(inner: W) -> Self {
+        ZipWriter {
+            inner,
+            archive: Archive::new(false),
+            located: false,
+        }
+    }
+
+    /// Set the comment of the archive, written at the very end. Only the first 65535 bytes
+    /// are written.
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        self.archive.set_comment(comment);
+    }
+
+    /// Get the number of entries added so far, including the one being written.
+    pub fn len(&self) -> usize {
+        self.archive.len()
+    }
+
+    /// Returns `true` if no entry was added yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: Write + Seek + Send> ZipWriter<W> {
+    /// Start a new entry called `name`, finishing the previous one if any.
+    ///
+    /// Use `/` to separate directories in `name`, and end it with `/` for a directory.
+    pub fn start_entry<'a>(
+        &'a mut self,
+        name: &'a str,
+        options: &'a EntryOptions,
+    ) -> impl Future<Output = Result<(), ZipError>> + Send + 'a {
+        async move {
+            self.finish_entry().await?;
+            self.locate().await?;
+            self.archive
+                .start_entry(&mut self.inner, name, options)
+                .await
+        }
+    }
+
+    /// Add an entry called `name` with `data`, finishing the previous one if any.
+    pub fn add_entry<'a>(
+        &'a mut self,
+        name: &'a str,
+        data: &'a [u8],
+        options: &'a EntryOptions,
+    ) -> impl Future<Output = Result<(), ZipError>> + Send + 'a {
+        async move {
+            let options = EntryOptions {
+                large_file: options.large_file || may_exceed_u32(data.len() as u64),
+                ..options.clone()
+            };
+            self.start_entry(name, &options).await?;
+            self.archive.write_entry_data(&mut self.inner, data).await?;
+            self.finish_entry().await
+        }
+    }
+
+    /// Finish the last entry and write the central directory, returning the inner writer.
+    pub fn finish(mut self) -> impl Future<Output = Result<W, ZipError>> + Send {
+        async move {
+            self.finish_entry().await?;
+            self.locate().await?;
+            self.archive.write_directory(&mut self.inner).await?;
+            Ok(self.inner)
+        }
+    }
+
+    /// Read the position of `inner` where the archive starts, the first time.
+    async fn locate(&mut self) -> Result<(), ZipError> {
+        if !self.located {
+            self.archive.position = self.inner.seek(SeekFrom::Current(0)).await?;
+            self.located = true;
+        }
+        Ok(())
+    }
+
+    /// Write the end of the current entry, if any, and fill in its local header unless it has
+    /// a data descriptor.
+    async fn finish_entry(&mut self) -> Result<(), ZipError> {
+        let Some(record) = self.archive.end_entry(&mut self.inner).await? else {
+            return Ok(());
+        };
+        if record.descriptor {
+            return Ok(());
+        }
+
+        let crc_pos = record.header_offset + LOCAL_HEADER_CRC_OFFSET;
+        let mut fields = Vec::with_capacity(16);
+        if record.zip64 {
+            put_u32(&mut fields, record.crc32);
+            self.inner.seek(SeekFrom::Start(crc_pos)).await?;
+            self.inner.write_all(&fields).await?;
+
+            // The sizes go in the extra field, after its id and size.
+            fields.clear();
+            put_u64(&mut fields, record.uncompressed_size);
+            put_u64(&mut fields, record.compressed_size);
+            let extra_pos = record.header_offset + (LOCAL_HEADER_LEN + record.name.len()) as u64;
+            self.inner.seek(SeekFrom::Start(extra_pos + 4)).await?;
+            self.inner.write_all(&fields).await?;
+        } else {
+            // `end_entry` checked that the sizes fit.
+            put_u32(&mut fields, record.crc32);
+            put_u32(&mut fields, record.compressed_size as u32);
+            put_u32(&mut fields, record.uncompressed_size as u32);
+            self.inner.seek(SeekFrom::Start(crc_pos)).await?;
+            self.inner.write_all(&fields).await?;
+        }
+        self.inner
+            .seek(SeekFrom::Start(self.archive.position))
+            .await?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek + Send> Write for ZipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            self.archive.write_entry_data(&mut self.inner, buf).await?;
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { self.inner.flush().await }
+    }
+}
+
+/// Writes a zip archive to `W` from start to end, without seeking, so it can go to a pipe or
+/// a socket.
+///
+/// It works like [`ZipWriter`], but the CRC-32 and sizes of each entry are written in a data
+/// descriptor after its data rather than in its local header, which has bit 3 of the flags
+/// set. Readers that use the central directory, like [`ZipReader`](super::ZipReader), don't
+/// see a difference, but some streaming readers can't find the end of stored entries.
+///
+/// Offsets in the archive start from the first byte written to `W`. As the local header is
+/// written first, [`EntryOptions::large_file`] has to be set for entries that may be larger
+/// than 4 GiB.
+pub struct ZipStreamWriter<W> {
+    inner: W,
+    archive: Archive,
+}
+
+impl<W> ZipStreamWriter<W> {
+    /// Create a writer for an archive starting with the next byte written to `inner`.
+    pub fn new(inner: W) -> Self {
+        ZipStreamWriter {
+            inner,
+            archive: Archive::new(true),
+        }
+    }
+
+    /// Set the comment of the archive, written at the very end. Only the first 65535 bytes
+    /// are written.
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        self.archive.set_comment(comment);
+    }
+
+    /// Get the number of entries added so far, including the one being written.
+    pub fn len(&self) -> usize {
+        self.archive.len()
+    }
+
+    /// Returns `true` if no entry was added yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of bytes written to the inner writer so far.
+    pub fn written(&self) -> u64 {
+        self.archive.position
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: Write + Send> ZipStreamWriter<W> {
+    /// Start a new entry called `name`, finishing the previous one if any.
+    ///
+    /// Use `/` to separate directories in `name`, and end it with `/` for a directory.
+    pub fn start_entry<'a>(
+        &'a mut self,
+        name: &'a str,
+        options: &'a EntryOptions,
+    ) -> impl Future<Output = Result<(), ZipError>> + Send + 'a {
+        async move {
+            self.archive.end_entry(&mut self.inner).await?;
+            self.archive
+                .start_entry(&mut self.inner, name, options)
+                .await
+        }
+    }
+
+    /// Add an entry called `name` with `data`, finishing the previous one if any.
+    pub fn add_entry<'a>(
+        &'a mut self,
+        name: &'a str,
+        data: &'a [u8],
+        options: &'a EntryOptions,
+    ) -> impl Future<Output = Result<(), ZipError>> + Send + 'a {
+        async move {
+            let options = EntryOptions {
+                large_file: options.large_file || may_exceed_u32(data.len() as u64),
+                ..options.clone()
+            };
+            self.start_entry(name, &options).await?;
+            self.archive.write_entry_data(&mut self.inner, data).await?;
+            self.archive.end_entry(&mut self.inner).await?;
+            Ok(())
+        }
+    }
+
+    /// Finish the last entry and write the central directory, returning the inner writer.
+    pub fn finish(mut self) -> impl Future<Output = Result<W, ZipError>> + Send {
+        async move {
+            self.archive.end_entry(&mut self.inner).await?;
+            self.archive.write_directory(&mut self.inner).await?;
+            Ok(self.inner)
+        }
+    }
+}
+
+impl<W: Write + Send> Write for ZipStreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+        async move {
+            self.archive.write_entry_data(&mut self.inner, buf).await?;
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move { self.inner.flush().await }
+    }
+}
+
+/// The version needed to extract the entry, as written in its local header.
+const fn version_needed(entry: &EntryRecord) -> u16 {
+    if entry.zip64 {
+        return VERSION_ZIP64;
+    }
+    if entry.descriptor {
+        return VERSION_DEFLATE;
+    }
+    match entry.method {
+        CompressionMethod::Stored => VERSION_STORED,
+        CompressionMethod::Deflated => VERSION_DEFLATE,
+    }
+}
+
+/// Get `value` as a 32-bit field, or `Err(value)` if it has to go in the ZIP64 extra field
+/// instead, because it doesn't fit or `force` is set.
+fn zip64_field(value: u64, force: bool) -> Result<u32, u64> {
+    u32::try_from(value)
+        .ok()
+        .filter(|&value| value != ZIP64_MARKER && !force)
+        .ok_or(value)
+}
+
+/// Returns `true` if `len` bytes of data may take 4 GiB or more once compressed.
+///
+/// Deflate adds at most 5 bytes to each 65535 bytes of data that doesn't compress, and a few
+/// more at the end.
+fn may_exceed_u32(len: u64) -> bool {
+    len + len / 65535 * 5 + 64 >= u64::from(ZIP64_MARKER)
+}
+
+/// The general purpose flags of an entry.
+fn flags(entry: &EntryRecord) -> u16 {
+    let mut flags = 0;
+    if !entry.name.is_ascii() {
+        flags |= FLAG_UTF8;
+    }
+    if entry.encrypted {
+        flags |= FLAG_ENCRYPTED;
+    }
+    if entry.descriptor {
+        flags |= FLAG_DATA_DESCRIPTOR;
+    }
+    flags
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EntryOptions, ZipStreamWriter, ZipWriter};
+    use crate::inflate::decompress_to_vec;
+    use crate::shared::update_crc32;
+    use crate::zip::ZipReader;
+    use binrw::io::write::Write;
+    use std::io::Cursor;
+
+    /// An output that can only be written to, like a pipe.
+    struct Pipe(Vec<u8>);
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> impl Future<Output = std::io::Result<usize>> + Send {
+            async move {
+                self.0.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+
+        fn flush(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+            async move { Ok(()) }
+        }
+    }
+
+    fn u16_at(data: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[tokio::test]
+    async fn write_archive() {
+        let text = b"zip writer ".repeat(10_000);
+        let stored = EntryOptions::stored();
+
+        // Start after some bytes, like an archive appended to another file.
+        let mut prefixed = Cursor::new(b"prefix".to_vec());
+        prefixed.set_position(6);
+        let mut writer = ZipWriter::new(prefixed);
+        writer.set_comment(b"archive comment");
+        writer
+            .add_entry("text.txt", &text, &EntryOptions::default())
+            .await
+            .unwrap();
+        writer.add_entry("dir/", &[], &stored).await.unwrap();
+        writer
+            .start_entry("dir/stored-é.bin", &stored)
+            .await
+            .unwrap();
+        writer.write_all(b"stored ").await.unwrap();
+        writer.write_all(b"data").await.unwrap();
+        assert_eq!(writer.len(), 3);
+        let archive = &writer.finish().await.unwrap().into_inner()[..];
+        assert_eq!(&archive[..6], b"prefix");
+
+        // Walk the central directory and check each entry against its local header.
+        let eocd = archive.len() - 22 - 15;
+        assert_eq!(u32_at(archive, eocd), 0x0605_4B50);
+        assert_eq!(u16_at(archive, eocd + 10), 3);
+        assert_eq!(&archive[eocd + 22..], b"archive comment");
+        let mut pos = u32_at(archive, eocd + 16) as usize;
+        let expected: [(&str, &[u8], u16); 3] = [
+            ("text.txt", &text, 8),
+            ("dir/", b"", 0),
+            ("dir/stored-é.bin", b"stored data", 0),
+        ];
+        for (name, data, method) in expected {
+            assert_eq!(u32_at(archive, pos), 0x0201_4B50);
+            let name_len = u16_at(archive, pos + 28) as usize;
+            assert_eq!(&archive[pos + 46..pos + 46 + name_len], name.as_bytes());
+            assert_eq!(
+                u16_at(archive, pos + 8),
+                if name.is_ascii() { 0 } else { 1 << 11 }
+            );
+            assert_eq!(u16_at(archive, pos + 10), method);
+            let crc = u32_at(archive, pos + 16);
+            let compressed_size = u32_at(archive, pos + 20) as usize;
+            assert_eq!(crc, update_crc32(0, data));
+            assert_eq!(u32_at(archive, pos + 24) as usize, data.len());
+
+            let local = u32_at(archive, pos + 42) as usize;
+            assert_eq!(u32_at(archive, local), 0x0403_4B50);
+            assert_eq!(archive[local + 14..local + 26], archive[pos + 16..pos + 28]);
+            let start = local + 30 + name_len;
+            let contents = &archive[start..start + compressed_size];
+            if method == 8 {
+                assert!(compressed_size < data.len() / 10);
+                assert_eq!(decompress_to_vec(contents).unwrap(), data);
+            } else {
+                assert_eq!(contents, data);
+            }
+            pos += 46 + name_len;
+        }
+        assert_eq!(pos, eocd);
+    }
+
+    #[tokio::test]
+    async fn stream_archive() {
+        let text = b"zip stream ".repeat(10_000);
+        let large = EntryOptions {
+            large_file: true,
+            ..EntryOptions::default()
+        };
+
+        let mut writer = ZipStreamWriter::new(Pipe(Vec::new()));
+        writer.set_comment(b"streamed");
+        writer
+            .add_entry("text.txt", &text, &EntryOptions::default())
+            .await
+            .unwrap();
+        writer
+            .start_entry("stored.bin", &EntryOptions::stored())
+            .await
+            .unwrap();
+        writer.write_all(b"stored data").await.unwrap();
+        writer.start_entry("large.txt", &large).await.unwrap();
+        writer.write_all(&text).await.unwrap();
+        let written = writer.written();
+        let archive = writer.finish().await.unwrap().0;
+        assert!(archive.len() as u64 > written);
+
+        let mut reader = ZipReader::new(Cursor::new(&archive[..])).await.unwrap();
+        assert_eq!(reader.comment(), b"streamed");
+        let expected: [(&str, &[u8]); 3] = [
+            ("text.txt", &text),
+            ("stored.bin", b"stored data"),
+            ("large.txt", &text),
+        ];
+        for (index, (name, data)) in expected.into_iter().enumerate() {
+            let entry = &reader.entries()[index];
+            assert_eq!(entry.name, name);
+            assert_eq!(entry.flags & (1 << 3), 1 << 3);
+            assert_eq!(entry.crc32, update_crc32(0, data));
+
+            // The local header is left empty, and the data descriptor follows the data.
+            let local = entry.header_offset as usize;
+            assert_eq!(u32_at(&archive, local + 14), 0);
+            let extra_len = u16_at(&archive, local + 28) as usize;
+            let descriptor = local + 30 + name.len() + extra_len + entry.compressed_size as usize;
+            assert_eq!(u32_at(&archive, descriptor), 0x0807_4B50);
+            assert_eq!(u32_at(&archive, descriptor + 4), entry.crc32);
+            let sizes = &archive[descriptor + 8..];
+            if name == "large.txt" {
+                assert_eq!(extra_len, 20);
+                assert_eq!(sizes[..8], entry.compressed_size.to_le_bytes());
+                assert_eq!(sizes[8..16], entry.uncompressed_size.to_le_bytes());
+            } else {
+                assert_eq!(u32_at(sizes, 0) as u64, entry.compressed_size);
+                assert_eq!(u32_at(sizes, 4) as u64, entry.uncompressed_size);
+            }
+
+            let mut contents = Vec::new();
+            reader
+                .extract_with(index, |buf| contents.extend_from_slice(buf))
+                .await
+                .unwrap();
+            assert_eq!(contents, data);
+        }
+    }
+}